@@ -0,0 +1,69 @@
+fn main() {
+    println!("cargo:rerun-if-changed=build.rs");
+    #[cfg(feature = "grpc")]
+    grpc::generate();
+}
+
+/// Generates the gRPC service traits from the methods below, which match
+/// proto/deep_archive.proto; the messages are written out in
+/// src/server/grpc.rs. This keeps protoc out of the build.
+#[cfg(feature = "grpc")]
+mod grpc {
+    use tonic_build::manual::{Builder, Method, Service};
+
+    /// (route, input, output, server streaming).
+    type Rpc = (&'static str, &'static str, &'static str, bool);
+
+    const SERVICES: &[(&str, &[Rpc])] = &[
+        ("Ingest", &[
+            ("Start", "StartIngestRequest", "Run", false),
+            ("GetRun", "GetRunRequest", "Run", false),
+            ("ListRuns", "ListRunsRequest", "Run", true),
+            ("Watch", "GetRunRequest", "Run", true),
+        ]),
+        ("Query", &[
+            ("Find", "Filter", "Artifact", true),
+            ("Count", "Filter", "CountResponse", false),
+            ("Get", "GetArtifactRequest", "ArtifactDetail", false),
+        ]),
+        ("Archive", &[
+            ("ListVolumes", "ListVolumesRequest", "Volume", true),
+            ("ListVolumeFiles", "ListVolumeFilesRequest", "VolumeFile", true),
+            ("Locate", "GetArtifactRequest", "LocateResponse", false),
+        ]),
+    ];
+
+    pub fn generate() {
+        let services: Vec<Service> = SERVICES
+            .iter()
+            .map(|(name, methods)| {
+                let mut service = Service::builder().name(name).package("deep_archive.v1");
+                for (route, input, output, streaming) in methods.iter() {
+                    let mut method = Method::builder()
+                        .name(snake_case(route))
+                        .route_name(route)
+                        .input_type(format!("crate::server::grpc::pb::{}", input))
+                        .output_type(format!("crate::server::grpc::pb::{}", output))
+                        .codec_path("tonic_prost::ProstCodec");
+                    if *streaming {
+                        method = method.server_streaming();
+                    }
+                    service = service.method(method.build());
+                }
+                service.build()
+            })
+            .collect();
+        Builder::new().build_client(false).build_transport(false).compile(&services);
+    }
+
+    fn snake_case(name: &str) -> String {
+        let mut out = String::new();
+        for (i, c) in name.chars().enumerate() {
+            if c.is_ascii_uppercase() && i > 0 {
+                out.push('_');
+            }
+            out.push(c.to_ascii_lowercase());
+        }
+        out
+    }
+}
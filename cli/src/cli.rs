@@ -0,0 +1,1016 @@
+use std::path::PathBuf;
+use clap::{Args, Parser, Subcommand, ValueEnum};
+use serde::Serialize;
+use deep_archive::archive;
+use deep_archive::archive::{media_server, xmp};
+use deep_archive::daemon;
+use deep_archive::database::repo::FilterSet;
+use deep_archive::ingest::bench;
+use deep_archive::utils::config::{self, HwAccel};
+use deep_archive::utils::priority;
+use deep_archive::utils::units::{parse_date, parse_distance, parse_size};
+
+#[derive(Parser, Debug)]
+#[command(author, version, about, long_about = None)]
+pub struct Cli {
+    /// Path of the SQLite catalog
+    #[arg(short, long, global = true, default_value = "data/archive_index.db")]
+    pub db_path: String,
+
+    /// Path to the config file (defaults to ./deep-archive.toml if present)
+    #[arg(short, long, global = true)]
+    pub config: Option<PathBuf>,
+
+    /// Report every file and run transition (discovered, hashed, analyzed, stored, failed, archived) in this format
+    #[arg(long, global = true, value_name = "FORMAT")]
+    pub events: Option<EventFormat>,
+
+    /// Where `--events` go: `-` for stdout (logs then go to stderr) or the path of a listening Unix socket
+    #[arg(long, global = true, value_name = "DEST", default_value = "-")]
+    pub events_to: String,
+
+    /// Run at this CPU priority, from -20 (highest) to 19 (lowest), as `process.nice`; on Windows above 0 is below normal and 10 and up idle
+    #[arg(long, global = true, value_name = "N", allow_hyphen_values = true)]
+    pub nice: Option<i32>,
+
+    /// Disk priority class, as `process.io_priority` (Linux and Windows)
+    #[arg(long, global = true, value_name = "CLASS")]
+    pub io_priority: Option<IoPriority>,
+
+    #[command(subcommand)]
+    pub command: Command,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, ValueEnum)]
+pub enum EventFormat {
+    /// One JSON object per line
+    Jsonl,
+}
+
+#[derive(Args, Debug)]
+pub struct BenchArgs {
+    /// Stages to measure on their own
+    #[arg(long, value_delimiter = ',', default_values = ["hash", "decode", "inference"])]
+    pub workload: Vec<BenchWorkload>,
+
+    /// Thread counts to try (default: 1, 2, 4, ... up to the CPU count)
+    #[arg(long, value_delimiter = ',')]
+    pub threads: Vec<usize>,
+
+    /// Hardware decoders to compare for decoding, as `media.hwaccel` (default: the configured one)
+    #[arg(long, value_delimiter = ',', value_parser = parse_hwaccel)]
+    pub hwaccel: Vec<HwAccel>,
+
+    /// Measure files from this directory instead of generated ones, e.g. a sample of the collection on its own storage
+    #[arg(long, value_name = "DIR")]
+    pub sample: Option<PathBuf>,
+
+    /// Files per workload, taken from `--sample` or generated
+    #[arg(long, default_value_t = 32)]
+    pub files: usize,
+
+    /// Print one JSON object per measurement
+    #[arg(long)]
+    pub json: bool,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, ValueEnum)]
+pub enum BenchWorkload {
+    /// Read and hash files
+    Hash,
+    /// Sample frames from images and videos
+    Decode,
+    /// Run the models on decoded frames
+    Inference,
+}
+
+impl From<BenchWorkload> for bench::Workload {
+    fn from(workload: BenchWorkload) -> Self {
+        match workload {
+            BenchWorkload::Hash => bench::Workload::Hash,
+            BenchWorkload::Decode => bench::Workload::Decode,
+            BenchWorkload::Inference => bench::Workload::Inference,
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, ValueEnum)]
+pub enum IoPriority {
+    /// As the process was started with
+    Normal,
+    /// Only use the disk when nothing else does
+    Idle,
+}
+
+impl From<IoPriority> for priority::IoPriority {
+    fn from(io: IoPriority) -> Self {
+        match io {
+            IoPriority::Normal => priority::IoPriority::Normal,
+            IoPriority::Idle => priority::IoPriority::Idle,
+        }
+    }
+}
+
+#[derive(Subcommand, Debug)]
+pub enum Command {
+    /// Scan, hash and analyze a directory, or hash and analyze individual files, into the catalog
+    Ingest(IngestArgs),
+    /// Write a directory or catalog query to an ISO or UDF image, a compressed tarball, a ZIP file or a BagIt bag, or burn an image to disc
+    Archive(Box<ArchiveArgs>),
+    /// List catalog entries matching the given filters
+    Query(Box<QueryArgs>),
+    /// Report content stored at several paths, largest savings first
+    Dedupe(Box<DedupeArgs>),
+    /// Write matching catalog entries to a file or stdout
+    Export(Box<ExportArgs>),
+    /// Load catalog entries written by `export`
+    Import(ImportArgs),
+    /// Tombstone (or purge) catalog entries matching the given filters
+    Delete(Box<DeleteArgs>),
+    /// Inspect or roll back ingest runs
+    #[command(subcommand)]
+    Runs(RunsCommand),
+    /// Copy matching files back out of their original locations or archive volumes, verifying each
+    Restore(Box<RestoreArgs>),
+    /// Re-hash catalogued files and record the results in the fixity history
+    Verify(Box<VerifyArgs>),
+    /// Manage tag aliases and implications
+    #[command(subcommand)]
+    Tags(TagsCommand),
+    /// Link derived artifacts (thumbnails, extracted files) to their originals
+    #[command(subcommand)]
+    Relations(RelationsCommand),
+    /// List the volumes (drives, hosts) files were ingested from
+    Sources,
+    /// List the archive volumes written by `archive`, with their labels and checksums
+    Volumes {
+        /// Also list the discs burned from each volume
+        #[arg(long)]
+        discs: bool,
+
+        /// Also list the copies of each volume uploaded to remotes
+        #[arg(long)]
+        uploads: bool,
+
+        /// Print one JSON object per volume
+        #[arg(long)]
+        json: bool,
+    },
+    /// Write standalone index files of the archive volumes, or find files in them without the catalog
+    #[command(subcommand)]
+    Index(IndexCommand),
+    /// Summarize the catalog: totals, mimetypes, top tags, NSFW scores and run throughput
+    Stats {
+        /// Number of most used tags to list
+        #[arg(long, default_value_t = 20)]
+        top_tags: usize,
+
+        /// Also list the N files ingest spent the most time on, with the time per stage
+        #[arg(long, value_name = "N", default_value_t = 0)]
+        slowest: usize,
+
+        /// Print the statistics as a JSON object
+        #[arg(long)]
+        json: bool,
+    },
+    /// Inspect or retry files that failed during ingest
+    #[command(subcommand)]
+    Errors(ErrorsCommand),
+    /// Re-inject files that failed during ingest into a new run, skipping the stages they got through
+    ReplayFailed {
+        /// Only files that failed in this run
+        #[arg(long = "run")]
+        run_id: Option<i64>,
+
+        /// Only files that failed in these stages (scan, hash, mimetype, models, preview)
+        #[arg(long = "stage", value_delimiter = ',')]
+        stages: Vec<String>,
+    },
+    /// Hash and analyze files for an `ingest --serve-workers` on another machine, reading them from the same paths
+    Worker {
+        /// Address of the coordinator, e.g. nas.local:7700
+        coordinator: String,
+
+        /// Files to work on at once (default: as `pipeline.workers`)
+        #[arg(long)]
+        threads: Option<usize>,
+    },
+    /// Measure hashing, decoding and inference throughput at several thread counts, to tune `[pipeline]` for this machine
+    Bench(BenchArgs),
+    /// Serve a REST API for browsing the catalog, starting ingests and managing tags
+    Serve {
+        /// Address to listen on, instead of `server.addr`
+        #[arg(long, value_name = "ADDR")]
+        addr: Option<String>,
+
+        /// Also serve the gRPC services on this address, instead of `server.grpc_addr`
+        #[arg(long, value_name = "ADDR")]
+        grpc_addr: Option<String>,
+    },
+    /// Browse the catalog in the terminal: search, results, metadata, tags and image previews
+    Tui(Box<TuiArgs>),
+    /// Run as a service: ingest what appears in the `daemon.watch` directories, serve the HTTP API and take `ctl` requests
+    Daemon {
+        /// Watch this directory too (repeatable)
+        #[arg(long, value_name = "DIR")]
+        watch: Vec<PathBuf>,
+
+        /// Serve the API on this address, instead of `server.addr`
+        #[arg(long, value_name = "ADDR")]
+        addr: Option<String>,
+
+        /// Don't serve the HTTP API, as `daemon.serve = false`
+        #[arg(long)]
+        no_serve: bool,
+
+        /// Take `ctl` requests on this Unix socket, instead of `daemon.socket`
+        #[arg(long, value_name = "PATH")]
+        socket: Option<PathBuf>,
+    },
+    /// Ask a running daemon for its status, or to pause, resume or reload its config
+    Ctl {
+        #[command(subcommand)]
+        command: CtlCommand,
+
+        /// The daemon's control socket, instead of `daemon.socket`
+        #[arg(long, value_name = "PATH", global = true)]
+        socket: Option<PathBuf>,
+
+        /// Print the daemon's status as a JSON object
+        #[arg(long, global = true)]
+        json: bool,
+    },
+    /// Rebuild the full-text search index from the artifacts table
+    ReindexFts,
+    /// Catalog maintenance
+    #[command(subcommand)]
+    Db(DbCommand),
+}
+
+#[derive(Subcommand, Debug)]
+pub enum IndexCommand {
+    /// Write `<LABEL>.json` for every registered volume and `all-volumes.jsonl` listing where each file's content is
+    Write {
+        /// Directory to write the index files to
+        dir: PathBuf,
+    },
+    /// List the volumes holding each file, from the index files alone
+    Lookup {
+        /// Index directory, or its all-volumes.jsonl
+        #[arg(long)]
+        index: PathBuf,
+
+        /// Files to look up (directories for every file under them), or SHA-256 hashes
+        #[arg(required = true)]
+        items: Vec<String>,
+
+        /// Print one JSON object per file
+        #[arg(long)]
+        json: bool,
+    },
+}
+
+#[derive(Subcommand, Debug)]
+pub enum DbCommand {
+    /// Check the catalog for corruption; exits non-zero if anything is found
+    Check {
+        /// Print the results as a JSON object
+        #[arg(long)]
+        json: bool,
+    },
+    /// Compact the catalog: optimize the search index, ANALYZE, VACUUM and checkpoint the WAL
+    Optimize {
+        /// Skip VACUUM, which rewrites the whole file and locks out other connections
+        #[arg(long)]
+        no_vacuum: bool,
+    },
+    /// Write an encrypted copy of the catalog, keyed from $DEEP_ARCHIVE_KEY, key_command or a prompt
+    #[cfg(feature = "sqlcipher")]
+    Encrypt {
+        /// New catalog file; must not exist yet
+        output: PathBuf,
+    },
+}
+
+#[derive(Subcommand, Debug)]
+pub enum RunsCommand {
+    /// List ingest runs, newest first
+    List,
+    /// Show the summary stored with a pipeline run: files and bytes per stage, throughput and errors
+    Show {
+        run_id: i64,
+        /// Print the summary as a JSON object
+        #[arg(long)]
+        json: bool,
+    },
+    /// Tombstone everything a run added to the catalog
+    Rollback {
+        run_id: i64,
+        /// Delete the rows for good instead of tombstoning them
+        #[arg(long)]
+        purge: bool,
+    },
+}
+
+#[derive(Subcommand, Debug, Clone, Copy)]
+pub enum CtlCommand {
+    /// Show what the daemon is watching and doing
+    Status,
+    /// Start no new runs until resumed; the run in progress finishes
+    Pause,
+    /// Start runs again and look at the directories now
+    Resume,
+    /// Read the config file again (`[server]` keeps its settings until a restart)
+    Reload,
+}
+
+impl From<CtlCommand> for daemon::Request {
+    fn from(command: CtlCommand) -> Self {
+        match command {
+            CtlCommand::Status => daemon::Request::Status,
+            CtlCommand::Pause => daemon::Request::Pause,
+            CtlCommand::Resume => daemon::Request::Resume,
+            CtlCommand::Reload => daemon::Request::Reload,
+        }
+    }
+}
+
+#[derive(Subcommand, Debug)]
+pub enum TagsCommand {
+    /// Make ALIAS stand for TAG, e.g. `ww2 world_war_2`; existing ALIAS tags are merged into TAG
+    Alias { alias: String, tag: String },
+    /// Remove an alias
+    Unalias { alias: String },
+    /// Give every artifact tagged TAG the tag IMPLIED too, e.g. `golden_retriever dog`
+    Imply { tag: String, implied: String },
+    /// Stop applying an implication (tags it already added stay)
+    Unimply { tag: String, implied: String },
+    /// List all aliases and implications
+    Rules,
+}
+
+/// How one artifact relates to another, read as `<HASH> <KIND> <RELATED>`.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, ValueEnum)]
+pub enum RelationKind {
+    /// Any artifact produced from another, e.g. a transcode or edit
+    DerivativeOf,
+    /// A preview image or proxy clip
+    ThumbnailOf,
+    /// A video frame, or a file found inside an archive
+    ExtractedFrom,
+}
+
+impl RelationKind {
+    pub fn as_str(self) -> &'static str {
+        match self {
+            RelationKind::DerivativeOf => "derivative-of",
+            RelationKind::ThumbnailOf => "thumbnail-of",
+            RelationKind::ExtractedFrom => "extracted-from",
+        }
+    }
+}
+
+#[derive(Subcommand, Debug)]
+pub enum RelationsCommand {
+    /// Record that HASH is KIND RELATED, e.g. `<frame> extracted-from <video>`
+    Add { hash: String, kind: RelationKind, related: String },
+    /// Remove a relationship
+    Remove { hash: String, kind: RelationKind, related: String },
+}
+
+#[derive(Subcommand, Debug)]
+pub enum ErrorsCommand {
+    /// List unresolved ingest errors
+    List {
+        /// Only errors from this run
+        #[arg(long = "run")]
+        run_id: Option<i64>,
+
+        /// Include errors already resolved by a later run
+        #[arg(long)]
+        all: bool,
+    },
+    /// Re-ingest every file with an unresolved error as a new run
+    Retry {
+        /// Only files that failed in this run
+        #[arg(long = "run")]
+        run_id: Option<i64>,
+    },
+}
+
+#[derive(Args, Debug, Serialize)]
+pub struct IngestArgs {
+    /// Directory to scan, or a WebDAV share to mirror and ingest: `webdav://host/path`, or `webdavs://` over HTTPS
+    #[arg(short, long, visible_alias = "input", required_unless_present_any = ["files", "urls", "ytdlp"], conflicts_with_all = ["files", "urls", "ytdlp"])]
+    pub input_dir: Option<PathBuf>,
+
+    /// Files to ingest without scanning a directory, e.g. one that was just downloaded; no ISO is written for them
+    #[arg(value_name = "FILE")]
+    pub files: Vec<PathBuf>,
+
+    /// File listing HTTP(S) URLs, one per line (`-` for stdin), to download into `download.spool_dir` and ingest
+    #[arg(long, value_name = "FILE", conflicts_with = "files")]
+    pub urls: Option<PathBuf>,
+
+    /// Video, playlist or channel URL to download with yt-dlp into `download.spool_dir` and ingest
+    #[arg(long, value_name = "URL", conflicts_with_all = ["files", "urls"])]
+    pub ytdlp: Option<String>,
+
+    #[arg(short, long, default_value = "iso/archive.iso")]
+    pub output_iso: PathBuf,
+
+    /// Threads hashing files, instead of `pipeline.hashers` or the default for the CPU count and input storage
+    #[arg(long)]
+    pub hashers: Option<usize>,
+
+    /// Threads decoding media and running the models, instead of `pipeline.workers` or the default for the CPU count
+    #[arg(long)]
+    pub workers: Option<usize>,
+
+    /// Continue an interrupted or crashed run (by default the latest over this input) instead of starting one, skipping the files it committed
+    #[arg(long, value_name = "RUN")]
+    pub resume: Option<Option<i64>>,
+
+    /// Serve Prometheus metrics at http://ADDR/metrics while the pipeline runs, instead of `pipeline.metrics_addr`
+    #[arg(long, value_name = "ADDR")]
+    pub metrics: Option<String>,
+
+    /// Shrink the hashers and workers while the queue after them stays full, as `pipeline.adaptive`
+    #[arg(long)]
+    pub adaptive: bool,
+
+    /// Move threads between hashing and analysis to whichever is behind, as `pipeline.rebalance`
+    #[arg(long)]
+    pub rebalance: bool,
+
+    /// Analyze files even if their content is already in the catalog, e.g. after a model upgrade
+    #[arg(long)]
+    pub reanalyze: bool,
+
+    /// Hand files out to remote `worker`s connecting on ADDR instead of hashing and analyzing them here, as `pipeline.serve_workers`
+    #[arg(long, value_name = "ADDR")]
+    pub serve_workers: Option<String>,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, ValueEnum)]
+pub enum ArchiveFormat {
+    /// ISO 9660 with Rock Ridge and Joliet, for optical media
+    Iso,
+    /// UDF 1.02, for optical media holding files over 4 GiB (Blu-ray)
+    Udf,
+    /// Deterministic GNU tar compressed with zstd
+    #[value(name = "tar.zst")]
+    TarZst,
+    /// ZIP (ZIP64 when needed), deflating all but already-compressed formats
+    Zip,
+    /// BagIt (RFC 8493) bag directory, for transfer to institutional archives
+    Bagit,
+    /// Files on an LTFS-formatted tape, written at its mount point
+    Ltfs,
+    /// Plain GNU tar stream written straight to a tape drive, e.g. `/dev/st0`
+    Tape,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, ValueEnum)]
+pub enum ArchiveLayout {
+    /// The input directory tree as it is
+    Tree,
+    /// Each distinct file once as `objects/ab/cd/<sha256>`, mapped to its paths by the manifest
+    Cas,
+}
+
+impl ArchiveFormat {
+    /// File extension, also recorded as the volume format.
+    pub fn extension(self) -> &'static str {
+        match self {
+            ArchiveFormat::Iso => "iso",
+            ArchiveFormat::Udf => "udf",
+            ArchiveFormat::TarZst => "tar.zst",
+            ArchiveFormat::Zip => "zip",
+            ArchiveFormat::Bagit => "bagit",
+            ArchiveFormat::Ltfs => "ltfs",
+            ArchiveFormat::Tape => "tape",
+        }
+    }
+
+    /// What volume file names end with: a dot and the extension, or nothing
+    /// for bags, which are directories, and tapes.
+    pub fn suffix(self) -> String {
+        match self {
+            ArchiveFormat::Bagit | ArchiveFormat::Ltfs | ArchiveFormat::Tape => String::new(),
+            format => format!(".{}", format.extension()),
+        }
+    }
+
+    /// True for the formats written to tape, where `--output` is the drive
+    /// or mount point every volume goes to in turn.
+    pub fn is_tape(self) -> bool {
+        matches!(self, ArchiveFormat::Ltfs | ArchiveFormat::Tape)
+    }
+}
+
+#[derive(Args, Debug)]
+#[command(args_conflicts_with_subcommands = true, subcommand_negates_reqs = true)]
+pub struct ArchiveArgs {
+    #[command(subcommand)]
+    pub command: Option<ArchiveCommand>,
+
+    /// Directory to archive
+    #[arg(short, long, required_unless_present = "from_catalog")]
+    pub input_dir: Option<PathBuf>,
+
+    /// Archive the catalogued artifacts matching the filters instead of a directory, each from a path still holding its content
+    #[arg(long, conflicts_with = "input_dir")]
+    pub from_catalog: bool,
+
+    #[command(flatten)]
+    pub filter: FilterArgs,
+
+    /// Image, tarball or bag directory to create; with `--volume-size` numbered as `NAME_001.EXT`, `NAME_002.EXT`, ...; or a directory to write volumes named by archive.file_name into; for tapes the LTFS mount point or tape device
+    #[arg(short, long, required = true)]
+    pub output: Option<PathBuf>,
+
+    #[arg(long, value_enum, default_value_t = ArchiveFormat::Iso)]
+    pub format: ArchiveFormat,
+
+    #[arg(long, value_enum, default_value_t = ArchiveLayout::Tree)]
+    pub layout: ArchiveLayout,
+
+    /// Only archive files whose content isn't on any recorded volume yet: new files and changed ones
+    #[arg(long)]
+    pub incremental: bool,
+
+    /// Store each distinct file once; the manifest lists the other paths with the copy they match
+    #[arg(long)]
+    pub dedupe: bool,
+
+    /// Split across volumes of this size: a size like `25GB` or a preset (cd, dvd, dvd-dl, bd, bd-dl, bd-xl, lto-5 ... lto-9)
+    #[arg(long, value_parser = parse_volume_size)]
+    pub volume_size: Option<u64>,
+
+    /// Also put a copy of the catalog, pruned to the volume's artifacts, at `deep-archive/catalog.db` on each volume
+    #[arg(long)]
+    pub with_catalog: bool,
+
+    /// Also list every file's CRC-32 in `deep-archive/checksums.sfv`, for SFV tools; SHA-256s are always in `deep-archive/SHA256SUMS`
+    #[arg(long)]
+    pub sfv: bool,
+
+    /// Also describe each volume in `deep-archive/mets.xml`: a METS structural map with PREMIS fixity and provenance events from the catalog
+    #[arg(long)]
+    pub mets: bool,
+
+    /// Volumes are labelled `PREFIX-0001`, `PREFIX-0002`, ..., continuing after the highest label already registered
+    #[arg(long, default_value = "VOL", value_parser = parse_label_prefix)]
+    pub label_prefix: String,
+
+    /// Timestamp for every entry, as unix seconds or YYYY-MM-DD (UTC); defaults to archive.epoch, $SOURCE_DATE_EPOCH, then the newest file's mtime
+    #[arg(long, value_parser = parse_epoch)]
+    pub epoch: Option<i64>,
+
+    /// Don't read each volume back to check it against the files archived
+    #[arg(long)]
+    pub no_verify: bool,
+
+    /// Build each volume a second time and fail unless both come out byte-identical; needs room for the second copy
+    #[arg(long)]
+    pub verify_reproducible: bool,
+
+    /// Barcode of the tape for each volume, in order (repeatable); otherwise read from LTFS or asked for
+    #[arg(long = "barcode")]
+    pub barcodes: Vec<String>,
+}
+
+#[derive(Subcommand, Debug)]
+pub enum ArchiveCommand {
+    /// Burn a registered ISO or UDF volume image to disc, read it back and record the disc
+    Burn(BurnArgs),
+    /// Upload a registered volume to a bucket named under `[archive.remotes]` and record the copy
+    #[cfg(feature = "cloud")]
+    Upload(UploadArgs),
+}
+
+#[derive(Args, Debug)]
+pub struct BurnArgs {
+    /// Image written by `archive`
+    pub image: PathBuf,
+
+    /// Drive to burn in, e.g. `/dev/sr0`, or `E:` on Windows
+    #[arg(long)]
+    pub device: String,
+
+    /// Don't read the disc back to compare it with the image
+    #[arg(long)]
+    pub no_verify: bool,
+}
+
+#[cfg(feature = "cloud")]
+#[derive(Args, Debug)]
+pub struct UploadArgs {
+    /// Volume written by `archive`: an image, tarball, ZIP file or bag directory
+    pub volume: PathBuf,
+
+    /// Remote to upload to, as named under `[archive.remotes]`
+    #[arg(long)]
+    pub remote: String,
+
+    /// Upload each file on the volume as `objects/ab/cd/<sha256>` instead of the volume file; content already in the bucket isn't sent again
+    #[arg(long)]
+    pub objects: bool,
+}
+
+#[derive(Args, Debug)]
+pub struct QueryArgs {
+    #[command(flatten)]
+    pub filter: FilterArgs,
+
+    /// Print only the number of matches
+    #[arg(long)]
+    pub count: bool,
+
+    /// Also list every location each artifact was seen at, with its volume, and the archives holding it
+    #[arg(long)]
+    pub paths: bool,
+
+    /// Also list the artifacts each one is related to
+    #[arg(long)]
+    pub related: bool,
+}
+
+#[derive(Args, Debug)]
+pub struct TuiArgs {
+    #[command(flatten)]
+    pub filter: FilterArgs,
+
+    /// How to draw previews, instead of `tui.images`
+    #[arg(long, value_enum, value_name = "PROTOCOL")]
+    pub images: Option<ImageProtocol>,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, ValueEnum)]
+pub enum ImageProtocol {
+    /// Kitty graphics or sixel where the terminal is known to support them, else blocks
+    Auto,
+    /// The kitty graphics protocol
+    Kitty,
+    /// Sixel graphics
+    Sixel,
+    /// Coloured half blocks, in any terminal with true colour
+    Blocks,
+    /// No previews
+    None,
+}
+
+impl From<ImageProtocol> for config::ImageProtocol {
+    fn from(protocol: ImageProtocol) -> Self {
+        match protocol {
+            ImageProtocol::Auto => config::ImageProtocol::Auto,
+            ImageProtocol::Kitty => config::ImageProtocol::Kitty,
+            ImageProtocol::Sixel => config::ImageProtocol::Sixel,
+            ImageProtocol::Blocks => config::ImageProtocol::Blocks,
+            ImageProtocol::None => config::ImageProtocol::None,
+        }
+    }
+}
+
+#[derive(Args, Debug)]
+pub struct DedupeArgs {
+    #[command(flatten)]
+    pub filter: FilterArgs,
+
+    /// Print only the totals
+    #[arg(long)]
+    pub summary: bool,
+}
+
+#[derive(Args, Debug)]
+pub struct VerifyArgs {
+    #[command(flatten)]
+    pub filter: FilterArgs,
+
+    /// Print totals over the whole fixity history instead of verifying
+    #[arg(long)]
+    pub report: bool,
+}
+
+#[derive(Args, Debug)]
+pub struct RestoreArgs {
+    #[command(flatten)]
+    pub filter: FilterArgs,
+
+    /// Directory to restore into; files keep their original paths below it
+    #[arg(long)]
+    pub dest: PathBuf,
+
+    /// Volume to read from: an image, tarball or ZIP file, the directory a disc or LTFS tape is mounted at, or a tape drive (repeatable)
+    #[arg(long = "volume")]
+    pub volumes: Vec<PathBuf>,
+
+    /// Read only from archive volumes, even where the original file is still in place
+    #[arg(long)]
+    pub from_volumes: bool,
+
+    /// Don't ask for volumes that weren't given; report them instead
+    #[arg(long)]
+    pub no_prompt: bool,
+
+    /// Fetch files from volumes uploaded to this remote instead of asking for the media; Glacier copies are restored first
+    #[cfg(feature = "cloud")]
+    #[arg(long)]
+    pub remote: Option<String>,
+}
+
+#[derive(Args, Debug)]
+pub struct DeleteArgs {
+    #[command(flatten)]
+    pub filter: FilterArgs,
+
+    /// Why the artifacts are being removed, kept with the tombstone
+    #[arg(long)]
+    pub reason: String,
+
+    /// Delete the rows for good instead of tombstoning them
+    #[arg(long)]
+    pub purge: bool,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, ValueEnum, Serialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum ExportFormat {
+    /// One JSON object per artifact and line
+    Jsonl,
+    /// `artifacts`, `tags` and `scores` tables in an output directory
+    #[cfg(feature = "parquet")]
+    Parquet,
+    /// Videos linked into a Jellyfin, Emby, Kodi or Plex library in an output directory, with `.nfo` files
+    MediaServer,
+    /// XMP sidecars with tags, ratings and scores, next to the originals or in an output directory
+    Xmp,
+    /// A Hydrus tag archive keyed by SHA-256; imported, it tags the artifacts already catalogued
+    Hydrus,
+    /// A JSON array shaped like `exiftool -json` output, one object per artifact
+    Exiftool,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, ValueEnum)]
+pub enum SidecarName {
+    /// `photo.jpg.xmp` (digiKam, darktable)
+    Append,
+    /// `photo.xmp` (Lightroom, Capture One)
+    Replace,
+}
+
+impl From<SidecarName> for xmp::SidecarName {
+    fn from(name: SidecarName) -> Self {
+        match name {
+            SidecarName::Append => xmp::SidecarName::Append,
+            SidecarName::Replace => xmp::SidecarName::Replace,
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, ValueEnum)]
+pub enum LinkMode {
+    /// Symbolic links to the original paths
+    Symlink,
+    /// Hard links, on the originals' file system
+    Hardlink,
+    /// Full copies
+    Copy,
+}
+
+impl From<LinkMode> for media_server::LinkMode {
+    fn from(link: LinkMode) -> Self {
+        match link {
+            LinkMode::Symlink => media_server::LinkMode::Symlink,
+            LinkMode::Hardlink => media_server::LinkMode::Hardlink,
+            LinkMode::Copy => media_server::LinkMode::Copy,
+        }
+    }
+}
+
+#[derive(Args, Debug)]
+pub struct ExportArgs {
+    #[arg(long, value_enum, default_value_t = ExportFormat::Jsonl)]
+    pub format: ExportFormat,
+
+    /// Output file (defaults to stdout; required for `hydrus`); a directory for `parquet`, `media-server` and `xmp` (defaults to next to the originals)
+    #[arg(short, long)]
+    pub output: Option<PathBuf>,
+
+    /// How `media-server` puts the videos into the library
+    #[arg(long, value_enum, default_value_t = LinkMode::Symlink)]
+    pub link: LinkMode,
+
+    /// What `xmp` calls the sidecar of `photo.jpg`
+    #[arg(long, value_enum, default_value_t = SidecarName::Append)]
+    pub sidecar_name: SidecarName,
+
+    /// Replace `xmp` sidecars that already exist, along with any edits made to them
+    #[arg(long)]
+    pub overwrite: bool,
+
+    /// Print `exiftool` values as numbers, like `exiftool -n`
+    #[arg(short = 'n', long)]
+    pub numeric: bool,
+
+    #[command(flatten)]
+    pub filter: FilterArgs,
+}
+
+#[derive(Args, Debug)]
+pub struct ImportArgs {
+    #[arg(long, value_enum, default_value_t = ExportFormat::Jsonl)]
+    pub format: ExportFormat,
+
+    /// File to read, or `-` for stdin (`jsonl` only)
+    pub input: PathBuf,
+}
+
+/// Catalog filters shared by every command that selects artifacts.
+#[derive(Args, Debug, Clone, Default)]
+pub struct FilterArgs {
+    /// Require this tag, e.g. `person:alice`; `ns:*` matches a whole namespace (repeatable; all must match)
+    #[arg(long = "tag")]
+    pub tags: Vec<String>,
+
+    /// Require at least one of these tags (repeatable)
+    #[arg(long = "any-tag")]
+    pub any_tags: Vec<String>,
+
+    /// Exclude artifacts with this tag (repeatable)
+    #[arg(long = "exclude-tag")]
+    pub exclude_tags: Vec<String>,
+
+    /// Mimetype, exact (`image/png`) or wildcard (`video/*`) (repeatable)
+    #[arg(long = "type")]
+    pub media_types: Vec<String>,
+
+    /// Minimum NSFW score (0.0-1.0)
+    #[arg(long)]
+    pub min_nsfw: Option<f32>,
+
+    /// Maximum NSFW score (0.0-1.0)
+    #[arg(long)]
+    pub max_nsfw: Option<f32>,
+
+    /// Minimum file size, e.g. `10MB`
+    #[arg(long, value_parser = parse_size)]
+    pub min_size: Option<u64>,
+
+    /// Maximum file size, e.g. `2GiB`
+    #[arg(long, value_parser = parse_size)]
+    pub max_size: Option<u64>,
+
+    /// Modified on or after this date (YYYY-MM-DD, UTC)
+    #[arg(long, value_parser = parse_date)]
+    pub after: Option<i64>,
+
+    /// Modified before this date (YYYY-MM-DD, UTC)
+    #[arg(long, value_parser = parse_date)]
+    pub before: Option<i64>,
+
+    /// Only artifacts first discovered by this ingest run
+    #[arg(long = "run")]
+    pub run_id: Option<i64>,
+
+    /// Full-text search over paths and tags (FTS5 syntax)
+    #[arg(long)]
+    pub search: Option<String>,
+
+    /// EXIF camera model contains this (case-insensitive)
+    #[arg(long = "camera")]
+    pub camera_model: Option<String>,
+
+    /// Minimum audio/video duration in seconds
+    #[arg(long)]
+    pub min_duration: Option<f64>,
+
+    /// Maximum audio/video duration in seconds
+    #[arg(long)]
+    pub max_duration: Option<f64>,
+
+    /// Only artifacts related to the artifact with this hash, in either direction
+    #[arg(long, value_name = "HASH")]
+    pub related_to: Option<String>,
+
+    /// Only originals, i.e. artifacts not derived from another one
+    #[arg(long)]
+    pub originals: bool,
+
+    /// Only artifacts without a successful fixity check since this date (YYYY-MM-DD, UTC)
+    #[arg(long, value_parser = parse_date)]
+    pub unverified_since: Option<i64>,
+
+    /// Only artifacts without a vector from this embedding model version (`MODEL@VERSION`)
+    #[arg(long, value_name = "MODEL@VERSION", value_parser = parse_model_version)]
+    pub stale_embedding: Option<(String, String)>,
+
+    /// Only geotagged artifacts within `--radius` of this point
+    #[arg(long, value_name = "LAT,LON", value_parser = parse_coordinates)]
+    pub near: Option<(f64, f64)>,
+
+    /// Search radius around `--near`, e.g. `500m`, `5km` or `3mi`
+    #[arg(long, value_parser = parse_distance, default_value = "1km", requires = "near")]
+    pub radius: f64,
+
+    /// Only geotagged artifacts inside this box; MIN_LON > MAX_LON crosses the antimeridian
+    #[arg(long, value_name = "MIN_LAT,MIN_LON,MAX_LAT,MAX_LON", value_parser = parse_bbox)]
+    pub bbox: Option<(f64, f64, f64, f64)>,
+
+    /// Also match deleted (tombstoned) artifacts
+    #[arg(long)]
+    pub include_deleted: bool,
+
+    /// Stop after this many results
+    #[arg(long)]
+    pub limit: Option<usize>,
+}
+
+impl FilterArgs {
+    pub fn to_filter_set(&self) -> FilterSet {
+        FilterSet {
+            all_tags: self.tags.clone(),
+            any_tags: self.any_tags.clone(),
+            exclude_tags: self.exclude_tags.clone(),
+            media_types: self.media_types.clone(),
+            min_nsfw: self.min_nsfw,
+            max_nsfw: self.max_nsfw,
+            min_size: self.min_size,
+            max_size: self.max_size,
+            modified_after: self.after,
+            modified_before: self.before,
+            run_id: self.run_id,
+            search: self.search.clone(),
+            camera_model: self.camera_model.clone(),
+            min_duration: self.min_duration,
+            max_duration: self.max_duration,
+            stale_embedding: self.stale_embedding.clone(),
+            near: self.near.map(|(lat, lon)| (lat, lon, self.radius)),
+            bbox: self.bbox,
+            hash: None,
+            related_to: self.related_to.clone(),
+            originals_only: self.originals,
+            unverified_since: self.unverified_since,
+            include_deleted: self.include_deleted,
+            after_id: None,
+            limit: self.limit,
+        }
+    }
+}
+
+fn parse_hwaccel(s: &str) -> Result<HwAccel, String> {
+    HwAccel::from_name(s).ok_or_else(|| format!("unknown hwaccel '{}'; use none, auto, vaapi, cuda, videotoolbox or qsv", s))
+}
+
+fn parse_label_prefix(s: &str) -> Result<String, String> {
+    if s.is_empty() || !s.chars().all(|c| c.is_ascii_alphanumeric() || c == '_' || c == '-') {
+        return Err("use letters, digits, '_' and '-'".to_string());
+    }
+    Ok(s.to_ascii_uppercase())
+}
+
+fn parse_epoch(s: &str) -> Result<i64, String> {
+    match s.trim().parse::<i64>() {
+        Ok(seconds) => Ok(seconds),
+        Err(_) => parse_date(s).map_err(|e| e.to_string()),
+    }
+}
+
+fn parse_volume_size(s: &str) -> Result<u64, String> {
+    archive::parse_volume_size(s).map_err(|e| e.to_string())
+}
+
+fn parse_degrees(s: &str, max: f64) -> Result<f64, String> {
+    match s.trim().parse::<f64>() {
+        Ok(v) if v.abs() <= max => Ok(v),
+        Ok(v) => Err(format!("{} is out of range (±{})", v, max)),
+        Err(_) => Err(format!("'{}' is not a number", s.trim())),
+    }
+}
+
+fn parse_coordinates(s: &str) -> Result<(f64, f64), String> {
+    let Some((lat, lon)) = s.split_once(',') else {
+        return Err(format!("expected LAT,LON, got '{}'", s));
+    };
+    Ok((parse_degrees(lat, 90.0)?, parse_degrees(lon, 180.0)?))
+}
+
+fn parse_bbox(s: &str) -> Result<(f64, f64, f64, f64), String> {
+    let parts: Vec<&str> = s.split(',').collect();
+    let [min_lat, min_lon, max_lat, max_lon] = parts[..] else {
+        return Err(format!("expected MIN_LAT,MIN_LON,MAX_LAT,MAX_LON, got '{}'", s));
+    };
+    let (min_lat, max_lat) = (parse_degrees(min_lat, 90.0)?, parse_degrees(max_lat, 90.0)?);
+    if min_lat > max_lat {
+        return Err(format!("MIN_LAT {} is north of MAX_LAT {}", min_lat, max_lat));
+    }
+    Ok((min_lat, parse_degrees(min_lon, 180.0)?, max_lat, parse_degrees(max_lon, 180.0)?))
+}
+
+fn parse_model_version(s: &str) -> Result<(String, String), String> {
+    match s.split_once('@') {
+        Some((model, version)) if !model.is_empty() && !version.is_empty() => Ok((model.to_string(), version.to_string())),
+        _ => Err(format!("expected MODEL@VERSION, got '{}'", s)),
+    }
+}
@@ -0,0 +1,382 @@
+use std::collections::{HashMap, HashSet};
+use std::fs;
+use std::io::{self, BufRead, IsTerminal, Write};
+use std::path::{Path, PathBuf};
+use std::time::Instant;
+use anyhow::{Result, Context, bail};
+use tracing::{info, warn};
+
+use crate::cli::{ArchiveArgs, ArchiveFormat, ArchiveLayout};
+use deep_archive::archive::{self, bagit_builder, iso_builder, reader, tape, tar_builder, udf_builder, zip_builder, Member};
+use deep_archive::archive::manifest::{self, Manifest};
+use deep_archive::archive::progress::Progress;
+use deep_archive::archive::select::{self, Staging};
+use deep_archive::database::repo::{ArchiveVolume, CatalogReader, TransactionManager};
+use deep_archive::ingest::events::{self, Event};
+use deep_archive::ingest::hasher::{self, Fingerprint};
+use deep_archive::notify::{Notice, Notifier};
+use deep_archive::utils::config::Config;
+use deep_archive::utils::units::format_size;
+
+pub fn run(args: ArchiveArgs, db_path: &str, config: &Config) -> Result<()> {
+    if args.with_catalog && config.database.encrypted {
+        bail!("--with-catalog would write the encrypted catalog's contents to the media in plain text");
+    }
+    let filter = args.filter.to_filter_set();
+    if !args.from_catalog && !filter.is_unrestricted() {
+        bail!("The filters select catalogued artifacts to archive; add --from-catalog");
+    }
+    // Both are required unless a subcommand is given, which is run instead.
+    let base = args.output.as_deref().context("--output is required")?;
+    let mut manager = TransactionManager::new(db_path, &config.database)?;
+    let reader = CatalogReader::open(db_path, &config.database)?;
+    let notifier = Notifier::new(&config.notify);
+    let mut members = if args.from_catalog {
+        let (members, missing) = select::select(&reader, &filter)?;
+        if missing > 0 {
+            warn!("{} matching artifacts have no intact copy left and are left out", missing);
+        }
+        let files: Vec<&Member> = members.iter().filter(|m| !m.is_dir).collect();
+        info!("Selected {} files ({}) from the catalog", files.len(), format_size(files.iter().map(|m| m.size).sum()));
+        if files.is_empty() {
+            info!("Nothing to archive");
+            return Ok(());
+        }
+        members
+    } else {
+        let input_dir = args.input_dir.as_deref().context("--input-dir is required")?;
+        let mut members = archive::walk(input_dir)?;
+        if members.iter().any(|m| m.path.as_os_str() == manifest::METADATA_DIR) {
+            warn!("Leaving out {:?}; every volume gets its own", input_dir.join(manifest::METADATA_DIR));
+            members.retain(|m| !manifest::is_metadata(&m.path));
+        }
+        // Hashes link each archived file to its catalog entry.
+        archive::hash_members(&mut members)?;
+        members
+    };
+
+    if args.incremental {
+        let archived = reader.archived_hashes()?;
+        let files = members.iter().filter(|m| !m.is_dir).count();
+        members = archive::retain_files(members, |m| m.hash.as_ref().is_none_or(|h| !archived.contains(h)));
+        let remaining = members.iter().filter(|m| !m.is_dir).count();
+        info!("{} of {} files are already archived", files - remaining, files);
+        if remaining == 0 {
+            info!("Nothing new to archive");
+            return Ok(());
+        }
+    }
+    let (mut members, duplicates) = match args.dedupe {
+        true => archive::deduplicate(members),
+        false => (members, Vec::new()),
+    };
+    // For `archived` events, which name files where they were found rather
+    // than where they are staged.
+    let originals: HashMap<PathBuf, PathBuf> = members.iter().map(|m| (m.path.clone(), m.source.clone())).collect();
+    // Selected files can be anywhere and in use, so they are held in place
+    // for as long as they are archived.
+    let _staging = match args.from_catalog {
+        true => {
+            let dir = match args.format.is_tape() {
+                true => std::env::temp_dir().join(format!("deep-archive-selection-{}", std::process::id())),
+                false => manifest::staging_path(base, "selection"),
+            };
+            let (staging, linked) = Staging::link(&mut members, &dir)?;
+            let files = members.iter().filter(|m| !m.is_dir).count();
+            info!("Linked {} of {} files into {:?}; the rest are read where they are", linked, files, dir);
+            Some(staging)
+        }
+        false => None,
+    };
+    if !duplicates.is_empty() {
+        let saved: u64 = duplicates.iter().map(|(m, _)| m.size).sum();
+        info!("Leaving out {} duplicate files ({})", duplicates.len(), format_size(saved));
+    }
+    let per_file = if args.with_catalog { manifest::CATALOG_BYTES_PER_FILE } else { 0 }
+        + if args.mets { archive::mets::BYTES_PER_FILE } else { 0 };
+    let volumes = match args.volume_size {
+        Some(capacity) => archive::split(members, capacity, per_file)?,
+        None => vec![members],
+    };
+    // Checked before anything is written, so a volume that can't fit fails
+    // now rather than hours into writing it.
+    let estimates: Vec<u64> = volumes.iter().map(|v| archive::estimated_volume_size(v, per_file)).collect();
+    if let Some(capacity) = args.volume_size {
+        if let Some(i) = estimates.iter().position(|&e| e > capacity) {
+            bail!("Volume {} of {} would take about {}, more than the volume size of {}", i + 1, volumes.len(), format_size(estimates[i]), format_size(capacity));
+        }
+    }
+    info!("About {} to write in {} volumes", format_size(estimates.iter().sum()), volumes.len());
+    if args.format.is_tape() && volumes.len() > 1 && !io::stdin().is_terminal() {
+        bail!("The files need {} tapes; run `archive` on a terminal to be asked for each", volumes.len());
+    }
+
+    // Named up front, so a bad template fails before anything is written.
+    let first_number = reader.next_volume_number(&args.label_prefix)?;
+    // A bag is a directory itself, so only a trailing separator says to
+    // write bags into the directory rather than as it.
+    let into_dir = base.as_os_str().to_string_lossy().ends_with(['/', std::path::MAIN_SEPARATOR])
+        || (base.is_dir() && args.format != ArchiveFormat::Bagit);
+    let mut names = Vec::with_capacity(volumes.len());
+    for (i, volume) in volumes.iter().enumerate() {
+        let seq = first_number + i as u32;
+        let project = &config.archive.project;
+        // Taken from the files themselves, not the manifest and catalog
+        // written later.
+        let epoch = archive::epoch(args.epoch.or(config.archive.epoch), volume);
+        let volume_id = archive::volume_id(&config.archive.volume_id, project, epoch, seq)?;
+        let output = if args.format.is_tape() {
+            base.to_path_buf()
+        } else if into_dir {
+            let stem = archive::expand(&config.archive.file_name, project, epoch, seq)?;
+            if stem.is_empty() || stem.contains(['/', '\\']) {
+                bail!("archive.file_name {:?} gives {:?}, which isn't a file name", config.archive.file_name, stem);
+            }
+            base.join(format!("{}{}", stem, args.format.suffix()))
+        } else if args.volume_size.is_some() {
+            archive::volume_path(base, &args.format.suffix(), i + 1)
+        } else {
+            base.to_path_buf()
+        };
+        names.push((format!("{}-{:04}", args.label_prefix, seq), volume_id, epoch, output));
+    }
+    let outputs: HashSet<&PathBuf> = names.iter().map(|(_, _, _, output)| output).collect();
+    if !args.format.is_tape() && outputs.len() < names.len() {
+        bail!("archive.file_name {:?} gives several volumes the same name; include {{seq}}", config.archive.file_name);
+    }
+
+    let started = Instant::now();
+    let (mut archived_files, mut archived_bytes) = (0, 0);
+    for (i, (volume, (label, volume_id, epoch, output))) in volumes.iter().zip(names).enumerate() {
+        let (name, barcode, staged_at) = if args.format.is_tape() {
+            let barcode = load_tape(&args, i, &label, &output)?;
+            let name = barcode.clone().unwrap_or_else(|| label.clone());
+            // Nothing but the volume goes on the tape.
+            (name, barcode, std::env::temp_dir().join(format!("deep-archive-{}", label)))
+        } else {
+            if let Some(parent) = output.parent().filter(|p| !p.as_os_str().is_empty()) {
+                fs::create_dir_all(parent).with_context(|| format!("Failed to create {:?}", parent))?;
+            }
+            (output.file_name().map(|n| n.to_string_lossy().into_owned()).unwrap_or_default(), None, output.clone())
+        };
+
+        let files = volume.iter().filter(|m| !m.is_dir).count();
+        info!("Writing volume {} ({}/{}) to {:?} ({} files, about {})", label, i + 1, volumes.len(), output, files, format_size(estimates[i]));
+        let stored = match args.layout {
+            ArchiveLayout::Tree => volume.clone(),
+            ArchiveLayout::Cas => archive::content_addressed(volume),
+        };
+        let listed = with_duplicates(volume, &duplicates);
+        // Writes the volume to `output` and returns its registry fingerprint
+        // and, unless skipped, what reading it back found. A `rebuild` is
+        // only fingerprinted: a tape's stream goes nowhere, and LTFS files,
+        // copied as they are, come down to the SHA256SUMS listing them.
+        let write = |output: &Path, rebuild: bool| -> Result<(Fingerprint, Option<reader::Verified>)> {
+            let staging = manifest::staging_path(&staged_at, "manifest.json");
+            let catalog = args.with_catalog.then(|| manifest::staging_path(&staged_at, "catalog.db"));
+            let sums = manifest::staging_path(&staged_at, "SHA256SUMS");
+            let sfv = args.sfv.then(|| manifest::staging_path(&staged_at, "checksums.sfv"));
+            let mets = args.mets.then(|| manifest::staging_path(&staged_at, "mets.xml"));
+            let written = snapshot(&manager, catalog.as_deref(), volume)
+                .and_then(|_| Manifest::build(&reader, &label, &name, i + 1, volumes.len(), &listed))
+                .and_then(|mut manifest| {
+                    manifest.link_duplicates(&duplicates);
+                    if args.layout == ArchiveLayout::Cas {
+                        manifest.link_objects();
+                    }
+                    manifest.attach(&stored, &staging, catalog.as_deref())
+                })
+                .and_then(|members| match &mets {
+                    Some(mets) => archive::mets::attach(&reader, &label, epoch, members, mets),
+                    None => Ok(members),
+                })
+                .and_then(|members| manifest::attach_checksums(members, &sums, sfv.as_deref()))
+                .and_then(|members| {
+                    let content = members.iter().filter(|m| !m.is_dir).map(|m| m.size).sum();
+                    let progress = Progress::new(&label, content);
+                    let mut streamed = None;
+                    let files: reader::Files = match args.format {
+                        ArchiveFormat::Iso => {
+                            iso_builder::create_iso(&members, output, &volume_id, epoch, &config.archive, &progress)?;
+                            reader::iso_files
+                        }
+                        ArchiveFormat::Udf => {
+                            udf_builder::create_udf(&members, output, &volume_id, epoch, &progress)?;
+                            reader::udf_files
+                        }
+                        ArchiveFormat::TarZst => {
+                            tar_builder::create_tar_zst(&members, output, epoch, &config.archive, &progress)?;
+                            reader::tar_zst_files
+                        }
+                        ArchiveFormat::Zip => {
+                            zip_builder::create_zip(&members, output, epoch, &config.archive, &progress)?;
+                            reader::zip_files
+                        }
+                        ArchiveFormat::Bagit => {
+                            let mut info = vec![
+                                ("External-Identifier".to_string(), label.clone()),
+                                ("Bag-Count".to_string(), format!("{} of {}", i + 1, volumes.len())),
+                            ];
+                            info.extend(config.archive.bag_info.iter().map(|(k, v)| (k.clone(), v.clone())));
+                            bagit_builder::create_bag(&members, output, epoch, &info, &progress)?;
+                            reader::bag_files
+                        }
+                        ArchiveFormat::Ltfs => {
+                            if !rebuild {
+                                tape::create_ltfs(&members, output, epoch, &progress)?;
+                            }
+                            reader::tree_files
+                        }
+                        ArchiveFormat::Tape => {
+                            streamed = Some(match rebuild {
+                                true => tape::stream_fingerprint(&members, epoch, &progress)?,
+                                false => tape::write_tape(&members, output, epoch, &progress)?,
+                            });
+                            reader::tape_files
+                        }
+                    };
+                    let verified = if args.no_verify || rebuild {
+                        None
+                    } else {
+                        // A backend can exit successfully and still leave a short or damaged image.
+                        info!("Verifying {:?}", output);
+                        let verified = reader::verify(&members, |visit| files(output, visit))
+                            .with_context(|| format!("Verification of {:?} failed", output))?;
+                        Some(verified)
+                    };
+                    let image = match args.format {
+                        ArchiveFormat::Bagit => bagit_builder::fingerprint(output)?,
+                        ArchiveFormat::Ltfs if rebuild => hasher::fingerprint(&sums)?,
+                        ArchiveFormat::Ltfs => tape::fingerprint(output)?,
+                        // Reading the tape again just for this would take hours.
+                        ArchiveFormat::Tape => streamed.expect("the stream was written"),
+                        _ => hasher::fingerprint(output)?,
+                    };
+                    Ok((image, verified))
+                });
+            for staged in [Some(&staging), catalog.as_ref(), mets.as_ref(), Some(&sums), sfv.as_ref()].into_iter().flatten() {
+                let _ = fs::remove_file(staged);
+            }
+            written
+        };
+        let (image, verified) = write(&output, false)?;
+        if args.verify_reproducible {
+            info!("Building volume {} again to check that it is reproducible", label);
+            let rebuilt = manifest::staging_path(&output, "rebuild");
+            let again = write(&rebuilt, true);
+            if !args.format.is_tape() {
+                let _ = fs::remove_file(&rebuilt).or_else(|_| fs::remove_dir_all(&rebuilt));
+            }
+            let (again, _) = again?;
+            if again.hash != image.hash {
+                bail!(
+                    "Volume {} is not reproducible: building it again gave SHA-256 {} instead of {}; {:?} was left unregistered",
+                    label,
+                    again.hash,
+                    image.hash,
+                    output
+                );
+            }
+            info!("Volume {} rebuilt to the same SHA-256", label);
+        }
+        let size_bytes = image.size;
+        let record = ArchiveVolume {
+            label: label.clone(),
+            name,
+            format: args.format.extension().to_string(),
+            created_at: chrono::Utc::now().timestamp(),
+            capacity_bytes: args.volume_size,
+            size_bytes,
+            sha256: Some(image.hash),
+            barcode,
+        };
+        let (checked, offsets) = match verified {
+            Some(verified) => (Some(verified.files), verified.offsets),
+            None => (None, HashMap::new()),
+        };
+        manager.record_volume(&record, &stored, &offsets)?;
+        for member in volume.iter().filter(|m| !m.is_dir) {
+            let archive_path = match (args.layout, &member.hash) {
+                (ArchiveLayout::Cas, Some(hash)) => archive::archive_path(&archive::object_path(hash)),
+                _ => member.archive_path(),
+            };
+            events::emit(Event::Archived {
+                path: &originals.get(&member.path).unwrap_or(&member.source).to_string_lossy(),
+                hash: member.hash.as_deref(),
+                volume: &label,
+                archive_path: &archive_path,
+            });
+        }
+        match checked {
+            Some(files) => info!("Volume {} written to {:?} ({}), {} files verified", label, output, format_size(size_bytes), files),
+            None => info!("Volume {} written to {:?} ({})", label, output, format_size(size_bytes)),
+        }
+        let files = volume.iter().filter(|m| !m.is_dir).count() as u64;
+        notifier.notify(&Notice::volume_finished(&record, &output, files));
+        archived_files += files;
+        archived_bytes += size_bytes;
+    }
+    notifier.notify(&Notice::ArchiveFinished {
+        volumes: volumes.len() as u64,
+        files: archived_files,
+        size_bytes: archived_bytes,
+        elapsed_secs: started.elapsed().as_secs_f64(),
+    });
+    Ok(())
+}
+
+/// Has the tape for the `i`th volume loaded, asking for it after the
+/// first, and returns its barcode: from `--barcode`, else as LTFS reports
+/// it, else as typed in on a terminal.
+fn load_tape(args: &ArchiveArgs, i: usize, label: &str, output: &Path) -> Result<Option<String>> {
+    let terminal = io::stdin().is_terminal();
+    if i > 0 {
+        let prompt = match args.format {
+            ArchiveFormat::Ltfs => format!("Unmount the last tape, then mount a freshly formatted one for volume {} at {:?} and press Enter: ", label, output),
+            _ => format!("Load a new tape for volume {} into {:?} and press Enter: ", label, output),
+        };
+        ask(&prompt)?;
+    }
+    if let Some(barcode) = args.barcodes.get(i) {
+        return Ok(Some(barcode.clone()));
+    }
+    let reported = match args.format {
+        ArchiveFormat::Ltfs => tape::barcode(output),
+        _ => None,
+    };
+    if reported.is_some() {
+        return Ok(reported);
+    }
+    if !terminal {
+        return Ok(None);
+    }
+    let typed = ask(&format!("Barcode of the tape for volume {} (empty if none): ", label))?;
+    Ok((!typed.is_empty()).then_some(typed))
+}
+
+fn ask(prompt: &str) -> Result<String> {
+    eprint!("{}", prompt);
+    io::stderr().flush()?;
+    let mut line = String::new();
+    io::stdin().lock().read_line(&mut line)?;
+    Ok(line.trim().to_string())
+}
+
+/// `volume` plus the duplicates of its files, in path order, for listing
+/// in its manifest.
+fn with_duplicates(volume: &[Member], duplicates: &[(Member, PathBuf)]) -> Vec<Member> {
+    let stored: HashSet<&Path> = volume.iter().map(|m| m.path.as_path()).collect();
+    let mut listed = volume.to_vec();
+    listed.extend(duplicates.iter().filter(|(_, copy)| stored.contains(copy.as_path())).map(|(m, _)| m.clone()));
+    listed.sort_by(|a, b| a.path.cmp(&b.path));
+    listed
+}
+
+/// Writes the catalog rows of `volume`'s files to `output`, if wanted.
+fn snapshot(manager: &TransactionManager, output: Option<&Path>, volume: &[Member]) -> Result<()> {
+    let Some(output) = output else { return Ok(()) };
+    // Left behind by an interrupted run.
+    let _ = fs::remove_file(output);
+    let hashes: Vec<String> = volume.iter().filter_map(|m| m.hash.clone()).collect();
+    manager.snapshot(output, &hashes)
+}
@@ -0,0 +1,113 @@
+use anyhow::Result;
+use tracing::info;
+
+use crate::cli::BenchArgs;
+use deep_archive::ingest::bench::{self, Measurement, Sample, Workload};
+use deep_archive::ingest::pipeline;
+use deep_archive::media::ffmpeg;
+use deep_archive::utils::config::{Config, HwAccel};
+use deep_archive::utils::units::format_size;
+
+/// Generated files for hashing are this large, enough for the hash to
+/// dominate opening them.
+const SYNTHETIC_FILE_SIZE: usize = 16 * 1024 * 1024;
+
+pub fn run(args: BenchArgs, mut config: Config) -> Result<()> {
+    let workloads: Vec<Workload> = args.workload.iter().map(|&w| w.into()).collect();
+    let threads = if args.threads.is_empty() { default_threads() } else { args.threads.clone() };
+
+    // Loading the models also checks ffmpeg and resolves the configured hwaccel.
+    let engine = if workloads.contains(&Workload::Inference) {
+        pipeline::load_engine(&mut config)
+    } else {
+        ffmpeg::check_binaries(&config.media);
+        config.media.hwaccel = ffmpeg::resolve_hwaccel(&config.media);
+        None
+    };
+    let mut decoders = Vec::new();
+    for &requested in &args.hwaccel {
+        let mut media = config.media.clone();
+        media.hwaccel = requested;
+        // Unsupported ones fall back to software, which is measured once.
+        let resolved = ffmpeg::resolve_hwaccel(&media);
+        if !decoders.contains(&resolved) {
+            decoders.push(resolved);
+        }
+    }
+    if decoders.is_empty() {
+        decoders.push(config.media.hwaccel);
+    }
+
+    let mut measurements = Vec::new();
+    for &workload in &workloads {
+        let sample = match &args.sample {
+            Some(dir) => Sample::from_dir(dir, workload, args.files)?,
+            None => {
+                info!("Generating {} files to {}", args.files, workload.name());
+                Sample::synthetic(workload, args.files, SYNTHETIC_FILE_SIZE)?
+            }
+        };
+        let decoders: &[HwAccel] = if workload == Workload::Decode { &decoders } else { &[config.media.hwaccel] };
+        for &hwaccel in decoders {
+            let mut config = config.clone();
+            config.media.hwaccel = hwaccel;
+            for &n in &threads {
+                info!("Measuring {} with {} threads over {} files", workload.name(), n, sample.len());
+                let measurement = bench::measure(workload, &sample, n, &config, engine.as_deref())?;
+                if args.json {
+                    println!("{}", serde_json::to_string(&measurement)?);
+                }
+                measurements.push(measurement);
+            }
+        }
+    }
+    if args.json {
+        return Ok(());
+    }
+
+    println!("WORKLOAD\tTHREADS\tHWACCEL\tFILES\tSECONDS\tFILES/S\tSIZE/S\tFRAMES/S\tERRORS");
+    for m in &measurements {
+        println!(
+            "{}\t{}\t{}\t{}\t{:.2}\t{:.1}\t{}\t{}\t{}",
+            m.workload.name(),
+            m.threads,
+            m.hwaccel.unwrap_or("-"),
+            m.files,
+            m.secs,
+            m.files_per_sec(),
+            if m.bytes > 0 { format_size(m.bytes_per_sec() as u64) } else { "-".to_string() },
+            if m.frames > 0 { format!("{:.1}", m.frames_per_sec()) } else { "-".to_string() },
+            m.errors
+        );
+    }
+
+    println!();
+    for &workload in &workloads {
+        if let Some(best) = bench::best(&measurements, workload) {
+            println!("{}", suggestion(best));
+        }
+    }
+    Ok(())
+}
+
+/// 1, 2, 4, ... and the CPU count itself.
+fn default_threads() -> Vec<usize> {
+    let cpus = std::thread::available_parallelism().map_or(1, |n| n.get());
+    let mut threads: Vec<usize> = std::iter::successors(Some(1), |n| Some(n * 2)).take_while(|&n| n < cpus).collect();
+    threads.push(cpus);
+    threads
+}
+
+fn suggestion(best: &Measurement) -> String {
+    match best.workload {
+        Workload::Hash => format!("hash: fastest at {} threads; set pipeline.hashers = {}", best.threads, best.threads),
+        Workload::Decode => format!(
+            "decode: fastest at {} threads with hwaccel {}; set pipeline.workers = {} and media.hwaccel = \"{}\"",
+            best.threads,
+            best.hwaccel.unwrap_or("none"),
+            best.threads,
+            best.hwaccel.unwrap_or("none")
+        ),
+        Workload::Inference => format!("inference: fastest at {} threads; weigh against decode for pipeline.workers", best.threads),
+    }
+}
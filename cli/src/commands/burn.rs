@@ -0,0 +1,56 @@
+use anyhow::{Result, Context, bail};
+use tracing::{info, warn};
+use crate::cli::BurnArgs;
+use deep_archive::archive::burner;
+use deep_archive::database::repo::{CatalogReader, TransactionManager, VolumeDisc};
+use deep_archive::ingest::hasher;
+use deep_archive::utils::config::Config;
+
+/// Burns a registered volume image, reads the disc back and records it
+/// under the volume, so the registry knows how many good copies exist.
+pub fn run(args: BurnArgs, db_path: &str, config: &Config) -> Result<()> {
+    let reader = CatalogReader::open(db_path, &config.database)?;
+    let image = hasher::fingerprint(&args.image)?;
+    let name = args.image.file_name().map(|n| n.to_string_lossy().into_owned()).unwrap_or_default();
+    let registry = reader.volumes()?;
+    // Volumes registered before checksums were kept can only go by name.
+    let volume = registry
+        .iter()
+        .find(|s| s.volume.sha256.as_deref() == Some(image.hash.as_str()))
+        .or_else(|| match registry.iter().filter(|s| s.volume.name == name).collect::<Vec<_>>().as_slice() {
+            [only] if only.volume.sha256.is_none() => Some(only),
+            _ => None,
+        })
+        .map(|s| &s.volume)
+        .with_context(|| format!("{:?} isn't a registered volume image, or was changed after `archive` wrote it", args.image))?;
+    if !matches!(volume.format.as_str(), "iso" | "udf") {
+        bail!("Volume {} is a {} file; only iso and udf images can be burned", volume.label, volume.format);
+    }
+
+    let drive = burner::drive(&args.device);
+    burner::burn(&args.image, &args.device, config.archive.burner)?;
+    let verified = match args.no_verify {
+        true => None,
+        false => {
+            info!("Reading the disc in {} back", args.device);
+            let disc = burner::read_back(&args.device, image.size)?;
+            Some(disc == image.hash)
+        }
+    };
+    let disc = VolumeDisc {
+        label: volume.label.clone(),
+        burned_at: chrono::Utc::now().timestamp(),
+        device: args.device.clone(),
+        drive,
+        media: burner::media(&args.device),
+        verified,
+    };
+    TransactionManager::new(db_path, &config.database)?.record_disc(&disc)?;
+
+    match verified {
+        Some(true) => info!("Burned volume {} to {} and verified it", volume.label, args.device),
+        Some(false) => bail!("The disc in {} doesn't match {:?}; recorded as failed", args.device, args.image),
+        None => warn!("Burned volume {} to {} without reading it back", volume.label, args.device),
+    }
+    Ok(())
+}
@@ -0,0 +1,138 @@
+use std::path::PathBuf;
+use std::sync::Arc;
+use anyhow::{Result, bail};
+use tracing::info;
+
+use crate::cli::CtlCommand;
+use deep_archive::daemon::{self, control, Daemon, Status};
+use deep_archive::server::api::Api;
+use deep_archive::server::Server;
+use deep_archive::utils::config::Config;
+use deep_archive::utils::units::format_duration;
+
+/// Runs until SIGTERM or Ctrl-C, which let the run in progress save what
+/// it has, as an interrupted `ingest` does. SIGHUP reloads the config, as
+/// `ctl reload` does; `watch` is watched whatever the config says.
+pub fn run(db_path: &str, config: Config, config_path: Option<PathBuf>, watch: Vec<PathBuf>) -> Result<()> {
+    if config.daemon.watch.is_empty() && watch.is_empty() && !config.daemon.serve {
+        bail!("Nothing to do: no daemon.watch directories, and the API isn't served");
+    }
+    let daemon = Arc::new(Daemon::new(db_path, config.clone(), config_path, watch));
+    let _socket = listen(&config, &daemon)?;
+    let _server = match config.daemon.serve {
+        true => {
+            let api = Api::new(db_path, config.clone())?;
+            let server = Server::start(&config.server, move |request| api.handle(request))?;
+            daemon.serving(server.url());
+            Some(server)
+        }
+        false => None,
+    };
+    handle_signals(daemon.clone())?;
+    daemon::notify_supervisor("READY=1");
+    let result = daemon.run();
+    daemon::notify_supervisor("STOPPING=1");
+    info!("Daemon stopped");
+    result
+}
+
+#[cfg(unix)]
+fn listen(config: &Config, daemon: &Arc<Daemon>) -> Result<control::ControlSocket> {
+    let path = config.daemon.socket.clone().unwrap_or_else(control::default_socket);
+    control::listen(&path, daemon.clone())
+}
+
+#[cfg(not(unix))]
+fn listen(_config: &Config, _daemon: &Arc<Daemon>) -> Result<()> {
+    tracing::warn!("There is no control socket on this platform; `ctl` can't reach the daemon");
+    Ok(())
+}
+
+/// The first stop signal lets the run in progress finish what it's doing
+/// and save it; a second one quits at once.
+fn stop(daemon: &Daemon) {
+    use deep_archive::media::ffmpeg;
+    use tracing::{error, warn};
+    if daemon.stopping() {
+        error!("Stopped again, killing ffmpeg processes");
+        ffmpeg::kill_all_children();
+        std::process::exit(130);
+    }
+    warn!("Stopping; finishing the files in progress and saving the catalog");
+    daemon.stop();
+}
+
+#[cfg(unix)]
+fn handle_signals(daemon: Arc<Daemon>) -> Result<()> {
+    use signal_hook::consts::{SIGHUP, SIGINT, SIGTERM};
+    use signal_hook::iterator::Signals;
+    let mut signals = Signals::new([SIGHUP, SIGINT, SIGTERM])?;
+    std::thread::Builder::new().name("signals".to_string()).spawn(move || {
+        for signal in signals.forever() {
+            match signal {
+                SIGHUP => {
+                    if let Err(e) = daemon.reload() {
+                        tracing::error!("Failed to reload the config: {:#}", e);
+                    }
+                }
+                _ => stop(&daemon),
+            }
+        }
+    })?;
+    Ok(())
+}
+
+#[cfg(not(unix))]
+fn handle_signals(daemon: Arc<Daemon>) -> Result<()> {
+    ctrlc::set_handler(move || stop(&daemon))?;
+    Ok(())
+}
+
+pub fn ctl(command: CtlCommand, socket: Option<PathBuf>, json: bool) -> Result<()> {
+    let status = request(socket.unwrap_or_else(control::default_socket), command)?;
+    if json {
+        println!("{}", serde_json::to_string(&status)?);
+        return Ok(());
+    }
+    print(&status);
+    Ok(())
+}
+
+#[cfg(unix)]
+fn request(socket: PathBuf, command: CtlCommand) -> Result<Status> {
+    control::request(&socket, command.into())
+}
+
+#[cfg(not(unix))]
+fn request(_socket: PathBuf, _command: CtlCommand) -> Result<Status> {
+    bail!("`ctl` needs a Unix control socket, which this platform lacks")
+}
+
+fn print(status: &Status) {
+    let now = chrono::Utc::now().timestamp();
+    let up = format_duration((now - status.started_at).max(0) as f64);
+    println!("Daemon {}, up {}{}", status.pid, up, if status.paused { ", paused" } else { "" });
+    if status.watching.is_empty() {
+        println!("Watching nothing");
+    } else {
+        let dirs: Vec<String> = status.watching.iter().map(|d| d.display().to_string()).collect();
+        let looked = match status.last_poll {
+            Some(at) => format!("last looked {} ago", format_duration((now - at).max(0) as f64)),
+            None => "not looked at yet".to_string(),
+        };
+        println!("Watching {} every {}s, {}", dirs.join(", "), status.interval_secs, looked);
+    }
+    if status.ingesting > 0 || status.settling > 0 {
+        println!("Ingesting {} files, {} still being written", status.ingesting, status.settling);
+    }
+    match &status.last_run {
+        Some(summary) => println!("{} runs; the last: {}", status.runs, summary.to_string().lines().next().unwrap_or_default()),
+        None => println!("{} runs", status.runs),
+    }
+    if let Some(error) = &status.last_error {
+        println!("Last error: {}", error);
+    }
+    if let Some(addr) = &status.serving {
+        println!("Serving {}", addr);
+    }
+}
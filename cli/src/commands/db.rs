@@ -0,0 +1,62 @@
+use anyhow::{Result, bail};
+use tracing::info;
+use crate::cli::DbCommand;
+use deep_archive::database::repo::TransactionManager;
+use deep_archive::database::store::{CatalogStore, LeaseKind};
+use deep_archive::utils::config::Config;
+use deep_archive::utils::units::format_size;
+
+pub fn run(command: DbCommand, db_path: &str, config: &Config) -> Result<()> {
+    match command {
+        DbCommand::Check { json } => check(db_path, config, json),
+        DbCommand::Optimize { no_vacuum } => optimize(db_path, config, !no_vacuum),
+        #[cfg(feature = "sqlcipher")]
+        DbCommand::Encrypt { output } => encrypt(db_path, config, &output),
+    }
+}
+
+#[cfg(feature = "sqlcipher")]
+fn encrypt(db_path: &str, config: &Config, output: &std::path::Path) -> Result<()> {
+    let tm = TransactionManager::new(db_path, &config.database)?;
+    let key = deep_archive::database::encryption::resolve(&config.database)?;
+    tm.encrypt_to(output, key)?;
+    info!("Wrote encrypted catalog to {:?}; set database.encrypted = true to use it", output);
+    Ok(())
+}
+
+fn optimize(db_path: &str, config: &Config, vacuum: bool) -> Result<()> {
+    let mut tm = TransactionManager::new(db_path, &config.database)?;
+    tm.acquire_lease(LeaseKind::Exclusive)?;
+    let (before, after) = tm.optimize(vacuum)?;
+    info!("Catalog optimized: {} -> {}", format_size(before), format_size(after));
+    Ok(())
+}
+
+fn check(db_path: &str, config: &Config, json: bool) -> Result<()> {
+    let results = TransactionManager::new(db_path, &config.database)?.check()?;
+    let problems: u64 = results.iter().map(|r| r.count).sum();
+
+    if json {
+        let report = serde_json::json!({ "ok": problems == 0, "problems": problems, "checks": results });
+        println!("{}", serde_json::to_string_pretty(&report)?);
+    } else {
+        for result in &results {
+            if result.ok() {
+                println!("ok\t{}", result.name);
+                continue;
+            }
+            println!("FAIL\t{} ({} problems)", result.name, result.count);
+            for problem in &result.problems {
+                println!("\t{}", problem);
+            }
+            if result.count > result.problems.len() as u64 {
+                println!("\t... and {} more", result.count - result.problems.len() as u64);
+            }
+        }
+    }
+
+    if problems > 0 {
+        bail!("Catalog check found {} problems", problems);
+    }
+    Ok(())
+}
@@ -0,0 +1,31 @@
+use anyhow::Result;
+use crate::cli::DedupeArgs;
+use deep_archive::database::repo::CatalogReader;
+use deep_archive::utils::config::Config;
+use deep_archive::utils::units::format_size;
+
+/// Lists each duplicated artifact with its copies; nothing is deleted.
+pub fn run(args: DedupeArgs, db_path: &str, config: &Config) -> Result<()> {
+    let reader = CatalogReader::open(db_path, &config.database)?;
+    let groups = reader.duplicate_groups(&args.filter.to_filter_set())?;
+
+    if !args.summary {
+        for group in &groups {
+            println!(
+                "{}\t{} x {}\t{} wasted",
+                group.hash_sha256,
+                group.copies,
+                group.size_bytes.map_or_else(|| "?".to_string(), format_size),
+                format_size(group.wasted_bytes)
+            );
+            for location in reader.paths(group.artifact_id)? {
+                println!("\t{}", location.path);
+            }
+        }
+    }
+
+    let wasted: u64 = groups.iter().map(|g| g.wasted_bytes).sum();
+    let copies: u64 = groups.iter().map(|g| g.copies - 1).sum();
+    println!("{} duplicated artifacts, {} extra copies, {} reclaimable", groups.len(), copies, format_size(wasted));
+    Ok(())
+}
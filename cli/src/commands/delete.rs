@@ -0,0 +1,22 @@
+use anyhow::{bail, Result};
+use tracing::info;
+use crate::cli::DeleteArgs;
+use deep_archive::database::repo::TransactionManager;
+use deep_archive::database::store::{CatalogStore, LeaseKind};
+use deep_archive::utils::config::Config;
+
+/// Tombstones matching artifacts so they drop out of queries but keep their
+/// history; `--purge` removes them outright.
+pub fn run(args: DeleteArgs, db_path: &str, config: &Config) -> Result<()> {
+    let filter = args.filter.to_filter_set();
+    if filter.is_unrestricted() {
+        bail!("Refusing to delete the whole catalog; pass at least one filter");
+    }
+
+    let mut tm = TransactionManager::new(db_path, &config.database)?;
+    tm.acquire_lease(LeaseKind::Exclusive)?;
+    let affected = tm.delete_matching(&filter, &args.reason, args.purge)?;
+    let verb = if args.purge { "Purged" } else { "Tombstoned" };
+    info!("{} {} artifacts", verb, affected);
+    Ok(())
+}
@@ -0,0 +1,101 @@
+use std::collections::{BTreeMap, BTreeSet};
+use std::path::PathBuf;
+use anyhow::Result;
+use tracing::{info, warn};
+use crate::cli::ErrorsCommand;
+use crate::commands::ingest;
+use deep_archive::ingest::pipeline::{FailedFile, Input};
+use deep_archive::database::store;
+use deep_archive::utils::config::Config;
+use deep_archive::utils::units::format_timestamp;
+
+pub fn run(command: ErrorsCommand, db_path: &str, config: Config) -> Result<()> {
+    match command {
+        ErrorsCommand::List { run_id, all } => list(db_path, &config, run_id, all),
+        ErrorsCommand::Retry { run_id } => retry(db_path, config, run_id),
+    }
+}
+
+fn list(db_path: &str, config: &Config, run_id: Option<i64>, all: bool) -> Result<()> {
+    let mut store = store::open(db_path, &config.database)?;
+    println!("ID\tRUN\tAT\tSTAGE\tRESOLVED\tPATH\tERROR\tSTDERR");
+    for e in store.errors(run_id, all)? {
+        println!(
+            "{}\t{}\t{}\t{}\t{}\t{}\t{}\t{}",
+            e.id,
+            e.run_id.map_or_else(|| "-".to_string(), |r| r.to_string()),
+            format_timestamp(Some(e.created_at)),
+            e.stage,
+            format_timestamp(e.resolved_at),
+            e.path,
+            e.error.replace('\n', " | "),
+            // Keep one error per line; ffmpeg's stderr tail spans several.
+            e.stderr.map_or_else(|| "-".to_string(), |stderr| stderr.replace('\n', " | "))
+        );
+    }
+    Ok(())
+}
+
+fn retry(db_path: &str, mut config: Config, run_id: Option<i64>) -> Result<()> {
+    let mut store = store::open(db_path, &config.database)?;
+    let mut paths = BTreeSet::new();
+    for e in store.errors(run_id, false)? {
+        let path = PathBuf::from(&e.path);
+        // A scan failure names the directory, which can't be retried file by file.
+        if path.is_file() {
+            paths.insert(path);
+        } else {
+            warn!("Skipping {:?} ({} error): not a file", path, e.stage);
+        }
+    }
+    drop(store);
+
+    if paths.is_empty() {
+        info!("Nothing to retry");
+        return Ok(());
+    }
+
+    info!("Retrying {} files", paths.len());
+    // A file that failed after it was hashed is usually in the catalog already.
+    config.pipeline.skip_known = false;
+    let input_roots: Vec<String> = paths.iter().map(|p| p.to_string_lossy().to_string()).collect();
+    let options = serde_json::json!({ "retry_of_run": run_id }).to_string();
+    ingest::pipeline(Input::Files(paths.into_iter().collect()), input_roots, options, db_path, config)
+}
+
+/// Re-ingests the files with an unresolved error from the dead letters in
+/// `ingest_errors`. A file that is unchanged since it failed picks up after
+/// the stages it got through, e.g. isn't hashed again.
+pub fn replay(db_path: &str, mut config: Config, run_id: Option<i64>, stages: &[String]) -> Result<()> {
+    let mut store = store::open(db_path, &config.database)?;
+    let mut files = BTreeMap::new();
+    for e in store.errors(run_id, false)? {
+        if !stages.is_empty() && !stages.contains(&e.stage) {
+            continue;
+        }
+        let path = PathBuf::from(&e.path);
+        if !path.is_file() {
+            warn!("Skipping {:?} ({} error): not a file", path, e.stage);
+            continue;
+        }
+        // Errors come oldest first; the latest one that got anywhere wins.
+        let partial = files.entry(path).or_insert(None);
+        if e.partial.is_some() {
+            *partial = e.partial;
+        }
+    }
+    drop(store);
+
+    if files.is_empty() {
+        info!("Nothing to replay");
+        return Ok(());
+    }
+
+    let resumable = files.values().filter(|p| p.is_some()).count();
+    info!("Replaying {} files, {} of them from where they failed", files.len(), resumable);
+    config.pipeline.skip_known = false;
+    let input_roots: Vec<String> = files.keys().map(|p| p.to_string_lossy().to_string()).collect();
+    let options = serde_json::json!({ "replay_of_run": run_id, "stages": stages }).to_string();
+    let files = files.into_iter().map(|(path, partial)| FailedFile { path, partial }).collect();
+    ingest::pipeline(Input::Replay(files), input_roots, options, db_path, config)
+}
@@ -0,0 +1,101 @@
+use std::fs::File;
+use std::io::{self, BufWriter, Write};
+use std::path::Path;
+use anyhow::{Result, Context};
+use tracing::{info, warn};
+use crate::cli::{ExportArgs, ExportFormat};
+use deep_archive::archive::{media_server, xmp};
+use deep_archive::database::hydrus;
+use deep_archive::media::exiftool;
+use deep_archive::database::repo::{CatalogReader, FilterSet};
+use deep_archive::utils::config::Config;
+
+#[cfg(feature = "parquet")]
+use deep_archive::database::parquet;
+
+pub fn run(args: ExportArgs, db_path: &str, config: &Config) -> Result<()> {
+    let reader = CatalogReader::open(db_path, &config.database)?;
+    let filter = args.filter.to_filter_set();
+
+    match args.format {
+        ExportFormat::Jsonl => {
+            let count = export_jsonl(&reader, &filter, args.output.as_deref())?;
+            if let Some(path) = &args.output {
+                info!("Exported {} artifacts to {:?}", count, path);
+            }
+        }
+        #[cfg(feature = "parquet")]
+        ExportFormat::Parquet => {
+            let dir = args.output.as_deref().context("--output <DIR> is required for parquet")?;
+            let count = parquet::write_parquet(&reader, &filter, dir)?;
+            info!("Exported {} artifacts to {:?}", count, dir);
+        }
+        ExportFormat::MediaServer => {
+            let dir = args.output.as_deref().context("--output <DIR> is required for media-server")?;
+            let summary = media_server::export(&reader, &filter, dir, args.link.into())?;
+            if summary.missing > 0 {
+                warn!("{} videos were skipped because none of their paths exist", summary.missing);
+            }
+        }
+        ExportFormat::Xmp => {
+            let summary = xmp::export(&reader, &filter, args.output.as_deref(), args.sidecar_name.into(), args.overwrite)?;
+            if summary.existing > 0 {
+                warn!("{} sidecars already existed and were left alone; pass --overwrite to replace them", summary.existing);
+            }
+            if summary.missing > 0 {
+                warn!("{} artifacts were skipped because their directory no longer exists", summary.missing);
+            }
+        }
+        ExportFormat::Hydrus => {
+            let path = args.output.as_deref().context("--output <FILE> is required for hydrus")?;
+            hydrus::export(&reader, &filter, path)?;
+        }
+        ExportFormat::Exiftool => {
+            let count = export_exiftool(&reader, &filter, args.output.as_deref(), args.numeric)?;
+            if let Some(path) = &args.output {
+                info!("Exported {} artifacts to {:?}", count, path);
+            }
+        }
+    }
+    Ok(())
+}
+
+fn export_jsonl(reader: &CatalogReader, filter: &FilterSet, output: Option<&Path>) -> Result<u64> {
+    let out: Box<dyn Write> = match output {
+        Some(path) => Box::new(File::create(path).with_context(|| format!("Failed to create {:?}", path))?),
+        None => Box::new(io::stdout().lock()),
+    };
+    let mut out = BufWriter::new(out);
+    let count = write_jsonl(reader, filter, &mut out)?;
+    out.flush()?;
+    Ok(count)
+}
+
+/// Streams a JSON array as `exiftool -json` prints one, an object per line.
+fn export_exiftool(reader: &CatalogReader, filter: &FilterSet, output: Option<&Path>, numeric: bool) -> Result<u64> {
+    let out: Box<dyn Write> = match output {
+        Some(path) => Box::new(File::create(path).with_context(|| format!("Failed to create {:?}", path))?),
+        None => Box::new(io::stdout().lock()),
+    };
+    let mut out = BufWriter::new(out);
+    let mut count = 0;
+    for artifact in reader.find(filter) {
+        out.write_all(if count == 0 { b"[" } else { b",\n" })?;
+        serde_json::to_writer(&mut out, &exiftool::json(&artifact?, numeric))?;
+        count += 1;
+    }
+    out.write_all(if count == 0 { b"[]\n" } else { b"]\n" })?;
+    out.flush()?;
+    Ok(count)
+}
+
+/// Streams one artifact per line; memory use doesn't grow with the catalog.
+fn write_jsonl(reader: &CatalogReader, filter: &FilterSet, out: &mut impl Write) -> Result<u64> {
+    let mut count = 0;
+    for artifact in reader.find(filter) {
+        serde_json::to_writer(&mut *out, &artifact?)?;
+        out.write_all(b"\n")?;
+        count += 1;
+    }
+    Ok(count)
+}
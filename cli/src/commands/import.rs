@@ -0,0 +1,68 @@
+use std::fs::File;
+use std::io::{self, BufRead, BufReader};
+use std::path::Path;
+use anyhow::{Result, Context};
+use tracing::info;
+use crate::cli::{ExportFormat, ImportArgs};
+use deep_archive::database::hydrus;
+use deep_archive::database::repo::Artifact;
+use deep_archive::database::store::{self, CatalogStore};
+use deep_archive::utils::config::Config;
+
+/// Imports as a run of its own, so a bad import can be undone with `runs rollback`.
+pub fn run(args: ImportArgs, db_path: &str, config: &Config) -> Result<()> {
+    let mut tm = store::open(db_path, &config.database)?;
+    // Tags existing artifacts rather than adding any, so there is no run
+    // to roll back.
+    if args.format == ExportFormat::Hydrus {
+        hydrus::import(tm.as_mut(), &hydrus::TagArchive::open(&args.input)?)?;
+        return Ok(());
+    }
+    let run_id = tm.begin_run(
+        &[args.input.to_string_lossy().to_string()],
+        &serde_json::json!({ "import": args.format }).to_string(),
+    )?;
+
+    let result = match args.format {
+        ExportFormat::Jsonl => read_jsonl(&args.input, tm.as_mut()),
+        #[cfg(feature = "parquet")]
+        ExportFormat::Parquet => Err(anyhow::anyhow!("Importing parquet is not supported; use jsonl")),
+        ExportFormat::MediaServer => Err(anyhow::anyhow!("Importing a media server library is not supported; use jsonl")),
+        ExportFormat::Xmp => Err(anyhow::anyhow!("Importing XMP sidecars is not supported; use jsonl")),
+        ExportFormat::Exiftool => Err(anyhow::anyhow!("Importing exiftool JSON is not supported; use jsonl")),
+        ExportFormat::Hydrus => unreachable!("imported above"),
+    };
+
+    match result {
+        Ok(count) => {
+            tm.finish_run("completed", 0, None)?;
+            info!("Imported {} artifacts as run {}", count, run_id);
+            Ok(())
+        }
+        Err(e) => {
+            tm.finish_run("failed", 1, None)?;
+            Err(e)
+        }
+    }
+}
+
+fn read_jsonl(input: &Path, tm: &mut dyn CatalogStore) -> Result<u64> {
+    let reader: Box<dyn BufRead> = if input == Path::new("-") {
+        Box::new(io::stdin().lock())
+    } else {
+        Box::new(BufReader::new(File::open(input).with_context(|| format!("Failed to open {:?}", input))?))
+    };
+
+    let mut count = 0;
+    for (i, line) in reader.lines().enumerate() {
+        let line = line?;
+        if line.trim().is_empty() {
+            continue;
+        }
+        let artifact: Artifact = serde_json::from_str(&line)
+            .with_context(|| format!("Invalid artifact on line {}", i + 1))?;
+        tm.add(artifact.into())?;
+        count += 1;
+    }
+    Ok(count)
+}
@@ -0,0 +1,66 @@
+use std::collections::HashSet;
+use std::path::Path;
+use anyhow::{Result, bail};
+use tracing::info;
+use crate::cli::IndexCommand;
+use deep_archive::archive::{self, index};
+use deep_archive::database::repo::CatalogReader;
+use deep_archive::ingest::hasher;
+use deep_archive::utils::config::Config;
+
+pub fn run(command: IndexCommand, db_path: &str, config: &Config) -> Result<()> {
+    match command {
+        IndexCommand::Write { dir } => {
+            let reader = CatalogReader::open(db_path, &config.database)?;
+            let (volumes, files) = index::write(&reader, &dir)?;
+            info!("Indexed {} distinct files on {} volumes in {:?}", files, volumes, dir);
+            Ok(())
+        }
+        IndexCommand::Lookup { index, items, json } => lookup(&index, &items, json),
+    }
+}
+
+/// Prints the volumes holding each of `items` according to the index at
+/// `index`, without opening the catalog.
+fn lookup(index: &Path, items: &[String], json: bool) -> Result<()> {
+    let mut wanted = Vec::new();
+    for item in items {
+        let path = Path::new(item);
+        if path.is_dir() {
+            for member in archive::walk(path)?.into_iter().filter(|m| !m.is_dir) {
+                let hash = hasher::fingerprint(&member.source)?.hash;
+                wanted.push((member.source.display().to_string(), hash));
+            }
+        } else if path.exists() {
+            wanted.push((item.clone(), hasher::fingerprint(path)?.hash));
+        } else if item.len() == 64 && item.chars().all(|c| c.is_ascii_hexdigit()) {
+            wanted.push((item.clone(), item.to_lowercase()));
+        } else {
+            bail!("{:?} is neither a file nor a SHA-256", item);
+        }
+    }
+
+    let hashes: HashSet<String> = wanted.iter().map(|(_, hash)| hash.clone()).collect();
+    let found = index::lookup(index, &hashes)?;
+    let mut missing = 0;
+    for (item, hash) in &wanted {
+        let copies = found.iter().find(|e| &e.sha256 == hash).map(|e| e.copies.as_slice()).unwrap_or_default();
+        if copies.is_empty() {
+            missing += 1;
+        }
+        if json {
+            println!("{}", serde_json::json!({ "item": item, "sha256": hash, "copies": copies }));
+            continue;
+        }
+        if copies.is_empty() {
+            println!("{}\t-\tnot on any volume", item);
+        }
+        for copy in copies {
+            println!("{}\t{}\t{}\t{}", item, copy.volume, copy.barcode.as_deref().unwrap_or(&copy.name), copy.path);
+        }
+    }
+    if missing > 0 {
+        bail!("{} of {} files are on no indexed volume", missing, wanted.len());
+    }
+    Ok(())
+}
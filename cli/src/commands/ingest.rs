@@ -0,0 +1,182 @@
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicBool, Ordering};
+use anyhow::{Result, bail};
+use tracing::{info, warn, error};
+
+use crate::cli::{ArchiveArgs, ArchiveFormat, ArchiveLayout, FilterArgs, IngestArgs};
+use crate::commands::archive;
+use deep_archive::ingest::pipeline::{self, Fetched, Input};
+#[cfg(feature = "urls")]
+use deep_archive::ingest::download::{self, Downloader};
+#[cfg(feature = "webdav")]
+use deep_archive::ingest::webdav::Share;
+use deep_archive::ingest::ytdlp;
+use deep_archive::media::ffmpeg;
+use deep_archive::utils::config::Config;
+
+pub fn run(args: IngestArgs, db_path: &str, mut config: Config) -> Result<()> {
+    info!("Deep Archive Pipeline Starting...");
+    // What the ISO is written from: the mirror of a WebDAV share, or the
+    // spool URLs or videos were downloaded into.
+    let mut archive_dir = args.input_dir.clone();
+    let (input, input_roots) = match (&args.input_dir, &args.urls, &args.ytdlp) {
+        (_, Some(list), _) => {
+            info!("Input: URLs in {:?}", list);
+            let (files, spool) = self::download(list, &config)?;
+            archive_dir = Some(spool);
+            (Input::Fetched(files), vec![root(list)])
+        }
+        (_, _, Some(url)) => {
+            info!("Input: {} with yt-dlp", url);
+            // Installed early, so a second Ctrl-C kills yt-dlp too.
+            handle_interrupts()?;
+            let (files, spool) = ytdlp::download(url, &config.download, &config.retry)?;
+            archive_dir = Some(spool);
+            (Input::Fetched(files), vec![url.clone()])
+        }
+        (Some(dir), _, _) if is_webdav(dir) => {
+            let url = dir.to_string_lossy().to_string();
+            info!("Input: {}", url);
+            let (files, mirror) = self::mirror(&url, &config)?;
+            archive_dir = Some(mirror);
+            (Input::Fetched(files), vec![url])
+        }
+        (Some(dir), _, _) => {
+            info!("Input: {:?}", dir);
+            (Input::Directory(dir.clone()), vec![root(dir)])
+        }
+        (None, _, _) => {
+            for file in &args.files {
+                if !file.is_file() {
+                    bail!("{:?} is not a file; ingest a directory with --input-dir", file);
+                }
+            }
+            info!("Input: {} files", args.files.len());
+            (Input::Files(args.files.clone()), args.files.iter().map(|f| root(f)).collect())
+        }
+    };
+    let options = serde_json::to_string(&args)?;
+    config.pipeline.hashers = args.hashers.or(config.pipeline.hashers);
+    config.pipeline.workers = args.workers.or(config.pipeline.workers);
+    config.pipeline.metrics_addr = args.metrics.clone().or(config.pipeline.metrics_addr);
+    config.pipeline.adaptive |= args.adaptive;
+    config.pipeline.rebalance |= args.rebalance;
+    config.pipeline.skip_known &= !args.reanalyze;
+    config.pipeline.serve_workers = args.serve_workers.clone().or(config.pipeline.serve_workers);
+    let archive_config = config.clone();
+    match args.resume {
+        Some(run_id) => {
+            handle_interrupts()?;
+            pipeline::resume(input, input_roots, run_id, db_path, config)?;
+        }
+        None => pipeline(input, input_roots, options, db_path, config)?,
+    }
+
+    // Single files are added to the catalog only; archive them with the rest.
+    let Some(input_dir) = archive_dir else {
+        info!("Pipeline completed.");
+        return Ok(());
+    };
+
+    info!("Creating ISO archive at {:?}", args.output_iso);
+    let archive_args = ArchiveArgs {
+        command: None,
+        input_dir: Some(input_dir),
+        from_catalog: false,
+        filter: FilterArgs::default(),
+        output: Some(args.output_iso.clone()),
+        format: ArchiveFormat::Iso,
+        layout: ArchiveLayout::Tree,
+        incremental: false,
+        dedupe: false,
+        volume_size: None,
+        with_catalog: false,
+        sfv: false,
+        mets: false,
+        label_prefix: "VOL".to_string(),
+        epoch: None,
+        no_verify: false,
+        verify_reproducible: false,
+        barcodes: Vec::new(),
+    };
+    let iso = archive::run(archive_args, db_path, &archive_config);
+    if let Err(e) = iso {
+        error!("Archival failed: {}", e);
+    } else {
+        info!("ISO created successfully.");
+    }
+
+    info!("Pipeline completed.");
+    Ok(())
+}
+
+fn is_webdav(dir: &Path) -> bool {
+    dir.to_str().is_some_and(|dir| dir.starts_with("webdav://") || dir.starts_with("webdavs://"))
+}
+
+/// Brings the mirror of the share at `url` up to date, returning its files
+/// and where it is.
+#[cfg(feature = "webdav")]
+fn mirror(url: &str, config: &Config) -> Result<(Vec<Fetched>, PathBuf)> {
+    let share = Share::open(url, &config.webdav)?;
+    let files = share.sync(&config.retry)?;
+    Ok((files, share.mirror))
+}
+
+#[cfg(not(feature = "webdav"))]
+fn mirror(url: &str, _config: &Config) -> Result<(Vec<Fetched>, PathBuf)> {
+    bail!("Ingesting {} needs a build with the `webdav` feature", url)
+}
+
+/// Downloads the URLs listed in `list`, returning the files and the spool
+/// they are in.
+#[cfg(feature = "urls")]
+fn download(list: &Path, config: &Config) -> Result<(Vec<Fetched>, PathBuf)> {
+    let urls = download::read_list(list)?;
+    let downloader = Downloader::new(&config.download);
+    let files = downloader.fetch_all(&urls, &config.retry)?;
+    Ok((files, downloader.spool))
+}
+
+#[cfg(not(feature = "urls"))]
+fn download(_list: &Path, _config: &Config) -> Result<(Vec<Fetched>, PathBuf)> {
+    bail!("Ingesting --urls needs a build with the `urls` feature")
+}
+
+/// The absolute path a run records as its input root.
+fn root(path: &Path) -> String {
+    path.canonicalize().unwrap_or_else(|_| path.to_path_buf()).to_string_lossy().to_string()
+}
+
+/// Runs the ingest pipeline as this process's only job.
+pub fn pipeline(input: Input, input_roots: Vec<String>, options: String, db_path: &str, config: Config) -> Result<()> {
+    handle_interrupts()?;
+    pipeline::run(input, input_roots, options, db_path, config)?;
+    Ok(())
+}
+
+/// Works for a coordinator until it has no more files.
+pub fn worker(coordinator: &str, threads: Option<usize>, config: Config) -> Result<()> {
+    handle_interrupts()?;
+    pipeline::work(coordinator, threads, config)
+}
+
+fn handle_interrupts() -> Result<()> {
+    static INSTALLED: AtomicBool = AtomicBool::new(false);
+    if INSTALLED.swap(true, Ordering::SeqCst) {
+        return Ok(());
+    }
+    // The first Ctrl-C lets the run finish what it's doing and save it;
+    // a second one quits at once, without leaving ffmpeg or yt-dlp
+    // children behind.
+    ctrlc::set_handler(|| {
+        if pipeline::interrupted() {
+            error!("Interrupted again, killing ffmpeg and yt-dlp processes");
+            ffmpeg::kill_all_children();
+            std::process::exit(130);
+        }
+        warn!("Interrupted; finishing the files in progress and saving the catalog (Ctrl-C again to quit now)");
+        pipeline::interrupt();
+    })?;
+    Ok(())
+}
@@ -0,0 +1,25 @@
+pub mod archive;
+pub mod bench;
+pub mod burn;
+pub mod daemon;
+pub mod db;
+pub mod dedupe;
+pub mod delete;
+pub mod errors;
+pub mod export;
+pub mod import;
+pub mod index;
+pub mod ingest;
+pub mod query;
+pub mod relations;
+pub mod restore;
+pub mod runs;
+pub mod serve;
+pub mod sources;
+pub mod stats;
+pub mod tags;
+pub mod tui;
+#[cfg(feature = "cloud")]
+pub mod upload;
+pub mod verify;
+pub mod volumes;
@@ -0,0 +1,47 @@
+use anyhow::Result;
+use crate::cli::QueryArgs;
+use deep_archive::database::repo::CatalogReader;
+use deep_archive::utils::config::Config;
+
+pub fn run(args: QueryArgs, db_path: &str, config: &Config) -> Result<()> {
+    let reader = CatalogReader::open(db_path, &config.database)?;
+    let filter = args.filter.to_filter_set();
+
+    if args.count {
+        println!("{}", reader.count(&filter)?);
+        return Ok(());
+    }
+
+    for artifact in reader.find(&filter) {
+        let artifact = artifact?;
+        let score = artifact.nsfw_score.map(|s| format!("{:.2}", s)).unwrap_or_else(|| "-".to_string());
+        println!(
+            "{}\t{}\t{}\t{}\t{}",
+            artifact.hash_sha256,
+            artifact.media_type,
+            score,
+            artifact.tags.join(","),
+            artifact.original_path
+        );
+
+        if args.paths {
+            for location in reader.paths(artifact.id)? {
+                match location.source {
+                    Some(source) => println!("\t\t\t\t{}\t{}", location.path, source),
+                    None => println!("\t\t\t\t{}", location.path),
+                }
+            }
+            for copy in reader.archive_copies(artifact.id)? {
+                println!("\t\t\t\t{}\tvolume {} ({})", copy.path, copy.label, copy.volume);
+            }
+        }
+
+        if args.related {
+            for relation in reader.relationships(artifact.id)? {
+                let direction = if relation.outgoing { "" } else { "<- " };
+                println!("\t\t\t\t{}{}\t{}\t{}", direction, relation.kind, relation.hash_sha256, relation.original_path);
+            }
+        }
+    }
+    Ok(())
+}
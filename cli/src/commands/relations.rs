@@ -0,0 +1,22 @@
+use anyhow::{bail, Result};
+use tracing::info;
+use crate::cli::RelationsCommand;
+use deep_archive::database::store;
+use deep_archive::utils::config::Config;
+
+pub fn run(command: RelationsCommand, db_path: &str, config: &Config) -> Result<()> {
+    let mut store = store::open(db_path, &config.database)?;
+    match command {
+        RelationsCommand::Add { hash, kind, related } => {
+            store.relate(&hash, kind.as_str(), &related)?;
+            info!("{} {} {}", hash, kind.as_str(), related);
+        }
+        RelationsCommand::Remove { hash, kind, related } => {
+            if !store.unrelate(&hash, kind.as_str(), &related)? {
+                bail!("{} is not {} {}", hash, kind.as_str(), related);
+            }
+            info!("Removed {} {} {}", hash, kind.as_str(), related);
+        }
+    }
+    Ok(())
+}
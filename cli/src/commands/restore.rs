@@ -0,0 +1,369 @@
+use std::collections::{HashMap, HashSet};
+use std::fs::File;
+use std::io::{self, BufRead, BufReader, IsTerminal, Write};
+use std::path::{Path, PathBuf};
+use anyhow::{Result, Context, bail};
+use tracing::{info, warn};
+use crate::cli::RestoreArgs;
+use deep_archive::archive::restore::{self, Location};
+use deep_archive::archive::tape;
+use deep_archive::database::repo::{ArchiveCopy, Artifact, CatalogReader, VolumeSummary};
+#[cfg(feature = "cloud")]
+use deep_archive::archive::{self, cloud::{Bucket, Retrieval}};
+#[cfg(feature = "cloud")]
+use deep_archive::database::repo::VolumeUpload;
+use deep_archive::ingest::hasher;
+use deep_archive::utils::config::Config;
+#[cfg(feature = "cloud")]
+use deep_archive::utils::units::format_size;
+
+/// An artifact still to be restored and the volumes it can come from.
+struct Pending {
+    artifact: Artifact,
+    target: PathBuf,
+    copies: Vec<ArchiveCopy>,
+}
+
+/// Copies the matching artifacts to `--dest`, from their original paths
+/// where the content is still intact and otherwise from archive volumes,
+/// asking for the volumes that weren't given. Every file is checked
+/// against its catalogued hash before it is put in place.
+pub fn run(args: RestoreArgs, db_path: &str, config: &Config) -> Result<()> {
+    let reader = CatalogReader::open(db_path, &config.database)?;
+    let registry = reader.volumes()?;
+    let mut locations = Vec::new();
+    for path in &args.volumes {
+        let location = Location::open(path)?;
+        let label = identify(&location, path, &registry)?;
+        info!("{:?} is volume {}", path, label);
+        locations.push((label, location));
+    }
+
+    let mut pending = Vec::new();
+    let mut failed = Vec::new();
+    let mut from_sources = 0;
+    for artifact in reader.find(&args.filter.to_filter_set()) {
+        let artifact = artifact?;
+        let target = restore::target_path(&args.dest, &artifact.original_path);
+        if !args.from_volumes && from_paths(&reader, &artifact, &target)? {
+            from_sources += 1;
+            continue;
+        }
+        let copies = reader.archive_copies(artifact.id)?;
+        if copies.is_empty() {
+            failed.push(format!("{}: no intact original and not on any volume", artifact.original_path));
+            continue;
+        }
+        pending.push(Pending { artifact, target, copies });
+    }
+
+    #[cfg(feature = "cloud")]
+    let mut remote = match &args.remote {
+        Some(name) => Some(RemoteCopies::open(name, &reader, &registry, config, &args.dest)?),
+        None => None,
+    };
+    let prompt = !args.no_prompt && io::stdin().is_terminal();
+    let mut from_volumes = 0;
+    while !pending.is_empty() {
+        let ready = locations
+            .iter()
+            .position(|(label, _)| pending.iter().any(|p| p.copies.iter().any(|c| &c.label == label)));
+        let Some(i) = ready else {
+            #[cfg(feature = "cloud")]
+            if let Some(remote) = &mut remote {
+                if let Some(restored) = remote.fetch_objects(&mut pending, &mut failed) {
+                    from_volumes += restored;
+                    continue;
+                }
+                if let Some(location) = remote.download(&mut pending, &mut failed) {
+                    locations.push(location);
+                    continue;
+                }
+            }
+            let (label, name, count) = most_wanted(&pending);
+            if !prompt {
+                break;
+            }
+            match ask(&label, &name, count)? {
+                Some(path) => match Location::open(&path) {
+                    Ok(location) => {
+                        // A mounted disc says which volume it is; trust the manifest over the request.
+                        let given = location.label().unwrap_or(label);
+                        locations.push((given, location));
+                    }
+                    Err(e) => eprintln!("{:#}", e),
+                },
+                None => {
+                    for p in &mut pending {
+                        p.copies.retain(|c| c.label != label);
+                    }
+                    failed.extend(unavailable(&mut pending));
+                }
+            }
+            continue;
+        };
+
+        let (label, location) = locations.remove(i);
+        from_volumes += extract(&label, &location, &mut pending, &mut failed);
+        #[cfg(feature = "cloud")]
+        if let (Some(remote), Location::Image(path, _)) = (&remote, &location) {
+            remote.discard(path);
+        }
+    }
+
+    let restored = from_sources + from_volumes;
+    info!("Restored {} files to {:?} ({} from original paths, {} from volumes)", restored, args.dest, from_sources, from_volumes);
+    if !pending.is_empty() {
+        let mut needed: Vec<_> = volume_counts(&pending).into_iter().collect();
+        needed.sort();
+        for ((label, name), count) in needed {
+            warn!("Volume {} ({}) holds {} of the files not restored", label, name, count);
+        }
+    }
+    for failure in &failed {
+        warn!("{}", failure);
+    }
+    let missing = pending.len() + failed.len();
+    if missing > 0 {
+        bail!("{} files could not be restored", missing);
+    }
+    Ok(())
+}
+
+/// Label of a volume given with `--volume`: from the manifest of a mounted
+/// disc or a tape, or by file name from the registry. Where several volumes
+/// share the name, the image's checksum decides.
+fn identify(location: &Location, path: &Path, registry: &[VolumeSummary]) -> Result<String> {
+    match location {
+        Location::Directory(_) => {
+            return location
+                .label()
+                .with_context(|| format!("{:?} has no readable deep-archive/manifest.json; mount the volume's root", path));
+        }
+        Location::Tape(device) => return tape::label(device),
+        Location::Image(..) => {}
+    }
+    let name = location.name().unwrap_or_default();
+    let named: Vec<&VolumeSummary> = registry.iter().filter(|s| s.volume.name == name).collect();
+    match named.as_slice() {
+        [] => bail!("{:?} isn't a registered volume; see `deep-archive volumes`", path),
+        [only] => Ok(only.volume.label.clone()),
+        _ => {
+            let image = hasher::fingerprint(path)?;
+            named
+                .iter()
+                .find(|s| s.volume.sha256.as_deref() == Some(image.hash.as_str()))
+                .map(|s| s.volume.label.clone())
+                .with_context(|| format!("{:?} matches none of the {} volumes named {}", path, named.len(), name))
+        }
+    }
+}
+
+/// Restores `artifact` from the first of its catalogued paths that still
+/// holds the right content.
+fn from_paths(reader: &CatalogReader, artifact: &Artifact, target: &Path) -> Result<bool> {
+    for location in reader.paths(artifact.id)? {
+        let Ok(file) = File::open(&location.path) else { continue };
+        match restore::write_verified(&mut BufReader::new(file), target, &artifact.hash_sha256) {
+            Ok(()) => return Ok(true),
+            Err(e) => warn!("Not restoring from {}: {:#}", location.path, e),
+        }
+    }
+    Ok(false)
+}
+
+/// Restores everything pending that `label` holds from `location`, and
+/// returns how many files were. Copies that turn out missing or damaged
+/// are dropped, so their files wait for another volume.
+fn extract(label: &str, location: &Location, pending: &mut Vec<Pending>, failed: &mut Vec<String>) -> usize {
+    let wanted: HashMap<String, usize> = pending
+        .iter()
+        .enumerate()
+        .filter_map(|(i, p)| p.copies.iter().find(|c| c.label == label).map(|c| (c.path.clone(), i)))
+        .collect();
+    info!("Reading {} files from volume {}", wanted.len(), label);
+    let paths: HashSet<String> = wanted.keys().cloned().collect();
+    let mut done = HashSet::new();
+    let read = location.read(&paths, &mut |path, _, _, content| {
+        let p = &pending[wanted[path]];
+        match restore::write_verified(content, &p.target, &p.artifact.hash_sha256) {
+            Ok(()) => {
+                done.insert(wanted[path]);
+            }
+            Err(e) => warn!("{} on volume {}: {:#}", path, label, e),
+        }
+        Ok(())
+    });
+    if let Err(e) = read {
+        warn!("Failed to read volume {}: {:#}", label, e);
+    }
+
+    let restored = done.len();
+    *pending = std::mem::take(pending)
+        .into_iter()
+        .enumerate()
+        .filter(|(i, _)| !done.contains(i))
+        .map(|(_, mut p)| {
+            p.copies.retain(|c| c.label != label);
+            p
+        })
+        .collect();
+    failed.extend(unavailable(pending));
+    restored
+}
+
+/// Takes the files no volume is left for out of `pending`.
+fn unavailable(pending: &mut Vec<Pending>) -> Vec<String> {
+    let (gone, kept): (Vec<_>, Vec<_>) = std::mem::take(pending).into_iter().partition(|p| p.copies.is_empty());
+    *pending = kept;
+    gone.into_iter().map(|p| format!("{}: no intact copy on the volumes read", p.artifact.original_path)).collect()
+}
+
+/// The volume holding the most pending files, as label, name and count.
+fn most_wanted(pending: &[Pending]) -> (String, String, usize) {
+    let ((label, name), count) = volume_counts(pending)
+        .into_iter()
+        .max_by(|a, b| a.1.cmp(&b.1).then(b.0.cmp(&a.0)))
+        .expect("pending files have copies");
+    (label.to_string(), name.to_string(), count)
+}
+
+/// Number of pending files each volume holds, by label and name.
+fn volume_counts(pending: &[Pending]) -> HashMap<(&str, &str), usize> {
+    let mut counts = HashMap::new();
+    for p in pending {
+        let volumes: HashSet<(&str, &str)> = p.copies.iter().map(|c| (c.label.as_str(), c.volume.as_str())).collect();
+        for volume in volumes {
+            *counts.entry(volume).or_default() += 1;
+        }
+    }
+    counts
+}
+
+/// Asks for the path of a volume; `None` skips it.
+fn ask(label: &str, name: &str, count: usize) -> Result<Option<PathBuf>> {
+    eprint!("Insert or mount volume {} ({}), which holds {} of the remaining files, and enter its path (empty to skip): ", label, name, count);
+    io::stderr().flush()?;
+    let mut line = String::new();
+    io::stdin().lock().read_line(&mut line)?;
+    let line = line.trim();
+    Ok((!line.is_empty()).then(|| PathBuf::from(line)))
+}
+
+/// Copies of volumes in the `--remote` bucket, fetched as the restore
+/// runs out of volumes at hand.
+#[cfg(feature = "cloud")]
+struct RemoteCopies {
+    bucket: Bucket,
+    /// The latest upload of each volume to the remote, by label; taken out
+    /// once tried.
+    uploads: HashMap<String, VolumeUpload>,
+    /// SHA-256 of each volume by label, for checking downloaded images.
+    images: HashMap<String, Option<String>>,
+    /// Where images are downloaded to until their files are extracted.
+    downloads: PathBuf,
+}
+
+#[cfg(feature = "cloud")]
+impl RemoteCopies {
+    fn open(name: &str, reader: &CatalogReader, registry: &[VolumeSummary], config: &Config, dest: &Path) -> Result<Self> {
+        let remote = config.archive.remotes.get(name).with_context(|| format!("No remote named {} under [archive.remotes]", name))?;
+        let mut uploads = HashMap::new();
+        for summary in registry.iter().filter(|s| s.uploads > 0) {
+            if let Some(upload) = reader.uploads(summary.id)?.into_iter().rfind(|u| u.remote == name) {
+                uploads.insert(upload.label.clone(), upload);
+            }
+        }
+        let images = registry.iter().map(|s| (s.volume.label.clone(), s.volume.sha256.clone())).collect();
+        Ok(Self { bucket: Bucket::open(remote)?, uploads, images, downloads: dest.join(".deep-archive-downloads") })
+    }
+
+    /// Restores the pending files on volumes uploaded file by file, and
+    /// returns how many were; `None` once no pending file is on one.
+    fn fetch_objects(&mut self, pending: &mut Vec<Pending>, failed: &mut Vec<String>) -> Option<usize> {
+        let labels: HashSet<String> = self.uploads.iter().filter(|(_, u)| u.layout == "objects").map(|(l, _)| l.clone()).collect();
+        let wanted: Vec<usize> = (0..pending.len()).filter(|&i| pending[i].copies.iter().any(|c| labels.contains(&c.label))).collect();
+        if wanted.is_empty() {
+            return None;
+        }
+        info!("Fetching {} files from {}", wanted.len(), self.bucket.name);
+        let mut done = HashSet::new();
+        for i in wanted {
+            let p = &pending[i];
+            let copy = p.copies.iter().find(|c| labels.contains(&c.label)).expect("filtered on copies");
+            let key = format!("{}{}", self.uploads[&copy.label].key, archive::archive_path(&archive::object_path(&p.artifact.hash_sha256)));
+            let fetched = self.bucket.get(&key).and_then(|retrieval| match retrieval {
+                Retrieval::Ready(mut content) => restore::write_verified(&mut content, &p.target, &p.artifact.hash_sha256).map(|_| true),
+                Retrieval::Restoring => Ok(false),
+            });
+            match fetched {
+                Ok(true) => {
+                    done.insert(i);
+                }
+                Ok(false) => warn!("{} is in Glacier; requested a restore, run again once it completes", key),
+                Err(e) => warn!("{} in {}: {:#}", key, self.bucket.name, e),
+            }
+        }
+        let restored = done.len();
+        *pending = std::mem::take(pending)
+            .into_iter()
+            .enumerate()
+            .filter(|(i, _)| !done.contains(i))
+            .map(|(_, mut p)| {
+                p.copies.retain(|c| !labels.contains(&c.label));
+                p
+            })
+            .collect();
+        failed.extend(unavailable(pending));
+        self.uploads.retain(|label, _| !labels.contains(label));
+        Some(restored)
+    }
+
+    /// Downloads the image of the uploaded volume holding the most pending
+    /// files; `None` once no pending file is on one.
+    fn download(&mut self, pending: &mut Vec<Pending>, failed: &mut Vec<String>) -> Option<(String, Location)> {
+        loop {
+            let label = volume_counts(pending)
+                .into_iter()
+                .filter(|((label, _), _)| self.uploads.contains_key(*label))
+                .max_by(|a, b| a.1.cmp(&b.1).then(b.0.cmp(&a.0)))
+                .map(|((label, _), _)| label.to_string())?;
+            let upload = self.uploads.remove(&label).expect("filtered on uploads");
+            match self.fetch_image(&upload) {
+                Ok(Some(location)) => return Some((label, location)),
+                Ok(None) => warn!("Volume {} is in Glacier; requested a restore, run again once it completes", label),
+                Err(e) => warn!("Failed to download volume {} from {}: {:#}", label, self.bucket.name, e),
+            }
+            for p in pending.iter_mut() {
+                p.copies.retain(|c| c.label != label);
+            }
+            failed.extend(unavailable(pending));
+        }
+    }
+
+    /// Deletes `path` if it is an image downloaded here.
+    fn discard(&self, path: &Path) {
+        if path.starts_with(&self.downloads) {
+            let _ = std::fs::remove_file(path);
+        }
+    }
+
+    fn fetch_image(&self, upload: &VolumeUpload) -> Result<Option<Location>> {
+        let name = upload.key.rsplit('/').next().unwrap_or(&upload.key);
+        let path = self.downloads.join(name);
+        let hash = self.images.get(&upload.label).cloned().flatten().context("The volume has no recorded checksum")?;
+        info!("Downloading volume {} ({}) from {}", upload.label, format_size(upload.size_bytes), self.bucket.name);
+        match self.bucket.get(&upload.key)? {
+            Retrieval::Ready(mut content) => restore::write_verified(&mut content, &path, &hash)?,
+            Retrieval::Restoring => return Ok(None),
+        }
+        Location::open(&path).map(Some)
+    }
+}
+
+#[cfg(feature = "cloud")]
+impl Drop for RemoteCopies {
+    fn drop(&mut self) {
+        let _ = std::fs::remove_dir_all(&self.downloads);
+    }
+}
@@ -0,0 +1,56 @@
+use anyhow::{Result, bail};
+use tracing::info;
+use crate::cli::RunsCommand;
+use deep_archive::database::store::{self, LeaseKind};
+use deep_archive::utils::config::Config;
+use deep_archive::utils::units::format_timestamp;
+
+pub fn run(command: RunsCommand, db_path: &str, config: &Config) -> Result<()> {
+    match command {
+        RunsCommand::List => list(db_path, config),
+        RunsCommand::Show { run_id, json } => show(run_id, json, db_path, config),
+        RunsCommand::Rollback { run_id, purge } => {
+            let mut tm = store::open(db_path, &config.database)?;
+            tm.acquire_lease(LeaseKind::Exclusive)?;
+            let removed = tm.rollback_run(run_id, purge)?;
+            let verb = if purge { "purged" } else { "tombstoned" };
+            info!("Rolled back run {}: {} {} artifacts", run_id, verb, removed);
+            Ok(())
+        }
+    }
+}
+
+fn list(db_path: &str, config: &Config) -> Result<()> {
+    let mut store = store::open(db_path, &config.database)?;
+    println!("ID\tSTARTED\tFINISHED\tSTATUS\tFILES\tADDED\tERRORS\tINPUT");
+    for run in store.runs()? {
+        println!(
+            "{}\t{}\t{}\t{}\t{}\t{}\t{}\t{}",
+            run.id,
+            format_timestamp(Some(run.started_at)),
+            format_timestamp(run.finished_at),
+            run.status,
+            run.files_seen,
+            run.artifacts_added,
+            run.errors,
+            run.input_roots
+        );
+    }
+    Ok(())
+}
+
+fn show(run_id: i64, json: bool, db_path: &str, config: &Config) -> Result<()> {
+    let mut store = store::open(db_path, &config.database)?;
+    let Some(run) = store.runs()?.into_iter().find(|run| run.id == run_id) else {
+        bail!("No run {}", run_id);
+    };
+    let Some(summary) = run.summary else {
+        bail!("Run {} has no summary; only pipeline runs that got to their end store one", run_id);
+    };
+    if json {
+        println!("{}", serde_json::to_string_pretty(&summary)?);
+    } else {
+        print!("{}", summary);
+    }
+    Ok(())
+}
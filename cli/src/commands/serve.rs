@@ -0,0 +1,30 @@
+use anyhow::Result;
+use deep_archive::server::api::Api;
+use deep_archive::server::Server;
+use deep_archive::utils::config::Config;
+
+/// Serves the API until the process is killed. Runs it started are left
+/// open then, to be resumed with `ingest --resume`.
+pub fn run(db_path: &str, mut config: Config, addr: Option<String>, grpc_addr: Option<String>) -> Result<()> {
+    config.server.addr = addr.unwrap_or(config.server.addr);
+    config.server.grpc_addr = grpc_addr.or(config.server.grpc_addr);
+    let server = config.server.clone();
+    let api = Api::new(db_path, config)?;
+    if let Some(grpc_addr) = &server.grpc_addr {
+        start_grpc(grpc_addr, &api)?;
+    }
+    Server::start(&server, move |request| api.handle(request))?.wait();
+    Ok(())
+}
+
+#[cfg(feature = "grpc")]
+fn start_grpc(addr: &str, api: &Api) -> Result<()> {
+    use deep_archive::server::grpc::{self, Services};
+    grpc::start(addr, Services::new(api.readers().clone(), api.runs().clone(), api.auth().clone()))?;
+    Ok(())
+}
+
+#[cfg(not(feature = "grpc"))]
+fn start_grpc(_addr: &str, _api: &Api) -> Result<()> {
+    anyhow::bail!("--grpc-addr needs a build with the `grpc` feature")
+}
@@ -0,0 +1,24 @@
+use anyhow::Result;
+use deep_archive::database::repo::CatalogReader;
+use deep_archive::utils::config::Config;
+use deep_archive::utils::units::format_timestamp;
+
+pub fn run(db_path: &str, config: &Config) -> Result<()> {
+    let reader = CatalogReader::open(db_path, &config.database)?;
+    println!("ID\tLABEL\tUUID\tHOST\tMOUNT\tFIRST SEEN\tLAST SEEN\tPATHS");
+    for summary in reader.sources()? {
+        let source = summary.source;
+        println!(
+            "{}\t{}\t{}\t{}\t{}\t{}\t{}\t{}",
+            summary.id,
+            source.label.as_deref().unwrap_or("-"),
+            source.fs_uuid.as_deref().unwrap_or("-"),
+            source.host,
+            source.mount_point.as_deref().unwrap_or("-"),
+            format_timestamp(Some(summary.first_seen)),
+            format_timestamp(Some(summary.last_seen)),
+            summary.paths
+        );
+    }
+    Ok(())
+}
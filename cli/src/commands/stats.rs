@@ -0,0 +1,71 @@
+use anyhow::Result;
+use deep_archive::database::repo::CatalogReader;
+use deep_archive::utils::config::Config;
+use deep_archive::utils::units::{format_size, format_timestamp};
+
+pub fn run(db_path: &str, config: &Config, top_tags: usize, slowest: usize, json: bool) -> Result<()> {
+    let reader = CatalogReader::open(db_path, &config.database)?;
+    let stats = reader.stats(top_tags, slowest)?;
+    if json {
+        println!("{}", serde_json::to_string_pretty(&stats)?);
+        return Ok(());
+    }
+
+    println!(
+        "{} artifacts, {}, {} paths ({} deleted)",
+        stats.artifacts,
+        format_size(stats.bytes),
+        stats.paths,
+        stats.deleted
+    );
+
+    println!("\nMIMETYPE\tARTIFACTS\tSIZE");
+    for media_type in &stats.media_types {
+        println!("{}\t{}\t{}", media_type.media_type, media_type.artifacts, format_size(media_type.bytes));
+    }
+
+    println!("\nTAG\tARTIFACTS");
+    for tag in &stats.top_tags {
+        println!("{}\t{}", tag.tag, tag.artifacts);
+    }
+
+    println!("\nNSFW\tARTIFACTS");
+    for bucket in &stats.nsfw_histogram {
+        println!("{:.1}-{:.1}\t{}", bucket.lower, bucket.lower + 0.1, bucket.artifacts);
+    }
+
+    println!("\nRUN\tSTARTED\tSTATUS\tFILES\tADDED\tSIZE\tERRORS\tSECONDS\tFILES/S");
+    for run in &stats.runs {
+        println!(
+            "{}\t{}\t{}\t{}\t{}\t{}\t{}\t{}\t{}",
+            run.run_id,
+            format_timestamp(Some(run.started_at)),
+            run.status,
+            run.files_seen,
+            run.artifacts_added,
+            format_size(run.bytes_added),
+            run.errors,
+            run.duration_secs.map_or_else(|| "-".to_string(), |d| d.to_string()),
+            run.files_per_sec.map_or_else(|| "-".to_string(), |f| format!("{:.1}", f))
+        );
+    }
+
+    if slowest > 0 {
+        // In seconds, each stage as well as the total.
+        println!("\nSECONDS\tHASH\tDECODE\tINFERENCE\tSIZE\tRUN\tPATH");
+        let ms = |ms: u64| format!("{:.3}", ms as f64 / 1000.0);
+        for file in &stats.slowest {
+            println!(
+                "{}\t{}\t{}\t{}\t{}\t{}\t{}",
+                ms(file.timings.total_ms),
+                ms(file.timings.hash_ms),
+                ms(file.timings.decode_ms),
+                ms(file.timings.inference_ms),
+                file.size_bytes.map_or_else(|| "-".to_string(), format_size),
+                file.run_id,
+                file.path
+            );
+        }
+    }
+    Ok(())
+}
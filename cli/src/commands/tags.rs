@@ -0,0 +1,41 @@
+use anyhow::{bail, Result};
+use tracing::info;
+use crate::cli::TagsCommand;
+use deep_archive::database::store;
+use deep_archive::utils::config::Config;
+
+pub fn run(command: TagsCommand, db_path: &str, config: &Config) -> Result<()> {
+    let mut store = store::open(db_path, &config.database)?;
+    match command {
+        TagsCommand::Alias { alias, tag } => {
+            store.add_alias(&alias, &tag)?;
+            info!("'{}' now stands for '{}'", alias, tag);
+        }
+        TagsCommand::Unalias { alias } => {
+            if !store.remove_alias(&alias)? {
+                bail!("No alias '{}'", alias);
+            }
+            info!("Removed alias '{}'", alias);
+        }
+        TagsCommand::Imply { tag, implied } => {
+            store.add_implication(&tag, &implied)?;
+            info!("'{}' now implies '{}'", tag, implied);
+        }
+        TagsCommand::Unimply { tag, implied } => {
+            if !store.remove_implication(&tag, &implied)? {
+                bail!("'{}' does not imply '{}'", tag, implied);
+            }
+            info!("'{}' no longer implies '{}'", tag, implied);
+        }
+        TagsCommand::Rules => {
+            let rules = store.tag_rules()?;
+            for (alias, tag) in rules.aliases {
+                println!("alias\t{}\t{}", alias, tag);
+            }
+            for (tag, implied) in rules.implications {
+                println!("implies\t{}\t{}", tag, implied);
+            }
+        }
+    }
+    Ok(())
+}
@@ -0,0 +1,400 @@
+mod graphics;
+
+use std::io::{self, Write};
+use std::time::Duration;
+use anyhow::{Result, Context, bail};
+use ratatui::crossterm::event::{self, Event, KeyCode, KeyEvent, KeyEventKind, KeyModifiers};
+use ratatui::layout::{Constraint, Layout, Rect};
+use ratatui::style::{Modifier, Style, Stylize};
+use ratatui::text::{Line, Span};
+use ratatui::widgets::{Block, List, ListItem, ListState, Paragraph, Wrap};
+use ratatui::{DefaultTerminal, Frame};
+
+use crate::cli::TuiArgs;
+use deep_archive::database::repo::{ArchiveCopy, Artifact, ArtifactPath, CatalogReader, FilterSet, Relationship};
+use deep_archive::utils::config::{Config, ImageProtocol};
+use deep_archive::utils::units::{format_size, format_timestamp};
+use graphics::{Blank, HalfBlocks, Preview, Previews};
+
+/// Artifacts loaded at a time; the next page loads as the selection nears
+/// the end of the list.
+const PAGE_SIZE: usize = 200;
+/// How long to wait for a key before checking on a decoding preview.
+const TICK: Duration = Duration::from_millis(100);
+
+/// Where an artifact was seen, archived to and related to, loaded when it
+/// is selected.
+struct Detail {
+    id: i64,
+    paths: Vec<ArtifactPath>,
+    copies: Vec<ArchiveCopy>,
+    relationships: Vec<Relationship>,
+}
+
+struct App {
+    reader: CatalogReader,
+    /// The filters given on the command line; the search box narrows them.
+    base: FilterSet,
+    filter: FilterSet,
+    search: String,
+    /// The search box's text while it is being edited.
+    editing: Option<String>,
+    artifacts: Vec<Artifact>,
+    total: usize,
+    exhausted: bool,
+    list: ListState,
+    detail: Option<Detail>,
+    error: Option<String>,
+    protocol: ImageProtocol,
+    previews: Previews,
+    /// The preview last written with kitty or sixel, and where.
+    drawn: Option<(String, Rect)>,
+    /// The preview the pane last showed, and where; `generation` is bumped
+    /// whenever it changes, to clear a sixel left behind.
+    shown: Option<(String, Rect)>,
+    generation: u64,
+    /// Set when the layout changes under a preview, to clear the screen.
+    redraw: bool,
+}
+
+pub fn run(args: TuiArgs, db_path: &str, config: &Config) -> Result<()> {
+    let reader = CatalogReader::open(db_path, &config.database)?;
+    let protocol = graphics::resolve(args.images.map_or(config.tui.images, Into::into));
+    let base = args.filter.to_filter_set();
+    let mut app = App {
+        reader,
+        filter: base.clone(),
+        base,
+        search: String::new(),
+        editing: None,
+        artifacts: Vec::new(),
+        total: 0,
+        exhausted: false,
+        list: ListState::default(),
+        detail: None,
+        error: None,
+        protocol,
+        previews: Previews::new(config.media.clone()),
+        drawn: None,
+        shown: None,
+        generation: 0,
+        redraw: false,
+    };
+    app.reload()?;
+
+    let mut terminal = ratatui::init();
+    let result = app.run(&mut terminal);
+    if protocol == ImageProtocol::Kitty {
+        let _ = graphics::clear_kitty(&mut io::stdout());
+    }
+    ratatui::restore();
+    result
+}
+
+impl App {
+    fn run(&mut self, terminal: &mut DefaultTerminal) -> Result<()> {
+        loop {
+            if std::mem::take(&mut self.redraw) {
+                terminal.clear()?;
+                if self.drawn.take().is_some() {
+                    graphics::clear_kitty(&mut io::stdout())?;
+                }
+            }
+            let mut preview_area = None;
+            terminal.draw(|frame| preview_area = self.draw(frame))?;
+            self.draw_graphics(preview_area)?;
+
+            if !event::poll(TICK)? {
+                continue;
+            }
+            match event::read()? {
+                Event::Key(key) if key.kind == KeyEventKind::Press && !self.key(key)? => return Ok(()),
+                Event::Resize(..) => self.redraw = true,
+                _ => {}
+            }
+        }
+    }
+
+    /// Handles a key; false to quit.
+    fn key(&mut self, key: KeyEvent) -> Result<bool> {
+        if key.modifiers.contains(KeyModifiers::CONTROL) && key.code == KeyCode::Char('c') {
+            return Ok(false);
+        }
+        if let Some(text) = &mut self.editing {
+            match key.code {
+                KeyCode::Enter => {
+                    self.search = self.editing.take().unwrap_or_default();
+                    if let Err(e) = self.reload() {
+                        self.error = Some(format!("{:#}", e));
+                    }
+                }
+                KeyCode::Esc => self.editing = None,
+                KeyCode::Backspace => {
+                    text.pop();
+                }
+                KeyCode::Char(c) => text.push(c),
+                _ => {}
+            }
+            return Ok(true);
+        }
+        let selected = self.list.selected().unwrap_or(0);
+        match key.code {
+            KeyCode::Char('q') | KeyCode::Esc => return Ok(false),
+            KeyCode::Char('/') => self.editing = Some(self.search.clone()),
+            KeyCode::Char('j') | KeyCode::Down => self.select(selected + 1)?,
+            KeyCode::Char('k') | KeyCode::Up => self.select(selected.saturating_sub(1))?,
+            KeyCode::PageDown => self.select(selected + 20)?,
+            KeyCode::PageUp => self.select(selected.saturating_sub(20))?,
+            KeyCode::Char('g') | KeyCode::Home => self.select(0)?,
+            KeyCode::Char('G') | KeyCode::End => self.select(self.artifacts.len().saturating_sub(1))?,
+            KeyCode::Char('p') => {
+                self.protocol = match self.protocol {
+                    ImageProtocol::None => graphics::resolve(ImageProtocol::Auto),
+                    _ => ImageProtocol::None,
+                };
+                self.redraw = true;
+            }
+            _ => {}
+        }
+        Ok(true)
+    }
+
+    /// Runs the search again from the top.
+    fn reload(&mut self) -> Result<()> {
+        self.filter = apply_search(&self.search, &self.base)?;
+        self.total = self.reader.count(&FilterSet { limit: None, ..self.filter.clone() })?;
+        self.artifacts.clear();
+        self.exhausted = false;
+        self.error = None;
+        self.load_more()?;
+        self.list.select((!self.artifacts.is_empty()).then_some(0));
+        self.load_detail()
+    }
+
+    fn load_more(&mut self) -> Result<()> {
+        // A `--limit` caps the whole list, not each page.
+        let remaining = self.base.limit.map_or(PAGE_SIZE, |limit| limit.saturating_sub(self.artifacts.len()).min(PAGE_SIZE));
+        let page = FilterSet { after_id: self.artifacts.last().map(|a| a.id), limit: Some(remaining), ..self.filter.clone() };
+        let before = self.artifacts.len();
+        if remaining > 0 {
+            for artifact in self.reader.find(&page) {
+                self.artifacts.push(artifact?);
+            }
+        }
+        self.exhausted = self.artifacts.len() - before < PAGE_SIZE;
+        Ok(())
+    }
+
+    fn select(&mut self, index: usize) -> Result<()> {
+        if !self.exhausted && index + PAGE_SIZE / 4 >= self.artifacts.len() {
+            self.load_more()?;
+        }
+        if self.artifacts.is_empty() {
+            return Ok(());
+        }
+        self.list.select(Some(index.min(self.artifacts.len() - 1)));
+        self.load_detail()
+    }
+
+    fn selected(&self) -> Option<&Artifact> {
+        self.list.selected().and_then(|i| self.artifacts.get(i))
+    }
+
+    fn load_detail(&mut self) -> Result<()> {
+        let Some(id) = self.selected().map(|a| a.id) else {
+            self.detail = None;
+            return Ok(());
+        };
+        if self.detail.as_ref().is_some_and(|d| d.id == id) {
+            return Ok(());
+        }
+        self.detail = Some(Detail {
+            id,
+            paths: self.reader.paths(id)?,
+            copies: self.reader.archive_copies(id)?,
+            relationships: self.reader.relationships(id)?,
+        });
+        Ok(())
+    }
+
+    /// Draws the panes, returning where the preview goes if kitty or sixel
+    /// is to draw it.
+    fn draw(&mut self, frame: &mut Frame) -> Option<Rect> {
+        let [search, main, status] = Layout::vertical([Constraint::Length(3), Constraint::Min(5), Constraint::Length(1)]).areas(frame.area());
+        let [results, side] = Layout::horizontal([Constraint::Percentage(45), Constraint::Percentage(55)]).areas(main);
+        let (preview, side) = match self.protocol {
+            ImageProtocol::None => (None, side),
+            _ => {
+                let [preview, rest] = Layout::vertical([Constraint::Percentage(50), Constraint::Percentage(50)]).areas(side);
+                (Some(preview), rest)
+            }
+        };
+        let [metadata, tags] = Layout::vertical([Constraint::Min(4), Constraint::Length(6)]).areas(side);
+
+        self.draw_search(frame, search);
+        self.draw_results(frame, results);
+        frame.render_widget(Paragraph::new(self.metadata_lines()).wrap(Wrap { trim: false }).block(Block::bordered().title(" Metadata ")), metadata);
+        let tag_line = self.selected().map_or(String::new(), |a| a.tags.join("  "));
+        frame.render_widget(Paragraph::new(tag_line).wrap(Wrap { trim: false }).block(Block::bordered().title(" Tags ")), tags);
+
+        let help = match (&self.error, self.editing.is_some()) {
+            (Some(e), _) => Line::from(e.as_str().red()),
+            (None, true) => Line::from(" enter search  esc cancel  +TAG  -TAG  type:MIME  nsfw<N  nsfw>N  other words match paths and tags".dim()),
+            (None, false) => Line::from(" / search  j/k move  PgUp/PgDn page  g/G first/last  p previews  q quit".dim()),
+        };
+        frame.render_widget(Paragraph::new(help), status);
+
+        let preview = preview?;
+        let block = Block::bordered().title(" Preview ");
+        let inner = block.inner(preview);
+        frame.render_widget(block, preview);
+        let artifact = self.selected()?;
+        let (hash, path, media_type) = (artifact.hash_sha256.clone(), artifact.original_path.clone(), artifact.media_type.clone());
+        if self.shown.as_ref().is_none_or(|(h, area)| *h != hash || *area != inner) {
+            self.generation += 1;
+            self.shown = Some((hash.clone(), inner));
+        }
+        frame.render_widget(Blank(self.generation), inner);
+        match self.previews.get(&hash, &path, &media_type) {
+            Preview::Ready(image) if self.protocol == ImageProtocol::Blocks => {
+                frame.render_widget(HalfBlocks(&image), inner);
+                None
+            }
+            Preview::Ready(_) => Some(inner),
+            Preview::Loading => {
+                frame.render_widget(Paragraph::new("Loading…".dim()), inner);
+                None
+            }
+            Preview::Failed(e) => {
+                frame.render_widget(Paragraph::new(e.dim()).wrap(Wrap { trim: false }), inner);
+                None
+            }
+        }
+    }
+
+    fn draw_search(&self, frame: &mut Frame, area: Rect) {
+        let (text, style) = match &self.editing {
+            Some(text) => (format!("{}▏", text), Style::default().add_modifier(Modifier::BOLD)),
+            None => (self.search.clone(), Style::default()),
+        };
+        let title = if self.editing.is_some() { " Search (enter to run) " } else { " Search (/) " };
+        frame.render_widget(Paragraph::new(text).style(style).block(Block::bordered().title(title)), area);
+    }
+
+    fn draw_results(&mut self, frame: &mut Frame, area: Rect) {
+        let items: Vec<ListItem> = self
+            .artifacts
+            .iter()
+            .map(|a| {
+                let kind = a.media_type.split('/').next().unwrap_or("");
+                ListItem::new(Line::from(vec![Span::from(format!("{:<6}", kind)).dim(), Span::from(a.original_path.as_str())]))
+            })
+            .collect();
+        let shown = if self.exhausted { String::new() } else { format!("{} loaded of ", self.artifacts.len()) };
+        let list = List::new(items)
+            .block(Block::bordered().title(format!(" Results ({}{}) ", shown, self.total)))
+            .highlight_style(Style::default().add_modifier(Modifier::REVERSED));
+        frame.render_stateful_widget(list, area, &mut self.list);
+    }
+
+    fn metadata_lines(&self) -> Vec<Line<'static>> {
+        let Some(a) = self.selected() else {
+            return vec![Line::from("No matches".dim())];
+        };
+        let field = |name: &str, value: String| Line::from(vec![Span::from(format!("{:<11}", name)).dim(), Span::from(value)]);
+        let mut lines = vec![
+            field("Path", a.original_path.clone()),
+            field("Type", a.media_type.clone()),
+            field("Size", a.size_bytes.map_or("?".to_string(), format_size)),
+        ];
+        if let (Some(w), Some(h)) = (a.width, a.height) {
+            lines.push(field("Dimensions", format!("{} x {}", w, h)));
+        }
+        lines.push(field("Modified", format_timestamp(a.mtime)));
+        if let Some(score) = a.nsfw_score {
+            lines.push(field("NSFW", format!("{:.3}", score)));
+        }
+        lines.push(field("SHA-256", a.hash_sha256.clone()));
+        if let Some(detail) = self.detail.as_ref().filter(|d| d.id == a.id) {
+            for path in detail.paths.iter().filter(|p| p.path != a.original_path) {
+                lines.push(field("Copy", path.path.clone()));
+            }
+            for copy in &detail.copies {
+                lines.push(field("Archived", format!("{} ({}) {}", copy.label, copy.volume, copy.path)));
+            }
+            for r in &detail.relationships {
+                let relation = if r.outgoing { format!("{} {}", r.kind, r.original_path) } else { format!("{} <- {}", r.kind, r.original_path) };
+                lines.push(field("Related", relation));
+            }
+        }
+        if let Some(serde_json::Value::Object(metadata)) = &a.metadata {
+            for (key, value) in metadata {
+                let value = match value {
+                    serde_json::Value::String(s) => s.clone(),
+                    other => other.to_string(),
+                };
+                lines.push(field(key, value));
+            }
+        }
+        lines
+    }
+
+    /// Writes the selected preview with kitty or sixel, once per change.
+    fn draw_graphics(&mut self, area: Option<Rect>) -> Result<()> {
+        let Some(area) = area else {
+            if self.drawn.take().is_some() && self.protocol == ImageProtocol::Kitty {
+                graphics::clear_kitty(&mut io::stdout())?;
+            }
+            return Ok(());
+        };
+        let Some((hash, path, media_type)) = self.selected().map(|a| (a.hash_sha256.clone(), a.original_path.clone(), a.media_type.clone())) else {
+            return Ok(());
+        };
+        if self.drawn.as_ref().is_some_and(|(h, a)| *h == hash && *a == area) {
+            return Ok(());
+        }
+        if let Preview::Ready(image) = self.previews.get(&hash, &path, &media_type) {
+            let mut out = io::stdout().lock();
+            if self.protocol == ImageProtocol::Kitty {
+                graphics::clear_kitty(&mut out)?;
+            }
+            graphics::draw(&mut out, self.protocol, &image, area)?;
+            out.flush()?;
+            self.drawn = Some((hash, area));
+        }
+        Ok(())
+    }
+}
+
+/// Narrows `base` by the search box: `+TAG` requires a tag, `-TAG`
+/// excludes one, `type:MIME` adds a media type, `nsfw<N` and `nsfw>N`
+/// bound the score, and the remaining words go to the full-text search.
+fn apply_search(text: &str, base: &FilterSet) -> Result<FilterSet> {
+    let mut filter = base.clone();
+    let mut words = Vec::new();
+    for word in text.split_whitespace() {
+        if let Some(tag) = word.strip_prefix('+').filter(|t| !t.is_empty()) {
+            filter.all_tags.push(tag.to_string());
+        } else if let Some(tag) = word.strip_prefix('-').filter(|t| !t.is_empty()) {
+            filter.exclude_tags.push(tag.to_string());
+        } else if let Some(media_type) = word.strip_prefix("type:") {
+            filter.media_types.push(media_type.to_string());
+        } else if let Some(score) = word.strip_prefix("nsfw<") {
+            filter.max_nsfw = Some(score.parse().with_context(|| format!("Invalid score in '{}'", word))?);
+        } else if let Some(score) = word.strip_prefix("nsfw>") {
+            filter.min_nsfw = Some(score.parse().with_context(|| format!("Invalid score in '{}'", word))?);
+        } else if word.starts_with("nsfw") && word.len() > 4 && !word[4..].starts_with(|c: char| c.is_alphanumeric()) {
+            bail!("Use nsfw<N or nsfw>N, not '{}'", word);
+        } else {
+            words.push(word);
+        }
+    }
+    if !words.is_empty() {
+        let search = words.join(" ");
+        filter.search = Some(match &base.search {
+            Some(base) => format!("{} {}", base, search),
+            None => search,
+        });
+    }
+    Ok(filter)
+}
@@ -0,0 +1,200 @@
+use std::collections::HashMap;
+use std::io::{self, Cursor, Write};
+use std::path::PathBuf;
+use std::sync::mpsc::{self, Receiver, Sender};
+use std::sync::Arc;
+use std::thread;
+use anyhow::Result;
+use base64::Engine;
+use image::imageops::FilterType;
+use image::{DynamicImage, GenericImageView, ImageFormat};
+use ratatui::buffer::Buffer;
+use ratatui::crossterm::{cursor, terminal, QueueableCommand};
+use ratatui::layout::Rect;
+use ratatui::style::Color;
+use ratatui::widgets::Widget;
+use deep_archive::media::decode;
+use deep_archive::utils::config::{ImageProtocol, MediaConfig};
+
+/// Previews are decoded to fit a square this large, and scaled down from
+/// there to the pane.
+const PREVIEW_SIZE: u32 = 768;
+/// Decoded previews kept for scrolling back.
+const CACHED_PREVIEWS: usize = 64;
+/// Pixels per cell where the terminal doesn't say.
+const DEFAULT_CELL: (u16, u16) = (8, 16);
+/// Kitty takes base64 payloads in chunks of at most this many bytes.
+const KITTY_CHUNK: usize = 4096;
+
+/// The protocol `requested` resolves to in this terminal. Multiplexers
+/// swallow graphics escapes, so under tmux or screen `auto` uses blocks.
+pub fn resolve(requested: ImageProtocol) -> ImageProtocol {
+    if requested != ImageProtocol::Auto {
+        return requested;
+    }
+    let var = |name: &str| std::env::var(name).unwrap_or_default();
+    let (term, program) = (var("TERM"), var("TERM_PROGRAM"));
+    if !var("TMUX").is_empty() || !var("STY").is_empty() {
+        ImageProtocol::Blocks
+    } else if !var("KITTY_WINDOW_ID").is_empty() || term.contains("kitty") || term.contains("ghostty") || program == "WezTerm" || program == "ghostty" {
+        ImageProtocol::Kitty
+    } else if term.contains("foot") || term.contains("mlterm") || term.contains("contour") || program == "iTerm.app" || !var("WT_SESSION").is_empty() {
+        ImageProtocol::Sixel
+    } else {
+        ImageProtocol::Blocks
+    }
+}
+
+/// A preview as far as it got: still decoding, or failed with why.
+#[derive(Clone)]
+pub enum Preview {
+    Loading,
+    Ready(Arc<DynamicImage>),
+    Failed(String),
+}
+
+/// Decodes previews on a thread of its own, so scrolling never waits on
+/// ffmpeg. Only the newest request is decoded; ones scrolled past are
+/// dropped.
+pub struct Previews {
+    requests: Sender<(String, PathBuf, String)>,
+    results: Receiver<(String, Result<DynamicImage, String>)>,
+    cache: HashMap<String, Preview>,
+}
+
+impl Previews {
+    pub fn new(media: MediaConfig) -> Self {
+        let (requests, jobs) = mpsc::channel::<(String, PathBuf, String)>();
+        let (done, results) = mpsc::channel();
+        thread::spawn(move || {
+            while let Ok(mut job) = jobs.recv() {
+                while let Ok(newer) = jobs.try_recv() {
+                    job = newer;
+                }
+                let (hash, path, media_type) = job;
+                let image = decode::thumbnail(&path, &media_type, PREVIEW_SIZE, &media).map_err(|e| format!("{:#}", e));
+                if done.send((hash, image)).is_err() {
+                    break;
+                }
+            }
+        });
+        Previews { requests, results, cache: HashMap::new() }
+    }
+
+    /// The preview of `hash`, asking for it if it hasn't been yet.
+    pub fn get(&mut self, hash: &str, path: &str, media_type: &str) -> Preview {
+        while let Ok((done, image)) = self.results.try_recv() {
+            let preview = match image {
+                Ok(image) => Preview::Ready(Arc::new(image)),
+                Err(e) => Preview::Failed(e),
+            };
+            self.cache.insert(done, preview);
+        }
+        if let Some(preview) = self.cache.get(hash) {
+            return preview.clone();
+        }
+        // Dropped requests are asked for again when they come back into view.
+        self.cache.retain(|_, p| !matches!(p, Preview::Loading));
+        if self.cache.len() >= CACHED_PREVIEWS {
+            self.cache.clear();
+        }
+        self.cache.insert(hash.to_string(), Preview::Loading);
+        let _ = self.requests.send((hash.to_string(), PathBuf::from(path), media_type.to_string()));
+        Preview::Loading
+    }
+}
+
+/// Coloured half blocks: each cell shows two pixels, the top one as the
+/// foreground of `▀` and the bottom one as its background.
+pub struct HalfBlocks<'a>(pub &'a DynamicImage);
+
+impl Widget for HalfBlocks<'_> {
+    fn render(self, area: Rect, buf: &mut Buffer) {
+        if area.is_empty() {
+            return;
+        }
+        let image = self.0.resize(area.width as u32, area.height as u32 * 2, FilterType::Triangle).to_rgb8();
+        let (width, height) = image.dimensions();
+        let x0 = area.x + (area.width - width as u16) / 2;
+        let y0 = area.y + (area.height - height.div_ceil(2) as u16) / 2;
+        for y in (0..height).step_by(2) {
+            for x in 0..width {
+                let top = image.get_pixel(x, y);
+                let cell = &mut buf[(x0 + x as u16, y0 + (y / 2) as u16)];
+                cell.set_symbol("▀").set_fg(Color::Rgb(top[0], top[1], top[2]));
+                if y + 1 < height {
+                    let bottom = image.get_pixel(x, y + 1);
+                    cell.set_bg(Color::Rgb(bottom[0], bottom[1], bottom[2]));
+                }
+            }
+        }
+    }
+}
+
+/// Blank cells that differ from the last ones drawn whenever `generation`
+/// changes, so the terminal overwrites a sixel left there by the previous
+/// preview.
+pub struct Blank(pub u64);
+
+impl Widget for Blank {
+    fn render(self, area: Rect, buf: &mut Buffer) {
+        let symbol = if self.0.is_multiple_of(2) { " " } else { "\u{2800}" };
+        for y in area.top()..area.bottom() {
+            for x in area.left()..area.right() {
+                buf[(x, y)].set_symbol(symbol);
+            }
+        }
+    }
+}
+
+/// Writes `image` over `area` with the kitty or sixel protocol, scaled to
+/// fit and centred. Called after the frame is drawn, outside ratatui.
+pub fn draw(out: &mut impl Write, protocol: ImageProtocol, image: &DynamicImage, area: Rect) -> Result<()> {
+    let (cell_w, cell_h) = cell_size();
+    let image = image.resize(area.width as u32 * cell_w as u32, area.height as u32 * cell_h as u32, FilterType::Triangle);
+    let (width, height) = image.dimensions();
+    let columns = width.div_ceil(cell_w as u32) as u16;
+    let rows = height.div_ceil(cell_h as u32) as u16;
+    out.queue(cursor::MoveTo(area.x + (area.width - columns.min(area.width)) / 2, area.y + (area.height - rows.min(area.height)) / 2))?;
+    match protocol {
+        ImageProtocol::Kitty => {
+            let mut png = Vec::new();
+            image.to_rgb8().write_to(&mut Cursor::new(&mut png), ImageFormat::Png)?;
+            let data = base64::engine::general_purpose::STANDARD.encode(png);
+            let chunks: Vec<&[u8]> = data.as_bytes().chunks(KITTY_CHUNK).collect();
+            for (i, chunk) in chunks.iter().enumerate() {
+                let more = u8::from(i + 1 < chunks.len());
+                match i {
+                    // Transmit and show as PNG, quietly, without moving the cursor.
+                    0 => write!(out, "\x1b_Ga=T,f=100,q=2,C=1,m={};", more)?,
+                    _ => write!(out, "\x1b_Gm={};", more)?,
+                }
+                out.write_all(chunk)?;
+                out.write_all(b"\x1b\\")?;
+            }
+        }
+        ImageProtocol::Sixel => {
+            let rgba = image.to_rgba8();
+            let sixel = icy_sixel::SixelImage::from_rgba(rgba.into_raw(), width as usize, height as usize).encode()?;
+            out.write_all(sixel.as_bytes())?;
+        }
+        _ => {}
+    }
+    out.flush()?;
+    Ok(())
+}
+
+/// Removes every kitty image from the screen.
+pub fn clear_kitty(out: &mut impl Write) -> io::Result<()> {
+    out.write_all(b"\x1b_Ga=d,q=2\x1b\\")?;
+    out.flush()
+}
+
+fn cell_size() -> (u16, u16) {
+    match terminal::window_size() {
+        Ok(size) if size.width > 0 && size.height > 0 && size.columns > 0 && size.rows > 0 => {
+            ((size.width / size.columns).max(1), (size.height / size.rows).max(1))
+        }
+        _ => DEFAULT_CELL,
+    }
+}
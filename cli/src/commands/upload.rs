@@ -0,0 +1,106 @@
+use std::collections::{HashMap, HashSet};
+use std::fs;
+use std::path::Path;
+use anyhow::{Result, Context, bail};
+use tracing::info;
+use crate::cli::UploadArgs;
+use deep_archive::archive::cloud::Bucket;
+use deep_archive::archive::restore::{self, Location};
+use deep_archive::archive::{self, bagit_builder};
+use deep_archive::database::repo::{CatalogReader, TransactionManager, VolumeSummary, VolumeUpload};
+use deep_archive::ingest::hasher;
+use deep_archive::utils::config::Config;
+use deep_archive::utils::units::format_size;
+
+/// Uploads a registered volume to a remote, as one object or file by file,
+/// and records the copy under the volume.
+pub fn run(args: UploadArgs, db_path: &str, config: &Config) -> Result<()> {
+    let remote = config
+        .archive
+        .remotes
+        .get(&args.remote)
+        .with_context(|| format!("No remote named {} under [archive.remotes]", args.remote))?;
+    let reader = CatalogReader::open(db_path, &config.database)?;
+    let is_bag = args.volume.join(bagit_builder::TAG_MANIFEST).is_file();
+    let fingerprint = match is_bag {
+        true => bagit_builder::fingerprint(&args.volume)?,
+        false => hasher::fingerprint(&args.volume)?,
+    };
+    let registry = reader.volumes()?;
+    let summary = registry
+        .iter()
+        .find(|s| s.volume.sha256.as_deref() == Some(fingerprint.hash.as_str()))
+        .with_context(|| format!("{:?} isn't a registered volume, or was changed after `archive` wrote it", args.volume))?;
+    let volume = &summary.volume;
+    let bucket = Bucket::open(remote)?;
+
+    let (key, layout, object_id, size) = match args.objects {
+        false => {
+            if is_bag {
+                bail!("{:?} is a bag, which is a directory; upload its files with --objects", args.volume);
+            }
+            let key = format!("{}{}/{}", remote.prefix, volume.label, volume.name);
+            info!("Uploading volume {} ({}) to {}/{}", volume.label, format_size(fingerprint.size), bucket.name, key);
+            let object_id = bucket.upload(&key, &args.volume)?;
+            (key, "image", object_id, fingerprint.size)
+        }
+        true => {
+            upload_objects(&bucket, &remote.prefix, &reader, summary, &args.volume)?;
+            (remote.prefix.clone(), "objects", None, summary.file_bytes)
+        }
+    };
+
+    let upload = VolumeUpload {
+        label: volume.label.clone(),
+        remote: args.remote.clone(),
+        bucket: bucket.name.clone(),
+        key,
+        layout: layout.to_string(),
+        object_id,
+        storage_class: bucket.storage_class.clone(),
+        size_bytes: size,
+        uploaded_at: chrono::Utc::now().timestamp(),
+    };
+    TransactionManager::new(db_path, &config.database)?.record_upload(&upload)?;
+    info!("Recorded volume {} in {}/{}", volume.label, bucket.name, upload.key);
+    Ok(())
+}
+
+/// Uploads each distinct file on the volume at `path` that the bucket lacks
+/// to its `objects/` key, checking it against the registry on the way.
+fn upload_objects(bucket: &Bucket, prefix: &str, reader: &CatalogReader, summary: &VolumeSummary, path: &Path) -> Result<()> {
+    let mut wanted = HashMap::new();
+    let mut seen = HashSet::new();
+    for file in reader.volume_files(summary.id)? {
+        if !seen.insert(file.hash.clone()) {
+            continue;
+        }
+        let key = format!("{}{}", prefix, archive::archive_path(&archive::object_path(&file.hash)));
+        if !bucket.exists(&key)? {
+            wanted.insert(file.path, (file.hash, key));
+        }
+    }
+    info!("Uploading {} of the {} distinct files on volume {} to {}", wanted.len(), seen.len(), summary.volume.label, bucket.name);
+
+    let staging = std::env::temp_dir().join(format!("deep-archive-upload-{}", std::process::id()));
+    let paths: HashSet<String> = wanted.keys().cloned().collect();
+    let mut sent = HashSet::new();
+    let read = Location::open(path)?.read(&paths, &mut |file, _, _, content| {
+        let (hash, key) = &wanted[file];
+        // Staged so a damaged copy on the volume is caught before it goes up.
+        let staged = staging.join(hash);
+        let uploaded = restore::write_verified(content, &staged, hash)
+            .with_context(|| format!("{} on volume {}", file, summary.volume.label))
+            .and_then(|_| bucket.upload(key, &staged));
+        let _ = fs::remove_file(&staged);
+        uploaded?;
+        sent.insert(file.to_string());
+        Ok(())
+    });
+    let _ = fs::remove_dir(&staging);
+    read?;
+    if sent.len() < wanted.len() {
+        bail!("{} files of volume {} are missing from {:?}", wanted.len() - sent.len(), summary.volume.label, path);
+    }
+    Ok(())
+}
@@ -0,0 +1,83 @@
+use std::collections::BTreeMap;
+use std::io::ErrorKind;
+use std::path::Path;
+use anyhow::Result;
+use tracing::{info, warn};
+use crate::cli::VerifyArgs;
+use deep_archive::database::repo::{CatalogReader, FixityCheck, FixityResult, TransactionManager};
+use deep_archive::ingest::hasher;
+use deep_archive::utils::config::Config;
+use deep_archive::utils::units::format_timestamp;
+
+/// Checks are written in batches so an interrupted scrub keeps what it did.
+const BATCH_SIZE: usize = 1000;
+
+/// Re-hashes every live path of the matching artifacts and appends the
+/// outcome to `fixity_checks`.
+pub fn run(args: VerifyArgs, db_path: &str, config: &Config) -> Result<()> {
+    let reader = CatalogReader::open(db_path, &config.database)?;
+    if args.report {
+        return report(&reader);
+    }
+
+    let mut tm = TransactionManager::new(db_path, &config.database)?;
+    let mut batch = Vec::with_capacity(BATCH_SIZE);
+    let mut totals: BTreeMap<FixityResult, u64> = BTreeMap::new();
+
+    for artifact in reader.find(&args.filter.to_filter_set()) {
+        let artifact = artifact?;
+        for location in reader.paths(artifact.id)? {
+            let (result, detail) = check(Path::new(&location.path), &artifact.hash_sha256);
+            if result != FixityResult::Ok {
+                warn!("{} {}: {}", result.as_str(), location.path, detail.as_deref().unwrap_or(""));
+            }
+            *totals.entry(result).or_default() += 1;
+
+            batch.push(FixityCheck {
+                artifact_id: artifact.id,
+                path: location.path,
+                checked_at: chrono::Utc::now().timestamp(),
+                algo: "sha256",
+                result,
+                detail,
+            });
+            if batch.len() >= BATCH_SIZE {
+                tm.record_fixity(&batch)?;
+                batch.clear();
+            }
+        }
+    }
+    tm.record_fixity(&batch)?;
+
+    let summary: Vec<String> = totals.iter().map(|(result, n)| format!("{} {}", n, result.as_str())).collect();
+    info!("Verified {} paths: {}", totals.values().sum::<u64>(), summary.join(", "));
+    Ok(())
+}
+
+fn check(path: &Path, expected: &str) -> (FixityResult, Option<String>) {
+    match hasher::fingerprint(path) {
+        Ok(fp) if fp.hash == expected => (FixityResult::Ok, None),
+        Ok(fp) => (FixityResult::Mismatch, Some(fp.hash)),
+        Err(e) => {
+            let missing = e.downcast_ref::<std::io::Error>().is_some_and(|e| e.kind() == ErrorKind::NotFound);
+            let result = if missing { FixityResult::Missing } else { FixityResult::Error };
+            (result, Some(format!("{:#}", e)))
+        }
+    }
+}
+
+fn report(reader: &CatalogReader) -> Result<()> {
+    let stats = reader.fixity_stats()?;
+    println!(
+        "{} checks between {} and {}: {} ok, {} mismatch, {} missing, {} error",
+        stats.checks,
+        format_timestamp(stats.first_check),
+        format_timestamp(stats.last_check),
+        stats.ok,
+        stats.mismatch,
+        stats.missing,
+        stats.error
+    );
+    println!("{} artifacts never verified", stats.never_verified);
+    Ok(())
+}
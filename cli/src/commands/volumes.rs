@@ -0,0 +1,72 @@
+use anyhow::Result;
+use deep_archive::database::repo::CatalogReader;
+use deep_archive::utils::config::Config;
+use deep_archive::utils::units::{format_size, format_timestamp};
+
+pub fn run(db_path: &str, config: &Config, discs: bool, uploads: bool, json: bool) -> Result<()> {
+    let reader = CatalogReader::open(db_path, &config.database)?;
+    let volumes = reader.volumes()?;
+    if json {
+        for summary in &volumes {
+            let mut value = serde_json::to_value(summary)?;
+            if discs {
+                value["burned"] = serde_json::to_value(reader.discs(summary.id)?)?;
+            }
+            if uploads {
+                value["uploaded"] = serde_json::to_value(reader.uploads(summary.id)?)?;
+            }
+            println!("{}", value);
+        }
+        return Ok(());
+    }
+
+    println!("LABEL\tNAME\tBARCODE\tFORMAT\tWRITTEN\tSIZE\tFILES\tDISCS\tUPLOADS\tSHA256");
+    for summary in volumes {
+        let volume = summary.volume;
+        println!(
+            "{}\t{}\t{}\t{}\t{}\t{}\t{}\t{}\t{}\t{}",
+            volume.label,
+            volume.name,
+            volume.barcode.as_deref().unwrap_or("-"),
+            volume.format,
+            format_timestamp(Some(volume.created_at)),
+            format_size(volume.size_bytes),
+            summary.files,
+            summary.discs,
+            summary.uploads,
+            volume.sha256.as_deref().unwrap_or("-")
+        );
+        if uploads {
+            for upload in reader.uploads(summary.id)? {
+                println!(
+                    "  {}\t{}\t{}/{}\t{}\t{}",
+                    format_timestamp(Some(upload.uploaded_at)),
+                    upload.remote,
+                    upload.bucket,
+                    upload.key,
+                    upload.layout,
+                    upload.storage_class.as_deref().unwrap_or("-")
+                );
+            }
+        }
+        if !discs {
+            continue;
+        }
+        for disc in reader.discs(summary.id)? {
+            let verified = match disc.verified {
+                Some(true) => "verified",
+                Some(false) => "FAILED",
+                None => "not verified",
+            };
+            println!(
+                "  {}\t{}\t{}\t{}\t{}",
+                format_timestamp(Some(disc.burned_at)),
+                verified,
+                disc.device,
+                disc.drive.as_deref().unwrap_or("-"),
+                disc.media.as_deref().unwrap_or("-")
+            );
+        }
+    }
+    Ok(())
+}
@@ -0,0 +1,112 @@
+mod cli;
+mod commands;
+
+use std::io;
+use anyhow::Result;
+use clap::Parser;
+use tracing::info;
+use tracing_subscriber::fmt::writer::BoxMakeWriter;
+
+use crate::cli::{ArchiveCommand, Cli, Command};
+use deep_archive::database::repo::TransactionManager;
+use deep_archive::ingest::events;
+use deep_archive::utils::{config, priority};
+use deep_archive::utils::config::TelemetryConfig;
+
+fn main() -> Result<()> {
+    let cli = Cli::parse();
+    // Read before logging starts, as `[telemetry]` has a say in it.
+    let mut config = config::load_config(cli.config.as_deref())?;
+    let writer = if matches!(cli.command, Command::Tui(_)) {
+        // Log lines would tear through the screen.
+        BoxMakeWriter::new(io::sink)
+    } else if cli.events.is_some() && cli.events_to == "-" {
+        // Keeps stdout to the events.
+        BoxMakeWriter::new(io::stderr)
+    } else {
+        BoxMakeWriter::new(io::stdout)
+    };
+    // Exports what is left of the spans when main returns.
+    let _telemetry = init_logging(writer, &config.telemetry)?;
+    if let Some(path) = config::config_file(cli.config.as_deref()) {
+        info!("Loaded config from {:?}", path);
+    }
+    if cli.events.is_some() {
+        events::init(&cli.events_to)?;
+    }
+    config.process.nice = cli.nice.or(config.process.nice);
+    if let Some(io) = cli.io_priority {
+        config.process.io_priority = io.into();
+    }
+    // Before any thread pools start, so their threads inherit it.
+    priority::apply(config.process.nice, config.process.io_priority)?;
+
+    match cli.command {
+        Command::Ingest(args) => commands::ingest::run(args, &cli.db_path, config),
+        Command::Archive(args) => match args.command {
+            Some(ArchiveCommand::Burn(burn)) => commands::burn::run(burn, &cli.db_path, &config),
+            #[cfg(feature = "cloud")]
+            Some(ArchiveCommand::Upload(upload)) => commands::upload::run(upload, &cli.db_path, &config),
+            None => commands::archive::run(*args, &cli.db_path, &config),
+        },
+        Command::Query(args) => commands::query::run(*args, &cli.db_path, &config),
+        Command::Dedupe(args) => commands::dedupe::run(*args, &cli.db_path, &config),
+        Command::Export(args) => commands::export::run(*args, &cli.db_path, &config),
+        Command::Import(args) => commands::import::run(args, &cli.db_path, &config),
+        Command::Delete(args) => commands::delete::run(*args, &cli.db_path, &config),
+        Command::Runs(command) => commands::runs::run(command, &cli.db_path, &config),
+        Command::Restore(args) => commands::restore::run(*args, &cli.db_path, &config),
+        Command::Verify(args) => commands::verify::run(*args, &cli.db_path, &config),
+        Command::Tags(command) => commands::tags::run(command, &cli.db_path, &config),
+        Command::Relations(command) => commands::relations::run(command, &cli.db_path, &config),
+        Command::Sources => commands::sources::run(&cli.db_path, &config),
+        Command::Volumes { discs, uploads, json } => commands::volumes::run(&cli.db_path, &config, discs, uploads, json),
+        Command::Index(command) => commands::index::run(command, &cli.db_path, &config),
+        Command::Stats { top_tags, slowest, json } => commands::stats::run(&cli.db_path, &config, top_tags, slowest, json),
+        Command::Errors(command) => commands::errors::run(command, &cli.db_path, config),
+        Command::ReplayFailed { run_id, stages } => commands::errors::replay(&cli.db_path, config, run_id, &stages),
+        Command::Worker { coordinator, threads } => commands::ingest::worker(&coordinator, threads, config),
+        Command::Bench(args) => commands::bench::run(args, config),
+        Command::Serve { addr, grpc_addr } => commands::serve::run(&cli.db_path, config, addr, grpc_addr),
+        Command::Tui(args) => commands::tui::run(*args, &cli.db_path, &config),
+        Command::Daemon { watch, addr, no_serve, socket } => {
+            config.server.addr = addr.unwrap_or(config.server.addr);
+            config.daemon.serve &= !no_serve;
+            config.daemon.socket = socket.or(config.daemon.socket);
+            commands::daemon::run(&cli.db_path, config, cli.config, watch)
+        }
+        Command::Ctl { command, socket, json } => commands::daemon::ctl(command, socket.or(config.daemon.socket), json),
+        Command::ReindexFts => {
+            TransactionManager::new(&cli.db_path, &config.database)?.reindex_fts()?;
+            info!("Search index rebuilt");
+            Ok(())
+        }
+        Command::Db(command) => commands::db::run(command, &cli.db_path, &config),
+    }
+}
+
+/// Logs at info level to `writer`, and exports the pipeline's spans where
+/// `[telemetry]` or `$OTEL_EXPORTER_OTLP_ENDPOINT` says.
+#[cfg(feature = "otel")]
+fn init_logging(writer: BoxMakeWriter, config: &TelemetryConfig) -> Result<Option<deep_archive::utils::telemetry::Telemetry>> {
+    use deep_archive::utils::telemetry;
+    use tracing_subscriber::filter::LevelFilter;
+    use tracing_subscriber::prelude::*;
+    let logs = tracing_subscriber::fmt::layer().with_writer(writer).with_filter(LevelFilter::INFO);
+    if !config.enabled() {
+        tracing_subscriber::registry().with(logs).init();
+        return Ok(None);
+    }
+    let (spans, telemetry) = telemetry::layer(config)?;
+    tracing_subscriber::registry().with(logs).with(spans).init();
+    Ok(Some(telemetry))
+}
+
+#[cfg(not(feature = "otel"))]
+fn init_logging(writer: BoxMakeWriter, config: &TelemetryConfig) -> Result<Option<()>> {
+    tracing_subscriber::fmt().with_writer(writer).init();
+    if config.endpoint.is_some() {
+        tracing::warn!("telemetry.endpoint is set, but this build lacks the `otel` feature; no spans are exported");
+    }
+    Ok(None)
+}
@@ -27,19 +27,26 @@ else
     echo -e "${GREEN}✔ ffmpeg is installed.${NC}"
 fi
 
+# Only needed with archive.iso_backend = "xorriso"; ISOs are built natively by default.
 if ! command -v xorriso &> /dev/null; then
-    echo -e "${RED}✘ xorriso is not installed.${NC}"
-    MISSING_DEPS=1
+    echo -e "${YELLOW}- xorriso is not installed (optional).${NC}"
 else
     echo -e "${GREEN}✔ xorriso is installed.${NC}"
 fi
 
+# Only needed for `ingest --ytdlp`.
+if ! command -v yt-dlp &> /dev/null; then
+    echo -e "${YELLOW}- yt-dlp is not installed (optional).${NC}"
+else
+    echo -e "${GREEN}✔ yt-dlp is installed.${NC}"
+fi
+
 if [ $MISSING_DEPS -eq 1 ]; then
     echo -e "${YELLOW}Please install missing dependencies:${NC}"
-    echo "  Debian/Ubuntu: sudo apt install ffmpeg xorriso"
-    echo "  macOS: brew install ffmpeg xorriso"
-    echo "  Fedora: sudo dnf install ffmpeg xorriso"
-    echo "  Arch: sudo pacman -S ffmpeg xorriso"
+    echo "  Debian/Ubuntu: sudo apt install ffmpeg"
+    echo "  macOS: brew install ffmpeg"
+    echo "  Fedora: sudo dnf install ffmpeg"
+    echo "  Arch: sudo pacman -S ffmpeg"
     # We don't exit here, we allow the script to continue to download models,
     # but the user is warned.
 fi
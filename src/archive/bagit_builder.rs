@@ -0,0 +1,125 @@
+use std::fs::{self, File};
+use std::io::{self, BufReader, Read, Write};
+use std::path::Path;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+use anyhow::{Result, Context, bail};
+use chrono::DateTime;
+use sha2::{Digest, Sha256};
+use walkdir::WalkDir;
+use crate::archive::Member;
+use crate::archive::progress::Progress;
+use crate::ingest::hasher::{self, Fingerprint};
+use crate::utils::units::format_size;
+
+/// Directory of a bag holding the files archived.
+pub const PAYLOAD_DIR: &str = "data";
+
+/// Tag file listing the SHA-256 of every other tag file; its own hash
+/// stands for the whole bag in the volume registry.
+pub const TAG_MANIFEST: &str = "tagmanifest-sha256.txt";
+
+const DECLARATION: &str = "BagIt-Version: 1.0\nTag-File-Character-Encoding: UTF-8\n";
+
+/// Writes `members` as a BagIt bag at `output`, a directory that must not
+/// exist yet or be empty, reporting to `progress`.
+pub fn create_bag(members: &[Member], output: &Path, epoch: i64, info: &[(String, String)], progress: &Progress) -> Result<()> {
+    if fs::read_dir(output).is_ok_and(|mut entries| entries.next().is_some()) {
+        bail!("{:?} already exists; bags are only written into new or empty directories", output);
+    }
+    write_bag(members, output, epoch, info, progress)
+}
+
+/// Writes an RFC 8493 bag of `members` without external tools: the
+/// members under `data/`, their SHA-256 in `manifest-sha256.txt`,
+/// `bag-info.txt` with `info` after the fields every bag gets, and
+/// `tagmanifest-sha256.txt` over the tag files. `Bagging-Date` and every
+/// file's mtime are `epoch`, so the same tree gives the same bag.
+pub fn write_bag(members: &[Member], output: &Path, epoch: i64, info: &[(String, String)], progress: &Progress) -> Result<()> {
+    let payload = output.join(PAYLOAD_DIR);
+    fs::create_dir_all(&payload).with_context(|| format!("Failed to create {:?}", payload))?;
+    let mtime = UNIX_EPOCH + Duration::from_secs(epoch.max(0) as u64);
+
+    let mut manifest = String::new();
+    let mut octets = 0;
+    let mut count = 0;
+    for member in members {
+        let target = payload.join(&member.path);
+        if member.is_dir {
+            fs::create_dir_all(&target).with_context(|| format!("Failed to create {:?}", target))?;
+            continue;
+        }
+        if let Some(parent) = target.parent() {
+            fs::create_dir_all(parent).with_context(|| format!("Failed to create {:?}", parent))?;
+        }
+        let hash = copy_hashed(&member.source, &target, mtime, progress)?;
+        manifest.push_str(&format!("{}  {}/{}\n", hash, PAYLOAD_DIR, encode_path(&member.archive_path())));
+        octets += member.size;
+        count += 1;
+    }
+
+    let date = DateTime::from_timestamp(epoch, 0).unwrap_or_default().format("%Y-%m-%d").to_string();
+    let mut fields = vec![
+        ("Bag-Software-Agent".to_string(), format!("deep-archive {}", env!("CARGO_PKG_VERSION"))),
+        ("Bagging-Date".to_string(), date),
+        ("Payload-Oxum".to_string(), format!("{}.{}", octets, count)),
+        ("Bag-Size".to_string(), format_size(octets)),
+    ];
+    fields.extend(info.iter().cloned());
+    let bag_info: String = fields
+        .iter()
+        .map(|(label, value)| format!("{}: {}\n", label, value.trim().replace('\n', "\n  ")))
+        .collect();
+
+    let mut tag_manifest = String::new();
+    for (name, content) in [("bagit.txt", DECLARATION), ("bag-info.txt", &bag_info), ("manifest-sha256.txt", &manifest)] {
+        write_tag(output, name, content, mtime)?;
+        tag_manifest.push_str(&format!("{}  {}\n", hex::encode(Sha256::digest(content)), name));
+    }
+    write_tag(output, TAG_MANIFEST, &tag_manifest, mtime)
+}
+
+/// Registry fingerprint of the bag at `bag`: the SHA-256 of its tag
+/// manifest, which pins down every file in it, and the size of all files.
+pub fn fingerprint(bag: &Path) -> Result<Fingerprint> {
+    let tags = hasher::fingerprint(&bag.join(TAG_MANIFEST))?;
+    let mut size = 0;
+    for entry in WalkDir::new(bag) {
+        let entry = entry.with_context(|| format!("Failed to read {:?}", bag))?;
+        if entry.file_type().is_file() {
+            size += entry.metadata()?.len();
+        }
+    }
+    Ok(Fingerprint { hash: tags.hash, size, mtime: None, device: None })
+}
+
+fn write_tag(bag: &Path, name: &str, content: &str, mtime: SystemTime) -> Result<()> {
+    let path = bag.join(name);
+    let file = File::create(&path).with_context(|| format!("Failed to create {:?}", path))?;
+    (&file).write_all(content.as_bytes()).with_context(|| format!("Failed to write {:?}", path))?;
+    file.set_modified(mtime)?;
+    Ok(())
+}
+
+/// Manifest paths escape `%`, CR and LF, as RFC 8493 requires.
+fn encode_path(path: &str) -> String {
+    path.replace('%', "%25").replace('\r', "%0D").replace('\n', "%0A")
+}
+
+fn copy_hashed(source: &Path, target: &Path, mtime: SystemTime, progress: &Progress) -> Result<String> {
+    let mut reader = progress.reader(BufReader::new(File::open(source).with_context(|| format!("Failed to open {:?}", source))?));
+    let mut file = File::create(target).with_context(|| format!("Failed to create {:?}", target))?;
+    let mut hasher = Sha256::new();
+    let mut buffer = vec![0; 1 << 16];
+    loop {
+        let read = match reader.read(&mut buffer) {
+            Ok(0) => break,
+            Ok(read) => read,
+            Err(e) if e.kind() == io::ErrorKind::Interrupted => continue,
+            Err(e) => return Err(e).with_context(|| format!("Failed to read {:?}", source)),
+        };
+        hasher.update(&buffer[..read]);
+        file.write_all(&buffer[..read]).with_context(|| format!("Failed to write {:?}", target))?;
+    }
+    file.set_modified(mtime)?;
+    Ok(hex::encode(hasher.finalize()))
+}
@@ -0,0 +1,147 @@
+use std::fs::{self, File};
+use std::io::{BufReader, ErrorKind, Read};
+use std::path::{Path, PathBuf};
+use std::process::{Command, Stdio};
+use std::thread;
+use std::time::{Duration, Instant};
+use anyhow::{Result, Context, bail};
+use sha2::{Digest, Sha256};
+use tracing::info;
+use crate::utils::config::Burner;
+
+/// How long to wait for a drive to offer the disc again after burning;
+/// most reload the tray, and some take a while to spin the disc back up.
+const RELOAD_TIMEOUT: Duration = Duration::from_secs(60);
+
+/// Writes `image` to the disc in `device` with `burner`, closing the disc.
+pub fn burn(image: &Path, device: &str, burner: Burner) -> Result<()> {
+    let mut command = match burner {
+        Burner::Growisofs => {
+            // -dvd-compat closes the disc so any player can read it.
+            let mut command = Command::new("growisofs");
+            command.arg("-dvd-compat").arg("-Z").arg(format!("{}={}", device, image.display()));
+            command
+        }
+        Burner::Cdrecord => {
+            let program = if runnable("cdrecord") { "cdrecord" } else { "wodim" };
+            let mut command = Command::new(program);
+            command.arg("-v").arg("-dao").arg(format!("dev={}", device)).arg(image);
+            command
+        }
+        Burner::Xorriso => {
+            let mut command = Command::new("xorriso");
+            command.arg("-as").arg("cdrecord").arg("-v").arg(format!("dev={}", device)).arg(image);
+            command
+        }
+        Burner::Isoburn => {
+            // /Q burns without showing the dialog and waits until done.
+            let mut command = Command::new("isoburn.exe");
+            command.arg("/Q").arg(device).arg(image);
+            command
+        }
+    };
+    let program = command.get_program().to_string_lossy().into_owned();
+    info!("Burning {:?} to {} with {}", image, device, program);
+    let status = command
+        .stdin(Stdio::null())
+        .status()
+        .with_context(|| format!("Failed to execute {}. Is it installed?", program))?;
+    if !status.success() {
+        bail!("{} exited with {}", program, status);
+    }
+    Ok(())
+}
+
+/// SHA-256 of the first `len` bytes on the disc in `device`, for comparing
+/// with the image burned; the rest of the disc is padding.
+pub fn read_back(device: &str, len: u64) -> Result<String> {
+    let path = device_path(device);
+    let file = open_reloaded(&path)?;
+    // Raw devices only take whole sectors, which a large buffer keeps to.
+    let mut disc = BufReader::with_capacity(1 << 20, file).take(len);
+    let mut hasher = Sha256::new();
+    let mut buffer = vec![0; 1 << 20];
+    let mut read_total = 0;
+    loop {
+        let read = match disc.read(&mut buffer) {
+            Ok(0) => break,
+            Ok(read) => read,
+            Err(e) if e.kind() == ErrorKind::Interrupted => continue,
+            Err(e) => return Err(e).with_context(|| format!("Failed to read {:?} at byte {}", path, read_total)),
+        };
+        hasher.update(&buffer[..read]);
+        read_total += read as u64;
+    }
+    if read_total < len {
+        bail!("{:?} ended after {} of {} bytes", path, read_total, len);
+    }
+    Ok(hex::encode(hasher.finalize()))
+}
+
+/// Vendor, model and serial number of the drive behind `device`, where
+/// the system says (Linux only).
+pub fn drive(device: &str) -> Option<String> {
+    let block = fs::canonicalize(device).ok()?.file_name()?.to_string_lossy().into_owned();
+    let sys = PathBuf::from("/sys/block").join(&block);
+    let read = |name: &str| fs::read_to_string(sys.join("device").join(name)).ok().map(|s| s.trim().to_string());
+    let mut parts: Vec<String> = [read("vendor"), read("model")].into_iter().flatten().filter(|s| !s.is_empty()).collect();
+    let serial = fs::read_to_string(sys.join("dev")).ok().and_then(|dev| {
+        let udev = fs::read_to_string(format!("/run/udev/data/b{}", dev.trim())).ok()?;
+        udev.lines().find_map(|l| l.strip_prefix("E:ID_SERIAL_SHORT=").map(String::from))
+    });
+    if let Some(serial) = serial {
+        parts.push(format!("serial {}", serial));
+    }
+    (!parts.is_empty()).then(|| parts.join(" "))
+}
+
+/// Media type and manufacturer id of the disc in `device`, e.g.
+/// `BD-R SRM, CMCMAG/BA5`, from `dvd+rw-mediainfo` where installed.
+pub fn media(device: &str) -> Option<String> {
+    let output = Command::new("dvd+rw-mediainfo").arg(device).stdin(Stdio::null()).stderr(Stdio::null()).output().ok()?;
+    let text = String::from_utf8_lossy(&output.stdout);
+    let field = |name: &str| {
+        text.lines()
+            .find_map(|l| l.trim().strip_prefix(name))
+            .map(|v| v.trim().to_string())
+            .filter(|v| !v.is_empty())
+    };
+    // "Mounted Media: 41h, BD-R SRM"; the profile number means nothing to people.
+    let kind = field("Mounted Media:").map(|v| v.split_once(", ").map_or(v.clone(), |(_, name)| name.to_string()));
+    let parts: Vec<String> = [kind, field("Media ID:")].into_iter().flatten().collect();
+    (!parts.is_empty()).then(|| parts.join(", "))
+}
+
+/// The path raw reads of `device` go through: `\\.\E:` for a Windows
+/// drive letter, the device itself elsewhere.
+fn device_path(device: &str) -> PathBuf {
+    let letter = device.trim_end_matches(['\\', '/']);
+    if cfg!(windows) && letter.len() == 2 && letter.ends_with(':') {
+        return PathBuf::from(format!(r"\\.\{}", letter));
+    }
+    PathBuf::from(device)
+}
+
+/// Opens `path`, retrying while the drive reloads the disc.
+fn open_reloaded(path: &Path) -> Result<File> {
+    let started = Instant::now();
+    loop {
+        match File::open(path) {
+            Ok(file) => return Ok(file),
+            Err(e) if started.elapsed() < RELOAD_TIMEOUT && e.kind() != ErrorKind::PermissionDenied => {
+                thread::sleep(Duration::from_secs(2));
+            }
+            Err(e) => return Err(e).with_context(|| format!("Failed to open {:?} to read the disc back", path)),
+        }
+    }
+}
+
+/// True if `program` can be started at all.
+pub fn runnable(program: &str) -> bool {
+    Command::new(program)
+        .arg("-version")
+        .stdout(Stdio::null())
+        .stderr(Stdio::null())
+        .status()
+        .is_ok()
+}
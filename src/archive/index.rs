@@ -0,0 +1,143 @@
+use std::collections::{BTreeMap, HashSet};
+use std::fs::{self, File};
+use std::io::{BufRead, BufReader, BufWriter, Write};
+use std::path::Path;
+use anyhow::{Result, Context};
+use serde::{Deserialize, Serialize};
+use crate::database::repo::CatalogReader;
+
+/// File name of the index of all volumes, next to the per-volume ones.
+pub const ALL_VOLUMES: &str = "all-volumes.jsonl";
+
+/// The index of one volume, `<label>.json`: its registry entry and every
+/// file on it.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct VolumeIndex {
+    pub label: String,
+    pub name: String,
+    pub format: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub barcode: Option<String>,
+    /// Unix seconds.
+    pub created_at: i64,
+    pub size_bytes: u64,
+    /// SHA-256 of the whole image or tarball.
+    pub sha256: Option<String>,
+    pub files: Vec<IndexedFile>,
+}
+
+/// A file in a volume index.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct IndexedFile {
+    pub sha256: String,
+    /// Path on the volume.
+    pub path: String,
+    pub size: u64,
+    /// Where the content starts in the image, for reading it with `dd`;
+    /// only where the image holds it uncompressed and in one piece.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub offset: Option<u64>,
+}
+
+/// A line of `all-volumes.jsonl`: some content and its copies on volumes.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct IndexEntry {
+    pub sha256: String,
+    pub size: u64,
+    pub copies: Vec<IndexedCopy>,
+}
+
+/// A copy of some content on a volume.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct IndexedCopy {
+    /// Label of the volume.
+    pub volume: String,
+    pub name: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub barcode: Option<String>,
+    pub path: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub offset: Option<u64>,
+}
+
+/// Writes an index of every registered volume to `dir`, as `<label>.json`
+/// each, and `all-volumes.jsonl` with one line per distinct content, in
+/// hash order, listing every copy. Neither needs the catalog or
+/// deep-archive to read; `grep <sha256> all-volumes.jsonl` finds a file.
+/// Returns the number of volumes and of distinct files indexed.
+pub fn write(reader: &CatalogReader, dir: &Path) -> Result<(usize, usize)> {
+    fs::create_dir_all(dir).with_context(|| format!("Failed to create {:?}", dir))?;
+    let volumes = reader.volumes()?;
+    let mut contents: BTreeMap<String, IndexEntry> = BTreeMap::new();
+    for summary in &volumes {
+        let volume = &summary.volume;
+        let files: Vec<IndexedFile> = reader
+            .volume_files(summary.id)?
+            .into_iter()
+            .map(|f| IndexedFile { sha256: f.hash, path: f.path, size: f.size, offset: f.offset })
+            .collect();
+        for file in &files {
+            let entry = contents
+                .entry(file.sha256.clone())
+                .or_insert_with(|| IndexEntry { sha256: file.sha256.clone(), size: file.size, copies: Vec::new() });
+            entry.copies.push(IndexedCopy {
+                volume: volume.label.clone(),
+                name: volume.name.clone(),
+                barcode: volume.barcode.clone(),
+                path: file.path.clone(),
+                offset: file.offset,
+            });
+        }
+        let index = VolumeIndex {
+            label: volume.label.clone(),
+            name: volume.name.clone(),
+            format: volume.format.clone(),
+            barcode: volume.barcode.clone(),
+            created_at: volume.created_at,
+            size_bytes: volume.size_bytes,
+            sha256: volume.sha256.clone(),
+            files,
+        };
+        let path = dir.join(format!("{}.json", volume.label));
+        fs::write(&path, serde_json::to_vec(&index)?).with_context(|| format!("Failed to write {:?}", path))?;
+    }
+
+    let path = dir.join(ALL_VOLUMES);
+    // Written aside and moved into place, so a lookup never sees half of it.
+    let partial = dir.join(format!(".{}.partial", ALL_VOLUMES));
+    let mut out = BufWriter::new(File::create(&partial).with_context(|| format!("Failed to create {:?}", partial))?);
+    for entry in contents.values() {
+        serde_json::to_writer(&mut out, entry)?;
+        out.write_all(b"\n")?;
+    }
+    out.into_inner().map_err(|e| e.into_error())?.sync_all()?;
+    fs::rename(&partial, &path).with_context(|| format!("Failed to write {:?}", path))?;
+    Ok((volumes.len(), contents.len()))
+}
+
+/// The entries for `hashes` in the index at `path`: a directory written by
+/// `write`, or its `all-volumes.jsonl`. Content on no volume has none.
+pub fn lookup(path: &Path, hashes: &HashSet<String>) -> Result<Vec<IndexEntry>> {
+    let path = match path.is_dir() {
+        true => path.join(ALL_VOLUMES),
+        false => path.to_path_buf(),
+    };
+    let file = File::open(&path).with_context(|| format!("Failed to open {:?}", path))?;
+    let mut found = Vec::new();
+    for (n, line) in BufReader::new(file).lines().enumerate() {
+        let line = line.with_context(|| format!("Failed to read {:?}", path))?;
+        if line.trim().is_empty() {
+            continue;
+        }
+        // `write` puts the hash first, so most lines needn't be parsed.
+        let hash = line.strip_prefix("{\"sha256\":\"").and_then(|rest| rest.get(..64));
+        if hash.is_some_and(|hash| !hashes.contains(hash)) {
+            continue;
+        }
+        let entry: IndexEntry = serde_json::from_str(&line).with_context(|| format!("{:?} line {}", path, n + 1))?;
+        if hashes.contains(&entry.sha256) {
+            found.push(entry);
+        }
+    }
+    Ok(found)
+}
@@ -1,43 +1,92 @@
-use std::path::Path;
-use std::process::Command;
-use std::env;
-use std::fs;
-use anyhow::{Result, Context, anyhow};
+use std::collections::{HashMap, HashSet, VecDeque};
+use std::fs::{self, File};
+use std::io::{self, BufRead, BufReader, BufWriter, Read, Write};
+use std::path::{Path, PathBuf};
+use std::process::{Command, Stdio};
+use anyhow::{Result, Context, anyhow, bail};
+use chrono::{DateTime, Datelike, Timelike};
+use crate::archive::Member;
+use crate::archive::progress::Progress;
+use crate::utils::config::{ArchiveConfig, IsoBackend};
 
-pub fn create_iso(source_dir: &Path, output_iso: &Path) -> Result<()> {
-    // Ensure reproducible builds by setting SOURCE_DATE_EPOCH
-    // We use a fixed timestamp or one provided by the user/env.
-    // For this project, let's just set it to a fixed value (e.g., 0 or explicit date) if not present,
-    // or just ensure it is set. The prompt implies we must set it.
+/// Timestamp of volumes with no files to take one from (2024-01-01); see
+/// `archive::epoch`.
+pub const DEFAULT_EPOCH: i64 = 1704067200;
 
-    // Check if it's already set, if not, set to a deterministic value (e.g. 1704067200 for 2024-01-01)
-    if env::var("SOURCE_DATE_EPOCH").is_err() {
-        env::set_var("SOURCE_DATE_EPOCH", "1704067200");
-    }
+/// Default `{project}` of volume IDs and file names; see `archive::expand`.
+pub const VOLUME_ID: &str = "DEEP_ARCHIVE";
+const APPLICATION_ID: &str = "DEEP-ARCHIVE";
+
+pub const SECTOR: usize = 2048;
+/// Sectors 0-15 are the system area; volume descriptors start at 16.
+pub const FIRST_DESCRIPTOR: u32 = 16;
 
+/// Builds an ISO 9660 image of `members` at `output_iso` with the
+/// configured backend, named `volume_id` (see `archive::volume_id`) and
+/// with `epoch` for every timestamp, reporting to `progress`.
+pub fn create_iso(members: &[Member], output_iso: &Path, volume_id: &str, epoch: i64, config: &ArchiveConfig, progress: &Progress) -> Result<()> {
     // Ensure the parent directory exists
     if let Some(parent) = output_iso.parent() {
         fs::create_dir_all(parent)
             .context("Failed to create parent directory for ISO output")?;
     }
 
-    // Command: xorriso -as mkisofs -o output.iso -R -J source_dir
+    match config.iso_backend {
+        IsoBackend::Native => write_iso(members, output_iso, volume_id, epoch, progress),
+        IsoBackend::Xorriso => xorriso(members, output_iso, volume_id, epoch, progress),
+    }
+}
+
+fn xorriso(members: &[Member], output_iso: &Path, volume_id: &str, epoch: i64, progress: &Progress) -> Result<()> {
+    // Command: xorriso -as mkisofs -o output.iso -R -J -graft-points -path-list list
     // -R: Rock Ridge extensions (posix perms)
     // -J: Joliet extensions (windows compatibility)
+    // -iso-level 3: files over 4 GiB, split into several extents
     // -V: Volume ID
+    // Each line of the list grafts one file as `path/in/image=source`, so
+    // only the members end up in the image (but no empty directories).
+    // SOURCE_DATE_EPOCH in xorriso's environment (only) makes it use one
+    // fixed date for every timestamp.
+    let escape = |p: &Path| p.to_string_lossy().replace('\\', "\\\\").replace('=', "\\=");
+    let list_path = output_iso.with_extension("paths");
+    let mut list = String::new();
+    for member in members.iter().filter(|m| !m.is_dir) {
+        list.push_str(&format!("{}={}\n", escape(&member.path), escape(&member.source)));
+    }
+    fs::write(&list_path, list).with_context(|| format!("Failed to write {:?}", list_path))?;
 
-    let status = Command::new("xorriso")
+    let child = Command::new("xorriso")
+        .env("SOURCE_DATE_EPOCH", epoch.to_string())
         .arg("-as")
         .arg("mkisofs")
         .arg("-o")
         .arg(output_iso)
         .arg("-R")
         .arg("-J")
+        .arg("-iso-level")
+        .arg("3")
         .arg("-V")
-        .arg("DEEP_ARCHIVE")
-        .arg(source_dir)
-        .status()
-        .context("Failed to execute xorriso command. Is it installed?")?;
+        .arg(volume_id)
+        .arg("-graft-points")
+        .arg("-path-list")
+        .arg(&list_path)
+        .stderr(Stdio::piped())
+        .spawn();
+    let status = child.context("Failed to execute xorriso command. Is it installed?").and_then(|mut child| {
+        // xorriso reports `UPDATE :  12.34% done` about once a second; the
+        // rest is passed on as it is.
+        let stderr = child.stderr.take().expect("stderr is piped");
+        for line in BufReader::new(stderr).lines() {
+            let line = line?;
+            match line.split_once("% done").and_then(|(before, _)| before.rsplit(' ').next()?.parse().ok()) {
+                Some(percent) => progress.set_percent(percent),
+                None => eprintln!("{}", line),
+            }
+        }
+        Ok(child.wait()?)
+    });
+    let _ = fs::remove_file(&list_path);
+    let status = status?;
 
     if !status.success() {
         return Err(anyhow!("xorriso exited with non-zero status"));
@@ -45,3 +94,657 @@ pub fn create_iso(source_dir: &Path, output_iso: &Path) -> Result<()> {
 
     Ok(())
 }
+
+/// Writes an ISO 9660 image of `members` without external tools.
+///
+/// The primary tree uses uppercase level 2 names (up to 30 characters) and
+/// carries Rock Ridge entries with the original names, so Linux and macOS
+/// see the tree as it was. A Joliet tree gives Windows the original names
+/// too, up to 64 characters. Every timestamp is `epoch`, files are mode
+/// 0444, directories 0555 and both are owned by root, as with `mkisofs -r`.
+/// Files over 4 GiB are rejected.
+pub fn write_iso(members: &[Member], output_iso: &Path, volume_id: &str, epoch: i64, progress: &Progress) -> Result<()> {
+    let image = Image::new(members, volume_id, epoch)?;
+    let file = File::create(output_iso).with_context(|| format!("Failed to create {:?}", output_iso))?;
+    let mut out = BufWriter::new(file);
+    image.write(&mut out, progress).with_context(|| format!("Failed to write {:?}", output_iso))?;
+    out.flush()?;
+    Ok(())
+}
+
+/// The node of the directory at `path`, created along with any missing
+/// ancestors.
+fn directory(nodes: &mut Vec<Node>, dirs: &mut HashMap<PathBuf, usize>, path: &Path) -> usize {
+    if let Some(&index) = dirs.get(path) {
+        return index;
+    }
+    let parent = directory(nodes, dirs, path.parent().unwrap_or(Path::new("")));
+    let index = nodes.len();
+    nodes.push(Node { name: file_name(path), path: PathBuf::new(), size: 0, parent, children: Some(Vec::new()) });
+    nodes[parent].children.as_mut().expect("parent is a directory").push(index);
+    dirs.insert(path.to_path_buf(), index);
+    index
+}
+
+fn file_name(path: &Path) -> String {
+    path.file_name().map(|n| n.to_string_lossy().into_owned()).unwrap_or_default()
+}
+
+struct Node {
+    /// Original file name, used by Rock Ridge; empty for the root.
+    name: String,
+    /// Content of files.
+    path: PathBuf,
+    size: u64,
+    parent: usize,
+    /// `Some` for directories.
+    children: Option<Vec<usize>>,
+}
+
+impl Node {
+    fn is_dir(&self) -> bool {
+        self.children.is_some()
+    }
+}
+
+#[derive(Clone, Copy, PartialEq, Eq)]
+enum Tree {
+    Primary,
+    Joliet,
+}
+
+/// One directory hierarchy (primary or Joliet) over the shared nodes.
+struct Layout {
+    /// Directory record identifier per node.
+    ids: Vec<Vec<u8>>,
+    /// Children of each directory in identifier order.
+    sorted: Vec<Vec<usize>>,
+    /// Directories in path table order; a directory's number is its index + 1.
+    dirs: Vec<usize>,
+    /// Per node: first sector and sector count of the directory extent, and
+    /// of its Rock Ridge continuation area.
+    extent: Vec<(u32, u32)>,
+    continuation: Vec<(u32, u32)>,
+    path_table_size: u32,
+    /// Little- and big-endian path table locations.
+    path_tables: (u32, u32),
+}
+
+struct Image {
+    nodes: Vec<Node>,
+    volume_id: String,
+    epoch: i64,
+    primary: Layout,
+    joliet: Layout,
+    /// First sector of each file's data.
+    file_lba: Vec<u32>,
+    /// Files in the order their data is written.
+    files: Vec<usize>,
+    total_sectors: u32,
+}
+
+impl Image {
+    fn new(members: &[Member], volume_id: &str, epoch: i64) -> Result<Self> {
+        let mut nodes = vec![Node { name: String::new(), path: PathBuf::new(), size: 0, parent: 0, children: Some(Vec::new()) }];
+        let mut dirs = HashMap::from([(PathBuf::new(), 0)]);
+        for member in members {
+            if member.size > u32::MAX as u64 {
+                bail!("{:?} is larger than 4 GiB, which ISO 9660 can't hold; write a UDF image instead", member.source);
+            }
+            let parent = member.path.parent().unwrap_or(Path::new(""));
+            let parent = directory(&mut nodes, &mut dirs, parent);
+            if member.is_dir {
+                directory(&mut nodes, &mut dirs, &member.path);
+                continue;
+            }
+            let index = nodes.len();
+            nodes.push(Node {
+                name: file_name(&member.path),
+                path: member.source.clone(),
+                size: member.size,
+                parent,
+                children: None,
+            });
+            nodes[parent].children.as_mut().expect("parent is a directory").push(index);
+        }
+
+        let mut image = Image {
+            primary: Layout::new(&nodes, Tree::Primary),
+            joliet: Layout::new(&nodes, Tree::Joliet),
+            file_lba: vec![0; nodes.len()],
+            files: Vec::new(),
+            total_sectors: 0,
+            nodes,
+            volume_id: volume_id.to_string(),
+            epoch,
+        };
+        image.allocate();
+        Ok(image)
+    }
+
+    /// Assigns sectors: volume descriptors, path tables, directories of
+    /// both trees, then file data in primary tree order.
+    fn allocate(&mut self) {
+        // Primary, Joliet supplementary, terminator.
+        let mut next = FIRST_DESCRIPTOR + 3;
+        for layout in [&mut self.primary, &mut self.joliet] {
+            let sectors = sectors_for(layout.path_table_size as u64);
+            layout.path_tables = (next, next + sectors);
+            next += 2 * sectors;
+        }
+
+        for tree in [Tree::Primary, Tree::Joliet] {
+            let dirs = self.layout(tree).dirs.clone();
+            for dir in dirs {
+                // Sizes don't depend on locations, so render once to measure.
+                let (records, continuation) = self.directory(tree, dir);
+                let layout = self.layout_mut(tree);
+                let sectors = sectors_for(records.len() as u64);
+                layout.extent[dir] = (next, sectors);
+                next += sectors;
+                let sectors = sectors_for(continuation.len() as u64);
+                layout.continuation[dir] = (next, sectors);
+                next += sectors;
+            }
+        }
+
+        for &dir in &self.primary.dirs {
+            for &child in &self.primary.sorted[dir] {
+                if self.nodes[child].is_dir() {
+                    continue;
+                }
+                self.files.push(child);
+                if self.nodes[child].size > 0 {
+                    self.file_lba[child] = next;
+                    next += sectors_for(self.nodes[child].size);
+                }
+            }
+        }
+        self.total_sectors = next;
+    }
+
+    fn layout(&self, tree: Tree) -> &Layout {
+        match tree {
+            Tree::Primary => &self.primary,
+            Tree::Joliet => &self.joliet,
+        }
+    }
+
+    fn layout_mut(&mut self, tree: Tree) -> &mut Layout {
+        match tree {
+            Tree::Primary => &mut self.primary,
+            Tree::Joliet => &mut self.joliet,
+        }
+    }
+
+    fn write(&self, out: &mut impl Write, progress: &Progress) -> Result<()> {
+        out.write_all(&[0; SECTOR * FIRST_DESCRIPTOR as usize])?;
+        out.write_all(&self.volume_descriptor(Tree::Primary))?;
+        out.write_all(&self.volume_descriptor(Tree::Joliet))?;
+        let mut terminator = [0; SECTOR];
+        terminator[0] = 255;
+        terminator[1..6].copy_from_slice(b"CD001");
+        terminator[6] = 1;
+        out.write_all(&terminator)?;
+
+        for tree in [Tree::Primary, Tree::Joliet] {
+            write_padded(out, &self.path_table(tree, false))?;
+            write_padded(out, &self.path_table(tree, true))?;
+        }
+
+        for tree in [Tree::Primary, Tree::Joliet] {
+            for &dir in &self.layout(tree).dirs {
+                let (records, continuation) = self.directory(tree, dir);
+                write_padded(out, &records)?;
+                write_padded(out, &continuation)?;
+            }
+        }
+
+        for &file in &self.files {
+            let node = &self.nodes[file];
+            let source = File::open(&node.path).with_context(|| format!("Failed to open {:?}", node.path))?;
+            let copied = io::copy(&mut progress.reader(source.take(node.size)), out)?;
+            if copied != node.size {
+                bail!("{:?} shrank while the image was being written", node.path);
+            }
+            let tail = (node.size % SECTOR as u64) as usize;
+            if tail > 0 {
+                out.write_all(&vec![0; SECTOR - tail])?;
+            }
+        }
+        Ok(())
+    }
+
+    fn volume_descriptor(&self, tree: Tree) -> [u8; SECTOR] {
+        let layout = self.layout(tree);
+        let joliet = tree == Tree::Joliet;
+        let mut d = [0; SECTOR];
+        d[0] = if joliet { 2 } else { 1 };
+        d[1..6].copy_from_slice(b"CD001");
+        d[6] = 1;
+        fill_text(&mut d[8..40], "", joliet);
+        // Joliet has room for the first 16 characters.
+        fill_text(&mut d[40..72], &self.volume_id, joliet);
+        both32(&mut d[80..88], self.total_sectors);
+        if joliet {
+            // UCS-2 level 3
+            d[88..91].copy_from_slice(b"%/E");
+        }
+        both16(&mut d[120..124], 1);
+        both16(&mut d[124..128], 1);
+        both16(&mut d[128..132], SECTOR as u16);
+        both32(&mut d[132..140], layout.path_table_size);
+        d[140..144].copy_from_slice(&layout.path_tables.0.to_le_bytes());
+        d[148..152].copy_from_slice(&layout.path_tables.1.to_be_bytes());
+        let root = self.record(tree, 0, Some(&[0]), &[]);
+        d[156..156 + root.len()].copy_from_slice(&root);
+        fill_text(&mut d[190..318], "", joliet);
+        fill_text(&mut d[318..446], "", joliet);
+        fill_text(&mut d[446..574], "", joliet);
+        fill_text(&mut d[574..702], APPLICATION_ID, joliet);
+        fill_text(&mut d[702..813], "", joliet);
+        let date = volume_date(self.epoch);
+        d[813..830].copy_from_slice(&date);
+        d[830..847].copy_from_slice(&date);
+        // No expiration date.
+        d[847..863].fill(b'0');
+        d[864..881].copy_from_slice(&date);
+        d[881] = 1;
+        d
+    }
+
+    fn path_table(&self, tree: Tree, big_endian: bool) -> Vec<u8> {
+        let layout = self.layout(tree);
+        let mut numbers = vec![0u16; self.nodes.len()];
+        for (i, &dir) in layout.dirs.iter().enumerate() {
+            numbers[dir] = i as u16 + 1;
+        }
+        let mut table = Vec::new();
+        for &dir in &layout.dirs {
+            let id: &[u8] = if dir == 0 { &[0] } else { &layout.ids[dir] };
+            let parent = numbers[self.nodes[dir].parent];
+            let lba = layout.extent[dir].0;
+            table.push(id.len() as u8);
+            table.push(0);
+            if big_endian {
+                table.extend(lba.to_be_bytes());
+                table.extend(parent.to_be_bytes());
+            } else {
+                table.extend(lba.to_le_bytes());
+                table.extend(parent.to_le_bytes());
+            }
+            table.extend(id);
+            if id.len() % 2 == 1 {
+                table.push(0);
+            }
+        }
+        table
+    }
+
+    /// The directory extent of `dir` and its continuation area, unpadded.
+    /// Records never straddle a sector, nor continuation entries a block.
+    fn directory(&self, tree: Tree, dir: usize) -> (Vec<u8>, Vec<u8>) {
+        let layout = self.layout(tree);
+        let mut records = Vec::new();
+        let mut continuation = Vec::new();
+
+        let entries = [(dir, Some(&[0u8][..])), (self.nodes[dir].parent, Some(&[1u8][..]))]
+            .into_iter()
+            .chain(layout.sorted[dir].iter().map(|&child| (child, None)));
+        for (i, (node, dot)) in entries.enumerate() {
+            let mut susp = Vec::new();
+            if tree == Tree::Primary {
+                if dir == 0 && i == 0 {
+                    // SUSP indicator; must be the first entry of the root's "." record.
+                    susp.push(vec![b'S', b'P', 7, 1, 0xBE, 0xEF, 0]);
+                }
+                susp.push(self.posix_attributes(node));
+                susp.push(timestamps(self.epoch));
+                if dot.is_none() {
+                    susp.extend(alternate_name(&self.nodes[node].name));
+                }
+                if dir == 0 && i == 0 {
+                    susp.push(extension_reference());
+                }
+            }
+
+            let fixed = 33 + dot.map_or(layout.ids[node].len(), <[u8]>::len);
+            let available = 255 - (fixed + fixed % 2);
+            let mut inline: Vec<u8> = susp.concat();
+            if inline.len() > available {
+                // Keep what fits beside a CE entry and move the rest out.
+                let mut kept = 0;
+                let mut used = 0;
+                while used + susp[kept].len() + CE_LEN <= available {
+                    used += susp[kept].len();
+                    kept += 1;
+                }
+                let overflow = susp[kept..].concat();
+                let offset = continuation.len() % SECTOR;
+                if offset + overflow.len() > SECTOR {
+                    continuation.resize(continuation.len() + SECTOR - offset, 0);
+                }
+                let block = layout.continuation[dir].0 + (continuation.len() / SECTOR) as u32;
+                let ce = continuation_entry(block, (continuation.len() % SECTOR) as u32, overflow.len() as u32);
+                continuation.extend(overflow);
+                inline = susp[..kept].concat();
+                inline.extend(ce);
+            }
+
+            let record = self.record(tree, node, dot, &inline);
+            let offset = records.len() % SECTOR;
+            if offset + record.len() > SECTOR {
+                records.resize(records.len() + SECTOR - offset, 0);
+            }
+            records.extend(record);
+        }
+        (records, continuation)
+    }
+
+    /// A directory record for `node`; `dot` overrides the identifier for the
+    /// "." (0x00) and ".." (0x01) entries.
+    fn record(&self, tree: Tree, node: usize, dot: Option<&[u8]>, system_use: &[u8]) -> Vec<u8> {
+        let layout = self.layout(tree);
+        let id = dot.unwrap_or(&layout.ids[node]);
+        let (lba, size, flags) = if self.nodes[node].is_dir() {
+            let (lba, sectors) = layout.extent[node];
+            (lba, sectors * SECTOR as u32, 2)
+        } else {
+            (self.file_lba[node], self.nodes[node].size as u32, 0)
+        };
+
+        let mut r = vec![0; 33];
+        both32(&mut r[2..10], lba);
+        both32(&mut r[10..18], size);
+        r[18..25].copy_from_slice(&record_date(self.epoch));
+        r[25] = flags;
+        both16(&mut r[28..32], 1);
+        r[32] = id.len() as u8;
+        r.extend(id);
+        if id.len().is_multiple_of(2) {
+            r.push(0);
+        }
+        r.extend(system_use);
+        if r.len() % 2 == 1 {
+            r.push(0);
+        }
+        r[0] = r.len() as u8;
+        r
+    }
+
+    /// Rock Ridge PX: mode, link count, uid and gid.
+    fn posix_attributes(&self, node: usize) -> Vec<u8> {
+        let (mode, links) = match &self.nodes[node].children {
+            Some(children) => (0o040555, 2 + children.iter().filter(|&&c| self.nodes[c].is_dir()).count() as u32),
+            None => (0o100444, 1),
+        };
+        let mut px = vec![b'P', b'X', 36, 1];
+        px.extend(both32_bytes(mode));
+        px.extend(both32_bytes(links));
+        px.extend(both32_bytes(0));
+        px.extend(both32_bytes(0));
+        px
+    }
+}
+
+impl Layout {
+    fn new(nodes: &[Node], tree: Tree) -> Self {
+        let mut ids = vec![Vec::new(); nodes.len()];
+        let mut sorted = vec![Vec::new(); nodes.len()];
+        for (dir, node) in nodes.iter().enumerate() {
+            let Some(children) = &node.children else { continue };
+            let mut taken = HashSet::new();
+            for &child in children {
+                let child_node = &nodes[child];
+                ids[child] = match tree {
+                    Tree::Primary => primary_id(&child_node.name, child_node.is_dir(), &mut taken),
+                    Tree::Joliet => joliet_id(&child_node.name, child_node.is_dir(), &mut taken),
+                };
+            }
+            let mut order = children.clone();
+            order.sort_by(|&a, &b| ids[a].cmp(&ids[b]));
+            sorted[dir] = order;
+        }
+
+        // Breadth first over sorted children gives the path table order:
+        // by depth, then parent number, then identifier.
+        let mut dirs = Vec::new();
+        let mut queue = VecDeque::from([0]);
+        while let Some(dir) = queue.pop_front() {
+            dirs.push(dir);
+            queue.extend(sorted[dir].iter().filter(|&&c| nodes[c].is_dir()));
+        }
+
+        let path_table_size = dirs
+            .iter()
+            .map(|&d| {
+                let len = if d == 0 { 1 } else { ids[d].len() };
+                8 + len + len % 2
+            })
+            .sum::<usize>() as u32;
+
+        Layout {
+            ids,
+            sorted,
+            dirs,
+            extent: vec![(0, 0); nodes.len()],
+            continuation: vec![(0, 0); nodes.len()],
+            path_table_size,
+            path_tables: (0, 0),
+        }
+    }
+}
+
+/// Uppercase d-characters, at most 30 for name and extension, then `;1`
+/// for files. Collisions within a directory get a `~N` suffix.
+fn primary_id(name: &str, is_dir: bool, taken: &mut HashSet<String>) -> Vec<u8> {
+    fn d_chars(s: &str) -> String {
+        s.chars()
+            .map(|c| c.to_ascii_uppercase())
+            .map(|c| if c.is_ascii_uppercase() || c.is_ascii_digit() || c == '_' { c } else { '_' })
+            .collect()
+    }
+
+    let (stem, ext) = match name.rsplit_once('.') {
+        Some((stem, ext)) if !is_dir && !stem.is_empty() => {
+            let ext: String = d_chars(ext).chars().take(8).collect();
+            (d_chars(stem), format!(".{}", ext))
+        }
+        _ => (d_chars(name), String::new()),
+    };
+    let max = if is_dir { 31 } else { 30 };
+    let mut id = unique(&stem, &ext, max, taken, |s| s.to_string());
+    if !is_dir {
+        id.push_str(";1");
+    }
+    id.into_bytes()
+}
+
+/// UCS-2 big-endian, at most 64 characters including the `;1` of files,
+/// without the characters Joliet reserves. Compared case-insensitively for
+/// collisions, as Windows does.
+fn joliet_id(name: &str, is_dir: bool, taken: &mut HashSet<String>) -> Vec<u8> {
+    let clean: String = name
+        .chars()
+        .map(|c| match c {
+            '*' | '/' | ':' | ';' | '?' | '\\' => '_',
+            c if (c as u32) < 0x20 || (c as u32) > 0xFFFF => '_',
+            c => c,
+        })
+        .collect();
+    let (stem, ext) = match clean.rsplit_once('.') {
+        Some((stem, ext)) if !is_dir && !stem.is_empty() => (stem.to_string(), format!(".{}", ext)),
+        _ => (clean.clone(), String::new()),
+    };
+    let max = if is_dir { 64 } else { 62 };
+    let mut id = unique(&stem, &ext, max, taken, str::to_lowercase);
+    if !is_dir {
+        id.push_str(";1");
+    }
+    id.encode_utf16().flat_map(u16::to_be_bytes).collect()
+}
+
+/// `stem + ext` cut to `max` characters, with a `~N` suffix on the stem
+/// until its `key` is not yet taken.
+pub fn unique(stem: &str, ext: &str, max: usize, taken: &mut HashSet<String>, key: impl Fn(&str) -> String) -> String {
+    let ext: String = ext.chars().take(max / 2).collect();
+    let stem = if stem.is_empty() { "_" } else { stem };
+    let mut suffix = String::new();
+    for n in 1.. {
+        let room = max - ext.chars().count() - suffix.len();
+        let candidate: String = stem.chars().take(room).chain(suffix.chars()).chain(ext.chars()).collect();
+        if taken.insert(key(&candidate)) {
+            return candidate;
+        }
+        suffix = format!("~{}", n);
+    }
+    unreachable!()
+}
+
+const CE_LEN: usize = 28;
+
+/// SUSP CE: where the rest of a record's system use entries continue.
+fn continuation_entry(block: u32, offset: u32, len: u32) -> Vec<u8> {
+    let mut ce = vec![b'C', b'E', CE_LEN as u8, 1];
+    ce.extend(both32_bytes(block));
+    ce.extend(both32_bytes(offset));
+    ce.extend(both32_bytes(len));
+    ce
+}
+
+/// Rock Ridge NM entries for `name`, split so each stays under 255 bytes.
+fn alternate_name(name: &str) -> Vec<Vec<u8>> {
+    let bytes = name.as_bytes();
+    let chunks: Vec<&[u8]> = bytes.chunks(250).collect();
+    chunks
+        .iter()
+        .enumerate()
+        .map(|(i, chunk)| {
+            let more = i + 1 < chunks.len();
+            let mut nm = vec![b'N', b'M', 5 + chunk.len() as u8, 1, more as u8];
+            nm.extend(*chunk);
+            nm
+        })
+        .collect()
+}
+
+/// Rock Ridge TF with modification, access and attribute change times.
+fn timestamps(epoch: i64) -> Vec<u8> {
+    let mut tf = vec![b'T', b'F', 5 + 3 * 7, 1, 0b1110];
+    for _ in 0..3 {
+        tf.extend(record_date(epoch));
+    }
+    tf
+}
+
+/// SUSP ER identifying the Rock Ridge version in use.
+fn extension_reference() -> Vec<u8> {
+    const ID: &[u8] = b"RRIP_1991A";
+    const DESCRIPTOR: &[u8] = b"THE ROCK RIDGE INTERCHANGE PROTOCOL PROVIDES SUPPORT FOR POSIX FILE SYSTEM SEMANTICS";
+    const SOURCE: &[u8] = b"PLEASE CONTACT DISC PUBLISHER FOR SPECIFICATION SOURCE.  SEE PUBLISHER IDENTIFIER IN PRIMARY VOLUME DESCRIPTOR FOR CONTACT INFORMATION.";
+    let mut er = vec![b'E', b'R', (8 + ID.len() + DESCRIPTOR.len() + SOURCE.len()) as u8, 1];
+    er.extend([ID.len() as u8, DESCRIPTOR.len() as u8, SOURCE.len() as u8, 1]);
+    er.extend(ID);
+    er.extend(DESCRIPTOR);
+    er.extend(SOURCE);
+    er
+}
+
+/// The 7-byte binary date of directory records, in UTC.
+fn record_date(epoch: i64) -> [u8; 7] {
+    let t = DateTime::from_timestamp(epoch, 0).unwrap_or_default();
+    [
+        (t.year() - 1900).clamp(0, 255) as u8,
+        t.month() as u8,
+        t.day() as u8,
+        t.hour() as u8,
+        t.minute() as u8,
+        t.second() as u8,
+        0,
+    ]
+}
+
+/// The 17-byte `YYYYMMDDHHMMSScc` + UTC offset date of volume descriptors.
+fn volume_date(epoch: i64) -> [u8; 17] {
+    let t = DateTime::from_timestamp(epoch, 0).unwrap_or_default();
+    let mut date = [0; 17];
+    date[..16].copy_from_slice(t.format("%Y%m%d%H%M%S00").to_string().as_bytes());
+    date
+}
+
+/// Space-padded text, UCS-2 big-endian in the Joliet descriptor.
+fn fill_text(field: &mut [u8], text: &str, ucs2: bool) {
+    let bytes: Vec<u8> = if ucs2 {
+        text.encode_utf16().flat_map(u16::to_be_bytes).chain([0, b' '].into_iter().cycle()).take(field.len()).collect()
+    } else {
+        text.bytes().chain(std::iter::repeat(b' ')).take(field.len()).collect()
+    };
+    field.copy_from_slice(&bytes);
+}
+
+fn sectors_for(bytes: u64) -> u32 {
+    bytes.div_ceil(SECTOR as u64) as u32
+}
+
+fn write_padded(out: &mut impl Write, data: &[u8]) -> io::Result<()> {
+    out.write_all(data)?;
+    let tail = data.len() % SECTOR;
+    if tail > 0 {
+        out.write_all(&vec![0; SECTOR - tail])?;
+    }
+    Ok(())
+}
+
+/// ISO 9660 "both-endian" fields: little-endian then big-endian.
+fn both32_bytes(v: u32) -> [u8; 8] {
+    let mut b = [0; 8];
+    both32(&mut b, v);
+    b
+}
+
+fn both32(field: &mut [u8], v: u32) {
+    field[..4].copy_from_slice(&v.to_le_bytes());
+    field[4..8].copy_from_slice(&v.to_be_bytes());
+}
+
+fn both16(field: &mut [u8], v: u16) {
+    field[..2].copy_from_slice(&v.to_le_bytes());
+    field[2..4].copy_from_slice(&v.to_be_bytes());
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_write_iso() -> Result<()> {
+        let dir = std::env::temp_dir().join(format!("deep_archive_iso_{}", std::process::id()));
+        fs::create_dir_all(dir.join("Holiday Photos"))?;
+        fs::write(dir.join("Holiday Photos/beach.jpg"), vec![7u8; 3000])?;
+        fs::write(dir.join("notes.txt"), b"hello")?;
+        fs::write(dir.join("NOTES.TXT"), b"other")?;
+        let iso = dir.with_extension("iso");
+
+        let members = crate::archive::walk(&dir)?;
+        write_iso(&members, &iso, VOLUME_ID, DEFAULT_EPOCH, &Progress::none())?;
+        let bytes = fs::read(&iso)?;
+        let pvd = &bytes[16 * SECTOR..17 * SECTOR];
+        assert_eq!(&pvd[1..6], b"CD001");
+        assert_eq!(&pvd[40..52], VOLUME_ID.as_bytes());
+        assert_eq!(u32::from_le_bytes(pvd[80..84].try_into()?) as usize * SECTOR, bytes.len());
+        assert_eq!(bytes[17 * SECTOR], 2);
+
+        let find = |needle: &[u8]| bytes.windows(needle.len()).position(|w| w == needle);
+        // Both "notes" files survive as distinct primary names, with Rock Ridge names.
+        assert!(find(b"NOTES.TXT;1").is_some() && find(b"NOTES~1.TXT;1").is_some());
+        assert!(find(b"HOLIDAY_PHOTOS").is_some() && find(b"Holiday Photos").is_some());
+        assert!(find(&[7u8; 3000]).is_some());
+
+        write_iso(&members, &iso, VOLUME_ID, DEFAULT_EPOCH, &Progress::none())?;
+        assert_eq!(fs::read(&iso)?, bytes, "rebuilds are byte-identical");
+
+        fs::remove_dir_all(&dir)?;
+        fs::remove_file(&iso)?;
+        Ok(())
+    }
+}
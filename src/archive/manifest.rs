@@ -0,0 +1,251 @@
+use std::collections::HashMap;
+use std::fs::{self, File};
+use std::io::{BufReader, Read};
+use std::path::{Path, PathBuf};
+use anyhow::{Result, Context};
+use flate2::Crc;
+use rayon::prelude::*;
+use serde::{Deserialize, Serialize};
+use crate::archive::{self, Member};
+use crate::database::repo::{CatalogReader, FilterSet};
+use crate::ingest::hasher;
+
+/// Directory at the root of every volume holding what deep-archive knows
+/// about it; see `is_metadata`.
+pub const METADATA_DIR: &str = "deep-archive";
+
+/// Location of the manifest inside a volume.
+pub const MANIFEST_PATH: &str = "deep-archive/manifest.json";
+
+/// Location of the catalog snapshot inside a volume, when included.
+pub const CATALOG_PATH: &str = "deep-archive/catalog.db";
+
+/// Location of the list of every other file's SHA-256 inside a volume, in
+/// `sha256sum` format.
+pub const CHECKSUMS_PATH: &str = "deep-archive/SHA256SUMS";
+
+/// Location of the same list with CRC-32s in SFV format, when included.
+pub const SFV_PATH: &str = "deep-archive/checksums.sfv";
+
+/// Room a file's rows take in a catalog snapshot (artifact, tags, scores,
+/// an embedding and the search index), for sizing volumes.
+pub const CATALOG_BYTES_PER_FILE: u64 = 4096;
+
+/// Written into every volume so it describes itself: a disc found years
+/// later can be checked and searched without the catalog it came from.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Manifest {
+    /// Bumped on incompatible changes to this layout.
+    pub version: u32,
+    /// Label the volume is registered under, e.g. `BD-0042`.
+    pub label: String,
+    /// File name of the volume, e.g. `archive_001.iso`.
+    pub volume: String,
+    /// 1-based position in the set written together.
+    pub volume_number: usize,
+    pub volume_count: usize,
+    pub files: Vec<ManifestEntry>,
+}
+
+/// One file on the volume, with its catalog record if there is one.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ManifestEntry {
+    /// Location inside the volume, `/`-separated.
+    pub path: String,
+    pub size_bytes: u64,
+    pub hash_sha256: Option<String>,
+    /// Where the content is stored instead of `path`, on content-addressed
+    /// volumes; see `link_objects`.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub object: Option<String>,
+    /// Path of the identical file stored in place of this one, on
+    /// deduplicated volumes; see `link_duplicates`.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub same_as: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub media_type: Option<String>,
+    /// Where the file was ingested from.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub original_path: Option<String>,
+    /// Unix seconds; archives themselves store a fixed timestamp.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub mtime: Option<i64>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub width: Option<u32>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub height: Option<u32>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub nsfw_score: Option<f32>,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub tags: Vec<String>,
+}
+
+impl Manifest {
+    pub const VERSION: u32 = 1;
+
+    /// Describes the file members of one volume, filling in media type,
+    /// tags and score from the catalog for every hash it knows.
+    pub fn build(reader: &CatalogReader, label: &str, volume: &str, volume_number: usize, volume_count: usize, members: &[Member]) -> Result<Self> {
+        let mut files = Vec::new();
+        for member in members.iter().filter(|m| !m.is_dir) {
+            let artifact = match &member.hash {
+                Some(hash) => reader.find(&FilterSet::new().hash(hash)).next().transpose()?,
+                None => None,
+            };
+            let mut entry = ManifestEntry {
+                path: member.archive_path(),
+                size_bytes: member.size,
+                hash_sha256: member.hash.clone(),
+                object: None,
+                same_as: None,
+                media_type: None,
+                original_path: None,
+                mtime: None,
+                width: None,
+                height: None,
+                nsfw_score: None,
+                tags: Vec::new(),
+            };
+            if let Some(artifact) = artifact {
+                entry.media_type = Some(artifact.media_type);
+                entry.original_path = Some(artifact.original_path);
+                entry.mtime = artifact.mtime;
+                entry.width = artifact.width;
+                entry.height = artifact.height;
+                entry.nsfw_score = artifact.nsfw_score;
+                entry.tags = artifact.tags;
+            }
+            files.push(entry);
+        }
+        Ok(Self {
+            version: Self::VERSION,
+            label: label.to_string(),
+            volume: volume.to_string(),
+            volume_number,
+            volume_count,
+            files,
+        })
+    }
+
+    /// Points every entry with a hash at its object, for volumes written
+    /// with `archive::content_addressed`; `path` stays the original one.
+    pub fn link_objects(&mut self) {
+        for entry in &mut self.files {
+            if let Some(hash) = &entry.hash_sha256 {
+                entry.object = Some(archive::archive_path(&archive::object_path(hash)));
+            }
+        }
+    }
+
+    /// Marks the files left out by `archive::deduplicate` with the path of
+    /// the copy stored in their place.
+    pub fn link_duplicates(&mut self, duplicates: &[(Member, PathBuf)]) {
+        let copies: HashMap<String, String> = duplicates
+            .iter()
+            .map(|(member, copy)| (member.archive_path(), archive::archive_path(copy)))
+            .collect();
+        for entry in &mut self.files {
+            entry.same_as = copies.get(&entry.path).cloned();
+        }
+    }
+
+    /// Writes the manifest to `staging` and returns `members` with it, the
+    /// optional catalog snapshot and their directory in front, so tape and
+    /// tar readers reach them first.
+    pub fn attach(&self, members: &[Member], staging: &Path, catalog: Option<&Path>) -> Result<Vec<Member>> {
+        let json = serde_json::to_vec_pretty(self)?;
+        fs::write(staging, &json).with_context(|| format!("Failed to write {:?}", staging))?;
+
+        let mut attached = Vec::with_capacity(members.len() + 3);
+        let parent = staging.parent().unwrap_or(Path::new(".")).to_path_buf();
+        attached.push(Member { path: METADATA_DIR.into(), source: parent, size: 0, is_dir: true, hash: None });
+        if let Some(catalog) = catalog {
+            let size = fs::metadata(catalog).with_context(|| format!("Failed to read {:?}", catalog))?.len();
+            attached.push(Member { path: CATALOG_PATH.into(), source: catalog.to_path_buf(), size, is_dir: false, hash: None });
+        }
+        attached.push(Member { path: MANIFEST_PATH.into(), source: staging.to_path_buf(), size: json.len() as u64, is_dir: false, hash: None });
+        attached.extend(members.iter().cloned());
+        Ok(attached)
+    }
+}
+
+/// Adds `SHA256SUMS` to `members`, written to `sums`, and with `sfv` a
+/// `checksums.sfv` written there, each covering every file in `members`
+/// by its path from the volume root. The media can then be checked from its
+/// root with `sha256sum -c deep-archive/SHA256SUMS`, or any SFV tool, by
+/// people who have never heard of deep-archive. They go right after the
+/// other metadata files.
+pub fn attach_checksums(members: Vec<Member>, sums: &Path, sfv: Option<&Path>) -> Result<Vec<Member>> {
+    let files: Vec<&Member> = members.iter().filter(|m| !m.is_dir).collect();
+    // Files archived have the hashes the catalog knows them by; only the
+    // manifest and catalog snapshot need hashing here.
+    let hashes = files
+        .par_iter()
+        .map(|m| match &m.hash {
+            Some(hash) => Ok(hash.clone()),
+            None => Ok(hasher::fingerprint(&m.source)?.hash),
+        })
+        .collect::<Result<Vec<String>>>()?;
+    let mut list = String::new();
+    for (member, hash) in files.iter().zip(&hashes) {
+        list.push_str(&sha256sum_line(hash, &member.archive_path()));
+    }
+    let mut added = vec![(CHECKSUMS_PATH, sums, list)];
+
+    if let Some(sfv) = sfv {
+        let crcs = files.par_iter().map(|m| crc32(&m.source)).collect::<Result<Vec<u32>>>()?;
+        let mut list = String::from("; CRC-32 of every file on the volume, by path from its root\n");
+        for (member, crc) in files.iter().zip(crcs) {
+            list.push_str(&format!("{} {:08X}\n", member.archive_path(), crc));
+        }
+        added.push((SFV_PATH, sfv, list));
+    }
+
+    let at = members.iter().take_while(|m| is_metadata(&m.path)).count();
+    let mut attached = members;
+    for (i, (path, staging, list)) in added.into_iter().enumerate() {
+        fs::write(staging, &list).with_context(|| format!("Failed to write {:?}", staging))?;
+        let member = Member { path: path.into(), source: staging.to_path_buf(), size: list.len() as u64, is_dir: false, hash: None };
+        attached.insert(at + i, member);
+    }
+    Ok(attached)
+}
+
+/// A line of `sha256sum` output. Names with a backslash or line break are
+/// escaped and the line marked with a leading backslash, as GNU coreutils
+/// does.
+fn sha256sum_line(hash: &str, path: &str) -> String {
+    if !path.contains(['\\', '\n', '\r']) {
+        return format!("{}  {}\n", hash, path);
+    }
+    let escaped = path.replace('\\', "\\\\").replace('\n', "\\n").replace('\r', "\\r");
+    format!("\\{}  {}\n", hash, escaped)
+}
+
+fn crc32(path: &Path) -> Result<u32> {
+    let file = File::open(path).with_context(|| format!("Failed to open {:?}", path))?;
+    let mut reader = BufReader::new(file);
+    let mut crc = Crc::new();
+    let mut buffer = vec![0; 1 << 16];
+    loop {
+        let read = reader.read(&mut buffer).with_context(|| format!("Failed to read {:?}", path))?;
+        if read == 0 {
+            return Ok(crc.sum());
+        }
+        crc.update(&buffer[..read]);
+    }
+}
+
+/// True for paths under `METADATA_DIR`, which volumes reserve for
+/// themselves; such members are dropped from the input, e.g. when
+/// re-archiving an extracted volume.
+pub fn is_metadata(path: &Path) -> bool {
+    path.starts_with(METADATA_DIR)
+}
+
+/// Staging file for the metadata file `name` of the volume at `output`,
+/// next to it so it lands on the same filesystem.
+pub fn staging_path(output: &Path, name: &str) -> PathBuf {
+    let volume = output.file_name().map(|n| n.to_string_lossy().into_owned()).unwrap_or_default();
+    output.with_file_name(format!(".{}.{}", volume, name))
+}
@@ -0,0 +1,361 @@
+use std::collections::{BTreeMap, HashMap};
+use std::fmt::Write as _;
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+use anyhow::{Result, Context};
+use chrono::DateTime;
+use serde_json::Value;
+use tracing::{info, warn};
+use crate::archive::mets::escape;
+use crate::database::repo::{Artifact, CatalogReader, FilterSet};
+
+/// Tag namespaces that place a video in a show instead of the movies:
+/// `series:NAME`, and `season:N` and `episode:N` to number it.
+const SERIES: &str = "series:";
+const SEASON: &str = "season:";
+const EPISODE: &str = "episode:";
+
+/// How the videos get into the library.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum LinkMode {
+    /// Symbolic links to the original paths; the originals must stay mounted.
+    Symlink,
+    /// Hard links; the library must be on the originals' file system.
+    Hardlink,
+    /// Full copies.
+    Copy,
+}
+
+/// What an export wrote.
+#[derive(Debug, Default)]
+pub struct Summary {
+    pub movies: usize,
+    pub episodes: usize,
+    pub shows: usize,
+    /// Videos none of whose paths exist any more.
+    pub missing: usize,
+}
+
+/// A video placed in the library.
+struct Entry {
+    artifact: Artifact,
+    source: PathBuf,
+    title: String,
+    /// `YYYY-MM-DD`, from the container's tags or else the file's mtime.
+    date: Option<String>,
+}
+
+/// Writes the videos matching `filter` into `dir` in the layout Jellyfin,
+/// Emby, Kodi and Plex scan, each next to an `.nfo` with what the catalog
+/// knows about it:
+///
+/// * `Movies/Title (Year)/Title (Year).ext`
+/// * `Shows/Series/Season 01/Series - S01E02 - Title.ext` for videos tagged
+///   `series:Series`, numbered by their `season:` and `episode:` tags, or in
+///   date order within the season where they have none; each show also
+///   gets a `tvshow.nfo`
+///
+/// Titles come from the container's `title` tag, else the file name. Files
+/// already in `dir` are replaced, so exporting again picks up new videos
+/// and edited tags.
+pub fn export(reader: &CatalogReader, filter: &FilterSet, dir: &Path, link: LinkMode) -> Result<Summary> {
+    let mut filter = filter.clone();
+    if filter.media_types.is_empty() {
+        filter.media_types.push("video/*".to_string());
+    }
+    let mut summary = Summary::default();
+    let mut movies = Vec::new();
+    let mut shows: BTreeMap<String, Vec<Entry>> = BTreeMap::new();
+    for artifact in reader.find(&filter) {
+        let artifact = artifact?;
+        if !artifact.media_type.starts_with("video/") {
+            continue;
+        }
+        let mut candidates = vec![PathBuf::from(&artifact.original_path)];
+        candidates.extend(reader.paths(artifact.id)?.into_iter().map(|p| PathBuf::from(p.path)));
+        // Absolute, for symlinks.
+        let Some(source) = candidates.into_iter().find(|p| p.is_file()).map(|p| p.canonicalize().unwrap_or(p)) else {
+            warn!("Skipping {}: no path of it exists any more", artifact.original_path);
+            summary.missing += 1;
+            continue;
+        };
+        let entry = Entry { title: title(&artifact), date: date(&artifact), artifact, source };
+        match tag_value(&entry.artifact, SERIES) {
+            Some(series) => shows.entry(series.to_string()).or_default().push(entry),
+            None => movies.push(entry),
+        }
+    }
+
+    // Titles taken by another video get its hash appended, so neither is
+    // overwritten.
+    let mut taken: HashMap<PathBuf, String> = HashMap::new();
+    for entry in &movies {
+        let mut name = match entry.date.as_deref() {
+            Some(date) => format!("{} ({})", file_name(&entry.title), &date[..4]),
+            None => file_name(&entry.title),
+        };
+        let mut folder = dir.join("Movies").join(&name);
+        if taken.get(&folder).is_some_and(|hash| *hash != entry.artifact.hash_sha256) {
+            name = format!("{} [{}]", name, &entry.artifact.hash_sha256[..8]);
+            folder = dir.join("Movies").join(&name);
+        }
+        taken.insert(folder.clone(), entry.artifact.hash_sha256.clone());
+        place(entry, &folder, &name, &movie_nfo(entry), link)?;
+        summary.movies += 1;
+    }
+
+    for (series, mut entries) in shows {
+        let show = dir.join("Shows").join(file_name(&series));
+        fs::create_dir_all(&show).with_context(|| format!("Failed to create {:?}", show))?;
+        let nfo = show.join("tvshow.nfo");
+        fs::write(&nfo, tvshow_nfo(&series)).with_context(|| format!("Failed to write {:?}", nfo))?;
+        summary.shows += 1;
+
+        entries.sort_by(|a, b| (season(&a.artifact), &a.date, &a.artifact.original_path).cmp(&(season(&b.artifact), &b.date, &b.artifact.original_path)));
+        let mut next: HashMap<u32, u32> = HashMap::new();
+        for entry in &entries {
+            let season = season(&entry.artifact);
+            let counter = next.entry(season).or_insert(1);
+            let episode = tag_value(&entry.artifact, EPISODE).and_then(|e| e.parse().ok()).unwrap_or(*counter);
+            *counter = episode + 1;
+            let mut name = format!("{} - S{:02}E{:02} - {}", file_name(&series), season, episode, file_name(&entry.title));
+            let folder = show.join(format!("Season {:02}", season));
+            if taken.get(&folder.join(&name)).is_some_and(|hash| *hash != entry.artifact.hash_sha256) {
+                name = format!("{} [{}]", name, &entry.artifact.hash_sha256[..8]);
+            }
+            taken.insert(folder.join(&name), entry.artifact.hash_sha256.clone());
+            place(entry, &folder, &name, &episode_nfo(entry, &series, season, episode), link)?;
+            summary.episodes += 1;
+        }
+    }
+    info!("Wrote {} movies and {} episodes of {} shows to {:?}", summary.movies, summary.episodes, summary.shows, dir);
+    Ok(summary)
+}
+
+/// Links the video into `folder` as `name` with its original extension,
+/// and writes `nfo` next to it.
+fn place(entry: &Entry, folder: &Path, name: &str, nfo: &str, link: LinkMode) -> Result<()> {
+    fs::create_dir_all(folder).with_context(|| format!("Failed to create {:?}", folder))?;
+    let mut video = folder.join(name);
+    if let Some(extension) = entry.source.extension() {
+        video.as_mut_os_string().push(".");
+        video.as_mut_os_string().push(extension);
+    }
+    match fs::remove_file(&video) {
+        Err(e) if e.kind() != io::ErrorKind::NotFound => return Err(e).with_context(|| format!("Failed to replace {:?}", video)),
+        _ => {}
+    }
+    let linked = match link {
+        LinkMode::Symlink => symlink(&entry.source, &video),
+        LinkMode::Hardlink => fs::hard_link(&entry.source, &video),
+        LinkMode::Copy => fs::copy(&entry.source, &video).map(|_| ()),
+    };
+    linked.with_context(|| format!("Failed to link {:?} to {:?}", entry.source, video))?;
+    let path = folder.join(format!("{}.nfo", name));
+    fs::write(&path, nfo).with_context(|| format!("Failed to write {:?}", path))
+}
+
+#[cfg(unix)]
+fn symlink(source: &Path, link: &Path) -> io::Result<()> {
+    std::os::unix::fs::symlink(source, link)
+}
+
+#[cfg(windows)]
+fn symlink(source: &Path, link: &Path) -> io::Result<()> {
+    std::os::windows::fs::symlink_file(source, link)
+}
+
+fn movie_nfo(entry: &Entry) -> String {
+    let mut xml = String::from("<?xml version=\"1.0\" encoding=\"UTF-8\" standalone=\"yes\"?>\n<movie>\n");
+    element(&mut xml, "title", &entry.title);
+    if let Some(date) = &entry.date {
+        element(&mut xml, "year", &date[..4]);
+        element(&mut xml, "premiered", date);
+    }
+    details(&mut xml, entry);
+    xml.push_str("</movie>\n");
+    xml
+}
+
+fn episode_nfo(entry: &Entry, series: &str, season: u32, episode: u32) -> String {
+    let mut xml = String::from("<?xml version=\"1.0\" encoding=\"UTF-8\" standalone=\"yes\"?>\n<episodedetails>\n");
+    element(&mut xml, "title", &entry.title);
+    element(&mut xml, "showtitle", series);
+    element(&mut xml, "season", &season.to_string());
+    element(&mut xml, "episode", &episode.to_string());
+    if let Some(date) = &entry.date {
+        element(&mut xml, "aired", date);
+    }
+    details(&mut xml, entry);
+    xml.push_str("</episodedetails>\n");
+    xml
+}
+
+fn tvshow_nfo(series: &str) -> String {
+    let mut xml = String::from("<?xml version=\"1.0\" encoding=\"UTF-8\" standalone=\"yes\"?>\n<tvshow>\n");
+    element(&mut xml, "title", series);
+    xml.push_str("</tvshow>\n");
+    xml
+}
+
+/// The elements movies and episodes share: the content hash as the unique
+/// id, the runtime, tags (`person:` tags as actors and `genre:` tags as
+/// genres) and the streams ffprobe found.
+fn details(xml: &mut String, entry: &Entry) {
+    let artifact = &entry.artifact;
+    let _ = writeln!(xml, "  <uniqueid type=\"sha256\" default=\"true\">{}</uniqueid>", artifact.hash_sha256);
+    let duration = artifact.metadata.as_ref().and_then(|m| m.get("duration")).and_then(Value::as_f64);
+    if let Some(duration) = duration {
+        element(xml, "runtime", &((duration / 60.0).round() as u64).to_string());
+    }
+    for tag in &artifact.tags {
+        if let Some(genre) = tag.strip_prefix("genre:") {
+            element(xml, "genre", genre);
+        } else if let Some(person) = tag.strip_prefix("person:") {
+            let _ = writeln!(xml, "  <actor>\n    <name>{}</name>\n  </actor>", escape(person));
+        } else if ![SERIES, SEASON, EPISODE].iter().any(|ns| tag.starts_with(ns)) {
+            element(xml, "tag", tag);
+        }
+    }
+
+    let streams = artifact.metadata.as_ref().and_then(|m| m.pointer("/ffprobe/streams")).and_then(Value::as_array);
+    let Some(streams) = streams.filter(|s| !s.is_empty()) else {
+        return;
+    };
+    xml.push_str("  <fileinfo>\n    <streamdetails>\n");
+    for stream in streams {
+        let field = |name: &str| stream.get(name).map(|v| v.as_str().map_or_else(|| v.to_string(), str::to_string));
+        let language = stream.pointer("/tags/language").and_then(Value::as_str);
+        let kind = match stream.get("codec_type").and_then(Value::as_str) {
+            Some(kind @ ("video" | "audio")) => kind,
+            Some("subtitle") => "subtitle",
+            _ => continue,
+        };
+        let _ = writeln!(xml, "      <{}>", kind);
+        let mut inner = |name: &str, value: Option<String>| {
+            if let Some(value) = value {
+                let _ = writeln!(xml, "        <{}>{}</{}>", name, escape(&value), name);
+            }
+        };
+        if kind != "subtitle" {
+            inner("codec", field("codec_name"));
+        }
+        match kind {
+            "video" => {
+                inner("width", field("width"));
+                inner("height", field("height"));
+                inner("aspect", field("display_aspect_ratio"));
+                inner("durationinseconds", duration.map(|d| (d.round() as u64).to_string()));
+            }
+            "audio" => inner("channels", field("channels")),
+            _ => {}
+        }
+        inner("language", language.map(str::to_string));
+        let _ = writeln!(xml, "      </{}>", kind);
+    }
+    xml.push_str("    </streamdetails>\n  </fileinfo>\n");
+}
+
+fn element(xml: &mut String, name: &str, value: &str) {
+    let _ = writeln!(xml, "  <{}>{}</{}>", name, escape(value), name);
+}
+
+fn tag_value<'a>(artifact: &'a Artifact, namespace: &str) -> Option<&'a str> {
+    artifact.tags.iter().find_map(|t| t.strip_prefix(namespace)).filter(|v| !v.is_empty())
+}
+
+fn season(artifact: &Artifact) -> u32 {
+    tag_value(artifact, SEASON).and_then(|s| s.parse().ok()).unwrap_or(1)
+}
+
+/// The container's `title` tag, or the file name without its extension.
+fn title(artifact: &Artifact) -> String {
+    let tags = artifact.metadata.as_ref().and_then(|m| m.pointer("/ffprobe/format/tags")).and_then(Value::as_object);
+    let tagged = tags.and_then(|tags| tags.iter().find(|(k, _)| k.eq_ignore_ascii_case("title"))).and_then(|(_, v)| v.as_str());
+    match tagged.map(str::trim).filter(|t| !t.is_empty()) {
+        Some(title) => title.to_string(),
+        None => Path::new(&artifact.original_path).file_stem().map_or_else(|| artifact.hash_sha256.clone(), |s| s.to_string_lossy().to_string()),
+    }
+}
+
+/// When the video was recorded or released, as `YYYY-MM-DD`: the
+/// container's `creation_time` or `date` tag, else the file's mtime.
+fn date(artifact: &Artifact) -> Option<String> {
+    let tags = artifact.metadata.as_ref().and_then(|m| m.pointer("/ffprobe/format/tags")).and_then(Value::as_object);
+    let tagged = tags.and_then(|tags| {
+        ["creation_time", "date"].iter().find_map(|key| tags.iter().find(|(k, _)| k.eq_ignore_ascii_case(key)).and_then(|(_, v)| v.as_str()))
+    });
+    match tagged {
+        Some(date) if date.len() >= 10 && date[..4].bytes().all(|b| b.is_ascii_digit()) && date.is_char_boundary(10) => Some(date[..10].to_string()),
+        Some(year) if year.len() == 4 && year.bytes().all(|b| b.is_ascii_digit()) => Some(format!("{}-01-01", year)),
+        _ => artifact.mtime.and_then(|t| DateTime::from_timestamp(t, 0)).map(|t| t.format("%Y-%m-%d").to_string()),
+    }
+}
+
+/// `name` with the characters file systems or media servers trip on
+/// replaced.
+fn file_name(name: &str) -> String {
+    let cleaned: String = name.chars().map(|c| if c.is_control() || "/\\:*?\"<>|".contains(c) { ' ' } else { c }).collect();
+    let cleaned = cleaned.split_whitespace().collect::<Vec<_>>().join(" ");
+    let cleaned = cleaned.trim_matches('.').trim();
+    if cleaned.is_empty() { "Untitled".to_string() } else { cleaned.to_string() }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    fn artifact(tags: &[&str], metadata: Value) -> Artifact {
+        Artifact {
+            id: 1,
+            hash_sha256: "ab".repeat(32),
+            original_path: "/videos/holiday.mkv".to_string(),
+            media_type: "video/x-matroska".to_string(),
+            size_bytes: Some(1024),
+            mtime: Some(1_600_000_000),
+            width: Some(1920),
+            height: Some(1080),
+            nsfw_score: None,
+            nsfw_model_version: None,
+            tags: tags.iter().map(|t| t.to_string()).collect(),
+            metadata: Some(metadata),
+            deleted_at: None,
+            deleted_reason: None,
+        }
+    }
+
+    #[test]
+    fn test_nfo() {
+        let metadata = json!({
+            "duration": 3725.4,
+            "ffprobe": {
+                "format": {"tags": {"TITLE": "Beach <Day>", "creation_time": "2019-07-14T10:00:00.000000Z"}},
+                "streams": [
+                    {"codec_type": "video", "codec_name": "h264", "width": 1920, "height": 1080},
+                    {"codec_type": "audio", "codec_name": "aac", "channels": 2, "tags": {"language": "eng"}},
+                ],
+            },
+        });
+        let tagged = artifact(&["person:alice", "genre:Travel", "beach", "series:Trips", "season:2"], metadata);
+        let entry = Entry { title: title(&tagged), date: date(&tagged), artifact: tagged, source: PathBuf::from("/videos/holiday.mkv") };
+        assert_eq!(entry.title, "Beach <Day>");
+        assert_eq!(entry.date.as_deref(), Some("2019-07-14"));
+        assert_eq!(season(&entry.artifact), 2);
+
+        let nfo = episode_nfo(&entry, "Trips", 2, 3);
+        assert!(nfo.contains("<title>Beach &lt;Day&gt;</title>"));
+        assert!(nfo.contains("<season>2</season>\n  <episode>3</episode>"));
+        assert!(nfo.contains("<runtime>62</runtime>"));
+        assert!(nfo.contains("<genre>Travel</genre>") && nfo.contains("<name>alice</name>") && nfo.contains("<tag>beach</tag>"));
+        assert!(!nfo.contains("series:"));
+        assert!(nfo.contains("<codec>aac</codec>\n        <channels>2</channels>\n        <language>eng</language>"));
+
+        // Without tags, the file name and mtime stand in.
+        let plain = artifact(&[], json!({}));
+        assert_eq!(title(&plain), "holiday");
+        assert_eq!(date(&plain).as_deref(), Some("2020-09-13"));
+        assert_eq!(file_name(" What? A: Film/Cut. "), "What A Film Cut");
+    }
+}
@@ -0,0 +1,218 @@
+use std::collections::BTreeMap;
+use std::fmt::Write as _;
+use std::fs;
+use std::path::Path;
+use anyhow::{Result, Context};
+use chrono::DateTime;
+use crate::archive::Member;
+use crate::archive::manifest::is_metadata;
+use crate::database::repo::{Artifact, CatalogReader, FilterSet, FixityEvent, Run};
+
+/// Location of the METS document inside a volume, when included.
+pub const METS_PATH: &str = "deep-archive/mets.xml";
+
+/// Room a file takes in the METS document (its PREMIS object, events and
+/// structural map entry), for sizing volumes.
+pub const BYTES_PER_FILE: u64 = 4096;
+
+const AGENT: &str = concat!("deep-archive ", env!("CARGO_PKG_VERSION"));
+
+/// A file on the volume with what the catalog holds about its content.
+struct Entry<'a> {
+    member: &'a Member,
+    artifact: Option<Artifact>,
+    run: Option<Run>,
+    fixity: Vec<FixityEvent>,
+}
+
+/// Adds `mets.xml` to `members`, written to `staging`: a METS document
+/// describing the volume `label` for preservation systems. Each file gets a
+/// PREMIS object (SHA-256, size, format, original name) and PREMIS events
+/// for the ingest run that catalogued it and every fixity check recorded
+/// by `verify`; the structural map follows the volume's directory tree.
+/// It goes right after the other metadata files.
+pub fn attach(reader: &CatalogReader, label: &str, epoch: i64, members: Vec<Member>, staging: &Path) -> Result<Vec<Member>> {
+    let mut entries = Vec::new();
+    for member in members.iter().filter(|m| !m.is_dir && !is_metadata(&m.path)) {
+        let artifact = match &member.hash {
+            Some(hash) => reader.find(&FilterSet::new().hash(hash)).next().transpose()?,
+            None => None,
+        };
+        let (run, fixity) = match &artifact {
+            Some(artifact) => (reader.discovery_run(artifact.id)?, reader.fixity_history(artifact.id)?),
+            None => (None, Vec::new()),
+        };
+        entries.push(Entry { member, artifact, run, fixity });
+    }
+    let xml = document(label, epoch, &entries);
+    fs::write(staging, &xml).with_context(|| format!("Failed to write {:?}", staging))?;
+
+    let at = members.iter().take_while(|m| is_metadata(&m.path)).count();
+    let mut attached = members;
+    attached.insert(at, Member { path: METS_PATH.into(), source: staging.to_path_buf(), size: xml.len() as u64, is_dir: false, hash: None });
+    Ok(attached)
+}
+
+fn document(label: &str, epoch: i64, entries: &[Entry]) -> String {
+    let mut xml = String::new();
+    xml.push_str("<?xml version=\"1.0\" encoding=\"UTF-8\"?>\n");
+    let _ = writeln!(
+        xml,
+        "<mets:mets xmlns:mets=\"http://www.loc.gov/METS/\" xmlns:premis=\"http://www.loc.gov/premis/v3\" \
+         xmlns:xlink=\"http://www.w3.org/1999/xlink\" xmlns:xsi=\"http://www.w3.org/2001/XMLSchema-instance\" \
+         xsi:schemaLocation=\"http://www.loc.gov/METS/ http://www.loc.gov/standards/mets/mets.xsd \
+         http://www.loc.gov/premis/v3 http://www.loc.gov/standards/premis/v3/premis.xsd\" OBJID=\"{}\" LABEL=\"{}\">",
+        escape(label),
+        escape(label)
+    );
+    // The volume's epoch rather than the time of writing, so the document
+    // doesn't change between rebuilds.
+    let _ = writeln!(xml, "  <mets:metsHdr CREATEDATE=\"{}\">", date_time(epoch));
+    let _ = writeln!(xml, "    <mets:agent ROLE=\"CREATOR\" TYPE=\"OTHER\" OTHERTYPE=\"SOFTWARE\"><mets:name>{}</mets:name></mets:agent>", AGENT);
+    xml.push_str("  </mets:metsHdr>\n");
+
+    for (i, entry) in entries.iter().enumerate() {
+        amd_sec(&mut xml, i + 1, entry);
+    }
+
+    xml.push_str("  <mets:fileSec>\n    <mets:fileGrp USE=\"original\">\n");
+    for (i, entry) in entries.iter().enumerate() {
+        let n = i + 1;
+        let mut attributes = format!("ID=\"FILE_{}\" SIZE=\"{}\" ADMID=\"AMD_{}\"", n, entry.member.size, n);
+        if let Some(artifact) = &entry.artifact {
+            let _ = write!(attributes, " MIMETYPE=\"{}\"", escape(&artifact.media_type));
+        }
+        if let Some(hash) = &entry.member.hash {
+            let _ = write!(attributes, " CHECKSUM=\"{}\" CHECKSUMTYPE=\"SHA-256\"", hash);
+        }
+        let _ = writeln!(xml, "      <mets:file {}>", attributes);
+        let _ = writeln!(
+            xml,
+            "        <mets:FLocat LOCTYPE=\"OTHER\" OTHERLOCTYPE=\"SYSTEM\" xlink:type=\"simple\" xlink:href=\"{}\"/>",
+            escape(&href(&entry.member.archive_path()))
+        );
+        xml.push_str("      </mets:file>\n");
+    }
+    xml.push_str("    </mets:fileGrp>\n  </mets:fileSec>\n");
+
+    let mut root = Dir::default();
+    for (i, entry) in entries.iter().enumerate() {
+        let path = entry.member.archive_path();
+        let mut parts: Vec<&str> = path.split('/').collect();
+        parts.pop();
+        let dir = parts.into_iter().fold(&mut root, |dir, part| dir.dirs.entry(part.to_string()).or_default());
+        dir.files.push(i + 1);
+    }
+    xml.push_str("  <mets:structMap TYPE=\"physical\">\n");
+    struct_div(&mut xml, label, &root, 2);
+    xml.push_str("  </mets:structMap>\n</mets:mets>\n");
+    xml
+}
+
+/// A directory of the structural map: subdirectories by name, and the
+/// numbers of its files.
+#[derive(Default)]
+struct Dir {
+    dirs: BTreeMap<String, Dir>,
+    files: Vec<usize>,
+}
+
+fn struct_div(xml: &mut String, label: &str, dir: &Dir, depth: usize) {
+    let indent = "  ".repeat(depth);
+    let _ = writeln!(xml, "{}<mets:div TYPE=\"directory\" LABEL=\"{}\">", indent, escape(label));
+    for (name, sub) in &dir.dirs {
+        struct_div(xml, name, sub, depth + 1);
+    }
+    for n in &dir.files {
+        let _ = writeln!(xml, "{}  <mets:fptr FILEID=\"FILE_{}\"/>", indent, n);
+    }
+    let _ = writeln!(xml, "{}</mets:div>", indent);
+}
+
+fn amd_sec(xml: &mut String, n: usize, entry: &Entry) {
+    let _ = writeln!(xml, "  <mets:amdSec ID=\"AMD_{}\">", n);
+    let _ = writeln!(xml, "    <mets:techMD ID=\"TECH_{}\"><mets:mdWrap MDTYPE=\"PREMIS:OBJECT\"><mets:xmlData>", n);
+    xml.push_str("      <premis:object xsi:type=\"premis:file\">\n");
+    if let Some(hash) = &entry.member.hash {
+        let _ = writeln!(xml, "        <premis:objectIdentifier><premis:objectIdentifierType>SHA-256</premis:objectIdentifierType><premis:objectIdentifierValue>{}</premis:objectIdentifierValue></premis:objectIdentifier>", hash);
+    } else {
+        let _ = writeln!(xml, "        <premis:objectIdentifier><premis:objectIdentifierType>local</premis:objectIdentifierType><premis:objectIdentifierValue>{}</premis:objectIdentifierValue></premis:objectIdentifier>", escape(&entry.member.archive_path()));
+    }
+    xml.push_str("        <premis:objectCharacteristics>\n          <premis:compositionLevel>0</premis:compositionLevel>\n");
+    if let Some(hash) = &entry.member.hash {
+        let _ = writeln!(xml, "          <premis:fixity><premis:messageDigestAlgorithm>SHA-256</premis:messageDigestAlgorithm><premis:messageDigest>{}</premis:messageDigest><premis:messageDigestOriginator>deep-archive</premis:messageDigestOriginator></premis:fixity>", hash);
+    }
+    let _ = writeln!(xml, "          <premis:size>{}</premis:size>", entry.member.size);
+    if let Some(artifact) = &entry.artifact {
+        let _ = writeln!(xml, "          <premis:format><premis:formatDesignation><premis:formatName>{}</premis:formatName></premis:formatDesignation></premis:format>", escape(&artifact.media_type));
+    }
+    xml.push_str("        </premis:objectCharacteristics>\n");
+    if let Some(artifact) = &entry.artifact {
+        let _ = writeln!(xml, "        <premis:originalName>{}</premis:originalName>", escape(&artifact.original_path));
+    }
+    xml.push_str("      </premis:object>\n");
+    let _ = writeln!(xml, "    </mets:xmlData></mets:mdWrap></mets:techMD>");
+
+    let mut events = Vec::new();
+    if let Some(run) = &entry.run {
+        let detail = format!("Catalogued by ingest run {} of {}", run.id, run.input_roots);
+        events.push(("ingestion", run.started_at, "success", detail));
+    }
+    for check in &entry.fixity {
+        let outcome = if check.result == "ok" { "success" } else { "failure" };
+        let mut detail = format!("{} {}: {}", check.algo, check.path, check.result);
+        if let Some(more) = &check.detail {
+            let _ = write!(detail, " ({})", more);
+        }
+        events.push(("fixity check", check.checked_at, outcome, detail));
+    }
+    for (e, (kind, at, outcome, detail)) in events.iter().enumerate() {
+        let _ = writeln!(xml, "    <mets:digiprovMD ID=\"EVENT_{}_{}\"><mets:mdWrap MDTYPE=\"PREMIS:EVENT\"><mets:xmlData>", n, e + 1);
+        xml.push_str("      <premis:event>\n");
+        let _ = writeln!(xml, "        <premis:eventIdentifier><premis:eventIdentifierType>local</premis:eventIdentifierType><premis:eventIdentifierValue>EVENT_{}_{}</premis:eventIdentifierValue></premis:eventIdentifier>", n, e + 1);
+        let _ = writeln!(xml, "        <premis:eventType>{}</premis:eventType>", kind);
+        let _ = writeln!(xml, "        <premis:eventDateTime>{}</premis:eventDateTime>", date_time(*at));
+        let _ = writeln!(xml, "        <premis:eventDetailInformation><premis:eventDetail>{}</premis:eventDetail></premis:eventDetailInformation>", escape(detail));
+        let _ = writeln!(xml, "        <premis:eventOutcomeInformation><premis:eventOutcome>{}</premis:eventOutcome></premis:eventOutcomeInformation>", outcome);
+        let _ = writeln!(xml, "        <premis:linkingAgentIdentifier><premis:linkingAgentIdentifierType>software</premis:linkingAgentIdentifierType><premis:linkingAgentIdentifierValue>{}</premis:linkingAgentIdentifierValue></premis:linkingAgentIdentifier>", AGENT);
+        xml.push_str("      </premis:event>\n");
+        xml.push_str("    </mets:xmlData></mets:mdWrap></mets:digiprovMD>\n");
+    }
+    xml.push_str("  </mets:amdSec>\n");
+}
+
+/// Unix seconds as an `xsd:dateTime` in UTC.
+fn date_time(epoch: i64) -> String {
+    DateTime::from_timestamp(epoch, 0).unwrap_or_default().format("%Y-%m-%dT%H:%M:%SZ").to_string()
+}
+
+/// A relative URI for the volume path `path`, percent-encoding everything
+/// but unreserved characters and separators.
+fn href(path: &str) -> String {
+    let mut uri = String::new();
+    for byte in path.bytes() {
+        match byte {
+            b'A'..=b'Z' | b'a'..=b'z' | b'0'..=b'9' | b'-' | b'.' | b'_' | b'~' | b'/' => uri.push(byte as char),
+            _ => {
+                let _ = write!(uri, "%{:02X}", byte);
+            }
+        }
+    }
+    uri
+}
+
+pub(crate) fn escape(text: &str) -> String {
+    let mut escaped = String::with_capacity(text.len());
+    for c in text.chars() {
+        match c {
+            '&' => escaped.push_str("&amp;"),
+            '<' => escaped.push_str("&lt;"),
+            '>' => escaped.push_str("&gt;"),
+            '"' => escaped.push_str("&quot;"),
+            // Not allowed in XML 1.0 at all.
+            c if (c as u32) < 0x20 && !matches!(c, '\t' | '\n' | '\r') => escaped.push('\u{FFFD}'),
+            c => escaped.push(c),
+        }
+    }
+    escaped
+}
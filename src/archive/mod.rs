@@ -1 +1,408 @@
+pub mod bagit_builder;
+pub mod burner;
+#[cfg(feature = "cloud")]
+pub mod cloud;
+pub mod index;
 pub mod iso_builder;
+pub mod manifest;
+pub mod media_server;
+pub mod mets;
+pub mod progress;
+pub mod reader;
+pub mod restore;
+pub mod select;
+pub mod tape;
+pub mod tar_builder;
+pub mod udf_builder;
+pub mod xmp;
+pub mod zip_builder;
+
+use std::collections::{BTreeMap, HashMap, HashSet};
+use std::env;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::time::UNIX_EPOCH;
+use anyhow::{Result, Context, bail};
+use chrono::DateTime;
+use rayon::prelude::*;
+use tracing::warn;
+use walkdir::WalkDir;
+use crate::ingest::hasher;
+use crate::utils::units::{format_size, parse_size};
+
+/// A directory or regular file to be written into an archive.
+#[derive(Debug, Clone)]
+pub struct Member {
+    /// Location inside the archive, relative to its root.
+    pub path: PathBuf,
+    /// Where the content is read from.
+    pub source: PathBuf,
+    pub size: u64,
+    pub is_dir: bool,
+    /// SHA-256 of the content, once known; see `hash_members`.
+    pub hash: Option<String>,
+}
+
+impl Member {
+    /// `path` with `/` separators, as archive formats store it.
+    pub fn archive_path(&self) -> String {
+        archive_path(&self.path)
+    }
+}
+
+/// `path` with `/` separators.
+pub fn archive_path(path: &Path) -> String {
+    path.components()
+        .map(|c| c.as_os_str().to_string_lossy())
+        .collect::<Vec<_>>()
+        .join("/")
+}
+
+/// Everything under `source_dir` as archive members, parents before their
+/// children and siblings sorted by name, so every backend writes the same
+/// tree in the same order. Symlinks are followed; special files, broken
+/// links and loops are skipped with a warning.
+pub fn walk(source_dir: &Path) -> Result<Vec<Member>> {
+    let mut members = Vec::new();
+    for entry in WalkDir::new(source_dir).follow_links(true).sort_by_file_name().min_depth(1) {
+        let entry = match entry {
+            Ok(entry) => entry,
+            Err(e) if e.depth() == 0 => return Err(e).with_context(|| format!("Failed to read {:?}", source_dir)),
+            Err(e) => {
+                warn!("Skipping {}", e);
+                continue;
+            }
+        };
+        let meta = match entry.metadata() {
+            Ok(meta) => meta,
+            Err(e) => {
+                warn!("Skipping {:?}: {}", entry.path(), e);
+                continue;
+            }
+        };
+        if !meta.is_dir() && !meta.is_file() {
+            warn!("Skipping {:?}: not a regular file", entry.path());
+            continue;
+        }
+        members.push(Member {
+            path: entry.path().strip_prefix(source_dir)?.to_path_buf(),
+            source: entry.path().to_path_buf(),
+            size: if meta.is_dir() { 0 } else { meta.len() },
+            is_dir: meta.is_dir(),
+            hash: None,
+        });
+    }
+    Ok(members)
+}
+
+/// Fills in the hash of every file member that lacks one, in parallel.
+pub fn hash_members(members: &mut [Member]) -> Result<()> {
+    members
+        .par_iter_mut()
+        .filter(|m| !m.is_dir && m.hash.is_none())
+        .try_for_each(|m| -> Result<()> {
+            m.hash = Some(hasher::fingerprint(&m.source)?.hash);
+            Ok(())
+        })
+}
+
+/// Keeps the files for which `keep` is true, and the directories still
+/// leading to one. Directories that were empty to begin with stay.
+pub fn retain_files(members: Vec<Member>, keep: impl Fn(&Member) -> bool) -> Vec<Member> {
+    let mut needed = HashSet::new();
+    let mut emptied = HashSet::new();
+    let mut files = Vec::with_capacity(members.len());
+    for member in &members {
+        if member.is_dir {
+            continue;
+        }
+        let ancestors = member.path.ancestors().skip(1).map(Path::to_path_buf);
+        if keep(member) {
+            needed.extend(ancestors);
+            files.push(true);
+        } else {
+            emptied.extend(ancestors);
+            files.push(false);
+        }
+    }
+    let mut files = files.into_iter();
+    members
+        .into_iter()
+        .filter(|m| match m.is_dir {
+            true => needed.contains(&m.path) || !emptied.contains(&m.path),
+            false => files.next().unwrap_or(false),
+        })
+        .collect()
+}
+
+/// Leaves out every file whose content an earlier member already has.
+/// Returns the members kept and each file left out with the path of the
+/// copy kept in its place.
+pub fn deduplicate(members: Vec<Member>) -> (Vec<Member>, Vec<(Member, PathBuf)>) {
+    let mut first: HashMap<String, PathBuf> = HashMap::new();
+    let mut kept = Vec::with_capacity(members.len());
+    let mut duplicates = Vec::new();
+    for member in members {
+        if let (false, Some(hash)) = (member.is_dir, &member.hash) {
+            if let Some(copy) = first.get(hash) {
+                duplicates.push((member, copy.clone()));
+                continue;
+            }
+            first.insert(hash.clone(), member.path.clone());
+        }
+        kept.push(member);
+    }
+    (kept, duplicates)
+}
+
+/// Where content with hash `hash` is stored in a content-addressed volume:
+/// `objects/ab/cd/abcd...`, so no directory grows past 256 entries.
+pub fn object_path(hash: &str) -> PathBuf {
+    let prefix = |range: std::ops::Range<usize>| hash.get(range).unwrap_or("00");
+    Path::new("objects").join(prefix(0..2)).join(prefix(2..4)).join(hash)
+}
+
+/// The content-addressed form of `members`: every distinct content once at
+/// its `object_path`, each after the directories leading to it. Members
+/// without a hash keep their path; directories are dropped, since a
+/// manifest maps the original paths to objects.
+pub fn content_addressed(members: &[Member]) -> Vec<Member> {
+    let mut arranged = BTreeMap::new();
+    for member in members.iter().filter(|m| !m.is_dir) {
+        let path = match &member.hash {
+            Some(hash) => object_path(hash),
+            None => member.path.clone(),
+        };
+        for dir in path.ancestors().skip(1).filter(|d| !d.as_os_str().is_empty()) {
+            arranged.entry(dir.to_path_buf()).or_insert_with(|| Member {
+                path: dir.to_path_buf(),
+                source: PathBuf::new(),
+                size: 0,
+                is_dir: true,
+                hash: None,
+            });
+        }
+        arranged.entry(path.clone()).or_insert_with(|| Member { path, ..member.clone() });
+    }
+    // Paths order component-wise, so parents come before their children.
+    arranged.into_values().collect()
+}
+
+/// The timestamp every entry of a volume holding `members` gets: `fixed`
+/// if given, else `SOURCE_DATE_EPOCH`, else the latest modification time
+/// among the files (`iso_builder::DEFAULT_EPOCH` when there are none). So
+/// rebuilding an unchanged tree gives the same bytes, and the dates still
+/// say roughly when its content was last touched.
+pub fn epoch(fixed: Option<i64>, members: &[Member]) -> i64 {
+    if let Some(epoch) = fixed {
+        return epoch;
+    }
+    if let Some(epoch) = env::var("SOURCE_DATE_EPOCH").ok().and_then(|v| v.trim().parse().ok()) {
+        return epoch;
+    }
+    members
+        .iter()
+        .filter(|m| !m.is_dir)
+        .filter_map(|m| fs::metadata(&m.source).and_then(|meta| meta.modified()).ok())
+        .filter_map(|t| t.duration_since(UNIX_EPOCH).ok())
+        .map(|d| d.as_secs() as i64)
+        .max()
+        .unwrap_or(iso_builder::DEFAULT_EPOCH)
+}
+
+/// Expands the placeholders of a volume naming template: `{project}`,
+/// `{date}` (the volume's epoch as `YYYYMMDD`) and `{seq}` (its number in
+/// the label sequence, e.g. `0042`).
+pub fn expand(template: &str, project: &str, epoch: i64, seq: u32) -> Result<String> {
+    let mut expanded = String::new();
+    let mut rest = template;
+    while let Some(start) = rest.find('{') {
+        expanded.push_str(&rest[..start]);
+        let end = rest[start..].find('}').with_context(|| format!("Unclosed '{{' in {:?}", template))? + start;
+        match &rest[start + 1..end] {
+            "project" => expanded.push_str(project),
+            "date" => expanded.push_str(&DateTime::from_timestamp(epoch, 0).unwrap_or_default().format("%Y%m%d").to_string()),
+            "seq" => expanded.push_str(&format!("{:04}", seq)),
+            other => bail!("Unknown placeholder {{{}}} in {:?}; use {{project}}, {{date}} or {{seq}}", other, template),
+        }
+        rest = &rest[end + 1..];
+    }
+    expanded.push_str(rest);
+    Ok(expanded)
+}
+
+/// `template` expanded into an ISO 9660 volume ID: uppercased, it must be
+/// 1-32 letters, digits and `_`. UDF takes the same ID.
+pub fn volume_id(template: &str, project: &str, epoch: i64, seq: u32) -> Result<String> {
+    let id = expand(template, project, epoch, seq)?.to_ascii_uppercase();
+    if id.is_empty() || id.len() > 32 || !id.bytes().all(|b| b.is_ascii_alphanumeric() || b == b'_') {
+        bail!("Volume ID {:?} from {:?} isn't valid in ISO 9660; use 1-32 letters, digits and '_'", id, template);
+    }
+    Ok(id)
+}
+
+/// Named media sizes for `--volume-size`, in bytes of user data.
+pub const VOLUME_PRESETS: &[(&str, u64)] = &[
+    ("cd", 737_280_000),
+    ("dvd", 4_700_372_992),
+    ("dvd-dl", 8_547_991_552),
+    ("bd", 25_025_314_816),
+    ("bd-dl", 50_050_629_632),
+    ("bd-xl", 100_103_356_416),
+    ("lto-5", 1_500_000_000_000),
+    ("lto-6", 2_500_000_000_000),
+    ("lto-7", 6_000_000_000_000),
+    ("lto-8", 12_000_000_000_000),
+    ("lto-9", 18_000_000_000_000),
+];
+
+/// A preset name from `VOLUME_PRESETS` or a size such as `25GB`.
+pub fn parse_volume_size(input: &str) -> Result<u64> {
+    let name = input.trim().to_ascii_lowercase();
+    if let Some(&(_, size)) = VOLUME_PRESETS.iter().find(|(preset, _)| *preset == name) {
+        return Ok(size);
+    }
+    parse_size(input)
+}
+
+/// Space reserved per volume for descriptors, path tables and central
+/// directories.
+const VOLUME_OVERHEAD: u64 = 1 << 20;
+
+const SECTOR: u64 = 2048;
+const DIR_SIZE: u64 = 3 * SECTOR;
+
+/// Upper bound of the space a member takes in any archive format: its data
+/// rounded up to a 2 KiB sector plus two sectors for headers, directory
+/// records and its manifest entry (UDF gives every file entry a sector of
+/// its own), three for directories.
+fn estimated_size(member: &Member) -> u64 {
+    if member.is_dir {
+        DIR_SIZE
+    } else {
+        member.size.div_ceil(SECTOR) * SECTOR + 2 * SECTOR
+    }
+}
+
+/// Upper bound of the size of a volume holding `members`, with `per_file`
+/// as for `split`; compressed formats come out smaller.
+pub fn estimated_volume_size(members: &[Member], per_file: u64) -> u64 {
+    let files = members.iter().filter(|m| !m.is_dir).count() as u64;
+    VOLUME_OVERHEAD + members.iter().map(estimated_size).sum::<u64>() + files * per_file
+}
+
+/// Splits `members` into volumes of at most `capacity` bytes by first fit
+/// decreasing: the largest files are placed first, each into the first
+/// volume with room left. Each volume keeps the original member order and
+/// the directories leading to its files, which count towards its size;
+/// empty directories go to the first.
+/// `per_file` is space each file takes beyond `estimated_size`, e.g. its
+/// rows in a catalog snapshot.
+pub fn split(members: Vec<Member>, capacity: u64, per_file: u64) -> Result<Vec<Vec<Member>>> {
+    let room = capacity.saturating_sub(VOLUME_OVERHEAD);
+    let mut files: Vec<usize> = (0..members.len()).filter(|&i| !members[i].is_dir).collect();
+    files.sort_by_key(|&i| std::cmp::Reverse(estimated_size(&members[i])));
+
+    let mut assignment = vec![0; members.len()];
+    let mut used: Vec<u64> = Vec::new();
+    // Directories leading to each volume's files, which take room too.
+    let mut dirs: Vec<HashSet<PathBuf>> = Vec::new();
+    for i in files {
+        let size = estimated_size(&members[i]) + per_file;
+        let ancestors: Vec<&Path> = members[i].path.ancestors().skip(1).filter(|p| !p.as_os_str().is_empty()).collect();
+        if size + ancestors.len() as u64 * DIR_SIZE > room {
+            bail!("{:?} ({}) doesn't fit on a {} volume", members[i].source, format_size(members[i].size), format_size(capacity));
+        }
+        let needs = |dirs: &HashSet<PathBuf>| size + ancestors.iter().filter(|p| !dirs.contains(**p)).count() as u64 * DIR_SIZE;
+        let volume = match (0..used.len()).find(|&v| used[v] + needs(&dirs[v]) <= room) {
+            Some(volume) => volume,
+            None => {
+                used.push(0);
+                dirs.push(HashSet::new());
+                used.len() - 1
+            }
+        };
+        used[volume] += needs(&dirs[volume]);
+        dirs[volume].extend(ancestors.iter().map(|p| p.to_path_buf()));
+        assignment[i] = volume;
+    }
+
+    let mut volumes: Vec<Vec<Member>> = vec![Vec::new(); used.len().max(1)];
+    dirs.resize(volumes.len(), HashSet::new());
+    for (i, member) in members.into_iter().enumerate() {
+        if !member.is_dir {
+            volumes[assignment[i]].push(member);
+            continue;
+        }
+        // Walked before their files, so they precede them in every volume.
+        let needed: Vec<usize> = (0..volumes.len()).filter(|&v| dirs[v].contains(&member.path)).collect();
+        if needed.is_empty() {
+            volumes[0].push(member.clone());
+        }
+        for v in needed {
+            volumes[v].push(member.clone());
+        }
+    }
+    Ok(volumes)
+}
+
+/// `archive.iso` becomes `archive_001.iso` for the first volume; `suffix`
+/// may span several dots, e.g. `.tar.zst`, or be empty.
+pub fn volume_path(output: &Path, suffix: &str, number: usize) -> PathBuf {
+    let name = output.file_name().map(|n| n.to_string_lossy().into_owned()).unwrap_or_default();
+    let stem = name.strip_suffix(suffix).unwrap_or(&name);
+    output.with_file_name(format!("{}_{:03}{}", stem, number, suffix))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn file(path: &str, size: u64) -> Member {
+        Member { path: path.into(), source: path.into(), size, is_dir: false, hash: None }
+    }
+
+    fn dir(path: &str) -> Member {
+        Member { path: path.into(), source: path.into(), size: 0, is_dir: true, hash: None }
+    }
+
+    #[test]
+    fn test_split_and_layout() -> Result<()> {
+        let mb = 1 << 20;
+        let members = vec![dir("a"), file("a/big", 6 * mb), file("a/small", mb), dir("empty"), file("mid", 4 * mb), file("tiny", 10)];
+        let volumes = split(members, 10 * mb, 0)?;
+        let paths: Vec<Vec<String>> = volumes
+            .iter()
+            .map(|v| v.iter().map(|m| m.path.to_string_lossy().into_owned()).collect())
+            .collect();
+        // big is placed first, then mid doesn't fit beside it but small and tiny do.
+        assert_eq!(paths, [vec!["a", "a/big", "a/small", "empty", "tiny"], vec!["mid"]]);
+
+        assert!(split(vec![file("huge", 20 * mb)], 10 * mb, 0).is_err());
+        assert_eq!(parse_volume_size("BD")?, 25_025_314_816);
+        assert_eq!(volume_path(Path::new("out/media.tar.zst"), ".tar.zst", 2), Path::new("out/media_002.tar.zst"));
+
+        let kept = retain_files(vec![dir("a"), file("a/old", 1), dir("b"), file("b/new", 1), dir("empty")], |m| m.path.ends_with("new"));
+        let paths: Vec<String> = kept.iter().map(|m| m.path.to_string_lossy().into_owned()).collect();
+        assert_eq!(paths, ["b", "b/new", "empty"]);
+
+        let hashed = |path: &str, hash: &str| Member { hash: Some(hash.into()), ..file(path, 10) };
+        let (kept, duplicates) = deduplicate(vec![hashed("a", "aa"), hashed("b", "bb"), hashed("c", "aa")]);
+        assert_eq!(kept.len(), 2);
+        assert_eq!((duplicates[0].0.path.as_path(), duplicates[0].1.as_path()), (Path::new("c"), Path::new("a")));
+
+        let objects = content_addressed(&[dir("a"), hashed("a/x", "abcd01"), hashed("a/y", "abcd01"), hashed("z", "ef0123")]);
+        let paths: Vec<String> = objects.iter().map(|m| m.path.to_string_lossy().into_owned()).collect();
+        assert_eq!(paths, ["objects", "objects/ab", "objects/ab/cd", "objects/ab/cd/abcd01", "objects/ef", "objects/ef/01", "objects/ef/01/ef0123"]);
+
+        assert_eq!(epoch(Some(42), &[file("Cargo.toml", 1)]), 42);
+        assert_eq!(volume_id("{project}_{date}_{seq}", "bd", 1704067200, 42)?, "BD_20240101_0042");
+        assert!(volume_id("{project}-{seq}", "bd", 0, 1).is_err());
+        assert!(volume_id("{label}", "bd", 0, 1).is_err());
+        if env::var_os("SOURCE_DATE_EPOCH").is_none() {
+            assert_eq!(epoch(None, &[dir("src"), file("missing", 1)]), iso_builder::DEFAULT_EPOCH);
+            assert!(epoch(None, &[file("Cargo.toml", 1)]) > iso_builder::DEFAULT_EPOCH);
+        }
+        Ok(())
+    }
+}
@@ -0,0 +1,87 @@
+use std::cell::Cell;
+use std::io::{self, Read};
+use std::time::{Duration, Instant};
+use tracing::info;
+use crate::utils::units::{format_duration, format_size};
+
+/// Time between two reports of the same volume.
+const INTERVAL: Duration = Duration::from_secs(5);
+
+/// How much of a volume's content a builder has written, reported through
+/// the log like the ingest pipeline's stages: at most every `INTERVAL`,
+/// with the share done, the throughput and the time left.
+pub struct Progress {
+    label: String,
+    total: u64,
+    done: Cell<u64>,
+    started: Instant,
+    reported: Cell<Instant>,
+}
+
+impl Progress {
+    /// Progress of writing `total` bytes of file content into the volume
+    /// `label`.
+    pub fn new(label: &str, total: u64) -> Self {
+        let now = Instant::now();
+        Progress { label: label.to_string(), total, done: Cell::new(0), started: now, reported: Cell::new(now) }
+    }
+
+    /// Progress that is never reported, for writing where nobody watches.
+    pub fn none() -> Self {
+        Self::new("", 0)
+    }
+
+    /// Counts `bytes` more as written.
+    pub fn add(&self, bytes: u64) {
+        self.update(self.done.get() + bytes);
+    }
+
+    /// Sets how much is written in percent, for backends that report
+    /// progress themselves.
+    pub fn set_percent(&self, percent: f64) {
+        self.update((self.total as f64 * percent.clamp(0.0, 100.0) / 100.0) as u64);
+    }
+
+    fn update(&self, done: u64) {
+        self.done.set(done.min(self.total));
+        if self.total == 0 || self.reported.get().elapsed() < INTERVAL {
+            return;
+        }
+        self.reported.set(Instant::now());
+        let done = self.done.get();
+        let elapsed = self.started.elapsed().as_secs_f64();
+        let rate = done as f64 / elapsed;
+        let left = match rate > 0.0 {
+            true => format!(", {} left", format_duration((self.total - done) as f64 / rate)),
+            false => String::new(),
+        };
+        info!(
+            "{}: {}% written ({} of {}, {}/s{})",
+            self.label,
+            done * 100 / self.total,
+            format_size(done),
+            format_size(self.total),
+            format_size(rate as u64),
+            left
+        );
+    }
+
+    /// `inner`, counting everything read from it as written.
+    pub fn reader<R: Read>(&self, inner: R) -> Counted<'_, R> {
+        Counted { inner, progress: self }
+    }
+}
+
+/// A reader whose bytes count towards a `Progress`.
+pub struct Counted<'a, R> {
+    inner: R,
+    progress: &'a Progress,
+}
+
+impl<R: Read> Read for Counted<'_, R> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let n = self.inner.read(buf)?;
+        self.progress.add(n as u64);
+        Ok(n)
+    }
+}
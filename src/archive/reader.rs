@@ -0,0 +1,635 @@
+use std::collections::{HashMap, HashSet};
+use std::fs::File;
+use std::io::{self, BufReader, Read, Seek, SeekFrom};
+use std::path::Path;
+use anyhow::{Result, Context, bail};
+use flate2::read::DeflateDecoder;
+use sha2::{Digest, Sha256};
+use walkdir::WalkDir;
+use crate::archive::{archive_path, Member};
+use crate::archive::bagit_builder::PAYLOAD_DIR;
+use crate::archive::iso_builder::{FIRST_DESCRIPTOR, SECTOR};
+use crate::archive::tape;
+use crate::archive::udf_builder::{
+    ANCHOR, FILE_CHARACTERISTIC_DELETED, FILE_CHARACTERISTIC_DIRECTORY, FILE_CHARACTERISTIC_PARENT, FILE_ENTRY_HEADER,
+    TAG_ANCHOR, TAG_EXTENDED_FILE_ENTRY, TAG_FILE_ENTRY, TAG_FILE_IDENTIFIER, TAG_FILE_SET, TAG_LOGICAL_VOLUME, TAG_PARTITION,
+    TAG_TERMINATING,
+};
+use crate::archive::zip_builder::{CENTRAL_HEADER, DEFLATED, END_OF_CENTRAL_DIRECTORY, LOCAL_HEADER, STORED, ZIP64_END_OF_CENTRAL_DIRECTORY, ZIP64_LOCATOR};
+use crate::ingest::hasher;
+
+/// Called with each regular file of an archive: its `/`-separated path,
+/// recorded size, the byte offset of its content in the archive where it
+/// is stored there uncompressed and in one piece, and the content.
+pub type Visit<'a> = dyn FnMut(&str, u64, Option<u64>, &mut dyn Read) -> Result<()> + 'a;
+
+/// Reads every file of an archive at a path; one of `iso_files`,
+/// `udf_files`, `tar_zst_files`, `zip_files`, `bag_files`, `tree_files`
+/// and `tape_files`.
+pub type Files = fn(&Path, &mut Visit) -> Result<()>;
+
+/// Reads an archive back through `files` and checks that it holds exactly
+/// the file members of `members`, each complete and with its recorded hash
+/// (members without one are hashed from their source).
+pub fn verify(members: &[Member], files: impl FnOnce(&mut Visit) -> Result<()>) -> Result<Verified> {
+    let mut expected: HashMap<String, &Member> =
+        members.iter().filter(|m| !m.is_dir).map(|m| (m.archive_path(), m)).collect();
+    let mut problems = Vec::new();
+    let mut checked = 0;
+    let mut offsets = HashMap::new();
+    files(&mut |path, size, offset, content| {
+        let (hash, read) = hash_reader(content).with_context(|| format!("Failed to read {} back", path))?;
+        let Some(member) = expected.remove(path) else {
+            problems.push(format!("{}: not one of the files archived", path));
+            return Ok(());
+        };
+        let wanted = match &member.hash {
+            Some(hash) => hash.clone(),
+            None => hasher::fingerprint(&member.source)?.hash,
+        };
+        if size != member.size || read != member.size {
+            problems.push(format!("{}: {} bytes instead of {}", path, read.min(size), member.size));
+        } else if hash != wanted {
+            problems.push(format!("{}: SHA-256 {} instead of {}", path, hash, wanted));
+        } else if let Some(offset) = offset {
+            offsets.insert(path.to_string(), offset);
+        }
+        checked += 1;
+        Ok(())
+    })?;
+
+    let mut missing: Vec<String> = expected.into_keys().collect();
+    missing.sort();
+    problems.extend(missing.into_iter().map(|path| format!("{}: missing", path)));
+    if !problems.is_empty() {
+        const SHOWN: usize = 20;
+        let mut report = problems.iter().take(SHOWN).cloned().collect::<Vec<_>>().join("\n  ");
+        if problems.len() > SHOWN {
+            report.push_str(&format!("\n  ... and {} more", problems.len() - SHOWN));
+        }
+        bail!("The archive doesn't match what was written ({} problems):\n  {}", problems.len(), report);
+    }
+    Ok(Verified { files: checked, offsets })
+}
+
+/// What `verify` found in an archive.
+#[derive(Debug)]
+pub struct Verified {
+    /// Number of files checked.
+    pub files: usize,
+    /// Byte offset of each file's content in the archive, by path, where
+    /// the archive holds it uncompressed and in one piece.
+    pub offsets: HashMap<String, u64>,
+}
+
+fn hash_reader(content: &mut dyn Read) -> io::Result<(String, u64)> {
+    let mut hasher = Sha256::new();
+    let read = io::copy(content, &mut hasher)?;
+    Ok((hex::encode(hasher.finalize()), read))
+}
+
+/// Visits the files of a zstd-compressed tarball in stored order.
+pub fn tar_zst_files(path: &Path, visit: &mut Visit) -> Result<()> {
+    let file = File::open(path).with_context(|| format!("Failed to open {:?}", path))?;
+    tar_files(zstd::Decoder::new(file)?, false, visit)
+}
+
+/// Visits the files of the tar stream on the tape in the drive at `path`
+/// in stored order, from the start of the tape.
+pub fn tape_files(path: &Path, visit: &mut Visit) -> Result<()> {
+    tar_files(tape::open(path)?, true, visit)
+}
+
+/// Visits the files of a tar stream; with `offsets`, giving the position of
+/// each in the stream, which is only meaningful when it isn't compressed.
+fn tar_files(stream: impl Read, offsets: bool, visit: &mut Visit) -> Result<()> {
+    let mut tarball = tar::Archive::new(stream);
+    for entry in tarball.entries()? {
+        let mut entry = entry?;
+        if !entry.header().entry_type().is_file() {
+            continue;
+        }
+        let name = entry.path()?.to_string_lossy().into_owned();
+        let size = entry.header().size()?;
+        let offset = offsets.then(|| entry.raw_file_position());
+        visit(&name, size, offset, &mut entry)?;
+    }
+    Ok(())
+}
+
+/// Visits the payload files of the BagIt bag at `path` in name order, by
+/// their paths under `data/`.
+pub fn bag_files(path: &Path, visit: &mut Visit) -> Result<()> {
+    tree_files(&path.join(PAYLOAD_DIR), visit)
+}
+
+/// Visits the files under the directory `path`, such as a mounted LTFS
+/// tape, in name order.
+pub fn tree_files(path: &Path, visit: &mut Visit) -> Result<()> {
+    for entry in WalkDir::new(path).sort_by_file_name() {
+        let entry = entry.with_context(|| format!("Failed to read {:?}", path))?;
+        if !entry.file_type().is_file() {
+            continue;
+        }
+        let name = archive_path(entry.path().strip_prefix(path)?);
+        let file = File::open(entry.path()).with_context(|| format!("Failed to open {:?}", entry.path()))?;
+        let size = file.metadata()?.len();
+        visit(&name, size, None, &mut BufReader::new(file))?;
+    }
+    Ok(())
+}
+
+/// Visits the files of a ZIP archive (ZIP64 included) in central directory
+/// order. Only stored and deflated entries can be read.
+pub fn zip_files(path: &Path, visit: &mut Visit) -> Result<()> {
+    let mut file = BufReader::new(File::open(path).with_context(|| format!("Failed to open {:?}", path))?);
+    let (entries, directory) = zip_directory(&mut file, path)?;
+    let mut pos = 0;
+    for _ in 0..entries {
+        let header = directory.get(pos..pos + 46).filter(|h| le32(h) == CENTRAL_HEADER);
+        let Some(header) = header else { bail!("{:?} has a broken central directory", path) };
+        let method = le16(&header[10..]);
+        let mut compressed = le32(&header[20..]) as u64;
+        let mut size = le32(&header[24..]) as u64;
+        let (name_len, extra_len, comment_len) =
+            (le16(&header[28..]) as usize, le16(&header[30..]) as usize, le16(&header[32..]) as usize);
+        let mut offset = le32(&header[42..]) as u64;
+        let Some(name) = directory.get(pos + 46..pos + 46 + name_len) else { bail!("{:?} has a broken central directory", path) };
+        let name = String::from_utf8_lossy(name).into_owned();
+        let extra = directory.get(pos + 46 + name_len..pos + 46 + name_len + extra_len).unwrap_or_default();
+        // ZIP64 extended information: the real values of saturated fields, in this order.
+        for (id, mut data) in extra_fields(extra) {
+            if id != 1 {
+                continue;
+            }
+            for field in [&mut size, &mut compressed, &mut offset] {
+                if *field == 0xFFFF_FFFF && data.len() >= 8 {
+                    *field = le64(data);
+                    data = &data[8..];
+                }
+            }
+        }
+        pos += 46 + name_len + extra_len + comment_len;
+        if name.ends_with('/') {
+            continue;
+        }
+
+        let local = read_at(&mut file, offset, 30)?;
+        if le32(&local) != LOCAL_HEADER {
+            bail!("{:?}: no local header for {}", path, name);
+        }
+        let data = offset + 30 + le16(&local[26..]) as u64 + le16(&local[28..]) as u64;
+        let mut source = File::open(path)?;
+        source.seek(SeekFrom::Start(data))?;
+        let mut raw = BufReader::new(source).take(compressed);
+        match method {
+            STORED => visit(&name, size, Some(data), &mut raw)?,
+            DEFLATED => visit(&name, size, None, &mut DeflateDecoder::new(raw))?,
+            other => bail!("{}: unsupported compression method {}", name, other),
+        }
+    }
+    Ok(())
+}
+
+/// The entry count and central directory of the ZIP archive `file`, read
+/// from its end record.
+pub(crate) fn zip_directory(file: &mut BufReader<File>, path: &Path) -> Result<(u64, Vec<u8>)> {
+    let len = file.seek(SeekFrom::End(0))?;
+    // The end record sits within the last 22 bytes plus a comment of up to 64 KiB.
+    let tail_len = len.min(22 + 0xFFFF);
+    let tail = read_at(file, len - tail_len, tail_len as usize)?;
+    let end = (0..tail.len().saturating_sub(21))
+        .rev()
+        .find(|&i| le32(&tail[i..]) == END_OF_CENTRAL_DIRECTORY)
+        .with_context(|| format!("{:?} is not a ZIP archive", path))?;
+    let mut entries = le16(&tail[end + 10..]) as u64;
+    let mut directory_size = le32(&tail[end + 12..]) as u64;
+    let mut directory_offset = le32(&tail[end + 16..]) as u64;
+    if end >= 20 && le32(&tail[end - 20..]) == ZIP64_LOCATOR {
+        let record = read_at(file, le64(&tail[end - 12..]), 56)?;
+        if le32(&record) != ZIP64_END_OF_CENTRAL_DIRECTORY {
+            bail!("{:?} has a broken ZIP64 end record", path);
+        }
+        entries = le64(&record[32..]);
+        directory_size = le64(&record[40..]);
+        directory_offset = le64(&record[48..]);
+    }
+    Ok((entries, read_at(file, directory_offset, directory_size as usize)?))
+}
+
+fn extra_fields(mut extra: &[u8]) -> Vec<(u16, &[u8])> {
+    let mut fields = Vec::new();
+    while extra.len() >= 4 {
+        let (id, len) = (le16(extra), le16(&extra[2..]) as usize);
+        let Some(data) = extra.get(4..4 + len) else { break };
+        fields.push((id, data));
+        extra = &extra[4 + len..];
+    }
+    fields
+}
+
+/// Visits the files of an ISO 9660 image under their Rock Ridge names, or
+/// the primary identifiers (without `;1`) if it has none. Directories
+/// relocated by mkisofs are followed to where Rock Ridge says they belong,
+/// and multi-extent files are read whole.
+pub fn iso_files(path: &Path, visit: &mut Visit) -> Result<()> {
+    let mut image = BufReader::new(File::open(path).with_context(|| format!("Failed to open {:?}", path))?);
+    let descriptor = read_at(&mut image, FIRST_DESCRIPTOR as u64 * SECTOR as u64, SECTOR)?;
+    if descriptor[0] != 1 || &descriptor[1..6] != b"CD001" {
+        bail!("{:?} is not an ISO 9660 image", path);
+    }
+    let root = Record::parse(&descriptor[156..190]).context("Broken root directory record")?;
+
+    // Rock Ridge is announced by an SP entry in the root's "." record.
+    let first = read_at(&mut image, root.lba as u64 * SECTOR as u64, SECTOR)?;
+    let dot = Record::parse(&first).context("Broken root directory")?;
+    let skip = (dot.system_use.starts_with(b"SP") && dot.system_use.get(4..6) == Some(&[0xBE, 0xEF]))
+        .then(|| dot.system_use[6] as usize);
+
+    let mut pending = vec![(root.lba, root.size, String::new())];
+    let mut seen = HashSet::new();
+    while let Some((lba, size, prefix)) = pending.pop() {
+        if !seen.insert(lba) {
+            continue;
+        }
+        let extent = read_at(&mut image, lba as u64 * SECTOR as u64, size as usize)?;
+        let mut extents: Vec<(u32, u32)> = Vec::new();
+        for record in records(&extent) {
+            if record.id == [0] || record.id == [1] {
+                continue;
+            }
+            let susp = match skip {
+                Some(skip) => rock_ridge(&mut image, record.system_use.get(skip..).unwrap_or_default())?,
+                None => RockRidge::default(),
+            };
+            if susp.relocated {
+                continue;
+            }
+            let name = susp.name.unwrap_or_else(|| primary_name(&record.id));
+            let full = if prefix.is_empty() { name } else { format!("{}/{}", prefix, name) };
+
+            if let Some(child) = susp.child {
+                let first = read_at(&mut image, child as u64 * SECTOR as u64, SECTOR)?;
+                let dot = Record::parse(&first).context("Broken relocated directory")?;
+                pending.push((child, dot.size, full));
+            } else if record.flags & FLAG_DIRECTORY != 0 {
+                pending.push((record.lba, record.size, full));
+            } else {
+                extents.push((record.lba, record.size));
+                if record.flags & FLAG_MULTI_EXTENT != 0 {
+                    continue;
+                }
+                let total = extents.iter().map(|&(_, size)| size as u64).sum();
+                let offset = contiguous(extents.iter().map(|&(lba, size)| (lba as u64 * SECTOR as u64, size as u64)));
+                let mut content: Box<dyn Read> = Box::new(io::empty());
+                for (lba, size) in extents.drain(..) {
+                    let mut source = File::open(path)?;
+                    source.seek(SeekFrom::Start(lba as u64 * SECTOR as u64))?;
+                    content = Box::new(content.chain(BufReader::new(source).take(size as u64)));
+                }
+                visit(&full, total, offset, &mut content)?;
+            }
+        }
+    }
+    Ok(())
+}
+
+const FLAG_DIRECTORY: u8 = 2;
+const FLAG_MULTI_EXTENT: u8 = 0x80;
+
+struct Record {
+    lba: u32,
+    size: u32,
+    flags: u8,
+    id: Vec<u8>,
+    system_use: Vec<u8>,
+}
+
+impl Record {
+    fn parse(bytes: &[u8]) -> Option<Self> {
+        let len = *bytes.first()? as usize;
+        let bytes = bytes.get(..len)?;
+        let id_len = *bytes.get(32)? as usize;
+        let id = bytes.get(33..33 + id_len)?.to_vec();
+        // The identifier is padded to an even length.
+        let system_use = bytes.get(33 + id_len + (1 - id_len % 2)..).unwrap_or_default().to_vec();
+        Some(Record { lba: le32(&bytes[2..]), size: le32(&bytes[10..]), flags: bytes[25], id, system_use })
+    }
+}
+
+/// The records of a directory extent; a zero length byte pads to the next sector.
+fn records(extent: &[u8]) -> Vec<Record> {
+    let mut records = Vec::new();
+    let mut pos = 0;
+    while pos < extent.len() {
+        if extent[pos] == 0 {
+            pos = (pos / SECTOR + 1) * SECTOR;
+            continue;
+        }
+        match Record::parse(&extent[pos..]) {
+            Some(record) => records.push(record),
+            None => break,
+        }
+        pos += extent[pos] as usize;
+    }
+    records
+}
+
+fn primary_name(id: &[u8]) -> String {
+    let id = String::from_utf8_lossy(id);
+    let id = id.split(';').next().unwrap_or_default();
+    id.strip_suffix('.').unwrap_or(id).to_string()
+}
+
+#[derive(Default)]
+struct RockRidge {
+    name: Option<String>,
+    /// RE: a relocated directory, listed again where it belongs.
+    relocated: bool,
+    /// CL: where the directory standing in this place was moved.
+    child: Option<u32>,
+}
+
+/// Collects the Rock Ridge entries of a record, following CE continuations.
+fn rock_ridge(image: &mut BufReader<File>, system_use: &[u8]) -> Result<RockRidge> {
+    let mut found = RockRidge::default();
+    let mut name: Option<Vec<u8>> = None;
+    let mut area = system_use.to_vec();
+    // Bounds a malicious or broken chain of continuations.
+    for _ in 0..64 {
+        let mut next = None;
+        let mut pos = 0;
+        while pos + 4 <= area.len() {
+            let len = area[pos + 2] as usize;
+            let Some(entry) = area.get(pos..pos + len).filter(|_| len >= 4) else { break };
+            match &entry[..2] {
+                b"NM" if len >= 5 => name.get_or_insert_with(Vec::new).extend(&entry[5..]),
+                b"CE" if len >= 28 => next = Some((le32(&entry[4..]), le32(&entry[12..]), le32(&entry[20..]))),
+                b"RE" => found.relocated = true,
+                b"CL" if len >= 12 => found.child = Some(le32(&entry[4..])),
+                b"ST" => break,
+                _ => {}
+            }
+            pos += len;
+        }
+        let Some((block, offset, len)) = next else { break };
+        area = read_at(image, block as u64 * SECTOR as u64 + offset as u64, len as usize)?;
+    }
+    found.name = name.map(|n| String::from_utf8_lossy(&n).into_owned());
+    Ok(found)
+}
+
+/// Visits the files of a UDF image with a single type 1 partition, as
+/// written by `write_udf` or mkudffs. File entries may be extended, with
+/// short, long or embedded allocation descriptors; unrecorded extents read
+/// as zeros.
+pub fn udf_files(path: &Path, visit: &mut Visit) -> Result<()> {
+    let mut image = BufReader::new(File::open(path).with_context(|| format!("Failed to open {:?}", path))?);
+    let anchor = read_at(&mut image, ANCHOR as u64 * SECTOR as u64, SECTOR)?;
+    if le16(&anchor) != TAG_ANCHOR {
+        bail!("{:?} is not a UDF image", path);
+    }
+    let (sequence_len, sequence) = (le32(&anchor[16..]), le32(&anchor[20..]));
+    let mut partition = None;
+    let mut file_set = None;
+    for i in 0..(sequence_len as u64 / SECTOR as u64).min(64) {
+        let descriptor = read_at(&mut image, (sequence as u64 + i) * SECTOR as u64, SECTOR)?;
+        match le16(&descriptor) {
+            TAG_PARTITION => partition = Some(le32(&descriptor[188..])),
+            TAG_LOGICAL_VOLUME => {
+                if le32(&descriptor[212..]) != SECTOR as u32 {
+                    bail!("{:?} doesn't use 2 KiB blocks", path);
+                }
+                file_set = Some(le32(&descriptor[252..]));
+            }
+            TAG_TERMINATING => break,
+            _ => {}
+        }
+    }
+    let (Some(partition), Some(file_set)) = (partition, file_set) else {
+        bail!("{:?} has no partition or logical volume descriptor", path);
+    };
+    let block = |lbn: u32| (partition as u64 + lbn as u64) * SECTOR as u64;
+
+    let descriptor = read_at(&mut image, block(file_set), SECTOR)?;
+    if le16(&descriptor) != TAG_FILE_SET {
+        bail!("Broken UDF file set descriptor");
+    }
+    let mut pending = vec![(le32(&descriptor[404..]), String::new())];
+    let mut seen = HashSet::new();
+    while let Some((icb, prefix)) = pending.pop() {
+        if !seen.insert(icb) {
+            continue;
+        }
+        let directory = UdfEntry::read(&mut image, block(icb), block)?;
+        let mut data = Vec::new();
+        directory.content(path)?.read_to_end(&mut data)?;
+
+        let mut pos = 0;
+        while pos + 38 <= data.len() && le16(&data[pos..]) == TAG_FILE_IDENTIFIER {
+            let characteristics = data[pos + 18];
+            let id_len = data[pos + 19] as usize;
+            let icb = le32(&data[pos + 24..]);
+            let id_start = pos + 38 + le16(&data[pos + 36..]) as usize;
+            let Some(id) = data.get(id_start..id_start + id_len) else { break };
+            let name = udf_name(id);
+            pos = (id_start + id_len).next_multiple_of(4);
+            if characteristics & (FILE_CHARACTERISTIC_PARENT | FILE_CHARACTERISTIC_DELETED) != 0 {
+                continue;
+            }
+            let full = if prefix.is_empty() { name } else { format!("{}/{}", prefix, name) };
+            if characteristics & FILE_CHARACTERISTIC_DIRECTORY != 0 {
+                pending.push((icb, full));
+                continue;
+            }
+            let file = UdfEntry::read(&mut image, block(icb), block)?;
+            visit(&full, file.length, file.offset(), &mut file.content(path)?)?;
+        }
+    }
+    Ok(())
+}
+
+struct UdfEntry {
+    length: u64,
+    /// Byte offset and length of each extent; `None` where nothing is
+    /// recorded.
+    extents: Vec<(Option<u64>, u64)>,
+    embedded: Option<Vec<u8>>,
+}
+
+impl UdfEntry {
+    fn read(image: &mut BufReader<File>, offset: u64, block: impl Fn(u32) -> u64) -> Result<Self> {
+        let entry = read_at(image, offset, SECTOR)?;
+        let header = match le16(&entry) {
+            TAG_FILE_ENTRY => FILE_ENTRY_HEADER,
+            TAG_EXTENDED_FILE_ENTRY => FILE_ENTRY_HEADER + 40,
+            _ => bail!("Broken UDF file entry at byte {}", offset),
+        };
+        let length = le64(&entry[56..]);
+        let start = header + le32(&entry[header - 8..]) as usize;
+        let Some(descriptors) = entry.get(start..start + le32(&entry[header - 4..]) as usize) else {
+            bail!("Broken UDF file entry at byte {}", offset);
+        };
+        let size = match le16(&entry[34..]) & 7 {
+            0 => 8,
+            1 => 16,
+            3 => {
+                let embedded = descriptors.get(..length as usize).context("Broken embedded UDF file")?.to_vec();
+                return Ok(Self { length, extents: Vec::new(), embedded: Some(embedded) });
+            }
+            other => bail!("Unsupported UDF allocation descriptor type {}", other),
+        };
+        let mut extents = Vec::new();
+        for ad in descriptors.chunks_exact(size) {
+            let len = (le32(ad) & 0x3FFF_FFFF) as u64;
+            if len == 0 {
+                break;
+            }
+            match le32(ad) >> 30 {
+                0 => extents.push((Some(block(le32(&ad[4..]))), len)),
+                1 | 2 => extents.push((None, len)),
+                _ => bail!("UDF allocation extent continuations are not supported"),
+            }
+        }
+        Ok(Self { length, extents, embedded: None })
+    }
+
+    /// Where the content starts in the image, if it is all recorded in one
+    /// run of blocks.
+    fn offset(&self) -> Option<u64> {
+        if self.embedded.is_some() {
+            return None;
+        }
+        let extents: Option<Vec<(u64, u64)>> = self.extents.iter().map(|&(offset, len)| offset.map(|o| (o, len))).collect();
+        contiguous(extents?.into_iter())
+    }
+
+    fn content(&self, path: &Path) -> Result<Box<dyn Read>> {
+        if let Some(data) = &self.embedded {
+            return Ok(Box::new(io::Cursor::new(data.clone())));
+        }
+        let mut content: Box<dyn Read> = Box::new(io::empty());
+        for &(offset, len) in &self.extents {
+            content = match offset {
+                Some(offset) => {
+                    let mut source = File::open(path)?;
+                    source.seek(SeekFrom::Start(offset))?;
+                    Box::new(content.chain(BufReader::new(source).take(len)))
+                }
+                None => Box::new(content.chain(io::repeat(0).take(len))),
+            };
+        }
+        Ok(Box::new(content.take(self.length)))
+    }
+}
+
+/// Start of `extents`, given as byte offset and length, if each begins
+/// where the one before ends; `None` for none at all.
+fn contiguous(mut extents: impl Iterator<Item = (u64, u64)>) -> Option<u64> {
+    let (start, len) = extents.next()?;
+    let mut end = start + len;
+    for (offset, len) in extents {
+        if offset != end {
+            return None;
+        }
+        end += len;
+    }
+    Some(start)
+}
+
+/// A file identifier in OSTA compressed unicode.
+fn udf_name(id: &[u8]) -> String {
+    match id.split_first() {
+        Some((16, ucs2)) => {
+            let units: Vec<u16> = ucs2.chunks_exact(2).map(|c| u16::from_be_bytes([c[0], c[1]])).collect();
+            String::from_utf16_lossy(&units)
+        }
+        Some((_, latin1)) => latin1.iter().map(|&b| b as char).collect(),
+        None => String::new(),
+    }
+}
+
+pub(crate) fn read_at(file: &mut BufReader<File>, offset: u64, len: usize) -> Result<Vec<u8>> {
+    // Checked first so a broken length can't allocate gigabytes.
+    if offset.saturating_add(len as u64) > file.get_ref().metadata()?.len() {
+        bail!("Archive ends before byte {}", offset.saturating_add(len as u64));
+    }
+    let mut buffer = vec![0; len];
+    file.seek(SeekFrom::Start(offset))?;
+    file.read_exact(&mut buffer)?;
+    Ok(buffer)
+}
+
+pub(crate) fn le16(bytes: &[u8]) -> u16 {
+    u16::from_le_bytes([bytes[0], bytes[1]])
+}
+
+pub(crate) fn le32(bytes: &[u8]) -> u32 {
+    u32::from_le_bytes(bytes[..4].try_into().expect("4 bytes"))
+}
+
+pub(crate) fn le64(bytes: &[u8]) -> u64 {
+    u64::from_le_bytes(bytes[..8].try_into().expect("8 bytes"))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::fs;
+    use crate::archive::bagit_builder::write_bag;
+    use crate::archive::iso_builder::{write_iso, DEFAULT_EPOCH, VOLUME_ID};
+    use crate::archive::progress::Progress;
+    use crate::archive::udf_builder::write_udf;
+    use crate::archive::zip_builder::{write_zip, ZipOptions};
+
+    #[test]
+    fn test_verify() -> Result<()> {
+        let dir = std::env::temp_dir().join(format!("deep_archive_verify_{}", std::process::id()));
+        fs::create_dir_all(dir.join("a/b"))?;
+        fs::write(dir.join("a/b/photo.jpg"), vec![7u8; 5000])?;
+        fs::write(dir.join(format!("{}.txt", "n".repeat(200))), b"long name")?;
+        let mut members = crate::archive::walk(&dir)?;
+        crate::archive::hash_members(&mut members)?;
+
+        let zip = dir.with_extension("zip");
+        write_zip(&members, &zip, DEFAULT_EPOCH, &ZipOptions { level: 6, store_extensions: &[] }, &Progress::none())?;
+        assert_eq!(verify(&members, |visit| zip_files(&zip, visit))?.files, 2);
+
+        let iso = dir.with_extension("iso");
+        write_iso(&members, &iso, VOLUME_ID, DEFAULT_EPOCH, &Progress::none())?;
+        let verified = verify(&members, |visit| iso_files(&iso, visit))?;
+        assert_eq!(verified.files, 2);
+        let offset = verified.offsets["a/b/photo.jpg"] as usize;
+        assert!(fs::read(&iso)?[offset..offset + 5000].iter().all(|&b| b == 7), "offset of the content");
+
+        let udf = dir.with_extension("udf");
+        write_udf(&members, &udf, VOLUME_ID, DEFAULT_EPOCH, &Progress::none())?;
+        let verified = verify(&members, |visit| udf_files(&udf, visit))?;
+        assert_eq!(verified.files, 2);
+        let offset = verified.offsets["a/b/photo.jpg"] as usize;
+        assert!(fs::read(&udf)?[offset..offset + 5000].iter().all(|&b| b == 7), "offset of the content");
+        fs::remove_file(&udf)?;
+
+        let bag = dir.with_extension("bag");
+        write_bag(&members, &bag, DEFAULT_EPOCH, &[], &Progress::none())?;
+        assert_eq!(verify(&members, |visit| bag_files(&bag, visit))?.files, 2);
+        let tags = fs::read_to_string(bag.join("bag-info.txt"))?;
+        assert!(tags.contains("Bagging-Date: 2024-01-01\nPayload-Oxum: 5009.2\n"), "{}", tags);
+        fs::remove_dir_all(&bag)?;
+
+        let mut bytes = fs::read(&iso)?;
+        let data = bytes.windows(64).position(|w| w.iter().all(|&b| b == 7)).expect("photo data");
+        bytes[data + 100] = 8;
+        fs::write(&iso, &bytes)?;
+        let error = verify(&members, |visit| iso_files(&iso, visit)).unwrap_err();
+        assert!(format!("{:#}", error).contains("a/b/photo.jpg: SHA-256"));
+
+        fs::write(&iso, &bytes[..data + 1000])?;
+        assert!(verify(&members, |visit| iso_files(&iso, visit)).is_err(), "truncated image");
+
+        fs::remove_dir_all(&dir)?;
+        fs::remove_file(&iso)?;
+        fs::remove_file(&zip)?;
+        Ok(())
+    }
+}
@@ -0,0 +1,183 @@
+use std::collections::HashSet;
+use std::fs::{self, File};
+use std::io::{self, BufReader, ErrorKind, Read, Write};
+use std::path::{Component, Path, PathBuf};
+use anyhow::{anyhow, Result, Context, bail};
+use sha2::{Digest, Sha256};
+use crate::archive::bagit_builder::PAYLOAD_DIR;
+use crate::archive::manifest::{Manifest, MANIFEST_PATH};
+use crate::archive::reader::{self, Files, Visit};
+use crate::archive::tape;
+
+/// Somewhere an archive volume can be read from.
+pub enum Location {
+    /// An image, tarball or ZIP file as written by `archive`.
+    Image(PathBuf, Files),
+    /// A directory the volume is mounted at or was extracted to.
+    Directory(PathBuf),
+    /// A tape drive holding a volume written with `--format tape`.
+    Tape(PathBuf),
+}
+
+impl Location {
+    /// Opens `path` as a directory, the payload of a BagIt bag, a tape
+    /// drive, or as a volume file picked by its extension.
+    pub fn open(path: &Path) -> Result<Self> {
+        let metadata = fs::metadata(path).with_context(|| format!("Failed to read {:?}", path))?;
+        if tape::is_device(&metadata) {
+            return Ok(Self::Tape(path.to_path_buf()));
+        }
+        if metadata.is_dir() && path.join("bagit.txt").is_file() {
+            return Ok(Self::Directory(path.join(PAYLOAD_DIR)));
+        }
+        if metadata.is_dir() {
+            return Ok(Self::Directory(path.to_path_buf()));
+        }
+        let name = path.file_name().map(|n| n.to_string_lossy().to_lowercase()).unwrap_or_default();
+        let files: Files = if name.ends_with(".iso") {
+            reader::iso_files
+        } else if name.ends_with(".udf") {
+            reader::udf_files
+        } else if name.ends_with(".tar.zst") {
+            reader::tar_zst_files
+        } else if name.ends_with(".zip") {
+            reader::zip_files
+        } else {
+            bail!("Don't know how to read {:?}; expected a directory or an .iso, .udf, .tar.zst or .zip file", path);
+        };
+        Ok(Self::Image(path.to_path_buf(), files))
+    }
+
+    /// File name of the volume, as registered by `archive`; `None` for
+    /// directories and tape drives, whose paths say nothing about the
+    /// volume.
+    pub fn name(&self) -> Option<String> {
+        match self {
+            Self::Image(path, _) => path.file_name().map(|n| n.to_string_lossy().into_owned()),
+            Self::Directory(_) | Self::Tape(_) => None,
+        }
+    }
+
+    /// Label from the manifest of a mounted volume or a tape. Images
+    /// aren't opened for this; they are recognised by `name`.
+    pub fn label(&self) -> Option<String> {
+        match self {
+            Self::Image(..) => None,
+            Self::Directory(dir) => {
+                let json = fs::read(dir.join(MANIFEST_PATH)).ok()?;
+                serde_json::from_slice::<Manifest>(&json).ok().map(|m| m.label)
+            }
+            Self::Tape(device) => tape::label(device).ok(),
+        }
+    }
+
+    /// Calls `visit` with each of `paths` present on the volume; the rest
+    /// are skipped silently.
+    pub fn read(&self, paths: &HashSet<String>, visit: &mut Visit) -> Result<()> {
+        let (path, files): (&Path, Files) = match self {
+            Self::Image(path, files) => (path, *files),
+            Self::Tape(device) => (device, reader::tape_files),
+            Self::Directory(dir) => {
+                for path in paths {
+                    let file = match File::open(dir.join(path)) {
+                        Ok(file) => file,
+                        Err(e) if e.kind() == ErrorKind::NotFound => continue,
+                        Err(e) => return Err(e).with_context(|| format!("Failed to open {:?}", dir.join(path))),
+                    };
+                    let size = file.metadata()?.len();
+                    visit(path, size, None, &mut BufReader::new(file))?;
+                }
+                return Ok(());
+            }
+        };
+        files(path, &mut |path, size, offset, content| match paths.contains(path) {
+            true => visit(path, size, offset, content),
+            false => Ok(()),
+        })
+    }
+}
+
+/// Where a file catalogued at `original_path` is restored to under `dest`:
+/// the same path with any root or drive prefix, and `..`, dropped.
+pub fn target_path(dest: &Path, original_path: &str) -> PathBuf {
+    let mut target = dest.to_path_buf();
+    for component in Path::new(original_path).components() {
+        if let Component::Normal(part) = component {
+            target.push(part);
+        }
+    }
+    target
+}
+
+/// Copies `content` to `target` if it hashes to `hash`. The copy goes to a
+/// temporary file next to `target` first, so a mismatch or a failed read
+/// leaves nothing behind. A `target` that already holds the content is
+/// left alone; one with other content is an error.
+pub fn write_verified(content: &mut dyn Read, target: &Path, hash: &str) -> Result<()> {
+    if target.exists() {
+        let existing = crate::ingest::hasher::fingerprint(target)?;
+        if existing.hash == hash {
+            return Ok(());
+        }
+        bail!("{:?} already exists with other content", target);
+    }
+    if let Some(parent) = target.parent() {
+        fs::create_dir_all(parent).with_context(|| format!("Failed to create {:?}", parent))?;
+    }
+    let name = target.file_name().map(|n| n.to_string_lossy().into_owned()).unwrap_or_default();
+    let partial = target.with_file_name(format!(".{}.partial", name));
+    let written = copy_hashed(content, &partial);
+    let result = match written {
+        Ok(written) if written == hash => fs::rename(&partial, target).with_context(|| format!("Failed to move {:?} into place", target)),
+        Ok(written) => Err(anyhow!("SHA-256 {} instead of {}", written, hash)),
+        Err(e) => Err(e),
+    };
+    if result.is_err() {
+        let _ = fs::remove_file(&partial);
+    }
+    result
+}
+
+fn copy_hashed(content: &mut dyn Read, output: &Path) -> Result<String> {
+    let mut file = File::create(output).with_context(|| format!("Failed to create {:?}", output))?;
+    let mut hasher = Sha256::new();
+    let mut buffer = vec![0; 1 << 16];
+    loop {
+        let read = match content.read(&mut buffer) {
+            Ok(0) => break,
+            Ok(read) => read,
+            Err(e) if e.kind() == io::ErrorKind::Interrupted => continue,
+            Err(e) => return Err(e).context("Failed to read the file to restore"),
+        };
+        hasher.update(&buffer[..read]);
+        file.write_all(&buffer[..read]).with_context(|| format!("Failed to write {:?}", output))?;
+    }
+    file.sync_all()?;
+    Ok(hex::encode(hasher.finalize()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_write_verified() {
+        let dir = std::env::temp_dir().join(format!("deep_archive_restore_{}", std::process::id()));
+        let content = b"restored";
+        let hash = hex::encode(Sha256::digest(content));
+        assert_eq!(target_path(&dir, "/home/me/../a.txt"), dir.join("home/me/a.txt"));
+
+        let target = target_path(&dir, "/home/me/a.txt");
+        assert!(write_verified(&mut &b"damaged"[..], &target, &hash).is_err());
+        assert!(!target.exists());
+        assert_eq!(fs::read_dir(target.parent().unwrap()).unwrap().count(), 0);
+
+        write_verified(&mut &content[..], &target, &hash).unwrap();
+        assert_eq!(fs::read(&target).unwrap(), content);
+        // Re-running is harmless, but nothing gets overwritten.
+        write_verified(&mut &content[..], &target, &hash).unwrap();
+        fs::write(&target, b"edited").unwrap();
+        assert!(write_verified(&mut &content[..], &target, &hash).is_err());
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
@@ -0,0 +1,149 @@
+use std::collections::BTreeMap;
+use std::fs;
+use std::io;
+use std::path::{Component, Path, PathBuf};
+use anyhow::{Result, Context};
+use rayon::prelude::*;
+use tracing::warn;
+use crate::archive::Member;
+use crate::database::repo::{CatalogReader, FilterSet};
+use crate::ingest::hasher;
+
+/// The artifacts matching `filter` as archive members, each read from the
+/// first of its catalogued paths that still holds its content and placed
+/// in the volume at that path without its root or drive prefix, as
+/// `restore` would restore it. The directories leading to them come first,
+/// in the order `walk` gives. Artifacts with no intact copy left are
+/// skipped with a warning; returns how many were.
+pub fn select(reader: &CatalogReader, filter: &FilterSet) -> Result<(Vec<Member>, usize)> {
+    let mut candidates = Vec::new();
+    for artifact in reader.find(filter) {
+        let artifact = artifact?;
+        let mut paths: Vec<String> = reader.paths(artifact.id)?.into_iter().map(|p| p.path).collect();
+        if !paths.contains(&artifact.original_path) {
+            paths.push(artifact.original_path.clone());
+        }
+        candidates.push((artifact, paths));
+    }
+
+    // Every candidate path is hashed, so a file changed since it was
+    // catalogued isn't archived under the old content's hash.
+    let found: Vec<Option<(PathBuf, u64)>> = candidates
+        .par_iter()
+        .map(|(artifact, paths)| {
+            paths.iter().map(PathBuf::from).find_map(|path| {
+                let size = intact(&path, &artifact.hash_sha256, artifact.size_bytes)?;
+                Some((path, size))
+            })
+        })
+        .collect();
+
+    let mut files = BTreeMap::new();
+    let mut missing = 0;
+    for ((artifact, _), found) in candidates.into_iter().zip(found) {
+        let Some((source, size)) = found else {
+            warn!("Leaving out {}: no catalogued path holds its content any more", artifact.original_path);
+            missing += 1;
+            continue;
+        };
+        let path: PathBuf = source.components().filter(|c| matches!(c, Component::Normal(_))).collect();
+        if files.contains_key(&path) {
+            warn!("Leaving out {:?}: another file is archived as {:?}", source, path);
+            continue;
+        }
+        files.insert(path.clone(), Member { path, source, size, is_dir: false, hash: Some(artifact.hash_sha256) });
+    }
+
+    let mut members: BTreeMap<PathBuf, Member> = BTreeMap::new();
+    for file in files.values() {
+        let root: PathBuf = file.source.components().take_while(|c| !matches!(c, Component::Normal(_))).collect();
+        for dir in file.path.ancestors().skip(1).filter(|p| !p.as_os_str().is_empty()) {
+            members.entry(dir.to_path_buf()).or_insert_with(|| Member {
+                path: dir.to_path_buf(),
+                source: root.join(dir),
+                size: 0,
+                is_dir: true,
+                hash: None,
+            });
+        }
+    }
+    members.extend(files);
+    // Paths order component by component, i.e. parents before children and
+    // siblings by name.
+    Ok((members.into_values().collect(), missing))
+}
+
+/// Size of the regular file at `path` if it holds the content `hash`.
+fn intact(path: &Path, hash: &str, size: Option<u64>) -> Option<u64> {
+    let meta = fs::metadata(path).ok().filter(|m| m.is_file())?;
+    if size.is_some_and(|size| size != meta.len()) {
+        return None;
+    }
+    let found = hasher::fingerprint(path).ok()?;
+    (found.hash == hash).then_some(meta.len())
+}
+
+/// Links to the selected files, kept in a directory of their own while
+/// they are archived, so that a file saved over or deleted meanwhile still
+/// has its catalogued content in the volume. A reflink is a copy of its
+/// own, which even changes in place don't reach; a hard link shares the
+/// file, but outlasts renames and deletions. Both need the directory on
+/// the files' file system. The directory is removed when dropped.
+pub struct Staging {
+    dir: PathBuf,
+}
+
+impl Staging {
+    /// Links every file in `members` into `dir`, by reflink where the file
+    /// system supports it and by hard link otherwise, and points the member
+    /// at the link. Files that can't be linked are read in place. Returns
+    /// the staging and the number of files linked.
+    pub fn link(members: &mut [Member], dir: &Path) -> Result<(Self, usize)> {
+        // Left behind by an interrupted run.
+        let _ = fs::remove_dir_all(dir);
+        fs::create_dir_all(dir).with_context(|| format!("Failed to create {:?}", dir))?;
+        let staging = Staging { dir: dir.to_path_buf() };
+        let mut linked = 0;
+        for member in members.iter_mut().filter(|m| !m.is_dir) {
+            let target = dir.join(&member.path);
+            if let Some(parent) = target.parent() {
+                fs::create_dir_all(parent).with_context(|| format!("Failed to create {:?}", parent))?;
+            }
+            if reflink(&member.source, &target).or_else(|_| fs::hard_link(&member.source, &target)).is_ok() {
+                member.source = target;
+                linked += 1;
+            }
+        }
+        Ok((staging, linked))
+    }
+}
+
+impl Drop for Staging {
+    fn drop(&mut self) {
+        let _ = fs::remove_dir_all(&self.dir);
+    }
+}
+
+/// Clones `source` to `target` sharing its blocks (Btrfs, XFS), with its
+/// mtime, which volume epochs are taken from.
+#[cfg(target_os = "linux")]
+fn reflink(source: &Path, target: &Path) -> io::Result<()> {
+    use std::fs::File;
+    use std::os::fd::AsRawFd;
+    let from = File::open(source)?;
+    let to = File::create_new(target)?;
+    // SAFETY: both descriptors are open for the duration of the call.
+    if unsafe { libc::ioctl(to.as_raw_fd(), libc::FICLONE, from.as_raw_fd()) } != 0 {
+        let error = io::Error::last_os_error();
+        drop(to);
+        let _ = fs::remove_file(target);
+        return Err(error);
+    }
+    to.set_modified(from.metadata()?.modified()?)?;
+    Ok(())
+}
+
+#[cfg(not(target_os = "linux"))]
+fn reflink(_source: &Path, _target: &Path) -> io::Result<()> {
+    Err(io::ErrorKind::Unsupported.into())
+}
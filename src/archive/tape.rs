@@ -0,0 +1,255 @@
+use std::fs::{self, File, OpenOptions};
+use std::io::{self, BufReader, BufWriter, Read, Write};
+use std::path::Path;
+use std::process::{Command, Stdio};
+use std::time::{Duration, UNIX_EPOCH};
+use anyhow::{Result, Context, bail};
+use sha2::{Digest, Sha256};
+use walkdir::WalkDir;
+use crate::archive::burner::runnable;
+use crate::archive::manifest::{self, Manifest, CHECKSUMS_PATH, MANIFEST_PATH};
+use crate::archive::progress::Progress;
+use crate::archive::{tar_builder, Member};
+use crate::ingest::hasher::{self, Fingerprint};
+
+/// Size of every block written to and read from a tape drive. Drives in
+/// variable-block mode keep each write as one block and can only read it
+/// back whole, so both sides use the same size; 256 KiB keeps LTO drives
+/// streaming.
+pub const BLOCK_SIZE: usize = 256 * 1024;
+
+/// Extended attributes LTFS shows on the root of a mounted tape, tried in
+/// order: the barcode from the cartridge memory, then the volume serial
+/// given at formatting, which is usually the barcode too.
+const BARCODE_ATTRIBUTES: [&str; 2] = ["user.ltfs.mamBarcode", "user.ltfs.volumeSerial"];
+
+/// Copies `members` onto the LTFS tape mounted at `mount`, which must be
+/// empty, i.e. freshly formatted. Files are written in member order, each
+/// in one pass so the drive keeps streaming, and get `epoch` as mtime.
+/// LTFS writes its index when the tape is unmounted.
+pub fn create_ltfs(members: &[Member], mount: &Path, epoch: i64, progress: &Progress) -> Result<()> {
+    let mut entries = fs::read_dir(mount).with_context(|| format!("Failed to read {:?}; is the LTFS tape mounted there?", mount))?;
+    if entries.next().is_some() {
+        bail!("{:?} isn't empty; volumes are only written to freshly formatted LTFS tapes", mount);
+    }
+    let mtime = UNIX_EPOCH + Duration::from_secs(epoch.max(0) as u64);
+    for member in members {
+        let target = mount.join(&member.path);
+        if member.is_dir {
+            fs::create_dir_all(&target).with_context(|| format!("Failed to create {:?}", target))?;
+            continue;
+        }
+        if let Some(parent) = target.parent() {
+            fs::create_dir_all(parent).with_context(|| format!("Failed to create {:?}", parent))?;
+        }
+        let mut source = File::open(&member.source).with_context(|| format!("Failed to open {:?}", member.source))?;
+        let file = File::create(&target).with_context(|| format!("Failed to create {:?}", target))?;
+        let mut out = BufWriter::with_capacity(BLOCK_SIZE, file);
+        let copied = io::copy(&mut progress.reader(&mut source), &mut out).with_context(|| format!("Failed to write {:?}", target))?;
+        if copied != member.size {
+            bail!("{:?} changed size while the volume was being written", member.source);
+        }
+        let file = out.into_inner().map_err(|e| e.into_error()).with_context(|| format!("Failed to write {:?}", target))?;
+        file.set_modified(mtime)?;
+    }
+    Ok(())
+}
+
+/// Registry fingerprint of the volume on the LTFS tape mounted at `mount`:
+/// the SHA-256 of its `SHA256SUMS`, which pins down every other file, and
+/// the size of all files.
+pub fn fingerprint(mount: &Path) -> Result<Fingerprint> {
+    let sums = hasher::fingerprint(&mount.join(CHECKSUMS_PATH))?;
+    let mut size = 0;
+    for entry in WalkDir::new(mount) {
+        let entry = entry.with_context(|| format!("Failed to read {:?}", mount))?;
+        if entry.file_type().is_file() {
+            size += entry.metadata()?.len();
+        }
+    }
+    Ok(Fingerprint { hash: sums.hash, size, mtime: None, device: None })
+}
+
+/// Barcode of the LTFS tape mounted at `mount`, where LTFS reports one.
+pub fn barcode(mount: &Path) -> Option<String> {
+    if !xattr::SUPPORTED_PLATFORM {
+        return None;
+    }
+    BARCODE_ATTRIBUTES.iter().find_map(|name| {
+        let value = xattr::get(mount, name).ok()??;
+        let value = String::from_utf8_lossy(&value).trim().to_string();
+        (!value.is_empty()).then_some(value)
+    })
+}
+
+/// Writes `members` from the start of the tape in `device` as one
+/// uncompressed GNU tar stream, normalized as for `tar.zst`, in blocks of
+/// `BLOCK_SIZE`; drives compress in hardware. The stream goes through
+/// `mbuffer` where installed, so the drive keeps streaming while small
+/// files are read instead of stopping and repositioning. Returns the
+/// SHA-256 and size of what was written.
+pub fn write_tape(members: &[Member], device: &Path, epoch: i64, progress: &Progress) -> Result<Fingerprint> {
+    rewind(device)?;
+    let written = if runnable("mbuffer") {
+        // Writing starts once the buffer is 90% full, and keeps the drive
+        // fed through stretches of small files.
+        let mut child = Command::new("mbuffer")
+            .args(["-q", "-m", "1G", "-s", "256k", "-P", "90", "-f", "-o"])
+            .arg(device)
+            .stdin(Stdio::piped())
+            .spawn()
+            .context("Failed to execute mbuffer")?;
+        let stdin = child.stdin.take().expect("stdin is piped");
+        let written = write_blocks(members, stdin, epoch, progress);
+        let status = child.wait().context("Failed to wait for mbuffer")?;
+        if !status.success() {
+            bail!("mbuffer exited with {} writing to {:?}", status, device);
+        }
+        written?
+    } else {
+        let file = OpenOptions::new().write(true).open(device).with_context(|| format!("Failed to open {:?}", device))?;
+        write_blocks(members, file, epoch, progress)?
+    };
+    Ok(written)
+}
+
+/// SHA-256 and size of the stream `write_tape` would write for `members`,
+/// without writing it anywhere.
+pub fn stream_fingerprint(members: &[Member], epoch: i64, progress: &Progress) -> Result<Fingerprint> {
+    write_blocks(members, io::sink(), epoch, progress)
+}
+
+fn write_blocks<W: Write>(members: &[Member], out: W, epoch: i64, progress: &Progress) -> Result<Fingerprint> {
+    let blocks = Blocks { inner: out, block: Vec::with_capacity(BLOCK_SIZE), hasher: Sha256::new(), size: 0 };
+    let mut blocks = tar_builder::write_tar(members, blocks, epoch, progress)?;
+    // Zeros after the end of the archive, so the last block is whole too.
+    if !blocks.block.is_empty() {
+        blocks.block.resize(BLOCK_SIZE, 0);
+        blocks.write_block()?;
+    }
+    blocks.inner.flush()?;
+    Ok(Fingerprint { hash: hex::encode(blocks.hasher.finalize()), size: blocks.size, mtime: None, device: None })
+}
+
+/// Passes everything written on in whole blocks of `BLOCK_SIZE`, hashing
+/// it on the way.
+struct Blocks<W> {
+    inner: W,
+    block: Vec<u8>,
+    hasher: Sha256,
+    size: u64,
+}
+
+impl<W: Write> Blocks<W> {
+    fn write_block(&mut self) -> io::Result<()> {
+        self.inner.write_all(&self.block)?;
+        self.hasher.update(&self.block);
+        self.size += self.block.len() as u64;
+        self.block.clear();
+        Ok(())
+    }
+}
+
+impl<W: Write> Write for Blocks<W> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        let n = buf.len().min(BLOCK_SIZE - self.block.len());
+        self.block.extend_from_slice(&buf[..n]);
+        if self.block.len() == BLOCK_SIZE {
+            self.write_block()?;
+        }
+        Ok(n)
+    }
+
+    /// Partial blocks stay buffered; only the end of the stream pads them.
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+/// Opens the tape in `device` for reading from the start, in blocks of
+/// `BLOCK_SIZE`.
+pub fn open(device: &Path) -> Result<impl Read> {
+    rewind(device)?;
+    let file = File::open(device).with_context(|| format!("Failed to open {:?}", device))?;
+    Ok(BufReader::with_capacity(BLOCK_SIZE, file))
+}
+
+/// Label of the volume on the tape in `device`, from the manifest near the
+/// start of the stream; the files after it aren't read.
+pub fn label(device: &Path) -> Result<String> {
+    let mut stream = tar::Archive::new(open(device)?);
+    for entry in stream.entries()? {
+        let mut entry = entry?;
+        let path = entry.path()?.into_owned();
+        if !manifest::is_metadata(&path) {
+            break;
+        }
+        if path == Path::new(MANIFEST_PATH) {
+            let mut json = Vec::new();
+            entry.read_to_end(&mut json)?;
+            return Ok(serde_json::from_slice::<Manifest>(&json)?.label);
+        }
+    }
+    bail!("The tape in {:?} doesn't start with a deep-archive volume", device)
+}
+
+/// Rewinds the tape in `device` with `mt` where installed; otherwise the
+/// device has to be one that rewinds on close, such as `/dev/st0`.
+fn rewind(device: &Path) -> Result<()> {
+    if !runnable("mt") {
+        return Ok(());
+    }
+    let status = Command::new("mt")
+        .arg("-f")
+        .arg(device)
+        .arg("rewind")
+        .stdin(Stdio::null())
+        .status()
+        .context("Failed to execute mt")?;
+    if !status.success() {
+        bail!("mt failed to rewind {:?} ({}); is a tape loaded?", device, status);
+    }
+    Ok(())
+}
+
+/// True for a character device, which is how tape drives appear on Unix.
+pub fn is_device(metadata: &fs::Metadata) -> bool {
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::FileTypeExt;
+        metadata.file_type().is_char_device()
+    }
+    #[cfg(not(unix))]
+    {
+        let _ = metadata;
+        false
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_write_blocks() -> Result<()> {
+        let dir = std::env::temp_dir().join(format!("deep_archive_tape_{}", std::process::id()));
+        fs::create_dir_all(dir.join("album"))?;
+        fs::write(dir.join("album/photo.jpg"), vec![7u8; 300_000])?;
+        let members = crate::archive::walk(&dir)?;
+
+        let mut stream = Vec::new();
+        let written = write_blocks(&members, &mut stream, 0, &Progress::none())?;
+        assert_eq!(stream.len() % BLOCK_SIZE, 0, "only whole blocks are written");
+        assert_eq!(written.size, stream.len() as u64);
+        assert_eq!(written.hash, hex::encode(Sha256::digest(&stream)));
+
+        let mut names = Vec::new();
+        for entry in tar::Archive::new(&stream[..]).entries()? {
+            names.push(entry?.path()?.to_string_lossy().into_owned());
+        }
+        assert_eq!(names, ["album", "album/photo.jpg"]);
+
+        fs::remove_dir_all(&dir)?;
+        Ok(())
+    }
+}
@@ -0,0 +1,114 @@
+use std::fs::{self, File};
+use std::io::{self, BufWriter, Read, Write};
+use std::path::Path;
+use anyhow::{Result, Context};
+use tar::{EntryType, Header};
+use crate::archive::Member;
+use crate::archive::progress::Progress;
+use crate::utils::config::ArchiveConfig;
+
+/// Writes `members` to a zstd-compressed tarball at `output`, with `epoch`
+/// for every mtime, reporting to `progress`.
+pub fn create_tar_zst(members: &[Member], output: &Path, epoch: i64, config: &ArchiveConfig, progress: &Progress) -> Result<()> {
+    if let Some(parent) = output.parent() {
+        fs::create_dir_all(parent)
+            .context("Failed to create parent directory for archive output")?;
+    }
+    write_tar_zst(members, output, epoch, config.zstd_level, progress)
+}
+
+/// Writes `members` as a GNU tar compressed with zstd at `level`. Entries
+/// are stored in member order with mtime `epoch`, owner root and modes 0644
+/// (files) / 0755 (directories), so the same tree always gives the same
+/// bytes.
+pub fn write_tar_zst(members: &[Member], output: &Path, epoch: i64, level: i32, progress: &Progress) -> Result<()> {
+    let file = File::create(output).with_context(|| format!("Failed to create {:?}", output))?;
+    let mut encoder = zstd::Encoder::new(BufWriter::new(file), level)?;
+    encoder.include_checksum(true)?;
+    let mut out = write_tar(members, encoder, epoch, progress)?.finish()?;
+    out.flush()?;
+    Ok(())
+}
+
+/// Writes `members` as an uncompressed GNU tar stream to `out`, normalized
+/// as for `write_tar_zst`, and returns `out` once the end of the archive is
+/// written.
+pub fn write_tar<W: Write>(members: &[Member], out: W, epoch: i64, progress: &Progress) -> Result<W> {
+    let mut builder = tar::Builder::new(out);
+    for member in members {
+        let mut header = Header::new_gnu();
+        header.set_mtime(epoch.max(0) as u64);
+        header.set_uid(0);
+        header.set_gid(0);
+        if member.is_dir {
+            header.set_entry_type(EntryType::Directory);
+            header.set_mode(0o755);
+            header.set_size(0);
+            builder.append_data(&mut header, &member.path, io::empty())?;
+        } else {
+            header.set_entry_type(EntryType::Regular);
+            header.set_mode(0o644);
+            header.set_size(member.size);
+            let source = File::open(&member.source).with_context(|| format!("Failed to open {:?}", member.source))?;
+            let content = Exact { inner: progress.reader(source.take(member.size)), remaining: member.size };
+            builder
+                .append_data(&mut header, &member.path, content)
+                .with_context(|| format!("Failed to add {:?}", member.source))?;
+        }
+    }
+
+    Ok(builder.into_inner()?)
+}
+
+/// Fails instead of ending early, since tar pads a short entry silently and
+/// the header already promised `remaining` bytes.
+struct Exact<R> {
+    inner: R,
+    remaining: u64,
+}
+
+impl<R: Read> Read for Exact<R> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let n = self.inner.read(buf)?;
+        if n == 0 && self.remaining > 0 && !buf.is_empty() {
+            return Err(io::Error::new(io::ErrorKind::UnexpectedEof, "file shrank while the archive was being written"));
+        }
+        self.remaining -= n as u64;
+        Ok(n)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::archive::iso_builder::DEFAULT_EPOCH;
+
+    #[test]
+    fn test_write_tar_zst() -> Result<()> {
+        let dir = std::env::temp_dir().join(format!("deep_archive_tar_{}", std::process::id()));
+        fs::create_dir_all(dir.join("album"))?;
+        fs::write(dir.join("album/photo.jpg"), vec![1u8; 5000])?;
+        fs::write(dir.join(format!("{}.txt", "n".repeat(150))), b"long name")?;
+        let output = dir.with_extension("tar.zst");
+
+        let members = crate::archive::walk(&dir)?;
+        write_tar_zst(&members, &output, DEFAULT_EPOCH, 3, &Progress::none())?;
+        let bytes = fs::read(&output)?;
+
+        let mut tarball = tar::Archive::new(zstd::Decoder::new(&bytes[..])?);
+        let mut names = Vec::new();
+        for entry in tarball.entries()? {
+            let entry = entry?;
+            assert_eq!(entry.header().mtime()?, DEFAULT_EPOCH as u64);
+            names.push(entry.path()?.to_string_lossy().into_owned());
+        }
+        assert_eq!(names, ["album", "album/photo.jpg", format!("{}.txt", "n".repeat(150)).as_str()]);
+
+        write_tar_zst(&members, &output, DEFAULT_EPOCH, 3, &Progress::none())?;
+        assert_eq!(fs::read(&output)?, bytes, "rebuilds are byte-identical");
+
+        fs::remove_dir_all(&dir)?;
+        fs::remove_file(&output)?;
+        Ok(())
+    }
+}
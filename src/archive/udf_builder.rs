@@ -0,0 +1,665 @@
+use std::collections::{HashMap, HashSet, VecDeque};
+use std::fs::{self, File};
+use std::io::{self, BufWriter, Read, Write};
+use std::path::{Path, PathBuf};
+use anyhow::{Result, Context, bail};
+use chrono::{DateTime, Datelike, Timelike};
+use crate::archive::Member;
+use crate::archive::progress::Progress;
+use crate::archive::iso_builder::{self, SECTOR};
+
+/// The volume recognition sequence (BEA01, NSR02, TEA01) starts where ISO
+/// 9660 volume descriptors would.
+const RECOGNITION: u32 = 16;
+const MAIN_SEQUENCE: u32 = 32;
+const RESERVE_SEQUENCE: u32 = 48;
+const INTEGRITY_SEQUENCE: u32 = 64;
+/// Sector of the anchor volume descriptor pointer; a second copy is the
+/// last sector of the image.
+pub const ANCHOR: u32 = 256;
+/// The partition holding the file system starts right after the anchor.
+const PARTITION_START: u32 = ANCHOR + 1;
+
+pub const TAG_ANCHOR: u16 = 2;
+pub const TAG_PARTITION: u16 = 5;
+pub const TAG_LOGICAL_VOLUME: u16 = 6;
+pub const TAG_TERMINATING: u16 = 8;
+pub const TAG_FILE_SET: u16 = 256;
+pub const TAG_FILE_IDENTIFIER: u16 = 257;
+pub const TAG_FILE_ENTRY: u16 = 261;
+pub const TAG_EXTENDED_FILE_ENTRY: u16 = 266;
+
+/// Longest extent a short allocation descriptor describes, in whole sectors.
+pub const MAX_EXTENT: u64 = (1 << 30) - SECTOR as u64;
+/// Size of a file entry before its allocation descriptors.
+pub const FILE_ENTRY_HEADER: usize = 176;
+/// Allocation descriptors fitting in a one-sector file entry; about 250 GB
+/// of file.
+const MAX_EXTENTS: u64 = ((SECTOR - FILE_ENTRY_HEADER) / 8) as u64;
+
+pub const FILE_TYPE_DIRECTORY: u8 = 4;
+pub const FILE_CHARACTERISTIC_DIRECTORY: u8 = 0x02;
+pub const FILE_CHARACTERISTIC_DELETED: u8 = 0x04;
+pub const FILE_CHARACTERISTIC_PARENT: u8 = 0x08;
+
+const UDF_REVISION: u16 = 0x0102;
+const DOMAIN_ID: &str = "*OSTA UDF Compliant";
+const IMPLEMENTATION_ID: &str = "*deep-archive";
+/// Unique ids 1-15 are reserved; the root has 0.
+const FIRST_UNIQUE_ID: u64 = 16;
+
+/// Builds a UDF image of `members` at `output`, for Blu-ray and other
+/// media holding files over 4 GiB, named `volume_id` and with `epoch` for
+/// every timestamp, reporting to `progress`.
+pub fn create_udf(members: &[Member], output: &Path, volume_id: &str, epoch: i64, progress: &Progress) -> Result<()> {
+    if let Some(parent) = output.parent() {
+        fs::create_dir_all(parent)
+            .context("Failed to create parent directory for UDF output")?;
+    }
+    write_udf(members, output, volume_id, epoch, progress)
+}
+
+/// Writes a UDF 1.02 image of `members` without external tools.
+///
+/// UDF 1.02 is read natively by Windows (98 and later), macOS and Linux.
+/// Files can be as large as the medium and names up to 254 characters (127
+/// outside Latin-1) are kept as they are. The layout is the one mkudffs uses
+/// for read-only media: one partition after the anchor at sector 256, with
+/// every file entry first, then directories, then file data in tree order.
+/// As with `write_iso`, every timestamp is `epoch`, files are 0444 and
+/// directories 0555, owned by root.
+pub fn write_udf(members: &[Member], output: &Path, volume_id: &str, epoch: i64, progress: &Progress) -> Result<()> {
+    let image = Image::new(members, volume_id, epoch)?;
+    let file = File::create(output).with_context(|| format!("Failed to create {:?}", output))?;
+    let mut out = BufWriter::new(file);
+    image.write(&mut out, progress).with_context(|| format!("Failed to write {:?}", output))?;
+    out.flush()?;
+    Ok(())
+}
+
+struct Node {
+    /// File identifier in OSTA compressed unicode; empty for the root.
+    id: Vec<u8>,
+    /// Content of files.
+    path: PathBuf,
+    size: u64,
+    parent: usize,
+    /// `Some` for directories, in identifier order once built.
+    children: Option<Vec<usize>>,
+}
+
+impl Node {
+    fn is_dir(&self) -> bool {
+        self.children.is_some()
+    }
+}
+
+struct Image {
+    nodes: Vec<Node>,
+    volume_id: String,
+    epoch: i64,
+    /// Nodes breadth first; the order of file entries and data.
+    order: Vec<usize>,
+    /// Per node: block of its file entry, and of its directory or file data.
+    entry: Vec<u32>,
+    data: Vec<u32>,
+    /// Directory contents, as the file identifier descriptors would take.
+    dir_size: Vec<u64>,
+    partition_blocks: u32,
+}
+
+impl Image {
+    fn new(members: &[Member], volume_id: &str, epoch: i64) -> Result<Self> {
+        let mut nodes = vec![Node { id: Vec::new(), path: PathBuf::new(), size: 0, parent: 0, children: Some(Vec::new()) }];
+        let mut dirs = HashMap::from([(PathBuf::new(), 0)]);
+        let mut names: Vec<String> = vec![String::new()];
+        for member in members {
+            if member.size > MAX_EXTENT * MAX_EXTENTS {
+                bail!("{:?} is too large for a UDF file entry", member.source);
+            }
+            let parent = directory(&mut nodes, &mut names, &mut dirs, member.path.parent().unwrap_or(Path::new("")));
+            if member.is_dir {
+                directory(&mut nodes, &mut names, &mut dirs, &member.path);
+                continue;
+            }
+            let index = nodes.len();
+            nodes.push(Node { id: Vec::new(), path: member.source.clone(), size: member.size, parent, children: None });
+            names.push(file_name(&member.path));
+            nodes[parent].children.as_mut().expect("parent is a directory").push(index);
+        }
+
+        for dir in 0..nodes.len() {
+            let Some(mut children) = nodes[dir].children.take() else { continue };
+            let mut taken = HashSet::new();
+            for &child in &children {
+                nodes[child].id = identifier(&names[child], nodes[child].is_dir(), &mut taken);
+            }
+            children.sort_by(|&a, &b| nodes[a].id.cmp(&nodes[b].id));
+            nodes[dir].children = Some(children);
+        }
+
+        let mut order = Vec::with_capacity(nodes.len());
+        let mut queue = VecDeque::from([0]);
+        while let Some(node) = queue.pop_front() {
+            order.push(node);
+            queue.extend(nodes[node].children.iter().flatten());
+        }
+
+        let dir_size = nodes
+            .iter()
+            .map(|node| match &node.children {
+                Some(children) => fid_len(0) + children.iter().map(|&c| fid_len(nodes[c].id.len())).sum::<u64>(),
+                None => 0,
+            })
+            .collect();
+        let mut image = Image {
+            entry: vec![0; nodes.len()],
+            data: vec![0; nodes.len()],
+            dir_size,
+            order,
+            nodes,
+            volume_id: volume_id.to_string(),
+            epoch,
+            partition_blocks: 0,
+        };
+        image.allocate()?;
+        Ok(image)
+    }
+
+    /// Assigns blocks in the partition: the file set descriptor and its
+    /// terminator, every file entry, directory contents, then file data.
+    fn allocate(&mut self) -> Result<()> {
+        let mut next: u64 = 2;
+        for &node in &self.order {
+            self.entry[node] = next as u32;
+            next += 1;
+        }
+        for &node in &self.order {
+            if self.nodes[node].is_dir() {
+                self.data[node] = next as u32;
+                next += blocks_for(self.dir_size[node]);
+            }
+        }
+        for &node in &self.order {
+            if !self.nodes[node].is_dir() {
+                self.data[node] = next as u32;
+                next += blocks_for(self.nodes[node].size);
+            }
+        }
+        // Room for the trailing anchor.
+        if PARTITION_START as u64 + next + 1 > u32::MAX as u64 {
+            bail!("The files don't fit in a UDF image with 2 KiB sectors");
+        }
+        self.partition_blocks = next as u32;
+        Ok(())
+    }
+
+    fn total_sectors(&self) -> u32 {
+        PARTITION_START + self.partition_blocks + 1
+    }
+
+    fn write(&self, out: &mut impl Write, progress: &Progress) -> Result<()> {
+        let mut sectors = Sectors { out, written: 0 };
+        sectors.skip_to(RECOGNITION)?;
+        for id in [b"BEA01", b"NSR02", b"TEA01"] {
+            let mut d = [0; SECTOR];
+            d[1..6].copy_from_slice(id);
+            d[6] = 1;
+            sectors.write(&d)?;
+        }
+
+        for start in [MAIN_SEQUENCE, RESERVE_SEQUENCE] {
+            sectors.skip_to(start)?;
+            for descriptor in self.volume_descriptors(start) {
+                sectors.write(&descriptor)?;
+            }
+        }
+
+        sectors.skip_to(INTEGRITY_SEQUENCE)?;
+        sectors.write(&self.integrity_descriptor())?;
+        sectors.write(&terminating_descriptor(INTEGRITY_SEQUENCE + 1))?;
+
+        sectors.skip_to(ANCHOR)?;
+        sectors.write(&anchor(ANCHOR))?;
+
+        sectors.write(&self.file_set_descriptor())?;
+        sectors.write(&terminating_descriptor(1))?;
+        for &node in &self.order {
+            sectors.write(&self.file_entry(node))?;
+        }
+        for &node in &self.order {
+            if self.nodes[node].is_dir() {
+                sectors.write(&self.directory(node))?;
+            }
+        }
+        for &node in &self.order {
+            let node = &self.nodes[node];
+            if node.is_dir() {
+                continue;
+            }
+            let source = File::open(&node.path).with_context(|| format!("Failed to open {:?}", node.path))?;
+            let copied = io::copy(&mut progress.reader(source.take(node.size)), sectors.out)?;
+            if copied != node.size {
+                bail!("{:?} shrank while the image was being written", node.path);
+            }
+            sectors.pad(node.size)?;
+        }
+
+        debug_assert_eq!(sectors.written, self.total_sectors() - 1);
+        sectors.write(&anchor(self.total_sectors() - 1))?;
+        Ok(())
+    }
+
+    /// Primary, implementation use, partition, logical volume, unallocated
+    /// space and terminating descriptors, for a sequence starting at `start`.
+    fn volume_descriptors(&self, start: u32) -> Vec<Vec<u8>> {
+        let date = timestamp(self.epoch);
+
+        let mut primary = vec![0; 512];
+        primary[20..24].copy_from_slice(&0u32.to_le_bytes());
+        primary[24..56].copy_from_slice(&dstring(&self.volume_id, 32));
+        primary[56..58].copy_from_slice(&1u16.to_le_bytes());
+        primary[58..60].copy_from_slice(&1u16.to_le_bytes());
+        primary[60..62].copy_from_slice(&2u16.to_le_bytes());
+        primary[62..64].copy_from_slice(&2u16.to_le_bytes());
+        primary[64..68].copy_from_slice(&1u32.to_le_bytes());
+        primary[68..72].copy_from_slice(&1u32.to_le_bytes());
+        // The first 16 characters are meant to tell volume sets apart.
+        primary[72..200].copy_from_slice(&dstring(&format!("{:016X}{}", self.epoch, self.volume_id), 128));
+        primary[200..264].copy_from_slice(&charspec());
+        primary[264..328].copy_from_slice(&charspec());
+        primary[344..376].copy_from_slice(&regid(IMPLEMENTATION_ID, &[]));
+        primary[376..388].copy_from_slice(&date);
+        primary[388..420].copy_from_slice(&regid(IMPLEMENTATION_ID, &[]));
+
+        let mut implementation_use = vec![0; 512];
+        implementation_use[20..52].copy_from_slice(&regid("*UDF LV Info", &udf_suffix()));
+        implementation_use[52..116].copy_from_slice(&charspec());
+        implementation_use[116..244].copy_from_slice(&dstring(&self.volume_id, 128));
+        implementation_use[352..384].copy_from_slice(&regid(IMPLEMENTATION_ID, &[]));
+
+        let mut partition = vec![0; 512];
+        // Allocated, partition number 0.
+        partition[20..22].copy_from_slice(&1u16.to_le_bytes());
+        partition[24..56].copy_from_slice(&regid("+NSR02", &[]));
+        // Read-only: no space bitmaps needed.
+        partition[184..188].copy_from_slice(&1u32.to_le_bytes());
+        partition[188..192].copy_from_slice(&PARTITION_START.to_le_bytes());
+        partition[192..196].copy_from_slice(&self.partition_blocks.to_le_bytes());
+        partition[196..228].copy_from_slice(&regid(IMPLEMENTATION_ID, &[]));
+
+        let mut logical = vec![0; 446];
+        logical[20..84].copy_from_slice(&charspec());
+        logical[84..212].copy_from_slice(&dstring(&self.volume_id, 128));
+        logical[212..216].copy_from_slice(&(SECTOR as u32).to_le_bytes());
+        logical[216..248].copy_from_slice(&regid(DOMAIN_ID, &domain_suffix()));
+        logical[248..264].copy_from_slice(&long_ad(SECTOR as u32, 0));
+        logical[264..268].copy_from_slice(&6u32.to_le_bytes());
+        logical[268..272].copy_from_slice(&1u32.to_le_bytes());
+        logical[272..304].copy_from_slice(&regid(IMPLEMENTATION_ID, &[]));
+        logical[432..436].copy_from_slice(&(2 * SECTOR as u32).to_le_bytes());
+        logical[436..440].copy_from_slice(&INTEGRITY_SEQUENCE.to_le_bytes());
+        // Type 1 partition map: volume 1, partition 0.
+        logical[440..446].copy_from_slice(&[1, 6, 1, 0, 0, 0]);
+
+        let unallocated = vec![0; 24];
+
+        let mut descriptors = Vec::new();
+        for (i, (id, mut d)) in [(1, primary), (4, implementation_use), (5, partition), (6, logical), (7, unallocated)]
+            .into_iter()
+            .enumerate()
+        {
+            d[16..20].copy_from_slice(&(i as u32).to_le_bytes());
+            tag(&mut d, id, start + i as u32);
+            descriptors.push(d);
+        }
+        descriptors.push(terminating_descriptor(start + descriptors.len() as u32));
+        descriptors
+    }
+
+    /// Logical volume integrity descriptor of a closed volume.
+    fn integrity_descriptor(&self) -> Vec<u8> {
+        let files = self.nodes.iter().filter(|n| !n.is_dir()).count() as u32;
+        let dirs = self.nodes.len() as u32 - files;
+        let mut d = vec![0; 134];
+        d[16..28].copy_from_slice(&timestamp(self.epoch));
+        d[28..32].copy_from_slice(&1u32.to_le_bytes());
+        d[40..48].copy_from_slice(&(FIRST_UNIQUE_ID + self.nodes.len() as u64).to_le_bytes());
+        d[72..76].copy_from_slice(&1u32.to_le_bytes());
+        d[76..80].copy_from_slice(&46u32.to_le_bytes());
+        // No free space; the size of partition 0.
+        d[84..88].copy_from_slice(&self.partition_blocks.to_le_bytes());
+        d[88..120].copy_from_slice(&regid(IMPLEMENTATION_ID, &[]));
+        d[120..124].copy_from_slice(&files.to_le_bytes());
+        d[124..128].copy_from_slice(&dirs.to_le_bytes());
+        for (i, revision) in [UDF_REVISION; 3].into_iter().enumerate() {
+            d[128 + 2 * i..130 + 2 * i].copy_from_slice(&revision.to_le_bytes());
+        }
+        tag(&mut d, 9, INTEGRITY_SEQUENCE);
+        d
+    }
+
+    fn file_set_descriptor(&self) -> Vec<u8> {
+        let mut d = vec![0; 512];
+        d[16..28].copy_from_slice(&timestamp(self.epoch));
+        d[28..30].copy_from_slice(&3u16.to_le_bytes());
+        d[30..32].copy_from_slice(&3u16.to_le_bytes());
+        d[32..36].copy_from_slice(&1u32.to_le_bytes());
+        d[36..40].copy_from_slice(&1u32.to_le_bytes());
+        d[48..112].copy_from_slice(&charspec());
+        d[112..240].copy_from_slice(&dstring(&self.volume_id, 128));
+        d[240..304].copy_from_slice(&charspec());
+        d[304..336].copy_from_slice(&dstring(&self.volume_id, 32));
+        d[400..416].copy_from_slice(&long_ad(SECTOR as u32, self.entry[0]));
+        d[416..448].copy_from_slice(&regid(DOMAIN_ID, &domain_suffix()));
+        tag(&mut d, TAG_FILE_SET, 0);
+        d
+    }
+
+    fn file_entry(&self, node: usize) -> Vec<u8> {
+        let n = &self.nodes[node];
+        let (length, file_type, permissions, links) = match &n.children {
+            Some(children) => {
+                let subdirs = children.iter().filter(|&&c| self.nodes[c].is_dir()).count() as u16;
+                (self.dir_size[node], FILE_TYPE_DIRECTORY, 0o555, 1 + subdirs)
+            }
+            None => (n.size, 5, 0o444, 1),
+        };
+        let mut extents = Vec::new();
+        let mut remaining = length;
+        let mut block = self.data[node];
+        while remaining > 0 {
+            let len = remaining.min(MAX_EXTENT);
+            extents.extend((len as u32).to_le_bytes());
+            extents.extend(block.to_le_bytes());
+            block += blocks_for(len) as u32;
+            remaining -= len;
+        }
+
+        let mut d = vec![0; FILE_ENTRY_HEADER];
+        // ICB tag: strategy 4, one entry, short allocation descriptors.
+        d[20..22].copy_from_slice(&4u16.to_le_bytes());
+        d[24..26].copy_from_slice(&1u16.to_le_bytes());
+        d[27] = file_type;
+        d[44..48].copy_from_slice(&udf_permissions(permissions).to_le_bytes());
+        d[48..50].copy_from_slice(&links.to_le_bytes());
+        d[56..64].copy_from_slice(&length.to_le_bytes());
+        d[64..72].copy_from_slice(&blocks_for(length).to_le_bytes());
+        let date = timestamp(self.epoch);
+        for field in [72, 84, 96] {
+            d[field..field + 12].copy_from_slice(&date);
+        }
+        d[108..112].copy_from_slice(&1u32.to_le_bytes());
+        d[128..160].copy_from_slice(&regid(IMPLEMENTATION_ID, &[]));
+        let unique_id = if node == 0 { 0 } else { FIRST_UNIQUE_ID + node as u64 };
+        d[160..168].copy_from_slice(&unique_id.to_le_bytes());
+        d[172..176].copy_from_slice(&(extents.len() as u32).to_le_bytes());
+        d.extend(extents);
+        tag(&mut d, TAG_FILE_ENTRY, self.entry[node]);
+        d
+    }
+
+    /// File identifier descriptors of `dir`: its parent, then its children.
+    /// They run on across block boundaries, as UDF allows.
+    fn directory(&self, dir: usize) -> Vec<u8> {
+        let children = self.nodes[dir].children.as_deref().unwrap_or_default();
+        let mut data = Vec::with_capacity(self.dir_size[dir] as usize);
+        let parent = self.nodes[dir].parent;
+        let entries = std::iter::once((parent, true)).chain(children.iter().map(|&c| (c, false)));
+        for (node, is_parent) in entries {
+            let id: &[u8] = if is_parent { &[] } else { &self.nodes[node].id };
+            let mut characteristics = 0;
+            if self.nodes[node].is_dir() {
+                characteristics |= FILE_CHARACTERISTIC_DIRECTORY;
+            }
+            if is_parent {
+                characteristics |= FILE_CHARACTERISTIC_PARENT;
+            }
+            let mut d = vec![0; 38];
+            d[16..18].copy_from_slice(&1u16.to_le_bytes());
+            d[18] = characteristics;
+            d[19] = id.len() as u8;
+            d[20..36].copy_from_slice(&long_ad(SECTOR as u32, self.entry[node]));
+            d.extend(id);
+            d.resize(fid_len(id.len()) as usize, 0);
+            let block = self.data[dir] + (data.len() / SECTOR) as u32;
+            tag(&mut d, TAG_FILE_IDENTIFIER, block);
+            data.extend(d);
+        }
+        data
+    }
+}
+
+/// The node of the directory at `path`, created along with any missing
+/// ancestors.
+fn directory(nodes: &mut Vec<Node>, names: &mut Vec<String>, dirs: &mut HashMap<PathBuf, usize>, path: &Path) -> usize {
+    if let Some(&index) = dirs.get(path) {
+        return index;
+    }
+    let parent = directory(nodes, names, dirs, path.parent().unwrap_or(Path::new("")));
+    let index = nodes.len();
+    nodes.push(Node { id: Vec::new(), path: PathBuf::new(), size: 0, parent, children: Some(Vec::new()) });
+    names.push(file_name(path));
+    nodes[parent].children.as_mut().expect("parent is a directory").push(index);
+    dirs.insert(path.to_path_buf(), index);
+    index
+}
+
+fn file_name(path: &Path) -> String {
+    path.file_name().map(|n| n.to_string_lossy().into_owned()).unwrap_or_default()
+}
+
+/// `name` in OSTA compressed unicode, at most 255 bytes: 8 bits per
+/// character if it is all Latin-1, else UCS-2. Characters beyond UCS-2 are
+/// replaced, and names cut short get a `~N` suffix where they would clash.
+fn identifier(name: &str, is_dir: bool, taken: &mut HashSet<String>) -> Vec<u8> {
+    let clean: String = name.chars().map(|c| if c == '\0' || (c as u32) > 0xFFFF { '_' } else { c }).collect();
+    let max = if clean.chars().all(|c| (c as u32) < 0x100) { 254 } else { 127 };
+    let (stem, ext) = match clean.rsplit_once('.') {
+        Some((stem, ext)) if !is_dir && !stem.is_empty() => (stem.to_string(), format!(".{}", ext)),
+        _ => (clean.clone(), String::new()),
+    };
+    let name = iso_builder::unique(&stem, &ext, max, taken, |s| s.to_string());
+    compressed_unicode(&name)
+}
+
+fn compressed_unicode(text: &str) -> Vec<u8> {
+    if text.chars().all(|c| (c as u32) < 0x100) {
+        std::iter::once(8).chain(text.chars().map(|c| c as u8)).collect()
+    } else {
+        std::iter::once(16).chain(text.encode_utf16().flat_map(u16::to_be_bytes)).collect()
+    }
+}
+
+/// A fixed-size d-string: compressed unicode, zero padded, with the used
+/// length in the last byte.
+fn dstring(text: &str, size: usize) -> Vec<u8> {
+    let mut field = vec![0; size];
+    if text.is_empty() {
+        return field;
+    }
+    let bytes = compressed_unicode(text);
+    let mut len = bytes.len().min(size - 1);
+    if bytes[0] == 16 && len.is_multiple_of(2) {
+        // Don't split a UCS-2 character.
+        len -= 1;
+    }
+    field[..len].copy_from_slice(&bytes[..len]);
+    field[size - 1] = len as u8;
+    field
+}
+
+/// Size of a file identifier descriptor with an identifier of `len` bytes,
+/// padded to four bytes.
+fn fid_len(len: usize) -> u64 {
+    (38 + len).next_multiple_of(4) as u64
+}
+
+/// Fills in the descriptor tag of `d`, whose CRC covers everything after it.
+fn tag(d: &mut [u8], id: u16, location: u32) {
+    d[0..2].copy_from_slice(&id.to_le_bytes());
+    d[2..4].copy_from_slice(&2u16.to_le_bytes());
+    d[6..8].copy_from_slice(&1u16.to_le_bytes());
+    let crc = crc_itu(&d[16..]);
+    let crc_len = (d.len() - 16) as u16;
+    d[8..10].copy_from_slice(&crc.to_le_bytes());
+    d[10..12].copy_from_slice(&crc_len.to_le_bytes());
+    d[12..16].copy_from_slice(&location.to_le_bytes());
+    d[4] = d[..16].iter().enumerate().filter(|&(i, _)| i != 4).fold(0u8, |sum, (_, &b)| sum.wrapping_add(b));
+}
+
+/// CRC-ITU-T (polynomial 0x1021, initial value 0), as ECMA-167 uses for
+/// descriptor tags.
+pub fn crc_itu(data: &[u8]) -> u16 {
+    let mut crc = 0u16;
+    for &byte in data {
+        crc ^= (byte as u16) << 8;
+        for _ in 0..8 {
+            crc = if crc & 0x8000 != 0 { (crc << 1) ^ 0x1021 } else { crc << 1 };
+        }
+    }
+    crc
+}
+
+fn anchor(location: u32) -> Vec<u8> {
+    let mut d = vec![0; 512];
+    let length = 6 * SECTOR as u32;
+    d[16..20].copy_from_slice(&length.to_le_bytes());
+    d[20..24].copy_from_slice(&MAIN_SEQUENCE.to_le_bytes());
+    d[24..28].copy_from_slice(&length.to_le_bytes());
+    d[28..32].copy_from_slice(&RESERVE_SEQUENCE.to_le_bytes());
+    tag(&mut d, TAG_ANCHOR, location);
+    d
+}
+
+fn terminating_descriptor(location: u32) -> Vec<u8> {
+    let mut d = vec![0; 512];
+    tag(&mut d, TAG_TERMINATING, location);
+    d
+}
+
+/// A long allocation descriptor: `length` bytes at `block` of partition 0.
+fn long_ad(length: u32, block: u32) -> [u8; 16] {
+    let mut ad = [0; 16];
+    ad[0..4].copy_from_slice(&length.to_le_bytes());
+    ad[4..8].copy_from_slice(&block.to_le_bytes());
+    ad
+}
+
+/// CS0 character set: OSTA compressed unicode.
+fn charspec() -> [u8; 64] {
+    let mut spec = [0; 64];
+    spec[1..24].copy_from_slice(b"OSTA Compressed Unicode");
+    spec
+}
+
+fn regid(id: &str, suffix: &[u8]) -> [u8; 32] {
+    let mut regid = [0; 32];
+    regid[1..1 + id.len()].copy_from_slice(id.as_bytes());
+    regid[24..24 + suffix.len()].copy_from_slice(suffix);
+    regid
+}
+
+fn domain_suffix() -> [u8; 2] {
+    UDF_REVISION.to_le_bytes()
+}
+
+/// UDF revision, then operating system class and identifier (undefined).
+fn udf_suffix() -> [u8; 4] {
+    let [low, high] = UDF_REVISION.to_le_bytes();
+    [low, high, 0, 0]
+}
+
+/// UDF orders permissions other, group, owner with five bits each
+/// (execute, write, read, change attributes, delete).
+fn udf_permissions(mode: u32) -> u32 {
+    (0..3).map(|who| ((mode >> (3 * who)) & 7) << (5 * who)).sum()
+}
+
+/// The 12-byte timestamp of UDF descriptors, in UTC.
+fn timestamp(epoch: i64) -> [u8; 12] {
+    let t = DateTime::from_timestamp(epoch, 0).unwrap_or_default();
+    let mut date = [0; 12];
+    // Local time with an offset of 0 minutes.
+    date[0..2].copy_from_slice(&0x1000u16.to_le_bytes());
+    date[2..4].copy_from_slice(&(t.year() as i16).to_le_bytes());
+    date[4] = t.month() as u8;
+    date[5] = t.day() as u8;
+    date[6] = t.hour() as u8;
+    date[7] = t.minute() as u8;
+    date[8] = t.second() as u8;
+    date
+}
+
+fn blocks_for(bytes: u64) -> u64 {
+    bytes.div_ceil(SECTOR as u64)
+}
+
+/// Writes whole sectors and keeps count of them.
+struct Sectors<'a, W: Write> {
+    out: &'a mut W,
+    written: u32,
+}
+
+impl<W: Write> Sectors<'_, W> {
+    /// Writes `data` zero padded to whole sectors.
+    fn write(&mut self, data: &[u8]) -> io::Result<()> {
+        self.out.write_all(data)?;
+        self.pad(data.len() as u64)
+    }
+
+    /// Pads `len` bytes just written to whole sectors.
+    fn pad(&mut self, len: u64) -> io::Result<()> {
+        let tail = (len % SECTOR as u64) as usize;
+        if tail > 0 {
+            self.out.write_all(&vec![0; SECTOR - tail])?;
+        }
+        self.written += blocks_for(len) as u32;
+        Ok(())
+    }
+
+    /// Zero fills up to sector `sector`.
+    fn skip_to(&mut self, sector: u32) -> io::Result<()> {
+        let zero = [0; SECTOR];
+        while self.written < sector {
+            self.out.write_all(&zero)?;
+            self.written += 1;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::archive::iso_builder::VOLUME_ID;
+
+    #[test]
+    fn test_write_udf() -> Result<()> {
+        // Example from ECMA-167 7.2.6.
+        assert_eq!(crc_itu(&[0x70, 0x6A, 0x77]), 0x3299);
+        assert_eq!(udf_permissions(0o555), 0b00101_00101_00101);
+
+        let dir = std::env::temp_dir().join(format!("deep_archive_udf_{}", std::process::id()));
+        fs::create_dir_all(dir.join("Holiday Photos"))?;
+        fs::write(dir.join("Holiday Photos/beach.jpg"), vec![7u8; 3000])?;
+        fs::write(dir.join(format!("{}.txt", "ü".repeat(120))), b"long name")?;
+        let udf = dir.with_extension("udf");
+
+        let members = crate::archive::walk(&dir)?;
+        write_udf(&members, &udf, VOLUME_ID, iso_builder::DEFAULT_EPOCH, &Progress::none())?;
+        let bytes = fs::read(&udf)?;
+        assert_eq!(&bytes[17 * SECTOR + 1..17 * SECTOR + 6], b"NSR02");
+        let last = bytes.len() - SECTOR;
+        // Both anchors point at the same sequences; only their locations differ.
+        assert_eq!(bytes[ANCHOR as usize * SECTOR + 16..][..496], bytes[last + 16..last + 512]);
+        assert!(bytes.windows(3000).any(|w| w == [7u8; 3000]));
+
+        write_udf(&members, &udf, VOLUME_ID, iso_builder::DEFAULT_EPOCH, &Progress::none())?;
+        assert_eq!(fs::read(&udf)?, bytes, "rebuilds are byte-identical");
+
+        fs::remove_dir_all(&dir)?;
+        fs::remove_file(&udf)?;
+        Ok(())
+    }
+}
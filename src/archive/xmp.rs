@@ -0,0 +1,180 @@
+use std::fmt::Write as _;
+use std::fs;
+use std::path::{Path, PathBuf};
+use anyhow::{Result, Context};
+use tracing::{info, warn};
+use crate::archive::mets::escape;
+use crate::archive::restore::target_path;
+use crate::database::repo::{Artifact, CatalogReader, FilterSet};
+
+/// Namespace of the properties no standard schema has room for: the
+/// content hash and the NSFW score.
+pub const NAMESPACE: &str = "https://github.com/Shib-Das/deep-archive/ns/1.0/";
+
+/// Tags in this namespace, `rating:0` to `rating:5`, become the star rating.
+const RATING: &str = "rating:";
+
+const AGENT: &str = concat!("deep-archive ", env!("CARGO_PKG_VERSION"));
+
+/// What a sidecar for `photo.jpg` is called.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SidecarName {
+    /// `photo.jpg.xmp`, as digiKam and darktable write them.
+    Append,
+    /// `photo.xmp`, as Lightroom and Capture One write them.
+    Replace,
+}
+
+/// What an export wrote.
+#[derive(Debug, Default)]
+pub struct Summary {
+    pub written: usize,
+    /// Sidecars left alone because one was already there.
+    pub existing: usize,
+    /// Artifacts whose directory doesn't exist any more.
+    pub missing: usize,
+}
+
+/// Writes an XMP sidecar for every artifact matching `filter`: next to its
+/// original path, or with `dest` at the same path under `dest` as `restore`
+/// would put the file. Sidecars already there may hold edits made in a
+/// photo manager, so they are only replaced with `overwrite`.
+pub fn export(reader: &CatalogReader, filter: &FilterSet, dest: Option<&Path>, name: SidecarName, overwrite: bool) -> Result<Summary> {
+    let mut summary = Summary::default();
+    for artifact in reader.find(filter) {
+        let artifact = artifact?;
+        let original = match dest {
+            Some(dest) => target_path(dest, &artifact.original_path),
+            None => PathBuf::from(&artifact.original_path),
+        };
+        let path = sidecar_path(&original, name);
+        if path.exists() && !overwrite {
+            summary.existing += 1;
+            continue;
+        }
+        let Some(dir) = path.parent() else {
+            continue;
+        };
+        if dest.is_some() {
+            fs::create_dir_all(dir).with_context(|| format!("Failed to create {:?}", dir))?;
+        } else if !dir.is_dir() {
+            warn!("Skipping {}: its directory no longer exists", artifact.original_path);
+            summary.missing += 1;
+            continue;
+        }
+        fs::write(&path, sidecar(&artifact)).with_context(|| format!("Failed to write {:?}", path))?;
+        summary.written += 1;
+    }
+    info!("Wrote {} XMP sidecars, left {} existing ones alone", summary.written, summary.existing);
+    Ok(summary)
+}
+
+pub fn sidecar_path(original: &Path, name: SidecarName) -> PathBuf {
+    match name {
+        SidecarName::Append => {
+            let mut path = original.as_os_str().to_owned();
+            path.push(".xmp");
+            PathBuf::from(path)
+        }
+        SidecarName::Replace => original.with_extension("xmp"),
+    }
+}
+
+/// An XMP packet with the artifact's tags as keywords, both flat
+/// (`dc:subject`) and with their namespace as the parent
+/// (`lr:hierarchicalSubject` for Lightroom, `digiKam:TagsList`), its
+/// `rating:` tag as the rating, and its hash and NSFW score.
+pub fn sidecar(artifact: &Artifact) -> String {
+    let mut keywords = Vec::new();
+    let mut hierarchical = Vec::new();
+    let mut rating = None;
+    for tag in &artifact.tags {
+        if let Some(stars) = tag.strip_prefix(RATING).and_then(|r| r.parse::<u8>().ok()).filter(|r| *r <= 5) {
+            rating = Some(stars);
+            continue;
+        }
+        let (namespace, name) = tag.split_once(':').unwrap_or(("", tag));
+        if !keywords.contains(&name) {
+            keywords.push(name);
+        }
+        hierarchical.push((namespace, name));
+    }
+
+    let mut xml = String::new();
+    xml.push_str("<?xpacket begin=\"\u{feff}\" id=\"W5M0MpCehiHzreSzNTczkc9d\"?>\n");
+    let _ = writeln!(xml, "<x:xmpmeta xmlns:x=\"adobe:ns:meta/\" x:xmptk=\"{}\">", AGENT);
+    xml.push_str(" <rdf:RDF xmlns:rdf=\"http://www.w3.org/1999/02/22-rdf-syntax-ns#\">\n");
+    xml.push_str("  <rdf:Description rdf:about=\"\"\n");
+    xml.push_str("    xmlns:xmp=\"http://ns.adobe.com/xap/1.0/\"\n");
+    xml.push_str("    xmlns:dc=\"http://purl.org/dc/elements/1.1/\"\n");
+    xml.push_str("    xmlns:lr=\"http://ns.adobe.com/lightroom/1.0/\"\n");
+    xml.push_str("    xmlns:digiKam=\"http://www.digikam.org/ns/1.0/\"\n");
+    let _ = writeln!(xml, "    xmlns:deepArchive=\"{}\"", NAMESPACE);
+    let _ = writeln!(xml, "    xmp:CreatorTool=\"{}\"", AGENT);
+    if let Some(rating) = rating {
+        let _ = writeln!(xml, "    xmp:Rating=\"{}\"", rating);
+    }
+    let _ = writeln!(xml, "    dc:format=\"{}\"", escape(&artifact.media_type));
+    let _ = write!(xml, "    deepArchive:SHA256=\"{}\"", artifact.hash_sha256);
+    if let Some(score) = artifact.nsfw_score {
+        let _ = write!(xml, "\n    deepArchive:NsfwScore=\"{:.4}\"", score);
+        if let Some(version) = &artifact.nsfw_model_version {
+            let _ = write!(xml, "\n    deepArchive:NsfwModel=\"{}\"", escape(version));
+        }
+    }
+    if keywords.is_empty() {
+        xml.push_str("/>\n");
+    } else {
+        xml.push_str(">\n");
+        list(&mut xml, "dc:subject", "Bag", keywords.iter().map(|k| k.to_string()));
+        let paths = || hierarchical.iter().map(|(ns, name)| (ns.is_empty(), ns, name));
+        list(&mut xml, "lr:hierarchicalSubject", "Bag", paths().map(|(flat, ns, name)| if flat { name.to_string() } else { format!("{}|{}", ns, name) }));
+        list(&mut xml, "digiKam:TagsList", "Seq", paths().map(|(flat, ns, name)| if flat { name.to_string() } else { format!("{}/{}", ns, name) }));
+        xml.push_str("  </rdf:Description>\n");
+    }
+    xml.push_str(" </rdf:RDF>\n</x:xmpmeta>\n<?xpacket end=\"w\"?>\n");
+    xml
+}
+
+fn list(xml: &mut String, property: &str, kind: &str, items: impl Iterator<Item = String>) {
+    let _ = writeln!(xml, "   <{}>\n    <rdf:{}>", property, kind);
+    for item in items {
+        let _ = writeln!(xml, "     <rdf:li>{}</rdf:li>", escape(&item));
+    }
+    let _ = writeln!(xml, "    </rdf:{}>\n   </{}>", kind, property);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_sidecar() {
+        let artifact = Artifact {
+            id: 1,
+            hash_sha256: "ab".repeat(32),
+            original_path: "/photos/beach.jpg".to_string(),
+            media_type: "image/jpeg".to_string(),
+            size_bytes: None,
+            mtime: None,
+            width: None,
+            height: None,
+            nsfw_score: Some(0.125),
+            nsfw_model_version: Some("v2".to_string()),
+            tags: ["ml:dog", "person:alice", "rating:4", "holiday", "meta:camera=a&b"].iter().map(|t| t.to_string()).collect(),
+            metadata: None,
+            deleted_at: None,
+            deleted_reason: None,
+        };
+        let xml = sidecar(&artifact);
+        assert!(xml.contains("xmp:Rating=\"4\""));
+        assert!(xml.contains("deepArchive:NsfwScore=\"0.1250\"\n    deepArchive:NsfwModel=\"v2\">"));
+        assert!(xml.contains("<rdf:li>dog</rdf:li>") && xml.contains("<rdf:li>holiday</rdf:li>") && xml.contains("<rdf:li>camera=a&amp;b</rdf:li>"));
+        assert!(xml.contains("<rdf:li>person|alice</rdf:li>") && xml.contains("<rdf:li>ml/dog</rdf:li>"));
+        assert!(!xml.contains("rating:"));
+
+        let original = Path::new("/photos/beach.jpg");
+        assert_eq!(sidecar_path(original, SidecarName::Append), Path::new("/photos/beach.jpg.xmp"));
+        assert_eq!(sidecar_path(original, SidecarName::Replace), Path::new("/photos/beach.xmp"));
+    }
+}
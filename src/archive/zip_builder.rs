@@ -0,0 +1,347 @@
+use std::fs::{self, File};
+use std::io::{self, BufWriter, Read, Seek, SeekFrom, Write};
+use std::path::Path;
+use anyhow::{Result, Context, bail};
+use chrono::{DateTime, Datelike, Timelike};
+use flate2::Compression;
+use flate2::write::DeflateEncoder;
+use crate::archive::Member;
+use crate::archive::progress::Progress;
+use crate::utils::config::ArchiveConfig;
+
+pub const LOCAL_HEADER: u32 = 0x04034b50;
+pub const CENTRAL_HEADER: u32 = 0x02014b50;
+pub const END_OF_CENTRAL_DIRECTORY: u32 = 0x06054b50;
+pub const ZIP64_END_OF_CENTRAL_DIRECTORY: u32 = 0x06064b50;
+pub const ZIP64_LOCATOR: u32 = 0x07064b50;
+
+/// Version 4.5, the first with ZIP64; made on Unix so the modes apply.
+const VERSION: u16 = 45;
+const VERSION_MADE_BY: u16 = (3 << 8) | VERSION;
+/// General purpose flag: names are UTF-8.
+const UTF8_NAMES: u16 = 1 << 11;
+pub const STORED: u16 = 0;
+pub const DEFLATED: u16 = 8;
+
+/// Files at least this large get ZIP64 sizes in their local header, since
+/// deflate may grow them past 4 GiB before the real size is known.
+const ZIP64_THRESHOLD: u64 = 0xF000_0000;
+
+/// Writes `members` to a ZIP archive at `output`, with `epoch` for every
+/// timestamp, reporting to `progress`.
+pub fn create_zip(members: &[Member], output: &Path, epoch: i64, config: &ArchiveConfig, progress: &Progress) -> Result<()> {
+    if let Some(parent) = output.parent() {
+        fs::create_dir_all(parent)
+            .context("Failed to create parent directory for archive output")?;
+    }
+    let options = ZipOptions { level: config.zip_level, store_extensions: &config.zip_store_extensions };
+    write_zip(members, output, epoch, &options, progress)
+}
+
+/// Per-entry compression for `write_zip`.
+pub struct ZipOptions<'a> {
+    /// Deflate level, 0-9; 0 stores every entry.
+    pub level: u32,
+    /// Extensions (case-insensitive, without the dot) stored uncompressed,
+    /// typically formats that are compressed already.
+    pub store_extensions: &'a [String],
+}
+
+impl ZipOptions<'_> {
+    fn method(&self, member: &Member) -> u16 {
+        let stored = member
+            .path
+            .extension()
+            .and_then(|e| e.to_str())
+            .is_some_and(|e| self.store_extensions.iter().any(|s| s.eq_ignore_ascii_case(e)));
+        if self.level == 0 || stored || member.size == 0 {
+            STORED
+        } else {
+            DEFLATED
+        }
+    }
+}
+
+/// Central directory fields of a written entry.
+struct Entry {
+    name: Vec<u8>,
+    method: u16,
+    crc: u32,
+    compressed: u64,
+    size: u64,
+    offset: u64,
+    mode: u32,
+}
+
+/// Writes `members` as a ZIP archive, switching to ZIP64 records where
+/// sizes, offsets or the entry count outgrow the classic format. Entries
+/// are written in member order with every timestamp at `epoch` (both as a
+/// DOS time and a UTC extended timestamp) and modes 0644/0755, so the same
+/// tree always gives the same bytes.
+pub fn write_zip(members: &[Member], output: &Path, epoch: i64, options: &ZipOptions, progress: &Progress) -> Result<()> {
+    let file = File::create(output).with_context(|| format!("Failed to create {:?}", output))?;
+    let mut out = BufWriter::new(file);
+    let (time, date) = dos_datetime(epoch);
+    let mtime = epoch.clamp(0, u32::MAX as i64) as u32;
+
+    let mut entries = Vec::with_capacity(members.len());
+    for member in members {
+        let mut name = member.path.to_string_lossy().replace('\\', "/");
+        if member.is_dir {
+            name.push('/');
+        }
+        let method = if member.is_dir { STORED } else { options.method(member) };
+        let zip64 = member.size >= ZIP64_THRESHOLD;
+        let offset = out.stream_position()?;
+
+        // Sizes and CRC are patched in once the data is written.
+        let mut extra = extended_timestamp(mtime);
+        if zip64 {
+            extra.extend(0x0001u16.to_le_bytes());
+            extra.extend(16u16.to_le_bytes());
+            extra.extend([0; 16]);
+        }
+        let mut header = Vec::new();
+        header.extend(LOCAL_HEADER.to_le_bytes());
+        header.extend(VERSION.to_le_bytes());
+        header.extend(UTF8_NAMES.to_le_bytes());
+        header.extend(method.to_le_bytes());
+        header.extend(time.to_le_bytes());
+        header.extend(date.to_le_bytes());
+        header.extend([0; 12]);
+        header.extend((name.len() as u16).to_le_bytes());
+        header.extend((extra.len() as u16).to_le_bytes());
+        header.extend(name.as_bytes());
+        header.extend(&extra);
+        out.write_all(&header)?;
+
+        let (crc, compressed) = if member.is_dir {
+            (0, 0)
+        } else {
+            let source = File::open(&member.source).with_context(|| format!("Failed to open {:?}", member.source))?;
+            write_data(progress.reader(source), member, method, options.level, &mut out)
+                .with_context(|| format!("Failed to add {:?}", member.source))?
+        };
+
+        let end = out.stream_position()?;
+        out.seek(SeekFrom::Start(offset + 14))?;
+        out.write_all(&crc.to_le_bytes())?;
+        if zip64 {
+            out.write_all(&[0xFF; 8])?;
+            out.seek(SeekFrom::Start(offset + header.len() as u64 - 16))?;
+            out.write_all(&member.size.to_le_bytes())?;
+            out.write_all(&compressed.to_le_bytes())?;
+        } else {
+            if compressed > u32::MAX as u64 {
+                bail!("{:?} grew past 4 GiB when compressed; store it instead", member.source);
+            }
+            out.write_all(&(compressed as u32).to_le_bytes())?;
+            out.write_all(&(member.size as u32).to_le_bytes())?;
+        }
+        out.seek(SeekFrom::Start(end))?;
+
+        entries.push(Entry {
+            name: name.into_bytes(),
+            method,
+            crc,
+            compressed,
+            size: member.size,
+            offset,
+            mode: if member.is_dir { 0o040755 } else { 0o100644 },
+        });
+    }
+
+    let central_start = out.stream_position()?;
+    for entry in &entries {
+        // ZIP64 extra: only the fields that overflow, in this order.
+        let mut zip64 = Vec::new();
+        let size = clamp32(entry.size, &mut zip64);
+        let compressed = clamp32(entry.compressed, &mut zip64);
+        let offset = clamp32(entry.offset, &mut zip64);
+        let mut extra = extended_timestamp(mtime);
+        if !zip64.is_empty() {
+            extra.extend(0x0001u16.to_le_bytes());
+            extra.extend((zip64.len() as u16).to_le_bytes());
+            extra.extend(zip64);
+        }
+
+        let mut header = Vec::new();
+        header.extend(CENTRAL_HEADER.to_le_bytes());
+        header.extend(VERSION_MADE_BY.to_le_bytes());
+        header.extend(VERSION.to_le_bytes());
+        header.extend(UTF8_NAMES.to_le_bytes());
+        header.extend(entry.method.to_le_bytes());
+        header.extend(time.to_le_bytes());
+        header.extend(date.to_le_bytes());
+        header.extend(entry.crc.to_le_bytes());
+        header.extend(compressed.to_le_bytes());
+        header.extend(size.to_le_bytes());
+        header.extend((entry.name.len() as u16).to_le_bytes());
+        header.extend((extra.len() as u16).to_le_bytes());
+        // Comment length, disk number, internal attributes.
+        header.extend([0; 6]);
+        // Unix mode in the high half; 0x10 is the MS-DOS directory bit.
+        let dos = if entry.mode & 0o040000 != 0 { 0x10 } else { 0 };
+        header.extend(((entry.mode << 16) | dos).to_le_bytes());
+        header.extend(offset.to_le_bytes());
+        header.extend(&entry.name);
+        header.extend(extra);
+        out.write_all(&header)?;
+    }
+    let central_end = out.stream_position()?;
+    let central_size = central_end - central_start;
+
+    let count = entries.len() as u64;
+    if count >= 0xFFFF || central_size >= u32::MAX as u64 || central_start >= u32::MAX as u64 {
+        let mut record = Vec::new();
+        record.extend(ZIP64_END_OF_CENTRAL_DIRECTORY.to_le_bytes());
+        // Size of the rest of the record.
+        record.extend(44u64.to_le_bytes());
+        record.extend(VERSION_MADE_BY.to_le_bytes());
+        record.extend(VERSION.to_le_bytes());
+        record.extend([0; 8]);
+        record.extend(count.to_le_bytes());
+        record.extend(count.to_le_bytes());
+        record.extend(central_size.to_le_bytes());
+        record.extend(central_start.to_le_bytes());
+        record.extend(ZIP64_LOCATOR.to_le_bytes());
+        record.extend(0u32.to_le_bytes());
+        record.extend(central_end.to_le_bytes());
+        record.extend(1u32.to_le_bytes());
+        out.write_all(&record)?;
+    }
+
+    let mut end = Vec::new();
+    end.extend(END_OF_CENTRAL_DIRECTORY.to_le_bytes());
+    end.extend([0; 4]);
+    end.extend((count.min(0xFFFF) as u16).to_le_bytes());
+    end.extend((count.min(0xFFFF) as u16).to_le_bytes());
+    end.extend((central_size.min(u32::MAX as u64) as u32).to_le_bytes());
+    end.extend((central_start.min(u32::MAX as u64) as u32).to_le_bytes());
+    end.extend(0u16.to_le_bytes());
+    out.write_all(&end)?;
+    out.flush()?;
+    Ok(())
+}
+
+/// Copies exactly `member.size` bytes, compressed with `method`. Returns
+/// the CRC-32 of the content and the number of bytes written.
+fn write_data(source: impl Read, member: &Member, method: u16, level: u32, out: &mut impl Write) -> Result<(u32, u64)> {
+    let mut counted = Counter { inner: out, count: 0 };
+    let mut crc = crc32fast::Hasher::new();
+    let mut input = source.take(member.size);
+
+    let read = if method == DEFLATED {
+        let mut encoder = DeflateEncoder::new(&mut counted, Compression::new(level));
+        let read = copy_hashed(&mut input, &mut crc, &mut encoder)?;
+        encoder.finish()?;
+        read
+    } else {
+        copy_hashed(&mut input, &mut crc, &mut counted)?
+    };
+    if read != member.size {
+        bail!("file shrank while the archive was being written");
+    }
+    Ok((crc.finalize(), counted.count))
+}
+
+fn copy_hashed(input: &mut impl Read, crc: &mut crc32fast::Hasher, out: &mut impl Write) -> io::Result<u64> {
+    let mut buf = vec![0; 64 * 1024];
+    let mut read = 0;
+    loop {
+        let n = input.read(&mut buf)?;
+        if n == 0 {
+            return Ok(read);
+        }
+        crc.update(&buf[..n]);
+        out.write_all(&buf[..n])?;
+        read += n as u64;
+    }
+}
+
+struct Counter<W> {
+    inner: W,
+    count: u64,
+}
+
+impl<W: Write> Write for Counter<W> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        let n = self.inner.write(buf)?;
+        self.count += n as u64;
+        Ok(n)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.inner.flush()
+    }
+}
+
+/// `value` as a 32-bit field, or 0xFFFFFFFF with the real value appended
+/// to the ZIP64 extra.
+fn clamp32(value: u64, zip64: &mut Vec<u8>) -> u32 {
+    if value >= u32::MAX as u64 {
+        zip64.extend(value.to_le_bytes());
+        u32::MAX
+    } else {
+        value as u32
+    }
+}
+
+/// The "UT" extra field with the modification time in UTC, since DOS
+/// times carry no time zone.
+fn extended_timestamp(mtime: u32) -> Vec<u8> {
+    let mut extra = Vec::new();
+    extra.extend(0x5455u16.to_le_bytes());
+    extra.extend(5u16.to_le_bytes());
+    extra.push(1);
+    extra.extend(mtime.to_le_bytes());
+    extra
+}
+
+/// MS-DOS time and date fields, clamped to 1980-2107.
+fn dos_datetime(epoch: i64) -> (u16, u16) {
+    let t = DateTime::from_timestamp(epoch, 0).unwrap_or_default();
+    if t.year() < 1980 {
+        return (0, (1 << 5) | 1);
+    }
+    let year = (t.year() - 1980).min(127) as u16;
+    let time = ((t.hour() as u16) << 11) | ((t.minute() as u16) << 5) | (t.second() as u16 / 2);
+    let date = (year << 9) | ((t.month() as u16) << 5) | t.day() as u16;
+    (time, date)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::archive::iso_builder::DEFAULT_EPOCH;
+
+    #[test]
+    fn test_write_zip() -> Result<()> {
+        let dir = std::env::temp_dir().join(format!("deep_archive_zip_{}", std::process::id()));
+        fs::create_dir_all(dir.join("album"))?;
+        fs::write(dir.join("album/photo.JPG"), vec![1u8; 5000])?;
+        fs::write(dir.join("notes.txt"), "text ".repeat(1000))?;
+        let output = dir.with_extension("zip");
+
+        let members = crate::archive::walk(&dir)?;
+        let options = ZipOptions { level: 6, store_extensions: &["jpg".to_string()] };
+        write_zip(&members, &output, DEFAULT_EPOCH, &options, &Progress::none())?;
+        let bytes = fs::read(&output)?;
+
+        // The photo is stored as is, the text deflated to a fraction.
+        let find = |needle: &[u8]| bytes.windows(needle.len()).position(|w| w == needle);
+        assert!(find(&[1u8; 5000]).is_some());
+        assert!(find(b"text text").is_none());
+        assert!(bytes.len() < 5000 + 1000);
+        assert_eq!(&bytes[bytes.len() - 22..bytes.len() - 18], END_OF_CENTRAL_DIRECTORY.to_le_bytes());
+        // album/, album/photo.JPG, notes.txt
+        assert_eq!(u16::from_le_bytes([bytes[bytes.len() - 12], bytes[bytes.len() - 11]]), 3);
+
+        write_zip(&members, &output, DEFAULT_EPOCH, &options, &Progress::none())?;
+        assert_eq!(fs::read(&output)?, bytes, "rebuilds are byte-identical");
+
+        fs::remove_dir_all(&dir)?;
+        fs::remove_file(&output)?;
+        Ok(())
+    }
+}
@@ -0,0 +1,123 @@
+use std::path::PathBuf;
+use serde::{Deserialize, Serialize};
+use crate::daemon::Status;
+
+/// The control socket's answer to a request: one JSON line.
+#[derive(Debug, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+enum Reply {
+    Status(Box<Status>),
+    Error(String),
+}
+
+/// Where the daemon listens unless `daemon.socket` says otherwise:
+/// `$XDG_RUNTIME_DIR/deep-archive.sock`, which only its user can reach,
+/// else a socket named for the user in the temp directory.
+pub fn default_socket() -> PathBuf {
+    match std::env::var_os("XDG_RUNTIME_DIR") {
+        Some(dir) => PathBuf::from(dir).join("deep-archive.sock"),
+        None => std::env::temp_dir().join(format!("deep-archive-{}.sock", user_id())),
+    }
+}
+
+#[cfg(unix)]
+fn user_id() -> u32 {
+    // SAFETY: getuid has no preconditions and can't fail.
+    unsafe { libc::getuid() }
+}
+
+#[cfg(not(unix))]
+fn user_id() -> String {
+    std::env::var("USERNAME").unwrap_or_default()
+}
+
+#[cfg(unix)]
+pub use unix::{listen, request, ControlSocket};
+
+#[cfg(unix)]
+mod unix {
+    use std::fs;
+    use std::io::{BufRead, BufReader, Write};
+    use std::os::unix::fs::PermissionsExt;
+    use std::os::unix::net::{UnixListener, UnixStream};
+    use std::path::{Path, PathBuf};
+    use std::sync::Arc;
+    use std::thread;
+    use std::time::Duration;
+    use anyhow::{Result, Context, anyhow, bail};
+    use serde_json::Value;
+    use tracing::{info, warn};
+    use super::Reply;
+    use crate::daemon::{Daemon, Request, Status};
+
+    /// How long either side waits on the other.
+    const TIMEOUT: Duration = Duration::from_secs(30);
+
+    /// The daemon's end of the socket, removed when dropped.
+    pub struct ControlSocket {
+        path: PathBuf,
+    }
+
+    impl Drop for ControlSocket {
+        fn drop(&mut self) {
+            let _ = fs::remove_file(&self.path);
+        }
+    }
+
+    /// Answers requests on the socket at `path`, one line each naming a
+    /// `Request` (`status`, `pause`, `resume`, `reload`), on a thread of
+    /// its own. Only the daemon's user can connect.
+    pub fn listen(path: &Path, daemon: Arc<Daemon>) -> Result<ControlSocket> {
+        if path.exists() {
+            if UnixStream::connect(path).is_ok() {
+                bail!("A daemon is already listening on {:?}", path);
+            }
+            // Left behind by a daemon that was killed.
+            fs::remove_file(path).with_context(|| format!("Failed to remove the stale socket {:?}", path))?;
+        }
+        let listener = UnixListener::bind(path).with_context(|| format!("Failed to listen on {:?}", path))?;
+        fs::set_permissions(path, fs::Permissions::from_mode(0o600))?;
+        thread::Builder::new().name("control".to_string()).spawn(move || {
+            for stream in listener.incoming() {
+                let result = stream.map_err(anyhow::Error::from).and_then(|stream| answer(stream, &daemon));
+                if let Err(e) = result {
+                    warn!("Control request failed: {:#}", e);
+                }
+            }
+        })?;
+        info!("Taking control requests on {:?}", path);
+        Ok(ControlSocket { path: path.to_path_buf() })
+    }
+
+    fn answer(stream: UnixStream, daemon: &Daemon) -> Result<()> {
+        stream.set_read_timeout(Some(TIMEOUT))?;
+        let mut line = String::new();
+        BufReader::new(&stream).read_line(&mut line)?;
+        let reply = match serde_json::from_value::<Request>(Value::String(line.trim().to_lowercase())) {
+            Ok(request) => match daemon.handle(request) {
+                Ok(status) => Reply::Status(Box::new(status)),
+                Err(e) => Reply::Error(format!("{:#}", e)),
+            },
+            Err(_) => Reply::Error(format!("Unknown request {:?}; expected status, pause, resume or reload", line.trim())),
+        };
+        let mut stream = &stream;
+        writeln!(stream, "{}", serde_json::to_string(&reply)?)?;
+        Ok(())
+    }
+
+    /// Sends `request` to the daemon listening at `path` and returns its
+    /// status after carrying it out.
+    pub fn request(path: &Path, request: Request) -> Result<Status> {
+        let stream = UnixStream::connect(path).with_context(|| format!("No daemon is listening on {:?}", path))?;
+        stream.set_read_timeout(Some(TIMEOUT))?;
+        let name = serde_json::to_value(request)?;
+        let mut writer = &stream;
+        writeln!(writer, "{}", name.as_str().unwrap_or_default())?;
+        let mut line = String::new();
+        BufReader::new(&stream).read_line(&mut line)?;
+        match serde_json::from_str(&line).with_context(|| format!("Unexpected answer from the daemon: {:?}", line))? {
+            Reply::Status(status) => Ok(*status),
+            Reply::Error(e) => Err(anyhow!(e)),
+        }
+    }
+}
@@ -0,0 +1,305 @@
+pub mod control;
+mod watch;
+
+use std::path::PathBuf;
+use std::sync::{Condvar, Mutex, MutexGuard};
+use std::time::{Duration, Instant};
+use anyhow::Result;
+use serde::{Deserialize, Serialize};
+use serde_json::json;
+use tracing::{error, info, warn};
+use crate::daemon::watch::Tree;
+use crate::database::store;
+use crate::ingest::pipeline::{self, Input};
+use crate::ingest::summary::RunSummary;
+use crate::utils::config::{self, Config};
+
+/// What `ctl` asks the daemon to do.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Request {
+    Status,
+    /// Start no more runs until resumed; the one in progress finishes.
+    Pause,
+    Resume,
+    /// Read the config file again: directories to watch, the interval,
+    /// the pipeline and notifications. `[server]` keeps its settings until
+    /// a restart.
+    Reload,
+}
+
+/// What `ctl` answers with.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Status {
+    pub pid: u32,
+    /// Unix seconds.
+    pub started_at: i64,
+    pub paused: bool,
+    pub watching: Vec<PathBuf>,
+    pub interval_secs: u64,
+    /// Files in the run in progress.
+    pub ingesting: usize,
+    /// Files seen changing that haven't settled yet.
+    pub settling: usize,
+    /// When the directories were last looked at, in unix seconds.
+    pub last_poll: Option<i64>,
+    /// Runs started since the daemon was.
+    pub runs: u64,
+    pub last_run: Option<RunSummary>,
+    pub last_error: Option<String>,
+    /// The URL the HTTP API is served at, if it is.
+    pub serving: Option<String>,
+}
+
+struct State {
+    config: Config,
+    paused: bool,
+    stopping: bool,
+    /// Look at the directories without waiting out the interval.
+    poll_now: bool,
+    ingesting: usize,
+    settling: usize,
+    last_poll: Option<i64>,
+    runs: u64,
+    last_run: Option<RunSummary>,
+    last_error: Option<String>,
+    serving: Option<String>,
+}
+
+/// Ingests what appears in the `daemon.watch` directories until stopped,
+/// taking requests from `ctl` through its control socket in the meantime.
+pub struct Daemon {
+    db_path: String,
+    /// Read again on `reload`; unset reads `deep-archive.toml` if there is one.
+    config_path: Option<PathBuf>,
+    /// Watched on top of `daemon.watch`, given on the command line; a
+    /// reload keeps them.
+    watch: Vec<PathBuf>,
+    started_at: i64,
+    state: Mutex<State>,
+    wake: Condvar,
+}
+
+impl Daemon {
+    pub fn new(db_path: &str, mut config: Config, config_path: Option<PathBuf>, watch: Vec<PathBuf>) -> Self {
+        config.daemon.watch.extend(watch.iter().cloned());
+        let state = State {
+            config,
+            paused: false,
+            stopping: false,
+            poll_now: true,
+            ingesting: 0,
+            settling: 0,
+            last_poll: None,
+            runs: 0,
+            last_run: None,
+            last_error: None,
+            serving: None,
+        };
+        Daemon { db_path: db_path.to_string(), config_path, watch, started_at: chrono::Utc::now().timestamp(), state: Mutex::new(state), wake: Condvar::new() }
+    }
+
+    fn state(&self) -> MutexGuard<'_, State> {
+        self.state.lock().unwrap()
+    }
+
+    /// The config as last loaded.
+    pub fn config(&self) -> Config {
+        self.state().config.clone()
+    }
+
+    /// Records the URL the HTTP API is served at, for `status`.
+    pub fn serving(&self, url: String) {
+        self.state().serving = Some(url);
+    }
+
+    pub fn handle(&self, request: Request) -> Result<Status> {
+        match request {
+            Request::Status => {}
+            Request::Pause => self.pause(),
+            Request::Resume => self.resume(),
+            Request::Reload => self.reload()?,
+        }
+        Ok(self.status())
+    }
+
+    pub fn status(&self) -> Status {
+        let state = self.state();
+        Status {
+            pid: std::process::id(),
+            started_at: self.started_at,
+            paused: state.paused,
+            watching: state.config.daemon.watch.clone(),
+            interval_secs: state.config.daemon.interval_secs,
+            ingesting: state.ingesting,
+            settling: state.settling,
+            last_poll: state.last_poll,
+            runs: state.runs,
+            last_run: state.last_run.clone(),
+            last_error: state.last_error.clone(),
+            serving: state.serving.clone(),
+        }
+    }
+
+    pub fn pause(&self) {
+        let mut state = self.state();
+        if !state.paused {
+            info!("Paused; no new runs start until resumed");
+            state.paused = true;
+        }
+    }
+
+    pub fn resume(&self) {
+        let mut state = self.state();
+        if state.paused {
+            info!("Resumed");
+            state.paused = false;
+            state.poll_now = true;
+            self.wake.notify_all();
+        }
+    }
+
+    pub fn reload(&self) -> Result<()> {
+        let mut config = config::load_config(self.config_path.as_deref())?;
+        config.daemon.watch.extend(self.watch.iter().cloned());
+        let mut state = self.state();
+        info!("Reloaded the config; watching {} directories", config.daemon.watch.len());
+        state.config = config;
+        state.poll_now = true;
+        self.wake.notify_all();
+        Ok(())
+    }
+
+    /// Stops the run in progress as an interrupt would, and `run` once it has.
+    pub fn stop(&self) {
+        let mut state = self.state();
+        state.stopping = true;
+        pipeline::interrupt();
+        self.wake.notify_all();
+    }
+
+    pub fn stopping(&self) -> bool {
+        self.state().stopping
+    }
+
+    /// Looks at the watched directories every `daemon.interval_secs` and
+    /// ingests what settled there since, until `stop` is called.
+    pub fn run(&self) -> Result<()> {
+        let mut trees: Vec<Tree> = Vec::new();
+        let mut next = Instant::now();
+        loop {
+            let config = {
+                let mut state = self.state();
+                loop {
+                    if state.stopping {
+                        return Ok(());
+                    }
+                    let due = next.saturating_duration_since(Instant::now());
+                    if !state.paused && (state.poll_now || due.is_zero()) {
+                        break;
+                    }
+                    // Paused, it waits to be woken.
+                    let wait = if state.paused { Duration::from_secs(3600) } else { due };
+                    state = self.wake.wait_timeout(state, wait).unwrap().0;
+                }
+                state.poll_now = false;
+                state.config.clone()
+            };
+            next = Instant::now() + Duration::from_secs(config.daemon.interval_secs.max(1));
+            self.poll(&mut trees, &config);
+        }
+    }
+
+    /// Ingests the settled changes in each watched directory as a run of
+    /// its own.
+    fn poll(&self, trees: &mut Vec<Tree>, config: &Config) {
+        let roots: Vec<PathBuf> = config.daemon.watch.iter().map(|dir| dir.canonicalize().unwrap_or_else(|_| dir.clone())).collect();
+        trees.retain(|tree| roots.contains(&tree.root));
+        for root in &roots {
+            if trees.iter().any(|tree| &tree.root == root) {
+                continue;
+            }
+            let tree = store::open(&self.db_path, &config.database).and_then(|mut store| Tree::open(root.clone(), store.as_mut()));
+            match tree {
+                Ok(tree) => {
+                    info!("Watching {:?}", root);
+                    trees.push(tree);
+                }
+                Err(e) => self.failed(format!("Can't watch {:?}: {:#}", root, e)),
+            }
+        }
+
+        let settle = Duration::from_secs(config.daemon.settle_secs);
+        let mut settling = 0;
+        for tree in trees.iter_mut() {
+            let (ready, waiting) = tree.changes(settle);
+            settling += waiting;
+            {
+                let mut state = self.state();
+                if state.stopping || state.paused {
+                    return;
+                }
+                state.ingesting = ready.len();
+                state.settling = settling;
+            }
+            if ready.is_empty() {
+                continue;
+            }
+            info!("Ingesting {} new or changed files in {:?}", ready.len(), tree.root);
+            let paths = ready.iter().map(|(path, _)| path.clone()).collect();
+            let roots = vec![tree.root.to_string_lossy().to_string()];
+            let options = json!({ "daemon": true }).to_string();
+            let result = pipeline::run(Input::Files(paths), roots, options, &self.db_path, config.clone());
+            let mut state = self.state();
+            state.ingesting = 0;
+            match result {
+                Ok(summary) => {
+                    // Files that failed stay in `errors` for a retry rather
+                    // than being tried again at every look.
+                    tree.ingested(ready);
+                    state.runs += 1;
+                    state.last_run = Some(summary);
+                }
+                Err(_) if state.stopping => return,
+                Err(e) => {
+                    drop(state);
+                    self.failed(format!("Ingesting {:?} failed: {:#}", tree.root, e));
+                }
+            }
+        }
+        let mut state = self.state();
+        state.settling = settling;
+        state.last_poll = Some(chrono::Utc::now().timestamp());
+    }
+
+    fn failed(&self, message: String) {
+        error!("{}", message);
+        self.state().last_error = Some(message);
+    }
+}
+
+/// Tells systemd about the daemon's state (`READY=1`, `STOPPING=1`), where
+/// it was started as a `Type=notify` service.
+#[cfg(unix)]
+pub fn notify_supervisor(state: &str) {
+    #[cfg(target_os = "linux")]
+    use std::os::linux::net::SocketAddrExt;
+    use std::os::unix::net::{SocketAddr, UnixDatagram};
+    let Some(path) = std::env::var_os("NOTIFY_SOCKET") else {
+        return;
+    };
+    let addr = match path.to_string_lossy().strip_prefix('@') {
+        #[cfg(target_os = "linux")]
+        Some(name) => SocketAddr::from_abstract_name(name.as_bytes()),
+        #[cfg(not(target_os = "linux"))]
+        Some(_) => return,
+        None => SocketAddr::from_pathname(&path),
+    };
+    let sent = addr.and_then(|addr| UnixDatagram::unbound()?.send_to_addr(state.as_bytes(), &addr));
+    if let Err(e) = sent {
+        warn!("Failed to notify systemd: {}", e);
+    }
+}
+
+#[cfg(not(unix))]
+pub fn notify_supervisor(_state: &str) {}
@@ -0,0 +1,104 @@
+use std::collections::{HashMap, HashSet};
+use std::path::PathBuf;
+use std::time::Duration;
+use anyhow::Result;
+use tracing::warn;
+use walkdir::WalkDir;
+use crate::database::repo::CommittedFile;
+use crate::database::store::CatalogStore;
+use crate::ingest::{hasher, scanner};
+
+/// A watched directory, and the size and mtime of each file in it as last
+/// ingested.
+pub(crate) struct Tree {
+    pub root: PathBuf,
+    known: HashMap<PathBuf, CommittedFile>,
+}
+
+impl Tree {
+    /// Starts from what the catalog holds under `root`, so files ingested
+    /// before the daemon started aren't ingested again.
+    pub fn open(root: PathBuf, store: &mut dyn CatalogStore) -> Result<Self> {
+        let known = store.catalogued_files(&root.to_string_lossy())?;
+        Ok(Tree { root, known: known.into_iter().map(|(path, file)| (PathBuf::from(path), file)).collect() })
+    }
+
+    /// Files that are new or changed and have gone unmodified for `settle`,
+    /// and the number still being modified. Files that went away are
+    /// forgotten, so they are ingested again should they come back.
+    pub fn changes(&mut self, settle: Duration) -> (Vec<(PathBuf, CommittedFile)>, usize) {
+        let mut ready = Vec::new();
+        let mut settling = 0;
+        let mut seen = HashSet::with_capacity(self.known.len());
+        for entry in WalkDir::new(&self.root).into_iter().filter_entry(|e| !scanner::is_hidden(e)) {
+            let entry = match entry {
+                Ok(entry) => entry,
+                Err(e) => {
+                    warn!("Can't look at {}: {}", e.path().map_or(self.root.display(), |p| p.display()), e);
+                    continue;
+                }
+            };
+            if !entry.file_type().is_file() {
+                continue;
+            }
+            let Ok(metadata) = entry.metadata() else {
+                continue;
+            };
+            let file = CommittedFile { size_bytes: Some(metadata.len()), mtime: hasher::mtime(&metadata) };
+            let path = entry.into_path();
+            if self.known.get(&path) != Some(&file) {
+                // A file modified in the future has settled as far as can be told.
+                let age = metadata.modified().ok().and_then(|m| m.elapsed().ok());
+                if age.is_some_and(|age| age < settle) {
+                    settling += 1;
+                } else {
+                    ready.push((path.clone(), file));
+                }
+            }
+            seen.insert(path);
+        }
+        self.known.retain(|path, _| seen.contains(path));
+        (ready, settling)
+    }
+
+    /// Remembers files as ingested.
+    pub fn ingested(&mut self, files: Vec<(PathBuf, CommittedFile)>) {
+        self.known.extend(files);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::fs::{self, File};
+    use std::time::SystemTime;
+
+    #[test]
+    fn test_changes() -> Result<()> {
+        let root = std::env::temp_dir().join(format!("deep_archive_watch_{}", std::process::id()));
+        fs::create_dir_all(root.join(".hidden"))?;
+        let hour_ago = SystemTime::now() - Duration::from_secs(3600);
+        for name in ["old.jpg", "known.jpg", ".hidden/x.jpg"] {
+            fs::write(root.join(name), name)?;
+            File::options().write(true).open(root.join(name))?.set_modified(hour_ago)?;
+        }
+        fs::write(root.join("copying.mp4"), "partial")?;
+
+        let known = fs::metadata(root.join("known.jpg"))?;
+        let mut tree = Tree { root: root.clone(), known: HashMap::new() };
+        let gone = CommittedFile { size_bytes: Some(1), mtime: Some(1) };
+        tree.known.insert(root.join("known.jpg"), CommittedFile { size_bytes: Some(known.len()), mtime: hasher::mtime(&known) });
+        tree.known.insert(root.join("deleted.jpg"), gone);
+
+        let (ready, settling) = tree.changes(Duration::from_secs(30));
+        assert_eq!(ready.iter().map(|(p, _)| p.clone()).collect::<Vec<_>>(), vec![root.join("old.jpg")]);
+        assert_eq!(settling, 1);
+        assert!(!tree.known.contains_key(&root.join("deleted.jpg")));
+
+        tree.ingested(ready);
+        let (ready, _) = tree.changes(Duration::ZERO);
+        assert_eq!(ready.iter().map(|(p, _)| p.clone()).collect::<Vec<_>>(), vec![root.join("copying.mp4")]);
+        fs::remove_dir_all(&root)?;
+        Ok(())
+    }
+}
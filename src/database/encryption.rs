@@ -0,0 +1,107 @@
+use std::process::Command;
+use std::sync::OnceLock;
+use anyhow::{Result, Context, bail};
+use crate::utils::config::DatabaseConfig;
+
+/// Environment variable checked first for the catalog key.
+pub const KEY_ENV: &str = "DEEP_ARCHIVE_KEY";
+
+/// Resolved once, so a prompted key isn't asked for again by every connection.
+static KEY: OnceLock<String> = OnceLock::new();
+
+/// The SQLCipher key to open the catalog with, or `None` for a plaintext one.
+pub fn key(config: &DatabaseConfig) -> Result<Option<&'static str>> {
+    if !config.encrypted {
+        return Ok(None);
+    }
+    if !cfg!(feature = "sqlcipher") {
+        bail!("database.encrypted is set, but this build lacks the `sqlcipher` feature");
+    }
+    resolve(config).map(Some)
+}
+
+/// Looks the key up from `$DEEP_ARCHIVE_KEY`, then `database.key_command`
+/// (e.g. a keyring lookup), then a terminal prompt.
+pub fn resolve(config: &DatabaseConfig) -> Result<&'static str> {
+    if let Some(key) = KEY.get() {
+        return Ok(key);
+    }
+
+    let key = match std::env::var(KEY_ENV) {
+        Ok(key) => key,
+        Err(_) => match &config.key_command {
+            Some(command) => run_key_command(command)?,
+            None => prompt()?,
+        },
+    };
+    if key.is_empty() {
+        bail!("The catalog key is empty");
+    }
+    Ok(KEY.get_or_init(|| key))
+}
+
+fn run_key_command(command: &[String]) -> Result<String> {
+    let Some((program, args)) = command.split_first() else {
+        bail!("database.key_command is empty");
+    };
+    let output = Command::new(program)
+        .args(args)
+        .output()
+        .with_context(|| format!("Failed to run key command {:?}", program))?;
+    if !output.status.success() {
+        bail!(
+            "Key command {:?} failed ({}): {}",
+            program,
+            output.status,
+            String::from_utf8_lossy(&output.stderr).trim()
+        );
+    }
+    // Keyring tools print the secret followed by a newline.
+    let key = String::from_utf8(output.stdout).context("Key command printed invalid UTF-8")?;
+    Ok(key.trim_end_matches(['\r', '\n']).to_string())
+}
+
+/// Reads the key from the controlling terminal with echo turned off.
+#[cfg(unix)]
+fn prompt() -> Result<String> {
+    use std::fs::File;
+    use std::io::{BufRead, BufReader};
+
+    let tty = File::open("/dev/tty")
+        .with_context(|| format!("The catalog is encrypted; set {} or database.key_command", KEY_ENV))?;
+    eprint!("Catalog key: ");
+    let _ = Command::new("stty").arg("-echo").stdin(tty.try_clone()?).status();
+    let mut line = String::new();
+    let read = BufReader::new(&tty).read_line(&mut line);
+    let _ = Command::new("stty").arg("echo").stdin(tty).status();
+    eprintln!();
+
+    read.context("Failed to read the catalog key")?;
+    Ok(line.trim_end_matches(['\r', '\n']).to_string())
+}
+
+#[cfg(not(unix))]
+fn prompt() -> Result<String> {
+    bail!("The catalog is encrypted; set {} or database.key_command", KEY_ENV)
+}
+
+/// Copies the open catalog into a new SQLCipher file at `output`, keyed with
+/// `key`; this is how an existing plaintext catalog gets encrypted.
+#[cfg(feature = "sqlcipher")]
+pub fn export(conn: &rusqlite::Connection, output: &std::path::Path, key: &str) -> Result<()> {
+    if output.exists() {
+        bail!("{:?} already exists", output);
+    }
+    let path = output.to_str().context("Output path is not valid UTF-8")?;
+    conn.execute("ATTACH DATABASE ?1 AS encrypted KEY ?2", rusqlite::params![path, key])?;
+    let copied = conn
+        .query_row("SELECT sqlcipher_export('encrypted')", [], |_| Ok(()))
+        .and_then(|_| {
+            // sqlcipher_export copies tables and indexes, not the schema version.
+            let version: i64 = conn.query_row("PRAGMA main.user_version", [], |row| row.get(0))?;
+            conn.pragma_update(Some(rusqlite::DatabaseName::Attached("encrypted")), "user_version", version)
+        });
+    conn.execute("DETACH DATABASE encrypted", [])?;
+    copied.context("Failed to export the encrypted catalog")?;
+    Ok(())
+}
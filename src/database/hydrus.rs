@@ -0,0 +1,183 @@
+use std::collections::BTreeSet;
+use std::path::Path;
+use anyhow::{Result, Context, bail};
+use rusqlite::{Connection, OpenFlags, OptionalExtension, params};
+use tracing::info;
+use crate::database::repo::{CatalogReader, FilterSet};
+use crate::database::store::CatalogStore;
+
+/// `hash_type` of a tag archive keyed by SHA-256, as Hydrus numbers them
+/// (0 MD5, 1 SHA-1, 2 SHA-256, 3 SHA-512).
+const SHA256: i64 = 2;
+
+/// Pairs tagged per transaction on import.
+const BATCH: usize = 10_000;
+
+/// A Hydrus Tag Archive: the SQLite file Hydrus imports and exports tags
+/// in, mapping file hashes to `namespace:subtag` tags.
+pub struct TagArchive {
+    conn: Connection,
+}
+
+impl TagArchive {
+    /// Opens an archive for reading. Only ones keyed by SHA-256 can be
+    /// matched against the catalog.
+    pub fn open(path: &Path) -> Result<Self> {
+        let conn = Connection::open_with_flags(path, OpenFlags::SQLITE_OPEN_READ_ONLY)
+            .with_context(|| format!("Failed to open {:?}", path))?;
+        let hash_type: Option<i64> = conn
+            .query_row("SELECT hash_type FROM hash_type", [], |row| row.get(0))
+            .optional()
+            .with_context(|| format!("{:?} is not a Hydrus tag archive", path))?;
+        match hash_type {
+            Some(SHA256) => Ok(TagArchive { conn }),
+            Some(other) => bail!("{:?} is keyed by hash type {}; only SHA-256 archives (type 2) can be matched", path, other),
+            None => bail!("{:?} doesn't say what its hashes are", path),
+        }
+    }
+
+    /// Creates an empty archive keyed by SHA-256; `path` must not exist.
+    pub fn create(path: &Path) -> Result<Self> {
+        if path.exists() {
+            bail!("{:?} already exists", path);
+        }
+        let conn = Connection::open(path).with_context(|| format!("Failed to create {:?}", path))?;
+        conn.execute_batch(
+            "CREATE TABLE hash_type ( hash_type INTEGER );
+             CREATE TABLE hashes ( hash_id INTEGER PRIMARY KEY, hash BLOB_BYTES );
+             CREATE UNIQUE INDEX hashes_hash_index ON hashes ( hash );
+             CREATE TABLE mappings ( hash_id INTEGER, tag_id INTEGER, PRIMARY KEY ( hash_id, tag_id ) );
+             CREATE INDEX mappings_tag_id_index ON mappings ( tag_id );
+             CREATE TABLE namespaces ( namespace TEXT );
+             CREATE TABLE tags ( tag_id INTEGER PRIMARY KEY, tag TEXT );
+             CREATE UNIQUE INDEX tags_tag_index ON tags ( tag );",
+        )?;
+        conn.execute("INSERT INTO hash_type (hash_type) VALUES (?1)", params![SHA256])?;
+        Ok(TagArchive { conn })
+    }
+
+    /// The tags of the file with the hex SHA-256 `hash`.
+    pub fn tags(&self, hash: &str) -> Result<Vec<String>> {
+        let hash = hex::decode(hash).with_context(|| format!("'{}' is not a hex hash", hash))?;
+        let mut stmt = self.conn.prepare_cached(
+            "SELECT t.tag FROM hashes h JOIN mappings m ON m.hash_id = h.hash_id JOIN tags t ON t.tag_id = m.tag_id WHERE h.hash = ?1",
+        )?;
+        let tags = stmt.query_map(params![hash], |row| row.get(0))?.collect::<rusqlite::Result<_>>()?;
+        Ok(tags)
+    }
+}
+
+/// Tags every catalogued artifact with the tags `archive` has for its
+/// content, following aliases and implications. Returns the number of
+/// artifacts found in the archive and the number of tags added.
+pub fn import(store: &mut dyn CatalogStore, archive: &TagArchive) -> Result<(usize, usize)> {
+    let (mut matched, mut added) = (0, 0);
+    let mut batch = Vec::new();
+    for hash in store.known_hashes()? {
+        let tags = archive.tags(&hash)?;
+        if tags.is_empty() {
+            continue;
+        }
+        matched += 1;
+        batch.extend(tags.into_iter().map(|tag| (hash.clone(), tag)));
+        if batch.len() >= BATCH {
+            added += store.add_tags(&batch)?;
+            batch.clear();
+        }
+    }
+    added += store.add_tags(&batch)?;
+    info!("Matched {} artifacts in the tag archive and added {} tags", matched, added);
+    Ok((matched, added))
+}
+
+/// Writes the tags of the artifacts matching `filter` to a new archive at
+/// `path`, for Hydrus to import. Returns the number of artifacts written.
+pub fn export(reader: &CatalogReader, filter: &FilterSet, path: &Path) -> Result<usize> {
+    let mut archive = TagArchive::create(path)?;
+    let tx = archive.conn.transaction()?;
+    let mut namespaces = BTreeSet::new();
+    let mut count = 0;
+    for artifact in reader.find(filter) {
+        let artifact = artifact?;
+        if artifact.tags.is_empty() {
+            continue;
+        }
+        let hash = hex::decode(&artifact.hash_sha256)?;
+        tx.execute("INSERT OR IGNORE INTO hashes (hash) VALUES (?1)", params![hash])?;
+        let hash_id: i64 = tx.query_row("SELECT hash_id FROM hashes WHERE hash = ?1", params![hash], |row| row.get(0))?;
+        for tag in &artifact.tags {
+            if let Some((namespace, _)) = tag.split_once(':') {
+                namespaces.insert(namespace.to_string());
+            }
+            tx.execute("INSERT OR IGNORE INTO tags (tag) VALUES (?1)", params![tag])?;
+            tx.execute(
+                "INSERT OR IGNORE INTO mappings (hash_id, tag_id) SELECT ?1, tag_id FROM tags WHERE tag = ?2",
+                params![hash_id, tag],
+            )?;
+        }
+        count += 1;
+    }
+    for namespace in namespaces {
+        tx.execute("INSERT INTO namespaces (namespace) VALUES (?1)", params![namespace])?;
+    }
+    tx.commit()?;
+    info!("Wrote the tags of {} artifacts to {:?}", count, path);
+    Ok(count)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::database::repo::{ArtifactRecord, TransactionManager};
+    use crate::utils::config::DatabaseConfig;
+
+    fn record(hash: &str, tags: &[&str]) -> ArtifactRecord {
+        ArtifactRecord {
+            hash_sha256: hash.to_string(),
+            original_path: format!("/media/{}", hash),
+            media_type: "image/png".to_string(),
+            size_bytes: Some(100),
+            mtime: None,
+            width: None,
+            height: None,
+            tags: tags.iter().map(|t| t.to_string()).collect(),
+            nsfw_score: None,
+            nsfw_model_version: None,
+            embeddings: Vec::new(),
+            metadata: None,
+            source: None,
+            timings: None,
+        }
+    }
+
+    #[test]
+    fn test_round_trip() -> Result<()> {
+        let dir = std::env::temp_dir().join(format!("deep_archive_hydrus_{}", std::process::id()));
+        std::fs::create_dir_all(&dir)?;
+        let (from, to, archive) = (dir.join("from.db"), dir.join("to.db"), dir.join("tags.db"));
+        let config = DatabaseConfig::default();
+
+        let mut tm = TransactionManager::new(&from.to_string_lossy(), &config)?;
+        tm.add(record("aa", &["creator:someone", "blue"]))?;
+        tm.add(record("bb", &[]))?;
+        tm.flush()?;
+        let reader = CatalogReader::open(&from.to_string_lossy(), &config)?;
+        assert_eq!(export(&reader, &FilterSet::new(), &archive)?, 1);
+        assert!(export(&reader, &FilterSet::new(), &archive).is_err());
+
+        let mut tm = TransactionManager::new(&to.to_string_lossy(), &config)?;
+        tm.add(record("aa", &["red"]))?;
+        tm.add(record("cc", &[]))?;
+        tm.flush()?;
+        tm.add_implication("blue", "colour")?;
+        assert_eq!(import(&mut tm, &TagArchive::open(&archive)?)?, (1, 2));
+
+        let reader = CatalogReader::open(&to.to_string_lossy(), &config)?;
+        let mut tags = reader.find(&FilterSet::new().hash("aa")).next().unwrap()?.tags;
+        tags.sort();
+        assert_eq!(tags, vec!["blue", "colour", "creator:someone", "red"]);
+        drop((reader, tm));
+        std::fs::remove_dir_all(&dir)?;
+        Ok(())
+    }
+}
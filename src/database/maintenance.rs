@@ -0,0 +1,172 @@
+use rusqlite::Connection;
+use anyhow::{Context, Result};
+use serde::Serialize;
+use tracing::warn;
+
+/// Problems listed per check; the count is always exact.
+const MAX_PROBLEMS: usize = 100;
+
+/// Outcome of one `db check` step.
+#[derive(Debug, Serialize)]
+pub struct CheckResult {
+    pub name: &'static str,
+    /// Total problems found.
+    pub count: u64,
+    /// Up to `MAX_PROBLEMS` of them, human readable.
+    pub problems: Vec<String>,
+}
+
+impl CheckResult {
+    fn new(name: &'static str) -> Self {
+        Self { name, count: 0, problems: Vec::new() }
+    }
+
+    fn push(&mut self, problem: String) {
+        self.count += 1;
+        if self.problems.len() < MAX_PROBLEMS {
+            self.problems.push(problem);
+        }
+    }
+
+    pub fn ok(&self) -> bool {
+        self.count == 0
+    }
+}
+
+/// Cross-table invariants the schema can't enforce, as (description, query
+/// returning one identifying text column per offending row).
+const ORPHAN_QUERIES: &[(&str, &str)] = &[
+    (
+        "artifact has no live path",
+        "SELECT a.hash_sha256 FROM artifacts a
+         WHERE a.deleted_at IS NULL
+           AND NOT EXISTS (SELECT 1 FROM artifact_paths p WHERE p.artifact_id = a.id AND p.deleted_at IS NULL)",
+    ),
+    (
+        "original_path is not among the artifact's paths",
+        "SELECT a.hash_sha256 || ' ' || a.original_path FROM artifacts a
+         WHERE EXISTS (SELECT 1 FROM artifact_paths p WHERE p.artifact_id = a.id)
+           AND NOT EXISTS (SELECT 1 FROM artifact_paths p WHERE p.artifact_id = a.id AND p.path = a.original_path)",
+    ),
+    (
+        "tags_text is out of sync with artifact_tags",
+        "SELECT a.hash_sha256 FROM artifacts a
+         WHERE a.tags_text != COALESCE((
+            SELECT group_concat(CASE WHEN t.namespace = '' THEN t.name ELSE t.namespace || ':' || t.name END, ' ')
+            FROM artifact_tags at JOIN tags t ON t.id = at.tag_id
+            WHERE at.artifact_id = a.id), '')",
+    ),
+    (
+        "embedding size does not match its dimension",
+        "SELECT a.hash_sha256 || ' ' || e.model_name FROM embeddings e JOIN artifacts a ON a.id = e.artifact_id
+         WHERE length(e.vector) != e.dim * 4",
+    ),
+];
+
+/// Runs every consistency check against a writable connection (the FTS5
+/// integrity check is issued as an INSERT, though it changes nothing).
+pub fn check(conn: &Connection) -> Result<Vec<CheckResult>> {
+    Ok(vec![integrity(conn)?, foreign_keys(conn)?, fts(conn), orphans(conn)?])
+}
+
+fn integrity(conn: &Connection) -> Result<CheckResult> {
+    let mut result = CheckResult::new("integrity");
+    let mut stmt = conn.prepare("PRAGMA integrity_check")?;
+    let rows = stmt.query_map([], |row| row.get::<_, String>(0))?;
+    for row in rows {
+        let row = row?;
+        if row != "ok" {
+            result.push(row);
+        }
+    }
+    Ok(result)
+}
+
+fn foreign_keys(conn: &Connection) -> Result<CheckResult> {
+    let mut result = CheckResult::new("foreign_keys");
+    let mut stmt = conn.prepare("PRAGMA foreign_key_check")?;
+    let rows = stmt.query_map([], |row| {
+        Ok((row.get::<_, String>(0)?, row.get::<_, Option<i64>>(1)?, row.get::<_, String>(2)?))
+    })?;
+    for row in rows {
+        let (table, rowid, parent) = row?;
+        let rowid = rowid.map_or_else(|| "-".to_string(), |r| r.to_string());
+        result.push(format!("{} row {} references a missing {} row", table, rowid, parent));
+    }
+    Ok(result)
+}
+
+/// With `rank = 1` FTS5 also compares the index against the `artifacts`
+/// content table, and reports any difference as an error.
+fn fts(conn: &Connection) -> CheckResult {
+    let mut result = CheckResult::new("fts");
+    if let Err(e) = conn.execute("INSERT INTO search_index(search_index, rank) VALUES ('integrity-check', 1)", []) {
+        result.push(format!("{}; run `reindex-fts` to rebuild the index", e));
+    }
+    result
+}
+
+fn orphans(conn: &Connection) -> Result<CheckResult> {
+    let mut result = CheckResult::new("orphans");
+    for (description, sql) in ORPHAN_QUERIES {
+        let mut stmt = conn.prepare(sql)?;
+        let rows = stmt.query_map([], |row| row.get::<_, String>(0))?;
+        for row in rows {
+            result.push(format!("{}: {}", description, row?));
+        }
+    }
+    Ok(result)
+}
+
+/// Compacts the catalog after large deletes or merges: merges the FTS
+/// segments, refreshes planner statistics, rebuilds the file without free
+/// pages (unless `vacuum` is false) and truncates the WAL. Returns the file
+/// size in bytes before and after.
+pub fn optimize(conn: &Connection, vacuum: bool) -> Result<(u64, u64)> {
+    let before = file_size(conn)?;
+    conn.execute("INSERT INTO search_index(search_index) VALUES ('optimize')", [])
+        .context("Failed to optimize search index")?;
+    conn.execute_batch("ANALYZE").context("Failed to analyze")?;
+    if vacuum {
+        // Needs free disk space for a full copy and blocks every other connection.
+        conn.execute_batch("VACUUM").context("Failed to vacuum")?;
+    }
+    // Returns (busy, log pages, checkpointed pages); busy means a reader held it back.
+    let busy: i64 = conn.query_row("PRAGMA wal_checkpoint(TRUNCATE)", [], |row| row.get(0))?;
+    if busy != 0 {
+        warn!("WAL checkpoint incomplete; another connection is reading the catalog");
+    }
+    Ok((before, file_size(conn)?))
+}
+
+fn file_size(conn: &Connection) -> Result<u64> {
+    let pages: u64 = conn.query_row("PRAGMA page_count", [], |row| row.get(0))?;
+    let page_size: u64 = conn.query_row("PRAGMA page_size", [], |row| row.get(0))?;
+    Ok(pages * page_size)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::database::schema;
+
+    #[test]
+    fn test_check_finds_orphans() {
+        let mut conn = Connection::open_in_memory().unwrap();
+        schema::initialize(&mut conn).unwrap();
+        assert!(check(&conn).unwrap().iter().all(CheckResult::ok));
+
+        // Corruption of the kind a manual edit with foreign keys off leaves behind.
+        conn.execute_batch(
+            "PRAGMA foreign_keys = OFF;
+             INSERT INTO artifacts (id, hash_sha256, original_path, media_type) VALUES (1, 'abc', '/a', 'text/plain');
+             INSERT INTO artifact_tags (artifact_id, tag_id) VALUES (1, 42);",
+        )
+        .unwrap();
+        let results = check(&conn).unwrap();
+        let failed: Vec<&str> = results.iter().filter(|r| !r.ok()).map(|r| r.name).collect();
+        assert_eq!(failed, ["foreign_keys", "orphans"]);
+        let orphans = results.iter().find(|r| r.name == "orphans").unwrap();
+        assert_eq!(orphans.problems, ["artifact has no live path: abc"]);
+    }
+}
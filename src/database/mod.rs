@@ -1,2 +1,11 @@
 pub mod schema;
+pub mod encryption;
+pub mod hydrus;
+pub mod maintenance;
 pub mod repo;
+pub mod store;
+pub mod tags;
+#[cfg(feature = "parquet")]
+pub mod parquet;
+#[cfg(feature = "postgres")]
+pub mod postgres;
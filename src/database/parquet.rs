@@ -0,0 +1,210 @@
+use std::fs::File;
+use std::path::Path;
+use std::sync::Arc;
+use anyhow::{Result, Context};
+use arrow_array::builder::{Float32Builder, Int64Builder, StringBuilder, UInt32Builder, UInt64Builder};
+use arrow_array::{ArrayRef, RecordBatch};
+use arrow_schema::{DataType, Field, Schema, SchemaRef};
+use parquet::arrow::ArrowWriter;
+use parquet::basic::{Compression, ZstdLevel};
+use parquet::file::properties::WriterProperties;
+use crate::database::repo::{Artifact, CatalogReader, FilterSet};
+use crate::database::tags::Tag;
+
+/// Rows buffered per record batch (and thus per row group) before writing.
+const BATCH_SIZE: usize = 64 * 1024;
+
+/// Writes `artifacts.parquet`, `tags.parquet` and `scores.parquet` into `dir`,
+/// joinable on `artifact_id`. Artifacts are streamed, so memory stays bounded.
+pub fn write_parquet(reader: &CatalogReader, filter: &FilterSet, dir: &Path) -> Result<u64> {
+    std::fs::create_dir_all(dir).with_context(|| format!("Failed to create {:?}", dir))?;
+
+    let mut artifacts = Table::create(dir, "artifacts", ArtifactColumns::schema())?;
+    let mut tags = Table::create(dir, "tags", TagColumns::schema())?;
+    let mut scores = Table::create(dir, "scores", ScoreColumns::schema())?;
+
+    let mut artifact_cols = ArtifactColumns::default();
+    let mut tag_cols = TagColumns::default();
+    let mut score_cols = ScoreColumns::default();
+    let mut count = 0;
+
+    for artifact in reader.find(filter) {
+        let artifact = artifact?;
+        tag_cols.push(&artifact);
+        score_cols.push(&artifact);
+        artifact_cols.push(&artifact)?;
+        count += 1;
+
+        if artifact_cols.len >= BATCH_SIZE {
+            artifacts.write(artifact_cols.finish())?;
+            tags.write(tag_cols.finish())?;
+            scores.write(score_cols.finish())?;
+        }
+    }
+
+    artifacts.write(artifact_cols.finish())?;
+    tags.write(tag_cols.finish())?;
+    scores.write(score_cols.finish())?;
+
+    artifacts.close()?;
+    tags.close()?;
+    scores.close()?;
+    Ok(count)
+}
+
+struct Table {
+    schema: SchemaRef,
+    writer: ArrowWriter<File>,
+}
+
+impl Table {
+    fn create(dir: &Path, name: &str, schema: SchemaRef) -> Result<Self> {
+        let path = dir.join(format!("{}.parquet", name));
+        let file = File::create(&path).with_context(|| format!("Failed to create {:?}", path))?;
+        let props = WriterProperties::builder()
+            .set_compression(Compression::ZSTD(ZstdLevel::default()))
+            .build();
+        let writer = ArrowWriter::try_new(file, schema.clone(), Some(props))?;
+        Ok(Self { schema, writer })
+    }
+
+    fn write(&mut self, columns: Vec<ArrayRef>) -> Result<()> {
+        let batch = RecordBatch::try_new(self.schema.clone(), columns)?;
+        if batch.num_rows() > 0 {
+            self.writer.write(&batch)?;
+        }
+        Ok(())
+    }
+
+    fn close(self) -> Result<()> {
+        self.writer.close()?;
+        Ok(())
+    }
+}
+
+#[derive(Default)]
+struct ArtifactColumns {
+    len: usize,
+    id: Int64Builder,
+    hash_sha256: StringBuilder,
+    original_path: StringBuilder,
+    media_type: StringBuilder,
+    size_bytes: UInt64Builder,
+    mtime: Int64Builder,
+    width: UInt32Builder,
+    height: UInt32Builder,
+    metadata: StringBuilder,
+}
+
+impl ArtifactColumns {
+    fn schema() -> SchemaRef {
+        Arc::new(Schema::new(vec![
+            Field::new("artifact_id", DataType::Int64, false),
+            Field::new("hash_sha256", DataType::Utf8, false),
+            Field::new("original_path", DataType::Utf8, false),
+            Field::new("media_type", DataType::Utf8, false),
+            Field::new("size_bytes", DataType::UInt64, true),
+            Field::new("mtime", DataType::Int64, true),
+            Field::new("width", DataType::UInt32, true),
+            Field::new("height", DataType::UInt32, true),
+            // Raw JSON; DuckDB/Polars can parse it on demand.
+            Field::new("metadata", DataType::Utf8, true),
+        ]))
+    }
+
+    fn push(&mut self, a: &Artifact) -> Result<()> {
+        self.id.append_value(a.id);
+        self.hash_sha256.append_value(&a.hash_sha256);
+        self.original_path.append_value(&a.original_path);
+        self.media_type.append_value(&a.media_type);
+        self.size_bytes.append_option(a.size_bytes);
+        self.mtime.append_option(a.mtime);
+        self.width.append_option(a.width);
+        self.height.append_option(a.height);
+        self.metadata.append_option(a.metadata.as_ref().map(serde_json::to_string).transpose()?);
+        self.len += 1;
+        Ok(())
+    }
+
+    fn finish(&mut self) -> Vec<ArrayRef> {
+        self.len = 0;
+        vec![
+            Arc::new(self.id.finish()),
+            Arc::new(self.hash_sha256.finish()),
+            Arc::new(self.original_path.finish()),
+            Arc::new(self.media_type.finish()),
+            Arc::new(self.size_bytes.finish()),
+            Arc::new(self.mtime.finish()),
+            Arc::new(self.width.finish()),
+            Arc::new(self.height.finish()),
+            Arc::new(self.metadata.finish()),
+        ]
+    }
+}
+
+#[derive(Default)]
+struct TagColumns {
+    artifact_id: Int64Builder,
+    namespace: StringBuilder,
+    name: StringBuilder,
+}
+
+impl TagColumns {
+    fn schema() -> SchemaRef {
+        Arc::new(Schema::new(vec![
+            Field::new("artifact_id", DataType::Int64, false),
+            Field::new("namespace", DataType::Utf8, false),
+            Field::new("name", DataType::Utf8, false),
+        ]))
+    }
+
+    fn push(&mut self, a: &Artifact) {
+        for label in &a.tags {
+            let tag = Tag::parse(label);
+            self.artifact_id.append_value(a.id);
+            self.namespace.append_value(tag.namespace);
+            self.name.append_value(tag.name);
+        }
+    }
+
+    fn finish(&mut self) -> Vec<ArrayRef> {
+        vec![
+            Arc::new(self.artifact_id.finish()),
+            Arc::new(self.namespace.finish()),
+            Arc::new(self.name.finish()),
+        ]
+    }
+}
+
+#[derive(Default)]
+struct ScoreColumns {
+    artifact_id: Int64Builder,
+    nsfw_score: Float32Builder,
+    model_version: StringBuilder,
+}
+
+impl ScoreColumns {
+    fn schema() -> SchemaRef {
+        Arc::new(Schema::new(vec![
+            Field::new("artifact_id", DataType::Int64, false),
+            Field::new("nsfw_score", DataType::Float32, false),
+            Field::new("model_version", DataType::Utf8, true),
+        ]))
+    }
+
+    fn push(&mut self, a: &Artifact) {
+        if let Some(score) = a.nsfw_score {
+            self.artifact_id.append_value(a.id);
+            self.nsfw_score.append_value(score);
+            self.model_version.append_option(a.nsfw_model_version.as_deref());
+        }
+    }
+
+    fn finish(&mut self) -> Vec<ArrayRef> {
+        vec![
+            Arc::new(self.artifact_id.finish()),
+            Arc::new(self.nsfw_score.finish()),
+            Arc::new(self.model_version.finish()),
+        ]
+    }
+}
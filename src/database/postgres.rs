@@ -0,0 +1,1017 @@
+use std::collections::{HashMap, HashSet};
+use std::time::{Duration, Instant};
+use anyhow::{Result, Context, bail};
+use postgres::{Client, NoTls, Transaction};
+use crate::database::repo::{parse_partial, parse_summary, supersedes, ArtifactRecord, CommittedFile, IngestError, PartialResult, Run, TagRules};
+use crate::ingest::summary::RunSummary;
+use crate::database::store::{self, CatalogStore, LeaseHolder, LeaseKind, LEASE_RENEW, LEASE_TTL};
+use crate::database::tags::{self, Tag, IMPLIED_CLOSURE};
+use crate::ingest::source::Source;
+use crate::utils::config::DatabaseConfig;
+use tracing::{error, warn};
+
+/// PostgreSQL counterpart of `schema::SCHEMA` plus all SQLite migrations, so
+/// several ingest machines can write to one central catalog. Generated columns
+/// need PostgreSQL 12 or newer.
+const SCHEMA: &str = "
+    CREATE TABLE IF NOT EXISTS runs (
+        id BIGSERIAL PRIMARY KEY,
+        started_at BIGINT NOT NULL,
+        finished_at BIGINT,
+        status TEXT NOT NULL DEFAULT 'running',
+        input_roots TEXT NOT NULL,
+        options TEXT,
+        files_seen BIGINT NOT NULL DEFAULT 0,
+        artifacts_added BIGINT NOT NULL DEFAULT 0,
+        errors BIGINT NOT NULL DEFAULT 0
+    );
+
+    CREATE TABLE IF NOT EXISTS artifacts (
+        id BIGSERIAL PRIMARY KEY,
+        hash_sha256 TEXT UNIQUE NOT NULL,
+        original_path TEXT NOT NULL,
+        media_type TEXT NOT NULL,
+        width INTEGER,
+        height INTEGER,
+        size_bytes BIGINT,
+        mtime BIGINT,
+        run_id BIGINT REFERENCES runs(id),
+        metadata JSONB,
+        duration DOUBLE PRECISION GENERATED ALWAYS AS ((metadata->>'duration')::double precision) STORED,
+        camera_model TEXT GENERATED ALWAYS AS (metadata->>'camera_model') STORED,
+        gps_lat DOUBLE PRECISION GENERATED ALWAYS AS ((metadata->>'gps_lat')::double precision) STORED,
+        gps_lon DOUBLE PRECISION GENERATED ALWAYS AS ((metadata->>'gps_lon')::double precision) STORED
+    );
+    CREATE INDEX IF NOT EXISTS idx_artifacts_run ON artifacts(run_id);
+    CREATE INDEX IF NOT EXISTS idx_artifacts_duration ON artifacts(duration);
+    CREATE INDEX IF NOT EXISTS idx_artifacts_camera_model ON artifacts(camera_model);
+    CREATE INDEX IF NOT EXISTS idx_artifacts_gps ON artifacts(gps_lat, gps_lon);
+
+    CREATE TABLE IF NOT EXISTS artifact_paths (
+        artifact_id BIGINT NOT NULL REFERENCES artifacts(id),
+        path TEXT NOT NULL,
+        first_seen BIGINT NOT NULL,
+        last_seen BIGINT NOT NULL,
+        run_id BIGINT REFERENCES runs(id),
+        PRIMARY KEY(artifact_id, path)
+    );
+    CREATE INDEX IF NOT EXISTS idx_artifact_paths_path ON artifact_paths(path);
+    CREATE INDEX IF NOT EXISTS idx_artifact_paths_run ON artifact_paths(run_id);
+
+    CREATE TABLE IF NOT EXISTS tags (
+        id BIGSERIAL PRIMARY KEY,
+        namespace TEXT NOT NULL DEFAULT '',
+        name TEXT NOT NULL,
+        UNIQUE(namespace, name)
+    );
+    CREATE INDEX IF NOT EXISTS idx_tags_name ON tags(name);
+
+    CREATE TABLE IF NOT EXISTS artifact_tags (
+        artifact_id BIGINT NOT NULL REFERENCES artifacts(id),
+        tag_id BIGINT NOT NULL REFERENCES tags(id),
+        PRIMARY KEY(artifact_id, tag_id)
+    );
+
+    CREATE TABLE IF NOT EXISTS safety_scores (
+        artifact_id BIGINT PRIMARY KEY REFERENCES artifacts(id),
+        nsfw_score REAL NOT NULL
+    );
+
+    CREATE TABLE IF NOT EXISTS embeddings (
+        artifact_id BIGINT NOT NULL REFERENCES artifacts(id),
+        model_name TEXT NOT NULL,
+        model_version TEXT NOT NULL,
+        dim INTEGER NOT NULL,
+        vector BYTEA NOT NULL,
+        created_at BIGINT NOT NULL,
+        PRIMARY KEY(artifact_id, model_name)
+    );
+    CREATE INDEX IF NOT EXISTS idx_embeddings_model ON embeddings(model_name, model_version);
+
+    CREATE TABLE IF NOT EXISTS ingest_errors (
+        id BIGSERIAL PRIMARY KEY,
+        run_id BIGINT REFERENCES runs(id),
+        path TEXT NOT NULL,
+        stage TEXT NOT NULL,
+        error TEXT NOT NULL,
+        created_at BIGINT NOT NULL,
+        resolved_at BIGINT
+    );
+    CREATE INDEX IF NOT EXISTS idx_ingest_errors_path ON ingest_errors(path);
+    CREATE INDEX IF NOT EXISTS idx_ingest_errors_run ON ingest_errors(run_id);
+
+    ALTER TABLE artifacts ADD COLUMN IF NOT EXISTS deleted_at BIGINT;
+    ALTER TABLE artifacts ADD COLUMN IF NOT EXISTS deleted_reason TEXT;
+    ALTER TABLE artifact_paths ADD COLUMN IF NOT EXISTS deleted_at BIGINT;
+    CREATE INDEX IF NOT EXISTS idx_artifacts_deleted ON artifacts(deleted_at);
+
+    CREATE TABLE IF NOT EXISTS sources (
+        id BIGSERIAL PRIMARY KEY,
+        host TEXT NOT NULL,
+        fs_uuid TEXT,
+        label TEXT,
+        mount_point TEXT,
+        first_seen BIGINT NOT NULL,
+        last_seen BIGINT NOT NULL
+    );
+    CREATE UNIQUE INDEX IF NOT EXISTS idx_sources_uuid ON sources(fs_uuid) WHERE fs_uuid IS NOT NULL;
+    ALTER TABLE artifact_paths ADD COLUMN IF NOT EXISTS source_id BIGINT REFERENCES sources(id);
+    CREATE INDEX IF NOT EXISTS idx_artifact_paths_source ON artifact_paths(source_id);
+
+    CREATE TABLE IF NOT EXISTS fixity_checks (
+        id BIGSERIAL PRIMARY KEY,
+        artifact_id BIGINT NOT NULL REFERENCES artifacts(id),
+        path TEXT NOT NULL,
+        checked_at BIGINT NOT NULL,
+        algo TEXT NOT NULL,
+        result TEXT NOT NULL,
+        detail TEXT
+    );
+    CREATE INDEX IF NOT EXISTS idx_fixity_checks_artifact ON fixity_checks(artifact_id, checked_at);
+
+    CREATE TABLE IF NOT EXISTS tag_aliases (
+        namespace TEXT NOT NULL,
+        name TEXT NOT NULL,
+        tag_id BIGINT NOT NULL REFERENCES tags(id),
+        PRIMARY KEY (namespace, name)
+    );
+    CREATE TABLE IF NOT EXISTS tag_implications (
+        tag_id BIGINT NOT NULL REFERENCES tags(id),
+        implied_tag_id BIGINT NOT NULL REFERENCES tags(id),
+        PRIMARY KEY (tag_id, implied_tag_id)
+    );
+
+    CREATE TABLE IF NOT EXISTS relationships (
+        artifact_id BIGINT NOT NULL REFERENCES artifacts(id),
+        kind TEXT NOT NULL,
+        related_id BIGINT NOT NULL REFERENCES artifacts(id),
+        created_at BIGINT NOT NULL,
+        PRIMARY KEY (artifact_id, kind, related_id)
+    );
+    CREATE INDEX IF NOT EXISTS idx_relationships_related ON relationships(related_id);
+
+    ALTER TABLE safety_scores ADD COLUMN IF NOT EXISTS model_version TEXT;
+
+    CREATE TABLE IF NOT EXISTS leases (
+        id BIGSERIAL PRIMARY KEY,
+        kind TEXT NOT NULL,
+        command TEXT NOT NULL,
+        host TEXT NOT NULL,
+        pid BIGINT NOT NULL,
+        acquired_at BIGINT NOT NULL,
+        expires_at BIGINT NOT NULL
+    );
+
+    CREATE TABLE IF NOT EXISTS archive_volumes (
+        id BIGSERIAL PRIMARY KEY,
+        name TEXT NOT NULL,
+        format TEXT NOT NULL,
+        created_at BIGINT NOT NULL,
+        capacity_bytes BIGINT,
+        size_bytes BIGINT NOT NULL
+    );
+    CREATE TABLE IF NOT EXISTS archive_members (
+        volume_id BIGINT NOT NULL REFERENCES archive_volumes(id),
+        path TEXT NOT NULL,
+        hash_sha256 TEXT NOT NULL,
+        size_bytes BIGINT NOT NULL,
+        artifact_id BIGINT REFERENCES artifacts(id),
+        PRIMARY KEY(volume_id, path)
+    );
+    CREATE INDEX IF NOT EXISTS idx_archive_members_hash ON archive_members(hash_sha256);
+    CREATE INDEX IF NOT EXISTS idx_archive_members_artifact ON archive_members(artifact_id);
+    ALTER TABLE archive_volumes ADD COLUMN IF NOT EXISTS label TEXT;
+    ALTER TABLE archive_volumes ADD COLUMN IF NOT EXISTS sha256 TEXT;
+    CREATE UNIQUE INDEX IF NOT EXISTS idx_archive_volumes_label ON archive_volumes(label);
+    CREATE TABLE IF NOT EXISTS volume_discs (
+        id BIGSERIAL PRIMARY KEY,
+        volume_id BIGINT NOT NULL REFERENCES archive_volumes(id),
+        burned_at BIGINT NOT NULL,
+        device TEXT NOT NULL,
+        drive TEXT,
+        media TEXT,
+        verified BOOLEAN
+    );
+    CREATE INDEX IF NOT EXISTS idx_volume_discs_volume ON volume_discs(volume_id);
+    CREATE TABLE IF NOT EXISTS volume_uploads (
+        id BIGSERIAL PRIMARY KEY,
+        volume_id BIGINT NOT NULL REFERENCES archive_volumes(id),
+        remote TEXT NOT NULL,
+        bucket TEXT NOT NULL,
+        object_key TEXT NOT NULL,
+        layout TEXT NOT NULL,
+        object_id TEXT,
+        storage_class TEXT,
+        size_bytes BIGINT NOT NULL,
+        uploaded_at BIGINT NOT NULL
+    );
+    CREATE INDEX IF NOT EXISTS idx_volume_uploads_volume ON volume_uploads(volume_id);
+    ALTER TABLE archive_volumes ADD COLUMN IF NOT EXISTS barcode TEXT;
+    ALTER TABLE archive_members ADD COLUMN IF NOT EXISTS offset_bytes BIGINT;
+    ALTER TABLE runs ADD COLUMN IF NOT EXISTS summary TEXT;
+    ALTER TABLE ingest_errors ADD COLUMN IF NOT EXISTS partial TEXT;
+    ALTER TABLE ingest_errors ADD COLUMN IF NOT EXISTS stderr TEXT;
+    CREATE TABLE IF NOT EXISTS run_files (
+        run_id BIGINT NOT NULL REFERENCES runs(id),
+        path TEXT NOT NULL,
+        size_bytes BIGINT,
+        mtime BIGINT,
+        PRIMARY KEY(run_id, path)
+    );
+    CREATE TABLE IF NOT EXISTS file_timings (
+        run_id BIGINT NOT NULL REFERENCES runs(id),
+        path TEXT NOT NULL,
+        hash_sha256 TEXT NOT NULL,
+        size_bytes BIGINT,
+        hash_ms BIGINT NOT NULL,
+        decode_ms BIGINT NOT NULL,
+        inference_ms BIGINT NOT NULL,
+        total_ms BIGINT NOT NULL,
+        PRIMARY KEY(run_id, path)
+    );
+    CREATE INDEX IF NOT EXISTS idx_file_timings_total ON file_timings(total_ms);
+    ALTER TABLE leases ADD COLUMN IF NOT EXISTS run_id BIGINT;
+";
+
+/// Id of the tag `($1, $2)` stands for, following aliases and creating it if needed.
+/// The no-op update makes RETURNING yield the id of an existing tag too.
+const TAG_ID: &str = "
+    WITH aliased AS (SELECT tag_id AS id FROM tag_aliases WHERE namespace = $1 AND name = $2),
+    created AS (
+        INSERT INTO tags (namespace, name) SELECT $1, $2 WHERE NOT EXISTS (SELECT 1 FROM aliased)
+        ON CONFLICT (namespace, name) DO UPDATE SET name = EXCLUDED.name
+        RETURNING id
+    )
+    SELECT id FROM aliased UNION ALL SELECT id FROM created";
+
+/// Like `TAG_ID`, but never creates the tag.
+const FIND_TAG_ID: &str = "
+    SELECT tag_id FROM tag_aliases WHERE namespace = $1 AND name = $2
+    UNION ALL
+    SELECT id FROM tags WHERE namespace = $1 AND name = $2
+    LIMIT 1";
+
+pub struct PgStore {
+    client: Client,
+    buffer: Vec<ArtifactRecord>,
+    buffer_limit: usize,
+    flush_interval: Option<Duration>,
+    last_flush: Instant,
+    lease: Option<i64>,
+    lease_renewed: Instant,
+    run_id: Option<i64>,
+    files_seen: u64,
+}
+
+impl PgStore {
+    pub fn connect(url: &str, config: &DatabaseConfig) -> Result<Self> {
+        let mut client = Client::connect(url, NoTls)
+            .with_context(|| format!("Failed to connect to {}", store::redact(url)))?;
+        // Serialize schema setup between machines starting at the same time.
+        let mut tx = client.transaction()?;
+        tx.batch_execute(
+            "SET LOCAL client_min_messages = warning;
+             SELECT pg_advisory_xact_lock(hashtext('deep-archive schema'));",
+        )?;
+        tx.batch_execute(SCHEMA).context("Failed to initialize schema")?;
+        tx.commit()?;
+        Ok(Self {
+            client,
+            buffer: Vec::new(),
+            buffer_limit: config.buffer_size.max(1),
+            flush_interval: config.flush_interval(),
+            last_flush: Instant::now(),
+            lease: None,
+            lease_renewed: Instant::now(),
+            run_id: None,
+            files_seen: 0,
+        })
+    }
+
+    fn renew_lease(&mut self) -> Result<()> {
+        let Some(id) = self.lease else {
+            return Ok(());
+        };
+        if self.lease_renewed.elapsed() < LEASE_RENEW {
+            return Ok(());
+        }
+        let expires_at = chrono::Utc::now().timestamp() + LEASE_TTL.as_secs() as i64;
+        if self.client.execute("UPDATE leases SET expires_at = $2 WHERE id = $1", &[&id, &expires_at])? == 0 {
+            warn!("Lease {} expired before it was renewed; other processes may have taken over the catalog", id);
+        }
+        self.lease_renewed = Instant::now();
+        Ok(())
+    }
+
+    /// Records in this process's lease that it writes `run_id`, failing if
+    /// a live lease of another process already does.
+    fn claim_run(&mut self, run_id: i64) -> Result<()> {
+        let now = chrono::Utc::now().timestamp();
+        let mut tx = self.client.transaction()?;
+        tx.batch_execute("LOCK TABLE leases IN SHARE ROW EXCLUSIVE MODE")?;
+        let holder = tx.query_opt(
+            "SELECT command, kind, host, pid, acquired_at FROM leases
+             WHERE run_id = $1 AND id IS DISTINCT FROM $2 AND expires_at >= $3 ORDER BY id LIMIT 1",
+            &[&run_id, &self.lease, &now],
+        )?;
+        if let Some(row) = holder {
+            return Err(LeaseHolder {
+                command: row.get(0),
+                kind: row.get(1),
+                host: row.get(2),
+                pid: row.get(3),
+                acquired_at: row.get(4),
+            }
+            .writing(run_id));
+        }
+        tx.execute("UPDATE leases SET run_id = $1 WHERE id = $2", &[&run_id, &self.lease])?;
+        tx.commit()?;
+        Ok(())
+    }
+
+    fn release_lease(&mut self) -> Result<()> {
+        if let Some(id) = self.lease.take() {
+            self.client.execute("DELETE FROM leases WHERE id = $1", &[&id])?;
+        }
+        Ok(())
+    }
+}
+
+/// Adds every implied tag the catalog is missing, after a rule changed.
+fn apply_implications(tx: &mut Transaction) -> Result<u64> {
+    Ok(tx.execute(
+        &format!(
+            "WITH RECURSIVE {}
+             INSERT INTO artifact_tags (artifact_id, tag_id)
+             SELECT at.artifact_id, c.implied_id FROM artifact_tags at JOIN closure c ON c.tag_id = at.tag_id
+             ON CONFLICT DO NOTHING",
+            IMPLIED_CLOSURE
+        ),
+        &[],
+    )?)
+}
+
+/// Last resort for records still buffered when the store goes away early,
+/// e.g. after an error; the normal path is `finish_run`.
+impl Drop for PgStore {
+    fn drop(&mut self) {
+        if !self.buffer.is_empty() {
+            if let Err(e) = self.flush() {
+                error!("Failed to flush {} buffered records: {:#}", self.buffer.len(), e);
+            }
+        }
+        if let Err(e) = self.release_lease() {
+            warn!("Failed to release catalog lease, it expires in {:?}: {:#}", LEASE_TTL, e);
+        }
+    }
+}
+
+impl CatalogStore for PgStore {
+    fn acquire_lease(&mut self, kind: LeaseKind) -> Result<()> {
+        if self.lease.is_some() {
+            return Ok(());
+        }
+        let (command, host, pid) = store::lease_owner();
+        let now = chrono::Utc::now().timestamp();
+        let mut tx = self.client.transaction()?;
+        // Serializes acquisitions between machines; readers of the table aren't blocked.
+        tx.batch_execute("LOCK TABLE leases IN SHARE ROW EXCLUSIVE MODE")?;
+        tx.execute("DELETE FROM leases WHERE expires_at < $1", &[&now])?;
+        let holder = tx.query_opt(
+            "SELECT command, kind, host, pid, acquired_at FROM leases
+             WHERE $1 = 'exclusive' OR kind = 'exclusive' ORDER BY id LIMIT 1",
+            &[&kind.as_str()],
+        )?;
+        if let Some(row) = holder {
+            return Err(LeaseHolder {
+                command: row.get(0),
+                kind: row.get(1),
+                host: row.get(2),
+                pid: row.get(3),
+                acquired_at: row.get(4),
+            }
+            .into_error());
+        }
+        let row = tx.query_one(
+            "INSERT INTO leases (kind, command, host, pid, acquired_at, expires_at)
+             VALUES ($1, $2, $3, $4, $5, $6) RETURNING id",
+            &[&kind.as_str(), &command, &host, &pid, &now, &(now + LEASE_TTL.as_secs() as i64)],
+        )?;
+        tx.commit()?;
+        self.lease = Some(row.get(0));
+        self.lease_renewed = Instant::now();
+        Ok(())
+    }
+
+    fn begin_run(&mut self, input_roots: &[String], options: &str) -> Result<i64> {
+        self.acquire_lease(LeaseKind::Shared)?;
+        let roots = serde_json::to_string(input_roots)?;
+        let row = self.client.query_one(
+            "INSERT INTO runs (started_at, input_roots, options) VALUES ($1, $2, $3) RETURNING id",
+            &[&chrono::Utc::now().timestamp(), &roots, &options],
+        ).context("Failed to record run")?;
+        let run_id: i64 = row.get(0);
+        self.claim_run(run_id)?;
+        self.run_id = Some(run_id);
+        self.files_seen = 0;
+        Ok(run_id)
+    }
+
+    fn finish_run(&mut self, status: &str, errors: u64, summary: Option<&RunSummary>) -> Result<()> {
+        self.flush()?;
+        let Some(run_id) = self.run_id.take() else {
+            return Ok(());
+        };
+
+        let summary = summary.map(serde_json::to_string).transpose()?;
+        self.client.execute(
+            "UPDATE runs SET
+                finished_at = $2,
+                status = $3,
+                files_seen = $4,
+                artifacts_added = (SELECT COUNT(*) FROM artifacts WHERE run_id = $1),
+                errors = $5,
+                summary = $6
+             WHERE id = $1",
+            &[&run_id, &chrono::Utc::now().timestamp(), &status, &(self.files_seen as i64), &(errors as i64), &summary],
+        ).context("Failed to finalize run")?;
+        if status == "completed" {
+            self.client.execute("DELETE FROM run_files WHERE run_id = $1", &[&run_id])?;
+        }
+        self.release_lease()
+    }
+
+    fn resume_run(&mut self, run_id: i64) -> Result<HashMap<String, CommittedFile>> {
+        self.acquire_lease(LeaseKind::Shared)?;
+        let status: Option<String> = self.client.query_opt("SELECT status FROM runs WHERE id = $1", &[&run_id])?.map(|row| row.get(0));
+        store::check_resumable(run_id, status.as_deref())?;
+        self.claim_run(run_id)?;
+        self.client.execute("UPDATE runs SET status = 'running', finished_at = NULL WHERE id = $1", &[&run_id])?;
+        let mut files = HashMap::new();
+        for row in self.client.query("SELECT path, size_bytes, mtime FROM run_files WHERE run_id = $1", &[&run_id])? {
+            let size: Option<i64> = row.get(1);
+            files.insert(row.get(0), CommittedFile { size_bytes: size.map(|s| s as u64), mtime: row.get(2) });
+        }
+        self.run_id = Some(run_id);
+        self.files_seen = files.len() as u64;
+        Ok(files)
+    }
+
+    fn rollback_run(&mut self, run_id: i64, purge: bool) -> Result<usize> {
+        let mut tx = self.client.transaction()?;
+
+        // Artifacts a later run found at paths of its own are kept, as that
+        // run's; only the rolled-back run's paths to them go.
+        tx.execute(
+            "UPDATE artifacts SET run_id = (
+                SELECT MAX(p.run_id) FROM artifact_paths p
+                WHERE p.artifact_id = artifacts.id AND p.run_id <> $1 AND p.deleted_at IS NULL)
+             WHERE run_id = $1 AND EXISTS (
+                SELECT 1 FROM artifact_paths p
+                WHERE p.artifact_id = artifacts.id AND p.run_id <> $1 AND p.deleted_at IS NULL)",
+            &[&run_id],
+        )?;
+        let removed = if purge {
+            for table in ["artifact_tags", "safety_scores", "embeddings", "fixity_checks", "relationships", "artifact_paths"] {
+                tx.execute(
+                    &format!("DELETE FROM {} WHERE artifact_id IN (SELECT id FROM artifacts WHERE run_id = $1)", table),
+                    &[&run_id],
+                )?;
+            }
+            tx.execute(
+                "DELETE FROM relationships WHERE related_id IN (SELECT id FROM artifacts WHERE run_id = $1)",
+                &[&run_id],
+            )?;
+            // Archived copies outlive the catalog entry; they stay findable by hash.
+            tx.execute(
+                "UPDATE archive_members SET artifact_id = NULL WHERE artifact_id IN (SELECT id FROM artifacts WHERE run_id = $1)",
+                &[&run_id],
+            )?;
+            tx.execute("DELETE FROM artifact_paths WHERE run_id = $1", &[&run_id])?;
+            tx.execute("DELETE FROM artifacts WHERE run_id = $1", &[&run_id])?
+        } else {
+            let now = chrono::Utc::now().timestamp();
+            let reason = format!("rollback of run {}", run_id);
+            tx.execute(
+                "UPDATE artifact_paths SET deleted_at = $2 WHERE run_id = $1 AND deleted_at IS NULL",
+                &[&run_id, &now],
+            )?;
+            tx.execute(
+                "UPDATE artifacts SET deleted_at = $2, deleted_reason = $3 WHERE run_id = $1 AND deleted_at IS NULL",
+                &[&run_id, &now, &reason],
+            )?
+        };
+
+        tx.execute(
+            "UPDATE artifacts SET original_path = (
+                SELECT p.path FROM artifact_paths p WHERE p.artifact_id = artifacts.id AND p.deleted_at IS NULL
+                ORDER BY p.last_seen DESC LIMIT 1)
+             WHERE NOT EXISTS (
+                SELECT 1 FROM artifact_paths p WHERE p.artifact_id = artifacts.id
+                AND p.path = artifacts.original_path AND p.deleted_at IS NULL)
+             AND EXISTS (SELECT 1 FROM artifact_paths p WHERE p.artifact_id = artifacts.id AND p.deleted_at IS NULL)",
+            &[],
+        )?;
+        tx.execute("UPDATE runs SET status = 'rolled_back' WHERE id = $1", &[&run_id])?;
+        tx.execute("DELETE FROM run_files WHERE run_id = $1", &[&run_id])?;
+
+        tx.commit()?;
+        Ok(removed as usize)
+    }
+
+    fn record_error(&mut self, path: &str, stage: &str, error: &str, stderr: Option<&str>, partial: Option<&PartialResult>) -> Result<()> {
+        let partial = partial.map(serde_json::to_string).transpose()?;
+        self.client.execute(
+            "INSERT INTO ingest_errors (run_id, path, stage, error, created_at, partial, stderr) VALUES ($1, $2, $3, $4, $5, $6, $7)",
+            &[&self.run_id, &path, &stage, &error, &chrono::Utc::now().timestamp(), &partial, &stderr],
+        ).context("Failed to record ingest error")?;
+        Ok(())
+    }
+
+    fn add(&mut self, record: ArtifactRecord) -> Result<()> {
+        self.buffer.push(record);
+        if self.buffer.len() >= self.buffer_limit {
+            self.flush()
+        } else {
+            self.flush_if_due()
+        }
+    }
+
+    fn flush_if_due(&mut self) -> Result<()> {
+        match self.flush_interval {
+            Some(interval) if self.last_flush.elapsed() >= interval => self.flush(),
+            _ => self.renew_lease(),
+        }
+    }
+
+    fn flush(&mut self) -> Result<()> {
+        self.last_flush = Instant::now();
+        self.renew_lease()?;
+        if self.buffer.is_empty() {
+            return Ok(());
+        }
+
+        let mut tx = self.client.transaction().context("Failed to begin transaction")?;
+
+        let stmt_artifact = tx.prepare(
+            "INSERT INTO artifacts (hash_sha256, original_path, media_type, width, height, size_bytes, mtime, run_id, metadata)
+             VALUES ($1, $2, $3, $4, $5, $6, $7, $8, $9)
+             ON CONFLICT (hash_sha256) DO UPDATE SET
+                original_path = EXCLUDED.original_path,
+                media_type = CASE WHEN EXCLUDED.media_type = 'application/octet-stream'
+                             THEN artifacts.media_type ELSE EXCLUDED.media_type END,
+                width = CASE WHEN COALESCE(EXCLUDED.width::BIGINT * EXCLUDED.height, 0) > COALESCE(artifacts.width::BIGINT * artifacts.height, 0)
+                        THEN EXCLUDED.width ELSE artifacts.width END,
+                height = CASE WHEN COALESCE(EXCLUDED.width::BIGINT * EXCLUDED.height, 0) > COALESCE(artifacts.width::BIGINT * artifacts.height, 0)
+                         THEN EXCLUDED.height ELSE artifacts.height END,
+                size_bytes = EXCLUDED.size_bytes,
+                mtime = EXCLUDED.mtime,
+                metadata = COALESCE(artifacts.metadata || EXCLUDED.metadata, artifacts.metadata, EXCLUDED.metadata),
+                deleted_at = NULL,
+                deleted_reason = NULL
+             RETURNING id"
+        )?;
+        let stmt_path = tx.prepare(
+            "INSERT INTO artifact_paths (artifact_id, path, first_seen, last_seen, run_id, source_id)
+             VALUES ($1, $2, $3, $3, $4, $5)
+             ON CONFLICT (artifact_id, path) DO UPDATE SET
+                last_seen = EXCLUDED.last_seen,
+                deleted_at = NULL,
+                source_id = COALESCE(EXCLUDED.source_id, artifact_paths.source_id)"
+        )?;
+        let stmt_find_source = tx.prepare(
+            "SELECT id FROM sources
+             WHERE CASE WHEN $1::TEXT IS NOT NULL THEN fs_uuid = $1
+                   ELSE fs_uuid IS NULL AND host = $2 AND mount_point IS NOT DISTINCT FROM $3 END"
+        )?;
+        let stmt_update_source = tx.prepare(
+            "UPDATE sources SET host = $2, label = COALESCE($3, label), mount_point = $4, last_seen = $5
+             WHERE id = $1"
+        )?;
+        let stmt_insert_source = tx.prepare(
+            "INSERT INTO sources (host, fs_uuid, label, mount_point, first_seen, last_seen)
+             VALUES ($1, $2, $3, $4, $5, $5) RETURNING id"
+        )?;
+        let mut source_ids: HashMap<&Source, i64> = HashMap::new();
+        let stmt_resolve = tx.prepare(
+            "UPDATE ingest_errors SET resolved_at = $2
+             WHERE path = $1 AND resolved_at IS NULL AND run_id IS DISTINCT FROM $3
+             AND NOT EXISTS (SELECT 1 FROM ingest_errors e WHERE e.path = $1 AND e.run_id IS NOT DISTINCT FROM $3)"
+        )?;
+        let stmt_journal = tx.prepare(
+            "INSERT INTO run_files (run_id, path, size_bytes, mtime) VALUES ($1, $2, $3, $4)
+             ON CONFLICT (run_id, path) DO UPDATE SET size_bytes = EXCLUDED.size_bytes, mtime = EXCLUDED.mtime"
+        )?;
+        let stmt_timings = tx.prepare(
+            "INSERT INTO file_timings (run_id, path, hash_sha256, size_bytes, hash_ms, decode_ms, inference_ms, total_ms)
+             VALUES ($1, $2, $3, $4, $5, $6, $7, $8)
+             ON CONFLICT (run_id, path) DO UPDATE SET
+                hash_sha256 = EXCLUDED.hash_sha256, size_bytes = EXCLUDED.size_bytes, hash_ms = EXCLUDED.hash_ms,
+                decode_ms = EXCLUDED.decode_ms, inference_ms = EXCLUDED.inference_ms, total_ms = EXCLUDED.total_ms"
+        )?;
+        let stmt_tag = tx.prepare(TAG_ID)?;
+        // The tag and everything it implies, transitively.
+        let stmt_artifact_tag = tx.prepare(&format!(
+            "WITH RECURSIVE {}
+             INSERT INTO artifact_tags (artifact_id, tag_id)
+             SELECT $1::BIGINT, $2::BIGINT UNION SELECT $1, implied_id FROM closure WHERE tag_id = $2
+             ON CONFLICT DO NOTHING",
+            IMPLIED_CLOSURE
+        ))?;
+        let stmt_score_version = tx.prepare("SELECT model_version FROM safety_scores WHERE artifact_id = $1")?;
+        let stmt_score = tx.prepare(
+            "INSERT INTO safety_scores (artifact_id, nsfw_score, model_version) VALUES ($1, $2, $3)
+             ON CONFLICT (artifact_id) DO UPDATE SET
+                nsfw_score = EXCLUDED.nsfw_score,
+                model_version = EXCLUDED.model_version"
+        )?;
+        let stmt_embedding_version = tx.prepare(
+            "SELECT model_version FROM embeddings WHERE artifact_id = $1 AND model_name = $2"
+        )?;
+        let stmt_embedding = tx.prepare(
+            "INSERT INTO embeddings (artifact_id, model_name, model_version, dim, vector, created_at)
+             VALUES ($1, $2, $3, $4, $5, $6)
+             ON CONFLICT (artifact_id, model_name) DO UPDATE SET
+                model_version = EXCLUDED.model_version,
+                dim = EXCLUDED.dim,
+                vector = EXCLUDED.vector,
+                created_at = EXCLUDED.created_at"
+        )?;
+
+        let now = chrono::Utc::now().timestamp();
+
+        for record in &self.buffer {
+            let row = tx.query_one(&stmt_artifact, &[
+                &record.hash_sha256,
+                &record.original_path,
+                &record.media_type,
+                &record.width.map(|w| w as i32),
+                &record.height.map(|h| h as i32),
+                &record.size_bytes.map(|s| s as i64),
+                &record.mtime,
+                &self.run_id,
+                &record.metadata,
+            ]).context("Failed to insert/get artifact")?;
+            let artifact_id: i64 = row.get(0);
+
+            let source_id = match &record.source {
+                Some(source) => Some(match source_ids.get(source) {
+                    Some(&id) => id,
+                    None => {
+                        let existing = tx.query_opt(&stmt_find_source, &[&source.fs_uuid, &source.host, &source.mount_point])?;
+                        let id: i64 = match existing {
+                            Some(row) => {
+                                let id = row.get(0);
+                                tx.execute(&stmt_update_source, &[&id, &source.host, &source.label, &source.mount_point, &now])?;
+                                id
+                            }
+                            None => tx
+                                .query_one(&stmt_insert_source, &[&source.host, &source.fs_uuid, &source.label, &source.mount_point, &now])?
+                                .get(0),
+                        };
+                        source_ids.insert(source, id);
+                        id
+                    }
+                }),
+                None => None,
+            };
+
+            tx.execute(&stmt_path, &[&artifact_id, &record.original_path, &now, &self.run_id, &source_id])?;
+            tx.execute(&stmt_resolve, &[&record.original_path, &now, &self.run_id])?;
+            if let Some(run_id) = self.run_id {
+                tx.execute(&stmt_journal, &[&run_id, &record.original_path, &record.size_bytes.map(|s| s as i64), &record.mtime])?;
+                if let Some(t) = &record.timings {
+                    tx.execute(&stmt_timings, &[
+                        &run_id,
+                        &record.original_path,
+                        &record.hash_sha256,
+                        &record.size_bytes.map(|s| s as i64),
+                        &(t.hash_ms as i64),
+                        &(t.decode_ms as i64),
+                        &(t.inference_ms as i64),
+                        &(t.total_ms as i64),
+                    ])?;
+                }
+            }
+
+            for label in &record.tags {
+                let tag = Tag::parse(label);
+                let tag_id: i64 = tx.query_one(&stmt_tag, &[&tag.namespace, &tag.name])?.get(0);
+                tx.execute(&stmt_artifact_tag, &[&artifact_id, &tag_id])?;
+            }
+
+            if let Some(score) = record.nsfw_score {
+                let current = tx.query_opt(&stmt_score_version, &[&artifact_id])?.map(|row| row.get(0));
+                if supersedes(record.nsfw_model_version.as_deref(), current) {
+                    tx.execute(&stmt_score, &[&artifact_id, &score, &record.nsfw_model_version])?;
+                }
+            }
+
+            for embedding in &record.embeddings {
+                let current = tx
+                    .query_opt(&stmt_embedding_version, &[&artifact_id, &embedding.model_name])?
+                    .map(|row| row.get(0));
+                if !supersedes(Some(&embedding.model_version), current) {
+                    continue;
+                }
+                tx.execute(&stmt_embedding, &[
+                    &artifact_id,
+                    &embedding.model_name,
+                    &embedding.model_version,
+                    &(embedding.vector.len() as i32),
+                    &embedding.to_blob(),
+                    &now,
+                ])?;
+            }
+        }
+
+        tx.commit().context("Failed to commit transaction")?;
+        self.files_seen += self.buffer.len() as u64;
+        self.buffer.clear();
+        Ok(())
+    }
+
+    fn pending(&self) -> usize {
+        self.buffer.len()
+    }
+
+    fn errors(&mut self, run_id: Option<i64>, include_resolved: bool) -> Result<Vec<IngestError>> {
+        let rows = self.client.query(
+            "SELECT id, run_id, path, stage, error, created_at, resolved_at, partial, stderr FROM ingest_errors
+             WHERE ($1::BIGINT IS NULL OR run_id = $1) AND ($2 OR resolved_at IS NULL)
+             ORDER BY id",
+            &[&run_id, &include_resolved],
+        )?;
+        Ok(rows
+            .iter()
+            .map(|row| IngestError {
+                id: row.get(0),
+                run_id: row.get(1),
+                path: row.get(2),
+                stage: row.get(3),
+                error: row.get(4),
+                created_at: row.get(5),
+                resolved_at: row.get(6),
+                partial: parse_partial(row.get(7)),
+                stderr: row.get(8),
+            })
+            .collect())
+    }
+
+    fn known_hashes(&mut self) -> Result<HashSet<String>> {
+        let rows = self.client.query("SELECT hash_sha256 FROM artifacts", &[])?;
+        Ok(rows.iter().map(|row| row.get(0)).collect())
+    }
+
+    fn catalogued_files(&mut self, root: &str) -> Result<HashMap<String, CommittedFile>> {
+        let (from, to) = store::subtree(root);
+        // Newest content last, so it wins for a path that changed. COLLATE
+        // "C" compares bytes, as the bounds assume.
+        let rows = self.client.query(
+            "SELECT p.path, a.size_bytes, a.mtime FROM artifact_paths p JOIN artifacts a ON a.id = p.artifact_id
+             WHERE p.path COLLATE \"C\" >= $1 AND p.path COLLATE \"C\" < $2 AND p.deleted_at IS NULL AND a.deleted_at IS NULL ORDER BY a.id",
+            &[&from, &to],
+        )?;
+        let mut files = HashMap::new();
+        for row in rows {
+            let size: Option<i64> = row.get(1);
+            files.insert(row.get(0), CommittedFile { size_bytes: size.map(|s| s as u64), mtime: row.get(2) });
+        }
+        Ok(files)
+    }
+
+    fn live_paths(&mut self, hash: &str) -> Result<Vec<String>> {
+        let rows = self.client.query(
+            "SELECT p.path FROM artifact_paths p JOIN artifacts a ON a.id = p.artifact_id
+             WHERE a.hash_sha256 = $1 AND p.deleted_at IS NULL ORDER BY p.first_seen, p.path",
+            &[&hash],
+        )?;
+        Ok(rows.iter().map(|row| row.get(0)).collect())
+    }
+
+    fn runs(&mut self) -> Result<Vec<Run>> {
+        let rows = self.client.query(
+            "SELECT id, started_at, finished_at, status, input_roots, options, files_seen, artifacts_added, errors, summary
+             FROM runs ORDER BY id DESC",
+            &[],
+        )?;
+        Ok(rows
+            .iter()
+            .map(|row| Run {
+                id: row.get(0),
+                started_at: row.get(1),
+                finished_at: row.get(2),
+                status: row.get(3),
+                input_roots: row.get(4),
+                options: row.get(5),
+                files_seen: row.get::<_, i64>(6) as u64,
+                artifacts_added: row.get::<_, i64>(7) as u64,
+                errors: row.get::<_, i64>(8) as u64,
+                summary: parse_summary(row.get(9)),
+            })
+            .collect())
+    }
+
+    fn add_alias(&mut self, alias: &str, tag: &str) -> Result<()> {
+        let (alias, tag) = (Tag::parse(alias), Tag::parse(tag));
+        tags::check_rule(alias, tag)?;
+        let mut tx = self.client.transaction()?;
+
+        let target: i64 = tx.query_one(TAG_ID, &[&tag.namespace, &tag.name])?.get(0);
+        let existing: Option<i64> = tx
+            .query_opt("SELECT id FROM tags WHERE namespace = $1 AND name = $2", &[&alias.namespace, &alias.name])?
+            .map(|row| row.get(0));
+        if existing == Some(target) {
+            bail!("'{}' is already an alias of '{}'", tag, alias);
+        }
+
+        // Fold the old tag into the target so it never shows up again.
+        if let Some(old) = existing {
+            tx.execute(
+                "INSERT INTO artifact_tags (artifact_id, tag_id) SELECT artifact_id, $2 FROM artifact_tags WHERE tag_id = $1
+                 ON CONFLICT DO NOTHING",
+                &[&old, &target],
+            )?;
+            tx.execute("DELETE FROM artifact_tags WHERE tag_id = $1", &[&old])?;
+            tx.execute(
+                "INSERT INTO tag_implications (tag_id, implied_tag_id)
+                 SELECT CASE WHEN tag_id = $1 THEN $2 ELSE tag_id END, CASE WHEN implied_tag_id = $1 THEN $2 ELSE implied_tag_id END
+                 FROM tag_implications WHERE tag_id = $1 OR implied_tag_id = $1
+                 ON CONFLICT DO NOTHING",
+                &[&old, &target],
+            )?;
+            tx.execute(
+                "DELETE FROM tag_implications WHERE tag_id = $1 OR implied_tag_id = $1 OR tag_id = implied_tag_id",
+                &[&old],
+            )?;
+            tx.execute("UPDATE tag_aliases SET tag_id = $2 WHERE tag_id = $1", &[&old, &target])?;
+            tx.execute("DELETE FROM tags WHERE id = $1", &[&old])?;
+        }
+
+        tx.execute(
+            "INSERT INTO tag_aliases (namespace, name, tag_id) VALUES ($1, $2, $3)
+             ON CONFLICT (namespace, name) DO UPDATE SET tag_id = EXCLUDED.tag_id",
+            &[&alias.namespace, &alias.name, &target],
+        )?;
+        apply_implications(&mut tx)?;
+        tx.commit()?;
+        Ok(())
+    }
+
+    fn remove_alias(&mut self, alias: &str) -> Result<bool> {
+        let alias = Tag::parse(alias);
+        let removed = self.client.execute(
+            "DELETE FROM tag_aliases WHERE namespace = $1 AND name = $2",
+            &[&alias.namespace, &alias.name],
+        )?;
+        Ok(removed > 0)
+    }
+
+    fn add_implication(&mut self, tag: &str, implied: &str) -> Result<()> {
+        let (tag, implied) = (Tag::parse(tag), Tag::parse(implied));
+        tags::check_rule(tag, implied)?;
+        let mut tx = self.client.transaction()?;
+
+        let from: i64 = tx.query_one(TAG_ID, &[&tag.namespace, &tag.name])?.get(0);
+        let to: i64 = tx.query_one(TAG_ID, &[&implied.namespace, &implied.name])?.get(0);
+        let cycle: bool = tx.query_one(
+            &format!("WITH RECURSIVE {} SELECT $1::BIGINT = $2::BIGINT OR EXISTS (SELECT 1 FROM closure WHERE tag_id = $2 AND implied_id = $1)", IMPLIED_CLOSURE),
+            &[&from, &to],
+        )?.get(0);
+        if cycle {
+            bail!("'{}' already implies '{}'", implied, tag);
+        }
+
+        tx.execute(
+            "INSERT INTO tag_implications (tag_id, implied_tag_id) VALUES ($1, $2) ON CONFLICT DO NOTHING",
+            &[&from, &to],
+        )?;
+        apply_implications(&mut tx)?;
+        tx.commit()?;
+        Ok(())
+    }
+
+    fn remove_implication(&mut self, tag: &str, implied: &str) -> Result<bool> {
+        let (tag, implied) = (Tag::parse(tag), Tag::parse(implied));
+        let mut tx = self.client.transaction()?;
+        let from = tx.query_opt(FIND_TAG_ID, &[&tag.namespace, &tag.name])?;
+        let to = tx.query_opt(FIND_TAG_ID, &[&implied.namespace, &implied.name])?;
+        let removed = match (from, to) {
+            (Some(from), Some(to)) => tx.execute(
+                "DELETE FROM tag_implications WHERE tag_id = $1 AND implied_tag_id = $2",
+                &[&from.get::<_, i64>(0), &to.get::<_, i64>(0)],
+            )?,
+            _ => 0,
+        };
+        tx.commit()?;
+        Ok(removed > 0)
+    }
+
+    fn tag_rules(&mut self) -> Result<TagRules> {
+        let label = |row: &postgres::Row, i: usize| Tag { namespace: row.get(i), name: row.get(i + 1) }.to_string();
+
+        let aliases = self.client.query(
+            "SELECT a.namespace, a.name, t.namespace, t.name FROM tag_aliases a JOIN tags t ON t.id = a.tag_id
+             ORDER BY a.namespace, a.name",
+            &[],
+        )?;
+        let implications = self.client.query(
+            "SELECT t.namespace, t.name, u.namespace, u.name FROM tag_implications i
+             JOIN tags t ON t.id = i.tag_id JOIN tags u ON u.id = i.implied_tag_id
+             ORDER BY t.namespace, t.name, u.namespace, u.name",
+            &[],
+        )?;
+
+        Ok(TagRules {
+            aliases: aliases.iter().map(|row| (label(row, 0), label(row, 2))).collect(),
+            implications: implications.iter().map(|row| (label(row, 0), label(row, 2))).collect(),
+        })
+    }
+    fn relate(&mut self, hash: &str, kind: &str, related_hash: &str) -> Result<()> {
+        let mut tx = self.client.transaction()?;
+        let from = pg_artifact_id(&mut tx, hash)?;
+        let to = pg_artifact_id(&mut tx, related_hash)?;
+        if from == to {
+            bail!("An artifact cannot be related to itself");
+        }
+        tx.execute(
+            "INSERT INTO relationships (artifact_id, kind, related_id, created_at) VALUES ($1, $2, $3, $4)
+             ON CONFLICT DO NOTHING",
+            &[&from, &kind, &to, &chrono::Utc::now().timestamp()],
+        )?;
+        tx.commit()?;
+        Ok(())
+    }
+
+    fn unrelate(&mut self, hash: &str, kind: &str, related_hash: &str) -> Result<bool> {
+        let removed = self.client.execute(
+            "DELETE FROM relationships
+             WHERE artifact_id = (SELECT id FROM artifacts WHERE hash_sha256 = $1) AND kind = $2
+             AND related_id = (SELECT id FROM artifacts WHERE hash_sha256 = $3)",
+            &[&hash, &kind, &related_hash],
+        )?;
+        Ok(removed > 0)
+    }
+
+    fn add_tag(&mut self, hash: &str, tag: &str) -> Result<()> {
+        let tag = Tag::parse(tag);
+        if tag.name.is_empty() || tag.is_wildcard() {
+            bail!("'{}' is not a single tag", tag);
+        }
+        let mut tx = self.client.transaction()?;
+        let id = pg_artifact_id(&mut tx, hash)?;
+        let tag_id: i64 = tx.query_one(TAG_ID, &[&tag.namespace, &tag.name])?.get(0);
+        tx.execute(
+            "INSERT INTO artifact_tags (artifact_id, tag_id) VALUES ($1, $2) ON CONFLICT DO NOTHING",
+            &[&id, &tag_id],
+        )?;
+        apply_implications(&mut tx)?;
+        tx.commit()?;
+        Ok(())
+    }
+
+    fn add_tags(&mut self, tags: &[(String, String)]) -> Result<usize> {
+        let mut tx = self.client.transaction()?;
+        let mut added = 0;
+        for (hash, tag) in tags {
+            let tag = Tag::parse(tag);
+            if tag.name.is_empty() || tag.is_wildcard() {
+                continue;
+            }
+            let Some(row) = tx.query_opt("SELECT id FROM artifacts WHERE hash_sha256 = $1", &[hash])? else {
+                continue;
+            };
+            let id: i64 = row.get(0);
+            let tag_id: i64 = tx.query_one(TAG_ID, &[&tag.namespace, &tag.name])?.get(0);
+            added += tx.execute(
+                "INSERT INTO artifact_tags (artifact_id, tag_id) VALUES ($1, $2) ON CONFLICT DO NOTHING",
+                &[&id, &tag_id],
+            )? as usize;
+        }
+        apply_implications(&mut tx)?;
+        tx.commit()?;
+        Ok(added)
+    }
+
+    fn remove_tag(&mut self, hash: &str, tag: &str) -> Result<bool> {
+        let tag = Tag::parse(tag);
+        let mut tx = self.client.transaction()?;
+        let id = pg_artifact_id(&mut tx, hash)?;
+        let removed = match tx.query_opt(FIND_TAG_ID, &[&tag.namespace, &tag.name])? {
+            Some(row) => tx.execute(
+                "DELETE FROM artifact_tags WHERE artifact_id = $1 AND tag_id = $2",
+                &[&id, &row.get::<_, i64>(0)],
+            )?,
+            None => 0,
+        };
+        tx.commit()?;
+        Ok(removed > 0)
+    }
+}
+
+fn pg_artifact_id(tx: &mut Transaction, hash: &str) -> Result<i64> {
+    match tx.query_opt("SELECT id FROM artifacts WHERE hash_sha256 = $1", &[&hash])? {
+        Some(row) => Ok(row.get(0)),
+        None => bail!("No artifact with hash {}", hash),
+    }
+}
@@ -1,84 +1,543 @@
-use rusqlite::{Connection, params};
-use anyhow::{Result, Context};
-use crate::database::schema::SCHEMA;
+use std::cmp::Ordering;
+use std::collections::{HashMap, HashSet, VecDeque};
+use std::time::{Duration, Instant};
+use rusqlite::{Connection, ErrorCode, OpenFlags, OptionalExtension, ToSql, Transaction, TransactionBehavior, params, params_from_iter};
+use rusqlite::types::Value;
+use r2d2::{ManageConnection, Pool, PooledConnection};
+use r2d2_sqlite::SqliteConnectionManager;
+use anyhow::{Result, Context, bail};
+use serde::{Deserialize, Serialize};
+use tracing::{error, warn};
+use crate::archive::Member;
+use crate::database::encryption;
+use crate::database::maintenance::{self, CheckResult};
+use crate::database::schema;
+use crate::database::store::{self, CatalogStore, LeaseHolder, LeaseKind, LEASE_RENEW, LEASE_TTL};
+use crate::database::tags::{self, Tag, IMPLIED_CLOSURE, LABEL_SQL};
+use crate::ingest::source::Source;
+use crate::ingest::summary::RunSummary;
+use crate::utils::config::DatabaseConfig;
 
-#[derive(Debug, Clone)]
+/// What ingest found out about a file, as buffered for the catalog and sent
+/// back by remote workers.
+#[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct ArtifactRecord {
     pub hash_sha256: String,
     pub original_path: String,
     pub media_type: String,
+    pub size_bytes: Option<u64>,
+    /// Modification time in unix seconds.
+    pub mtime: Option<i64>,
     pub width: Option<u32>,
     pub height: Option<u32>,
     pub tags: Vec<String>,
     pub nsfw_score: Option<f32>,
+    /// Version of the model behind `nsfw_score`, see `supersedes`.
+    pub nsfw_model_version: Option<String>,
+    pub embeddings: Vec<Embedding>,
+    /// EXIF/ffprobe/xattr details, see `media::metadata`.
+    pub metadata: Option<serde_json::Value>,
+    /// Volume the file was read from; unknown for imports.
+    pub source: Option<Source>,
+    /// Time ingest spent on the file; unset for imports.
+    pub timings: Option<FileTimings>,
+}
+
+/// How long ingest spent on a file, in milliseconds. `total_ms` counts the
+/// time in hashing and analysis, not waiting in the queues between them;
+/// decoding and inference are 0 where the file wasn't analyzed.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct FileTimings {
+    pub hash_ms: u64,
+    pub decode_ms: u64,
+    pub inference_ms: u64,
+    pub total_ms: u64,
+}
+
+/// A feature vector tagged with the model that produced it, so vectors from an
+/// older model version can be found and recomputed after an upgrade.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[allow(dead_code)] // Constructed once an embedding model is wired into the pipeline.
+pub struct Embedding {
+    pub model_name: String,
+    pub model_version: String,
+    pub vector: Vec<f32>,
+}
+
+impl Embedding {
+    /// Little-endian f32s, the layout stored in `embeddings.vector`.
+    pub fn to_blob(&self) -> Vec<u8> {
+        self.vector.iter().flat_map(|v| v.to_le_bytes()).collect()
+    }
 }
 
 pub struct TransactionManager {
     conn: Connection,
     buffer: Vec<ArtifactRecord>,
     buffer_limit: usize,
+    flush_interval: Option<Duration>,
+    last_flush: Instant,
+    lease: Option<i64>,
+    lease_renewed: Instant,
+    run_id: Option<i64>,
+    files_seen: u64,
+}
+
+/// Attempts at a write that keeps failing with `database is locked`, each
+/// after waiting out the busy timeout.
+const LOCK_ATTEMPTS: u32 = 5;
+
+/// Retries `f` with backoff while another process holds the write lock.
+fn retry_on_lock<T>(mut f: impl FnMut() -> Result<T>) -> Result<T> {
+    let mut delay = Duration::from_millis(100);
+    let mut attempt = 1;
+    loop {
+        match f() {
+            Err(e) if attempt < LOCK_ATTEMPTS && is_locked(&e) => {
+                warn!("Catalog is locked by another process, retrying in {:?} ({}/{})", delay, attempt, LOCK_ATTEMPTS);
+                std::thread::sleep(delay);
+                delay *= 2;
+                attempt += 1;
+            }
+            result => return result,
+        }
+    }
+}
+
+fn is_locked(e: &anyhow::Error) -> bool {
+    e.chain().any(|cause| {
+        matches!(
+            cause.downcast_ref::<rusqlite::Error>(),
+            Some(rusqlite::Error::SqliteFailure(failure, _))
+                if matches!(failure.code, ErrorCode::DatabaseBusy | ErrorCode::DatabaseLocked)
+        )
+    })
+}
+
+/// Applies connection pragmas. Negative `cache_size` means KiB rather than pages.
+/// The SQLCipher `key` has to come first, and is checked by reading the schema.
+fn configure(conn: &Connection, config: &DatabaseConfig, key: Option<&str>, writable: bool) -> rusqlite::Result<()> {
+    if let Some(key) = key {
+        conn.pragma_update(None, "key", key)?;
+        conn.query_row("SELECT count(*) FROM sqlite_master", [], |_| Ok(()))?;
+    }
+    conn.busy_timeout(Duration::from_millis(config.busy_timeout_ms))?;
+    conn.pragma_update(None, "cache_size", -(config.cache_size_kib as i64))?;
+    if writable {
+        // journal_mode returns the resulting mode as a row, so it can't go through pragma_update.
+        let requested = config.journal_mode.name();
+        let mode: String = conn.query_row(&format!("PRAGMA journal_mode = {}", requested), [], |row| row.get(0))?;
+        if !mode.eq_ignore_ascii_case(requested) {
+            tracing::warn!("Requested journal_mode={} but SQLite is using {}", requested, mode);
+        }
+        conn.pragma_update(None, "synchronous", config.synchronous.name())?;
+    }
+    Ok(())
+}
+
+/// Whether a result from model version `new` should replace the stored one.
+/// `current` is `None` if nothing is stored and `Some(None)` for an
+/// unversioned result, which anything replaces.
+pub fn supersedes(new: Option<&str>, current: Option<Option<String>>) -> bool {
+    match current {
+        None | Some(None) => true,
+        Some(Some(current)) => new.is_some_and(|new| compare_versions(new, &current) == Ordering::Greater),
+    }
+}
+
+/// Compares dotted versions numerically where both parts are numbers, so `1.10` > `1.9`.
+fn compare_versions(a: &str, b: &str) -> Ordering {
+    let mut a_parts = a.split('.');
+    let mut b_parts = b.split('.');
+    loop {
+        match (a_parts.next(), b_parts.next()) {
+            (None, None) => return Ordering::Equal,
+            (Some(_), None) => return Ordering::Greater,
+            (None, Some(_)) => return Ordering::Less,
+            (Some(x), Some(y)) => {
+                let order = match (x.parse::<u64>(), y.parse::<u64>()) {
+                    (Ok(x), Ok(y)) => x.cmp(&y),
+                    _ => x.cmp(y),
+                };
+                if order != Ordering::Equal {
+                    return order;
+                }
+            }
+        }
+    }
+}
+
+/// Deletes the artifacts whose ids `ids_sql` selects, with their tags, scores,
+/// embeddings, fixity history, relationships and paths. Returns the number of artifacts removed.
+fn purge_artifacts(tx: &Transaction, ids_sql: &str, params: &[&dyn ToSql]) -> Result<usize> {
+    for table in ["artifact_tags", "safety_scores", "embeddings", "fixity_checks", "relationships", "artifact_paths"] {
+        tx.execute(&format!("DELETE FROM {} WHERE artifact_id IN ({})", table, ids_sql), params)?;
+    }
+    tx.execute(&format!("DELETE FROM relationships WHERE related_id IN ({})", ids_sql), params)?;
+    // Archived copies outlive the catalog entry; they stay findable by hash.
+    tx.execute(&format!("UPDATE archive_members SET artifact_id = NULL WHERE artifact_id IN ({})", ids_sql), params)?;
+    Ok(tx.execute(&format!("DELETE FROM artifacts WHERE id IN ({})", ids_sql), params)?)
+}
+
+/// Id of the tag `tag` stands for, following aliases. With `create`, a tag
+/// that doesn't exist yet is inserted; otherwise it is `None`.
+fn tag_id(tx: &Transaction, tag: Tag, create: bool) -> Result<Option<i64>> {
+    let aliased: Option<i64> = tx
+        .query_row(
+            "SELECT tag_id FROM tag_aliases WHERE namespace = ?1 AND name = ?2",
+            params![tag.namespace, tag.name],
+            |row| row.get(0),
+        )
+        .optional()?;
+    if aliased.is_some() {
+        return Ok(aliased);
+    }
+    if create {
+        tx.execute("INSERT OR IGNORE INTO tags (namespace, name) VALUES (?1, ?2)", params![tag.namespace, tag.name])?;
+    }
+    Ok(tx
+        .query_row(
+            "SELECT id FROM tags WHERE namespace = ?1 AND name = ?2",
+            params![tag.namespace, tag.name],
+            |row| row.get(0),
+        )
+        .optional()?)
+}
+
+fn artifact_id(tx: &Transaction, hash: &str) -> Result<i64> {
+    tx.query_row("SELECT id FROM artifacts WHERE hash_sha256 = ?1", params![hash], |row| row.get(0))
+        .optional()?
+        .with_context(|| format!("No artifact with hash {}", hash))
+}
+
+/// Adds every implied tag the catalog is missing, after a rule changed.
+fn apply_implications(tx: &Transaction) -> Result<usize> {
+    Ok(tx.execute(
+        &format!(
+            "WITH RECURSIVE {}
+             INSERT OR IGNORE INTO artifact_tags (artifact_id, tag_id)
+             SELECT at.artifact_id, c.implied_id FROM artifact_tags at JOIN closure c ON c.tag_id = at.tag_id",
+            IMPLIED_CLOSURE
+        ),
+        [],
+    )?)
 }
 
 impl TransactionManager {
-    pub fn new(path: &str) -> Result<Self> {
-        let conn = Connection::open(path).context("Failed to open database")?;
-        conn.execute_batch(SCHEMA).context("Failed to initialize schema")?;
+    pub fn new(path: &str, config: &DatabaseConfig) -> Result<Self> {
+        store::require_sqlite(path)?;
+        let key = encryption::key(config)?;
+        let mut conn = Connection::open(path).context("Failed to open database")?;
+        configure(&conn, config, key, true).with_context(|| match key {
+            Some(_) => "Failed to unlock the encrypted database; is the key right?",
+            None => "Failed to configure database connection",
+        })?;
+        schema::initialize(&mut conn)?;
         Ok(Self {
             conn,
             buffer: Vec::new(),
-            buffer_limit: 1000,
+            buffer_limit: config.buffer_size.max(1),
+            flush_interval: config.flush_interval(),
+            last_flush: Instant::now(),
+            lease: None,
+            lease_renewed: Instant::now(),
+            run_id: None,
+            files_seen: 0,
         })
     }
 
-    pub fn add(&mut self, record: ArtifactRecord) -> Result<()> {
-        self.buffer.push(record);
-        if self.buffer.len() >= self.buffer_limit {
-            self.flush()?;
+    /// Tombstones every artifact matching `filter` with `reason`, or with
+    /// `purge` removes them and everything attached to them for good.
+    pub fn delete_matching(&mut self, filter: &FilterSet, reason: &str, purge: bool) -> Result<usize> {
+        let (where_sql, values) = filter.to_sql();
+        let tx = self.conn.transaction()?;
+        tx.execute(
+            &format!(
+                "CREATE TEMP TABLE doomed AS
+                 SELECT a.id FROM artifacts a LEFT JOIN safety_scores s ON s.artifact_id = a.id
+                 WHERE {} ORDER BY a.id LIMIT {}",
+                where_sql,
+                filter.limit.map_or(-1, |l| l as i64)
+            ),
+            params_from_iter(values),
+        )?;
+
+        let affected = if purge {
+            purge_artifacts(&tx, "SELECT id FROM temp.doomed", &[])?
+        } else {
+            tx.execute(
+                "UPDATE artifacts SET deleted_at = ?1, deleted_reason = ?2
+                 WHERE id IN (SELECT id FROM temp.doomed) AND deleted_at IS NULL",
+                params![chrono::Utc::now().timestamp(), reason],
+            )?
+        };
+
+        tx.execute("DROP TABLE temp.doomed", [])?;
+        tx.commit()?;
+        Ok(affected)
+    }
+
+    /// Appends the outcome of one verify pass to `fixity_checks`.
+    pub fn record_fixity(&mut self, checks: &[FixityCheck]) -> Result<()> {
+        let tx = self.conn.transaction()?;
+        {
+            let mut stmt = tx.prepare(
+                "INSERT INTO fixity_checks (artifact_id, path, checked_at, algo, result, detail)
+                 VALUES (?1, ?2, ?3, ?4, ?5, ?6)"
+            )?;
+            for check in checks {
+                stmt.execute(params![
+                    check.artifact_id,
+                    check.path,
+                    check.checked_at,
+                    check.algo,
+                    check.result.as_str(),
+                    check.detail
+                ])?;
+            }
         }
+        tx.commit()?;
         Ok(())
     }
 
-    pub fn flush(&mut self) -> Result<()> {
-        if self.buffer.is_empty() {
-            return Ok(());
+    /// Records a written volume and the files on it, linking each to the
+    /// artifact with the same hash where there is one. Returns the volume id.
+    pub fn record_volume(&mut self, volume: &ArchiveVolume, members: &[Member], offsets: &HashMap<String, u64>) -> Result<i64> {
+        let tx = self.conn.transaction()?;
+        tx.execute(
+            "INSERT INTO archive_volumes (label, name, format, created_at, capacity_bytes, size_bytes, sha256, barcode)
+             VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8)",
+            params![
+                volume.label,
+                volume.name,
+                volume.format,
+                volume.created_at,
+                volume.capacity_bytes,
+                volume.size_bytes,
+                volume.sha256,
+                volume.barcode
+            ],
+        )?;
+        let volume_id = tx.last_insert_rowid();
+        {
+            let mut stmt = tx.prepare(
+                "INSERT INTO archive_members (volume_id, path, hash_sha256, size_bytes, artifact_id, offset_bytes)
+                 VALUES (?1, ?2, ?3, ?4, (SELECT id FROM artifacts WHERE hash_sha256 = ?3), ?5)"
+            )?;
+            for member in members {
+                let Some(hash) = &member.hash else { continue };
+                let offset = offsets.get(&member.archive_path());
+                stmt.execute(params![volume_id, member.path.to_string_lossy(), hash, member.size, offset])?;
+            }
+        }
+        tx.commit()?;
+        Ok(volume_id)
+    }
+
+    /// Records a disc burned from the volume labelled `disc.label`.
+    pub fn record_disc(&mut self, disc: &VolumeDisc) -> Result<i64> {
+        let inserted = self.conn.execute(
+            "INSERT INTO volume_discs (volume_id, burned_at, device, drive, media, verified)
+             SELECT id, ?2, ?3, ?4, ?5, ?6 FROM archive_volumes WHERE label = ?1",
+            params![disc.label, disc.burned_at, disc.device, disc.drive, disc.media, disc.verified],
+        )?;
+        if inserted == 0 {
+            bail!("No volume is labelled {}", disc.label);
+        }
+        Ok(self.conn.last_insert_rowid())
+    }
+
+    /// Records a copy of the volume labelled `upload.label` in a bucket.
+    pub fn record_upload(&mut self, upload: &VolumeUpload) -> Result<i64> {
+        let inserted = self.conn.execute(
+            "INSERT INTO volume_uploads (volume_id, remote, bucket, object_key, layout, object_id, storage_class, size_bytes, uploaded_at)
+             SELECT id, ?2, ?3, ?4, ?5, ?6, ?7, ?8, ?9 FROM archive_volumes WHERE label = ?1",
+            params![
+                upload.label,
+                upload.remote,
+                upload.bucket,
+                upload.key,
+                upload.layout,
+                upload.object_id,
+                upload.storage_class,
+                upload.size_bytes,
+                upload.uploaded_at
+            ],
+        )?;
+        if inserted == 0 {
+            bail!("No volume is labelled {}", upload.label);
+        }
+        Ok(self.conn.last_insert_rowid())
+    }
+
+    /// Writes a standalone copy of the catalog to `output` holding only the
+    /// artifacts with one of `hashes`, for putting on the archive media.
+    /// Runs, sources and tag rules are kept; the other volumes, ingest
+    /// errors and leases are not. The copy is vacuumed and uses a rollback
+    /// journal, so it opens from read-only media.
+    pub fn snapshot(&self, output: &std::path::Path, hashes: &[String]) -> Result<()> {
+        if output.exists() {
+            bail!("{:?} already exists", output);
         }
+        let path = output.to_str().context("Output path is not valid UTF-8")?;
+        self.conn.execute("VACUUM INTO ?1", params![path]).context("Failed to copy the catalog")?;
 
-        let tx = self.conn.transaction().context("Failed to begin transaction")?;
+        let mut conn = Connection::open(output)?;
+        conn.pragma_update(None, "journal_mode", "DELETE")?;
+        let tx = conn.transaction()?;
+        tx.execute("CREATE TEMP TABLE keep (hash_sha256 TEXT PRIMARY KEY)", [])?;
+        {
+            let mut stmt = tx.prepare("INSERT OR IGNORE INTO keep (hash_sha256) VALUES (?1)")?;
+            for hash in hashes {
+                stmt.execute(params![hash])?;
+            }
+        }
+        purge_artifacts(&tx, "SELECT id FROM artifacts WHERE hash_sha256 NOT IN (SELECT hash_sha256 FROM keep)", &[])?;
+        for sql in [
+            "DELETE FROM archive_members",
+            "DELETE FROM volume_discs",
+            "DELETE FROM volume_uploads",
+            "DELETE FROM archive_volumes",
+            "DELETE FROM ingest_errors",
+            "DELETE FROM leases",
+            "DELETE FROM run_files",
+            "DELETE FROM file_timings WHERE hash_sha256 NOT IN (SELECT hash_sha256 FROM keep)",
+            "DELETE FROM tags WHERE id NOT IN (SELECT tag_id FROM artifact_tags)
+                AND id NOT IN (SELECT tag_id FROM tag_aliases)
+                AND id NOT IN (SELECT tag_id FROM tag_implications)
+                AND id NOT IN (SELECT implied_tag_id FROM tag_implications)",
+        ] {
+            tx.execute(sql, [])?;
+        }
+        tx.execute("DROP TABLE keep", [])?;
+        tx.commit()?;
+        conn.execute("VACUUM", [])?;
+        Ok(())
+    }
+
+    /// Runs the `db check` consistency checks, see `maintenance::check`.
+    pub fn check(&self) -> Result<Vec<CheckResult>> {
+        maintenance::check(&self.conn)
+    }
+
+    /// Runs the `db optimize` maintenance, see `maintenance::optimize`.
+    pub fn optimize(&self, vacuum: bool) -> Result<(u64, u64)> {
+        maintenance::optimize(&self.conn, vacuum)
+    }
+
+    /// Writes an encrypted copy of the catalog to `output`, see `encryption::export`.
+    #[cfg(feature = "sqlcipher")]
+    pub fn encrypt_to(&self, output: &std::path::Path, key: &str) -> Result<()> {
+        encryption::export(&self.conn, output, key)
+    }
+
+    /// Rebuilds the full-text index from `artifacts`, e.g. after manual edits.
+    pub fn reindex_fts(&mut self) -> Result<()> {
+        self.conn
+            .execute("INSERT INTO search_index(search_index) VALUES ('rebuild')", [])
+            .context("Failed to rebuild search index")?;
+        Ok(())
+    }
+
+    /// One attempt at writing the buffer, see `CatalogStore::flush`. The
+    /// transaction takes the write lock up front, so it waits on the busy
+    /// timeout instead of failing midway when another writer got there first.
+    fn write_buffer(&mut self) -> Result<()> {
+        let tx = self
+            .conn
+            .transaction_with_behavior(TransactionBehavior::Immediate)
+            .context("Failed to begin transaction")?;
 
         {
             // We use prepared statements for efficiency.
             // Using RETURNING id is supported in modern SQLite.
+            // Merge rules for a hash seen again are documented on `CatalogStore::flush`.
             let mut stmt_artifact = tx.prepare(
-                "INSERT INTO artifacts (hash_sha256, original_path, media_type, width, height)
-                 VALUES (?1, ?2, ?3, ?4, ?5)
-                 ON CONFLICT(hash_sha256) DO UPDATE SET original_path=excluded.original_path
+                "INSERT INTO artifacts (hash_sha256, original_path, media_type, width, height, size_bytes, mtime, run_id, metadata)
+                 VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8, ?9)
+                 ON CONFLICT(hash_sha256) DO UPDATE SET
+                    original_path=excluded.original_path,
+                    media_type=CASE WHEN excluded.media_type = 'application/octet-stream' THEN media_type ELSE excluded.media_type END,
+                    width=CASE WHEN COALESCE(excluded.width * excluded.height, 0) > COALESCE(width * height, 0) THEN excluded.width ELSE width END,
+                    height=CASE WHEN COALESCE(excluded.width * excluded.height, 0) > COALESCE(width * height, 0) THEN excluded.height ELSE height END,
+                    size_bytes=excluded.size_bytes,
+                    mtime=excluded.mtime,
+                    metadata=CASE WHEN metadata IS NULL THEN excluded.metadata
+                                  ELSE json_patch(metadata, COALESCE(excluded.metadata, '{}')) END,
+                    deleted_at=NULL,
+                    deleted_reason=NULL
                  RETURNING id"
             )?;
 
-            let mut stmt_tag = tx.prepare(
-                "INSERT OR IGNORE INTO tags (name) VALUES (?1)"
+            // Every sighting is kept, so duplicates never lose their other locations.
+            let mut stmt_path = tx.prepare(
+                "INSERT INTO artifact_paths (artifact_id, path, first_seen, last_seen, run_id, source_id)
+                 VALUES (?1, ?2, ?3, ?3, ?4, ?5)
+                 ON CONFLICT(artifact_id, path) DO UPDATE SET
+                    last_seen=excluded.last_seen,
+                    deleted_at=NULL,
+                    source_id=COALESCE(excluded.source_id, source_id)"
             )?;
 
-            let mut stmt_get_tag_id = tx.prepare(
-                "SELECT id FROM tags WHERE name = ?1"
+            // A volume is known by its filesystem UUID, or failing that by host and mount point.
+            let mut stmt_find_source = tx.prepare(
+                "SELECT id FROM sources
+                 WHERE CASE WHEN ?1 IS NOT NULL THEN fs_uuid = ?1
+                       ELSE fs_uuid IS NULL AND host = ?2 AND mount_point IS ?3 END"
             )?;
-
-            let mut stmt_artifact_tag = tx.prepare(
-                "INSERT OR IGNORE INTO artifact_tags (artifact_id, tag_id) VALUES (?1, ?2)"
+            let mut stmt_update_source = tx.prepare(
+                "UPDATE sources SET host = ?2, label = COALESCE(?3, label), mount_point = ?4, last_seen = ?5
+                 WHERE id = ?1"
+            )?;
+            let mut stmt_insert_source = tx.prepare(
+                "INSERT INTO sources (host, fs_uuid, label, mount_point, first_seen, last_seen)
+                 VALUES (?1, ?2, ?3, ?4, ?5, ?5) RETURNING id"
             )?;
+            let mut source_ids: HashMap<&Source, i64> = HashMap::new();
 
+            // The tag and everything it implies, transitively.
+            let mut stmt_artifact_tag = tx.prepare(&format!(
+                "WITH RECURSIVE {}
+                 INSERT OR IGNORE INTO artifact_tags (artifact_id, tag_id)
+                 SELECT ?1, ?2 UNION SELECT ?1, implied_id FROM closure WHERE tag_id = ?2",
+                IMPLIED_CLOSURE
+            ))?;
+
+            let mut stmt_score_version = tx.prepare(
+                "SELECT model_version FROM safety_scores WHERE artifact_id = ?1"
+            )?;
             let mut stmt_score = tx.prepare(
-                "INSERT OR REPLACE INTO safety_scores (artifact_id, nsfw_score) VALUES (?1, ?2)"
+                "INSERT OR REPLACE INTO safety_scores (artifact_id, nsfw_score, model_version) VALUES (?1, ?2, ?3)"
+            )?;
+
+            // One vector per artifact and model; a newer version replaces the old one.
+            let mut stmt_embedding_version = tx.prepare(
+                "SELECT model_version FROM embeddings WHERE artifact_id = ?1 AND model_name = ?2"
+            )?;
+            let mut stmt_embedding = tx.prepare(
+                "INSERT OR REPLACE INTO embeddings (artifact_id, model_name, model_version, dim, vector, created_at)
+                 VALUES (?1, ?2, ?3, ?4, ?5, ?6)"
+            )?;
+
+            // A later run that gets a file through cleanly settles its earlier failures.
+            let mut stmt_resolve = tx.prepare(
+                "UPDATE ingest_errors SET resolved_at = ?2
+                 WHERE path = ?1 AND resolved_at IS NULL AND run_id IS NOT ?3
+                 AND NOT EXISTS (SELECT 1 FROM ingest_errors e WHERE e.path = ?1 AND e.run_id IS ?3)"
             )?;
 
-            // For FTS, we might want to avoid duplicates if the file is already there,
-            // but FTS doesn't have unique constraints easily.
-            // We'll just insert for now, assuming the upstream pipeline handles high-level deduplication logic
-            // or we accept multiple entries for now.
-            let mut stmt_fts = tx.prepare(
-                "INSERT INTO search_index (original_path, tags_concatenated) VALUES (?1, ?2)"
+            let mut stmt_journal = tx.prepare(
+                "INSERT OR REPLACE INTO run_files (run_id, path, size_bytes, mtime) VALUES (?1, ?2, ?3, ?4)"
+            )?;
+            let mut stmt_timings = tx.prepare(
+                "INSERT OR REPLACE INTO file_timings (run_id, path, hash_sha256, size_bytes, hash_ms, decode_ms, inference_ms, total_ms)
+                 VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8)"
             )?;
 
+            // search_index is maintained by triggers on artifacts/artifact_tags.
+
+            let now = chrono::Utc::now().timestamp();
+
             for record in &self.buffer {
                 // Insert artifact or update
                 let artifact_id: i64 = stmt_artifact.query_row(params![
@@ -86,34 +545,2375 @@ impl TransactionManager {
                     record.original_path,
                     record.media_type,
                     record.width,
-                    record.height
+                    record.height,
+                    record.size_bytes,
+                    record.mtime,
+                    self.run_id,
+                    record.metadata
                 ], |row| row.get(0)).context("Failed to insert/get artifact")?;
 
-                // Handle Tags
-                let mut tag_names = Vec::new();
-                for tag in &record.tags {
-                    stmt_tag.execute(params![tag])?;
+                let source_id = match &record.source {
+                    Some(source) => Some(match source_ids.get(source) {
+                        Some(&id) => id,
+                        None => {
+                            let existing: Option<i64> = stmt_find_source
+                                .query_row(params![source.fs_uuid, source.host, source.mount_point], |row| row.get(0))
+                                .optional()?;
+                            let id = match existing {
+                                Some(id) => {
+                                    stmt_update_source.execute(params![id, source.host, source.label, source.mount_point, now])?;
+                                    id
+                                }
+                                None => stmt_insert_source.query_row(
+                                    params![source.host, source.fs_uuid, source.label, source.mount_point, now],
+                                    |row| row.get(0),
+                                )?,
+                            };
+                            source_ids.insert(source, id);
+                            id
+                        }
+                    }),
+                    None => None,
+                };
 
-                    let tag_id: i64 = stmt_get_tag_id.query_row(params![tag], |row| row.get(0))
-                        .context("Failed to get tag id after insert")?;
+                stmt_path.execute(params![artifact_id, record.original_path, now, self.run_id, source_id])?;
+                stmt_resolve.execute(params![record.original_path, now, self.run_id])?;
+                if let Some(run_id) = self.run_id {
+                    stmt_journal.execute(params![run_id, record.original_path, record.size_bytes, record.mtime])?;
+                    if let Some(t) = &record.timings {
+                        stmt_timings.execute(params![
+                            run_id,
+                            record.original_path,
+                            record.hash_sha256,
+                            record.size_bytes,
+                            t.hash_ms,
+                            t.decode_ms,
+                            t.inference_ms,
+                            t.total_ms
+                        ])?;
+                    }
+                }
 
+                // Handle Tags
+                for label in &record.tags {
+                    let tag_id = tag_id(&tx, Tag::parse(label), true)?
+                        .context("Failed to get tag id after insert")?;
                     stmt_artifact_tag.execute(params![artifact_id, tag_id])?;
-                    tag_names.push(tag.as_str());
                 }
 
                 // Handle Safety Score
                 if let Some(score) = record.nsfw_score {
-                    stmt_score.execute(params![artifact_id, score])?;
+                    let current = stmt_score_version.query_row(params![artifact_id], |row| row.get(0)).optional()?;
+                    if supersedes(record.nsfw_model_version.as_deref(), current) {
+                        stmt_score.execute(params![artifact_id, score, record.nsfw_model_version])?;
+                    }
                 }
 
-                // Handle FTS
-                let tags_concat = tag_names.join(" ");
-                stmt_fts.execute(params![record.original_path, tags_concat])?;
+                for embedding in &record.embeddings {
+                    let current = stmt_embedding_version
+                        .query_row(params![artifact_id, embedding.model_name], |row| row.get(0))
+                        .optional()?;
+                    if !supersedes(Some(&embedding.model_version), current) {
+                        continue;
+                    }
+                    stmt_embedding.execute(params![
+                        artifact_id,
+                        embedding.model_name,
+                        embedding.model_version,
+                        embedding.vector.len(),
+                        embedding.to_blob(),
+                        now
+                    ])?;
+                }
             }
         }
 
         tx.commit().context("Failed to commit transaction")?;
+        Ok(())
+    }
+
+    fn renew_lease(&mut self) -> Result<()> {
+        let Some(id) = self.lease else {
+            return Ok(());
+        };
+        if self.lease_renewed.elapsed() < LEASE_RENEW {
+            return Ok(());
+        }
+        let expires_at = chrono::Utc::now().timestamp() + LEASE_TTL.as_secs() as i64;
+        if self.conn.execute("UPDATE leases SET expires_at = ?2 WHERE id = ?1", params![id, expires_at])? == 0 {
+            warn!("Lease {} expired before it was renewed; other processes may have taken over the catalog", id);
+        }
+        self.lease_renewed = Instant::now();
+        Ok(())
+    }
+
+    /// Records in this process's lease that it writes `run_id`, failing if
+    /// a live lease of another process already does.
+    fn claim_run(&mut self, run_id: i64) -> Result<()> {
+        let lease = self.lease;
+        retry_on_lock(|| {
+            let tx = self.conn.transaction_with_behavior(TransactionBehavior::Immediate)?;
+            let holder = tx
+                .query_row(
+                    "SELECT command, kind, host, pid, acquired_at FROM leases
+                     WHERE run_id = ?1 AND id IS NOT ?2 AND expires_at >= ?3 ORDER BY id LIMIT 1",
+                    params![run_id, lease, chrono::Utc::now().timestamp()],
+                    |row| {
+                        Ok(LeaseHolder {
+                            command: row.get(0)?,
+                            kind: row.get(1)?,
+                            host: row.get(2)?,
+                            pid: row.get(3)?,
+                            acquired_at: row.get(4)?,
+                        })
+                    },
+                )
+                .optional()?;
+            if let Some(holder) = holder {
+                return Err(holder.writing(run_id));
+            }
+            tx.execute("UPDATE leases SET run_id = ?1 WHERE id = ?2", params![run_id, lease])?;
+            tx.commit()?;
+            Ok(())
+        })
+    }
+
+    fn release_lease(&mut self) -> Result<()> {
+        if let Some(id) = self.lease.take() {
+            self.conn.execute("DELETE FROM leases WHERE id = ?1", params![id])?;
+        }
+        Ok(())
+    }
+}
+
+/// Last resort for records still buffered when the store goes away early,
+/// e.g. after an error; the normal path is `finish_run`.
+impl Drop for TransactionManager {
+    fn drop(&mut self) {
+        if !self.buffer.is_empty() {
+            if let Err(e) = self.flush() {
+                error!("Failed to flush {} buffered records: {:#}", self.buffer.len(), e);
+            }
+        }
+        if let Err(e) = self.release_lease() {
+            warn!("Failed to release catalog lease, it expires in {:?}: {:#}", LEASE_TTL, e);
+        }
+    }
+}
+
+impl CatalogStore for TransactionManager {
+    fn acquire_lease(&mut self, kind: LeaseKind) -> Result<()> {
+        if self.lease.is_some() {
+            return Ok(());
+        }
+        let (command, host, pid) = store::lease_owner();
+        let id = retry_on_lock(|| {
+            let tx = self.conn.transaction_with_behavior(TransactionBehavior::Immediate)?;
+            let now = chrono::Utc::now().timestamp();
+            tx.execute("DELETE FROM leases WHERE expires_at < ?1", params![now])?;
+            let holder = tx
+                .query_row(
+                    "SELECT command, kind, host, pid, acquired_at FROM leases
+                     WHERE ?1 = 'exclusive' OR kind = 'exclusive' ORDER BY id LIMIT 1",
+                    params![kind.as_str()],
+                    |row| {
+                        Ok(LeaseHolder {
+                            command: row.get(0)?,
+                            kind: row.get(1)?,
+                            host: row.get(2)?,
+                            pid: row.get(3)?,
+                            acquired_at: row.get(4)?,
+                        })
+                    },
+                )
+                .optional()?;
+            if let Some(holder) = holder {
+                return Err(holder.into_error());
+            }
+            let id: i64 = tx.query_row(
+                "INSERT INTO leases (kind, command, host, pid, acquired_at, expires_at)
+                 VALUES (?1, ?2, ?3, ?4, ?5, ?6) RETURNING id",
+                params![kind.as_str(), command, host, pid, now, now + LEASE_TTL.as_secs() as i64],
+                |row| row.get(0),
+            )?;
+            tx.commit()?;
+            Ok(id)
+        })?;
+        self.lease = Some(id);
+        self.lease_renewed = Instant::now();
+        Ok(())
+    }
+
+    fn begin_run(&mut self, input_roots: &[String], options: &str) -> Result<i64> {
+        self.acquire_lease(LeaseKind::Shared)?;
+        let roots = serde_json::to_string(input_roots)?;
+        self.conn.execute(
+            "INSERT INTO runs (started_at, input_roots, options) VALUES (?1, ?2, ?3)",
+            params![chrono::Utc::now().timestamp(), roots, options],
+        ).context("Failed to record run")?;
+        let run_id = self.conn.last_insert_rowid();
+        self.claim_run(run_id)?;
+        self.run_id = Some(run_id);
+        self.files_seen = 0;
+        Ok(run_id)
+    }
+
+    fn finish_run(&mut self, status: &str, errors: u64, summary: Option<&RunSummary>) -> Result<()> {
+        self.flush()?;
+        let Some(run_id) = self.run_id.take() else {
+            return Ok(());
+        };
+
+        let summary = summary.map(serde_json::to_string).transpose()?;
+        self.conn.execute(
+            "UPDATE runs SET
+                finished_at = ?2,
+                status = ?3,
+                files_seen = ?4,
+                artifacts_added = (SELECT COUNT(*) FROM artifacts WHERE run_id = ?1),
+                errors = ?5,
+                summary = ?6
+             WHERE id = ?1",
+            params![run_id, chrono::Utc::now().timestamp(), status, self.files_seen, errors, summary],
+        ).context("Failed to finalize run")?;
+        if status == "completed" {
+            self.conn.execute("DELETE FROM run_files WHERE run_id = ?1", params![run_id])?;
+        }
+        self.release_lease()
+    }
+
+    fn resume_run(&mut self, run_id: i64) -> Result<HashMap<String, CommittedFile>> {
+        self.acquire_lease(LeaseKind::Shared)?;
+        let status: Option<String> = self.conn
+            .query_row("SELECT status FROM runs WHERE id = ?1", params![run_id], |row| row.get(0))
+            .optional()?;
+        store::check_resumable(run_id, status.as_deref())?;
+        self.claim_run(run_id)?;
+        self.conn.execute("UPDATE runs SET status = 'running', finished_at = NULL WHERE id = ?1", params![run_id])?;
+        let mut stmt = self.conn.prepare("SELECT path, size_bytes, mtime FROM run_files WHERE run_id = ?1")?;
+        let files = stmt
+            .query_map(params![run_id], |row| Ok((row.get(0)?, CommittedFile { size_bytes: row.get(1)?, mtime: row.get(2)? })))?
+            .collect::<rusqlite::Result<HashMap<_, _>>>()?;
+        drop(stmt);
+        self.run_id = Some(run_id);
+        self.files_seen = files.len() as u64;
+        Ok(files)
+    }
+
+    fn rollback_run(&mut self, run_id: i64, purge: bool) -> Result<usize> {
+        let tx = self.conn.transaction()?;
+
+        // Artifacts a later run found at paths of its own are kept, as that
+        // run's; only the rolled-back run's paths to them go.
+        tx.execute(
+            "UPDATE artifacts SET run_id = (
+                SELECT MAX(p.run_id) FROM artifact_paths p
+                WHERE p.artifact_id = artifacts.id AND p.run_id <> ?1 AND p.deleted_at IS NULL)
+             WHERE run_id = ?1 AND EXISTS (
+                SELECT 1 FROM artifact_paths p
+                WHERE p.artifact_id = artifacts.id AND p.run_id <> ?1 AND p.deleted_at IS NULL)",
+            params![run_id],
+        )?;
+        let removed = if purge {
+            let removed = purge_artifacts(&tx, "SELECT id FROM artifacts WHERE run_id = ?1", &[&run_id])?;
+            tx.execute("DELETE FROM artifact_paths WHERE run_id = ?1", params![run_id])?;
+            removed
+        } else {
+            let now = chrono::Utc::now().timestamp();
+            let reason = format!("rollback of run {}", run_id);
+            tx.execute(
+                "UPDATE artifact_paths SET deleted_at = ?2 WHERE run_id = ?1 AND deleted_at IS NULL",
+                params![run_id, now],
+            )?;
+            tx.execute(
+                "UPDATE artifacts SET deleted_at = ?2, deleted_reason = ?3 WHERE run_id = ?1 AND deleted_at IS NULL",
+                params![run_id, now, reason],
+            )?
+        };
+
+        // Older artifacts whose latest path came from this run point back at a surviving one.
+        tx.execute(
+            "UPDATE artifacts SET original_path = (
+                SELECT p.path FROM artifact_paths p WHERE p.artifact_id = artifacts.id AND p.deleted_at IS NULL
+                ORDER BY p.last_seen DESC LIMIT 1)
+             WHERE NOT EXISTS (
+                SELECT 1 FROM artifact_paths p WHERE p.artifact_id = artifacts.id
+                AND p.path = artifacts.original_path AND p.deleted_at IS NULL)
+             AND EXISTS (SELECT 1 FROM artifact_paths p WHERE p.artifact_id = artifacts.id AND p.deleted_at IS NULL)",
+            [],
+        )?;
+        tx.execute("UPDATE runs SET status = 'rolled_back' WHERE id = ?1", params![run_id])?;
+        tx.execute("DELETE FROM run_files WHERE run_id = ?1", params![run_id])?;
+
+        tx.commit()?;
+        Ok(removed)
+    }
+
+    fn record_error(&mut self, path: &str, stage: &str, error: &str, stderr: Option<&str>, partial: Option<&PartialResult>) -> Result<()> {
+        let partial = partial.map(serde_json::to_string).transpose()?;
+        self.conn.execute(
+            "INSERT INTO ingest_errors (run_id, path, stage, error, created_at, partial, stderr) VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7)",
+            params![self.run_id, path, stage, error, chrono::Utc::now().timestamp(), partial, stderr],
+        ).context("Failed to record ingest error")?;
+        Ok(())
+    }
+
+    fn add(&mut self, record: ArtifactRecord) -> Result<()> {
+        self.buffer.push(record);
+        if self.buffer.len() >= self.buffer_limit {
+            self.flush()
+        } else {
+            self.flush_if_due()
+        }
+    }
+
+    fn flush_if_due(&mut self) -> Result<()> {
+        match self.flush_interval {
+            Some(interval) if self.last_flush.elapsed() >= interval => self.flush(),
+            _ => self.renew_lease(),
+        }
+    }
+
+    fn flush(&mut self) -> Result<()> {
+        self.last_flush = Instant::now();
+        self.renew_lease()?;
+        if self.buffer.is_empty() {
+            return Ok(());
+        }
+
+        retry_on_lock(|| self.write_buffer())?;
+        self.files_seen += self.buffer.len() as u64;
         self.buffer.clear();
         Ok(())
     }
+
+    fn pending(&self) -> usize {
+        self.buffer.len()
+    }
+
+    fn errors(&mut self, run_id: Option<i64>, include_resolved: bool) -> Result<Vec<IngestError>> {
+        let mut stmt = self.conn.prepare(
+            "SELECT id, run_id, path, stage, error, created_at, resolved_at, partial, stderr FROM ingest_errors
+             WHERE (?1 IS NULL OR run_id = ?1) AND (?2 OR resolved_at IS NULL)
+             ORDER BY id"
+        )?;
+        let errors = stmt.query_map(params![run_id, include_resolved], |row| {
+            Ok(IngestError {
+                id: row.get(0)?,
+                run_id: row.get(1)?,
+                path: row.get(2)?,
+                stage: row.get(3)?,
+                error: row.get(4)?,
+                created_at: row.get(5)?,
+                resolved_at: row.get(6)?,
+                partial: parse_partial(row.get(7)?),
+                stderr: row.get(8)?,
+            })
+        })?;
+        Ok(errors.collect::<rusqlite::Result<_>>()?)
+    }
+
+    fn known_hashes(&mut self) -> Result<HashSet<String>> {
+        let mut stmt = self.conn.prepare("SELECT hash_sha256 FROM artifacts")?;
+        let hashes = stmt.query_map([], |row| row.get(0))?;
+        Ok(hashes.collect::<rusqlite::Result<_>>()?)
+    }
+
+    fn catalogued_files(&mut self, root: &str) -> Result<HashMap<String, CommittedFile>> {
+        let (from, to) = store::subtree(root);
+        // Newest content last, so it wins for a path that changed.
+        let mut stmt = self.conn.prepare(
+            "SELECT p.path, a.size_bytes, a.mtime FROM artifact_paths p JOIN artifacts a ON a.id = p.artifact_id
+             WHERE p.path >= ?1 AND p.path < ?2 AND p.deleted_at IS NULL AND a.deleted_at IS NULL ORDER BY a.id",
+        )?;
+        let files = stmt
+            .query_map(params![from, to], |row| Ok((row.get(0)?, CommittedFile { size_bytes: row.get(1)?, mtime: row.get(2)? })))?
+            .collect::<rusqlite::Result<HashMap<_, _>>>()?;
+        Ok(files)
+    }
+
+    fn live_paths(&mut self, hash: &str) -> Result<Vec<String>> {
+        let mut stmt = self.conn.prepare(
+            "SELECT p.path FROM artifact_paths p JOIN artifacts a ON a.id = p.artifact_id
+             WHERE a.hash_sha256 = ?1 AND p.deleted_at IS NULL ORDER BY p.first_seen, p.path",
+        )?;
+        let paths = stmt.query_map(params![hash], |row| row.get(0))?.collect::<rusqlite::Result<Vec<String>>>()?;
+        Ok(paths)
+    }
+
+    fn runs(&mut self) -> Result<Vec<Run>> {
+        let mut stmt = self.conn.prepare(
+            "SELECT id, started_at, finished_at, status, input_roots, options, files_seen, artifacts_added, errors, summary
+             FROM runs ORDER BY id DESC"
+        )?;
+        let runs = stmt.query_map([], |row| {
+            Ok(Run {
+                id: row.get(0)?,
+                started_at: row.get(1)?,
+                finished_at: row.get(2)?,
+                status: row.get(3)?,
+                input_roots: row.get(4)?,
+                options: row.get(5)?,
+                files_seen: row.get(6)?,
+                artifacts_added: row.get(7)?,
+                errors: row.get(8)?,
+                summary: parse_summary(row.get(9)?),
+            })
+        })?;
+        Ok(runs.collect::<rusqlite::Result<_>>()?)
+    }
+
+    fn add_alias(&mut self, alias: &str, tag: &str) -> Result<()> {
+        let (alias, tag) = (Tag::parse(alias), Tag::parse(tag));
+        tags::check_rule(alias, tag)?;
+        let tx = self.conn.transaction()?;
+
+        let target = tag_id(&tx, tag, true)?.context("Failed to create tag")?;
+        let existing: Option<i64> = tx
+            .query_row("SELECT id FROM tags WHERE namespace = ?1 AND name = ?2", params![alias.namespace, alias.name], |row| row.get(0))
+            .optional()?;
+        if existing == Some(target) {
+            bail!("'{}' is already an alias of '{}'", tag, alias);
+        }
+
+        // Fold the old tag into the target so it never shows up again.
+        if let Some(old) = existing {
+            tx.execute(
+                "INSERT OR IGNORE INTO artifact_tags (artifact_id, tag_id) SELECT artifact_id, ?2 FROM artifact_tags WHERE tag_id = ?1",
+                params![old, target],
+            )?;
+            tx.execute("DELETE FROM artifact_tags WHERE tag_id = ?1", params![old])?;
+            tx.execute(
+                "INSERT OR IGNORE INTO tag_implications (tag_id, implied_tag_id)
+                 SELECT CASE WHEN tag_id = ?1 THEN ?2 ELSE tag_id END, CASE WHEN implied_tag_id = ?1 THEN ?2 ELSE implied_tag_id END
+                 FROM tag_implications WHERE tag_id = ?1 OR implied_tag_id = ?1",
+                params![old, target],
+            )?;
+            tx.execute(
+                "DELETE FROM tag_implications WHERE tag_id = ?1 OR implied_tag_id = ?1 OR tag_id = implied_tag_id",
+                params![old],
+            )?;
+            tx.execute("UPDATE tag_aliases SET tag_id = ?2 WHERE tag_id = ?1", params![old, target])?;
+            tx.execute("DELETE FROM tags WHERE id = ?1", params![old])?;
+        }
+
+        tx.execute(
+            "INSERT INTO tag_aliases (namespace, name, tag_id) VALUES (?1, ?2, ?3)
+             ON CONFLICT(namespace, name) DO UPDATE SET tag_id = excluded.tag_id",
+            params![alias.namespace, alias.name, target],
+        )?;
+        apply_implications(&tx)?;
+        tx.commit()?;
+        Ok(())
+    }
+
+    fn remove_alias(&mut self, alias: &str) -> Result<bool> {
+        let alias = Tag::parse(alias);
+        let removed = self.conn.execute(
+            "DELETE FROM tag_aliases WHERE namespace = ?1 AND name = ?2",
+            params![alias.namespace, alias.name],
+        )?;
+        Ok(removed > 0)
+    }
+
+    fn add_implication(&mut self, tag: &str, implied: &str) -> Result<()> {
+        let (tag, implied) = (Tag::parse(tag), Tag::parse(implied));
+        tags::check_rule(tag, implied)?;
+        let tx = self.conn.transaction()?;
+
+        let from = tag_id(&tx, tag, true)?.context("Failed to create tag")?;
+        let to = tag_id(&tx, implied, true)?.context("Failed to create tag")?;
+        let cycle: bool = tx.query_row(
+            &format!("WITH RECURSIVE {} SELECT ?1 = ?2 OR EXISTS (SELECT 1 FROM closure WHERE tag_id = ?2 AND implied_id = ?1)", IMPLIED_CLOSURE),
+            params![from, to],
+            |row| row.get(0),
+        )?;
+        if cycle {
+            bail!("'{}' already implies '{}'", implied, tag);
+        }
+
+        tx.execute(
+            "INSERT OR IGNORE INTO tag_implications (tag_id, implied_tag_id) VALUES (?1, ?2)",
+            params![from, to],
+        )?;
+        apply_implications(&tx)?;
+        tx.commit()?;
+        Ok(())
+    }
+
+    fn remove_implication(&mut self, tag: &str, implied: &str) -> Result<bool> {
+        let tx = self.conn.transaction()?;
+        let removed = match (tag_id(&tx, Tag::parse(tag), false)?, tag_id(&tx, Tag::parse(implied), false)?) {
+            (Some(from), Some(to)) => tx.execute(
+                "DELETE FROM tag_implications WHERE tag_id = ?1 AND implied_tag_id = ?2",
+                params![from, to],
+            )?,
+            _ => 0,
+        };
+        tx.commit()?;
+        Ok(removed > 0)
+    }
+
+    fn tag_rules(&mut self) -> Result<TagRules> {
+        let label = |namespace: String, name: String| Tag { namespace: &namespace, name: &name }.to_string();
+
+        let mut stmt = self.conn.prepare(
+            "SELECT a.namespace, a.name, t.namespace, t.name FROM tag_aliases a JOIN tags t ON t.id = a.tag_id
+             ORDER BY a.namespace, a.name"
+        )?;
+        let aliases = stmt
+            .query_map([], |row| Ok((label(row.get(0)?, row.get(1)?), label(row.get(2)?, row.get(3)?))))?
+            .collect::<rusqlite::Result<_>>()?;
+
+        let mut stmt = self.conn.prepare(
+            "SELECT t.namespace, t.name, u.namespace, u.name FROM tag_implications i
+             JOIN tags t ON t.id = i.tag_id JOIN tags u ON u.id = i.implied_tag_id
+             ORDER BY t.namespace, t.name, u.namespace, u.name"
+        )?;
+        let implications = stmt
+            .query_map([], |row| Ok((label(row.get(0)?, row.get(1)?), label(row.get(2)?, row.get(3)?))))?
+            .collect::<rusqlite::Result<_>>()?;
+
+        Ok(TagRules { aliases, implications })
+    }
+
+    fn relate(&mut self, hash: &str, kind: &str, related_hash: &str) -> Result<()> {
+        let tx = self.conn.transaction()?;
+        let from = artifact_id(&tx, hash)?;
+        let to = artifact_id(&tx, related_hash)?;
+        if from == to {
+            bail!("An artifact cannot be related to itself");
+        }
+        tx.execute(
+            "INSERT OR IGNORE INTO relationships (artifact_id, kind, related_id, created_at) VALUES (?1, ?2, ?3, ?4)",
+            params![from, kind, to, chrono::Utc::now().timestamp()],
+        )?;
+        tx.commit()?;
+        Ok(())
+    }
+
+    fn unrelate(&mut self, hash: &str, kind: &str, related_hash: &str) -> Result<bool> {
+        let removed = self.conn.execute(
+            "DELETE FROM relationships
+             WHERE artifact_id = (SELECT id FROM artifacts WHERE hash_sha256 = ?1) AND kind = ?2
+             AND related_id = (SELECT id FROM artifacts WHERE hash_sha256 = ?3)",
+            params![hash, kind, related_hash],
+        )?;
+        Ok(removed > 0)
+    }
+
+    fn add_tag(&mut self, hash: &str, tag: &str) -> Result<()> {
+        let tag = Tag::parse(tag);
+        if tag.name.is_empty() || tag.is_wildcard() {
+            bail!("'{}' is not a single tag", tag);
+        }
+        let tx = self.conn.transaction()?;
+        let id = artifact_id(&tx, hash)?;
+        let tag_id = tag_id(&tx, tag, true)?.context("Failed to create tag")?;
+        tx.execute("INSERT OR IGNORE INTO artifact_tags (artifact_id, tag_id) VALUES (?1, ?2)", params![id, tag_id])?;
+        apply_implications(&tx)?;
+        tx.commit()?;
+        Ok(())
+    }
+
+    fn add_tags(&mut self, tags: &[(String, String)]) -> Result<usize> {
+        let tx = self.conn.transaction()?;
+        let mut added = 0;
+        for (hash, tag) in tags {
+            let tag = Tag::parse(tag);
+            if tag.name.is_empty() || tag.is_wildcard() {
+                continue;
+            }
+            let Some(id) = tx.query_row("SELECT id FROM artifacts WHERE hash_sha256 = ?1", params![hash], |row| row.get::<_, i64>(0)).optional()? else {
+                continue;
+            };
+            let tag_id = tag_id(&tx, tag, true)?.context("Failed to create tag")?;
+            added += tx.execute("INSERT OR IGNORE INTO artifact_tags (artifact_id, tag_id) VALUES (?1, ?2)", params![id, tag_id])?;
+        }
+        apply_implications(&tx)?;
+        tx.commit()?;
+        Ok(added)
+    }
+
+    fn remove_tag(&mut self, hash: &str, tag: &str) -> Result<bool> {
+        let tx = self.conn.transaction()?;
+        let id = artifact_id(&tx, hash)?;
+        let removed = match tag_id(&tx, Tag::parse(tag), false)? {
+            Some(tag_id) => tx.execute("DELETE FROM artifact_tags WHERE artifact_id = ?1 AND tag_id = ?2", params![id, tag_id])?,
+            None => 0,
+        };
+        tx.commit()?;
+        Ok(removed > 0)
+    }
+}
+
+/// An artifact as read back from the catalog, with its tags and score.
+/// This is also the line format of `export`/`import --format jsonl`.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Artifact {
+    /// Row id in the source catalog; ignored on import.
+    #[serde(default)]
+    pub id: i64,
+    pub hash_sha256: String,
+    pub original_path: String,
+    pub media_type: String,
+    pub size_bytes: Option<u64>,
+    pub mtime: Option<i64>,
+    pub width: Option<u32>,
+    pub height: Option<u32>,
+    pub nsfw_score: Option<f32>,
+    #[serde(default)]
+    pub nsfw_model_version: Option<String>,
+    #[serde(default)]
+    pub tags: Vec<String>,
+    pub metadata: Option<serde_json::Value>,
+    /// Unix seconds the artifact was tombstoned; only set with `include_deleted`.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub deleted_at: Option<i64>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub deleted_reason: Option<String>,
+}
+
+impl From<Artifact> for ArtifactRecord {
+    fn from(artifact: Artifact) -> Self {
+        ArtifactRecord {
+            hash_sha256: artifact.hash_sha256,
+            original_path: artifact.original_path,
+            media_type: artifact.media_type,
+            size_bytes: artifact.size_bytes,
+            mtime: artifact.mtime,
+            width: artifact.width,
+            height: artifact.height,
+            tags: artifact.tags,
+            nsfw_score: artifact.nsfw_score,
+            nsfw_model_version: artifact.nsfw_model_version,
+            embeddings: Vec::new(),
+            metadata: artifact.metadata,
+            source: None,
+            timings: None,
+        }
+    }
+}
+
+/// One location an artifact's content was seen at.
+#[derive(Debug, Clone, Serialize)]
+pub struct ArtifactPath {
+    pub path: String,
+    /// Unix seconds.
+    pub first_seen: i64,
+    pub last_seen: i64,
+    /// Volume the path was last seen on, if known.
+    pub source: Option<Source>,
+}
+
+/// A link from or to an artifact, as seen from that artifact.
+#[derive(Debug, Clone, Serialize)]
+pub struct Relationship {
+    /// e.g. `thumbnail-of`.
+    pub kind: String,
+    /// True for `this <kind> other`, false for `other <kind> this`.
+    pub outgoing: bool,
+    pub hash_sha256: String,
+    pub original_path: String,
+}
+
+/// A volume from the `sources` table with the number of paths seen on it.
+#[derive(Debug, Clone, Serialize)]
+pub struct SourceSummary {
+    pub id: i64,
+    pub source: Source,
+    /// Unix seconds.
+    pub first_seen: i64,
+    pub last_seen: i64,
+    pub paths: u64,
+}
+
+/// A file as it was when committed, by an unfinished run or to the catalog.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct CommittedFile {
+    pub size_bytes: Option<u64>,
+    pub mtime: Option<i64>,
+}
+
+/// One ingest invocation, as recorded in the `runs` table.
+#[derive(Debug, Clone, Serialize)]
+pub struct Run {
+    pub id: i64,
+    pub started_at: i64,
+    pub finished_at: Option<i64>,
+    pub status: String,
+    pub input_roots: String,
+    pub options: Option<String>,
+    pub files_seen: u64,
+    pub artifacts_added: u64,
+    pub errors: u64,
+    /// For ingest runs that ended; unreadable summaries are left out.
+    pub summary: Option<RunSummary>,
+}
+
+/// Reads a `runs.summary` column.
+pub fn parse_summary(json: Option<String>) -> Option<RunSummary> {
+    serde_json::from_str(&json?).ok()
+}
+
+/// Reads an `ingest_errors.partial` column; unreadable ones are left out.
+pub fn parse_partial(json: Option<String>) -> Option<PartialResult> {
+    serde_json::from_str(&json?).ok()
+}
+
+/// Tag aliases and implications as `(from, to)` labels.
+#[derive(Debug, Clone, Default, Serialize)]
+pub struct TagRules {
+    /// `(alias, canonical tag)`.
+    pub aliases: Vec<(String, String)>,
+    /// `(tag, implied tag)`.
+    pub implications: Vec<(String, String)>,
+}
+
+/// Outcome of re-hashing one path.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum FixityResult {
+    /// Content still matches the catalogued hash.
+    Ok,
+    Mismatch,
+    Missing,
+    /// The file exists but could not be read.
+    Error,
+}
+
+impl FixityResult {
+    pub fn as_str(self) -> &'static str {
+        match self {
+            FixityResult::Ok => "ok",
+            FixityResult::Mismatch => "mismatch",
+            FixityResult::Missing => "missing",
+            FixityResult::Error => "error",
+        }
+    }
+}
+
+/// One row of `fixity_checks`.
+#[derive(Debug, Clone)]
+pub struct FixityCheck {
+    pub artifact_id: i64,
+    pub path: String,
+    /// Unix seconds.
+    pub checked_at: i64,
+    pub algo: &'static str,
+    pub result: FixityResult,
+    /// The differing hash or the I/O error.
+    pub detail: Option<String>,
+}
+
+/// One archive file written by `archive`, a row of `archive_volumes`.
+#[derive(Debug, Clone, Serialize)]
+pub struct ArchiveVolume {
+    /// Unique name to write on the disc or tape, e.g. `BD-0042`.
+    pub label: String,
+    /// File name of the image or tarball, e.g. `archive_002.iso`; for tapes
+    /// the barcode, or the label where the tape has none.
+    pub name: String,
+    /// `iso`, `udf`, `tar.zst`, `zip`, `bagit`, `ltfs` or `tape`.
+    pub format: String,
+    /// Unix seconds.
+    pub created_at: i64,
+    /// The `--volume-size` it was packed for, if any.
+    pub capacity_bytes: Option<u64>,
+    pub size_bytes: u64,
+    /// SHA-256 of the whole image or tarball.
+    pub sha256: Option<String>,
+    /// Barcode of the tape it was written to.
+    pub barcode: Option<String>,
+}
+
+/// A file on an archive volume, a row of `archive_members`.
+#[derive(Debug, Clone, Serialize)]
+pub struct VolumeFile {
+    /// Path on the volume.
+    pub path: String,
+    pub hash: String,
+    pub size: u64,
+    /// Where the content starts in the volume image, where it is stored
+    /// there uncompressed and in one piece.
+    pub offset: Option<u64>,
+}
+
+/// A registered volume with the number of files on it.
+#[derive(Debug, Clone, Serialize)]
+pub struct VolumeSummary {
+    pub id: i64,
+    #[serde(flatten)]
+    pub volume: ArchiveVolume,
+    pub files: u64,
+    pub file_bytes: u64,
+    /// Discs burned from it and read back intact.
+    pub discs: u64,
+    /// Copies uploaded to remotes.
+    pub uploads: u64,
+}
+
+/// A disc burned from a volume image, a row of `volume_discs`.
+#[derive(Debug, Clone, Serialize)]
+pub struct VolumeDisc {
+    /// Label of the volume burned.
+    pub label: String,
+    /// Unix seconds.
+    pub burned_at: i64,
+    /// Drive it was burned in, e.g. `/dev/sr0`.
+    pub device: String,
+    /// Vendor, model and serial number of the drive, where known.
+    pub drive: Option<String>,
+    /// Media type and manufacturer id reported for the disc, where known.
+    pub media: Option<String>,
+    /// Whether the disc read back identical to the image; `None` if it
+    /// wasn't read back.
+    pub verified: Option<bool>,
+}
+
+/// A copy of a volume in a cloud bucket, a row of `volume_uploads`.
+#[derive(Debug, Clone, Serialize)]
+pub struct VolumeUpload {
+    /// Label of the volume uploaded.
+    pub label: String,
+    /// Name of the remote in the config.
+    pub remote: String,
+    pub bucket: String,
+    /// Key of the volume file, or the prefix its files are stored under.
+    pub key: String,
+    /// `image` for the volume file as one object, `objects` for each of its
+    /// files at `<key>objects/ab/cd/<sha256>`, as in a `cas` volume.
+    pub layout: String,
+    /// Version id of the object where the bucket keeps versions, else its
+    /// ETag; `None` for `objects` uploads.
+    pub object_id: Option<String>,
+    pub storage_class: Option<String>,
+    pub size_bytes: u64,
+    /// Unix seconds.
+    pub uploaded_at: i64,
+}
+
+/// Where an artifact's content was archived.
+#[derive(Debug, Clone, Serialize)]
+pub struct ArchiveCopy {
+    /// Label of the volume.
+    pub label: String,
+    /// File name of the volume.
+    pub volume: String,
+    /// Location inside the volume.
+    pub path: String,
+    /// Unix seconds the volume was written.
+    pub created_at: i64,
+}
+
+/// A recorded fixity check of one artifact, as read back.
+#[derive(Debug, Clone, Serialize)]
+pub struct FixityEvent {
+    pub path: String,
+    /// Unix seconds.
+    pub checked_at: i64,
+    pub algo: String,
+    /// `ok`, `mismatch`, `missing` or `error`; see `FixityResult`.
+    pub result: String,
+    pub detail: Option<String>,
+}
+
+/// Totals over the whole fixity history.
+#[derive(Debug, Clone, Default, Serialize)]
+pub struct FixityStats {
+    pub checks: u64,
+    pub ok: u64,
+    pub mismatch: u64,
+    pub missing: u64,
+    pub error: u64,
+    /// Live artifacts without a single check.
+    pub never_verified: u64,
+    /// Unix seconds of the oldest and newest check.
+    pub first_check: Option<i64>,
+    pub last_check: Option<i64>,
+}
+
+/// Live artifacts of one mimetype, from the `media_type_stats` view.
+#[derive(Debug, Clone, Serialize)]
+pub struct MediaTypeStats {
+    pub media_type: String,
+    pub artifacts: u64,
+    pub bytes: u64,
+}
+
+/// A tag with the number of live artifacts carrying it, from `tag_stats`.
+#[derive(Debug, Clone, Serialize)]
+pub struct TagCount {
+    pub tag: String,
+    pub artifacts: u64,
+}
+
+/// Live artifacts with an NSFW score in `[lower, lower + 0.1)`; the last
+/// bucket includes 1.0.
+#[derive(Debug, Clone, Serialize)]
+pub struct ScoreBucket {
+    pub lower: f32,
+    pub artifacts: u64,
+}
+
+/// Size and speed of one run, from the `run_stats` view.
+#[derive(Debug, Clone, Serialize)]
+pub struct RunStats {
+    pub run_id: i64,
+    pub started_at: i64,
+    pub finished_at: Option<i64>,
+    pub status: String,
+    pub files_seen: u64,
+    pub artifacts_added: u64,
+    pub errors: u64,
+    /// Size of the artifacts the run discovered.
+    pub bytes_added: u64,
+    /// `None` while the run hasn't finished.
+    pub duration_secs: Option<i64>,
+    pub files_per_sec: Option<f64>,
+}
+
+/// A file and the time ingest spent on it, from `file_timings`.
+#[derive(Debug, Clone, Serialize)]
+pub struct SlowFile {
+    pub run_id: i64,
+    pub path: String,
+    pub hash_sha256: String,
+    pub size_bytes: Option<u64>,
+    #[serde(flatten)]
+    pub timings: FileTimings,
+}
+
+/// Catalog-wide aggregates for `stats` and dashboards.
+#[derive(Debug, Clone, Serialize)]
+pub struct CatalogStats {
+    pub artifacts: u64,
+    pub bytes: u64,
+    /// Live locations, counting every copy.
+    pub paths: u64,
+    /// Tombstoned artifacts, not included anywhere else.
+    pub deleted: u64,
+    /// Largest first.
+    pub media_types: Vec<MediaTypeStats>,
+    pub top_tags: Vec<TagCount>,
+    pub nsfw_histogram: Vec<ScoreBucket>,
+    /// Newest first.
+    pub runs: Vec<RunStats>,
+    /// Slowest first; only as many as asked for.
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub slowest: Vec<SlowFile>,
+}
+
+/// Content stored at more than one path, from the `duplicate_groups` view.
+#[derive(Debug, Clone, Serialize)]
+pub struct DuplicateGroup {
+    pub artifact_id: i64,
+    pub hash_sha256: String,
+    pub size_bytes: Option<u64>,
+    pub copies: u64,
+    /// Bytes that could be reclaimed by keeping a single copy.
+    pub wasted_bytes: u64,
+}
+
+/// A failure recorded by an ingest stage.
+#[derive(Debug, Clone)]
+pub struct IngestError {
+    pub id: i64,
+    pub run_id: Option<i64>,
+    pub path: String,
+    /// `scan`, `hash` or an analysis stage: `mimetype`, `models` (`decode`
+    /// before analysis was split into stages) or `preview`.
+    pub stage: String,
+    pub error: String,
+    pub created_at: i64,
+    pub resolved_at: Option<i64>,
+    /// Set when the file failed after it was hashed.
+    pub partial: Option<PartialResult>,
+    /// The tail of ffmpeg's stderr, when ffmpeg failed.
+    pub stderr: Option<String>,
+}
+
+/// What a file that failed got through: its hash, and its type once that
+/// was detected. Replaying it skips those stages while the file keeps the
+/// same size and mtime.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct PartialResult {
+    pub hash_sha256: String,
+    pub size_bytes: u64,
+    pub mtime: Option<i64>,
+    pub device: Option<u64>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub media_type: Option<String>,
+}
+
+/// Predicates for selecting artifacts. All set fields must match.
+#[derive(Debug, Clone, Default)]
+pub struct FilterSet {
+    /// Every one of these tags must be present.
+    pub all_tags: Vec<String>,
+    /// At least one of these tags must be present (ignored if empty).
+    pub any_tags: Vec<String>,
+    /// None of these tags may be present.
+    pub exclude_tags: Vec<String>,
+    /// Exact mimetypes or `type/*` wildcards; any may match.
+    pub media_types: Vec<String>,
+    pub min_nsfw: Option<f32>,
+    pub max_nsfw: Option<f32>,
+    pub min_size: Option<u64>,
+    pub max_size: Option<u64>,
+    /// Unix seconds, inclusive.
+    pub modified_after: Option<i64>,
+    /// Unix seconds, exclusive.
+    pub modified_before: Option<i64>,
+    /// Only artifacts first discovered by this run.
+    pub run_id: Option<i64>,
+    /// FTS5 query over path and tags, e.g. `beach AND sunset`.
+    pub search: Option<String>,
+    /// Case-insensitive substring of the EXIF camera model.
+    pub camera_model: Option<String>,
+    /// Seconds, inclusive; only artifacts with a known duration match.
+    pub min_duration: Option<f64>,
+    pub max_duration: Option<f64>,
+    /// Also match tombstoned artifacts (hidden by default).
+    pub include_deleted: bool,
+    /// Only the artifact with exactly this content hash.
+    pub hash: Option<String>,
+    /// Only artifacts linked to the artifact with this hash, in either direction.
+    pub related_to: Option<String>,
+    /// Only artifacts not derived from another one (no outgoing relationship).
+    pub originals_only: bool,
+    /// Unix seconds: only artifacts without a successful fixity check since then.
+    pub unverified_since: Option<i64>,
+    /// `(model_name, model_version)`: only artifacts lacking a vector from exactly this model version.
+    pub stale_embedding: Option<(String, String)>,
+    /// `(lat, lon, radius_m)`: only geotagged artifacts within this distance.
+    pub near: Option<(f64, f64, f64)>,
+    /// `(min_lat, min_lon, max_lat, max_lon)`, inclusive; `min_lon > max_lon`
+    /// spans the antimeridian.
+    pub bbox: Option<(f64, f64, f64, f64)>,
+    /// Only artifacts with a higher row id, to page through results by
+    /// the id of the last one seen.
+    pub after_id: Option<i64>,
+    pub limit: Option<usize>,
+}
+
+// Builder API for programmatic use; the CLI fills the fields directly.
+#[allow(dead_code)]
+impl FilterSet {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn tag(mut self, tag: impl Into<String>) -> Self {
+        self.all_tags.push(tag.into());
+        self
+    }
+
+    pub fn any_tag(mut self, tag: impl Into<String>) -> Self {
+        self.any_tags.push(tag.into());
+        self
+    }
+
+    pub fn exclude_tag(mut self, tag: impl Into<String>) -> Self {
+        self.exclude_tags.push(tag.into());
+        self
+    }
+
+    pub fn media_type(mut self, media_type: impl Into<String>) -> Self {
+        self.media_types.push(media_type.into());
+        self
+    }
+
+    pub fn nsfw_between(mut self, min: Option<f32>, max: Option<f32>) -> Self {
+        self.min_nsfw = min;
+        self.max_nsfw = max;
+        self
+    }
+
+    pub fn size_between(mut self, min: Option<u64>, max: Option<u64>) -> Self {
+        self.min_size = min;
+        self.max_size = max;
+        self
+    }
+
+    pub fn modified_between(mut self, after: Option<i64>, before: Option<i64>) -> Self {
+        self.modified_after = after;
+        self.modified_before = before;
+        self
+    }
+
+    pub fn search(mut self, query: impl Into<String>) -> Self {
+        self.search = Some(query.into());
+        self
+    }
+
+    pub fn camera_model(mut self, camera: impl Into<String>) -> Self {
+        self.camera_model = Some(camera.into());
+        self
+    }
+
+    pub fn duration_between(mut self, min: Option<f64>, max: Option<f64>) -> Self {
+        self.min_duration = min;
+        self.max_duration = max;
+        self
+    }
+
+    pub fn stale_embedding(mut self, model_name: impl Into<String>, model_version: impl Into<String>) -> Self {
+        self.stale_embedding = Some((model_name.into(), model_version.into()));
+        self
+    }
+
+    pub fn hash(mut self, hash: impl Into<String>) -> Self {
+        self.hash = Some(hash.into());
+        self
+    }
+
+    pub fn related_to(mut self, hash: impl Into<String>) -> Self {
+        self.related_to = Some(hash.into());
+        self
+    }
+
+    pub fn originals_only(mut self) -> Self {
+        self.originals_only = true;
+        self
+    }
+
+    pub fn unverified_since(mut self, since: i64) -> Self {
+        self.unverified_since = Some(since);
+        self
+    }
+
+    pub fn near(mut self, lat: f64, lon: f64, radius_m: f64) -> Self {
+        self.near = Some((lat, lon, radius_m));
+        self
+    }
+
+    pub fn bbox(mut self, min_lat: f64, min_lon: f64, max_lat: f64, max_lon: f64) -> Self {
+        self.bbox = Some((min_lat, min_lon, max_lat, max_lon));
+        self
+    }
+
+    pub fn include_deleted(mut self) -> Self {
+        self.include_deleted = true;
+        self
+    }
+
+    pub fn after_id(mut self, id: i64) -> Self {
+        self.after_id = Some(id);
+        self
+    }
+
+    pub fn limit(mut self, limit: usize) -> Self {
+        self.limit = Some(limit);
+        self
+    }
+}
+
+impl FilterSet {
+    /// Compiles the predicates into a SQL `WHERE` fragment over `artifacts a`
+    /// and `safety_scores s`, with positional parameters.
+    fn to_sql(&self) -> (String, Vec<Value>) {
+        const HAS_TAG: &str = "SELECT 1 FROM artifact_tags at JOIN tags t ON t.id = at.tag_id WHERE at.artifact_id = a.id";
+
+        let mut clauses = Vec::new();
+        let mut values = Vec::new();
+
+        for tag in &self.all_tags {
+            clauses.push(format!("EXISTS ({} AND {})", HAS_TAG, tag_match(&[tag], &mut values)));
+        }
+
+        if !self.any_tags.is_empty() {
+            clauses.push(format!("EXISTS ({} AND {})", HAS_TAG, tag_match(&self.any_tags, &mut values)));
+        }
+
+        if !self.exclude_tags.is_empty() {
+            clauses.push(format!("NOT EXISTS ({} AND {})", HAS_TAG, tag_match(&self.exclude_tags, &mut values)));
+        }
+
+        if !self.media_types.is_empty() {
+            let mut alternatives = Vec::new();
+            for media_type in &self.media_types {
+                match media_type.strip_suffix("/*") {
+                    Some(top) => {
+                        alternatives.push("a.media_type LIKE ?");
+                        values.push(Value::Text(format!("{}/%", top)));
+                    }
+                    None => {
+                        alternatives.push("a.media_type = ?");
+                        values.push(Value::Text(media_type.clone()));
+                    }
+                }
+            }
+            clauses.push(format!("({})", alternatives.join(" OR ")));
+        }
+
+        if let Some(min) = self.min_nsfw {
+            clauses.push("s.nsfw_score >= ?".to_string());
+            values.push(Value::Real(min as f64));
+        }
+
+        if let Some(max) = self.max_nsfw {
+            clauses.push("s.nsfw_score <= ?".to_string());
+            values.push(Value::Real(max as f64));
+        }
+
+        if let Some(min) = self.min_size {
+            clauses.push("a.size_bytes >= ?".to_string());
+            values.push(Value::Integer(min as i64));
+        }
+
+        if let Some(max) = self.max_size {
+            clauses.push("a.size_bytes <= ?".to_string());
+            values.push(Value::Integer(max as i64));
+        }
+
+        if let Some(after) = self.modified_after {
+            clauses.push("a.mtime >= ?".to_string());
+            values.push(Value::Integer(after));
+        }
+
+        if let Some(before) = self.modified_before {
+            clauses.push("a.mtime < ?".to_string());
+            values.push(Value::Integer(before));
+        }
+
+        if let Some(run_id) = self.run_id {
+            clauses.push("a.run_id = ?".to_string());
+            values.push(Value::Integer(run_id));
+        }
+
+        if let Some(search) = &self.search {
+            clauses.push("a.id IN (SELECT rowid FROM search_index WHERE search_index MATCH ?)".to_string());
+            values.push(Value::Text(search.clone()));
+        }
+
+        if let Some(camera) = &self.camera_model {
+            clauses.push("a.camera_model LIKE ?".to_string());
+            values.push(Value::Text(format!("%{}%", camera)));
+        }
+
+        if let Some(min) = self.min_duration {
+            clauses.push("a.duration >= ?".to_string());
+            values.push(Value::Real(min));
+        }
+
+        if let Some(max) = self.max_duration {
+            clauses.push("a.duration <= ?".to_string());
+            values.push(Value::Real(max));
+        }
+
+        if let Some((model_name, model_version)) = &self.stale_embedding {
+            clauses.push("NOT EXISTS (SELECT 1 FROM embeddings e WHERE e.artifact_id = a.id AND e.model_name = ? AND e.model_version = ?)".to_string());
+            values.push(Value::Text(model_name.clone()));
+            values.push(Value::Text(model_version.clone()));
+        }
+
+        if let Some(hash) = &self.hash {
+            clauses.push("a.hash_sha256 = ?".to_string());
+            values.push(Value::Text(hash.clone()));
+        }
+
+        if let Some(hash) = &self.related_to {
+            clauses.push(
+                "a.id IN (SELECT r.artifact_id FROM relationships r JOIN artifacts o ON o.id = r.related_id WHERE o.hash_sha256 = ?
+                 UNION SELECT r.related_id FROM relationships r JOIN artifacts o ON o.id = r.artifact_id WHERE o.hash_sha256 = ?)".to_string(),
+            );
+            values.push(Value::Text(hash.clone()));
+            values.push(Value::Text(hash.clone()));
+        }
+
+        if self.originals_only {
+            clauses.push("NOT EXISTS (SELECT 1 FROM relationships r WHERE r.artifact_id = a.id)".to_string());
+        }
+
+        if let Some(since) = self.unverified_since {
+            clauses.push("NOT EXISTS (SELECT 1 FROM fixity_checks f WHERE f.artifact_id = a.id AND f.result = 'ok' AND f.checked_at >= ?)".to_string());
+            values.push(Value::Integer(since));
+        }
+
+        if let Some((min_lat, min_lon, max_lat, max_lon)) = self.bbox {
+            clauses.push(bbox_clause(min_lat, min_lon, max_lat, max_lon, &mut values));
+        }
+
+        if let Some((lat, lon, radius_m)) = self.near {
+            // The bounding box lets idx_artifacts_gps narrow the scan; the
+            // distance itself is equirectangular, scaling longitude by the
+            // cosine at the centre (SQLite has no trig functions by default).
+            // That is well within 1% for radii up to a few hundred km. Both
+            // wrap around the antimeridian.
+            let dlat = radius_m / METERS_PER_DEGREE;
+            let cos_lat = lat.to_radians().cos();
+            let dlon = if cos_lat > 1e-9 { dlat / cos_lat } else { 180.0 };
+            if dlon < 180.0 {
+                let wrap = |lon: f64| if lon < -180.0 { lon + 360.0 } else if lon > 180.0 { lon - 360.0 } else { lon };
+                clauses.push(bbox_clause(lat - dlat, wrap(lon - dlon), lat + dlat, wrap(lon + dlon), &mut values));
+            } else {
+                clauses.push(bbox_clause(lat - dlat, -180.0, lat + dlat, 180.0, &mut values));
+            }
+            let dlon = "min(abs(a.gps_lon - ?), 360 - abs(a.gps_lon - ?))";
+            clauses.push(format!("(a.gps_lat - ?) * (a.gps_lat - ?) + {dlon} * {dlon} * ? <= ?"));
+            values.extend([lat, lat, lon, lon, lon, lon, cos_lat * cos_lat, dlat * dlat].map(Value::Real));
+        }
+
+        if let Some(id) = self.after_id {
+            clauses.push("a.id > ?".to_string());
+            values.push(Value::Integer(id));
+        }
+
+        if !self.include_deleted {
+            clauses.push("a.deleted_at IS NULL".to_string());
+        }
+
+        if clauses.is_empty() {
+            ("1".to_string(), values)
+        } else {
+            (clauses.join(" AND "), values)
+        }
+    }
+
+    /// True if no predicate narrows the selection (tombstones and `limit` aside).
+    pub fn is_unrestricted(&self) -> bool {
+        let mut all = self.clone();
+        all.include_deleted = true;
+        all.to_sql().0 == "1"
+    }
+}
+
+/// Mean Earth radius times pi / 180.
+const METERS_PER_DEGREE: f64 = 111_195.08;
+
+/// Latitude/longitude range over the indexed GPS columns; a `min_lon` east of
+/// `max_lon` wraps around the antimeridian.
+fn bbox_clause(min_lat: f64, min_lon: f64, max_lat: f64, max_lon: f64, values: &mut Vec<Value>) -> String {
+    values.extend([min_lat, max_lat, min_lon, max_lon].map(Value::Real));
+    if min_lon <= max_lon {
+        "a.gps_lat BETWEEN ? AND ? AND a.gps_lon BETWEEN ? AND ?".to_string()
+    } else {
+        "a.gps_lat BETWEEN ? AND ? AND (a.gps_lon >= ? OR a.gps_lon <= ?)".to_string()
+    }
+}
+
+/// Matches `tags t` against any of the given labels; `ns:*` matches the whole
+/// namespace and aliases match the tag they stand for.
+fn tag_match<S: AsRef<str>>(labels: &[S], values: &mut Vec<Value>) -> String {
+    let alternatives: Vec<&str> = labels
+        .iter()
+        .map(|label| {
+            let tag = Tag::parse(label.as_ref());
+            values.push(Value::Text(tag.namespace.to_string()));
+            if tag.is_wildcard() {
+                "t.namespace = ?"
+            } else {
+                values.push(Value::Text(tag.name.to_string()));
+                values.push(Value::Text(tag.namespace.to_string()));
+                values.push(Value::Text(tag.name.to_string()));
+                "(t.namespace = ? AND t.name = ?) OR t.id IN (SELECT tag_id FROM tag_aliases WHERE namespace = ? AND name = ?)"
+            }
+        })
+        .collect();
+    format!("({})", alternatives.join(" OR "))
+}
+
+/// Read-only access to the catalog. Every command that selects artifacts
+/// (query, export, reports, archive selection) goes through this.
+pub struct CatalogReader {
+    conn: PooledConnection<SqliteConnectionManager>,
+}
+
+/// Read-only connections to the catalog. In WAL mode readers see the last
+/// committed state without blocking the ingest writer, so several readers
+/// (query, export, a server) can work against a live catalog.
+#[derive(Clone)]
+pub struct ReaderPool {
+    pool: Pool<SqliteConnectionManager>,
+}
+
+impl ReaderPool {
+    pub fn open(path: &str, config: &DatabaseConfig) -> Result<Self> {
+        store::require_sqlite(path)?;
+        let key = encryption::key(config)?;
+        let init_config = config.clone();
+        let manager = SqliteConnectionManager::file(path)
+            .with_flags(OpenFlags::SQLITE_OPEN_READ_ONLY | OpenFlags::SQLITE_OPEN_NO_MUTEX)
+            .with_init(move |conn| configure(conn, &init_config, key, false));
+        if key.is_some() {
+            // The pool would keep retrying a wrong key until its timeout.
+            manager.connect().context("Failed to unlock the encrypted database; is the key right?")?;
+        }
+        let pool = Pool::builder()
+            .max_size(config.read_pool_size)
+            .min_idle(Some(0))
+            .build(manager)
+            .with_context(|| format!("Failed to open database read-only: {}", path))?;
+
+        schema::check_version(&*pool.get()?)?;
+        Ok(Self { pool })
+    }
+
+    /// Borrows a connection, waiting for one to free up if all are in use.
+    pub fn reader(&self) -> Result<CatalogReader> {
+        Ok(CatalogReader { conn: self.pool.get()? })
+    }
+}
+
+const PAGE_SIZE: usize = 1000;
+
+impl CatalogReader {
+    /// Opens a one-off reader; long-lived callers should keep a `ReaderPool`.
+    pub fn open(path: &str, config: &DatabaseConfig) -> Result<Self> {
+        ReaderPool::open(path, config)?.reader()
+    }
+
+    /// Streams matching artifacts in id order. Rows are fetched in pages,
+    /// so memory stays bounded regardless of catalog size.
+    pub fn find(&self, filter: &FilterSet) -> impl Iterator<Item = Result<Artifact>> + '_ {
+        let (where_sql, values) = filter.to_sql();
+        ArtifactIter {
+            conn: &self.conn,
+            where_sql,
+            values,
+            last_id: 0,
+            remaining: filter.limit,
+            page: VecDeque::new(),
+            done: false,
+        }
+    }
+
+    /// All known locations of an artifact, most recently seen first.
+    pub fn paths(&self, artifact_id: i64) -> Result<Vec<ArtifactPath>> {
+        let mut stmt = self.conn.prepare_cached(
+            "SELECT p.path, p.first_seen, p.last_seen, s.host, s.fs_uuid, s.label, s.mount_point
+             FROM artifact_paths p LEFT JOIN sources s ON s.id = p.source_id
+             WHERE p.artifact_id = ?1 AND p.deleted_at IS NULL ORDER BY p.last_seen DESC, p.path"
+        )?;
+        let paths = stmt.query_map(params![artifact_id], |row| {
+            let host: Option<String> = row.get(3)?;
+            Ok(ArtifactPath {
+                path: row.get(0)?,
+                first_seen: row.get(1)?,
+                last_seen: row.get(2)?,
+                source: host.map(|host| -> rusqlite::Result<_> {
+                    Ok(Source { host, fs_uuid: row.get(4)?, label: row.get(5)?, mount_point: row.get(6)? })
+                }).transpose()?,
+            })
+        })?;
+        Ok(paths.collect::<rusqlite::Result<_>>()?)
+    }
+
+    /// The ingest run that first catalogued the artifact, if it was
+    /// ingested since runs were recorded.
+    pub fn discovery_run(&self, artifact_id: i64) -> Result<Option<Run>> {
+        let mut stmt = self.conn.prepare_cached(
+            "SELECT r.id, r.started_at, r.finished_at, r.status, r.input_roots, r.options, r.files_seen, r.artifacts_added, r.errors, r.summary
+             FROM artifacts a JOIN runs r ON r.id = a.run_id WHERE a.id = ?1"
+        )?;
+        let run = stmt
+            .query_row(params![artifact_id], |row| {
+                Ok(Run {
+                    id: row.get(0)?,
+                    started_at: row.get(1)?,
+                    finished_at: row.get(2)?,
+                    status: row.get(3)?,
+                    input_roots: row.get(4)?,
+                    options: row.get(5)?,
+                    files_seen: row.get(6)?,
+                    artifacts_added: row.get(7)?,
+                    errors: row.get(8)?,
+                    summary: parse_summary(row.get(9)?),
+                })
+            })
+            .optional()?;
+        Ok(run)
+    }
+
+    /// The artifact's fixity history, oldest first.
+    pub fn fixity_history(&self, artifact_id: i64) -> Result<Vec<FixityEvent>> {
+        let mut stmt = self.conn.prepare_cached(
+            "SELECT path, checked_at, algo, result, detail FROM fixity_checks
+             WHERE artifact_id = ?1 ORDER BY checked_at, id"
+        )?;
+        let events = stmt.query_map(params![artifact_id], |row| {
+            Ok(FixityEvent { path: row.get(0)?, checked_at: row.get(1)?, algo: row.get(2)?, result: row.get(3)?, detail: row.get(4)? })
+        })?;
+        Ok(events.collect::<rusqlite::Result<_>>()?)
+    }
+
+    /// Every archive volume holding the artifact's content, oldest first.
+    pub fn archive_copies(&self, artifact_id: i64) -> Result<Vec<ArchiveCopy>> {
+        let mut stmt = self.conn.prepare_cached(
+            "SELECT v.label, v.name, m.path, v.created_at
+             FROM archive_members m JOIN archive_volumes v ON v.id = m.volume_id
+             WHERE m.hash_sha256 = (SELECT hash_sha256 FROM artifacts WHERE id = ?1)
+             ORDER BY v.created_at, v.id"
+        )?;
+        let copies = stmt.query_map(params![artifact_id], |row| {
+            Ok(ArchiveCopy { label: row.get(0)?, volume: row.get(1)?, path: row.get(2)?, created_at: row.get(3)? })
+        })?;
+        Ok(copies.collect::<rusqlite::Result<_>>()?)
+    }
+
+    /// The volume registry, oldest first.
+    pub fn volumes(&self) -> Result<Vec<VolumeSummary>> {
+        let mut stmt = self.conn.prepare(
+            "SELECT v.id, v.label, v.name, v.format, v.created_at, v.capacity_bytes, v.size_bytes, v.sha256, v.barcode,
+                    COUNT(m.path), COALESCE(SUM(m.size_bytes), 0),
+                    (SELECT COUNT(*) FROM volume_discs d WHERE d.volume_id = v.id AND d.verified = 1),
+                    (SELECT COUNT(*) FROM volume_uploads u WHERE u.volume_id = v.id)
+             FROM archive_volumes v LEFT JOIN archive_members m ON m.volume_id = v.id
+             GROUP BY v.id ORDER BY v.created_at, v.id"
+        )?;
+        let volumes = stmt.query_map([], |row| {
+            Ok(VolumeSummary {
+                id: row.get(0)?,
+                volume: ArchiveVolume {
+                    label: row.get(1)?,
+                    name: row.get(2)?,
+                    format: row.get(3)?,
+                    created_at: row.get(4)?,
+                    capacity_bytes: row.get(5)?,
+                    size_bytes: row.get(6)?,
+                    sha256: row.get(7)?,
+                    barcode: row.get(8)?,
+                },
+                files: row.get(9)?,
+                file_bytes: row.get(10)?,
+                discs: row.get(11)?,
+                uploads: row.get(12)?,
+            })
+        })?;
+        Ok(volumes.collect::<rusqlite::Result<_>>()?)
+    }
+
+    /// Discs burned from the volume with id `volume_id`, oldest first.
+    pub fn discs(&self, volume_id: i64) -> Result<Vec<VolumeDisc>> {
+        let mut stmt = self.conn.prepare_cached(
+            "SELECT v.label, d.burned_at, d.device, d.drive, d.media, d.verified
+             FROM volume_discs d JOIN archive_volumes v ON v.id = d.volume_id
+             WHERE d.volume_id = ?1 ORDER BY d.burned_at, d.id"
+        )?;
+        let discs = stmt.query_map(params![volume_id], |row| {
+            Ok(VolumeDisc {
+                label: row.get(0)?,
+                burned_at: row.get(1)?,
+                device: row.get(2)?,
+                drive: row.get(3)?,
+                media: row.get(4)?,
+                verified: row.get(5)?,
+            })
+        })?;
+        Ok(discs.collect::<rusqlite::Result<_>>()?)
+    }
+
+    /// Copies of the volume with id `volume_id` in buckets, oldest first.
+    pub fn uploads(&self, volume_id: i64) -> Result<Vec<VolumeUpload>> {
+        let mut stmt = self.conn.prepare_cached(
+            "SELECT v.label, u.remote, u.bucket, u.object_key, u.layout, u.object_id, u.storage_class, u.size_bytes, u.uploaded_at
+             FROM volume_uploads u JOIN archive_volumes v ON v.id = u.volume_id
+             WHERE u.volume_id = ?1 ORDER BY u.uploaded_at, u.id"
+        )?;
+        let uploads = stmt.query_map(params![volume_id], |row| {
+            Ok(VolumeUpload {
+                label: row.get(0)?,
+                remote: row.get(1)?,
+                bucket: row.get(2)?,
+                key: row.get(3)?,
+                layout: row.get(4)?,
+                object_id: row.get(5)?,
+                storage_class: row.get(6)?,
+                size_bytes: row.get(7)?,
+                uploaded_at: row.get(8)?,
+            })
+        })?;
+        Ok(uploads.collect::<rusqlite::Result<_>>()?)
+    }
+
+    /// The number following the highest registered label `<prefix>-<number>`,
+    /// 1 if there is none.
+    pub fn next_volume_number(&self, prefix: &str) -> Result<u32> {
+        let mut stmt = self.conn.prepare("SELECT label FROM archive_volumes WHERE substr(label, 1, ?1) = ?2")?;
+        let start = format!("{}-", prefix);
+        let labels = stmt.query_map(params![start.len(), start], |row| row.get::<_, String>(0))?;
+        let mut highest = 0;
+        for label in labels {
+            if let Ok(number) = label?[start.len()..].parse::<u32>() {
+                highest = highest.max(number);
+            }
+        }
+        Ok(highest + 1)
+    }
+
+    /// The files on the volume with id `volume_id`, in path order.
+    pub fn volume_files(&self, volume_id: i64) -> Result<Vec<VolumeFile>> {
+        let mut stmt = self.conn.prepare_cached(
+            "SELECT path, hash_sha256, size_bytes, offset_bytes FROM archive_members WHERE volume_id = ?1 ORDER BY path"
+        )?;
+        let files = stmt.query_map(params![volume_id], |row| {
+            Ok(VolumeFile { path: row.get(0)?, hash: row.get(1)?, size: row.get(2)?, offset: row.get(3)? })
+        })?;
+        Ok(files.collect::<rusqlite::Result<_>>()?)
+    }
+
+    /// Hashes of all content stored on some archive volume.
+    pub fn archived_hashes(&self) -> Result<HashSet<String>> {
+        let mut stmt = self.conn.prepare("SELECT DISTINCT hash_sha256 FROM archive_members")?;
+        let hashes = stmt.query_map([], |row| row.get(0))?;
+        Ok(hashes.collect::<rusqlite::Result<_>>()?)
+    }
+
+    /// Relationships of an artifact in both directions, outgoing first.
+    pub fn relationships(&self, artifact_id: i64) -> Result<Vec<Relationship>> {
+        let mut stmt = self.conn.prepare_cached(
+            "SELECT r.kind, 1, o.hash_sha256, o.original_path FROM relationships r
+             JOIN artifacts o ON o.id = r.related_id WHERE r.artifact_id = ?1
+             UNION ALL
+             SELECT r.kind, 0, o.hash_sha256, o.original_path FROM relationships r
+             JOIN artifacts o ON o.id = r.artifact_id WHERE r.related_id = ?1
+             ORDER BY 2 DESC, 1, 3"
+        )?;
+        let relationships = stmt.query_map(params![artifact_id], |row| {
+            Ok(Relationship {
+                kind: row.get(0)?,
+                outgoing: row.get(1)?,
+                hash_sha256: row.get(2)?,
+                original_path: row.get(3)?,
+            })
+        })?;
+        Ok(relationships.collect::<rusqlite::Result<_>>()?)
+    }
+
+    /// Totals over every fixity check recorded so far.
+    pub fn fixity_stats(&self) -> Result<FixityStats> {
+        Ok(self.conn.query_row(
+            "SELECT COUNT(*),
+                    COUNT(*) FILTER (WHERE result = 'ok'),
+                    COUNT(*) FILTER (WHERE result = 'mismatch'),
+                    COUNT(*) FILTER (WHERE result = 'missing'),
+                    COUNT(*) FILTER (WHERE result = 'error'),
+                    (SELECT COUNT(*) FROM artifacts a WHERE a.deleted_at IS NULL
+                     AND NOT EXISTS (SELECT 1 FROM fixity_checks f WHERE f.artifact_id = a.id)),
+                    MIN(checked_at),
+                    MAX(checked_at)
+             FROM fixity_checks",
+            [],
+            |row| {
+                Ok(FixityStats {
+                    checks: row.get(0)?,
+                    ok: row.get(1)?,
+                    mismatch: row.get(2)?,
+                    missing: row.get(3)?,
+                    error: row.get(4)?,
+                    never_verified: row.get(5)?,
+                    first_check: row.get(6)?,
+                    last_check: row.get(7)?,
+                })
+            },
+        )?)
+    }
+
+    /// Every known volume, most recently seen first.
+    pub fn sources(&self) -> Result<Vec<SourceSummary>> {
+        let mut stmt = self.conn.prepare(
+            "SELECT s.id, s.host, s.fs_uuid, s.label, s.mount_point, s.first_seen, s.last_seen,
+                    (SELECT COUNT(*) FROM artifact_paths p WHERE p.source_id = s.id AND p.deleted_at IS NULL)
+             FROM sources s ORDER BY s.last_seen DESC, s.id"
+        )?;
+        let sources = stmt.query_map([], |row| {
+            Ok(SourceSummary {
+                id: row.get(0)?,
+                source: Source { host: row.get(1)?, fs_uuid: row.get(2)?, label: row.get(3)?, mount_point: row.get(4)? },
+                first_seen: row.get(5)?,
+                last_seen: row.get(6)?,
+                paths: row.get(7)?,
+            })
+        })?;
+        Ok(sources.collect::<rusqlite::Result<_>>()?)
+    }
+
+    /// Duplicate groups among the matching artifacts, most wasted bytes first.
+    /// `filter.limit` caps the number of groups.
+    pub fn duplicate_groups(&self, filter: &FilterSet) -> Result<Vec<DuplicateGroup>> {
+        let (where_sql, values) = filter.to_sql();
+        let sql = format!(
+            "SELECT d.artifact_id, d.hash_sha256, d.size_bytes, d.copies, d.wasted_bytes
+             FROM duplicate_groups d JOIN artifacts a ON a.id = d.artifact_id
+             LEFT JOIN safety_scores s ON s.artifact_id = a.id
+             WHERE {}
+             ORDER BY d.wasted_bytes DESC, d.artifact_id
+             LIMIT {}",
+            where_sql,
+            filter.limit.map_or(-1, |l| l as i64)
+        );
+        let mut stmt = self.conn.prepare(&sql)?;
+        let groups = stmt.query_map(params_from_iter(values), |row| {
+            Ok(DuplicateGroup {
+                artifact_id: row.get(0)?,
+                hash_sha256: row.get(1)?,
+                size_bytes: row.get(2)?,
+                copies: row.get(3)?,
+                wasted_bytes: row.get(4)?,
+            })
+        })?;
+        Ok(groups.collect::<rusqlite::Result<_>>()?)
+    }
+
+    /// Totals plus every breakdown below, with the `top_tags` most used tags
+    /// and the `slowest` files ingest spent the most time on.
+    pub fn stats(&self, top_tags: usize, slowest: usize) -> Result<CatalogStats> {
+        let (artifacts, bytes, deleted) = self.conn.query_row(
+            "SELECT COUNT(*) FILTER (WHERE deleted_at IS NULL),
+                    COALESCE(SUM(size_bytes) FILTER (WHERE deleted_at IS NULL), 0),
+                    COUNT(*) FILTER (WHERE deleted_at IS NOT NULL)
+             FROM artifacts",
+            [],
+            |row| Ok((row.get(0)?, row.get(1)?, row.get(2)?)),
+        )?;
+        let paths = self.conn.query_row(
+            "SELECT COUNT(*) FROM artifact_paths p JOIN artifacts a ON a.id = p.artifact_id
+             WHERE p.deleted_at IS NULL AND a.deleted_at IS NULL",
+            [],
+            |row| row.get(0),
+        )?;
+        Ok(CatalogStats {
+            artifacts,
+            bytes,
+            paths,
+            deleted,
+            media_types: self.media_type_stats()?,
+            top_tags: self.top_tags(top_tags)?,
+            nsfw_histogram: self.nsfw_histogram()?,
+            runs: self.run_stats()?,
+            slowest: self.slowest_files(slowest)?,
+        })
+    }
+
+    /// Count and bytes per mimetype, largest first.
+    pub fn media_type_stats(&self) -> Result<Vec<MediaTypeStats>> {
+        let mut stmt = self.conn.prepare(
+            "SELECT media_type, artifacts, bytes FROM media_type_stats ORDER BY bytes DESC, media_type"
+        )?;
+        let stats = stmt.query_map([], |row| {
+            Ok(MediaTypeStats { media_type: row.get(0)?, artifacts: row.get(1)?, bytes: row.get(2)? })
+        })?;
+        Ok(stats.collect::<rusqlite::Result<_>>()?)
+    }
+
+    /// The `limit` tags on the most artifacts.
+    pub fn top_tags(&self, limit: usize) -> Result<Vec<TagCount>> {
+        let mut stmt = self.conn.prepare(&format!(
+            "SELECT {}, t.artifacts FROM tag_stats t ORDER BY t.artifacts DESC, t.namespace, t.name LIMIT ?1",
+            LABEL_SQL
+        ))?;
+        let tags = stmt.query_map(params![limit as i64], |row| {
+            Ok(TagCount { tag: row.get(0)?, artifacts: row.get(1)? })
+        })?;
+        Ok(tags.collect::<rusqlite::Result<_>>()?)
+    }
+
+    /// NSFW scores in ten buckets of 0.1, empty ones included.
+    pub fn nsfw_histogram(&self) -> Result<Vec<ScoreBucket>> {
+        let mut counts = [0u64; 10];
+        let mut stmt = self.conn.prepare("SELECT bucket, artifacts FROM nsfw_score_histogram")?;
+        let rows = stmt.query_map([], |row| Ok((row.get::<_, i64>(0)?, row.get::<_, u64>(1)?)))?;
+        for row in rows {
+            let (bucket, artifacts) = row?;
+            // Out-of-range scores land in the nearest bucket.
+            counts[bucket.clamp(0, 9) as usize] += artifacts;
+        }
+        Ok(counts
+            .iter()
+            .enumerate()
+            .map(|(i, &artifacts)| ScoreBucket { lower: i as f32 / 10.0, artifacts })
+            .collect())
+    }
+
+    /// Every run with its throughput, newest first.
+    pub fn run_stats(&self) -> Result<Vec<RunStats>> {
+        let mut stmt = self.conn.prepare(
+            "SELECT run_id, started_at, finished_at, status, files_seen, artifacts_added, errors, bytes_added, duration_secs
+             FROM run_stats ORDER BY run_id DESC"
+        )?;
+        let runs = stmt.query_map([], |row| {
+            let files_seen: u64 = row.get(4)?;
+            let duration_secs: Option<i64> = row.get(8)?;
+            Ok(RunStats {
+                run_id: row.get(0)?,
+                started_at: row.get(1)?,
+                finished_at: row.get(2)?,
+                status: row.get(3)?,
+                files_seen,
+                artifacts_added: row.get(5)?,
+                errors: row.get(6)?,
+                bytes_added: row.get(7)?,
+                duration_secs,
+                // A run finished within the same second still did its work in up to one.
+                files_per_sec: duration_secs.map(|d| files_seen as f64 / d.max(1) as f64),
+            })
+        })?;
+        Ok(runs.collect::<rusqlite::Result<_>>()?)
+    }
+
+    /// The `limit` files ingest took longest on, over all runs.
+    pub fn slowest_files(&self, limit: usize) -> Result<Vec<SlowFile>> {
+        let mut stmt = self.conn.prepare(
+            "SELECT run_id, path, hash_sha256, size_bytes, hash_ms, decode_ms, inference_ms, total_ms
+             FROM file_timings ORDER BY total_ms DESC, run_id DESC, path LIMIT ?1"
+        )?;
+        let files = stmt.query_map(params![limit as i64], |row| {
+            Ok(SlowFile {
+                run_id: row.get(0)?,
+                path: row.get(1)?,
+                hash_sha256: row.get(2)?,
+                size_bytes: row.get(3)?,
+                timings: FileTimings {
+                    hash_ms: row.get(4)?,
+                    decode_ms: row.get(5)?,
+                    inference_ms: row.get(6)?,
+                    total_ms: row.get(7)?,
+                },
+            })
+        })?;
+        Ok(files.collect::<rusqlite::Result<_>>()?)
+    }
+
+    pub fn count(&self, filter: &FilterSet) -> Result<usize> {
+        let (where_sql, values) = filter.to_sql();
+        let sql = format!(
+            "SELECT COUNT(*) FROM artifacts a LEFT JOIN safety_scores s ON s.artifact_id = a.id WHERE {}",
+            where_sql
+        );
+        let count: i64 = self.conn.query_row(&sql, params_from_iter(values), |row| row.get(0))?;
+        let count = count as usize;
+        Ok(filter.limit.map_or(count, |limit| count.min(limit)))
+    }
+}
+
+struct ArtifactIter<'a> {
+    conn: &'a Connection,
+    where_sql: String,
+    values: Vec<Value>,
+    last_id: i64,
+    remaining: Option<usize>,
+    page: VecDeque<Artifact>,
+    done: bool,
+}
+
+impl ArtifactIter<'_> {
+    /// Keyset pagination on `a.id` keeps each page query cheap even deep into the catalog.
+    fn fetch_page(&mut self) -> Result<()> {
+        let page_size = self.remaining.map_or(PAGE_SIZE, |r| r.min(PAGE_SIZE));
+        let sql = format!(
+            "SELECT a.id, a.hash_sha256, a.original_path, a.media_type, a.size_bytes, a.mtime, a.width, a.height, s.nsfw_score, a.metadata, a.deleted_at, a.deleted_reason, s.model_version,
+                    (SELECT group_concat({}, char(31)) FROM artifact_tags at JOIN tags t ON t.id = at.tag_id
+                     WHERE at.artifact_id = a.id)
+             FROM artifacts a LEFT JOIN safety_scores s ON s.artifact_id = a.id
+             WHERE a.id > ? AND {}
+             ORDER BY a.id
+             LIMIT {}",
+            LABEL_SQL, self.where_sql, page_size
+        );
+
+        let mut params = Vec::with_capacity(self.values.len() + 1);
+        params.push(Value::Integer(self.last_id));
+        params.extend(self.values.iter().cloned());
+
+        let mut stmt = self.conn.prepare_cached(&sql)?;
+        let rows = stmt.query_map(params_from_iter(params), |row| {
+            let tags: Option<String> = row.get(13)?;
+            Ok(Artifact {
+                id: row.get(0)?,
+                hash_sha256: row.get(1)?,
+                original_path: row.get(2)?,
+                media_type: row.get(3)?,
+                size_bytes: row.get(4)?,
+                mtime: row.get(5)?,
+                width: row.get(6)?,
+                height: row.get(7)?,
+                nsfw_score: row.get(8)?,
+                nsfw_model_version: row.get(12)?,
+                tags: tags
+                    .map(|t| t.split('\u{1f}').map(str::to_string).collect())
+                    .unwrap_or_default(),
+                metadata: row.get(9)?,
+                deleted_at: row.get(10)?,
+                deleted_reason: row.get(11)?,
+            })
+        })?;
+
+        for artifact in rows {
+            self.page.push_back(artifact?);
+        }
+
+        if self.page.len() < page_size {
+            self.done = true;
+        }
+        if let Some(last) = self.page.back() {
+            self.last_id = last.id;
+        }
+        Ok(())
+    }
+}
+
+impl Iterator for ArtifactIter<'_> {
+    type Item = Result<Artifact>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.remaining == Some(0) {
+            return None;
+        }
+
+        if self.page.is_empty() && !self.done {
+            if let Err(e) = self.fetch_page() {
+                self.done = true;
+                return Some(Err(e));
+            }
+        }
+
+        let artifact = self.page.pop_front()?;
+        if let Some(r) = self.remaining.as_mut() {
+            *r -= 1;
+        }
+        Some(Ok(artifact))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn record(hash: &str, media_type: &str, tags: &[&str], score: Option<f32>) -> ArtifactRecord {
+        ArtifactRecord {
+            hash_sha256: hash.to_string(),
+            original_path: format!("/media/{}", hash),
+            media_type: media_type.to_string(),
+            size_bytes: Some(hash.len() as u64 * 100),
+            mtime: Some(1_700_000_000),
+            width: None,
+            height: None,
+            tags: tags.iter().map(|t| t.to_string()).collect(),
+            nsfw_score: score,
+            nsfw_model_version: None,
+            embeddings: Vec::new(),
+            metadata: None,
+            source: None,
+            timings: None,
+        }
+    }
+
+    fn hashes_of(reader: &CatalogReader, filter: FilterSet) -> Result<Vec<String>> {
+        reader.find(&filter).map(|a| a.map(|a| a.hash_sha256)).collect()
+    }
+
+    #[test]
+    fn test_catalog_reader_filters() -> Result<()> {
+        let path = std::env::temp_dir().join(format!("deep_archive_reader_{}.db", std::process::id()));
+        let db = path.to_string_lossy().to_string();
+
+        let mut tm = TransactionManager::new(&db, &DatabaseConfig::default())?;
+        tm.add(record("aa", "image/png", &["dog", "outdoor"], Some(0.1)))?;
+        let mut bb = record("bb", "image/jpeg", &["cat"], Some(0.9));
+        bb.embeddings.push(Embedding { model_name: "clip".into(), model_version: "1".into(), vector: vec![0.5, -1.0] });
+        bb.metadata = Some(serde_json::json!({"camera_model": "Canon EOS 5D", "gps_lat": 48.8566, "gps_lon": 2.3522}));
+        bb.source = Some(Source { host: "nas".into(), fs_uuid: Some("1234-ABCD".into()), label: None, mount_point: None });
+        tm.add(bb)?;
+        let mut cc = record("cc", "video/mp4", &["dog", "ml:dog", "person:alice"], None);
+        cc.metadata = Some(serde_json::json!({"gps_lat": -16.5, "gps_lon": -179.9}));
+        tm.add(cc)?;
+        tm.flush()?;
+        drop(tm);
+
+        let reader = CatalogReader::open(&db, &DatabaseConfig::default())?;
+        let hashes = |filter: FilterSet| -> Result<Vec<String>> {
+            reader.find(&filter).map(|a| a.map(|a| a.hash_sha256)).collect()
+        };
+
+        assert_eq!(hashes(FilterSet::new())?, vec!["aa", "bb", "cc"]);
+        assert_eq!(hashes(FilterSet::new().tag("dog").tag("outdoor"))?, vec!["aa"]);
+        assert_eq!(hashes(FilterSet::new().any_tag("cat").any_tag("outdoor"))?, vec!["aa", "bb"]);
+        assert_eq!(hashes(FilterSet::new().exclude_tag("dog"))?, vec!["bb"]);
+        assert_eq!(hashes(FilterSet::new().media_type("image/*"))?, vec!["aa", "bb"]);
+        assert_eq!(hashes(FilterSet::new().nsfw_between(None, Some(0.5)))?, vec!["aa"]);
+        assert_eq!(hashes(FilterSet::new().limit(2))?, vec!["aa", "bb"]);
+        assert_eq!(hashes(FilterSet::new().hash("bb"))?, vec!["bb"]);
+        assert_eq!(reader.count(&FilterSet::new().tag("dog"))?, 2);
+        assert_eq!(hashes(FilterSet::new().camera_model("canon"))?, vec!["bb"]);
+        // Versailles is about 17 km from central Paris.
+        assert_eq!(hashes(FilterSet::new().near(48.8049, 2.1204, 20_000.0))?, vec!["bb"]);
+        assert!(hashes(FilterSet::new().near(48.8049, 2.1204, 10_000.0))?.is_empty());
+        // About 21 km apart, on either side of the antimeridian.
+        assert_eq!(hashes(FilterSet::new().near(-16.5, 179.9, 50_000.0))?, vec!["cc"]);
+        assert!(hashes(FilterSet::new().near(-16.5, 179.9, 10_000.0))?.is_empty());
+        assert_eq!(hashes(FilterSet::new().bbox(48.0, 2.0, 49.0, 3.0))?, vec!["bb"]);
+        assert!(hashes(FilterSet::new().bbox(48.0, 170.0, 49.0, -170.0))?.is_empty());
+        assert_eq!(reader.count(&FilterSet::new().duration_between(Some(1.0), None))?, 0);
+        assert_eq!(hashes(FilterSet::new().stale_embedding("clip", "1"))?, vec!["aa", "cc"]);
+        assert_eq!(reader.count(&FilterSet::new().stale_embedding("clip", "2"))?, 3);
+        assert_eq!(hashes(FilterSet::new().tag("ml:dog"))?, vec!["cc"]);
+        assert_eq!(hashes(FilterSet::new().tag("person:*"))?, vec!["cc"]);
+        assert_eq!(hashes(FilterSet::new().exclude_tag("ml:*"))?, vec!["aa", "bb"]);
+        assert_eq!(reader.count(&FilterSet::new().size_between(Some(200), None))?, 3);
+        assert_eq!(reader.count(&FilterSet::new().modified_between(Some(1_700_000_001), None))?, 0);
+
+        let first = reader.find(&FilterSet::new()).next().unwrap()?;
+        assert_eq!(first.tags, vec!["dog", "outdoor"]);
+        drop(reader);
+
+        // A second sighting of the same content keeps both locations.
+        let mut tm = TransactionManager::new(&db, &DatabaseConfig::default())?;
+        let mut copy = record("aa", "image/png", &[], None);
+        copy.original_path = "/backup/aa".to_string();
+        tm.add(copy)?;
+        tm.flush()?;
+        drop(tm);
+
+        let reader = CatalogReader::open(&db, &DatabaseConfig::default())?;
+        let mut paths: Vec<String> = reader.paths(first.id)?.into_iter().map(|p| p.path).collect();
+        paths.sort();
+        assert_eq!(paths, vec!["/backup/aa", "/media/aa"]);
+
+        let sources = reader.sources()?;
+        assert_eq!(sources.len(), 1);
+        assert_eq!((sources[0].source.host.as_str(), sources[0].paths), ("nas", 1));
+
+        let groups = reader.duplicate_groups(&FilterSet::new())?;
+        assert_eq!(groups.len(), 1);
+        assert_eq!((groups[0].artifact_id, groups[0].copies, groups[0].wasted_bytes), (first.id, 2, 200));
+        assert!(reader.duplicate_groups(&FilterSet::new().media_type("video/*"))?.is_empty());
+
+        let stats = reader.stats(1, 0)?;
+        assert_eq!((stats.artifacts, stats.bytes, stats.paths), (3, 600, 4));
+        assert_eq!(stats.media_types.len(), 3);
+        assert_eq!(stats.top_tags.len(), 1);
+        assert_eq!((stats.top_tags[0].tag.as_str(), stats.top_tags[0].artifacts), ("dog", 2));
+        let scored: Vec<u64> = stats.nsfw_histogram.iter().map(|b| b.artifacts).collect();
+        assert_eq!(scored, [0, 1, 0, 0, 0, 0, 0, 0, 0, 1]);
+
+        // Re-ingest must not duplicate full-text hits.
+        let hashes = |filter: FilterSet| -> Result<Vec<String>> {
+            reader.find(&filter).map(|a| a.map(|a| a.hash_sha256)).collect()
+        };
+        assert_eq!(hashes(FilterSet::new().search("outdoor"))?, vec!["aa"]);
+        let fts_rows: i64 = reader.conn.query_row(
+            "SELECT COUNT(*) FROM search_index WHERE search_index MATCH 'outdoor'", [], |row| row.get(0))?;
+        assert_eq!(fts_rows, 1);
+
+        // Deletes tombstone by default and only `purge` drops rows.
+        let mut tm = TransactionManager::new(&db, &DatabaseConfig::default())?;
+        tm.record_fixity(&[FixityCheck {
+            artifact_id: first.id,
+            path: "/media/aa".into(),
+            checked_at: 1_800_000_000,
+            algo: "sha256",
+            result: FixityResult::Ok,
+            detail: None,
+        }])?;
+        assert_eq!(hashes_of(&reader, FilterSet::new().unverified_since(1_800_000_000))?, vec!["bb", "cc"]);
+        assert_eq!(reader.fixity_stats()?.never_verified, 2);
+
+        tm.relate("cc", "thumbnail-of", "aa")?;
+        assert!(tm.relate("cc", "thumbnail-of", "zz").is_err());
+        assert_eq!(hashes_of(&reader, FilterSet::new().related_to("aa"))?, vec!["cc"]);
+        assert_eq!(hashes_of(&reader, FilterSet::new().originals_only())?, vec!["aa", "bb"]);
+        let related = reader.relationships(first.id)?;
+        assert_eq!((related[0].kind.as_str(), related[0].outgoing, related[0].hash_sha256.as_str()), ("thumbnail-of", false, "cc"));
+        assert!(FilterSet::new().limit(1).is_unrestricted());
+        assert_eq!(tm.delete_matching(&FilterSet::new().tag("cat"), "test", false)?, 1);
+        assert_eq!(tm.delete_matching(&FilterSet::new().tag("person:*"), "test", true)?, 1);
+        drop(tm);
+        drop(reader);
+
+        let reader = CatalogReader::open(&db, &DatabaseConfig::default())?;
+        assert_eq!(hashes_of(&reader, FilterSet::new())?, vec!["aa"]);
+        let deleted: Vec<_> = reader.find(&FilterSet::new().include_deleted()).collect::<Result<_>>()?;
+        assert_eq!(deleted.iter().map(|a| a.hash_sha256.as_str()).collect::<Vec<_>>(), vec!["aa", "bb"]);
+        assert_eq!(deleted[1].deleted_reason.as_deref(), Some("test"));
+        assert!(reader.relationships(first.id)?.is_empty());
+
+        drop(reader);
+        std::fs::remove_file(path)?;
+        Ok(())
+    }
+
+    #[test]
+    fn test_reads_while_writing() -> Result<()> {
+        let path = std::env::temp_dir().join(format!("deep_archive_live_{}.db", std::process::id()));
+        let db = path.to_string_lossy().to_string();
+
+        let mut tm = TransactionManager::new(&db, &DatabaseConfig::default())?;
+        tm.add(record("aa", "image/png", &[], None))?;
+        tm.flush()?;
+
+        // An ingest mid-batch holds the write lock; readers still see the last commit.
+        tm.conn.execute_batch("BEGIN IMMEDIATE")?;
+        tm.conn.execute("UPDATE artifacts SET media_type = 'image/jpeg'", [])?;
+
+        let pool = ReaderPool::open(&db, &DatabaseConfig::default())?;
+        let (a, b) = (pool.reader()?, pool.reader()?);
+        assert_eq!(a.count(&FilterSet::new().media_type("image/png"))?, 1);
+        assert_eq!(b.count(&FilterSet::new())?, 1);
+
+        tm.conn.execute_batch("COMMIT")?;
+        assert_eq!(a.count(&FilterSet::new().media_type("image/jpeg"))?, 1);
+
+        drop((a, b, pool, tm));
+        for suffix in ["", "-wal", "-shm"] {
+            let _ = std::fs::remove_file(format!("{}{}", db, suffix));
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn test_merge_on_reingest() -> Result<()> {
+        let path = std::env::temp_dir().join(format!("deep_archive_merge_{}.db", std::process::id()));
+        let db = path.to_string_lossy().to_string();
+
+        let mut tm = TransactionManager::new(&db, &DatabaseConfig::default())?;
+        let mut first = record("aa", "image/png", &["dog"], Some(0.2));
+        (first.width, first.height) = (Some(1920), Some(1080));
+        first.nsfw_model_version = Some("2".into());
+        first.metadata = Some(serde_json::json!({"camera_model": "X100", "xattr": {"a": "1"}}));
+        tm.add(first)?;
+        tm.flush()?;
+
+        let mut again = record("aa", "application/octet-stream", &["park"], Some(0.9));
+        (again.width, again.height) = (Some(640), Some(480));
+        again.nsfw_model_version = Some("1".into());
+        again.metadata = Some(serde_json::json!({"xattr": {"b": "2"}}));
+        tm.add(again)?;
+        tm.flush()?;
+        assert_eq!(tm.known_hashes()?, HashSet::from(["aa".to_string()]));
+        drop(tm);
+
+        let reader = CatalogReader::open(&db, &DatabaseConfig::default())?;
+        let mut merged = reader.find(&FilterSet::new()).next().unwrap()?;
+        merged.tags.sort();
+        assert_eq!(merged.tags, vec!["dog", "park"]);
+        assert_eq!((merged.media_type.as_str(), merged.width, merged.height), ("image/png", Some(1920), Some(1080)));
+        assert_eq!((merged.nsfw_score, merged.nsfw_model_version.as_deref()), (Some(0.2), Some("2")));
+        assert_eq!(merged.metadata, Some(serde_json::json!({"camera_model": "X100", "xattr": {"a": "1", "b": "2"}})));
+
+        drop(reader);
+        for suffix in ["", "-wal", "-shm"] {
+            let _ = std::fs::remove_file(format!("{}{}", db, suffix));
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn test_rollback_keeps_artifacts_later_runs_found() -> Result<()> {
+        for purge in [false, true] {
+            let path = std::env::temp_dir().join(format!("deep_archive_rollback_{}_{}.db", purge, std::process::id()));
+            let db = path.to_string_lossy().to_string();
+
+            let mut tm = TransactionManager::new(&db, &DatabaseConfig::default())?;
+            let first = tm.begin_run(&["/media".to_string()], "{}")?;
+            tm.add(record("aa", "image/png", &["dog"], None))?;
+            tm.add(record("bb", "image/png", &["cat"], None))?;
+            tm.finish_run("completed", 0, None)?;
+            let second = tm.begin_run(&["/backup".to_string()], "{}")?;
+            let mut copy = record("aa", "image/png", &["dog"], None);
+            copy.original_path = "/backup/aa".to_string();
+            tm.add(copy)?;
+            tm.finish_run("completed", 0, None)?;
+            tm.rollback_run(first, purge)?;
+
+            let live = |sql: &str| -> Result<Vec<(String, i64)>> {
+                let mut stmt = tm.conn.prepare(sql)?;
+                let rows = stmt.query_map([], |row| Ok((row.get(0)?, row.get(1)?)))?;
+                Ok(rows.collect::<rusqlite::Result<_>>()?)
+            };
+            let artifacts = live("SELECT hash_sha256, run_id FROM artifacts WHERE deleted_at IS NULL ORDER BY hash_sha256")?;
+            assert_eq!(artifacts, [("aa".to_string(), second)], "purge: {}", purge);
+            let paths = live("SELECT path, run_id FROM artifact_paths WHERE deleted_at IS NULL")?;
+            assert_eq!(paths, [("/backup/aa".to_string(), second)], "purge: {}", purge);
+            let original: String = tm.conn.query_row("SELECT original_path FROM artifacts WHERE hash_sha256 = 'aa'", [], |row| row.get(0))?;
+            assert_eq!(original, "/backup/aa");
+
+            drop(tm);
+            for suffix in ["", "-wal", "-shm"] {
+                let _ = std::fs::remove_file(format!("{}{}", db, suffix));
+            }
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn test_resume_run() -> Result<()> {
+        let path = std::env::temp_dir().join(format!("deep_archive_resume_{}.db", std::process::id()));
+        let db = path.to_string_lossy().to_string();
+
+        let mut writer = TransactionManager::new(&db, &DatabaseConfig::default())?;
+        let run_id = writer.begin_run(&["/media".to_string()], "{}")?;
+        writer.add(record("aa", "image/png", &[], None))?;
+        writer.flush()?;
+
+        // The run's process is still alive, so its lease keeps it.
+        let mut resumer = TransactionManager::new(&db, &DatabaseConfig::default())?;
+        let err = resumer.resume_run(run_id).unwrap_err().to_string();
+        assert!(err.contains(&format!("pid {}", std::process::id())), "{}", err);
+
+        // Once the lease has expired, as after a crash, it can be resumed.
+        writer.conn.execute("UPDATE leases SET expires_at = 0 WHERE id = ?1", params![writer.lease])?;
+        let committed = resumer.resume_run(run_id)?;
+        assert_eq!(committed, HashMap::from([("/media/aa".to_string(), CommittedFile { size_bytes: Some(200), mtime: Some(1_700_000_000) })]));
+        assert!(writer.resume_run(run_id).is_err());
+        resumer.finish_run("completed", 0, None)?;
+        assert!(resumer.resume_run(run_id).is_err());
+
+        drop(writer);
+        drop(resumer);
+        for suffix in ["", "-wal", "-shm"] {
+            let _ = std::fs::remove_file(format!("{}{}", db, suffix));
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn test_leases() -> Result<()> {
+        let path = std::env::temp_dir().join(format!("deep_archive_leases_{}.db", std::process::id()));
+        let db = path.to_string_lossy().to_string();
+
+        let mut first = TransactionManager::new(&db, &DatabaseConfig::default())?;
+        let mut second = TransactionManager::new(&db, &DatabaseConfig::default())?;
+        first.acquire_lease(LeaseKind::Shared)?;
+        second.acquire_lease(LeaseKind::Shared)?;
+
+        // An exclusive lease waits for both runs, and says who holds it.
+        let mut maintenance = TransactionManager::new(&db, &DatabaseConfig::default())?;
+        let err = maintenance.acquire_lease(LeaseKind::Exclusive).unwrap_err().to_string();
+        assert!(err.contains("(shared lease)") && err.contains(&format!("pid {}", std::process::id())), "{}", err);
+        assert_eq!(maintenance.lease, None);
+
+        // Leases of crashed processes expire and are cleared away.
+        first.conn.execute("UPDATE leases SET expires_at = 0", [])?;
+        maintenance.acquire_lease(LeaseKind::Exclusive)?;
+        let live: i64 = first.conn.query_row("SELECT count(*) FROM leases", [], |row| row.get(0))?;
+        assert_eq!(live, 1);
+        let mut third = TransactionManager::new(&db, &DatabaseConfig::default())?;
+        assert!(third.acquire_lease(LeaseKind::Shared).unwrap_err().to_string().contains("(exclusive lease)"));
+
+        drop((first, second, third, maintenance));
+        for suffix in ["", "-wal", "-shm"] {
+            let _ = std::fs::remove_file(format!("{}{}", db, suffix));
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn test_dead_letters() -> Result<()> {
+        let path = std::env::temp_dir().join(format!("deep_archive_dead_letters_{}.db", std::process::id()));
+        let db = path.to_string_lossy().to_string();
+
+        let mut tm = TransactionManager::new(&db, &DatabaseConfig::default())?;
+        tm.begin_run(&["/media".to_string()], "{}")?;
+        let partial = PartialResult {
+            hash_sha256: "aa".to_string(),
+            size_bytes: 42,
+            mtime: Some(1700000000),
+            device: None,
+            media_type: Some("video/mp4".to_string()),
+        };
+        tm.record_error("/media/a.mp4", "decode", "ffmpeg exited with 1", Some("moov atom not found"), Some(&partial))?;
+        tm.record_error("/media/b.mp4", "hash", "Permission denied", None, None)?;
+        let errors = tm.errors(None, false)?;
+        assert_eq!(errors[0].partial.as_ref(), Some(&partial));
+        assert_eq!(errors[0].stderr.as_deref(), Some("moov atom not found"));
+        assert_eq!((errors[1].partial.as_ref(), errors[1].stderr.as_deref()), (None, None));
+
+        drop(tm);
+        for suffix in ["", "-wal", "-shm"] {
+            let _ = std::fs::remove_file(format!("{}{}", db, suffix));
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn test_slowest_files() -> Result<()> {
+        let path = std::env::temp_dir().join(format!("deep_archive_timings_{}.db", std::process::id()));
+        let db = path.to_string_lossy().to_string();
+
+        let mut tm = TransactionManager::new(&db, &DatabaseConfig::default())?;
+        let run_id = tm.begin_run(&["/media".to_string()], "{}")?;
+        for (hash, total_ms) in [("aa", 40), ("bb", 9000), ("cc", 700)] {
+            let mut timed = record(hash, "video/mp4", &[], None);
+            timed.timings = Some(FileTimings { hash_ms: 10, decode_ms: total_ms / 2, inference_ms: total_ms / 4, total_ms });
+            tm.add(timed)?;
+        }
+        tm.add(record("dd", "image/png", &[], None))?;
+        tm.flush()?;
+
+        // A volume's snapshot only times the files on it.
+        let snapshot = std::env::temp_dir().join(format!("deep_archive_timings_snapshot_{}.db", std::process::id()));
+        tm.snapshot(&snapshot, &["bb".to_string()])?;
+        let conn = Connection::open(&snapshot)?;
+        let timed: Vec<String> = conn.prepare("SELECT path FROM file_timings")?.query_map([], |row| row.get(0))?.collect::<rusqlite::Result<_>>()?;
+        assert_eq!(conn.query_row("SELECT COUNT(*) FROM run_files", [], |row| row.get::<_, i64>(0))?, 0);
+        drop(conn);
+        assert_eq!(timed, ["/media/bb"]);
+        std::fs::remove_file(&snapshot)?;
+        drop(tm);
+
+        let reader = CatalogReader::open(&db, &DatabaseConfig::default())?;
+        let slowest = reader.stats(0, 2)?.slowest;
+        let paths: Vec<&str> = slowest.iter().map(|f| f.path.as_str()).collect();
+        assert_eq!(paths, ["/media/bb", "/media/cc"]);
+        assert_eq!((slowest[0].run_id, slowest[0].timings.decode_ms), (run_id, 4500));
+
+        drop(reader);
+        for suffix in ["", "-wal", "-shm"] {
+            let _ = std::fs::remove_file(format!("{}{}", db, suffix));
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn test_supersedes() {
+        assert!(supersedes(None, None));
+        assert!(supersedes(Some("1"), Some(None)));
+        assert!(supersedes(Some("1.10"), Some(Some("1.9".into()))));
+        assert!(!supersedes(Some("1.9"), Some(Some("1.10".into()))));
+        assert!(!supersedes(Some("2"), Some(Some("2".into()))));
+        assert!(!supersedes(None, Some(Some("1".into()))));
+    }
+
+    #[test]
+    fn test_tag_rules() -> Result<()> {
+        let path = std::env::temp_dir().join(format!("deep_archive_rules_{}.db", std::process::id()));
+        let db = path.to_string_lossy().to_string();
+
+        let mut tm = TransactionManager::new(&db, &DatabaseConfig::default())?;
+        tm.add(record("aa", "image/png", &["ww2"], None))?;
+        tm.add(record("bb", "image/png", &["golden_retriever"], None))?;
+        tm.flush()?;
+
+        // Rules apply to what is already catalogued...
+        tm.add_alias("ww2", "world_war_2")?;
+        tm.add_implication("golden_retriever", "dog")?;
+        tm.add_implication("dog", "animal")?;
+        assert!(tm.add_implication("animal", "golden_retriever").is_err());
+        assert!(tm.add_alias("world_war_2", "ww2").is_err());
+
+        // ...and to new records.
+        tm.add(record("cc", "image/png", &["ww2", "golden_retriever"], None))?;
+        tm.flush()?;
+
+        let reader = CatalogReader::open(&db, &DatabaseConfig::default())?;
+        assert_eq!(hashes_of(&reader, FilterSet::new().tag("world_war_2"))?, vec!["aa", "cc"]);
+        assert_eq!(hashes_of(&reader, FilterSet::new().tag("ww2"))?, vec!["aa", "cc"]);
+        assert_eq!(hashes_of(&reader, FilterSet::new().tag("animal"))?, vec!["bb", "cc"]);
+        let mut tags = reader.find(&FilterSet::new().search("cc")).next().unwrap()?.tags;
+        tags.sort();
+        assert_eq!(tags, vec!["animal", "dog", "golden_retriever", "world_war_2"]);
+
+        let rules = tm.tag_rules()?;
+        assert_eq!(rules.aliases, vec![("ww2".to_string(), "world_war_2".to_string())]);
+        assert_eq!(rules.implications.len(), 2);
+        assert!(tm.remove_implication("dog", "animal")?);
+        assert!(!tm.remove_implication("dog", "animal")?);
+        assert!(tm.remove_alias("ww2")?);
+
+        drop((reader, tm));
+        for suffix in ["", "-wal", "-shm"] {
+            let _ = std::fs::remove_file(format!("{}{}", db, suffix));
+        }
+        Ok(())
+    }
 }
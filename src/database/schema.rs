@@ -1,3 +1,6 @@
+use rusqlite::Connection;
+use anyhow::{Result, Context, bail};
+
 pub const SCHEMA: &str = "
     CREATE TABLE IF NOT EXISTS artifacts (
         id INTEGER PRIMARY KEY,
@@ -29,3 +32,377 @@ pub const SCHEMA: &str = "
 
     CREATE VIRTUAL TABLE IF NOT EXISTS search_index USING fts5(original_path, tags_concatenated);
 ";
+
+/// Incremental changes applied on top of `SCHEMA`, tracked via `PRAGMA user_version`.
+/// Entry `i` upgrades a catalog from version `i` to `i + 1`. Append only; never edit.
+pub const MIGRATIONS: &[&str] = &[
+    // 1: file size and modification time (unix seconds)
+    "ALTER TABLE artifacts ADD COLUMN size_bytes INTEGER;
+     ALTER TABLE artifacts ADD COLUMN mtime INTEGER;",
+    // 2: every location a hash has been seen at (unix seconds)
+    "CREATE TABLE artifact_paths (
+        artifact_id INTEGER NOT NULL,
+        path TEXT NOT NULL,
+        first_seen INTEGER NOT NULL,
+        last_seen INTEGER NOT NULL,
+        FOREIGN KEY(artifact_id) REFERENCES artifacts(id),
+        PRIMARY KEY(artifact_id, path)
+     );
+     CREATE INDEX idx_artifact_paths_path ON artifact_paths(path);
+     INSERT INTO artifact_paths (artifact_id, path, first_seen, last_seen)
+        SELECT id, original_path, CAST(strftime('%s', 'now') AS INTEGER), CAST(strftime('%s', 'now') AS INTEGER)
+        FROM artifacts;",
+    // 3: ingest runs; artifacts and paths remember the run that first discovered them
+    "CREATE TABLE runs (
+        id INTEGER PRIMARY KEY,
+        started_at INTEGER NOT NULL,
+        finished_at INTEGER,
+        status TEXT NOT NULL DEFAULT 'running',
+        input_roots TEXT NOT NULL,
+        options TEXT,
+        files_seen INTEGER NOT NULL DEFAULT 0,
+        artifacts_added INTEGER NOT NULL DEFAULT 0,
+        errors INTEGER NOT NULL DEFAULT 0
+     );
+     ALTER TABLE artifacts ADD COLUMN run_id INTEGER REFERENCES runs(id);
+     ALTER TABLE artifact_paths ADD COLUMN run_id INTEGER REFERENCES runs(id);
+     CREATE INDEX idx_artifacts_run ON artifacts(run_id);
+     CREATE INDEX idx_artifact_paths_run ON artifact_paths(run_id);",
+    // 4: search_index becomes an external-content FTS table over artifacts, kept in
+    // sync by triggers, so re-ingesting a file no longer adds duplicate rows.
+    "ALTER TABLE artifacts ADD COLUMN tags_text TEXT NOT NULL DEFAULT '';
+     UPDATE artifacts SET tags_text = COALESCE((
+        SELECT group_concat(t.name, ' ') FROM artifact_tags at JOIN tags t ON t.id = at.tag_id
+        WHERE at.artifact_id = artifacts.id), '');
+     DROP TABLE search_index;
+     CREATE VIRTUAL TABLE search_index USING fts5(
+        original_path, tags_text, content='artifacts', content_rowid='id'
+     );
+     CREATE TRIGGER artifacts_fts_insert AFTER INSERT ON artifacts BEGIN
+        INSERT INTO search_index(rowid, original_path, tags_text)
+        VALUES (new.id, new.original_path, new.tags_text);
+     END;
+     CREATE TRIGGER artifacts_fts_delete AFTER DELETE ON artifacts BEGIN
+        INSERT INTO search_index(search_index, rowid, original_path, tags_text)
+        VALUES ('delete', old.id, old.original_path, old.tags_text);
+     END;
+     CREATE TRIGGER artifacts_fts_update AFTER UPDATE OF original_path, tags_text ON artifacts BEGIN
+        INSERT INTO search_index(search_index, rowid, original_path, tags_text)
+        VALUES ('delete', old.id, old.original_path, old.tags_text);
+        INSERT INTO search_index(rowid, original_path, tags_text)
+        VALUES (new.id, new.original_path, new.tags_text);
+     END;
+     CREATE TRIGGER artifact_tags_text_insert AFTER INSERT ON artifact_tags BEGIN
+        UPDATE artifacts SET tags_text = COALESCE((
+            SELECT group_concat(t.name, ' ') FROM artifact_tags at JOIN tags t ON t.id = at.tag_id
+            WHERE at.artifact_id = new.artifact_id), '')
+        WHERE id = new.artifact_id;
+     END;
+     CREATE TRIGGER artifact_tags_text_delete AFTER DELETE ON artifact_tags BEGIN
+        UPDATE artifacts SET tags_text = COALESCE((
+            SELECT group_concat(t.name, ' ') FROM artifact_tags at JOIN tags t ON t.id = at.tag_id
+            WHERE at.artifact_id = old.artifact_id), '')
+        WHERE id = old.artifact_id;
+     END;
+     INSERT INTO search_index(search_index) VALUES ('rebuild');",
+    // 5: tag namespaces; `name` is only unique within its namespace, so the table is rebuilt.
+    // Unprefixed tags written so far all came from the tagger, so they move to `ml`.
+    "DROP TRIGGER artifact_tags_text_insert;
+     DROP TRIGGER artifact_tags_text_delete;
+     CREATE TABLE tags_new (
+        id INTEGER PRIMARY KEY,
+        namespace TEXT NOT NULL DEFAULT '',
+        name TEXT NOT NULL,
+        UNIQUE(namespace, name)
+     );
+     INSERT INTO tags_new (id, namespace, name)
+        SELECT id,
+               CASE WHEN instr(name, ':') > 0 THEN substr(name, 1, instr(name, ':') - 1) ELSE 'ml' END,
+               CASE WHEN instr(name, ':') > 0 THEN substr(name, instr(name, ':') + 1) ELSE name END
+        FROM tags;
+     DROP TABLE tags;
+     ALTER TABLE tags_new RENAME TO tags;
+     CREATE INDEX idx_tags_name ON tags(name);
+     CREATE TRIGGER artifact_tags_text_insert AFTER INSERT ON artifact_tags BEGIN
+        UPDATE artifacts SET tags_text = COALESCE((
+            SELECT group_concat(CASE WHEN t.namespace = '' THEN t.name ELSE t.namespace || ':' || t.name END, ' ')
+            FROM artifact_tags at JOIN tags t ON t.id = at.tag_id
+            WHERE at.artifact_id = new.artifact_id), '')
+        WHERE id = new.artifact_id;
+     END;
+     CREATE TRIGGER artifact_tags_text_delete AFTER DELETE ON artifact_tags BEGIN
+        UPDATE artifacts SET tags_text = COALESCE((
+            SELECT group_concat(CASE WHEN t.namespace = '' THEN t.name ELSE t.namespace || ':' || t.name END, ' ')
+            FROM artifact_tags at JOIN tags t ON t.id = at.tag_id
+            WHERE at.artifact_id = old.artifact_id), '')
+        WHERE id = old.artifact_id;
+     END;
+     UPDATE artifacts SET tags_text = COALESCE((
+        SELECT group_concat(CASE WHEN t.namespace = '' THEN t.name ELSE t.namespace || ':' || t.name END, ' ')
+        FROM artifact_tags at JOIN tags t ON t.id = at.tag_id
+        WHERE at.artifact_id = artifacts.id), '');",
+    // 6: embedding vectors (little-endian f32 BLOBs), one per artifact and model
+    "CREATE TABLE embeddings (
+        artifact_id INTEGER NOT NULL,
+        model_name TEXT NOT NULL,
+        model_version TEXT NOT NULL,
+        dim INTEGER NOT NULL,
+        vector BLOB NOT NULL,
+        created_at INTEGER NOT NULL,
+        FOREIGN KEY(artifact_id) REFERENCES artifacts(id),
+        PRIMARY KEY(artifact_id, model_name)
+     );
+     CREATE INDEX idx_embeddings_model ON embeddings(model_name, model_version);",
+    // 7: free-form metadata JSON, with the frequently queried fields exposed as
+    // indexed generated columns
+    "ALTER TABLE artifacts ADD COLUMN metadata TEXT;
+     ALTER TABLE artifacts ADD COLUMN duration REAL
+        GENERATED ALWAYS AS (json_extract(metadata, '$.duration')) VIRTUAL;
+     ALTER TABLE artifacts ADD COLUMN camera_model TEXT
+        GENERATED ALWAYS AS (json_extract(metadata, '$.camera_model')) VIRTUAL;
+     ALTER TABLE artifacts ADD COLUMN gps_lat REAL
+        GENERATED ALWAYS AS (json_extract(metadata, '$.gps_lat')) VIRTUAL;
+     ALTER TABLE artifacts ADD COLUMN gps_lon REAL
+        GENERATED ALWAYS AS (json_extract(metadata, '$.gps_lon')) VIRTUAL;
+     CREATE INDEX idx_artifacts_duration ON artifacts(duration);
+     CREATE INDEX idx_artifacts_camera_model ON artifacts(camera_model);
+     CREATE INDEX idx_artifacts_gps ON artifacts(gps_lat, gps_lon);",
+    // 8: per-file failures, kept until a later run ingests the path successfully
+    "CREATE TABLE ingest_errors (
+        id INTEGER PRIMARY KEY,
+        run_id INTEGER REFERENCES runs(id),
+        path TEXT NOT NULL,
+        stage TEXT NOT NULL,
+        error TEXT NOT NULL,
+        created_at INTEGER NOT NULL,
+        resolved_at INTEGER
+     );
+     CREATE INDEX idx_ingest_errors_path ON ingest_errors(path);
+     CREATE INDEX idx_ingest_errors_run ON ingest_errors(run_id);",
+    // 9: content seen at more than one path, with the bytes extra copies take up
+    "CREATE VIEW duplicate_groups AS
+        SELECT a.id AS artifact_id, a.hash_sha256, a.size_bytes,
+               COUNT(*) AS copies,
+               COALESCE(a.size_bytes, 0) * (COUNT(*) - 1) AS wasted_bytes
+        FROM artifacts a JOIN artifact_paths p ON p.artifact_id = a.id
+        GROUP BY a.id
+        HAVING COUNT(*) > 1;",
+    // 10: tombstones; deletes and rollbacks mark rows instead of removing them
+    "ALTER TABLE artifacts ADD COLUMN deleted_at INTEGER;
+     ALTER TABLE artifacts ADD COLUMN deleted_reason TEXT;
+     ALTER TABLE artifact_paths ADD COLUMN deleted_at INTEGER;
+     CREATE INDEX idx_artifacts_deleted ON artifacts(deleted_at);
+     DROP VIEW duplicate_groups;
+     CREATE VIEW duplicate_groups AS
+        SELECT a.id AS artifact_id, a.hash_sha256, a.size_bytes,
+               COUNT(*) AS copies,
+               COALESCE(a.size_bytes, 0) * (COUNT(*) - 1) AS wasted_bytes
+        FROM artifacts a JOIN artifact_paths p ON p.artifact_id = a.id
+        WHERE p.deleted_at IS NULL
+        GROUP BY a.id
+        HAVING COUNT(*) > 1;",
+    // 11: the volume (filesystem UUID, label, host) each path was read from
+    "CREATE TABLE sources (
+        id INTEGER PRIMARY KEY,
+        host TEXT NOT NULL,
+        fs_uuid TEXT,
+        label TEXT,
+        mount_point TEXT,
+        first_seen INTEGER NOT NULL,
+        last_seen INTEGER NOT NULL
+     );
+     CREATE UNIQUE INDEX idx_sources_uuid ON sources(fs_uuid) WHERE fs_uuid IS NOT NULL;
+     ALTER TABLE artifact_paths ADD COLUMN source_id INTEGER REFERENCES sources(id);
+     CREATE INDEX idx_artifact_paths_source ON artifact_paths(source_id);",
+    // 12: fixity history, one row per path per verify pass
+    "CREATE TABLE fixity_checks (
+        id INTEGER PRIMARY KEY,
+        artifact_id INTEGER NOT NULL REFERENCES artifacts(id),
+        path TEXT NOT NULL,
+        checked_at INTEGER NOT NULL,
+        algo TEXT NOT NULL,
+        result TEXT NOT NULL,
+        detail TEXT
+     );
+     CREATE INDEX idx_fixity_checks_artifact ON fixity_checks(artifact_id, checked_at);",
+    // 13: tag aliases (alias label -> canonical tag) and implications (tag -> implied tag)
+    "CREATE TABLE tag_aliases (
+        namespace TEXT NOT NULL,
+        name TEXT NOT NULL,
+        tag_id INTEGER NOT NULL REFERENCES tags(id),
+        PRIMARY KEY(namespace, name)
+     );
+     CREATE TABLE tag_implications (
+        tag_id INTEGER NOT NULL REFERENCES tags(id),
+        implied_tag_id INTEGER NOT NULL REFERENCES tags(id),
+        PRIMARY KEY(tag_id, implied_tag_id)
+     );",
+    // 14: links between artifacts, read as `artifact <kind> related`, e.g. a frame extracted-from a video
+    "CREATE TABLE relationships (
+        artifact_id INTEGER NOT NULL REFERENCES artifacts(id),
+        kind TEXT NOT NULL,
+        related_id INTEGER NOT NULL REFERENCES artifacts(id),
+        created_at INTEGER NOT NULL,
+        PRIMARY KEY(artifact_id, kind, related_id)
+     );
+     CREATE INDEX idx_relationships_related ON relationships(related_id);",
+    // 15: which model version produced a score, so re-ingest never downgrades it
+    "ALTER TABLE safety_scores ADD COLUMN model_version TEXT;",
+    // 16: advisory leases of the processes using the catalog, see `store::LeaseKind`
+    "CREATE TABLE leases (
+        id INTEGER PRIMARY KEY,
+        kind TEXT NOT NULL,
+        command TEXT NOT NULL,
+        host TEXT NOT NULL,
+        pid INTEGER NOT NULL,
+        acquired_at INTEGER NOT NULL,
+        expires_at INTEGER NOT NULL
+     );",
+    // 17: aggregates for `stats` and dashboards, over live artifacts only
+    "CREATE VIEW media_type_stats AS
+        SELECT media_type, COUNT(*) AS artifacts, COALESCE(SUM(size_bytes), 0) AS bytes
+        FROM artifacts WHERE deleted_at IS NULL
+        GROUP BY media_type;
+     CREATE VIEW tag_stats AS
+        SELECT t.id AS tag_id, t.namespace, t.name, COUNT(*) AS artifacts
+        FROM tags t JOIN artifact_tags at ON at.tag_id = t.id JOIN artifacts a ON a.id = at.artifact_id
+        WHERE a.deleted_at IS NULL
+        GROUP BY t.id;
+     -- Scores are f32s, so 0.9 is stored as 0.8999999761; rounding keeps it in bucket 9.
+     CREATE VIEW nsfw_score_histogram AS
+        SELECT MIN(CAST(ROUND(s.nsfw_score * 10, 5) AS INTEGER), 9) AS bucket, COUNT(*) AS artifacts
+        FROM safety_scores s JOIN artifacts a ON a.id = s.artifact_id
+        WHERE a.deleted_at IS NULL
+        GROUP BY bucket;
+     CREATE VIEW run_stats AS
+        SELECT r.id AS run_id, r.started_at, r.finished_at, r.status, r.files_seen, r.artifacts_added, r.errors,
+               (SELECT COALESCE(SUM(a.size_bytes), 0) FROM artifacts a WHERE a.run_id = r.id) AS bytes_added,
+               r.finished_at - r.started_at AS duration_secs
+        FROM runs r;",
+    // 18: archive volumes written by `archive` and the content on each, so any
+    // file can be located later; artifact_id is matched by hash when known
+    "CREATE TABLE archive_volumes (
+        id INTEGER PRIMARY KEY,
+        name TEXT NOT NULL,
+        format TEXT NOT NULL,
+        created_at INTEGER NOT NULL,
+        capacity_bytes INTEGER,
+        size_bytes INTEGER NOT NULL
+     );
+     CREATE TABLE archive_members (
+        volume_id INTEGER NOT NULL REFERENCES archive_volumes(id),
+        path TEXT NOT NULL,
+        hash_sha256 TEXT NOT NULL,
+        size_bytes INTEGER NOT NULL,
+        artifact_id INTEGER REFERENCES artifacts(id),
+        PRIMARY KEY(volume_id, path)
+     );
+     CREATE INDEX idx_archive_members_hash ON archive_members(hash_sha256);
+     CREATE INDEX idx_archive_members_artifact ON archive_members(artifact_id);",
+    // 19: volume registry: the label written on the media and the image checksum
+    "ALTER TABLE archive_volumes ADD COLUMN label TEXT;
+     ALTER TABLE archive_volumes ADD COLUMN sha256 TEXT;
+     UPDATE archive_volumes SET label = printf('VOL-%04d', id);
+     CREATE UNIQUE INDEX idx_archive_volumes_label ON archive_volumes(label);",
+    // 20: discs burned from volume images; verified is NULL when the disc
+    // wasn't read back, 0 when it didn't match the image
+    "CREATE TABLE volume_discs (
+        id INTEGER PRIMARY KEY,
+        volume_id INTEGER NOT NULL REFERENCES archive_volumes(id),
+        burned_at INTEGER NOT NULL,
+        device TEXT NOT NULL,
+        drive TEXT,
+        media TEXT,
+        verified INTEGER
+     );
+     CREATE INDEX idx_volume_discs_volume ON volume_discs(volume_id);",
+    // 21: copies of volumes in cloud buckets; layout is 'image' for the
+    // volume file as one object, 'objects' for its files stored by hash
+    // under object_key
+    "CREATE TABLE volume_uploads (
+        id INTEGER PRIMARY KEY,
+        volume_id INTEGER NOT NULL REFERENCES archive_volumes(id),
+        remote TEXT NOT NULL,
+        bucket TEXT NOT NULL,
+        object_key TEXT NOT NULL,
+        layout TEXT NOT NULL,
+        object_id TEXT,
+        storage_class TEXT,
+        size_bytes INTEGER NOT NULL,
+        uploaded_at INTEGER NOT NULL
+     );
+     CREATE INDEX idx_volume_uploads_volume ON volume_uploads(volume_id);",
+    // 22: barcode of the tape a volume was written to
+    "ALTER TABLE archive_volumes ADD COLUMN barcode TEXT;",
+    // 23: where each file's content starts in the volume image, for images
+    // holding it uncompressed and in one piece (ISO, UDF, stored ZIP
+    // entries, tape); NULL otherwise
+    "ALTER TABLE archive_members ADD COLUMN offset_bytes INTEGER;",
+    // 24: journal of the files a run has committed, with the size and mtime
+    // they had, so `ingest --resume` can skip them after a crash; cleared
+    // once the run completes
+    "CREATE TABLE run_files (
+        run_id INTEGER NOT NULL REFERENCES runs(id),
+        path TEXT NOT NULL,
+        size_bytes INTEGER,
+        mtime INTEGER,
+        PRIMARY KEY(run_id, path)
+     );",
+    // 25: what the run did stage by stage, as `RunSummary` JSON
+    "ALTER TABLE runs ADD COLUMN summary TEXT;",
+    // 26: what a failed file got through before it failed, as `PartialResult`
+    // JSON, so `replay-failed` can pick up from there
+    "ALTER TABLE ingest_errors ADD COLUMN partial TEXT;",
+    // 27: how long ingest spent on each file, stage by stage, for
+    // `stats --slowest`; kept when the run is rolled back
+    "CREATE TABLE file_timings (
+        run_id INTEGER NOT NULL REFERENCES runs(id),
+        path TEXT NOT NULL,
+        hash_sha256 TEXT NOT NULL,
+        size_bytes INTEGER,
+        hash_ms INTEGER NOT NULL,
+        decode_ms INTEGER NOT NULL,
+        inference_ms INTEGER NOT NULL,
+        total_ms INTEGER NOT NULL,
+        PRIMARY KEY(run_id, path)
+     );
+     CREATE INDEX idx_file_timings_total ON file_timings(total_ms);",
+    // 28: the run a lease's process is writing, so a run still being
+    // written isn't resumed by a second process
+    "ALTER TABLE leases ADD COLUMN run_id INTEGER;",
+    // 29: the tail of ffmpeg's stderr, apart from the error message
+    "ALTER TABLE ingest_errors ADD COLUMN stderr TEXT;",
+];
+
+/// Fails with a clear message if a catalog needs migrations that a read-only
+/// connection can't apply.
+pub fn check_version(conn: &Connection) -> Result<()> {
+    let version: usize = conn.query_row("PRAGMA user_version", [], |row| row.get(0))?;
+    if version < MIGRATIONS.len() {
+        bail!(
+            "Catalog schema is at version {} but this build expects {}; run any writing command (e.g. `reindex-fts`) to upgrade it",
+            version,
+            MIGRATIONS.len()
+        );
+    }
+    Ok(())
+}
+
+/// Creates the base tables and brings the catalog up to the latest version.
+pub fn initialize(conn: &mut Connection) -> Result<()> {
+    conn.execute_batch(SCHEMA).context("Failed to initialize schema")?;
+
+    let version: usize = conn.query_row("PRAGMA user_version", [], |row| row.get(0))?;
+    if version >= MIGRATIONS.len() {
+        return Ok(());
+    }
+
+    let tx = conn.transaction()?;
+    for (i, migration) in MIGRATIONS.iter().enumerate().skip(version) {
+        tx.execute_batch(migration)
+            .with_context(|| format!("Failed to apply schema migration {}", i + 1))?;
+    }
+    tx.pragma_update(None, "user_version", MIGRATIONS.len())?;
+    tx.commit()?;
+    Ok(())
+}
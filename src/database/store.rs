@@ -0,0 +1,252 @@
+use std::collections::{HashMap, HashSet};
+use std::time::Duration;
+use anyhow::{Result, anyhow, bail};
+use crate::database::repo::{ArtifactRecord, CommittedFile, IngestError, PartialResult, Run, TagRules, TransactionManager};
+use crate::ingest::source;
+use crate::ingest::summary::RunSummary;
+use crate::utils::config::DatabaseConfig;
+use crate::utils::units::format_timestamp;
+
+/// A lease not renewed for this long belongs to a crashed process and is ignored.
+pub const LEASE_TTL: Duration = Duration::from_secs(120);
+/// Holders renew their lease at least this often while they work.
+pub const LEASE_RENEW: Duration = Duration::from_secs(30);
+
+/// How a process uses the catalog, recorded in the `leases` table so that
+/// concurrent invocations can see each other. Runs (ingest, import) hold
+/// shared leases and may overlap; maintenance that would invalidate a
+/// running ingest (rollback, delete, optimize) needs an exclusive one.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum LeaseKind {
+    Shared,
+    Exclusive,
+}
+
+impl LeaseKind {
+    pub fn as_str(self) -> &'static str {
+        match self {
+            LeaseKind::Shared => "shared",
+            LeaseKind::Exclusive => "exclusive",
+        }
+    }
+}
+
+/// A live lease of another process that blocks acquiring one.
+pub struct LeaseHolder {
+    pub command: String,
+    pub kind: String,
+    pub host: String,
+    pub pid: i64,
+    pub acquired_at: i64,
+}
+
+impl LeaseHolder {
+    pub fn into_error(self) -> anyhow::Error {
+        anyhow!(
+            "The catalog is in use ({} lease) by `{}` (pid {} on {}, since {}); try again once it has finished",
+            self.kind,
+            self.command,
+            self.pid,
+            self.host,
+            format_timestamp(Some(self.acquired_at))
+        )
+    }
+
+    /// The error for resuming `run_id` while this holder still writes it.
+    pub fn writing(self, run_id: i64) -> anyhow::Error {
+        anyhow!(
+            "Run {} is still being written by `{}` (pid {} on {}, since {}); resume it once that process has stopped",
+            run_id,
+            self.command,
+            self.pid,
+            self.host,
+            format_timestamp(Some(self.acquired_at))
+        )
+    }
+}
+
+/// `(command, host, pid)` identifying this process in `leases`.
+pub fn lease_owner() -> (String, String, i64) {
+    let command: Vec<String> = std::env::args().collect();
+    (command.join(" "), source::host_name(), std::process::id() as i64)
+}
+
+/// The write side of the catalog (plus run bookkeeping), implemented by the
+/// SQLite `TransactionManager` and, with the `postgres` feature, a PostgreSQL
+/// backend that several ingest machines can share.
+pub trait CatalogStore: Send {
+    /// Takes a lease for as long as the store lives, failing if another
+    /// process holds a conflicting one.
+    fn acquire_lease(&mut self, kind: LeaseKind) -> Result<()>;
+
+    /// Opens a `runs` row and takes a shared lease until `finish_run`; every
+    /// record flushed afterwards is stamped with it. `input_roots` and
+    /// `options` are stored as JSON.
+    fn begin_run(&mut self, input_roots: &[String], options: &str) -> Result<i64>;
+
+    /// Flushes and closes the current run with its final counts and, for
+    /// pipeline runs, their summary. A run that completes forgets which
+    /// files it committed.
+    fn finish_run(&mut self, status: &str, errors: u64, summary: Option<&RunSummary>) -> Result<()>;
+
+    /// Reopens a run that was interrupted or never finished (a crash)
+    /// instead of starting one with `begin_run`, unless another process's
+    /// live lease says it is still writing it. Returns the files it has
+    /// committed so far, by path.
+    fn resume_run(&mut self, run_id: i64) -> Result<HashMap<String, CommittedFile>>;
+
+    /// Undoes a run: artifacts it discovered and new paths it added to older
+    /// artifacts are tombstoned, or with `purge` deleted along with their tags,
+    /// scores and paths. An artifact it discovered that a later run found
+    /// elsewhere is kept and passed on to the latest such run.
+    fn rollback_run(&mut self, run_id: i64, purge: bool) -> Result<usize>;
+
+    /// Persists a failure for later triage, `errors retry` and
+    /// `replay-failed`, with the tail of ffmpeg's stderr if it failed and
+    /// what the file got through before it failed.
+    fn record_error(&mut self, path: &str, stage: &str, error: &str, stderr: Option<&str>, partial: Option<&PartialResult>) -> Result<()>;
+
+    /// Buffers a record, flushing once the buffer is full or the flush
+    /// interval has passed.
+    fn add(&mut self, record: ArtifactRecord) -> Result<()>;
+
+    /// Flushes if the flush interval has passed since the last flush, and
+    /// renews the lease. Callers waiting on slow input call this at least
+    /// every `LEASE_RENEW`.
+    fn flush_if_due(&mut self) -> Result<()>;
+
+    /// Writes all buffered records in one transaction, retried while another
+    /// process holds the write lock (SQLite). A hash that is
+    /// already catalogued is merged rather than overwritten:
+    ///
+    /// * tags are unioned and paths added
+    /// * width/height keep whichever sighting has more pixels
+    /// * a generic `application/octet-stream` never replaces a detected type
+    /// * metadata objects are merged, new keys winning
+    /// * scores and embeddings are replaced only by a newer model version
+    fn flush(&mut self) -> Result<()>;
+
+    /// Records buffered and not yet flushed.
+    fn pending(&self) -> usize;
+
+    /// Recorded ingest failures, oldest first, optionally limited to one run.
+    fn errors(&mut self, run_id: Option<i64>, include_resolved: bool) -> Result<Vec<IngestError>>;
+
+    /// All runs, newest first.
+    fn runs(&mut self) -> Result<Vec<Run>>;
+
+    /// Hashes of every artifact in the catalog, tombstoned ones included,
+    /// so ingest can skip analyzing content it has seen before.
+    fn known_hashes(&mut self) -> Result<HashSet<String>>;
+
+    /// Live paths under the directory `root`, with the size and mtime of
+    /// the content catalogued for them, so a watcher can tell which files
+    /// changed since. Paths holding content first found elsewhere carry
+    /// that file's mtime.
+    fn catalogued_files(&mut self, root: &str) -> Result<HashMap<String, CommittedFile>>;
+
+    /// Live paths of the artifact with `hash`, first seen first.
+    fn live_paths(&mut self, hash: &str) -> Result<Vec<String>>;
+
+    /// Makes `alias` stand for `tag` on write and in filters. An existing
+    /// `alias` tag is merged into `tag`.
+    fn add_alias(&mut self, alias: &str, tag: &str) -> Result<()>;
+
+    /// Returns false if there was no such alias.
+    fn remove_alias(&mut self, alias: &str) -> Result<bool>;
+
+    /// Every artifact tagged `tag` also gets `implied`, including those
+    /// already in the catalog.
+    fn add_implication(&mut self, tag: &str, implied: &str) -> Result<()>;
+
+    /// Stops applying the rule; tags it already added stay.
+    /// Returns false if there was no such implication.
+    fn remove_implication(&mut self, tag: &str, implied: &str) -> Result<bool>;
+
+    /// All aliases and implications, as labels.
+    fn tag_rules(&mut self) -> Result<TagRules>;
+
+    /// Records that the artifact `hash` is `kind` (e.g. `thumbnail-of`) the
+    /// artifact `related_hash`. Both must be in the catalog.
+    fn relate(&mut self, hash: &str, kind: &str, related_hash: &str) -> Result<()>;
+
+    /// Returns false if there was no such relationship.
+    fn unrelate(&mut self, hash: &str, kind: &str, related_hash: &str) -> Result<bool>;
+
+    /// Tags the artifact `hash` by hand, following aliases and adding the
+    /// tags `tag` implies.
+    fn add_tag(&mut self, hash: &str, tag: &str) -> Result<()>;
+
+    /// Returns false if the artifact didn't have the tag. Tags it implies
+    /// stay.
+    fn remove_tag(&mut self, hash: &str, tag: &str) -> Result<bool>;
+
+    /// Adds many `(hash, tag)` pairs in one transaction, as `add_tag` does
+    /// one. Pairs whose artifact isn't catalogued or whose tag is empty are
+    /// skipped. Returns the number of tags the artifacts didn't have yet.
+    fn add_tags(&mut self, tags: &[(String, String)]) -> Result<usize>;
+}
+
+/// Opens the catalog named by `--db-path`: a `postgres://` URL or a SQLite file.
+pub fn open(db_path: &str, config: &DatabaseConfig) -> Result<Box<dyn CatalogStore>> {
+    if is_postgres_url(db_path) {
+        #[cfg(feature = "postgres")]
+        return Ok(Box::new(crate::database::postgres::PgStore::connect(db_path, config)?));
+        #[cfg(not(feature = "postgres"))]
+        bail!("{} is a PostgreSQL URL, but this build lacks the `postgres` feature", redact(db_path));
+    }
+    Ok(Box::new(TransactionManager::new(db_path, config)?))
+}
+
+/// Fails unless a run with `status` can be resumed: one that was
+/// interrupted, or is still `running` because its process died.
+pub fn check_resumable(run_id: i64, status: Option<&str>) -> Result<()> {
+    match status {
+        None => bail!("There is no run {}", run_id),
+        Some("running" | "interrupted") => Ok(()),
+        Some(status) => bail!("Run {} is {}; only interrupted runs and runs that never finished can be resumed", run_id, status),
+    }
+}
+
+/// Commands that read through `CatalogReader` (query, export) are SQLite-only for now.
+pub fn require_sqlite(db_path: &str) -> Result<()> {
+    if is_postgres_url(db_path) {
+        bail!("This command only supports SQLite catalogs, not {}", redact(db_path));
+    }
+    Ok(())
+}
+
+/// The bounds of the paths under `root` in byte order: `root/` inclusive
+/// to `root` and the character after the separator, exclusive.
+pub(crate) fn subtree(root: &str) -> (String, String) {
+    let root = root.trim_end_matches(std::path::MAIN_SEPARATOR);
+    let after = (std::path::MAIN_SEPARATOR as u8 + 1) as char;
+    (format!("{}{}", root, std::path::MAIN_SEPARATOR), format!("{}{}", root, after))
+}
+
+fn is_postgres_url(db_path: &str) -> bool {
+    db_path.starts_with("postgres://") || db_path.starts_with("postgresql://")
+}
+
+/// Drops the password from a connection URL before it ends up in a message.
+pub fn redact(url: &str) -> String {
+    match (url.find("://"), url.rfind('@')) {
+        (Some(scheme), Some(at)) if at > scheme => {
+            let userinfo = &url[scheme + 3..at];
+            let user = userinfo.split(':').next().unwrap_or_default();
+            format!("{}{}:***{}", &url[..scheme + 3], user, &url[at..])
+        }
+        _ => url.to_string(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_redact() {
+        assert_eq!(redact("postgres://me:secret@db:5432/archive"), "postgres://me:***@db:5432/archive");
+        assert_eq!(redact("data/archive_index.db"), "data/archive_index.db");
+    }
+}
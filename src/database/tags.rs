@@ -0,0 +1,74 @@
+use std::fmt;
+use anyhow::{Result, bail};
+
+/// SQL expression rendering a `tags t` row back to its `namespace:name` label.
+pub const LABEL_SQL: &str = "CASE WHEN t.namespace = '' THEN t.name ELSE t.namespace || ':' || t.name END";
+
+/// Recursive CTE `closure(tag_id, implied_id)`: every tag each tag implies,
+/// directly or through a chain of `tag_implications`. Valid in SQLite and PostgreSQL.
+pub const IMPLIED_CLOSURE: &str = "closure(tag_id, implied_id) AS (
+        SELECT tag_id, implied_tag_id FROM tag_implications
+        UNION
+        SELECT c.tag_id, i.implied_tag_id FROM closure c JOIN tag_implications i ON i.tag_id = c.implied_id
+    )";
+
+/// A tag split at its first `:` into namespace and name, e.g. `meta:camera=canon`
+/// becomes (`meta`, `camera=canon`). Tags without a prefix live in the empty namespace.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Tag<'a> {
+    pub namespace: &'a str,
+    pub name: &'a str,
+}
+
+impl<'a> Tag<'a> {
+    pub fn parse(label: &'a str) -> Self {
+        match label.split_once(':') {
+            Some((namespace, name)) => Tag { namespace, name },
+            None => Tag { namespace: "", name: label },
+        }
+    }
+
+    /// `ns:*` selects every tag in a namespace rather than a single tag.
+    pub fn is_wildcard(&self) -> bool {
+        self.name == "*"
+    }
+}
+
+/// Rejects alias/implication rules that could never apply or would point a tag at itself.
+pub fn check_rule(from: Tag, to: Tag) -> Result<()> {
+    for tag in [from, to] {
+        if tag.name.is_empty() || tag.is_wildcard() {
+            bail!("'{}' is not a single tag", tag);
+        }
+    }
+    if from == to {
+        bail!("A rule cannot map '{}' to itself", from);
+    }
+    Ok(())
+}
+
+impl fmt::Display for Tag<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        if self.namespace.is_empty() {
+            write!(f, "{}", self.name)
+        } else {
+            write!(f, "{}:{}", self.namespace, self.name)
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse() {
+        assert_eq!(Tag::parse("person:alice"), Tag { namespace: "person", name: "alice" });
+        assert_eq!(Tag::parse("meta:camera=canon"), Tag { namespace: "meta", name: "camera=canon" });
+        assert_eq!(Tag::parse("a:b:c"), Tag { namespace: "a", name: "b:c" });
+        assert_eq!(Tag::parse("landscape"), Tag { namespace: "", name: "landscape" });
+        assert!(Tag::parse("ml:*").is_wildcard());
+        assert_eq!(Tag::parse("a:b:c").to_string(), "a:b:c");
+        assert_eq!(Tag::parse("landscape").to_string(), "landscape");
+    }
+}
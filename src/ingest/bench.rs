@@ -0,0 +1,302 @@
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::{Mutex, OnceLock};
+use std::thread;
+use std::time::Instant;
+use anyhow::{Result, Context, bail};
+use image::{DynamicImage, ImageBuffer, Rgb, RgbImage};
+use serde::Serialize;
+use tracing::{info, warn};
+use walkdir::WalkDir;
+use crate::ingest::hasher;
+use crate::media::{decode, mimetype};
+use crate::ml::engine::InferenceEngine;
+use crate::ml::pipeline;
+use crate::utils::config::Config;
+
+/// One pipeline stage measured on its own.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Workload {
+    /// Reading and hashing files, as the hashers do.
+    Hash,
+    /// Sampling frames from images and videos.
+    Decode,
+    /// Running the models on frames decoded beforehand.
+    Inference,
+}
+
+impl Workload {
+    pub fn name(self) -> &'static str {
+        match self {
+            Workload::Hash => "hash",
+            Workload::Decode => "decode",
+            Workload::Inference => "inference",
+        }
+    }
+}
+
+/// Files a workload runs over, with their detected types.
+pub struct Sample {
+    files: Vec<(PathBuf, String)>,
+    /// Frames for inference, decoded the first time they're needed.
+    frames: OnceLock<Vec<DynamicImage>>,
+    /// Generated files, removed when the sample is dropped.
+    generated: Option<PathBuf>,
+}
+
+impl Sample {
+    /// Up to `limit` files under `dir`; for decoding and inference only
+    /// images and videos.
+    pub fn from_dir(dir: &Path, workload: Workload, limit: usize) -> Result<Self> {
+        let mut files = Vec::new();
+        for entry in WalkDir::new(dir).into_iter().filter_map(|e| e.ok()).filter(|e| e.file_type().is_file()) {
+            if files.len() == limit {
+                break;
+            }
+            let media_type = match mimetype::detect_mimetype(entry.path()) {
+                Ok(media_type) => media_type,
+                Err(e) => {
+                    warn!("Leaving {:?} out of the sample: {}", entry.path(), e);
+                    continue;
+                }
+            };
+            if workload == Workload::Hash || media_type.starts_with("image/") || media_type.starts_with("video/") {
+                files.push((entry.into_path(), media_type));
+            }
+        }
+        if files.is_empty() {
+            bail!("No files to {} under {:?}", workload.name(), dir);
+        }
+        Ok(Sample { files, frames: OnceLock::new(), generated: None })
+    }
+
+    /// `count` generated files in the temp directory: incompressible data
+    /// of `size` bytes for hashing, else 1920x1080 PNGs.
+    pub fn synthetic(workload: Workload, count: usize, size: usize) -> Result<Self> {
+        let dir = std::env::temp_dir().join(format!("deep-archive-bench-{}-{}", std::process::id(), workload.name()));
+        fs::create_dir_all(&dir).with_context(|| format!("Failed to create {:?}", dir))?;
+        let mut sample = Sample { files: Vec::new(), frames: OnceLock::new(), generated: Some(dir.clone()) };
+        // A fixed xorshift stream: cheap, the same on every run, and too
+        // random for the page cache or disk compression to shortcut.
+        let mut state = 0x9e37_79b9_7f4a_7c15u64;
+        let mut next = move || {
+            state ^= state << 13;
+            state ^= state >> 7;
+            state ^= state << 17;
+            state
+        };
+        for i in 0..count {
+            let (path, media_type) = match workload {
+                Workload::Hash => {
+                    let path = dir.join(format!("{}.bin", i));
+                    let data: Vec<u8> = (0..size.div_ceil(8)).flat_map(|_| next().to_le_bytes()).take(size).collect();
+                    fs::write(&path, data)?;
+                    (path, "application/octet-stream")
+                }
+                Workload::Decode | Workload::Inference => {
+                    let path = dir.join(format!("{}.png", i));
+                    let seed = next();
+                    let image: RgbImage = ImageBuffer::from_fn(1920, 1080, |x, y| {
+                        let v = (x as u64 * 7 + y as u64 * 13 + seed) as u8;
+                        Rgb([v, v.wrapping_mul(3), (x ^ y) as u8])
+                    });
+                    image.save(&path)?;
+                    (path, "image/png")
+                }
+            };
+            sample.files.push((path, media_type.to_string()));
+        }
+        Ok(sample)
+    }
+
+    pub fn len(&self) -> usize {
+        self.files.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.files.is_empty()
+    }
+}
+
+impl Drop for Sample {
+    fn drop(&mut self) {
+        if let Some(dir) = &self.generated {
+            let _ = fs::remove_dir_all(dir);
+        }
+    }
+}
+
+/// Throughput of one workload at one configuration.
+#[derive(Debug, Clone, Serialize)]
+pub struct Measurement {
+    pub workload: Workload,
+    pub threads: usize,
+    /// `media.hwaccel` the frames were decoded with, for decoding.
+    pub hwaccel: Option<&'static str>,
+    pub files: usize,
+    pub bytes: u64,
+    pub frames: usize,
+    pub errors: usize,
+    pub secs: f64,
+}
+
+impl Measurement {
+    pub fn files_per_sec(&self) -> f64 {
+        self.files as f64 / self.secs.max(f64::EPSILON)
+    }
+
+    pub fn bytes_per_sec(&self) -> f64 {
+        self.bytes as f64 / self.secs.max(f64::EPSILON)
+    }
+
+    pub fn frames_per_sec(&self) -> f64 {
+        self.frames as f64 / self.secs.max(f64::EPSILON)
+    }
+}
+
+/// What one file contributed to a measurement.
+#[derive(Default)]
+struct Done {
+    bytes: u64,
+    frames: usize,
+}
+
+/// Runs `workload` over every file of `sample` with `threads` threads
+/// taking files in turn, as the pipeline's stages do. Inference needs
+/// `engine`; the frames it scores are decoded before the clock starts.
+pub fn measure(workload: Workload, sample: &Sample, threads: usize, config: &Config, engine: Option<&InferenceEngine>) -> Result<Measurement> {
+    let frames = match workload {
+        Workload::Inference => {
+            if engine.is_none() {
+                bail!("The inference benchmark needs the models; run ./setup.sh to download them");
+            }
+            sample.frames.get_or_init(|| decode_all(sample, config)).as_slice()
+        }
+        _ => &[][..],
+    };
+    let jobs = match workload {
+        Workload::Inference => frames.len(),
+        _ => sample.files.len(),
+    };
+
+    let next = AtomicUsize::new(0);
+    let errors = AtomicUsize::new(0);
+    let total = Mutex::new(Done::default());
+    let started = Instant::now();
+    thread::scope(|scope| {
+        for _ in 0..threads.max(1) {
+            scope.spawn(|| {
+                let mut done = Done::default();
+                loop {
+                    let i = next.fetch_add(1, Ordering::Relaxed);
+                    if i >= jobs {
+                        break;
+                    }
+                    let result = match workload {
+                        Workload::Hash => hasher::fingerprint(&sample.files[i].0).map(|f| Done { bytes: f.size, frames: 0 }),
+                        Workload::Decode => decode_file(&sample.files[i], config),
+                        Workload::Inference => {
+                            pipeline::score_frame(engine.expect("engine"), &frames[i]);
+                            Ok(Done { bytes: 0, frames: 1 })
+                        }
+                    };
+                    match result {
+                        Ok(file) => {
+                            done.bytes += file.bytes;
+                            done.frames += file.frames;
+                        }
+                        Err(e) => {
+                            warn!("Benchmark {} failed: {}", workload.name(), e);
+                            errors.fetch_add(1, Ordering::Relaxed);
+                        }
+                    }
+                }
+                let mut total = total.lock().unwrap();
+                total.bytes += done.bytes;
+                total.frames += done.frames;
+            });
+        }
+    });
+    let secs = started.elapsed().as_secs_f64();
+
+    let total = total.into_inner().unwrap();
+    let errors = errors.into_inner();
+    Ok(Measurement {
+        workload,
+        threads: threads.max(1),
+        hwaccel: (workload == Workload::Decode).then(|| config.media.hwaccel.ffmpeg_name().unwrap_or("none")),
+        files: match workload {
+            Workload::Inference => sample.files.len(),
+            _ => jobs - errors,
+        },
+        bytes: total.bytes,
+        frames: total.frames,
+        errors,
+        secs,
+    })
+}
+
+fn decode_file((path, media_type): &(PathBuf, String), config: &Config) -> Result<Done> {
+    let sampling = config.media.sampling_for(media_type);
+    let mut done = Done { bytes: fs::metadata(path)?.len(), frames: 0 };
+    for frame in decode::extract_frames(path, media_type, &config.media, &sampling)? {
+        frame?;
+        done.frames += 1;
+    }
+    Ok(done)
+}
+
+/// Every frame the sample yields, as the models stage gets them.
+fn decode_all(sample: &Sample, config: &Config) -> Vec<DynamicImage> {
+    let started = Instant::now();
+    let mut images = Vec::new();
+    for (path, media_type) in &sample.files {
+        let sampling = config.media.sampling_for(media_type);
+        let frames = match decode::extract_frames(path, media_type, &config.media, &sampling) {
+            Ok(frames) => frames,
+            Err(e) => {
+                warn!("Leaving {:?} out of the inference benchmark: {}", path, e);
+                continue;
+            }
+        };
+        for raw in frames.flatten() {
+            if let Some(buffer) = ImageBuffer::<Rgb<u8>, Vec<u8>>::from_raw(sampling.resolution, sampling.resolution, raw) {
+                images.push(DynamicImage::ImageRgb8(buffer));
+            }
+        }
+    }
+    info!("Decoded {} frames for the inference benchmark in {:?}", images.len(), started.elapsed());
+    images
+}
+
+/// The fastest measurement of `workload`, to suggest its thread count.
+pub fn best(measurements: &[Measurement], workload: Workload) -> Option<&Measurement> {
+    let rate = |m: &Measurement| match workload {
+        Workload::Hash => m.bytes_per_sec(),
+        _ => m.frames_per_sec(),
+    };
+    measurements.iter().filter(|m| m.workload == workload && m.errors == 0).max_by(|a, b| rate(a).total_cmp(&rate(b)))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_measure_hash() -> Result<()> {
+        let sample = Sample::synthetic(Workload::Hash, 6, 10_000)?;
+        let dir = sample.generated.clone().unwrap();
+        let config = Config::default();
+        let measurements = vec![measure(Workload::Hash, &sample, 1, &config, None)?, measure(Workload::Hash, &sample, 3, &config, None)?];
+        for m in &measurements {
+            assert_eq!((m.files, m.bytes, m.errors), (6, 60_000, 0));
+        }
+        assert!(best(&measurements, Workload::Hash).is_some());
+        assert!(measure(Workload::Inference, &sample, 1, &config, None).is_err());
+        drop(sample);
+        assert!(!dir.exists());
+        Ok(())
+    }
+}
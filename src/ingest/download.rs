@@ -0,0 +1,198 @@
+use std::collections::BTreeMap;
+use std::fs::{self, File};
+use std::io::{self, BufRead, BufReader};
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+use anyhow::{Result, Context, anyhow, bail};
+use chrono::Utc;
+use serde_json::json;
+use tracing::{debug, info, warn};
+use ureq::http::{Request, Response};
+use ureq::tls::{RootCerts, TlsConfig, TlsProvider};
+use ureq::{Agent, Body, ResponseExt};
+use crate::ingest::pipeline::Fetched;
+use crate::utils::config::{DownloadConfig, RetryPolicy};
+use crate::utils::retry::Transient;
+
+/// Name of a download whose URL ends in a slash.
+const INDEX: &str = "index";
+
+/// The URLs in `path` (`-` for stdin), one per line. Blank lines and lines
+/// starting with `#` are skipped.
+pub fn read_list(path: &Path) -> Result<Vec<String>> {
+    let reader: Box<dyn BufRead> = match path.to_str() {
+        Some("-") => Box::new(BufReader::new(io::stdin())),
+        _ => Box::new(BufReader::new(File::open(path).with_context(|| format!("Failed to open {:?}", path))?)),
+    };
+    let mut urls = Vec::new();
+    for line in reader.lines() {
+        let line = line?;
+        let line = line.trim();
+        if !line.is_empty() && !line.starts_with('#') {
+            urls.push(line.to_string());
+        }
+    }
+    Ok(urls)
+}
+
+/// Downloads HTTP(S) resources into a directory of this run's own under
+/// `download.spool_dir`, so every retrieval is kept.
+pub struct Downloader {
+    agent: Agent,
+    /// `spool_dir/<UTC time it was created>`
+    pub spool: PathBuf,
+}
+
+impl Downloader {
+    pub fn new(config: &DownloadConfig) -> Self {
+        let agent = Agent::new_with_config(
+            Agent::config_builder()
+                .http_status_as_error(false)
+                .proxy(ureq::Proxy::try_from_env())
+                .timeout_connect(Some(Duration::from_secs(30)))
+                .timeout_recv_response(Some(Duration::from_secs(60)))
+                .tls_config(TlsConfig::builder().provider(TlsProvider::NativeTls).root_certs(RootCerts::PlatformVerifier).build())
+                .user_agent(concat!("deep-archive/", env!("CARGO_PKG_VERSION")))
+                .build(),
+        );
+        Downloader { agent, spool: config.run_dir() }
+    }
+
+    /// Downloads each of `urls` in turn. One that fails is logged and left
+    /// out; it fails the lot only if none could be downloaded.
+    pub fn fetch_all(&self, urls: &[String], retry: &RetryPolicy) -> Result<Vec<Fetched>> {
+        let mut fetched = Vec::new();
+        for url in urls {
+            match retry.run(&format!("Downloading {}", url), || self.fetch(url)) {
+                Ok(file) => fetched.push(file),
+                Err(e) => warn!("Failed to download {}: {:#}", url, e),
+            }
+        }
+        info!("Downloaded {} of {} URLs into {:?}", fetched.len(), urls.len(), self.spool);
+        if fetched.is_empty() && !urls.is_empty() {
+            bail!("None of the {} URLs could be downloaded", urls.len());
+        }
+        Ok(fetched)
+    }
+
+    /// Downloads `url` to `spool/<host>/<name>`, with its URL, the one
+    /// redirects led to, the response's status and headers and when it
+    /// was retrieved as provenance.
+    pub fn fetch(&self, url: &str) -> Result<Fetched> {
+        if !url.starts_with("http://") && !url.starts_with("https://") {
+            bail!("{} is not an HTTP(S) URL", url);
+        }
+        let response = self.agent.run(Request::get(url).body(())?).map_err(|e| match e {
+            ureq::Error::BadUri(_) | ureq::Error::Tls(_) => anyhow!(e),
+            e => anyhow!(Transient(e.to_string())),
+        });
+        let response = response.with_context(|| format!("GET {} failed", url))?;
+        let status = response.status();
+        if status.is_server_error() || status == 429 {
+            return Err(anyhow!(Transient(format!("GET {} answered {}", url, status))));
+        }
+        if !status.is_success() {
+            bail!("GET {} answered {}", url, status);
+        }
+        let retrieved_at = Utc::now().to_rfc3339();
+        let final_url = response.get_uri().to_string();
+        let headers = headers(&response);
+
+        let path = self.place(&final_url)?;
+        let mut file = File::create(&path).with_context(|| format!("Failed to create {:?}", path))?;
+        let copied = io::copy(&mut response.into_body().into_reader(), &mut file);
+        if let Err(e) = copied {
+            let _ = fs::remove_file(&path);
+            return Err(anyhow!(Transient(e.to_string()))).with_context(|| format!("Reading {} failed", url));
+        }
+        debug!("Downloaded {} to {:?}", url, path);
+        let mut provenance = json!({
+            "url": url,
+            "retrieved_at": retrieved_at,
+            "status": status.as_u16(),
+            "headers": headers,
+        });
+        if final_url != url {
+            provenance["final_url"] = json!(final_url);
+        }
+        Ok(Fetched { path, provenance })
+    }
+
+    /// A new file for `url` in the spool: its host's directory, named after
+    /// the last segment of its path, numbered when another download took
+    /// that name.
+    fn place(&self, url: &str) -> Result<PathBuf> {
+        let (host, name) = file_name(url);
+        let dir = self.spool.join(host);
+        fs::create_dir_all(&dir).with_context(|| format!("Failed to create {:?}", dir))?;
+        let (stem, extension) = match name.rsplit_once('.') {
+            Some((stem, extension)) if !stem.is_empty() => (stem.to_string(), format!(".{}", extension)),
+            _ => (name.clone(), String::new()),
+        };
+        let mut path = dir.join(&name);
+        let mut n = 1;
+        while path.exists() {
+            n += 1;
+            path = dir.join(format!("{}-{}{}", stem, n, extension));
+        }
+        Ok(path)
+    }
+}
+
+/// The host of `url` and a file name for it, free of separators: the last
+/// segment of its path, still percent-encoded, without the query.
+fn file_name(url: &str) -> (String, String) {
+    let rest = url.split_once("://").map_or(url, |(_, rest)| rest);
+    let rest = rest.split(['?', '#']).next().unwrap_or_default();
+    let (authority, path) = rest.split_once('/').unwrap_or((rest, ""));
+    let host = authority.rsplit_once('@').map_or(authority, |(_, host)| host);
+    let clean = |text: &str| text.chars().map(|c| if c.is_control() || matches!(c, '/' | '\\' | ':') { '_' } else { c }).collect::<String>();
+    let name = match path.rsplit('/').next().map(clean) {
+        Some(name) if !name.is_empty() && name != "." && name != ".." => name,
+        _ => INDEX.to_string(),
+    };
+    (clean(host), name)
+}
+
+/// The response's headers by lowercase name, repeated ones joined with
+/// `, `.
+fn headers(response: &Response<Body>) -> BTreeMap<String, String> {
+    let mut headers: BTreeMap<String, String> = BTreeMap::new();
+    for (name, value) in response.headers() {
+        let value = String::from_utf8_lossy(value.as_bytes()).into_owned();
+        headers
+            .entry(name.as_str().to_string())
+            .and_modify(|joined| {
+                joined.push_str(", ");
+                joined.push_str(&value);
+            })
+            .or_insert(value);
+    }
+    headers
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_file_name() {
+        let name = |url| {
+            let (host, name) = file_name(url);
+            format!("{}/{}", host, name)
+        };
+        assert_eq!(name("https://example.org/media/cat%20video.mp4?dl=1#t=3"), "example.org/cat%20video.mp4");
+        assert_eq!(name("http://user@[::1]:8080/"), "[__1]_8080/index");
+        assert_eq!(name("https://example.org"), "example.org/index");
+        assert_eq!(name("https://example.org/a/.."), "example.org/index");
+    }
+
+    #[test]
+    fn test_read_list() -> Result<()> {
+        let path = std::env::temp_dir().join(format!("deep_archive_urls_{}.txt", std::process::id()));
+        fs::write(&path, "# Press kit\nhttps://example.org/a.jpg\n\n  https://example.org/b.pdf  \n")?;
+        assert_eq!(read_list(&path)?, ["https://example.org/a.jpg", "https://example.org/b.pdf"]);
+        fs::remove_file(&path)?;
+        Ok(())
+    }
+}
@@ -0,0 +1,97 @@
+use std::io::{self, Write};
+use std::path::Path;
+use std::sync::{Mutex, OnceLock};
+use anyhow::{Result, Context};
+use serde::Serialize;
+use tracing::warn;
+use crate::ingest::summary::RunSummary;
+
+/// A lifecycle transition of a file or run, written as one JSON object per
+/// line with its kind in `event` and a unix timestamp in milliseconds in
+/// `ts`.
+#[derive(Debug, Clone, Serialize)]
+#[serde(tag = "event", rename_all = "snake_case")]
+pub enum Event<'a> {
+    RunStarted { run_id: i64, resumed: bool },
+    /// Found by the scanner or given as input.
+    Discovered { path: &'a str },
+    Hashed { path: &'a str, hash: &'a str, size: u64 },
+    /// Through type detection, the models and metadata extraction. Files
+    /// whose content is already catalogued skip this.
+    Analyzed { path: &'a str, hash: &'a str, media_type: &'a str },
+    /// Committed to the catalog.
+    Stored { path: &'a str, hash: &'a str },
+    Failed { path: &'a str, stage: &'a str, error: &'a str },
+    RunFinished { summary: &'a RunSummary },
+    /// Written to an archive volume and registered.
+    Archived { path: &'a str, hash: Option<&'a str>, volume: &'a str, archive_path: &'a str },
+}
+
+#[derive(Serialize)]
+struct Line<'a> {
+    ts: i64,
+    #[serde(flatten)]
+    event: &'a Event<'a>,
+}
+
+static SINK: OnceLock<Mutex<Box<dyn Write + Send>>> = OnceLock::new();
+
+/// Sends events to `dest` from now on: `-` for stdout, else the path of a
+/// listening Unix socket. Can only be set once per process.
+pub fn init(dest: &str) -> Result<()> {
+    let writer: Box<dyn Write + Send> = match dest {
+        "-" => Box::new(io::stdout()),
+        path => connect(Path::new(path))?,
+    };
+    SINK.set(Mutex::new(writer)).map_err(|_| anyhow::anyhow!("Events are already being sent elsewhere"))
+}
+
+#[cfg(unix)]
+fn connect(path: &Path) -> Result<Box<dyn Write + Send>> {
+    let stream = std::os::unix::net::UnixStream::connect(path).with_context(|| format!("Failed to connect to the event socket {:?}", path))?;
+    Ok(Box::new(stream))
+}
+
+#[cfg(not(unix))]
+fn connect(path: &Path) -> Result<Box<dyn Write + Send>> {
+    anyhow::bail!("Events can only go to stdout (-) here; {:?} would need a Unix socket", path)
+}
+
+/// Whether `init` was called, so callers can skip work only events need.
+pub fn enabled() -> bool {
+    SINK.get().is_some()
+}
+
+/// Writes `event` if events are enabled. A reader that went away turns
+/// events off rather than failing the run.
+pub fn emit(event: Event) {
+    let Some(sink) = SINK.get() else {
+        return;
+    };
+    let line = Line { ts: chrono::Utc::now().timestamp_millis(), event: &event };
+    let Ok(mut json) = serde_json::to_vec(&line) else {
+        return;
+    };
+    json.push(b'\n');
+    let mut writer = sink.lock().unwrap();
+    if let Err(e) = writer.write_all(&json).and_then(|_| writer.flush()) {
+        warn!("Failed to write event, no more will be sent: {}", e);
+        *writer = Box::new(io::sink());
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_event_line() -> Result<()> {
+        let event = Event::Failed { path: "/media/a.mp4", stage: "decode", error: "ffmpeg exited with 1" };
+        let json = serde_json::to_value(Line { ts: 1700000000000, event: &event })?;
+        assert_eq!(
+            json,
+            serde_json::json!({"ts": 1700000000000i64, "event": "failed", "path": "/media/a.mp4", "stage": "decode", "error": "ffmpeg exited with 1"})
+        );
+        Ok(())
+    }
+}
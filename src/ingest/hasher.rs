@@ -1,17 +1,56 @@
 use std::fs::File;
 use std::io::{Read, BufReader};
 use std::path::Path;
+use std::time::UNIX_EPOCH;
 use sha2::{Sha256, Digest};
 use memmap2::MmapOptions;
 use anyhow::{Result, Context};
 
 const MMAP_THRESHOLD: u64 = 500 * 1024 * 1024; // 500 MB
 
-pub fn calculate_hash(path: &Path) -> Result<String> {
+/// Content hash plus the filesystem metadata captured from the same open handle.
+pub struct Fingerprint {
+    pub hash: String,
+    pub size: u64,
+    /// Modification time in unix seconds, if the platform reports one.
+    pub mtime: Option<i64>,
+    /// `st_dev` of the filesystem holding the file (unix only).
+    pub device: Option<u64>,
+}
+
+pub fn fingerprint(path: &Path) -> Result<Fingerprint> {
     let file = File::open(path).with_context(|| format!("Failed to open file: {:?}", path))?;
     let metadata = file.metadata()?;
-    let len = metadata.len();
 
+    Ok(Fingerprint {
+        hash: hash_file(file, metadata.len())?,
+        size: metadata.len(),
+        mtime: mtime(&metadata),
+        device: device_id(&metadata),
+    })
+}
+
+/// Modification time in unix seconds, as recorded in fingerprints.
+pub fn mtime(metadata: &std::fs::Metadata) -> Option<i64> {
+    metadata
+        .modified()
+        .ok()
+        .and_then(|t| t.duration_since(UNIX_EPOCH).ok())
+        .map(|d| d.as_secs() as i64)
+}
+
+#[cfg(unix)]
+fn device_id(metadata: &std::fs::Metadata) -> Option<u64> {
+    use std::os::unix::fs::MetadataExt;
+    Some(metadata.dev())
+}
+
+#[cfg(not(unix))]
+fn device_id(_metadata: &std::fs::Metadata) -> Option<u64> {
+    None
+}
+
+fn hash_file(file: File, len: u64) -> Result<String> {
     let mut hasher = Sha256::new();
 
     if len > MMAP_THRESHOLD {
@@ -0,0 +1,251 @@
+use std::io::Write;
+use std::process::{Command, Stdio};
+use std::sync::Arc;
+use std::sync::mpsc::{self, SyncSender};
+use std::thread::{self, JoinHandle};
+use std::time::{Duration, Instant};
+use anyhow::{Result, Context, bail};
+use serde::Serialize;
+use tracing::{info, warn};
+use crate::database::repo::ArtifactRecord;
+use crate::ingest::pipeline;
+use crate::utils::config::HooksConfig;
+
+/// Files queued for the hooks before the writer waits for them to catch up.
+const QUEUE: usize = 1024;
+
+/// Why a hook runs, in its JSON's `event` and `$DEEP_ARCHIVE_EVENT`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum HookEvent {
+    Ingested,
+    NsfwAboveThreshold,
+    DuplicateFound,
+}
+
+impl HookEvent {
+    fn name(self) -> &'static str {
+        match self {
+            HookEvent::Ingested => "ingested",
+            HookEvent::NsfwAboveThreshold => "nsfw_above_threshold",
+            HookEvent::DuplicateFound => "duplicate_found",
+        }
+    }
+
+    fn command(self, config: &HooksConfig) -> Option<&[String]> {
+        match self {
+            HookEvent::Ingested => config.on_ingested.as_deref(),
+            HookEvent::NsfwAboveThreshold => config.on_nsfw_above_threshold.as_deref(),
+            HookEvent::DuplicateFound => config.on_duplicate_found.as_deref(),
+        }
+    }
+}
+
+/// What a hook reads on stdin: the artifact as committed, with the event
+/// and run.
+#[derive(Serialize)]
+struct Payload<'a> {
+    event: HookEvent,
+    run_id: i64,
+    #[serde(flatten)]
+    record: &'a ArtifactRecord,
+    /// For `duplicate_found`, the other live paths of the content.
+    #[serde(skip_serializing_if = "<[String]>::is_empty")]
+    duplicate_of: &'a [String],
+}
+
+struct Job {
+    event: HookEvent,
+    record: ArtifactRecord,
+    duplicate_of: Vec<String>,
+}
+
+/// Runs a run's `[hooks]` one at a time on a thread of their own, so a
+/// slow hook holds up the pipeline only once the queue is full. A hook that
+/// fails is logged; the file stays ingested.
+pub struct Hooks {
+    config: Arc<HooksConfig>,
+    tx: Option<SyncSender<Job>>,
+    runner: Option<JoinHandle<()>>,
+}
+
+impl Hooks {
+    pub fn new(config: &HooksConfig, run_id: i64) -> Self {
+        let config = Arc::new(config.clone());
+        let events = [HookEvent::Ingested, HookEvent::NsfwAboveThreshold, HookEvent::DuplicateFound];
+        if events.iter().all(|event| event.command(&config).is_none()) {
+            return Hooks { config, tx: None, runner: None };
+        }
+        let shared = config.clone();
+        let (tx, rx) = mpsc::sync_channel::<Job>(QUEUE);
+        let runner = thread::Builder::new().name("hooks".to_string()).spawn(move || {
+            for job in rx {
+                // Queued hooks are dropped rather than holding up the exit.
+                if pipeline::interrupted() {
+                    continue;
+                }
+                if let Err(e) = run(&shared, &job, run_id) {
+                    warn!("The {} hook failed for {:?}: {:#}", job.event.name(), job.record.original_path, e);
+                }
+            }
+        });
+        match runner {
+            Ok(runner) => Hooks { config, tx: Some(tx), runner: Some(runner) },
+            Err(e) => {
+                warn!("Failed to start the hooks, none will run: {}", e);
+                Hooks { config, tx: None, runner: None }
+            }
+        }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.tx.is_none()
+    }
+
+    /// Whether `on_duplicate_found` is set, so the other paths of content
+    /// seen again are worth looking up.
+    pub fn wants_duplicates(&self) -> bool {
+        self.tx.is_some() && self.config.on_duplicate_found.is_some()
+    }
+
+    /// Queues `on_ingested` and, scoring high enough,
+    /// `on_nsfw_above_threshold` for a file analyzed and now committed.
+    pub fn stored(&self, record: ArtifactRecord) {
+        let mut events = vec![HookEvent::Ingested];
+        if record.nsfw_score.is_some_and(|score| score >= self.config.nsfw_threshold) {
+            events.push(HookEvent::NsfwAboveThreshold);
+        }
+        events.retain(|event| event.command(&self.config).is_some());
+        if let Some((last, rest)) = events.split_last() {
+            for event in rest {
+                self.queue(Job { event: *event, record: record.clone(), duplicate_of: Vec::new() });
+            }
+            self.queue(Job { event: *last, record, duplicate_of: Vec::new() });
+        }
+    }
+
+    /// Queues `on_duplicate_found` for a committed file whose content is
+    /// also at `duplicate_of`.
+    pub fn duplicate(&self, record: ArtifactRecord, duplicate_of: Vec<String>) {
+        if self.config.on_duplicate_found.is_some() {
+            self.queue(Job { event: HookEvent::DuplicateFound, record, duplicate_of });
+        }
+    }
+
+    fn queue(&self, job: Job) {
+        if let Some(tx) = &self.tx {
+            let _ = tx.send(job);
+        }
+    }
+
+    /// Waits for the hooks still queued.
+    pub fn finish(mut self) {
+        drop(self.tx.take());
+        if let Some(runner) = self.runner.take() {
+            if !runner.is_finished() {
+                info!("Waiting for the hooks to finish");
+            }
+            let _ = runner.join();
+        }
+    }
+}
+
+/// Runs the command for the job's event with the artifact as JSON on stdin
+/// and its main fields in `DEEP_ARCHIVE_*` variables, killing it after
+/// `timeout_secs`.
+fn run(config: &HooksConfig, job: &Job, run_id: i64) -> Result<()> {
+    let Job { event, record, duplicate_of } = job;
+    let Some((program, args)) = event.command(config).and_then(|command| command.split_first()) else {
+        bail!("hooks.on_{} is empty", event.name());
+    };
+    let payload = serde_json::to_vec(&Payload { event: *event, run_id, record, duplicate_of })?;
+    let mut command = Command::new(program);
+    command
+        .args(args)
+        .env("DEEP_ARCHIVE_EVENT", event.name())
+        .env("DEEP_ARCHIVE_RUN_ID", run_id.to_string())
+        .env("DEEP_ARCHIVE_PATH", &record.original_path)
+        .env("DEEP_ARCHIVE_HASH", &record.hash_sha256)
+        .env("DEEP_ARCHIVE_MEDIA_TYPE", &record.media_type)
+        .env("DEEP_ARCHIVE_TAGS", record.tags.join(","))
+        .stdin(Stdio::piped())
+        // Stdout may carry `--events`.
+        .stdout(Stdio::null());
+    if let Some(size) = record.size_bytes {
+        command.env("DEEP_ARCHIVE_SIZE", size.to_string());
+    }
+    if let Some(score) = record.nsfw_score {
+        command.env("DEEP_ARCHIVE_NSFW_SCORE", score.to_string());
+    }
+    if let Some(first) = duplicate_of.first() {
+        command.env("DEEP_ARCHIVE_DUPLICATE_OF", first);
+    }
+    let mut child = command.spawn().with_context(|| format!("Failed to run {:?}", program))?;
+    // Written from a thread, as a hook that doesn't read it would block a
+    // large payload; one that exits first just closes the pipe.
+    if let Some(mut stdin) = child.stdin.take() {
+        thread::spawn(move || {
+            let _ = stdin.write_all(&payload);
+        });
+    }
+
+    let deadline = Instant::now() + Duration::from_secs(config.timeout_secs);
+    loop {
+        if let Some(status) = child.try_wait()? {
+            if !status.success() {
+                bail!("{:?} exited with {}", program, status);
+            }
+            return Ok(());
+        }
+        if Instant::now() >= deadline {
+            let _ = child.kill();
+            let _ = child.wait();
+            bail!("{:?} was killed after {}s", program, config.timeout_secs);
+        }
+        thread::sleep(Duration::from_millis(20));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[cfg(unix)]
+    #[test]
+    fn test_run() -> Result<()> {
+        let out = std::env::temp_dir().join(format!("deep_archive_hooks_{}.json", std::process::id()));
+        let script = format!("{{ cat; echo; echo \"$DEEP_ARCHIVE_EVENT $DEEP_ARCHIVE_NSFW_SCORE $DEEP_ARCHIVE_TAGS\"; }} > {}", out.display());
+        let config = HooksConfig {
+            on_nsfw_above_threshold: Some(vec!["sh".to_string(), "-c".to_string(), script]),
+            on_duplicate_found: Some(vec!["false".to_string()]),
+            ..Default::default()
+        };
+        let record = ArtifactRecord {
+            hash_sha256: "ab12".to_string(),
+            original_path: "/media/a.jpg".to_string(),
+            media_type: "image/jpeg".to_string(),
+            size_bytes: Some(3),
+            mtime: None,
+            width: None,
+            height: None,
+            tags: vec!["ml:cat".to_string(), "ml:sofa".to_string()],
+            nsfw_score: Some(0.9),
+            nsfw_model_version: None,
+            embeddings: Vec::new(),
+            metadata: None,
+            source: None,
+            timings: None,
+        };
+        let job = Job { event: HookEvent::NsfwAboveThreshold, record, duplicate_of: Vec::new() };
+        run(&config, &job, 7)?;
+        let written = std::fs::read_to_string(&out)?;
+        let (json, vars) = written.split_once('\n').unwrap();
+        let json: serde_json::Value = serde_json::from_str(json)?;
+        assert_eq!((json["event"].as_str(), json["run_id"].as_i64(), json["hash_sha256"].as_str()), (Some("nsfw_above_threshold"), Some(7), Some("ab12")));
+        assert_eq!(vars.trim(), "nsfw_above_threshold 0.9 ml:cat,ml:sofa");
+        let job = Job { event: HookEvent::DuplicateFound, duplicate_of: vec!["/media/b.jpg".to_string()], ..job };
+        assert!(run(&config, &job, 7).is_err());
+        std::fs::remove_file(&out)?;
+        Ok(())
+    }
+}
@@ -0,0 +1,188 @@
+use std::fmt::Write as _;
+use std::io::{BufRead, BufReader, Write};
+use std::net::{SocketAddr, TcpListener, TcpStream};
+use std::sync::Arc;
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+use std::thread::{self, JoinHandle};
+use std::time::Duration;
+use anyhow::{Result, Context};
+use tracing::{info, warn};
+
+/// Upper bounds in seconds of the latency buckets, from a millisecond
+/// (an image through the models) to minutes (a long video, a big flush).
+const BUCKETS: [f64; 12] = [0.001, 0.005, 0.01, 0.025, 0.05, 0.1, 0.25, 0.5, 1.0, 5.0, 30.0, 120.0];
+
+/// A Prometheus histogram of durations.
+pub struct Histogram {
+    buckets: [AtomicU64; BUCKETS.len()],
+    count: AtomicU64,
+    /// Microseconds, so the sum can be kept in an atomic.
+    sum_micros: AtomicU64,
+}
+
+impl Default for Histogram {
+    fn default() -> Self {
+        Self {
+            buckets: std::array::from_fn(|_| AtomicU64::new(0)),
+            count: AtomicU64::new(0),
+            sum_micros: AtomicU64::new(0),
+        }
+    }
+}
+
+impl Histogram {
+    pub fn observe(&self, elapsed: Duration) {
+        let secs = elapsed.as_secs_f64();
+        for (bucket, bound) in self.buckets.iter().zip(BUCKETS) {
+            if secs <= bound {
+                bucket.fetch_add(1, Ordering::Relaxed);
+            }
+        }
+        self.count.fetch_add(1, Ordering::Relaxed);
+        self.sum_micros.fetch_add(elapsed.as_micros() as u64, Ordering::Relaxed);
+    }
+
+    /// Appends the `_bucket`, `_sum` and `_count` series of `name`.
+    pub fn render(&self, out: &mut Exposition, name: &str, help: &str) {
+        out.header(name, "histogram", help);
+        for (bucket, bound) in self.buckets.iter().zip(BUCKETS) {
+            let _ = writeln!(out.0, "{}_bucket{{le=\"{}\"}} {}", name, bound, bucket.load(Ordering::Relaxed));
+        }
+        let count = self.count.load(Ordering::Relaxed);
+        let _ = writeln!(out.0, "{}_bucket{{le=\"+Inf\"}} {}", name, count);
+        let _ = writeln!(out.0, "{}_sum {}", name, self.sum_micros.load(Ordering::Relaxed) as f64 / 1e6);
+        let _ = writeln!(out.0, "{}_count {}", name, count);
+    }
+}
+
+/// A scrape in the Prometheus text format.
+#[derive(Default)]
+pub struct Exposition(String);
+
+impl Exposition {
+    fn header(&mut self, name: &str, kind: &str, help: &str) {
+        let _ = writeln!(self.0, "# HELP {} {}", name, help);
+        let _ = writeln!(self.0, "# TYPE {} {}", name, kind);
+    }
+
+    /// Appends a counter or gauge with one series per `(label value, value)`.
+    pub fn family(&mut self, name: &str, kind: &str, help: &str, label: &str, series: &[(&str, f64)]) {
+        self.header(name, kind, help);
+        for (value_label, value) in series {
+            let _ = writeln!(self.0, "{}{{{}=\"{}\"}} {}", name, label, value_label, value);
+        }
+    }
+
+    /// Appends a counter or gauge without labels.
+    pub fn single(&mut self, name: &str, kind: &str, help: &str, value: f64) {
+        self.header(name, kind, help);
+        let _ = writeln!(self.0, "{} {}", name, value);
+    }
+
+    pub fn finish(self) -> String {
+        self.0
+    }
+}
+
+/// Serves `/metrics` on a thread of its own until dropped.
+pub struct MetricsServer {
+    addr: SocketAddr,
+    stop: Arc<AtomicBool>,
+    thread: Option<JoinHandle<()>>,
+}
+
+impl MetricsServer {
+    /// Listens on `addr` (e.g. `127.0.0.1:9898`), answering every scrape
+    /// with what `render` returns at the time.
+    pub fn start(addr: &str, render: impl Fn() -> String + Send + 'static) -> Result<Self> {
+        let listener = TcpListener::bind(addr).with_context(|| format!("Failed to listen for metrics on {}", addr))?;
+        let addr = listener.local_addr()?;
+        let stop = Arc::new(AtomicBool::new(false));
+        let stopping = stop.clone();
+        let thread = thread::Builder::new().name("metrics".to_string()).spawn(move || {
+            for stream in listener.incoming() {
+                if stopping.load(Ordering::Relaxed) {
+                    break;
+                }
+                let result = stream.and_then(|stream| respond(stream, &render));
+                if let Err(e) = result {
+                    warn!("Metrics request failed: {}", e);
+                }
+            }
+        })?;
+        info!("Serving metrics on http://{}/metrics", addr);
+        Ok(Self { addr, stop, thread: Some(thread) })
+    }
+
+    pub fn addr(&self) -> SocketAddr {
+        self.addr
+    }
+}
+
+impl Drop for MetricsServer {
+    fn drop(&mut self) {
+        self.stop.store(true, Ordering::Relaxed);
+        // Wakes the listener blocked in accept.
+        let _ = TcpStream::connect(self.addr);
+        if let Some(thread) = self.thread.take() {
+            let _ = thread.join();
+        }
+    }
+}
+
+fn respond(stream: TcpStream, render: &impl Fn() -> String) -> std::io::Result<()> {
+    stream.set_read_timeout(Some(Duration::from_secs(5)))?;
+    let mut reader = BufReader::new(stream);
+    let mut request = String::new();
+    reader.read_line(&mut request)?;
+    // The headers don't matter, but a client may wait for them to be read.
+    let mut line = String::new();
+    while reader.read_line(&mut line)? > 2 {
+        line.clear();
+    }
+
+    let mut stream = reader.into_inner();
+    let mut parts = request.split_whitespace();
+    let (status, body) = match (parts.next(), parts.next()) {
+        (Some("GET"), Some("/metrics")) => ("200 OK", render()),
+        _ => ("404 Not Found", "Not found; try /metrics\n".to_string()),
+    };
+    write!(
+        stream,
+        "HTTP/1.1 {}\r\nContent-Type: text/plain; version=0.0.4\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{}",
+        status,
+        body.len(),
+        body
+    )?;
+    stream.flush()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::Read;
+
+    #[test]
+    fn test_metrics_server() -> Result<()> {
+        let histogram = Arc::new(Histogram::default());
+        histogram.observe(Duration::from_millis(20));
+        histogram.observe(Duration::from_secs(2));
+        let scraped = histogram.clone();
+        let server = MetricsServer::start("127.0.0.1:0", move || {
+            let mut out = Exposition::default();
+            scraped.render(&mut out, "flush_seconds", "Flush time.");
+            out.finish()
+        })?;
+
+        let mut stream = TcpStream::connect(server.addr())?;
+        stream.write_all(b"GET /metrics HTTP/1.1\r\nHost: localhost\r\n\r\n")?;
+        let mut response = String::new();
+        stream.read_to_string(&mut response)?;
+        assert!(response.starts_with("HTTP/1.1 200 OK"));
+        assert!(response.contains("flush_seconds_bucket{le=\"0.01\"} 0\n"));
+        assert!(response.contains("flush_seconds_bucket{le=\"0.025\"} 1\n"));
+        assert!(response.contains("flush_seconds_bucket{le=\"+Inf\"} 2\n"));
+        assert!(response.contains("flush_seconds_sum 2.02\n"));
+        Ok(())
+    }
+}
@@ -1,2 +1,16 @@
+pub mod bench;
+#[cfg(feature = "urls")]
+pub mod download;
+pub mod events;
 pub mod scanner;
 pub mod hasher;
+pub mod hooks;
+pub mod metrics;
+pub mod pipeline;
+pub mod remote;
+pub mod source;
+pub mod stages;
+pub mod summary;
+#[cfg(feature = "webdav")]
+pub mod webdav;
+pub mod ytdlp;
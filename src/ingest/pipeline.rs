@@ -0,0 +1,1474 @@
+use std::collections::{BTreeMap, HashMap, HashSet};
+use std::future::Future;
+use std::path::{Path, PathBuf};
+use std::thread;
+use std::sync::{Arc, Mutex, OnceLock};
+use std::sync::atomic::{AtomicBool, AtomicU64, AtomicUsize, Ordering};
+use std::time::{Duration, Instant};
+use anyhow::{Result, Context, anyhow, bail};
+use serde::{Deserialize, Serialize};
+use serde_json::{Map, Value};
+use tokio::io::AsyncWriteExt;
+use tokio::net::TcpListener;
+use tokio::runtime::{self, Handle};
+use tokio::sync::{mpsc, oneshot, Notify, Semaphore};
+use tokio::task::{self, JoinSet};
+use tokio::time::{self, MissedTickBehavior};
+use tracing::{debug_span, field, info, warn, error, Span};
+
+use crate::ingest::{events, scanner, hasher, stages};
+use crate::ingest::hooks::Hooks;
+use crate::ingest::remote::{self, Connection};
+use crate::ingest::stages::{Analysis, Stage};
+use crate::ingest::source::{self, SourceResolver};
+use crate::ingest::metrics::{Exposition, Histogram, MetricsServer};
+use crate::ingest::summary::{RunSummary, StageMeter};
+use crate::database::repo::{ArtifactRecord, CommittedFile, FileTimings, PartialResult};
+use crate::database::store::{self, CatalogStore, LEASE_RENEW};
+use crate::ml::engine::{InferenceEngine, NSFW_MODEL_VERSION};
+use crate::notify::{Notice, Notifier};
+use crate::plugins::Plugins;
+use crate::server::auth::Auth;
+use crate::media::ffmpeg;
+use crate::utils::config::{self, Config, PipelineConfig, RetryPolicy, Storage};
+
+struct MediaJob {
+    path: PathBuf,
+    hash: String,
+    size: u64,
+    mtime: Option<i64>,
+    device: Option<u64>,
+    /// Known already when a failed file is replayed.
+    media_type: Option<String>,
+    /// Time the hasher took on the file.
+    hashed_in: Duration,
+    /// The file's trace, which the analysis and the write are added to.
+    span: Span,
+}
+
+impl MediaJob {
+    /// What the file got through, with its type once that is known.
+    fn partial(&self, media_type: Option<&str>) -> PartialResult {
+        PartialResult {
+            hash_sha256: self.hash.clone(),
+            size_bytes: self.size,
+            mtime: self.mtime,
+            device: self.device,
+            media_type: media_type.map(str::to_string),
+        }
+    }
+}
+
+/// What the DB writer persists, and what remote workers send back.
+#[derive(Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+enum DbMessage {
+    Record(Box<ArtifactRecord>),
+    /// A record analyzed here, with the file's trace; never sent to or
+    /// from remote workers.
+    #[serde(skip)]
+    Traced(Box<ArtifactRecord>, Span),
+    /// Another path of content already catalogued or seen in the run, as
+    /// `sighting` makes it.
+    #[serde(skip)]
+    Sighting(Box<ArtifactRecord>, Span),
+    Error {
+        path: String,
+        stage: String,
+        error: String,
+        /// The tail of ffmpeg's stderr, when that failed.
+        #[serde(default)]
+        stderr: Option<String>,
+        partial: Option<PartialResult>,
+    },
+    /// A remote worker is done with the file at `path`; only the
+    /// coordinator's bookkeeping needs it.
+    Done { path: String },
+}
+
+/// Logs a failure, counts it towards the run and queues it for `ingest_errors`.
+#[derive(Clone)]
+struct ErrorSink {
+    tx: mpsc::Sender<DbMessage>,
+    meters: Arc<Meters>,
+}
+
+impl ErrorSink {
+    /// Called from the blocking stages only. `partial` is what the file got
+    /// through, kept so `replay-failed` can start from there. ffmpeg's
+    /// stderr is logged with the error but stored on its own.
+    fn report(&self, path: &Path, stage: &'static str, partial: Option<PartialResult>, error: anyhow::Error) {
+        error!("{} failed for {:?}: {:#}", stage, path, error);
+        let stderr = ffmpeg::stderr_tail(&error).map(str::to_string);
+        let error = error.chain().filter(|cause| !cause.is::<ffmpeg::Stderr>()).map(ToString::to_string).collect::<Vec<_>>().join(": ");
+        self.meters.error(stage);
+        events::emit(events::Event::Failed { path: &path.to_string_lossy(), stage, error: &error });
+        let _ = self.tx.blocking_send(DbMessage::Error { path: path.to_string_lossy().to_string(), stage: stage.to_string(), error, stderr, partial });
+    }
+}
+
+/// What the stages got through, for the run's summary and `/metrics`.
+#[derive(Default)]
+struct Meters {
+    scan: StageMeter,
+    hash: StageMeter,
+    analyze: StageMeter,
+    write: StageMeter,
+    skipped: AtomicU64,
+    filtered: AtomicU64,
+    duplicates: AtomicU64,
+    errors: Mutex<BTreeMap<String, u64>>,
+    /// Time the models take per frame, shared with the models stage.
+    inference: Arc<Histogram>,
+    /// Time per catalog write transaction.
+    flush: Histogram,
+    queues: Vec<QueueGauge>,
+    /// Files the hashers and the workers may have in progress, which the
+    /// adaptive mode lowers while the queue after them stays full, and
+    /// rebalancing moves between them.
+    hashers: AtomicUsize,
+    workers: AtomicUsize,
+}
+
+/// Reads how full a channel between two stages is.
+struct QueueGauge {
+    name: &'static str,
+    capacity: usize,
+    depth: Box<dyn Fn() -> usize + Send + Sync>,
+}
+
+impl QueueGauge {
+    /// Doesn't keep the channel open.
+    fn new<T: Send + 'static>(name: &'static str, tx: &mpsc::Sender<T>) -> Self {
+        let weak = tx.downgrade();
+        QueueGauge {
+            name,
+            capacity: tx.max_capacity(),
+            depth: Box::new(move || weak.upgrade().map_or(0, |tx| tx.max_capacity() - tx.capacity())),
+        }
+    }
+
+    /// How full the queue is, from 0 to 1.
+    fn fill(&self) -> f64 {
+        (self.depth)() as f64 / self.capacity as f64
+    }
+}
+
+impl Meters {
+    fn error(&self, stage: &str) {
+        *self.errors.lock().unwrap().entry(stage.to_string()).or_default() += 1;
+    }
+
+    /// The live counters in the Prometheus text format.
+    fn render(&self) -> String {
+        let stages = [("scan", &self.scan), ("hash", &self.hash), ("analyze", &self.analyze), ("write", &self.write)]
+            .map(|(name, meter)| meter.summary(name));
+        let files: Vec<(&str, f64)> = stages.iter().map(|s| (s.stage.as_str(), s.files as f64)).collect();
+        let bytes: Vec<(&str, f64)> = stages.iter().map(|s| (s.stage.as_str(), s.bytes as f64)).collect();
+        let errors = self.errors.lock().unwrap().clone();
+        let errors: Vec<(&str, f64)> = errors.iter().map(|(stage, count)| (stage.as_str(), *count as f64)).collect();
+        let depths: Vec<(&str, f64)> = self.queues.iter().map(|q| (q.name, (q.depth)() as f64)).collect();
+        let capacities: Vec<(&str, f64)> = self.queues.iter().map(|q| (q.name, q.capacity as f64)).collect();
+        let threads = [("hash", self.hashers.load(Ordering::Relaxed) as f64), ("analyze", self.workers.load(Ordering::Relaxed) as f64)];
+
+        let mut out = Exposition::default();
+        out.family("deep_archive_files_total", "counter", "Files through each pipeline stage.", "stage", &files);
+        out.family("deep_archive_bytes_total", "counter", "Bytes through each pipeline stage.", "stage", &bytes);
+        out.family("deep_archive_errors_total", "counter", "Failures by the stage they happened in.", "stage", &errors);
+        out.single("deep_archive_skipped_total", "counter", "Files a resumed run had already committed.", self.skipped.load(Ordering::Relaxed) as f64);
+        out.single("deep_archive_filtered_total", "counter", "Files the plugins' filters left out.", self.filtered.load(Ordering::Relaxed) as f64);
+        out.single("deep_archive_duplicates_total", "counter", "Files whose content was already catalogued.", self.duplicates.load(Ordering::Relaxed) as f64);
+        out.family("deep_archive_queue_depth", "gauge", "Items waiting in the queue in front of a stage.", "queue", &depths);
+        out.family("deep_archive_queue_capacity", "gauge", "Size of the queue in front of a stage.", "queue", &capacities);
+        out.family("deep_archive_stage_concurrency", "gauge", "Files a stage may have in progress at once.", "stage", &threads);
+        self.inference.render(&mut out, "deep_archive_inference_seconds", "Time the models take per frame.");
+        self.flush.render(&mut out, "deep_archive_db_flush_seconds", "Time per catalog write transaction.");
+        out.finish()
+    }
+
+    fn summary(&self, status: &str, elapsed: Duration) -> RunSummary {
+        RunSummary {
+            status: status.to_string(),
+            elapsed_secs: elapsed.as_secs_f64(),
+            stages: vec![
+                self.scan.summary("scan"),
+                self.hash.summary("hash"),
+                self.analyze.summary("analyze"),
+                self.write.summary("write"),
+            ],
+            skipped: self.skipped.load(Ordering::Relaxed),
+            filtered: self.filtered.load(Ordering::Relaxed),
+            duplicates: self.duplicates.load(Ordering::Relaxed),
+            errors: self.errors.lock().unwrap().clone(),
+        }
+    }
+}
+
+/// When `interrupt` was first called.
+static INTERRUPTED: OnceLock<Instant> = OnceLock::new();
+static INTERRUPT: Notify = Notify::const_new();
+
+/// Asks the running pipeline to stop: no more files are scanned or
+/// started, the ones in progress are finished, everything buffered is
+/// written and the run is recorded as `interrupted`. Files still in
+/// progress after `pipeline.shutdown_timeout_secs` are given up on and
+/// their ffmpeg processes killed. Returns at once, so it can be called from
+/// a signal handler.
+pub fn interrupt() {
+    INTERRUPTED.get_or_init(Instant::now);
+    INTERRUPT.notify_waiters();
+}
+
+/// Whether `interrupt` has been called.
+pub fn interrupted() -> bool {
+    INTERRUPTED.get().is_some()
+}
+
+async fn wait_for_interrupt() {
+    loop {
+        // Registered before the check, so a call in between isn't missed.
+        let notified = INTERRUPT.notified();
+        if interrupted() {
+            return;
+        }
+        notified.await;
+    }
+}
+
+/// Waits until `grace` after an interrupt.
+async fn shutdown_deadline(grace: Duration) {
+    wait_for_interrupt().await;
+    let at = *INTERRUPTED.get().expect("interrupted");
+    time::sleep_until((at + grace).into()).await;
+}
+
+/// Where the pipeline gets its files from.
+pub enum Input {
+    Directory(PathBuf),
+    /// Explicit files, e.g. those that failed in an earlier run.
+    Files(Vec<PathBuf>),
+    /// Files that failed in an earlier run, with what they got through.
+    Replay(Vec<FailedFile>),
+    /// Files downloaded for the run, e.g. from a WebDAV share.
+    Fetched(Vec<Fetched>),
+}
+
+/// A downloaded file, with where it came from.
+pub struct Fetched {
+    pub path: PathBuf,
+    /// Stored as the artifact's `metadata.provenance`, e.g. the URL and
+    /// when it was retrieved.
+    pub provenance: Value,
+}
+
+/// A file to replay through the pipeline.
+pub struct FailedFile {
+    pub path: PathBuf,
+    pub partial: Option<PartialResult>,
+}
+
+impl FailedFile {
+    /// The job to resume the file from, if its partial result is still good
+    /// for it: the file is unchanged by size and mtime.
+    fn resume(&self) -> Option<MediaJob> {
+        let partial = self.partial.as_ref()?;
+        let metadata = std::fs::metadata(&self.path).ok()?;
+        if metadata.len() != partial.size_bytes || hasher::mtime(&metadata) != partial.mtime {
+            return None;
+        }
+        Some(MediaJob {
+            path: self.path.clone(),
+            hash: partial.hash_sha256.clone(),
+            size: partial.size_bytes,
+            mtime: partial.mtime,
+            device: partial.device,
+            media_type: partial.media_type.clone(),
+            hashed_in: Duration::ZERO,
+            span: file_span(&self.path),
+        })
+    }
+}
+
+/// Thread counts and queue sizes a run uses, resolved from
+/// `PipelineConfig`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Topology {
+    pub hashers: usize,
+    pub workers: usize,
+    pub scan_queue: usize,
+    pub hash_queue: usize,
+    pub db_queue: usize,
+    pub storage: Storage,
+}
+
+impl Topology {
+    /// Fills in what `config` leaves to `auto` from `cpus` and the storage
+    /// `input` is on. A file list is taken to be on the storage of its
+    /// first file.
+    pub fn resolve(config: &PipelineConfig, input: &Input, cpus: usize) -> Self {
+        let storage = match config.storage {
+            Storage::Auto => {
+                let path = match input {
+                    Input::Directory(dir) => Some(dir.as_path()),
+                    Input::Files(paths) => paths.first().map(PathBuf::as_path),
+                    Input::Replay(files) => files.first().map(|f| f.path.as_path()),
+                    Input::Fetched(files) => files.first().map(|f| f.path.as_path()),
+                };
+                match path.and_then(source::rotational) {
+                    Some(true) => Storage::Hdd,
+                    _ => Storage::Ssd,
+                }
+            }
+            storage => storage,
+        };
+        let hashers = match storage {
+            Storage::Hdd => 1,
+            _ => (cpus / 2).clamp(2, 8),
+        };
+        Topology {
+            hashers: config.hashers.unwrap_or(hashers).max(1),
+            workers: config.workers.unwrap_or((cpus / 4).clamp(2, 8)).max(1),
+            scan_queue: config.scan_queue.max(1),
+            hash_queue: config.hash_queue.max(1),
+            db_queue: config.db_queue.max(1),
+            storage,
+        }
+    }
+}
+
+/// Scans, hashes, analyzes and catalogs `input` as one run, blocking until
+/// every file is written to the catalog at `db_path`. `input_roots` and
+/// `options` are recorded with the run. Drives `run_async` on a runtime of
+/// its own.
+///
+/// Interrupting the process leaves the run open and ffmpeg children behind
+/// unless its signal handler calls `interrupt`, as the CLI's does; the
+/// run then fails once it has stopped.
+pub fn run(input: Input, input_roots: Vec<String>, options: String, db_path: &str, config: Config) -> Result<RunSummary> {
+    block_on(run_async(input, input_roots, options, db_path.to_string(), config))
+}
+
+/// Continues the run `run_id`, or the latest unfinished run over
+/// `input_roots`, after it was interrupted or its process died: files it
+/// committed that haven't changed since, by size and mtime, are skipped
+/// without being hashed again, and everything else is ingested as `run`
+/// would.
+pub fn resume(input: Input, input_roots: Vec<String>, run_id: Option<i64>, db_path: &str, config: Config) -> Result<RunSummary> {
+    block_on(resume_async(input, input_roots, run_id, db_path.to_string(), config))
+}
+
+fn block_on<T>(pipeline: impl Future<Output = Result<T>>) -> Result<T> {
+    let runtime = runtime::Builder::new_multi_thread()
+        .worker_threads(2)
+        .thread_name("pipeline")
+        .enable_time()
+        .enable_io()
+        .build()
+        .context("Failed to start the pipeline runtime")?;
+    let result = runtime.block_on(pipeline);
+    // Files given up on after an interrupt may still be hashing; they don't
+    // hold up the return.
+    runtime.shutdown_background();
+    result
+}
+
+/// `run` on the caller's runtime. The stages are tasks connected by bounded
+/// channels; hashing, decoding and catalog writes block, so they run on the
+/// blocking pool, at most `pipeline.hashers` and `pipeline.workers` files
+/// at a time.
+pub async fn run_async(input: Input, input_roots: Vec<String>, options: String, db_path: String, config: Config) -> Result<RunSummary> {
+    ingest(input, Start::New { input_roots, options }, db_path, config).await
+}
+
+/// `resume` on the caller's runtime.
+pub async fn resume_async(input: Input, input_roots: Vec<String>, run_id: Option<i64>, db_path: String, config: Config) -> Result<RunSummary> {
+    ingest(input, Start::Resume { input_roots, run_id }, db_path, config).await
+}
+
+/// The run a pipeline records its work in.
+enum Start {
+    New { input_roots: Vec<String>, options: String },
+    Resume { input_roots: Vec<String>, run_id: Option<i64> },
+}
+
+async fn ingest(input: Input, start: Start, db_path: String, config: Config) -> Result<RunSummary> {
+    let started = Instant::now();
+    let cpus = thread::available_parallelism().map_or(1, |n| n.get());
+    let topology = Topology::resolve(&config.pipeline, &input, cpus);
+    // Keyed by the path records are made for.
+    let provenance: HashMap<String, Value> = match &input {
+        Input::Fetched(files) => files.iter().map(|f| (f.path.to_string_lossy().to_string(), f.provenance.clone())).collect(),
+        _ => HashMap::new(),
+    };
+    let grace = Duration::from_secs(config.pipeline.shutdown_timeout_secs);
+
+    // Channels
+    let (scan_tx, scan_rx) = mpsc::channel::<PathBuf>(topology.scan_queue);
+    let (hash_tx, hash_rx) = mpsc::channel::<MediaJob>(topology.hash_queue);
+    let (db_tx, mut db_rx) = mpsc::channel::<DbMessage>(topology.db_queue);
+
+    let meters = Arc::new(Meters {
+        queues: vec![QueueGauge::new("scan", &scan_tx), QueueGauge::new("hash", &hash_tx), QueueGauge::new("db", &db_tx)],
+        hashers: AtomicUsize::new(topology.hashers),
+        workers: AtomicUsize::new(topology.workers),
+        ..Default::default()
+    });
+    // Bound before the run starts, so a taken port fails early.
+    let _metrics = match &config.pipeline.metrics_addr {
+        Some(addr) => {
+            let meters = meters.clone();
+            Some(MetricsServer::start(addr, move || meters.render())?)
+        }
+        None => None,
+    };
+    let listener = match &config.pipeline.serve_workers {
+        Some(addr) => {
+            let auth = Auth::with_tokens(&config.pipeline.worker_tokens, "pipeline.worker_tokens")?;
+            if auth.is_open() {
+                bail!("pipeline.serve_workers needs pipeline.worker_tokens, so only your workers can send records into the catalog");
+            }
+            let listener = TcpListener::bind(addr).await.with_context(|| format!("Failed to listen for workers on {}", addr))?;
+            info!("Handing files out to remote workers connecting to {}", listener.local_addr()?);
+            Some((listener, auth))
+        }
+        None => None,
+    };
+
+    stages::validate(&config.pipeline.stages)?;
+    // Opening the store and loading the models block, and the PostgreSQL
+    // client starts a runtime of its own, which can't happen on this one.
+    let Prepared { mut tm, run_id, committed, known, config, engine, plugins } = task::spawn_blocking(move || prepare(&db_path, start, config)).await??;
+    info!(
+        "Pipeline: {} hashers, {} workers on {} CPUs, input on {}; queues of {} paths, {} files, {} records{}",
+        topology.hashers, topology.workers, cpus, topology.storage.name(), topology.scan_queue, topology.hash_queue, topology.db_queue,
+        match (config.pipeline.adaptive, config.pipeline.rebalance) {
+            (true, true) => "; adaptive, rebalancing",
+            (true, false) => "; adaptive",
+            (false, true) => "; rebalancing",
+            (false, false) => "",
+        }
+    );
+    let flush_interval = config.database.flush_interval();
+    let notifier = Notifier::new(&config.notify);
+    let hooks = Hooks::new(&config.hooks, run_id);
+    let config = Arc::new(config);
+
+    // Failures across all stages, counted with the run and persisted in ingest_errors
+    let errors = ErrorSink { tx: db_tx.clone(), meters: meters.clone() };
+
+    // 1. Scanner
+    let scan_errors = errors.clone();
+    let scan_meters = meters.clone();
+    // Replayed files that were hashed before go straight to the workers,
+    // unless those are remote.
+    let replay_tx = hash_tx.clone();
+    let resumable = listener.is_none();
+    let scanner = task::spawn_blocking(move || {
+        info!("Scanner started");
+        match input {
+            Input::Directory(dir) => {
+                if let Err(e) = scanner::scan_directory(&dir, scan_tx) {
+                    scan_errors.report(&dir, "scan", None, e);
+                }
+            }
+            Input::Files(paths) => discovered(paths, &scan_tx),
+            Input::Fetched(files) => discovered(files.into_iter().map(|f| f.path), &scan_tx),
+            Input::Replay(files) => {
+                for file in files {
+                    events::emit(events::Event::Discovered { path: &file.path.to_string_lossy() });
+                    let sent = match file.resume().filter(|_| resumable) {
+                        Some(job) => {
+                            scan_meters.scan.record(0);
+                            scan_meters.hash.record(job.size);
+                            replay_tx.blocking_send(job).is_ok()
+                        }
+                        None => scan_tx.blocking_send(file.path).is_ok(),
+                    };
+                    if !sent {
+                        break;
+                    }
+                }
+            }
+        }
+        info!("Scanner finished");
+    });
+
+    // Shared, as the hashers' closure is cloned for every file.
+    let committed = Arc::new(committed);
+    let write_retry = config.retry.clone();
+    let (work, adapter) = match listener {
+        // 2-3. Remote workers hash and analyze
+        Some((listener, auth)) => {
+            drop(hash_rx);
+            let dispatcher = tokio::spawn(dispatch(listener, auth, scan_rx, db_tx, meters.clone(), committed, grace));
+            (dispatcher, None)
+        }
+        None => {
+            let stages = stages::build(&config, engine, &plugins, meters.inference.clone())?;
+            info!("Analysis stages: {}", stages.iter().map(|s| s.name()).collect::<Vec<_>>().join(", "));
+            let stages: Arc<[Box<dyn Stage>]> = stages.into();
+            let sources = Arc::new(SourceResolver::default());
+
+            // Shared with the adaptive mode, which takes permits away while a
+            // stage's output queue stays full, and with rebalancing, which
+            // moves them between the stages.
+            let hash_permits = Arc::new(Semaphore::new(topology.hashers));
+            let work_permits = Arc::new(Semaphore::new(topology.workers));
+            let adapter = (config.pipeline.adaptive || config.pipeline.rebalance).then(|| {
+                // More readers only add seeks on a spinning disk.
+                let max_hashers = match topology.storage {
+                    Storage::Hdd => topology.hashers,
+                    _ => usize::MAX,
+                };
+                tokio::spawn(adapt(
+                    meters.clone(),
+                    [
+                        Throttle::new("hash", hash_permits.clone(), topology.hashers),
+                        Throttle::new("analyze", work_permits.clone(), topology.workers),
+                    ],
+                    config.pipeline.adaptive,
+                    config.pipeline.rebalance.then(|| Rebalance::new(max_hashers)),
+                ))
+            });
+
+            // 2. Hashers
+            let hash_errors = errors.clone();
+            let hash_meters = meters.clone();
+            let hash_retry = config.retry.clone();
+            let hash_sources = sources.clone();
+            // Files with content already catalogued skip the workers.
+            let sighting_tx = db_tx.clone();
+            // Hashes seen during the run are added, so a second copy isn't
+            // analyzed either.
+            let known = known.map(|known| Arc::new(Mutex::new(known)));
+            let hashers = tokio::spawn(fan_out("Hashing", scan_rx, hash_permits, grace, move |path: PathBuf| {
+                // Taken as the scanner's pace, which the hashers set anyway.
+                hash_meters.scan.record(0);
+                if unchanged(&committed, &path) {
+                    hash_meters.skipped.fetch_add(1, Ordering::Relaxed);
+                    return;
+                }
+                if !plugins.keep(&path) {
+                    hash_meters.filtered.fetch_add(1, Ordering::Relaxed);
+                    return;
+                }
+                let span = file_span(&path);
+                let _file = span.enter();
+                let hashing = Instant::now();
+                match debug_span!("hash").in_scope(|| hash_retry.run("Hashing", || hasher::fingerprint(&path))) {
+                    Ok(fp) => {
+                        hash_meters.hash.record(fp.size);
+                        events::emit(events::Event::Hashed { path: &path.to_string_lossy(), hash: &fp.hash, size: fp.size });
+                        span.record("size", fp.size).record("hash", fp.hash.as_str());
+                        let job = MediaJob { path, hash: fp.hash, size: fp.size, mtime: fp.mtime, device: fp.device, media_type: None, hashed_in: hashing.elapsed(), span: span.clone() };
+                        if known.as_ref().is_some_and(|known| !known.lock().unwrap().insert(job.hash.clone())) {
+                            hash_meters.duplicates.fetch_add(1, Ordering::Relaxed);
+                            let _ = sighting_tx.blocking_send(DbMessage::Sighting(Box::new(sighting(job, &hash_sources)), span.clone()));
+                            return;
+                        }
+                        let _ = hash_tx.blocking_send(job);
+                    }
+                    Err(e) => hash_errors.report(&path, "hash", None, e),
+                }
+            }));
+
+            // 3. Media/AI workers
+            let work_errors = errors.clone();
+            let work_meters = meters.clone();
+            let workers = tokio::spawn(fan_out("Analysis", hash_rx, work_permits, grace, move |job: MediaJob| {
+                let size = job.size;
+                let span = job.span.clone();
+                let record = analyze(job, &config, &stages, &sources, &work_errors);
+                work_meters.analyze.record(size);
+                events::emit(events::Event::Analyzed { path: &record.original_path, hash: &record.hash_sha256, media_type: &record.media_type });
+                let _ = db_tx.blocking_send(DbMessage::Traced(Box::new(record), span));
+            }));
+
+            let work = tokio::spawn(async move {
+                hashers.await??;
+                workers.await?
+            });
+            (work, adapter)
+        }
+    };
+
+    // 4. DB writer
+    drop(errors);
+    let (stop_tx, mut stop_rx) = oneshot::channel::<()>();
+    let runtime = Handle::current();
+    let writer = task::spawn_blocking(move || {
+        info!("DB Writer started");
+
+        // Wakes the writer so records don't sit in the buffer while the
+        // workers are busy with a long video, and to keep the lease alive.
+        let mut ticker = time::interval(flush_interval.map_or(LEASE_RENEW, |interval| interval.min(LEASE_RENEW)));
+        ticker.set_missed_tick_behavior(MissedTickBehavior::Delay);
+        let mut unflushed = Unflushed { hooks, records: Vec::new() };
+        loop {
+            let event = runtime.block_on(async {
+                tokio::select! {
+                    message = db_rx.recv() => message.map_or(Event::Stop, Event::Message),
+                    _ = ticker.tick() => Event::Tick,
+                    _ = &mut stop_rx => Event::Stop,
+                }
+            });
+            let result = match event {
+                Event::Message(message) => write(tm.as_mut(), message, &provenance, &meters, &write_retry, &mut unflushed),
+                Event::Tick => flush_if_due(tm.as_mut(), &meters),
+                Event::Stop => break,
+            };
+            if let Err(e) = result {
+                error!("Failed to write to DB: {}", e);
+                meters.error("write");
+            }
+            unflushed.stored(tm.as_mut());
+            if !notifier.is_empty() {
+                notifier.errors(run_id, meters.errors.lock().unwrap().values().sum());
+            }
+        }
+        // Files given up on keep the channel open; what it holds now is
+        // all that is coming.
+        while let Ok(message) = db_rx.try_recv() {
+            if let Err(e) = write(tm.as_mut(), message, &provenance, &meters, &write_retry, &mut unflushed) {
+                error!("Failed to write to DB: {}", e);
+                meters.error("write");
+            }
+        }
+
+        let status = if interrupted() { "interrupted" } else { "completed" };
+        let summary = meters.summary(status, started.elapsed());
+        if let Err(e) = tm.finish_run(status, summary.error_count(), Some(&summary)) {
+             error!("Failed to flush remaining records: {}", e);
+        }
+        unflushed.stored(tm.as_mut());
+        unflushed.hooks.finish();
+        info!("DB Writer finished");
+        for line in summary.to_string().lines() {
+            info!("{}", line);
+        }
+        events::emit(events::Event::RunFinished { summary: &summary });
+        notifier.notify(&Notice::run_finished(run_id, &summary));
+        summary
+    });
+
+    let result = work.await?;
+    if let Some(adapter) = adapter {
+        adapter.abort();
+    }
+    let _ = stop_tx.send(());
+    let summary = writer.await?;
+    result?;
+    if interrupted() {
+        // The scanner stops at its next file, which a slow disk can take
+        // a while to find.
+        bail!("Interrupted; the run is recorded as interrupted, and resuming it picks up the rest");
+    }
+    scanner.await?;
+    Ok(summary)
+}
+
+/// Hands explicit files to the hashers.
+fn discovered(paths: impl IntoIterator<Item = PathBuf>, scan_tx: &mpsc::Sender<PathBuf>) {
+    for path in paths {
+        events::emit(events::Event::Discovered { path: &path.to_string_lossy() });
+        if scan_tx.blocking_send(path).is_err() {
+            break;
+        }
+    }
+}
+
+/// What wakes the DB writer.
+enum Event {
+    Message(DbMessage),
+    Tick,
+    /// The stages are done, or the channel closed.
+    Stop,
+}
+
+/// Persists a message, retrying transient failures. A record whose flush
+/// failed stays buffered, so retrying it means flushing again. Records are
+/// kept in `unflushed` until committed. Those of fetched files get their
+/// `provenance`.
+fn write(tm: &mut dyn CatalogStore, message: DbMessage, provenance: &HashMap<String, Value>, meters: &Meters, retry: &RetryPolicy, unflushed: &mut Unflushed) -> Result<()> {
+    match message {
+        DbMessage::Record(mut record) => {
+            provide(&mut record, provenance);
+            add(tm, *record, false, meters, retry, unflushed)
+        }
+        DbMessage::Traced(mut record, span) => {
+            provide(&mut record, provenance);
+            span.in_scope(|| debug_span!("write").in_scope(|| add(tm, *record, false, meters, retry, unflushed)))
+        }
+        DbMessage::Sighting(record, span) => span.in_scope(|| debug_span!("write").in_scope(|| add(tm, *record, true, meters, retry, unflushed))),
+        DbMessage::Error { path, stage, error, stderr, partial } => {
+            retry.run("Recording an error", || tm.record_error(&path, &stage, &error, stderr.as_deref(), partial.as_ref()))
+        }
+        DbMessage::Done { .. } => Ok(()),
+    }
+}
+
+fn provide(record: &mut ArtifactRecord, provenance: &HashMap<String, Value>) {
+    let Some(found) = provenance.get(&record.original_path) else { return };
+    match record.metadata.get_or_insert_with(|| Value::Object(Map::new())) {
+        Value::Object(metadata) => {
+            metadata.insert("provenance".to_string(), found.clone());
+        }
+        _ => warn!("The metadata of {} isn't an object; its provenance is dropped", record.original_path),
+    }
+}
+
+fn add(tm: &mut dyn CatalogStore, record: ArtifactRecord, duplicate: bool, meters: &Meters, retry: &RetryPolicy, unflushed: &mut Unflushed) -> Result<()> {
+    let size = record.size_bytes.unwrap_or(0);
+    unflushed.push(&record, duplicate);
+    let started = Instant::now();
+    if let Err(e) = tm.add(record) {
+        retry.recover(e, "Writing to the catalog", || tm.flush())?;
+    }
+    if tm.pending() == 0 {
+        meters.flush.observe(started.elapsed());
+    }
+    meters.write.record(size);
+    Ok(())
+}
+
+/// Records in the catalog's buffer, announced as stored and handed to the
+/// hooks once they are committed. Only kept with events on or hooks set.
+struct Unflushed {
+    hooks: Hooks,
+    /// With whether each is another path of content already catalogued.
+    records: Vec<(ArtifactRecord, bool)>,
+}
+
+impl Unflushed {
+    fn push(&mut self, record: &ArtifactRecord, duplicate: bool) {
+        if events::enabled() || !self.hooks.is_empty() {
+            self.records.push((record.clone(), duplicate));
+        }
+    }
+
+    /// Announces the records once nothing is buffered anymore. Content
+    /// seen again only counts as a duplicate at a path of its own.
+    fn stored(&mut self, tm: &mut dyn CatalogStore) {
+        if tm.pending() > 0 {
+            return;
+        }
+        for (record, duplicate) in self.records.drain(..) {
+            events::emit(events::Event::Stored { path: &record.original_path, hash: &record.hash_sha256 });
+            if !duplicate {
+                self.hooks.stored(record);
+            } else if self.hooks.wants_duplicates() {
+                match tm.live_paths(&record.hash_sha256) {
+                    Ok(mut paths) => {
+                        paths.retain(|path| *path != record.original_path);
+                        if !paths.is_empty() {
+                            self.hooks.duplicate(record, paths);
+                        }
+                    }
+                    Err(e) => warn!("Failed to look up the other paths of {:?}: {}", record.original_path, e),
+                }
+            }
+        }
+    }
+}
+
+fn flush_if_due(tm: &mut dyn CatalogStore, meters: &Meters) -> Result<()> {
+    let buffered = tm.pending() > 0;
+    let started = Instant::now();
+    tm.flush_if_due()?;
+    if buffered && tm.pending() == 0 {
+        meters.flush.observe(started.elapsed());
+    }
+    Ok(())
+}
+
+/// The catalog with the run started, and what the stages need from it.
+struct Prepared {
+    tm: Box<dyn CatalogStore>,
+    run_id: i64,
+    /// Files a resumed run already committed.
+    committed: HashMap<String, CommittedFile>,
+    /// Content already catalogued, with `pipeline.skip_known`.
+    known: Option<HashSet<String>>,
+    config: Config,
+    engine: Option<Arc<InferenceEngine>>,
+    plugins: Arc<Plugins>,
+}
+
+/// Opens the catalog and starts or resumes the run in it, and loads what
+/// analysis needs.
+fn prepare(db_path: &str, start: Start, mut config: Config) -> Result<Prepared> {
+    info!("DB: {}", db_path);
+
+    // Opened up front, so a catalog that is unreachable or leased exclusively
+    // by another process fails before any work is done.
+    let mut tm = store::open(db_path, &config.database)?;
+    let (run_id, committed) = match start {
+        Start::New { input_roots, options } => {
+            let run_id = tm.begin_run(&input_roots, &options)?;
+            info!("Started run {}", run_id);
+            events::emit(events::Event::RunStarted { run_id, resumed: false });
+            (run_id, HashMap::new())
+        }
+        Start::Resume { input_roots, run_id } => {
+            let run_id = match run_id {
+                Some(run_id) => run_id,
+                None => {
+                    let roots = serde_json::to_string(&input_roots)?;
+                    tm.runs()?
+                        .into_iter()
+                        .find(|run| matches!(run.status.as_str(), "running" | "interrupted") && run.input_roots == roots)
+                        .with_context(|| format!("No interrupted or unfinished run over {} to resume", roots))?
+                        .id
+                }
+            };
+            let committed = tm.resume_run(run_id)?;
+            info!("Resuming run {}, which committed {} files", run_id, committed.len());
+            events::emit(events::Event::RunStarted { run_id, resumed: true });
+            (run_id, committed)
+        }
+    };
+    // Remote workers don't know which content is catalogued.
+    let known = match config.pipeline.skip_known && config.pipeline.serve_workers.is_none() {
+        true => {
+            let known = tm.known_hashes()?;
+            info!("{} hashes already catalogued; files with them are not analyzed again", known.len());
+            Some(known)
+        }
+        false => None,
+    };
+
+    // With remote workers nothing is hashed or analyzed here.
+    let (engine, plugins) = match config.pipeline.serve_workers {
+        Some(_) => (None, Plugins::default()),
+        None => (load_engine(&mut config), Plugins::load(&config.plugins)?),
+    };
+    Ok(Prepared { tm, run_id, committed, known, config, engine, plugins: Arc::new(plugins) })
+}
+
+/// Checks the media tools and loads the models if the models stage is on,
+/// resolving `media.hwaccel` on the way.
+pub fn load_engine(config: &mut Config) -> Option<Arc<InferenceEngine>> {
+    ffmpeg::check_binaries(&config.media);
+    config.media.hwaccel = ffmpeg::resolve_hwaccel(&config.media);
+
+    if !config.pipeline.stages.iter().any(|stage| stage == "models") {
+        info!("Models stage disabled; not loading the models");
+        return None;
+    }
+
+    // 1. Locate Models (Auto-search + .env generation)
+    let model_paths = match config::get_model_paths() {
+        Ok(paths) => Some(paths),
+        Err(e) => {
+            error!("Failed to initialize AI Engine: {}. \n\nHint: Have you run './setup.sh' to download the models?", e);
+            None
+        }
+    };
+
+    // 2. Initialize ML Engine
+    if let Some(paths) = model_paths {
+        let nsfw_str = paths.nsfw.to_string_lossy().to_string();
+        let tagger_str = paths.tagger.to_string_lossy().to_string();
+
+        // Models on a network share may take a few tries to load.
+        match config.retry.run("Loading the models", || InferenceEngine::new(&nsfw_str, &tagger_str)) {
+            Ok(e) => Some(Arc::new(e)),
+            Err(e) => {
+                error!("Failed to initialize AI Engine with found paths: {}", e);
+                None
+            }
+        }
+    } else {
+        None
+    }
+}
+
+/// Whether `path` is among the files a resumed run committed, with the same
+/// size and mtime.
+fn unchanged(committed: &HashMap<String, CommittedFile>, path: &Path) -> bool {
+    let Some(file) = committed.get(path.to_string_lossy().as_ref()) else {
+        return false;
+    };
+    let Ok(metadata) = std::fs::metadata(path) else {
+        return false;
+    };
+    file.size_bytes == Some(metadata.len()) && file.mtime == hasher::mtime(&metadata)
+}
+
+/// How often the adaptive mode looks at the queues.
+const ADAPT_INTERVAL: Duration = Duration::from_secs(1);
+/// Samples a queue has to be full in a row before the stage feeding it
+/// loses a thread, or in rebalancing, before a thread moves.
+const FULL_SAMPLES: u32 = 5;
+
+/// Concurrency of one stage, as the permits of its `fan_out`.
+struct Throttle {
+    stage: &'static str,
+    permits: Arc<Semaphore>,
+    limit: usize,
+    /// Permits taken away.
+    withheld: usize,
+    full_for: u32,
+}
+
+impl Throttle {
+    fn new(stage: &'static str, permits: Arc<Semaphore>, limit: usize) -> Self {
+        Throttle { stage, permits, limit, withheld: 0, full_for: 0 }
+    }
+
+    /// Takes a thread away after the stage's output queue was at least 90%
+    /// full for `FULL_SAMPLES` samples, down to one, and gives one back
+    /// each sample it is less than half full. Returns the new concurrency
+    /// if it changed.
+    fn sample(&mut self, fill: f64) -> Option<usize> {
+        if fill >= 0.9 {
+            self.full_for += 1;
+            // Only idle permits can be taken; a busy stage gives one up at
+            // a later sample.
+            if self.full_for >= FULL_SAMPLES && self.withheld + 1 < self.limit && self.permits.forget_permits(1) == 1 {
+                self.withheld += 1;
+                self.full_for = 0;
+                return Some(self.limit - self.withheld);
+            }
+        } else {
+            self.full_for = 0;
+            if fill < 0.5 && self.withheld > 0 {
+                self.permits.add_permits(1);
+                self.withheld -= 1;
+                return Some(self.limit - self.withheld);
+            }
+        }
+        None
+    }
+
+    fn threads(&self) -> usize {
+        self.limit - self.withheld
+    }
+
+    /// Gives a thread up for another stage if one is idle and the stage
+    /// keeps at least one.
+    fn release(&mut self) -> bool {
+        if self.threads() > 1 && self.permits.forget_permits(1) == 1 {
+            self.limit -= 1;
+            return true;
+        }
+        false
+    }
+
+    fn acquire(&mut self) {
+        self.permits.add_permits(1);
+        self.limit += 1;
+    }
+}
+
+/// Moves threads between the hashers and the workers, keeping their sum:
+/// to the hashers while files pile up in front of them and the workers
+/// have nothing to do, and back while it's the other way round.
+struct Rebalance {
+    max_hashers: usize,
+    /// Samples in a row with the hashers behind and the workers idle.
+    hashers_behind: u32,
+    workers_behind: u32,
+}
+
+impl Rebalance {
+    fn new(max_hashers: usize) -> Self {
+        Rebalance { max_hashers, hashers_behind: 0, workers_behind: 0 }
+    }
+
+    /// Takes the fill of the queues in front of the hashers and the
+    /// workers, and moves a thread once one of them has been at least 90%
+    /// full and the other at most 10% for `FULL_SAMPLES` samples. Returns
+    /// the stage that gained a thread.
+    fn sample(&mut self, scan: f64, hash: f64, [hashers, workers]: &mut [Throttle; 2]) -> Option<&'static str> {
+        self.hashers_behind = if scan >= 0.9 && hash <= 0.1 { self.hashers_behind + 1 } else { 0 };
+        self.workers_behind = if hash >= 0.9 && scan <= 0.1 { self.workers_behind + 1 } else { 0 };
+        if self.hashers_behind >= FULL_SAMPLES && hashers.limit < self.max_hashers && workers.release() {
+            hashers.acquire();
+            self.hashers_behind = 0;
+            return Some(hashers.stage);
+        }
+        if self.workers_behind >= FULL_SAMPLES && hashers.release() {
+            workers.acquire();
+            self.workers_behind = 0;
+            return Some(workers.stage);
+        }
+        None
+    }
+}
+
+/// Adaptive backpressure: shrinks the hashers while the queue to the
+/// workers stays full, and the workers while the catalog writer falls
+/// behind, so a slow stage doesn't leave the ones before it holding
+/// files they can't hand on. With `rebalance`, also moves threads to
+/// whichever of the two is behind. Runs until aborted.
+async fn adapt(meters: Arc<Meters>, mut throttles: [Throttle; 2], adaptive: bool, mut rebalance: Option<Rebalance>) {
+    let mut ticker = time::interval(ADAPT_INTERVAL);
+    ticker.set_missed_tick_behavior(MissedTickBehavior::Delay);
+    let fill = |queue: &str| meters.queues.iter().find(|q| q.name == queue).map_or(0.0, QueueGauge::fill);
+    loop {
+        ticker.tick().await;
+        if let Some(rebalance) = &mut rebalance {
+            if let Some(stage) = rebalance.sample(fill("scan"), fill("hash"), &mut throttles) {
+                let [hashers, workers] = &throttles;
+                info!("Rebalancing: a thread moved to the {} stage, now {} hashing and {} analyzing", stage, hashers.threads(), workers.threads());
+            }
+        }
+        if adaptive {
+            for (throttle, queue) in throttles.iter_mut().zip(["hash", "db"]) {
+                if let Some(threads) = throttle.sample(fill(queue)) {
+                    info!("Adaptive: {} stage now at {} of {} threads, {} queue {:.0}% full", throttle.stage, threads, throttle.limit, queue, fill(queue) * 100.0);
+                }
+            }
+        }
+        meters.hashers.store(throttles[0].threads(), Ordering::Relaxed);
+        meters.workers.store(throttles[1].threads(), Ordering::Relaxed);
+    }
+}
+
+/// Feeds every item from `rx` to `work` on the blocking pool, at most one
+/// per permit at a time, until the channel closes and all of them are
+/// done. After an interrupt no more items are taken, and those in
+/// progress are waited for until `grace` has passed.
+async fn fan_out<T, F>(stage: &str, mut rx: mpsc::Receiver<T>, permits: Arc<Semaphore>, grace: Duration, work: F) -> Result<()>
+where
+    T: Send + 'static,
+    F: Fn(T) + Clone + Send + 'static,
+{
+    info!("{} started", stage);
+    let mut tasks = JoinSet::new();
+    loop {
+        let item = tokio::select! {
+            biased;
+            _ = wait_for_interrupt() => break,
+            item = rx.recv() => match item {
+                Some(item) => item,
+                None => break,
+            },
+        };
+        let permit = tokio::select! {
+            biased;
+            _ = wait_for_interrupt() => break,
+            permit = permits.clone().acquire_owned() => permit?,
+        };
+        let work = work.clone();
+        tasks.spawn_blocking(move || {
+            work(item);
+            drop(permit);
+        });
+        while let Some(done) = tasks.try_join_next() {
+            done.with_context(|| format!("{} failed", stage))?;
+        }
+    }
+    // Stops the stage before, which blocks on sending to it.
+    drop(rx);
+
+    if interrupted() {
+        while let Some(done) = tasks.try_join_next() {
+            done.with_context(|| format!("{} failed", stage))?;
+        }
+        info!("{}: interrupted, finishing {} files in progress", stage, tasks.len());
+    }
+    let deadline = shutdown_deadline(grace);
+    tokio::pin!(deadline);
+    loop {
+        tokio::select! {
+            done = tasks.join_next() => match done {
+                Some(done) => done.with_context(|| format!("{} failed", stage))?,
+                None => break,
+            },
+            _ = &mut deadline => {
+                warn!("{}: giving up on {} files still in progress", stage, tasks.len());
+                ffmpeg::kill_all_children();
+                tasks.detach_all();
+                break;
+            }
+        }
+    }
+    info!("{} finished", stage);
+    Ok(())
+}
+
+/// Hands the scanned files out to the remote workers connecting on
+/// `listener` with a token `auth` takes, as many at a time as each has
+/// threads, and queues what they send back for the writer. Done once every
+/// file was handed out and answered for; until a worker connects, the
+/// scanner waits.
+async fn dispatch(
+    listener: TcpListener,
+    auth: Auth,
+    scan_rx: mpsc::Receiver<PathBuf>,
+    db_tx: mpsc::Sender<DbMessage>,
+    meters: Arc<Meters>,
+    committed: Arc<HashMap<String, CommittedFile>>,
+    grace: Duration,
+) -> Result<()> {
+    info!("Dispatcher started");
+    let jobs = Arc::new(tokio::sync::Mutex::new(scan_rx));
+    // Set by the first connection to find no more files.
+    let drained = Arc::new(AtomicBool::new(false));
+    let mut connections = JoinSet::new();
+    loop {
+        tokio::select! {
+            accepted = listener.accept(), if !drained.load(Ordering::Relaxed) && !interrupted() => {
+                let (stream, peer) = match accepted {
+                    Ok(accepted) => accepted,
+                    Err(e) => {
+                        warn!("Failed to accept a worker: {}", e);
+                        continue;
+                    }
+                };
+                let worker = serve(Connection::new(stream), auth.clone(), jobs.clone(), drained.clone(), db_tx.clone(), meters.clone(), committed.clone(), grace);
+                connections.spawn(async move {
+                    if let Err(e) = worker.await {
+                        warn!("Worker at {} dropped: {:#}", peer, e);
+                    }
+                });
+            }
+            Some(done) = connections.join_next() => {
+                done.context("Dispatcher failed")?;
+                if (drained.load(Ordering::Relaxed) || interrupted()) && connections.is_empty() {
+                    break;
+                }
+            }
+            _ = wait_for_interrupt(), if connections.is_empty() => break,
+        }
+    }
+    info!("Dispatcher finished");
+    Ok(())
+}
+
+/// Serves one remote worker until there are no more files and it answered
+/// for all it was given, or it goes away. Files it had then are recorded
+/// as failed in stage `remote`, for `replay-failed`.
+#[allow(clippy::too_many_arguments)]
+async fn serve(
+    mut worker: Connection,
+    auth: Auth,
+    jobs: Arc<tokio::sync::Mutex<mpsc::Receiver<PathBuf>>>,
+    drained: Arc<AtomicBool>,
+    db_tx: mpsc::Sender<DbMessage>,
+    meters: Arc<Meters>,
+    committed: Arc<HashMap<String, CommittedFile>>,
+    grace: Duration,
+) -> Result<()> {
+    let hello: remote::Hello = worker.recv().await?.context("Worker closed the connection before saying hello")?;
+    remote::check(&hello, &auth)?;
+    let threads = hello.threads.max(1);
+    info!("Worker {} connected with {} threads", hello.host, threads);
+
+    let mut outstanding = HashSet::new();
+    let mut exhausted = false;
+    let deadline = shutdown_deadline(grace);
+    tokio::pin!(deadline);
+    let result = loop {
+        if exhausted && outstanding.is_empty() {
+            break Ok(());
+        }
+        tokio::select! {
+            job = async { jobs.lock().await.recv().await }, if !exhausted && outstanding.len() < threads => {
+                let Some(path) = job else {
+                    drained.store(true, Ordering::Relaxed);
+                    exhausted = true;
+                    worker.close().await?;
+                    continue;
+                };
+                meters.scan.record(0);
+                if unchanged(&committed, &path) {
+                    meters.skipped.fetch_add(1, Ordering::Relaxed);
+                    continue;
+                }
+                let path = path.to_string_lossy().to_string();
+                worker.send(&remote::Job { path: path.clone() }).await?;
+                outstanding.insert(path);
+            }
+            message = worker.recv::<DbMessage>() => {
+                let message = match message {
+                    Ok(Some(message)) => message,
+                    Ok(None) => break Err(anyhow!("Connection closed with {} files in progress", outstanding.len())),
+                    Err(e) => break Err(e),
+                };
+                match &message {
+                    DbMessage::Done { path } => {
+                        outstanding.remove(path);
+                        continue;
+                    }
+                    DbMessage::Record(record) | DbMessage::Traced(record, _) | DbMessage::Sighting(record, _) => {
+                        let size = record.size_bytes.unwrap_or(0);
+                        meters.hash.record(size);
+                        meters.analyze.record(size);
+                        events::emit(events::Event::Analyzed { path: &record.original_path, hash: &record.hash_sha256, media_type: &record.media_type });
+                    }
+                    DbMessage::Error { path, stage, error, .. } => {
+                        error!("{} failed for {:?} on {}: {}", stage, path, hello.host, error);
+                        meters.error(stage);
+                        events::emit(events::Event::Failed { path, stage, error });
+                    }
+                }
+                if db_tx.send(message).await.is_err() {
+                    break Err(anyhow!("The catalog writer stopped"));
+                }
+            }
+            _ = wait_for_interrupt(), if !exhausted => {
+                exhausted = true;
+                worker.close().await?;
+            }
+            _ = &mut deadline => break Err(anyhow!("Giving up on {} files still in progress", outstanding.len())),
+        }
+    };
+
+    for path in outstanding {
+        meters.error("remote");
+        let error = format!("Worker {} went away before finishing the file", hello.host);
+        let _ = db_tx.send(DbMessage::Error { path, stage: "remote".to_string(), error, stderr: None, partial: None }).await;
+    }
+    info!("Worker {} disconnected", hello.host);
+    result
+}
+
+/// Works for the coordinator at `addr`, an `ingest --serve-workers`: hashes
+/// and analyzes the files it hands out, `threads` at a time, and sends the
+/// results back. The files have to be at the same paths here as there, e.g.
+/// on a share mounted at the same place. Returns once the coordinator has
+/// no more files. Drives `work_async` on a runtime of its own.
+pub fn work(addr: &str, threads: Option<usize>, config: Config) -> Result<()> {
+    block_on(work_async(addr.to_string(), threads, config))
+}
+
+/// `work` on the caller's runtime.
+pub async fn work_async(addr: String, threads: Option<usize>, config: Config) -> Result<()> {
+    stages::validate(&config.pipeline.stages)?;
+    let cpus = thread::available_parallelism().map_or(1, |n| n.get());
+    let threads = threads.unwrap_or_else(|| Topology::resolve(&config.pipeline, &Input::Files(Vec::new()), cpus).workers).max(1);
+    let grace = Duration::from_secs(config.pipeline.shutdown_timeout_secs);
+    let Some(token) = config.pipeline.worker_token.clone() else {
+        bail!("pipeline.worker_token isn't set; the coordinator takes only workers with one of its pipeline.worker_tokens");
+    };
+    let mut coordinator = Connection::connect(&addr).await?;
+
+    let (config, engine, plugins) = task::spawn_blocking(move || {
+        let mut config = config;
+        let engine = load_engine(&mut config);
+        let plugins = Plugins::load(&config.plugins);
+        (config, engine, plugins)
+    })
+    .await?;
+    let plugins = Arc::new(plugins?);
+    let config = Arc::new(config);
+    let meters = Arc::new(Meters::default());
+    let stages: Arc<[Box<dyn Stage>]> = stages::build(&config, engine, &plugins, meters.inference.clone())?.into();
+    let sources = Arc::new(SourceResolver::default());
+
+    let host = source::host_name();
+    coordinator.send(&remote::Hello { protocol: remote::PROTOCOL_VERSION, host, threads, token: Some(token) }).await?;
+    info!("Working for {} with {} threads", addr, threads);
+    let (mut lines, mut writer) = coordinator.split();
+
+    // Jobs in from the coordinator
+    let (job_tx, job_rx) = mpsc::channel::<PathBuf>(threads);
+    let receiver = tokio::spawn(async move {
+        while let Some(job) = remote::recv::<remote::Job>(&mut lines).await? {
+            if job_tx.send(PathBuf::from(job.path)).await.is_err() {
+                break;
+            }
+        }
+        anyhow::Ok(())
+    });
+
+    // Results back to it, each file's followed by `Done`
+    let (result_tx, mut result_rx) = mpsc::channel::<DbMessage>(threads * 4);
+    let sender = tokio::spawn(async move {
+        while let Some(message) = result_rx.recv().await {
+            remote::send(&mut writer, &message).await?;
+        }
+        writer.shutdown().await?;
+        anyhow::Ok(())
+    });
+
+    let errors = ErrorSink { tx: result_tx.clone(), meters: meters.clone() };
+    let permits = Arc::new(Semaphore::new(threads));
+    let retry = config.retry.clone();
+    let work_meters = meters.clone();
+    fan_out("Remote work", job_rx, permits, grace, move |path: PathBuf| {
+        let span = file_span(&path);
+        let _file = span.enter();
+        let hashing = Instant::now();
+        match debug_span!("hash").in_scope(|| retry.run("Hashing", || hasher::fingerprint(&path))) {
+            Ok(fp) => {
+                span.record("size", fp.size).record("hash", fp.hash.as_str());
+                let job = MediaJob { path: path.clone(), hash: fp.hash, size: fp.size, mtime: fp.mtime, device: fp.device, media_type: None, hashed_in: hashing.elapsed(), span: span.clone() };
+                let record = analyze(job, &config, &stages, &sources, &errors);
+                work_meters.analyze.record(fp.size);
+                let _ = result_tx.blocking_send(DbMessage::Record(Box::new(record)));
+            }
+            Err(e) => errors.report(&path, "hash", None, e),
+        }
+        let _ = result_tx.blocking_send(DbMessage::Done { path: path.to_string_lossy().to_string() });
+    })
+    .await?;
+
+    // The coordinator may have closed the connection first.
+    receiver.abort();
+    sender.await??;
+    info!("Finished working for {}: {} files analyzed", addr, meters.analyze.summary("analyze").files);
+    Ok(())
+}
+
+/// Another path of content the catalog already has. Only what the path
+/// itself tells is set; the catalog's merge rules keep the rest of the
+/// artifact as it is.
+fn sighting(job: MediaJob, sources: &SourceResolver) -> ArtifactRecord {
+    let source = sources.resolve(&job.path, job.device);
+    ArtifactRecord {
+        hash_sha256: job.hash,
+        original_path: job.path.to_string_lossy().to_string(),
+        media_type: "application/octet-stream".to_string(),
+        size_bytes: Some(job.size),
+        mtime: job.mtime,
+        width: None,
+        height: None,
+        tags: Vec::new(),
+        nsfw_score: None,
+        nsfw_model_version: None,
+        embeddings: Vec::new(),
+        metadata: None,
+        source: Some(source),
+        timings: Some(FileTimings { hash_ms: millis(job.hashed_in), total_ms: millis(job.hashed_in), ..Default::default() }),
+    }
+}
+
+/// The trace of a file's way through the pipeline, down to the catalog;
+/// at debug level, so it costs nothing unless exported (`[telemetry]`).
+fn file_span(path: &Path) -> Span {
+    debug_span!("file", path = %path.display(), size = field::Empty, hash = field::Empty, media_type = field::Empty)
+}
+
+fn millis(elapsed: Duration) -> u64 {
+    elapsed.as_millis() as u64
+}
+
+/// Runs a hashed file through the analysis stages, reporting what fails.
+fn analyze(job: MediaJob, config: &Config, stages: &[Box<dyn Stage>], sources: &SourceResolver, errors: &ErrorSink) -> ArtifactRecord {
+    let _file = job.span.enter();
+    let _analyze = debug_span!("analyze").entered();
+    let analyzing = Instant::now();
+    let mut file = Analysis::new(&job.path, &job.hash, job.media_type.clone());
+    for stage in stages {
+        if !stage.accepts(file.media_type()) {
+            continue;
+        }
+        let _stage = debug_span!("stage", otel.name = stage.name()).entered();
+        if let Err(e) = stage.process(&mut file) {
+            errors.report(&job.path, stage.name(), Some(job.partial(file.media_type.as_deref())), e);
+        }
+    }
+
+    let Analysis { media_type, tags, nsfw_score, metadata, decode, inference, .. } = file;
+    let media_type = media_type.unwrap_or_else(|| "application/octet-stream".to_string());
+    job.span.record("media_type", media_type.as_str());
+    let sampling = config.media.sampling_for(&media_type);
+    let source = sources.resolve(&job.path, job.device);
+
+    ArtifactRecord {
+        hash_sha256: job.hash,
+        original_path: job.path.to_string_lossy().to_string(),
+        media_type,
+        size_bytes: Some(job.size),
+        mtime: job.mtime,
+        width: Some(sampling.resolution),
+        height: Some(sampling.resolution),
+        tags,
+        nsfw_score,
+        nsfw_model_version: nsfw_score.map(|_| NSFW_MODEL_VERSION.to_string()),
+        // No embedding model is loaded yet.
+        embeddings: Vec::new(),
+        metadata,
+        source: Some(source),
+        timings: Some(FileTimings {
+            hash_ms: millis(job.hashed_in),
+            decode_ms: millis(decode),
+            inference_ms: millis(inference),
+            total_ms: millis(job.hashed_in + analyzing.elapsed()),
+        }),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_topology() {
+        let config = PipelineConfig { storage: Storage::Hdd, ..Default::default() };
+        let topology = Topology::resolve(&config, &Input::Files(Vec::new()), 16);
+        assert_eq!((topology.hashers, topology.workers), (1, 4));
+
+        let config = PipelineConfig { storage: Storage::Ssd, workers: Some(3), db_queue: 0, ..Default::default() };
+        let topology = Topology::resolve(&config, &Input::Files(Vec::new()), 1);
+        assert_eq!((topology.hashers, topology.workers, topology.db_queue), (2, 3, 1));
+    }
+
+    #[test]
+    fn test_throttle() {
+        let permits = Arc::new(Semaphore::new(2));
+        let mut throttle = Throttle::new("hash", permits.clone(), 2);
+        for _ in 1..FULL_SAMPLES {
+            assert_eq!(throttle.sample(1.0), None);
+        }
+        assert_eq!(throttle.sample(0.95), Some(1));
+        assert_eq!(permits.available_permits(), 1);
+        // Never below one thread.
+        for _ in 0..FULL_SAMPLES {
+            assert_eq!(throttle.sample(1.0), None);
+        }
+        assert_eq!(throttle.sample(0.7), None);
+        assert_eq!(throttle.sample(0.2), Some(2));
+        assert_eq!(permits.available_permits(), 2);
+    }
+
+    #[test]
+    fn test_rebalance() {
+        let hash_permits = Arc::new(Semaphore::new(2));
+        let work_permits = Arc::new(Semaphore::new(2));
+        let mut throttles = [Throttle::new("hash", hash_permits.clone(), 2), Throttle::new("analyze", work_permits.clone(), 2)];
+        let mut rebalance = Rebalance::new(usize::MAX);
+        for _ in 1..FULL_SAMPLES {
+            assert_eq!(rebalance.sample(1.0, 0.0, &mut throttles), None);
+        }
+        assert_eq!(rebalance.sample(1.0, 0.0, &mut throttles), Some("hash"));
+        assert_eq!((hash_permits.available_permits(), work_permits.available_permits()), (3, 1));
+        // The workers keep one thread.
+        for _ in 0..FULL_SAMPLES {
+            assert_eq!(rebalance.sample(1.0, 0.0, &mut throttles), None);
+        }
+        for _ in 1..FULL_SAMPLES {
+            rebalance.sample(0.0, 0.95, &mut throttles);
+        }
+        assert_eq!(rebalance.sample(0.0, 0.95, &mut throttles), Some("analyze"));
+        assert_eq!((throttles[0].threads(), throttles[1].threads()), (2, 2));
+
+        // A spinning disk doesn't get more readers.
+        let mut rebalance = Rebalance::new(2);
+        for _ in 0..FULL_SAMPLES * 2 {
+            assert_eq!(rebalance.sample(1.0, 0.0, &mut throttles), None);
+        }
+    }
+
+    #[test]
+    fn test_unchanged() -> Result<()> {
+        let path = std::env::temp_dir().join(format!("deep_archive_unchanged_{}", std::process::id()));
+        std::fs::write(&path, b"frame")?;
+        let metadata = std::fs::metadata(&path)?;
+        let file = CommittedFile { size_bytes: Some(metadata.len()), mtime: hasher::mtime(&metadata) };
+        let mut committed = HashMap::from([(path.to_string_lossy().to_string(), file)]);
+        assert!(unchanged(&committed, &path));
+        assert!(!unchanged(&HashMap::new(), &path));
+
+        // A file rewritten since is ingested again.
+        committed.values_mut().for_each(|file| file.size_bytes = Some(4));
+        assert!(!unchanged(&committed, &path));
+        std::fs::remove_file(&path)?;
+        assert!(!unchanged(&committed, &path));
+        Ok(())
+    }
+}
@@ -0,0 +1,151 @@
+use anyhow::{Result, Context, bail};
+use serde::de::DeserializeOwned;
+use serde::{Deserialize, Serialize};
+use tokio::io::{AsyncBufRead, AsyncBufReadExt, AsyncReadExt, AsyncWrite, AsyncWriteExt, BufReader};
+use tokio::net::tcp::{OwnedReadHalf, OwnedWriteHalf};
+use tokio::net::TcpStream;
+use crate::server::auth::Auth;
+
+/// Bumped when the messages below change; workers and coordinators of
+/// different versions refuse to talk.
+pub const PROTOCOL_VERSION: u32 = 2;
+
+/// Longest message either side reads, so a peer can't run it out of memory
+/// with a line that never ends. Records with large metadata stay well
+/// below it.
+pub const MAX_MESSAGE: usize = 16 << 20;
+
+/// The reading side of a connection.
+pub type Reader = BufReader<OwnedReadHalf>;
+
+/// The first line a worker sends.
+#[derive(Debug, Serialize, Deserialize)]
+pub struct Hello {
+    pub protocol: u32,
+    pub host: String,
+    /// Files it takes on at once.
+    pub threads: usize,
+    /// Its `pipeline.worker_token`, one of the coordinator's
+    /// `pipeline.worker_tokens`.
+    #[serde(default)]
+    pub token: Option<String>,
+}
+
+/// A file for a worker to hash and analyze. The coordinator closes its side
+/// of the connection once there are no more.
+#[derive(Debug, Serialize, Deserialize)]
+pub struct Job {
+    pub path: String,
+}
+
+/// One side of a coordinator-worker connection: JSON objects, one per line.
+pub struct Connection {
+    reader: Reader,
+    writer: OwnedWriteHalf,
+}
+
+impl Connection {
+    pub fn new(stream: TcpStream) -> Self {
+        let (reader, writer) = stream.into_split();
+        Connection { reader: BufReader::new(reader), writer }
+    }
+
+    pub async fn connect(addr: &str) -> Result<Self> {
+        let stream = TcpStream::connect(addr).await.with_context(|| format!("Failed to connect to the coordinator at {}", addr))?;
+        Ok(Self::new(stream))
+    }
+
+    /// The next message, or `None` once the other side closed.
+    pub async fn recv<T: DeserializeOwned>(&mut self) -> Result<Option<T>> {
+        recv(&mut self.reader).await
+    }
+
+    pub async fn send<T: Serialize>(&mut self, message: &T) -> Result<()> {
+        send(&mut self.writer, message).await
+    }
+
+    /// Lets the reading and writing go on in separate tasks.
+    pub fn split(self) -> (Reader, OwnedWriteHalf) {
+        (self.reader, self.writer)
+    }
+
+    /// Tells the other side nothing more is coming, while still reading.
+    pub async fn close(&mut self) -> Result<()> {
+        Ok(self.writer.shutdown().await?)
+    }
+}
+
+/// The next message, or `None` once the other side closed. Fails on one
+/// longer than [`MAX_MESSAGE`].
+pub async fn recv<T: DeserializeOwned>(reader: &mut (impl AsyncBufRead + Unpin)) -> Result<Option<T>> {
+    let mut line = Vec::new();
+    if reader.take(MAX_MESSAGE as u64 + 1).read_until(b'\n', &mut line).await? == 0 {
+        return Ok(None);
+    }
+    if line.len() > MAX_MESSAGE {
+        bail!("Message longer than {} bytes", MAX_MESSAGE);
+    }
+    Ok(Some(serde_json::from_slice(&line).with_context(|| format!("Unreadable message: {}", String::from_utf8_lossy(&line).trim_end()))?))
+}
+
+pub async fn send<T: Serialize>(writer: &mut (impl AsyncWrite + Unpin), message: &T) -> Result<()> {
+    let mut line = serde_json::to_vec(message)?;
+    line.push(b'\n');
+    writer.write_all(&line).await?;
+    Ok(())
+}
+
+/// Fails unless `hello` comes from a worker speaking this protocol with
+/// one of the tokens `auth` takes.
+pub fn check(hello: &Hello, auth: &Auth) -> Result<()> {
+    if hello.protocol != PROTOCOL_VERSION {
+        bail!(
+            "Worker {} speaks protocol {}, this coordinator {}; run the same version of deep-archive on both",
+            hello.host, hello.protocol, PROTOCOL_VERSION
+        );
+    }
+    if auth.scope(hello.token.as_deref()).is_none() {
+        bail!("Worker {} didn't send one of pipeline.worker_tokens", hello.host);
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tokio::net::TcpListener;
+    use crate::utils::config::{ApiToken, Scope};
+
+    #[tokio::test]
+    async fn test_connection() -> Result<()> {
+        let listener = TcpListener::bind("127.0.0.1:0").await?;
+        let addr = listener.local_addr()?.to_string();
+        let worker = tokio::spawn(async move {
+            let mut coordinator = Connection::connect(&addr).await?;
+            coordinator.send(&Hello { protocol: PROTOCOL_VERSION, host: "node2".to_string(), threads: 4, token: Some("w0rk".to_string()) }).await?;
+            let mut paths = Vec::new();
+            while let Some(job) = coordinator.recv::<Job>().await? {
+                paths.push(job.path);
+            }
+            anyhow::Ok(paths)
+        });
+
+        let (stream, _) = listener.accept().await?;
+        let mut worker_side = Connection::new(stream);
+        let hello: Hello = worker_side.recv().await?.context("No hello")?;
+        assert_eq!((hello.host.as_str(), hello.threads), ("node2", 4));
+        let tokens = [ApiToken { name: "nodes".to_string(), token: Some("w0rk".to_string()), sha256: None, scope: Scope::Read }];
+        let auth = Auth::with_tokens(&tokens, "pipeline.worker_tokens")?;
+        check(&hello, &auth)?;
+        assert!(check(&Hello { token: Some("guess".to_string()), ..hello }, &auth).is_err());
+        worker_side.send(&Job { path: "/nas/a.mp4".to_string() }).await?;
+        worker_side.close().await?;
+        assert_eq!(worker.await??, ["/nas/a.mp4"]);
+
+        // A line that never ends is refused once it passes the limit.
+        let endless = [b'x'; 1024].repeat(MAX_MESSAGE / 1024 + 1);
+        assert!(recv::<Job>(&mut endless.as_slice()).await.is_err());
+        assert!(recv::<Job>(&mut &b"{\"path\": \"/nas/b.mp4\"}"[..]).await?.is_some());
+        Ok(())
+    }
+}
@@ -1,8 +1,11 @@
 use walkdir::{WalkDir, DirEntry};
 use std::path::{Path, PathBuf};
-use crossbeam::channel::Sender;
+use tokio::sync::mpsc::Sender;
 use anyhow::Result;
+use crate::ingest::events::{self, Event};
 
+/// Sends every non-hidden file under `root` to `tx`, blocking while the
+/// channel is full; call it off the async threads.
 pub fn scan_directory(root: &Path, tx: Sender<PathBuf>) -> Result<()> {
     let walker = WalkDir::new(root).into_iter();
 
@@ -13,7 +16,8 @@ pub fn scan_directory(root: &Path, tx: Sender<PathBuf>) -> Result<()> {
             // Using unwrap/expect here might panic if channel is closed,
             // but in this pipeline, if the receiver dies, we probably want to stop anyway.
             // Ideally we handle the error gracefully.
-            if let Err(_) = tx.send(entry.path().to_path_buf()) {
+            events::emit(Event::Discovered { path: &entry.path().to_string_lossy() });
+            if tx.blocking_send(entry.path().to_path_buf()).is_err() {
                 break;
             }
         }
@@ -21,7 +25,7 @@ pub fn scan_directory(root: &Path, tx: Sender<PathBuf>) -> Result<()> {
     Ok(())
 }
 
-fn is_hidden(entry: &DirEntry) -> bool {
+pub(crate) fn is_hidden(entry: &DirEntry) -> bool {
     entry.file_name()
          .to_str()
          .map(|s| s.starts_with('.'))
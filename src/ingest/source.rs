@@ -0,0 +1,194 @@
+use std::collections::HashMap;
+use std::fmt;
+use std::path::Path;
+use std::sync::Mutex;
+use serde::{Deserialize, Serialize};
+use tracing::debug;
+
+/// The volume a file was read from: enough to find the right drive on the shelf.
+#[derive(Debug, Clone, PartialEq, Eq, Hash, Serialize, Deserialize)]
+pub struct Source {
+    pub host: String,
+    /// Filesystem UUID, stable across hosts and mount points.
+    pub fs_uuid: Option<String>,
+    pub label: Option<String>,
+    pub mount_point: Option<String>,
+}
+
+impl fmt::Display for Source {
+    /// E.g. `BACKUP-1 (8c1e…) at /media/backup1 on nas`, leaving out what is unknown.
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        if let Some(label) = &self.label {
+            write!(f, "{} ", label)?;
+        }
+        if let Some(uuid) = &self.fs_uuid {
+            write!(f, "({}) ", uuid)?;
+        }
+        if let Some(mount) = &self.mount_point {
+            write!(f, "at {} ", mount)?;
+        }
+        write!(f, "on {}", self.host)
+    }
+}
+
+/// Resolves files to their [`Source`], looking each device up only once.
+pub struct SourceResolver {
+    host: String,
+    cache: Mutex<HashMap<u64, Source>>,
+}
+
+impl Default for SourceResolver {
+    fn default() -> Self {
+        Self { host: host_name(), cache: Mutex::new(HashMap::new()) }
+    }
+}
+
+impl SourceResolver {
+    /// `device` is the file's `st_dev`; without one only the host is known.
+    pub fn resolve(&self, path: &Path, device: Option<u64>) -> Source {
+        let Some(device) = device else {
+            return Source { host: self.host.clone(), fs_uuid: None, label: None, mount_point: None };
+        };
+
+        let mut cache = self.cache.lock().unwrap();
+        cache
+            .entry(device)
+            .or_insert_with(|| {
+                let source = self.lookup(path, device);
+                debug!("Device {} is {:?}", device, source);
+                source
+            })
+            .clone()
+    }
+
+    #[cfg(target_os = "linux")]
+    fn lookup(&self, path: &Path, device: u64) -> Source {
+        Source {
+            host: self.host.clone(),
+            fs_uuid: linux::disk_link("/dev/disk/by-uuid", device),
+            label: linux::disk_link("/dev/disk/by-label", device).map(|l| linux::unescape(&l)),
+            mount_point: linux::mount_point(path, device),
+        }
+    }
+
+    #[cfg(not(target_os = "linux"))]
+    fn lookup(&self, _path: &Path, _device: u64) -> Source {
+        Source { host: self.host.clone(), fs_uuid: None, label: None, mount_point: None }
+    }
+}
+
+pub fn host_name() -> String {
+    std::fs::read_to_string("/proc/sys/kernel/hostname")
+        .ok()
+        .or_else(|| std::env::var("HOSTNAME").ok())
+        .or_else(|| std::env::var("COMPUTERNAME").ok())
+        .map(|h| h.trim().to_string())
+        .filter(|h| !h.is_empty())
+        .unwrap_or_else(|| "unknown".to_string())
+}
+
+/// Whether the file system holding `path` is on a spinning disk, from the
+/// kernel's `queue/rotational` flag of its block device; `None` where that
+/// isn't known, e.g. for network and virtual file systems.
+#[cfg(target_os = "linux")]
+pub fn rotational(path: &Path) -> Option<bool> {
+    use std::os::unix::fs::MetadataExt;
+    linux::rotational(std::fs::metadata(path).ok()?.dev())
+}
+
+#[cfg(not(target_os = "linux"))]
+pub fn rotational(_path: &Path) -> Option<bool> {
+    None
+}
+
+#[cfg(target_os = "linux")]
+mod linux {
+    use std::fs;
+    use std::os::unix::fs::MetadataExt;
+    use std::path::Path;
+
+    /// Name of the udev symlink in `dir` pointing at the block device `device`.
+    pub fn disk_link(dir: &str, device: u64) -> Option<String> {
+        fs::read_dir(dir).ok()?.flatten().find_map(|entry| {
+            let target = fs::metadata(entry.path()).ok()?;
+            (target.rdev() == device).then(|| entry.file_name().to_string_lossy().to_string())
+        })
+    }
+
+    /// Deepest mount of `device` containing `path`, from `/proc/self/mountinfo`.
+    pub fn mount_point(path: &Path, device: u64) -> Option<String> {
+        let path = path.canonicalize().ok()?;
+        let wanted = format!("{}:{}", major(device), minor(device));
+        let mountinfo = fs::read_to_string("/proc/self/mountinfo").ok()?;
+
+        mountinfo
+            .lines()
+            .filter_map(|line| {
+                let mut fields = line.split(' ');
+                let dev = fields.nth(2)?;
+                let mount = unescape(fields.nth(1)?);
+                (dev == wanted && path.starts_with(&mount)).then_some(mount)
+            })
+            .max_by_key(|mount| mount.len())
+    }
+
+    /// Partitions have no queue of their own; theirs is the disk's, one
+    /// level up in sysfs.
+    pub fn rotational(device: u64) -> Option<bool> {
+        let block = Path::new("/sys/dev/block").join(format!("{}:{}", major(device), minor(device)));
+        let flag = fs::read_to_string(block.join("queue/rotational"))
+            .or_else(|_| fs::read_to_string(block.join("../queue/rotational")))
+            .ok()?;
+        Some(flag.trim() == "1")
+    }
+
+    fn major(dev: u64) -> u64 {
+        ((dev >> 8) & 0xfff) | ((dev >> 32) & !0xfff)
+    }
+
+    fn minor(dev: u64) -> u64 {
+        (dev & 0xff) | ((dev >> 12) & !0xff)
+    }
+
+    /// Decodes the `\ooo` escapes used by mountinfo and the `\xHH` ones used
+    /// by udev link names.
+    pub fn unescape(s: &str) -> String {
+        let bytes = s.as_bytes();
+        let mut out = Vec::with_capacity(bytes.len());
+        let mut i = 0;
+        while i < bytes.len() {
+            let decoded = match bytes.get(i..i + 4) {
+                Some([b'\\', b'x', hex @ ..]) => parse(hex, 16),
+                Some([b'\\', oct @ ..]) => parse(oct, 8),
+                _ => None,
+            };
+            match decoded {
+                Some(b) => {
+                    out.push(b);
+                    i += 4;
+                }
+                None => {
+                    out.push(bytes[i]);
+                    i += 1;
+                }
+            }
+        }
+        String::from_utf8_lossy(&out).to_string()
+    }
+
+    fn parse(digits: &[u8], radix: u32) -> Option<u8> {
+        u8::from_str_radix(std::str::from_utf8(digits).ok()?, radix).ok()
+    }
+}
+
+#[cfg(all(test, target_os = "linux"))]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_unescape() {
+        assert_eq!(linux::unescape(r"/media/My\040Drive"), "/media/My Drive");
+        assert_eq!(linux::unescape(r"BACKUP\x2d1"), "BACKUP-1");
+        assert_eq!(linux::unescape(r"a\9"), r"a\9");
+    }
+}
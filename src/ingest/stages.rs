@@ -0,0 +1,245 @@
+use std::path::Path;
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+use anyhow::{Result, bail};
+use image::{ImageBuffer, Rgb};
+use tracing::{debug_span, error};
+use crate::ingest::metrics::Histogram;
+use crate::media::{container, decode, metadata, mimetype, preview};
+use crate::ml::engine::InferenceEngine;
+use crate::ml::pipeline;
+use crate::plugins::{Hook, Plugins};
+use crate::utils::config::Config;
+
+/// Names of the built-in stages, in the order they run by default.
+pub const STAGES: [&str; 5] = ["mimetype", "models", "preview", "metadata", "plugins"];
+
+/// A hashed file on its way through the analysis stages, each reading what
+/// the ones before it found and adding to it.
+pub struct Analysis<'a> {
+    pub path: &'a Path,
+    pub hash: &'a str,
+    /// Unset until detected, or given when a failed file is replayed.
+    pub media_type: Option<String>,
+    pub tags: Vec<String>,
+    /// Highest score across the sampled frames.
+    pub nsfw_score: Option<f32>,
+    pub metadata: Option<serde_json::Value>,
+    /// Time spent decoding frames and running the models on them.
+    pub decode: Duration,
+    pub inference: Duration,
+}
+
+impl<'a> Analysis<'a> {
+    pub fn new(path: &'a Path, hash: &'a str, media_type: Option<String>) -> Self {
+        Analysis {
+            path,
+            hash,
+            media_type,
+            tags: Vec::new(),
+            nsfw_score: None,
+            metadata: None,
+            decode: Duration::ZERO,
+            inference: Duration::ZERO,
+        }
+    }
+
+    /// The detected type, or `application/octet-stream`.
+    pub fn media_type(&self) -> &str {
+        self.media_type.as_deref().unwrap_or("application/octet-stream")
+    }
+}
+
+/// One step of analysis. The workers run the enabled stages in turn on every
+/// file; a failing stage is reported under its name and the file goes on to
+/// the next one with what was found so far.
+pub trait Stage: Send + Sync {
+    /// Names the stage in `pipeline.stages` and in the failures it reports.
+    fn name(&self) -> &'static str;
+
+    /// Whether the stage has anything to do for a file of `media_type`.
+    fn accepts(&self, _media_type: &str) -> bool {
+        true
+    }
+
+    fn process(&self, file: &mut Analysis) -> Result<()>;
+}
+
+/// Fails on names in `pipeline.stages` that aren't stages.
+pub fn validate(names: &[String]) -> Result<()> {
+    for name in names {
+        if !STAGES.contains(&name.as_str()) {
+            bail!("Unknown pipeline stage '{}'; the stages are {}", name, STAGES.join(", "));
+        }
+    }
+    Ok(())
+}
+
+/// The stages `config.pipeline.stages` enables, in its order. The preview
+/// stage also needs `preview.enabled`, the models stage an engine and the
+/// plugins stage plugins that extract or tag. `inference` gets the time
+/// the models take per frame.
+pub fn build(config: &Arc<Config>, engine: Option<Arc<InferenceEngine>>, plugins: &Arc<Plugins>, inference: Arc<Histogram>) -> Result<Vec<Box<dyn Stage>>> {
+    validate(&config.pipeline.stages)?;
+    let mut stages: Vec<Box<dyn Stage>> = Vec::new();
+    for name in &config.pipeline.stages {
+        match name.as_str() {
+            "mimetype" => stages.push(Box::new(MimeType)),
+            "models" => stages.push(Box::new(Models { config: config.clone(), engine: engine.clone(), inference: inference.clone() })),
+            "preview" if config.preview.enabled => stages.push(Box::new(Preview { config: config.clone() })),
+            "metadata" => stages.push(Box::new(Metadata { config: config.clone() })),
+            "plugins" if plugins.exports(Hook::Extract) || plugins.exports(Hook::Tag) => stages.push(Box::new(Plugged { plugins: plugins.clone() })),
+            _ => {}
+        }
+    }
+    Ok(stages)
+}
+
+/// Detects the type from the file's magic bytes.
+struct MimeType;
+
+impl Stage for MimeType {
+    fn name(&self) -> &'static str {
+        "mimetype"
+    }
+
+    fn process(&self, file: &mut Analysis) -> Result<()> {
+        if file.media_type.is_none() {
+            file.media_type = Some(mimetype::detect_mimetype(file.path)?);
+        }
+        Ok(())
+    }
+}
+
+/// Samples frames of images and videos and scores and tags them.
+struct Models {
+    config: Arc<Config>,
+    engine: Option<Arc<InferenceEngine>>,
+    inference: Arc<Histogram>,
+}
+
+impl Stage for Models {
+    fn name(&self) -> &'static str {
+        "models"
+    }
+
+    fn accepts(&self, media_type: &str) -> bool {
+        media_type.starts_with("video/") || media_type.starts_with("image/")
+    }
+
+    fn process(&self, file: &mut Analysis) -> Result<()> {
+        // Frames are decoded as they are scored; decoding is the rest of the
+        // time spent on them.
+        let decoding = Instant::now();
+        let result = self.score_frames(file);
+        file.decode += decoding.elapsed().saturating_sub(file.inference);
+        result
+    }
+}
+
+impl Models {
+    fn score_frames(&self, file: &mut Analysis) -> Result<()> {
+        let media_type = file.media_type().to_string();
+        let sampling = self.config.media.sampling_for(&media_type);
+        let decoding = || debug_span!("decode");
+        let mut frames = decoding().in_scope(|| self.config.retry.run("Decoding", || decode::extract_frames(file.path, &media_type, &self.config.media, &sampling)))?;
+        // Frames arrive one at a time, so memory stays bounded on long videos.
+        while let Some(frame) = decoding().in_scope(|| frames.next()) {
+            let raw_bytes = frame?;
+            let Some(img_buffer) = ImageBuffer::<Rgb<u8>, Vec<u8>>::from_raw(sampling.resolution, sampling.resolution, raw_bytes) else {
+                error!("Failed to create ImageBuffer from raw bytes for {:?}", file.path);
+                continue;
+            };
+            let dynamic_image = image::DynamicImage::ImageRgb8(img_buffer);
+
+            if let Some(engine) = &self.engine {
+                let started = Instant::now();
+                let scores = debug_span!("inference").in_scope(|| pipeline::score_frame(engine, &dynamic_image));
+                // Keep the highest score across frames
+                if let Some(score) = scores.nsfw {
+                    file.nsfw_score = Some(file.nsfw_score.map_or(score, |s: f32| s.max(score)));
+                }
+                for tag in scores.tags {
+                    if !file.tags.contains(&tag) {
+                        file.tags.push(tag);
+                    }
+                }
+                self.inference.observe(started.elapsed());
+                file.inference += started.elapsed();
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Renders the thumbnail or contact sheet into the preview store.
+struct Preview {
+    config: Arc<Config>,
+}
+
+impl Stage for Preview {
+    fn name(&self) -> &'static str {
+        "preview"
+    }
+
+    fn process(&self, file: &mut Analysis) -> Result<()> {
+        let media_type = file.media_type();
+        self.config.retry.run("Rendering a preview", || {
+            preview::generate_preview(file.path, file.hash, media_type, &self.config.media, &self.config.preview)
+        })?;
+        Ok(())
+    }
+}
+
+/// EXIF, ffprobe, xattr and archive details. Archives no password opens
+/// are tagged as locked.
+struct Metadata {
+    config: Arc<Config>,
+}
+
+impl Stage for Metadata {
+    fn name(&self) -> &'static str {
+        "metadata"
+    }
+
+    fn process(&self, file: &mut Analysis) -> Result<()> {
+        file.metadata = metadata::extract(file.path, file.media_type(), &self.config.media);
+        let locked = file.metadata.as_ref().and_then(|m| m.pointer("/container/encryption")).is_some_and(|e| e == "locked");
+        if locked && !file.tags.iter().any(|tag| tag == container::LOCKED_TAG) {
+            file.tags.push(container::LOCKED_TAG.to_string());
+        }
+        Ok(())
+    }
+}
+
+/// The plugins' metadata extractors and tag post-processors.
+struct Plugged {
+    plugins: Arc<Plugins>,
+}
+
+impl Stage for Plugged {
+    fn name(&self) -> &'static str {
+        "plugins"
+    }
+
+    fn process(&self, file: &mut Analysis) -> Result<()> {
+        self.plugins.analyze(file)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_build_stages() -> Result<()> {
+        let mut config = Config::default();
+        config.pipeline.stages = vec!["metadata".to_string(), "mimetype".to_string(), "preview".to_string()];
+        config.preview.enabled = false;
+        let config = Arc::new(config);
+        let names: Vec<&str> = build(&config, None, &Arc::default(), Arc::default())?.iter().map(|s| s.name()).collect();
+        assert_eq!(names, ["metadata", "mimetype"]);
+        assert!(validate(&["ocr".to_string()]).is_err());
+        Ok(())
+    }
+}
@@ -0,0 +1,120 @@
+use std::collections::BTreeMap;
+use std::fmt;
+use std::sync::Mutex;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::time::Instant;
+use serde::{Deserialize, Serialize};
+use crate::utils::units::{format_duration, format_size};
+
+/// What a pipeline run did, stage by stage. Logged when the run ends and
+/// stored with it as JSON in `runs.summary`. A resumed run's summary covers
+/// the last session only.
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct RunSummary {
+    /// `completed` or `interrupted`.
+    pub status: String,
+    /// Wall-clock seconds from the start of the run to its last write.
+    pub elapsed_secs: f64,
+    /// `scan`, `hash`, `analyze` and `write`, in pipeline order.
+    pub stages: Vec<StageSummary>,
+    /// Files a resumed run had already committed.
+    pub skipped: u64,
+    /// Files the plugins' filters left out.
+    #[serde(default)]
+    pub filtered: u64,
+    /// Files whose content was already catalogued, recorded as another
+    /// path of it without being analyzed.
+    #[serde(default)]
+    pub duplicates: u64,
+    /// Failures by the stage they happened in: `scan`, `hash`, an analysis
+    /// stage (`mimetype`, `models`, `preview`) or `write`.
+    pub errors: BTreeMap<String, u64>,
+}
+
+impl RunSummary {
+    pub fn error_count(&self) -> u64 {
+        self.errors.values().sum()
+    }
+}
+
+/// Files and bytes one stage got through.
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct StageSummary {
+    pub stage: String,
+    pub files: u64,
+    pub bytes: u64,
+    /// Wall-clock seconds from the stage's first file to its last.
+    pub secs: f64,
+}
+
+impl StageSummary {
+    /// Bytes per second, where the stage took measurable time.
+    pub fn throughput(&self) -> Option<f64> {
+        (self.secs > 0.0).then(|| self.bytes as f64 / self.secs)
+    }
+}
+
+impl fmt::Display for RunSummary {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let written = self.stages.iter().find(|s| s.stage == "write").map_or(0, |s| s.files);
+        write!(f, "Run {} in {}: {} files written", self.status, format_duration(self.elapsed_secs), written)?;
+        if self.skipped > 0 {
+            write!(f, ", {} skipped as already committed", self.skipped)?;
+        }
+        if self.filtered > 0 {
+            write!(f, ", {} filtered out", self.filtered)?;
+        }
+        if self.duplicates > 0 {
+            write!(f, ", {} already catalogued", self.duplicates)?;
+        }
+        writeln!(f, ", {} errors", self.error_count())?;
+        for stage in &self.stages {
+            let rate = stage.throughput().map_or_else(String::new, |rate| format!(", {}/s", format_size(rate as u64)));
+            writeln!(
+                f,
+                "  {:<8} {:>8} files {:>10}{} in {}",
+                stage.stage,
+                stage.files,
+                // The scanner only counts files.
+                if stage.bytes > 0 { format_size(stage.bytes) } else { String::new() },
+                rate,
+                format_duration(stage.secs)
+            )?;
+        }
+        if !self.errors.is_empty() {
+            let errors: Vec<String> = self.errors.iter().map(|(stage, count)| format!("{} {}", stage, count)).collect();
+            writeln!(f, "  errors: {}", errors.join(", "))?;
+        }
+        Ok(())
+    }
+}
+
+/// Counts what passes through a stage while the pipeline runs.
+#[derive(Default)]
+pub struct StageMeter {
+    files: AtomicU64,
+    bytes: AtomicU64,
+    /// When the first and the latest file were done.
+    span: Mutex<Option<(Instant, Instant)>>,
+}
+
+impl StageMeter {
+    /// Counts a file of `bytes` as done now.
+    pub fn record(&self, bytes: u64) {
+        self.files.fetch_add(1, Ordering::Relaxed);
+        self.bytes.fetch_add(bytes, Ordering::Relaxed);
+        let now = Instant::now();
+        let mut span = self.span.lock().unwrap();
+        *span = Some(span.map_or((now, now), |(first, _)| (first, now)));
+    }
+
+    pub fn summary(&self, stage: &str) -> StageSummary {
+        let span = *self.span.lock().unwrap();
+        StageSummary {
+            stage: stage.to_string(),
+            files: self.files.load(Ordering::Relaxed),
+            bytes: self.bytes.load(Ordering::Relaxed),
+            secs: span.map_or(0.0, |(first, last)| (last - first).as_secs_f64()),
+        }
+    }
+}
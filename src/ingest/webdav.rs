@@ -0,0 +1,430 @@
+use std::collections::{BTreeMap, VecDeque};
+use std::env;
+use std::fmt::Write as _;
+use std::fs::{self, File};
+use std::io;
+use std::path::{Path, PathBuf};
+use std::time::{Duration, SystemTime};
+use anyhow::{Result, Context, anyhow, bail};
+use base64::Engine as _;
+use base64::engine::general_purpose::STANDARD;
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use serde_json::json;
+use sha2::{Digest, Sha256};
+use tracing::{debug, info, warn};
+use ureq::http::{Request, Response};
+use ureq::tls::{RootCerts, TlsConfig, TlsProvider};
+use ureq::{Agent, Body};
+use crate::ingest::pipeline::{self, Fetched};
+use crate::utils::config::{RetryPolicy, WebDavConfig};
+use crate::utils::retry::Transient;
+
+/// Asks a listing for what mirroring needs of each entry.
+const PROPFIND: &str = r#"<?xml version="1.0" encoding="utf-8"?><d:propfind xmlns:d="DAV:"><d:prop><d:resourcetype/><d:getetag/><d:getcontentlength/><d:getlastmodified/></d:prop></d:propfind>"#;
+
+/// Largest listing of one directory read.
+const LISTING_LIMIT: u64 = 256 << 20;
+
+/// Where unfinished downloads go, under `webdav.mirror_dir`, so none is
+/// left in a mirror.
+const PARTIAL_DIR: &str = ".partial";
+
+/// A file listed on the share.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Entry {
+    /// Below the share's root, `/`-separated.
+    pub path: String,
+    pub etag: Option<String>,
+    pub size: Option<u64>,
+    pub last_modified: Option<String>,
+}
+
+/// How a mirrored file was downloaded, kept in the share's index.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+struct Mirrored {
+    etag: Option<String>,
+    retrieved_at: String,
+}
+
+enum Download {
+    NotModified,
+    Done { etag: Option<String> },
+}
+
+/// A WebDAV share (Nextcloud, ownCloud, Apache's mod_dav...) mirrored
+/// into `webdav.mirror_dir`. A run downloads what is new or has another
+/// ETag since the last one, asking with `If-None-Match` where the listing
+/// gives no ETag.
+pub struct Share {
+    agent: Agent,
+    /// Scheme and host requests go to.
+    base: String,
+    /// Decoded path of the share, ending with a slash.
+    root: String,
+    authorization: Option<String>,
+    /// Where the share's files are mirrored.
+    pub mirror: PathBuf,
+    /// ETags of the mirrored files, by URL.
+    index: PathBuf,
+    partial: PathBuf,
+}
+
+impl Share {
+    /// Opens `webdav[s]://[user@]host[:port]/path` with the credentials in
+    /// `config`.
+    pub fn open(url: &str, config: &WebDavConfig) -> Result<Self> {
+        let (scheme, rest) = match url.split_once("://") {
+            Some(("webdav", rest)) => ("http", rest),
+            Some(("webdavs", rest)) => ("https", rest),
+            _ => bail!("{} is not a webdav:// or webdavs:// URL", url),
+        };
+        let (authority, path) = rest.split_once('/').unwrap_or((rest, ""));
+        let (user, host) = match authority.rsplit_once('@') {
+            Some((user, host)) => (Some(decode(user)), host),
+            None => (None, authority),
+        };
+        if host.is_empty() {
+            bail!("{} names no host", url);
+        }
+        let path = decode(path.trim_matches('/'));
+        let root = match path.is_empty() {
+            true => "/".to_string(),
+            false => format!("/{}/", path),
+        };
+
+        let authorization = match config.username.clone().or(user) {
+            Some(username) => {
+                let password = match &config.password {
+                    Some(password) => password.clone(),
+                    None => env::var("DEEP_ARCHIVE_WEBDAV_PASSWORD").context("Set webdav.password or $DEEP_ARCHIVE_WEBDAV_PASSWORD")?,
+                };
+                Some(format!("Basic {}", STANDARD.encode(format!("{}:{}", username, password))))
+            }
+            None => None,
+        };
+        let agent = Agent::new_with_config(
+            Agent::config_builder()
+                .http_status_as_error(false)
+                .allow_non_standard_methods(true)
+                .proxy(ureq::Proxy::try_from_env())
+                .timeout_connect(Some(Duration::from_secs(30)))
+                .tls_config(TlsConfig::builder().provider(TlsProvider::NativeTls).root_certs(RootCerts::PlatformVerifier).build())
+                .user_agent(concat!("deep-archive/", env!("CARGO_PKG_VERSION")))
+                .build(),
+        );
+        let host_dir = host.replace(':', "_");
+        Ok(Share {
+            agent,
+            base: format!("{}://{}", scheme, host),
+            mirror: config.mirror_dir.join(&host_dir).join(&path),
+            index: config.mirror_dir.join(format!("{}.json", host_dir)),
+            partial: config.mirror_dir.join(PARTIAL_DIR),
+            root,
+            authorization,
+        })
+    }
+
+    /// The URL of `path` below the share.
+    fn url(&self, path: &str) -> String {
+        format!("{}{}", self.base, encode(&format!("{}{}", self.root, path)))
+    }
+
+    /// The share's files, walking it a directory at a time, as Nextcloud
+    /// and others refuse `Depth: infinity`.
+    pub fn list(&self, retry: &RetryPolicy) -> Result<Vec<Entry>> {
+        let mut files = Vec::new();
+        let mut dirs = VecDeque::from([String::new()]);
+        while let Some(dir) = dirs.pop_front() {
+            // Servers redirect collections without the slash.
+            let url = match dir.is_empty() {
+                true => self.url(&dir),
+                false => self.url(&format!("{}/", dir)),
+            };
+            let xml = retry.run(&format!("Listing {}", url), || {
+                let response = self.request("PROPFIND", &url, &[("depth", "1".to_string()), ("content-type", "application/xml".to_string())], PROPFIND)?;
+                let mut response = check(response, &format!("Listing {}", url))?;
+                Ok(response.body_mut().with_config().limit(LISTING_LIMIT).read_to_string()?)
+            })?;
+            let (found, subdirs) = parse_listing(&xml, &self.root, &dir);
+            debug!("{}: {} files, {} directories", url, found.len(), subdirs.len());
+            files.extend(found);
+            dirs.extend(subdirs);
+        }
+        files.sort_by(|a, b| a.path.cmp(&b.path));
+        Ok(files)
+    }
+
+    /// Brings the mirror up to date and returns all the share's files in
+    /// it, with their URL, ETag and when they were downloaded as
+    /// provenance. A file that fails to download is left out of the run.
+    pub fn sync(&self, retry: &RetryPolicy) -> Result<Vec<Fetched>> {
+        let entries = self.list(retry)?;
+        info!("{}{}: {} files", self.base, self.root, entries.len());
+        let mut index: BTreeMap<String, Mirrored> = match fs::read(&self.index) {
+            Ok(json) => serde_json::from_slice(&json).with_context(|| format!("Failed to read {:?}", self.index))?,
+            Err(e) if e.kind() == io::ErrorKind::NotFound => BTreeMap::new(),
+            Err(e) => return Err(e).with_context(|| format!("Failed to read {:?}", self.index)),
+        };
+        fs::create_dir_all(&self.partial)?;
+
+        let mut fetched = Vec::new();
+        let (mut downloaded, mut failed) = (0, 0);
+        for entry in entries {
+            if pipeline::interrupted() {
+                break;
+            }
+            let url = self.url(&entry.path);
+            let local = self.mirror.join(&entry.path);
+            let known = index.get(&url).filter(|_| local.is_file());
+            let current = known.is_some_and(|known| known.etag.is_some() && known.etag == entry.etag);
+            if !current {
+                let etag = known.and_then(|known| known.etag.clone());
+                match retry.run(&format!("Downloading {}", url), || self.download(&url, &local, etag.as_deref())) {
+                    Ok(Download::Done { etag }) => {
+                        downloaded += 1;
+                        index.insert(url.clone(), Mirrored { etag: etag.or(entry.etag.clone()), retrieved_at: Utc::now().to_rfc3339() });
+                    }
+                    Ok(Download::NotModified) => {}
+                    Err(e) => {
+                        warn!("Failed to download {}: {:#}", url, e);
+                        failed += 1;
+                        continue;
+                    }
+                }
+            }
+            let Some(mirrored) = index.get(&url) else { continue };
+            let provenance = json!({
+                "url": url,
+                "etag": mirrored.etag,
+                "last_modified": entry.last_modified,
+                "retrieved_at": mirrored.retrieved_at,
+            });
+            fetched.push(Fetched { path: local, provenance });
+        }
+        let json = serde_json::to_vec_pretty(&index)?;
+        fs::write(&self.index, json).with_context(|| format!("Failed to write {:?}", self.index))?;
+        info!("Mirrored into {:?}: {} downloaded, {} up to date, {} failed", self.mirror, downloaded, fetched.len() - downloaded, failed);
+        Ok(fetched)
+    }
+
+    /// Downloads `url` to `local`, unless the server says the copy at
+    /// `etag` is current.
+    fn download(&self, url: &str, local: &Path, etag: Option<&str>) -> Result<Download> {
+        let headers: Vec<(&str, String)> = etag.map(|etag| ("if-none-match", etag.to_string())).into_iter().collect();
+        let response = self.request("GET", url, &headers, "")?;
+        if response.status() == 304 {
+            return Ok(Download::NotModified);
+        }
+        let response = check(response, &format!("Downloading {}", url))?;
+        let etag = header(&response, "etag");
+        let modified = header(&response, "last-modified").and_then(|date| DateTime::parse_from_rfc2822(&date).ok());
+
+        let name = hex::encode(Sha256::digest(url));
+        let partial = self.partial.join(format!("{}.{}", std::process::id(), &name[..16]));
+        let mut file = File::create(&partial).with_context(|| format!("Failed to create {:?}", partial))?;
+        io::copy(&mut response.into_body().into_reader(), &mut file).map_err(|e| anyhow!(Transient(e.to_string())))?;
+        // The pipeline records the file's mtime; the share's is the one
+        // that means something.
+        if let Some(modified) = modified {
+            let _ = file.set_modified(SystemTime::from(modified));
+        }
+        drop(file);
+        if let Some(parent) = local.parent() {
+            fs::create_dir_all(parent)?;
+        }
+        fs::rename(&partial, local).with_context(|| format!("Failed to move the download to {:?}", local))?;
+        debug!("Downloaded {} to {:?}", url, local);
+        Ok(Download::Done { etag })
+    }
+
+    /// Sends a request, marking network failures and busy servers as
+    /// transient for the retry policy.
+    fn request(&self, method: &str, url: &str, headers: &[(&str, String)], body: &str) -> Result<Response<Body>> {
+        let mut request = Request::builder().method(method).uri(url);
+        if let Some(authorization) = &self.authorization {
+            request = request.header("authorization", authorization);
+        }
+        for (name, value) in headers {
+            request = request.header(*name, value);
+        }
+        let result = match method {
+            "GET" => self.agent.run(request.body(())?),
+            _ => self.agent.run(request.body(body.as_bytes())?),
+        };
+        let response = result.map_err(|e| match e {
+            ureq::Error::BadUri(_) | ureq::Error::Tls(_) => anyhow!(e),
+            e => anyhow!(Transient(e.to_string())),
+        });
+        let response = response.with_context(|| format!("{} {} failed", method, url))?;
+        if response.status().is_server_error() || response.status() == 429 {
+            return Err(anyhow!(Transient(format!("{} {} answered {}", method, url, response.status()))));
+        }
+        Ok(response)
+    }
+}
+
+/// Passes on a successful response; turns any other into an error.
+fn check(response: Response<Body>, what: &str) -> Result<Response<Body>> {
+    match response.status().as_u16() {
+        200..=299 => Ok(response),
+        401 => bail!("{} failed: the share refused the user and password, or wants some (webdav.username, webdav.password)", what),
+        status => bail!("{} failed: {}", what, status),
+    }
+}
+
+fn header(response: &Response<Body>, name: &str) -> Option<String> {
+    response.headers().get(name).and_then(|v| v.to_str().ok()).map(String::from)
+}
+
+/// The files and subdirectories of `dir` in a `207 Multi-Status` listing,
+/// below `root`. Entries with `.` or `..` in their paths are left out.
+fn parse_listing(xml: &str, root: &str, dir: &str) -> (Vec<Entry>, Vec<String>) {
+    let (mut files, mut dirs) = (Vec::new(), Vec::new());
+    for response in elements(xml, "response") {
+        let Some(href) = elements(response, "href").into_iter().next() else { continue };
+        let href = decode(&unescape(href.trim()));
+        // Either a path or a whole URL.
+        let href = match href.split_once("://") {
+            Some((_, rest)) => rest.find('/').map_or("/".to_string(), |slash| rest[slash..].to_string()),
+            None => href,
+        };
+        let Some(path) = href.strip_prefix(root).or_else(|| (href == root.trim_end_matches('/')).then_some("")) else {
+            warn!("{} isn't below {}", href, root);
+            continue;
+        };
+        let path = path.trim_matches('/');
+        if path == dir.trim_matches('/') {
+            continue;
+        }
+        if path.split('/').any(|part| part.is_empty() || part == "." || part == "..") {
+            warn!("Leaving out {:?}", path);
+            continue;
+        }
+        // Properties the server has, leaving out those it answers 404 for.
+        let found: Vec<&str> = elements(response, "propstat").into_iter().filter(|propstat| elements(propstat, "status").iter().any(|status| status.contains(" 200"))).collect();
+        let property = |name: &str| found.iter().find_map(|propstat| elements(propstat, name).into_iter().next()).map(|value| unescape(value.trim()));
+        let is_dir = found.iter().any(|propstat| elements(propstat, "resourcetype").iter().any(|kind| !elements(kind, "collection").is_empty()));
+        match is_dir {
+            true => dirs.push(path.to_string()),
+            false => files.push(Entry {
+                path: path.to_string(),
+                etag: property("getetag").filter(|etag| !etag.is_empty()),
+                size: property("getcontentlength").and_then(|size| size.parse().ok()),
+                last_modified: property("getlastmodified").filter(|date| !date.is_empty()),
+            }),
+        }
+    }
+    (files, dirs)
+}
+
+/// Contents of the `<name>` elements in `xml`, whatever their namespace
+/// prefix; an empty one (`<d:collection/>`) counts with no content.
+fn elements<'a>(xml: &'a str, name: &str) -> Vec<&'a str> {
+    let mut found = Vec::new();
+    let mut rest = xml;
+    while let Some(start) = rest.find('<') {
+        rest = &rest[start + 1..];
+        let Some(end) = rest.find('>') else { break };
+        let tag = &rest[..end];
+        let qualified = tag.split(|c: char| c.is_whitespace() || c == '/').next().unwrap_or_default();
+        if tag.starts_with(['/', '?', '!']) || qualified.rsplit(':').next() != Some(name) {
+            continue;
+        }
+        rest = &rest[end + 1..];
+        if tag.ends_with('/') {
+            found.push("");
+            continue;
+        }
+        let close = format!("</{}>", qualified);
+        let Some(stop) = rest.find(&close) else { break };
+        found.push(&rest[..stop]);
+        rest = &rest[stop + close.len()..];
+    }
+    found
+}
+
+fn unescape(text: &str) -> String {
+    text.replace("&quot;", "\"").replace("&lt;", "<").replace("&gt;", ">").replace("&apos;", "'").replace("&amp;", "&")
+}
+
+/// Percent-encodes a path, keeping its slashes.
+fn encode(path: &str) -> String {
+    let mut encoded = String::with_capacity(path.len());
+    for byte in path.bytes() {
+        match byte {
+            b'A'..=b'Z' | b'a'..=b'z' | b'0'..=b'9' | b'-' | b'.' | b'_' | b'~' | b'/' => encoded.push(byte as char),
+            _ => {
+                let _ = write!(encoded, "%{:02X}", byte);
+            }
+        }
+    }
+    encoded
+}
+
+fn decode(text: &str) -> String {
+    let bytes = text.as_bytes();
+    let mut decoded = Vec::with_capacity(bytes.len());
+    let mut i = 0;
+    while i < bytes.len() {
+        let hex = (bytes[i] == b'%').then(|| text.get(i + 1..i + 3)).flatten().and_then(|hex| u8::from_str_radix(hex, 16).ok());
+        match hex {
+            Some(byte) => {
+                decoded.push(byte);
+                i += 3;
+            }
+            None => {
+                decoded.push(bytes[i]);
+                i += 1;
+            }
+        }
+    }
+    String::from_utf8_lossy(&decoded).into_owned()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// As Nextcloud answers, less some properties.
+    const LISTING: &str = r#"<?xml version="1.0"?>
+<d:multistatus xmlns:d="DAV:" xmlns:s="http://sabredav.org/ns" xmlns:oc="http://owncloud.org/ns">
+ <d:response><d:href>/remote.php/dav/files/alice/Photos/</d:href>
+  <d:propstat><d:prop><d:resourcetype><d:collection/></d:resourcetype><d:getetag>&quot;6520a1&quot;</d:getetag></d:prop><d:status>HTTP/1.1 200 OK</d:status></d:propstat>
+  <d:propstat><d:prop><d:getcontentlength/></d:prop><d:status>HTTP/1.1 404 Not Found</d:status></d:propstat>
+ </d:response>
+ <d:response><d:href>/remote.php/dav/files/alice/Photos/2024%20Trip/</d:href>
+  <d:propstat><d:prop><d:resourcetype><d:collection/></d:resourcetype></d:prop><d:status>HTTP/1.1 200 OK</d:status></d:propstat>
+ </d:response>
+ <d:response><d:href>https://cloud.example.com/remote.php/dav/files/alice/Photos/cat%20%26%20dog.jpg</d:href>
+  <d:propstat><d:prop><d:resourcetype/><d:getetag>&quot;9f3e&quot;</d:getetag><d:getcontentlength>48213</d:getcontentlength>
+   <d:getlastmodified>Sat, 05 Oct 2024 10:12:01 GMT</d:getlastmodified></d:prop><d:status>HTTP/1.1 200 OK</d:status></d:propstat>
+ </d:response>
+</d:multistatus>"#;
+
+    #[test]
+    fn test_parse_listing() {
+        let (files, dirs) = parse_listing(LISTING, "/remote.php/dav/files/alice/Photos/", "");
+        assert_eq!(dirs, ["2024 Trip"]);
+        assert_eq!(
+            files,
+            [Entry {
+                path: "cat & dog.jpg".to_string(),
+                etag: Some("\"9f3e\"".to_string()),
+                size: Some(48213),
+                last_modified: Some("Sat, 05 Oct 2024 10:12:01 GMT".to_string()),
+            }]
+        );
+    }
+
+    #[test]
+    fn test_open() -> Result<()> {
+        let config = WebDavConfig { password: Some("app-password".to_string()), ..Default::default() };
+        let share = Share::open("webdavs://alice@cloud.example.com/remote.php/dav/files/alice/My%20Photos/", &config)?;
+        assert_eq!(share.url("2024 Trip/a.jpg"), "https://cloud.example.com/remote.php/dav/files/alice/My%20Photos/2024%20Trip/a.jpg");
+        assert_eq!(share.mirror, Path::new("data/webdav/cloud.example.com/remote.php/dav/files/alice/My Photos"));
+        assert_eq!(share.authorization.as_deref(), Some("Basic YWxpY2U6YXBwLXBhc3N3b3Jk"));
+        assert!(Share::open("https://cloud.example.com/", &config).is_err());
+        Ok(())
+    }
+}
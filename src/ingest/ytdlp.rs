@@ -0,0 +1,178 @@
+use std::fs;
+use std::io::{BufRead, BufReader};
+use std::path::{Path, PathBuf};
+use std::process::{Command, Stdio};
+use std::time::Duration;
+use anyhow::{Result, Context};
+use chrono::Utc;
+use serde_json::{Map, Value, json};
+use tracing::{debug, info, warn};
+use crate::ingest::pipeline::Fetched;
+use crate::media::ffmpeg::{self, Supervised};
+use crate::utils::config::{DownloadConfig, RetryPolicy};
+use crate::utils::retry::Transient;
+
+/// Fields of yt-dlp's info JSON kept in the provenance of what it
+/// downloaded. The whole of it stays next to the download.
+const KEPT: &[&str] = &[
+    "id",
+    "extractor_key",
+    "title",
+    "uploader",
+    "uploader_id",
+    "channel",
+    "channel_id",
+    "upload_date",
+    "timestamp",
+    "duration",
+    "description",
+    "license",
+    "playlist_title",
+    "playlist_index",
+];
+
+/// What yt-dlp prints for failures that may not happen again: the site
+/// timing out, throttling or failing on its end.
+const TRANSIENT_ERRORS: &[&str] = &["timed out", "HTTP Error 429", "HTTP Error 5", "Connection reset", "Temporary failure in name resolution"];
+
+/// Downloads the video at `url`, or every one of a playlist or channel,
+/// with yt-dlp into a new run directory of the spool, returning the files
+/// and the directory. Each is saved as `<extractor>/<id>.<ext>` with its
+/// info JSON next to it, and what that says about it as provenance.
+///
+/// yt-dlp runs like ffmpeg: killed by a second Ctrl-C, or once it goes
+/// `download.ytdlp_timeout_secs` without finishing a video. A run that
+/// fails without a video, e.g. on a timeout, is tried again under `retry`;
+/// yt-dlp skips what it already downloaded.
+pub fn download(url: &str, config: &DownloadConfig, retry: &RetryPolicy) -> Result<(Vec<Fetched>, PathBuf)> {
+    let spool = config.run_dir();
+    fs::create_dir_all(&spool).with_context(|| format!("Failed to create {:?}", spool))?;
+    let files = retry.run(&format!("Downloading {}", url), || run(url, config, &spool))?;
+    info!("Downloaded {} files from {} into {:?}", files.len(), url, spool);
+    Ok((files, spool))
+}
+
+/// One run of yt-dlp, failing if it downloaded nothing.
+fn run(url: &str, config: &DownloadConfig, spool: &Path) -> Result<Vec<Fetched>> {
+    let program = &config.ytdlp_path;
+    // `--print` makes yt-dlp quiet, so stdout has only the paths; its
+    // warnings and errors still go to stderr.
+    let mut cmd = Command::new(program);
+    cmd.args(&config.ytdlp_args)
+        .arg("--paths")
+        .arg(spool)
+        .args(["--output", "%(extractor_key)s/%(id)s.%(ext)s", "--write-info-json"])
+        .args(["--no-simulate", "--print", "after_move:filepath", "--", url])
+        .stdout(Stdio::piped());
+    let timeout = (config.ytdlp_timeout_secs > 0).then(|| Duration::from_secs(config.ytdlp_timeout_secs));
+    let (mut process, stdout) = Supervised::spawn(cmd, timeout).with_context(|| format!("Failed to run {:?}. Is yt-dlp installed?", program))?;
+
+    let mut files = Vec::new();
+    let stdout = stdout.context("yt-dlp stdout was not captured")?;
+    for line in BufReader::new(stdout).lines() {
+        process.progress();
+        let path = PathBuf::from(line?);
+        let retrieved_at = Utc::now().to_rfc3339();
+        let info = read_info(&path).unwrap_or_else(|e| {
+            warn!("No info JSON for {:?}: {:#}", path, e);
+            Value::Null
+        });
+        debug!("yt-dlp downloaded {:?}", path);
+        files.push(Fetched { provenance: provenance(url, &info, retrieved_at), path });
+    }
+    let status = process.wait()?;
+    if !status.success() {
+        let error = process.exit_error(status);
+        let transient = process.timed_out() || ffmpeg::stderr_tail(&error).is_some_and(|tail| TRANSIENT_ERRORS.iter().any(|e| tail.contains(e)));
+        let message = format!("yt-dlp failed for {}", url);
+        let error = if transient { error.context(Transient(message)) } else { error.context(message) };
+        if files.is_empty() {
+            return Err(error);
+        }
+        warn!("{:#}; ingesting the {} files it downloaded", error, files.len());
+    }
+    Ok(files)
+}
+
+/// The info JSON yt-dlp wrote for the file at `path`.
+fn read_info(path: &Path) -> Result<Value> {
+    let info_path = path.with_extension("info.json");
+    let text = fs::read_to_string(&info_path).with_context(|| format!("Failed to read {:?}", info_path))?;
+    Ok(serde_json::from_str(&text)?)
+}
+
+/// The page of the download, the `requested_url` if that was another one
+/// (a playlist, say), when it was retrieved and the [`KEPT`] fields of its
+/// info JSON, `upload_date` as `YYYY-MM-DD`.
+fn provenance(url: &str, info: &Value, retrieved_at: String) -> Value {
+    let page = info.get("webpage_url").and_then(Value::as_str).unwrap_or(url);
+    let mut provenance = Map::new();
+    provenance.insert("url".to_string(), json!(page));
+    if page != url {
+        provenance.insert("requested_url".to_string(), json!(url));
+    }
+    provenance.insert("retrieved_at".to_string(), json!(retrieved_at));
+    provenance.insert("downloader".to_string(), json!("yt-dlp"));
+    for &key in KEPT {
+        let value = match info.get(key) {
+            None | Some(Value::Null) => continue,
+            Some(Value::String(date)) if key == "upload_date" && date.len() == 8 && date.bytes().all(|b| b.is_ascii_digit()) => {
+                json!(format!("{}-{}-{}", &date[..4], &date[4..6], &date[6..]))
+            }
+            Some(value) => value.clone(),
+        };
+        provenance.insert(key.to_string(), value);
+    }
+    Value::Object(provenance)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_provenance() {
+        let info = json!({
+            "id": "dQw4w9WgXcQ",
+            "extractor_key": "Youtube",
+            "title": "Never Gonna Give You Up",
+            "uploader": "Rick Astley",
+            "upload_date": "20091025",
+            "duration": 212,
+            "channel": null,
+            "formats": [{"format_id": "18"}],
+            "webpage_url": "https://www.youtube.com/watch?v=dQw4w9WgXcQ",
+            "playlist_title": "Hits",
+        });
+        let playlist = "https://www.youtube.com/playlist?list=PL1";
+        let kept = provenance(playlist, &info, "2026-10-17T00:00:00+00:00".to_string());
+        assert_eq!(kept["url"], "https://www.youtube.com/watch?v=dQw4w9WgXcQ");
+        assert_eq!(kept["requested_url"], playlist);
+        assert_eq!(kept["upload_date"], "2009-10-25");
+        assert_eq!(kept["uploader"], "Rick Astley");
+        assert_eq!(kept["duration"], 212);
+        assert!(kept.get("channel").is_none() && kept.get("formats").is_none());
+
+        let video = "https://example.org/v/1";
+        assert_eq!(provenance(video, &Value::Null, String::new()), json!({"url": video, "retrieved_at": "", "downloader": "yt-dlp"}));
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_stalled_download_is_retried() -> Result<()> {
+        use std::os::unix::fs::PermissionsExt;
+        let dir = std::env::temp_dir().join(format!("deep_archive_ytdlp_{}", std::process::id()));
+        fs::create_dir_all(&dir)?;
+        let script = dir.join("yt-dlp");
+        fs::write(&script, "#!/bin/sh\necho run >> \"$0.runs\"\nexec sleep 5\n")?;
+        fs::set_permissions(&script, fs::Permissions::from_mode(0o755))?;
+        let config = DownloadConfig { spool_dir: dir.join("spool"), ytdlp_path: script.clone(), ytdlp_args: Vec::new(), ytdlp_timeout_secs: 1 };
+        let retry = RetryPolicy { attempts: 2, initial_backoff_ms: 1, max_backoff_ms: 1, jitter: 0.0 };
+
+        let Err(err) = download("https://example.org/v/1", &config, &retry) else { panic!("downloaded from a stalled yt-dlp") };
+        assert!(format!("{:#}", err).contains("yt-dlp made no progress for 1s"), "{:#}", err);
+        assert_eq!(fs::read_to_string(script.with_extension("runs"))?.lines().count(), 2);
+        fs::remove_dir_all(&dir)?;
+        Ok(())
+    }
+}
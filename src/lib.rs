@@ -0,0 +1,51 @@
+//! Deep Archive's media preservation pipeline as a library: scanning,
+//! hashing, ML analysis, the catalog and archive builders. The
+//! `deep-archive` command line tool (the `deep-archive-cli` crate) is a thin
+//! layer over these modules.
+//!
+//! Ingesting a directory into a SQLite catalog and querying it back:
+//!
+//! ```no_run
+//! use deep_archive::{CatalogReader, Config, FilterSet};
+//! use deep_archive::ingest::pipeline::{self, Input};
+//!
+//! # fn main() -> anyhow::Result<()> {
+//! let config = Config::default();
+//! let roots = vec!["/media/photos".to_string()];
+//! pipeline::run(Input::Directory("/media/photos".into()), roots, "{}".to_string(), "catalog.db", config.clone())?;
+//!
+//! let reader = CatalogReader::open("catalog.db", &config.database)?;
+//! for artifact in reader.find(&FilterSet::new().tag("ml:dog").limit(10)) {
+//!     println!("{}", artifact?.original_path);
+//! }
+//! # Ok(())
+//! # }
+//! ```
+//!
+//! Records produced elsewhere can be written directly through a
+//! [`CatalogStore`], e.g. one returned by [`database::store::open`].
+
+/// Archive images (ISO 9660 with Rock Ridge and Joliet) built from ingested directories.
+pub mod archive;
+/// `daemon`: watched directories ingested as files appear, with a control socket.
+pub mod daemon;
+/// The catalog: schema, SQLite/PostgreSQL stores, reader and maintenance.
+pub mod database;
+/// Scanning, hashing, volume detection and the ingest pipeline.
+pub mod ingest;
+/// Mimetypes, metadata extraction, frame decoding and previews via ffmpeg.
+pub mod media;
+/// ONNX models for NSFW scoring and tagging.
+pub mod ml;
+/// Webhooks told when runs finish, fail a lot or fill a volume.
+pub mod notify;
+/// WebAssembly plugins that filter, extract metadata from and tag files.
+pub mod plugins;
+/// The `serve` HTTP server and its REST API.
+pub mod server;
+/// Configuration and unit parsing/formatting.
+pub mod utils;
+
+pub use database::repo::{Artifact, ArtifactRecord, CatalogReader, FilterSet, ReaderPool, TransactionManager};
+pub use database::store::CatalogStore;
+pub use utils::config::Config;
@@ -0,0 +1,390 @@
+use std::fs::File;
+use std::io::BufReader;
+use std::path::Path;
+use anyhow::{Result, Context, bail};
+use serde::Serialize;
+use crate::archive::reader::{le16, le32, le64, read_at, zip_directory};
+use crate::archive::zip_builder::{CENTRAL_HEADER, LOCAL_HEADER};
+
+/// Zip's flag for an encrypted entry.
+const ENCRYPTED: u16 = 1;
+/// Zip's flag for a CRC written after the data, in which case the password
+/// check byte is the high byte of the modification time instead.
+const DATA_DESCRIPTOR: u16 = 1 << 3;
+/// The compression method of WinZip AES entries.
+const ZIP_AES: u16 = 99;
+/// Entries a password is tried on, each check byte weeding out 255 of 256
+/// wrong ones.
+const CHECKED_ENTRIES: usize = 16;
+
+/// Tag of the files of an encrypted archive none of the passwords opens.
+/// Archives whose encryption they can't be tried on aren't tagged.
+pub const LOCKED_TAG: &str = "container:locked";
+
+const SEVEN_ZIP: &[u8] = b"7z\xBC\xAF\x27\x1C";
+/// The id of 7z's AES-256 + SHA-256 coder.
+const SEVEN_ZIP_AES: [u8; 4] = [0x06, 0xF1, 0x07, 0x01];
+const RAR4: &[u8] = b"Rar!\x1A\x07\x00";
+const RAR5: &[u8] = b"Rar!\x1A\x07\x01\x00";
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Encryption {
+    None,
+    /// Encrypted, but one of `media.container_passwords` opens it.
+    Unlocked,
+    /// Encrypted, and none of the passwords opens it.
+    Locked,
+    /// Encrypted with what passwords aren't tried on: AES zips, 7z and RAR
+    /// archives. Only zip's classic encryption is tried.
+    Untried,
+    /// The 7z directory is compressed, so this can't be told without
+    /// unpacking it.
+    Unknown,
+}
+
+/// What `metadata.container` records of a zip, 7z or RAR archive.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct Container {
+    pub format: &'static str,
+    /// Files in it, when its directory can be read.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub entries: Option<u64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub encrypted_entries: Option<u64>,
+    /// Whether even the names are encrypted.
+    pub headers_encrypted: bool,
+    pub encryption: Encryption,
+}
+
+impl Container {
+    fn open(format: &'static str, entries: u64, encrypted_entries: u64, encryption: Encryption) -> Self {
+        Container { format, entries: Some(entries), encrypted_entries: Some(encrypted_entries), headers_encrypted: false, encryption }
+    }
+
+    fn sealed(format: &'static str) -> Self {
+        Container { format, entries: None, encrypted_entries: None, headers_encrypted: true, encryption: Encryption::Untried }
+    }
+}
+
+/// Reads the directory of a zip, 7z or RAR archive, trying `passwords` on
+/// what is encrypted. `None` for other types.
+pub fn inspect(path: &Path, media_type: &str, passwords: &[String]) -> Result<Option<Container>> {
+    let inspect = match media_type {
+        "application/zip" => zip,
+        "application/x-7z-compressed" => seven_zip,
+        "application/vnd.rar" | "application/x-rar-compressed" => rar,
+        _ => return Ok(None),
+    };
+    let mut file = BufReader::new(File::open(path).with_context(|| format!("Failed to open {:?}", path))?);
+    inspect(&mut file, path, passwords).map(Some)
+}
+
+fn zip(file: &mut BufReader<File>, path: &Path, passwords: &[String]) -> Result<Container> {
+    let (entries, directory) = zip_directory(file, path)?;
+    let (mut files, mut encrypted, mut aes) = (0, 0, false);
+    // Where each checked entry's local header is, and the byte its
+    // encryption header decrypts to last.
+    let mut checks = Vec::new();
+    let mut pos = 0;
+    for _ in 0..entries {
+        let Some(header) = directory.get(pos..pos + 46).filter(|h| le32(h) == CENTRAL_HEADER) else { bail!("{:?} has a broken central directory", path) };
+        let name_len = le16(&header[28..]) as usize;
+        let is_dir = directory.get(pos + 46..pos + 46 + name_len).is_some_and(|name| name.ends_with(b"/"));
+        let (flags, offset) = (le16(&header[8..]), le32(&header[42..]));
+        let check = match flags & DATA_DESCRIPTOR {
+            0 => (le32(&header[16..]) >> 24) as u8,
+            _ => (le16(&header[12..]) >> 8) as u8,
+        };
+        let method = le16(&header[10..]);
+        pos += 46 + name_len + le16(&header[30..]) as usize + le16(&header[32..]) as usize;
+        if is_dir {
+            continue;
+        }
+        files += 1;
+        if flags & ENCRYPTED == 0 {
+            continue;
+        }
+        encrypted += 1;
+        if method == ZIP_AES {
+            aes = true;
+        } else if checks.len() < CHECKED_ENTRIES && offset != 0xFFFF_FFFF {
+            checks.push((offset as u64, check));
+        }
+    }
+    if encrypted == 0 {
+        return Ok(Container::open("zip", files, 0, Encryption::None));
+    }
+
+    let mut headers = Vec::new();
+    for (offset, check) in checks {
+        let local = read_at(file, offset, 30)?;
+        if le32(&local) != LOCAL_HEADER {
+            bail!("{:?}: no local header at {}", path, offset);
+        }
+        let data = offset + 30 + le16(&local[26..]) as u64 + le16(&local[28..]) as u64;
+        headers.push((read_at(file, data, 12)?, check));
+    }
+    let opens = |password: &String| {
+        headers.iter().all(|(header, check)| {
+            let mut keys = Keys::new(password.as_bytes());
+            header.iter().map(|&byte| keys.decrypt(byte)).last() == Some(*check)
+        })
+    };
+    // A password that opens the classic entries may still not open the
+    // AES ones, which can't be checked.
+    let encryption = match (headers.is_empty(), passwords.iter().any(opens)) {
+        (false, false) => Encryption::Locked,
+        (true, _) => Encryption::Untried,
+        (false, true) if aes => Encryption::Untried,
+        (false, true) => Encryption::Unlocked,
+    };
+    Ok(Container::open("zip", files, encrypted, encryption))
+}
+
+/// The keys of the traditional PKWARE cipher, as set up by a password.
+struct Keys([u32; 3]);
+
+impl Keys {
+    fn new(password: &[u8]) -> Self {
+        let mut keys = Keys([0x1234_5678, 0x2345_6789, 0x3456_7890]);
+        for &byte in password {
+            keys.update(byte);
+        }
+        keys
+    }
+
+    fn update(&mut self, byte: u8) {
+        self.0[0] = crc32(self.0[0], byte);
+        self.0[1] = self.0[1].wrapping_add(self.0[0] & 0xFF).wrapping_mul(134_775_813).wrapping_add(1);
+        self.0[2] = crc32(self.0[2], (self.0[1] >> 24) as u8);
+    }
+
+    fn stream(&self) -> u8 {
+        let temp = (self.0[2] | 2) & 0xFFFF;
+        ((temp * (temp ^ 1)) >> 8) as u8
+    }
+
+    fn decrypt(&mut self, byte: u8) -> u8 {
+        let plain = byte ^ self.stream();
+        self.update(plain);
+        plain
+    }
+}
+
+/// One byte of CRC-32, without the inversions around a whole checksum.
+fn crc32(crc: u32, byte: u8) -> u32 {
+    let mut crc = crc ^ byte as u32;
+    for _ in 0..8 {
+        crc = (crc >> 1) ^ (0xEDB8_8320 & (crc & 1).wrapping_neg());
+    }
+    crc
+}
+
+/// Only the coders of a 7z archive can be read without LZMA: those of its
+/// files when its directory is stored as is, otherwise those it is packed
+/// with, which include AES when the names are encrypted.
+fn seven_zip(file: &mut BufReader<File>, path: &Path, _passwords: &[String]) -> Result<Container> {
+    let start = read_at(file, 0, 32)?;
+    if !start.starts_with(SEVEN_ZIP) {
+        bail!("{:?} is not a 7z archive", path);
+    }
+    let (offset, size) = (le64(&start[12..]), le64(&start[20..]));
+    if size == 0 {
+        return Ok(Container::open("7z", 0, 0, Encryption::None));
+    }
+    let header = read_at(file, 32u64.saturating_add(offset), size as usize)?;
+    let aes = header.windows(SEVEN_ZIP_AES.len()).any(|id| id == SEVEN_ZIP_AES);
+    let unlisted = |encryption| Container { format: "7z", entries: None, encrypted_entries: None, headers_encrypted: false, encryption };
+    match header.first() {
+        // Header
+        Some(0x01) if aes => Ok(unlisted(Encryption::Untried)),
+        Some(0x01) => Ok(unlisted(Encryption::None)),
+        // EncodedHeader
+        Some(0x17) if aes => Ok(Container::sealed("7z")),
+        Some(0x17) => Ok(unlisted(Encryption::Unknown)),
+        _ => bail!("{:?} has a broken 7z header", path),
+    }
+}
+
+fn rar(file: &mut BufReader<File>, path: &Path, _passwords: &[String]) -> Result<Container> {
+    let signature = read_at(file, 0, RAR4.len())?;
+    if signature != RAR4 {
+        return rar5(file, path);
+    }
+    let len = file.get_ref().metadata()?.len();
+    let (mut files, mut encrypted) = (0, 0);
+    let mut pos = RAR4.len() as u64;
+    // Blocks: CRC (2), type, flags (2), size (2), then for files and with
+    // flag 0x8000 the size of the data after the block.
+    while pos + 7 <= len {
+        let head = read_at(file, pos, 7)?;
+        let (kind, flags, size) = (head[2], le16(&head[3..]), le16(&head[5..]) as u64);
+        if size < 7 {
+            bail!("{:?} has a broken RAR block at {}", path, pos);
+        }
+        let mut data = 0;
+        if kind == 0x74 || flags & 0x8000 != 0 {
+            let block = read_at(file, pos, size.max(11) as usize)?;
+            data = le32(&block[7..]) as u64;
+            // Larger files carry the high half of their packed size.
+            if kind == 0x74 && flags & 0x100 != 0 && block.len() >= 36 {
+                data |= (le32(&block[32..]) as u64) << 32;
+            }
+        }
+        match kind {
+            0x73 if flags & 0x80 != 0 => return Ok(Container::sealed("rar")),
+            0x74 if flags & 0xE0 != 0xE0 => {
+                files += 1;
+                if flags & 0x04 != 0 {
+                    encrypted += 1;
+                }
+            }
+            0x7B => break,
+            _ => {}
+        }
+        pos = size.checked_add(data).and_then(|n| pos.checked_add(n)).with_context(|| format!("{:?} has a broken RAR block at {}", path, pos))?;
+    }
+    let encryption = if encrypted > 0 { Encryption::Untried } else { Encryption::None };
+    Ok(Container::open("rar", files, encrypted, encryption))
+}
+
+fn rar5(file: &mut BufReader<File>, path: &Path) -> Result<Container> {
+    if read_at(file, 0, RAR5.len())? != RAR5 {
+        bail!("{:?} is not a RAR archive", path);
+    }
+    let len = file.get_ref().metadata()?.len();
+    let broken = || format!("{:?} has a broken RAR header", path);
+    let (mut files, mut encrypted) = (0, 0);
+    let mut pos = RAR5.len() as u64;
+    // Headers: CRC (4), then size, type, flags, and the sizes of the extra
+    // area and the data after the header when the flags say so, all vints.
+    while pos + 5 < len {
+        let head = read_at(file, pos, (len - pos).min(7) as usize)?;
+        let mut rest = &head[4..];
+        let size = vint(&mut rest).with_context(broken)?;
+        let start = pos + (head.len() - rest.len()) as u64;
+        let header = read_at(file, start, size as usize)?;
+        let mut fields = header.as_slice();
+        let kind = vint(&mut fields).with_context(broken)?;
+        let flags = vint(&mut fields).with_context(broken)?;
+        let extra = if flags & 1 != 0 { vint(&mut fields).with_context(broken)? } else { 0 };
+        let data = if flags & 2 != 0 { vint(&mut fields).with_context(broken)? } else { 0 };
+        match kind {
+            // File, unless its own flags make it a directory.
+            2 if vint(&mut fields).with_context(broken)? & 1 == 0 => {
+                files += 1;
+                let extra = header.get(header.len().saturating_sub(extra as usize)..).unwrap_or_default();
+                if extra_records(extra).contains(&1) {
+                    encrypted += 1;
+                }
+            }
+            4 => return Ok(Container::sealed("rar")),
+            5 => break,
+            _ => {}
+        }
+        // The sizes are untrusted, and may add up past the end of a u64.
+        pos = start.checked_add(size).and_then(|end| end.checked_add(data)).with_context(broken)?;
+    }
+    let encryption = if encrypted > 0 { Encryption::Untried } else { Encryption::None };
+    Ok(Container::open("rar", files, encrypted, encryption))
+}
+
+/// The types of the records in a RAR5 extra area; 1 is file encryption.
+fn extra_records(mut extra: &[u8]) -> Vec<u64> {
+    let mut types = Vec::new();
+    while let Some(size) = vint(&mut extra) {
+        let Some(mut record) = extra.get(..size as usize) else { break };
+        extra = &extra[size as usize..];
+        if let Some(kind) = vint(&mut record) {
+            types.push(kind);
+        }
+    }
+    types
+}
+
+/// A RAR5 variable-length integer: 7 bits a byte, low bits first.
+fn vint(bytes: &mut &[u8]) -> Option<u64> {
+    let mut value = 0;
+    for (i, &byte) in bytes.iter().enumerate().take(10) {
+        value |= ((byte & 0x7F) as u64) << (7 * i);
+        if byte & 0x80 == 0 {
+            *bytes = &bytes[i + 1..];
+            return Some(value);
+        }
+    }
+    None
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// A zip of one stored, empty file, encrypted with `password` if given.
+    fn zip(password: Option<&str>) -> Vec<u8> {
+        let crc: u32 = 0xA1B2_C3D4;
+        let flags = password.map_or(0, |_| ENCRYPTED);
+        let mut data = Vec::new();
+        if let Some(password) = password {
+            let mut keys = Keys::new(password.as_bytes());
+            for plain in [7, 1, 2, 3, 4, 5, 6, 7, 8, 9, 10, (crc >> 24) as u8] {
+                data.push(plain ^ keys.stream());
+                keys.update(plain);
+            }
+        }
+        let mut zip = Vec::new();
+        zip.extend(LOCAL_HEADER.to_le_bytes());
+        zip.extend([20, 0]);
+        zip.extend(flags.to_le_bytes());
+        zip.extend([0; 6]);
+        zip.extend(crc.to_le_bytes());
+        zip.extend((data.len() as u32).to_le_bytes());
+        zip.extend(0u32.to_le_bytes());
+        zip.extend(5u16.to_le_bytes());
+        zip.extend(0u16.to_le_bytes());
+        zip.extend(b"a.txt");
+        zip.extend(&data);
+        let directory = zip.len() as u32;
+        zip.extend(CENTRAL_HEADER.to_le_bytes());
+        zip.extend([20, 0, 20, 0]);
+        zip.extend(flags.to_le_bytes());
+        zip.extend([0; 6]);
+        zip.extend(crc.to_le_bytes());
+        zip.extend((data.len() as u32).to_le_bytes());
+        zip.extend(0u32.to_le_bytes());
+        zip.extend(5u16.to_le_bytes());
+        zip.extend([0; 12]);
+        zip.extend(0u32.to_le_bytes());
+        zip.extend(b"a.txt");
+        let size = zip.len() as u32 - directory;
+        zip.extend(0x0605_4b50u32.to_le_bytes());
+        zip.extend([0, 0, 0, 0, 1, 0, 1, 0]);
+        zip.extend(size.to_le_bytes());
+        zip.extend(directory.to_le_bytes());
+        zip.extend([0, 0]);
+        zip
+    }
+
+    #[test]
+    fn test_inspect() -> Result<()> {
+        let path = std::env::temp_dir().join(format!("deep_archive_container_{}", std::process::id()));
+        let passwords = ["wrong".to_string(), "s3cret".to_string()];
+        std::fs::write(&path, zip(None))?;
+        assert_eq!(inspect(&path, "application/zip", &passwords)?, Some(Container::open("zip", 1, 0, Encryption::None)));
+        std::fs::write(&path, zip(Some("s3cret")))?;
+        assert_eq!(inspect(&path, "application/zip", &passwords)?, Some(Container::open("zip", 1, 1, Encryption::Unlocked)));
+        assert_eq!(inspect(&path, "application/zip", &passwords[..1])?.map(|c| c.encryption), Some(Encryption::Locked));
+
+        // A RAR5 archive whose headers are encrypted: signature, then an
+        // encryption header (type 4) of 3 bytes.
+        std::fs::write(&path, [RAR5, &[0, 0, 0, 0, 3, 4, 0, 0]].concat())?;
+        assert_eq!(inspect(&path, "application/vnd.rar", &passwords)?, Some(Container::sealed("rar")));
+        // A main header (type 1) claiming u64::MAX bytes of data after it.
+        std::fs::write(&path, [RAR5, &[0, 0, 0, 0, 12, 1, 2], &[0xFF; 9], &[0x01]].concat())?;
+        assert!(inspect(&path, "application/vnd.rar", &passwords).is_err());
+        assert_eq!(inspect(&path, "image/png", &passwords)?, None);
+        assert!(inspect(&path, "application/x-7z-compressed", &passwords).is_err());
+        std::fs::remove_file(&path)?;
+        Ok(())
+    }
+}
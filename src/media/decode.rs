@@ -0,0 +1,61 @@
+use std::path::Path;
+use anyhow::{Result, Context};
+use image::imageops::FilterType;
+use image::{DynamicImage, ImageBuffer, ImageReader, Rgb};
+use tracing::debug;
+use crate::media::{ffmpeg, Frames};
+use crate::utils::config::{MediaConfig, FrameSampling};
+
+/// Formats decoded in-process by the `image` crate. Everything else
+/// (video, HEIC, RAW, ...) goes through ffmpeg.
+const NATIVE_IMAGE_TYPES: &[&str] = &[
+    "image/jpeg",
+    "image/png",
+    "image/webp",
+    "image/bmp",
+    "image/tiff",
+];
+
+/// Returns the raw RGB24 frames of `path`, choosing the decoder by mimetype.
+pub fn extract_frames(path: &Path, media_type: &str, config: &MediaConfig, sampling: &FrameSampling) -> Result<Frames> {
+    if NATIVE_IMAGE_TYPES.contains(&media_type) {
+        match decode_image(path, sampling) {
+            Ok(frame) => return Ok(Box::new(std::iter::once(Ok(frame)))),
+            Err(e) => debug!("Native image decode failed for {:?}, falling back to ffmpeg: {}", path, e),
+        }
+    }
+
+    ffmpeg::extract_frames(path, config, sampling)
+}
+
+fn decode_image(path: &Path, sampling: &FrameSampling) -> Result<Vec<u8>> {
+    let image = ImageReader::open(path)
+        .with_context(|| format!("Failed to open image: {:?}", path))?
+        .with_guessed_format()?
+        .decode()
+        .with_context(|| format!("Failed to decode image: {:?}", path))?;
+
+    // Same output as ffmpeg's `scale=R:R` + rgb24: a single tightly packed frame.
+    let resized = image.resize_exact(sampling.resolution, sampling.resolution, FilterType::Triangle);
+    Ok(resized.to_rgb8().into_raw())
+}
+
+/// `path` scaled to fit a `size` pixel square: images keep their aspect
+/// ratio, anything else is the first sampled frame, squared as frames are.
+pub fn thumbnail(path: &Path, media_type: &str, size: u32, config: &MediaConfig) -> Result<DynamicImage> {
+    if media_type.starts_with("image/") {
+        // Keeps the aspect ratio; formats the image crate can't read go
+        // through ffmpeg below.
+        if let Ok(image) = ImageReader::open(path).and_then(|r| r.with_guessed_format()).map_err(anyhow::Error::from).and_then(|r| Ok(r.decode()?)) {
+            return Ok(image.thumbnail(size, size));
+        }
+    }
+    let mut sampling = config.sampling_for(media_type);
+    sampling.max_frames = Some(1);
+    sampling.resolution = size;
+    let frame = extract_frames(path, media_type, config, &sampling)?
+        .next()
+        .with_context(|| format!("No frame decoded from {:?}", path))??;
+    let image = ImageBuffer::<Rgb<u8>, Vec<u8>>::from_raw(size, size, frame).context("Decoded frame has the wrong size")?;
+    Ok(DynamicImage::ImageRgb8(image))
+}
@@ -0,0 +1,224 @@
+use std::path::Path;
+use chrono::DateTime;
+use serde_json::{json, Map, Value};
+use crate::database::repo::Artifact;
+
+/// An artifact's metadata as one object of `exiftool -json`'s array, so
+/// scripts written against exiftool can read the catalog instead: the
+/// `File` group from the catalog, the EXIF tags read at ingest under their
+/// exiftool names, what ffprobe found under the names exiftool gives it
+/// for QuickTime and Matroska files, and the tags as `Subject`, as XMP
+/// sidecars carry them. With `numeric`, values are printed as
+/// `exiftool -n` does: raw numbers instead of `12 kB` or `0:01:02`.
+///
+/// EXIF values are converted from how they were stored at ingest, so
+/// formatting of less common tags can differ from exiftool's.
+pub fn json(artifact: &Artifact, numeric: bool) -> Value {
+    let mut out = Map::new();
+    let path = Path::new(&artifact.original_path);
+    out.insert("SourceFile".to_string(), json!(artifact.original_path));
+    if let Some(name) = path.file_name() {
+        out.insert("FileName".to_string(), json!(name.to_string_lossy()));
+    }
+    if let Some(dir) = path.parent() {
+        out.insert("Directory".to_string(), json!(dir.to_string_lossy()));
+    }
+    if let Some(size) = artifact.size_bytes {
+        out.insert("FileSize".to_string(), if numeric { json!(size) } else { json!(file_size(size)) });
+    }
+    if let Some(mtime) = artifact.mtime.and_then(|t| DateTime::from_timestamp(t, 0)) {
+        out.insert("FileModifyDate".to_string(), json!(mtime.format("%Y:%m:%d %H:%M:%S+00:00").to_string()));
+    }
+    if let Some(extension) = path.extension().map(|e| e.to_string_lossy().to_lowercase()) {
+        let file_type = match extension.as_str() {
+            "jpg" | "jpeg" => "JPEG".to_string(),
+            "tif" | "tiff" => "TIFF".to_string(),
+            "mkv" => "MKV".to_string(),
+            "mov" | "qt" => "MOV".to_string(),
+            other => other.to_uppercase(),
+        };
+        out.insert("FileType".to_string(), json!(file_type));
+        out.insert("FileTypeExtension".to_string(), json!(extension));
+    }
+    out.insert("MIMEType".to_string(), json!(artifact.media_type));
+
+    let metadata = artifact.metadata.as_ref();
+    if let Some(Value::Object(exif)) = metadata.and_then(|m| m.get("exif")) {
+        for (tag, value) in exif {
+            if let Some(value) = value.as_str() {
+                out.insert(tag.clone(), exif_value(value));
+            }
+        }
+    }
+    if let Some(probe) = metadata.and_then(|m| m.get("ffprobe")) {
+        probe_fields(probe, numeric, &mut out);
+    }
+
+    if let (Some(width), Some(height)) = (artifact.width, artifact.height) {
+        out.insert("ImageWidth".to_string(), json!(width));
+        out.insert("ImageHeight".to_string(), json!(height));
+        let size = if numeric { format!("{} {}", width, height) } else { format!("{}x{}", width, height) };
+        out.insert("ImageSize".to_string(), json!(size));
+        let megapixels = (width as f64 * height as f64 / 1e6 * 1e3).round() / 1e3;
+        out.insert("Megapixels".to_string(), json!(megapixels));
+    }
+    if let Some(duration) = metadata.and_then(|m| m.get("duration")).and_then(Value::as_f64) {
+        out.insert("Duration".to_string(), if numeric { json!(duration) } else { json!(duration_text(duration)) });
+    }
+    for (key, name, positive, negative) in [("gps_lat", "GPSLatitude", "N", "S"), ("gps_lon", "GPSLongitude", "E", "W")] {
+        if let Some(degrees) = metadata.and_then(|m| m.get(key)).and_then(Value::as_f64) {
+            let value = if numeric { json!(degrees) } else { json!(dms(degrees, if degrees < 0.0 { negative } else { positive })) };
+            out.insert(name.to_string(), value);
+            out.remove(&format!("{}Ref", name));
+        }
+    }
+
+    match artifact.tags.as_slice() {
+        [] => {}
+        [tag] => {
+            out.insert("Subject".to_string(), json!(tag));
+        }
+        tags => {
+            out.insert("Subject".to_string(), json!(tags));
+        }
+    }
+    Value::Object(out)
+}
+
+/// What ffprobe found, under the tag names exiftool uses for videos.
+fn probe_fields(probe: &Value, numeric: bool, out: &mut Map<String, Value>) {
+    if let Some(Value::Object(tags)) = probe.pointer("/format/tags") {
+        for (key, name) in [("title", "Title"), ("artist", "Artist"), ("comment", "Comment"), ("encoder", "Encoder")] {
+            if let Some(value) = tags.iter().find(|(k, _)| k.eq_ignore_ascii_case(key)).and_then(|(_, v)| v.as_str()) {
+                out.insert(name.to_string(), json!(value));
+            }
+        }
+        let created = tags.iter().find(|(k, _)| k.eq_ignore_ascii_case("creation_time")).and_then(|(_, v)| v.as_str());
+        if let Some(created) = created.and_then(|c| DateTime::parse_from_rfc3339(c).ok()) {
+            out.insert("CreateDate".to_string(), json!(created.format("%Y:%m:%d %H:%M:%S").to_string()));
+        }
+    }
+    let Some(streams) = probe.get("streams").and_then(Value::as_array) else {
+        return;
+    };
+    let kind = |wanted: &str| streams.iter().find(|s| s.get("codec_type").and_then(Value::as_str) == Some(wanted));
+    if let Some(video) = kind("video") {
+        if let Some(codec) = video.get("codec_tag_string").and_then(Value::as_str).filter(|c| !c.starts_with('[')) {
+            out.insert("CompressorID".to_string(), json!(codec));
+        }
+        let rate = video.get("avg_frame_rate").and_then(Value::as_str).and_then(|r| r.split_once('/'));
+        let rate = rate.and_then(|(n, d)| Some(n.parse::<f64>().ok()? / d.parse::<f64>().ok().filter(|d| *d > 0.0)?));
+        if let Some(rate) = rate {
+            let rate = if numeric { rate } else { (rate * 1000.0).round() / 1000.0 };
+            out.insert("VideoFrameRate".to_string(), json!(rate));
+        }
+    }
+    if let Some(audio) = kind("audio") {
+        if let Some(channels) = audio.get("channels").and_then(Value::as_u64) {
+            out.insert("AudioChannels".to_string(), json!(channels));
+        }
+        if let Some(rate) = audio.get("sample_rate").and_then(Value::as_str).and_then(|r| r.parse::<u64>().ok()) {
+            out.insert("AudioSampleRate".to_string(), json!(rate));
+        }
+    }
+}
+
+/// An EXIF value as stored at ingest, in exiftool's form: numbers as
+/// numbers, dates as `YYYY:MM:DD HH:MM:SS`, strings unquoted.
+fn exif_value(value: &str) -> Value {
+    let value = value.strip_prefix('"').and_then(|v| v.strip_suffix('"')).unwrap_or(value);
+    let value = value.strip_prefix("f/").filter(|v| v.parse::<f64>().is_ok()).unwrap_or(value);
+    if let Ok(number) = value.parse::<i64>() {
+        return json!(number);
+    }
+    if let Some(number) = value.parse::<f64>().ok().filter(|n| n.is_finite()) {
+        return json!(number);
+    }
+    let bytes = value.as_bytes();
+    if bytes.len() == 19 && bytes[4] == b'-' && bytes[7] == b'-' && bytes[10] == b' ' && value.is_char_boundary(10) {
+        return json!(format!("{}:{}:{}{}", &value[..4], &value[5..7], &value[8..10], &value[10..]));
+    }
+    json!(value)
+}
+
+/// `FileSize` as exiftool prints it.
+fn file_size(bytes: u64) -> String {
+    let b = bytes as f64;
+    match bytes {
+        0..2000 => format!("{} bytes", bytes),
+        2000..10_000 => format!("{:.1} kB", b / 1e3),
+        10_000..2_000_000 => format!("{:.0} kB", b / 1e3),
+        2_000_000..10_000_000 => format!("{:.1} MB", b / 1e6),
+        10_000_000..2_000_000_000 => format!("{:.0} MB", b / 1e6),
+        2_000_000_000..10_000_000_000 => format!("{:.1} GB", b / 1e9),
+        _ => format!("{:.0} GB", b / 1e9),
+    }
+}
+
+/// `Duration` as exiftool prints it: seconds under half a minute, else
+/// `H:MM:SS`.
+fn duration_text(secs: f64) -> String {
+    if secs < 30.0 {
+        return format!("{:.2} s", secs);
+    }
+    let secs = secs.round() as u64;
+    format!("{}:{:02}:{:02}", secs / 3600, secs % 3600 / 60, secs % 60)
+}
+
+/// Decimal degrees as exiftool prints coordinates, e.g. `51 deg 30' 26.00" N`.
+fn dms(degrees: f64, hemisphere: &str) -> String {
+    let degrees = degrees.abs();
+    let whole = degrees.trunc();
+    let minutes = (degrees - whole) * 60.0;
+    let seconds = (minutes - minutes.trunc()) * 60.0;
+    format!("{} deg {}' {:.2}\" {}", whole, minutes.trunc(), seconds, hemisphere)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_json() {
+        let artifact = Artifact {
+            id: 1,
+            hash_sha256: "ab".repeat(32),
+            original_path: "/photos/2019/beach.JPG".to_string(),
+            media_type: "image/jpeg".to_string(),
+            size_bytes: Some(2_345_678),
+            mtime: Some(1_600_000_000),
+            width: Some(4000),
+            height: Some(3000),
+            nsfw_score: None,
+            nsfw_model_version: None,
+            tags: vec!["ml:beach".to_string()],
+            metadata: Some(json!({
+                "gps_lat": -33.8568,
+                "exif": {"Model": "\"Canon EOS R\"", "DateTimeOriginal": "2019-07-14 10:00:00", "FNumber": "f/2.8", "ISOSpeed": "100"},
+            })),
+            deleted_at: None,
+            deleted_reason: None,
+        };
+        let out = json(&artifact, false);
+        assert_eq!(out["SourceFile"], "/photos/2019/beach.JPG");
+        assert_eq!(out["FileName"], "beach.JPG");
+        assert_eq!(out["Directory"], "/photos/2019");
+        assert_eq!(out["FileSize"], "2.3 MB");
+        assert_eq!(out["FileModifyDate"], "2020:09:13 12:26:40+00:00");
+        assert_eq!(out["FileType"], "JPEG");
+        assert_eq!(out["Model"], "Canon EOS R");
+        assert_eq!(out["DateTimeOriginal"], "2019:07:14 10:00:00");
+        assert_eq!(out["FNumber"], 2.8);
+        assert_eq!(out["ISOSpeed"], 100);
+        assert_eq!(out["ImageSize"], "4000x3000");
+        assert_eq!(out["Megapixels"], 12.0);
+        assert_eq!(out["GPSLatitude"], "33 deg 51' 24.48\" S");
+        assert_eq!(out["Subject"], "ml:beach");
+
+        let out = json(&artifact, true);
+        assert_eq!(out["FileSize"], 2_345_678);
+        assert_eq!(out["GPSLatitude"], -33.8568);
+        assert_eq!(duration_text(62.4), "0:01:02");
+        assert_eq!(duration_text(12.345), "12.35 s");
+    }
+}
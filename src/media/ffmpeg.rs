@@ -0,0 +1,475 @@
+use std::collections::VecDeque;
+use std::fmt;
+use std::io::{BufRead, BufReader, ErrorKind, Read};
+use std::path::Path;
+use std::process::{Child, ChildStderr, ChildStdout, Command, ExitStatus, Stdio};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Mutex, MutexGuard, Weak};
+use std::thread::{self, JoinHandle};
+use std::time::Duration;
+use anyhow::{Result, Context, anyhow};
+use crossbeam::channel::{bounded, RecvTimeoutError, Sender};
+use tracing::{info, warn};
+#[cfg(feature = "ffmpeg-native")]
+use tracing::debug;
+use crate::media::Frames;
+use crate::utils::config::{MediaConfig, FrameSampling, HwAccel};
+use crate::utils::retry::Transient;
+
+/// Streams frames from a video (or a single frame from an image) as raw
+/// RGB24 buffers of `sampling.frame_len()` bytes.
+///
+/// With the `ffmpeg-native` feature the in-process libav backend is tried
+/// first; the `ffmpeg` subprocess is always kept as the fallback. The
+/// native backend only decodes in software, so with a `hwaccel` set the
+/// subprocess is used straight away.
+pub fn extract_frames(path: &Path, config: &MediaConfig, sampling: &FrameSampling) -> Result<Frames> {
+    #[cfg(feature = "ffmpeg-native")]
+    if config.hwaccel == HwAccel::None {
+        let timeout = (config.timeout_secs > 0).then(|| Duration::from_secs(config.timeout_secs));
+        match crate::media::native::extract_frames(path, sampling, timeout) {
+            Ok(frames) => return Ok(Box::new(frames)),
+            Err(e) => debug!("Native decode failed for {:?}, falling back to ffmpeg subprocess: {}", path, e),
+        }
+    }
+
+    Ok(Box::new(extract_frames_subprocess(path, config, sampling)?))
+}
+
+fn extract_frames_subprocess(path: &Path, config: &MediaConfig, sampling: &FrameSampling) -> Result<FrameReader> {
+    // Command: ffmpeg [extra_args] -i input -vf fps=1/N,scale=R:R [-frames:v M] -f rawvideo -pix_fmt rgb24 -
+    let mut cmd = Command::new(&config.ffmpeg_path);
+    cmd.arg("-v")
+        .arg("error");
+
+    // Input options must precede `-i`. Decoded hardware frames are downloaded
+    // to system memory automatically because no -hwaccel_output_format is set.
+    if let Some(name) = config.hwaccel.ffmpeg_name() {
+        cmd.arg("-hwaccel").arg(name);
+        if let Some(device) = &config.hwaccel_device {
+            cmd.arg("-hwaccel_device").arg(device);
+        }
+    }
+
+    cmd.args(&config.extra_args)
+        .arg("-i")
+        .arg(path)
+        .arg("-vf")
+        .arg(format!("fps=1/{},scale={res}:{res}", sampling.interval_secs, res = sampling.resolution));
+
+    if let Some(max_frames) = sampling.max_frames {
+        cmd.arg("-frames:v").arg(max_frames.to_string());
+    }
+
+    cmd.arg("-f")
+        .arg("rawvideo")
+        .arg("-pix_fmt")
+        .arg("rgb24")
+        .arg("-")
+        .stdout(Stdio::piped());
+
+    let timeout = (config.timeout_secs > 0).then(|| Duration::from_secs(config.timeout_secs));
+    let (process, stdout) = Supervised::spawn(cmd, timeout)
+        .with_context(|| format!("Failed to execute {:?}. Is it installed?", config.ffmpeg_path))?;
+    let stdout = stdout.context("ffmpeg stdout was not captured")?;
+
+    Ok(FrameReader {
+        process,
+        stdout,
+        frame_len: sampling.frame_len(),
+        done: false,
+    })
+}
+
+/// Runs an ffmpeg command to completion under the same timeout and
+/// stderr capture as frame extraction. Used for side outputs like previews.
+pub fn run(mut cmd: Command, timeout: Option<Duration>) -> Result<()> {
+    cmd.stdout(Stdio::null());
+    let (mut process, _) = Supervised::spawn(cmd, timeout)?;
+    let status = process.wait()?;
+    if status.success() {
+        Ok(())
+    } else {
+        Err(process.exit_error(status))
+    }
+}
+
+/// Like `run`, but returns everything the command wrote to stdout (e.g. ffprobe JSON).
+pub fn output(mut cmd: Command, timeout: Option<Duration>) -> Result<Vec<u8>> {
+    cmd.stdout(Stdio::piped());
+    let (mut process, stdout) = Supervised::spawn(cmd, timeout)?;
+    let mut buf = Vec::new();
+    stdout.context("stdout was not captured")?.read_to_end(&mut buf)?;
+    let status = process.wait()?;
+    if status.success() {
+        Ok(buf)
+    } else {
+        Err(process.exit_error(status))
+    }
+}
+
+/// Every running supervised child, so they can be killed on Ctrl-C.
+static CHILDREN: Mutex<Vec<Weak<Mutex<Child>>>> = Mutex::new(Vec::new());
+
+fn register_child(child: &Arc<Mutex<Child>>) {
+    let mut children = lock(&CHILDREN);
+    children.retain(|c| c.strong_count() > 0);
+    children.push(Arc::downgrade(child));
+}
+
+/// Kills all supervised processes (ffmpeg, yt-dlp) that are still running.
+/// Safe to call from a signal handler thread.
+pub fn kill_all_children() {
+    let children = lock(&CHILDREN);
+    for child in children.iter().filter_map(Weak::upgrade) {
+        // try_lock: never block shutdown on a reader that is reaping its own child.
+        if let Ok(mut child) = child.try_lock() {
+            let _ = child.kill();
+        }
+    }
+}
+
+fn lock<T>(mutex: &Mutex<T>) -> MutexGuard<'_, T> {
+    mutex.lock().unwrap_or_else(|e| e.into_inner())
+}
+
+/// Messages ffmpeg prints for failures reading its input that may not
+/// happen again, e.g. on a network filesystem.
+const TRANSIENT_ERRORS: [&str; 5] = [
+    "Input/output error",
+    "Resource temporarily unavailable",
+    "Connection reset by peer",
+    "Connection timed out",
+    "Stale file handle",
+];
+
+/// The tail of a supervised child's stderr, under the error of a run that
+/// failed, so it can be stored apart from the message.
+#[derive(Debug)]
+pub struct Stderr(pub String);
+
+impl fmt::Display for Stderr {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str(&self.0)
+    }
+}
+
+impl std::error::Error for Stderr {}
+
+/// The stderr tail anywhere in `e`'s chain.
+pub fn stderr_tail(e: &anyhow::Error) -> Option<&str> {
+    e.chain().find_map(|cause| cause.downcast_ref::<Stderr>()).map(|stderr| stderr.0.as_str())
+}
+
+/// A registered child (ffmpeg, or yt-dlp for `ingest --ytdlp`) with stderr
+/// capture and an optional watchdog. The child is killed and reaped on drop
+/// if it is still running.
+pub struct Supervised {
+    /// The program's file name, for errors.
+    name: String,
+    child: Arc<Mutex<Child>>,
+    stderr: Option<JoinHandle<VecDeque<String>>>,
+    watchdog: Option<Sender<()>>,
+    timeout: Option<Duration>,
+    timed_out: Arc<AtomicBool>,
+}
+
+impl Supervised {
+    /// Starts `cmd` with stdin closed and stderr captured, killing it once
+    /// it goes `timeout` without [`progress`](Self::progress).
+    pub fn spawn(mut cmd: Command, timeout: Option<Duration>) -> Result<(Self, Option<ChildStdout>)> {
+        let program = Path::new(cmd.get_program());
+        let name = program.file_name().unwrap_or(program.as_os_str()).to_string_lossy().to_string();
+        let mut child = cmd
+            .stdin(Stdio::null())
+            .stderr(Stdio::piped())
+            .spawn()?;
+
+        let stdout = child.stdout.take();
+        let stderr = child.stderr.take().with_context(|| format!("{} stderr was not captured", name))?;
+
+        let child = Arc::new(Mutex::new(child));
+        register_child(&child);
+
+        let timed_out = Arc::new(AtomicBool::new(false));
+        let watchdog = timeout.map(|t| spawn_watchdog(&child, t, &timed_out));
+
+        let process = Self {
+            name,
+            child,
+            stderr: Some(spawn_stderr_collector(stderr)),
+            watchdog,
+            timeout,
+            timed_out,
+        };
+        Ok((process, stdout))
+    }
+
+    /// Restarts the watchdog's countdown, e.g. once a frame has been read,
+    /// so time spent on the frames themselves doesn't count against it.
+    pub fn progress(&self) {
+        if let Some(watchdog) = &self.watchdog {
+            let _ = watchdog.try_send(());
+        }
+    }
+
+    /// Waits for exit without holding the lock, so the watchdog can still kill a hung child.
+    pub fn wait(&self) -> std::io::Result<ExitStatus> {
+        loop {
+            if let Some(status) = lock(&self.child).try_wait()? {
+                return Ok(status);
+            }
+            thread::sleep(Duration::from_millis(10));
+        }
+    }
+
+    /// Whether the watchdog killed it.
+    pub fn timed_out(&self) -> bool {
+        self.timed_out.load(Ordering::SeqCst)
+    }
+
+    /// Builds the error for a failed run, with the tail of its stderr as
+    /// its [`Stderr`] source.
+    pub fn exit_error(&mut self, status: ExitStatus) -> anyhow::Error {
+        if self.timed_out() {
+            let secs = self.timeout.map(|t| t.as_secs()).unwrap_or_default();
+            return anyhow!("{} made no progress for {}s and was killed", self.name, secs);
+        }
+
+        let tail = self
+            .stderr
+            .take()
+            .and_then(|h| h.join().ok())
+            .unwrap_or_default();
+
+        if tail.is_empty() {
+            return anyhow!("{} exited with {}", self.name, status);
+        }
+        let transient = tail.iter().any(|line| TRANSIENT_ERRORS.iter().any(|error| line.contains(error)));
+        let lines: Vec<String> = tail.into_iter().collect();
+        let message = format!("{} exited with {}", self.name, status);
+        let stderr = anyhow::Error::new(Stderr(lines.join("\n")));
+        if transient {
+            stderr.context(Transient(message))
+        } else {
+            stderr.context(message)
+        }
+    }
+}
+
+impl Drop for Supervised {
+    fn drop(&mut self) {
+        {
+            let mut child = lock(&self.child);
+            if let Ok(None) = child.try_wait() {
+                let _ = child.kill();
+            }
+            let _ = child.wait();
+        }
+        self.watchdog.take();
+        if let Some(handle) = self.stderr.take() {
+            let _ = handle.join();
+        }
+    }
+}
+
+/// Kills the child if it is still running `timeout` after it was spawned
+/// or last made progress, as signalled on the returned sender. Dropping the
+/// sender (when the process is reaped) stops the watchdog early.
+fn spawn_watchdog(child: &Arc<Mutex<Child>>, timeout: Duration, timed_out: &Arc<AtomicBool>) -> Sender<()> {
+    let (tx, rx) = bounded::<()>(1);
+    let child = Arc::downgrade(child);
+    let timed_out = timed_out.clone();
+
+    thread::spawn(move || loop {
+        match rx.recv_timeout(timeout) {
+            Ok(()) => continue,
+            Err(RecvTimeoutError::Disconnected) => break,
+            Err(RecvTimeoutError::Timeout) => {
+                if let Some(child) = child.upgrade() {
+                    let mut child = lock(&child);
+                    if let Ok(None) = child.try_wait() {
+                        timed_out.store(true, Ordering::SeqCst);
+                        let _ = child.kill();
+                    }
+                }
+                break;
+            }
+        }
+    });
+
+    tx
+}
+
+const STDERR_MAX_LINES: usize = 20;
+const STDERR_MAX_LINE_LEN: usize = 512;
+
+/// Drains stderr on a separate thread (so a chatty ffmpeg never blocks on a
+/// full pipe) while keeping only the last few lines for error reports.
+fn spawn_stderr_collector(stderr: ChildStderr) -> JoinHandle<VecDeque<String>> {
+    thread::spawn(move || {
+        let mut tail = VecDeque::with_capacity(STDERR_MAX_LINES);
+        for line in BufReader::new(stderr).split(b'\n') {
+            let Ok(line) = line else { break };
+            let mut line = String::from_utf8_lossy(&line).trim_end().to_string();
+            if line.is_empty() {
+                continue;
+            }
+            if line.len() > STDERR_MAX_LINE_LEN {
+                let mut cut = STDERR_MAX_LINE_LEN;
+                while !line.is_char_boundary(cut) {
+                    cut -= 1;
+                }
+                line.truncate(cut);
+            }
+            if tail.len() == STDERR_MAX_LINES {
+                tail.pop_front();
+            }
+            tail.push_back(line);
+        }
+        tail
+    })
+}
+
+/// Reads one frame at a time from a running ffmpeg process.
+/// The child is killed and reaped when the reader is dropped early or the timeout expires.
+pub struct FrameReader {
+    process: Supervised,
+    stdout: ChildStdout,
+    frame_len: usize,
+    done: bool,
+}
+
+impl Iterator for FrameReader {
+    type Item = Result<Vec<u8>>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.done {
+            return None;
+        }
+
+        let mut frame = vec![0u8; self.frame_len];
+        match self.stdout.read_exact(&mut frame) {
+            Ok(()) => {
+                self.process.progress();
+                Some(Ok(frame))
+            }
+            Err(e) if e.kind() == ErrorKind::UnexpectedEof => {
+                // End of stream; any trailing partial frame is discarded.
+                self.done = true;
+                match self.process.wait() {
+                    Ok(status) if status.success() => None,
+                    Ok(status) => Some(Err(self.process.exit_error(status))),
+                    Err(e) => Some(Err(e.into())),
+                }
+            }
+            Err(e) => {
+                self.done = true;
+                Some(Err(e.into()))
+            }
+        }
+    }
+}
+
+/// Warns at startup if the configured ffmpeg/ffprobe binaries cannot be run,
+/// rather than failing once per file later on.
+pub fn check_binaries(config: &MediaConfig) {
+    for binary in [&config.ffmpeg_path, &config.ffprobe_path] {
+        let runnable = Command::new(binary)
+            .arg("-version")
+            .stdout(Stdio::null())
+            .stderr(Stdio::null())
+            .status()
+            .map(|s| s.success())
+            .unwrap_or(false);
+
+        if !runnable {
+            warn!("{:?} could not be executed; check `media` in the config file", binary);
+        }
+    }
+}
+
+/// Lists the hardware accelerators compiled into the configured ffmpeg (`ffmpeg -hwaccels`).
+pub fn probe_hwaccels(config: &MediaConfig) -> Result<Vec<String>> {
+    let output = Command::new(&config.ffmpeg_path)
+        .arg("-hide_banner")
+        .arg("-hwaccels")
+        .stdin(Stdio::null())
+        .stderr(Stdio::null())
+        .output()
+        .with_context(|| format!("Failed to execute {:?}", config.ffmpeg_path))?;
+
+    if !output.status.success() {
+        return Err(anyhow!("ffmpeg -hwaccels exited with non-zero status"));
+    }
+
+    // Output is a "Hardware acceleration methods:" header followed by one name per line.
+    Ok(String::from_utf8_lossy(&output.stdout)
+        .lines()
+        .skip(1)
+        .map(|l| l.trim().to_string())
+        .filter(|l| !l.is_empty())
+        .collect())
+}
+
+/// Resolves the configured `hwaccel` against what ffmpeg actually supports.
+/// Never returns `HwAccel::Auto`; falls back to software decoding if unavailable.
+pub fn resolve_hwaccel(config: &MediaConfig) -> HwAccel {
+    if config.hwaccel == HwAccel::None {
+        return HwAccel::None;
+    }
+
+    let available = match probe_hwaccels(config) {
+        Ok(list) => list,
+        Err(e) => {
+            warn!("Hardware acceleration probe failed, using software decoding: {}", e);
+            return HwAccel::None;
+        }
+    };
+    let supported = |h: HwAccel| h.ffmpeg_name().is_some_and(|n| available.iter().any(|a| a == n));
+
+    let resolved = match config.hwaccel {
+        HwAccel::Auto => HwAccel::PREFERRED.into_iter().find(|h| supported(*h)).unwrap_or(HwAccel::None),
+        requested if supported(requested) => requested,
+        requested => {
+            warn!("Requested hwaccel {:?} is not supported by ffmpeg (available: {:?}), using software decoding", requested, available);
+            HwAccel::None
+        }
+    };
+
+    info!("Video decoding: {}", resolved.ffmpeg_name().unwrap_or("software"));
+    resolved
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[cfg(unix)]
+    #[test]
+    fn test_watchdog_restarts_on_progress() -> Result<()> {
+        let mut sleep = Command::new("sleep");
+        sleep.arg("5");
+        let (mut process, _) = Supervised::spawn(sleep, Some(Duration::from_millis(300)))?;
+        for _ in 0..5 {
+            thread::sleep(Duration::from_millis(150));
+            process.progress();
+        }
+        assert!(lock(&process.child).try_wait()?.is_none());
+        let status = process.wait()?;
+        assert!(process.exit_error(status).to_string().contains("no progress for"));
+        Ok(())
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_exit_error_keeps_stderr() -> Result<()> {
+        let mut failing = Command::new("sh");
+        failing.args(["-c", "echo 'a.mp4: Input/output error' >&2; exit 1"]);
+        let e = run(failing, None).unwrap_err();
+        assert_eq!(e.to_string(), "sh exited with exit status: 1");
+        assert_eq!(stderr_tail(&e), Some("a.mp4: Input/output error"));
+        assert!(crate::utils::retry::is_transient(&e.context("Decoding failed")));
+        Ok(())
+    }
+}
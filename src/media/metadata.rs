@@ -0,0 +1,128 @@
+use std::fs::File;
+use std::io::BufReader;
+use std::path::Path;
+use std::process::Command;
+use std::time::Duration;
+use anyhow::{Result, Context};
+use exif::{Exif, In, Tag, Value as ExifValue};
+use serde_json::{json, Map, Value};
+use tracing::debug;
+use crate::media::{container, ffmpeg};
+use crate::utils::config::MediaConfig;
+
+/// Collects everything we know about a file beyond its bytes into one JSON object,
+/// stored in `artifacts.metadata`:
+///
+/// * `exif`, `ffprobe`, `xattr`: raw output of each extractor, when it applies
+/// * `container`: what zip, 7z and RAR archives hold and whether it is encrypted
+/// * `duration`, `camera_model`, `gps_lat`, `gps_lon`: promoted fields backing
+///   the indexed generated columns
+///
+/// Extractors that don't apply or fail are skipped; only an empty result is `None`.
+pub fn extract(path: &Path, media_type: &str, config: &MediaConfig) -> Option<Value> {
+    let mut metadata = Map::new();
+
+    if media_type.starts_with("image/") {
+        match read_exif(path) {
+            Ok(exif) => promote_exif(&exif, &mut metadata),
+            Err(e) => debug!("No EXIF data in {:?}: {}", path, e),
+        }
+    }
+
+    if media_type.starts_with("video/") || media_type.starts_with("audio/") {
+        match probe(path, config) {
+            Ok(probe) => {
+                let duration = probe
+                    .pointer("/format/duration")
+                    .and_then(Value::as_str)
+                    .and_then(|d| d.parse::<f64>().ok());
+                if let Some(duration) = duration {
+                    metadata.insert("duration".to_string(), json!(duration));
+                }
+                metadata.insert("ffprobe".to_string(), probe);
+            }
+            Err(e) => debug!("ffprobe failed for {:?}: {:#}", path, e),
+        }
+    }
+
+    match container::inspect(path, media_type, &config.container_passwords) {
+        Ok(Some(container)) => {
+            metadata.insert("container".to_string(), json!(container));
+        }
+        Ok(None) => {}
+        Err(e) => debug!("Could not read the directory of {:?}: {:#}", path, e),
+    }
+
+    match read_xattrs(path) {
+        Ok(xattrs) if !xattrs.is_empty() => {
+            metadata.insert("xattr".to_string(), Value::Object(xattrs));
+        }
+        Ok(_) => {}
+        Err(e) => debug!("Could not read xattrs of {:?}: {}", path, e),
+    }
+
+    (!metadata.is_empty()).then_some(Value::Object(metadata))
+}
+
+fn read_exif(path: &Path) -> Result<Exif> {
+    let mut reader = BufReader::new(File::open(path)?);
+    Ok(exif::Reader::new().read_from_container(&mut reader)?)
+}
+
+fn promote_exif(exif: &Exif, metadata: &mut Map<String, Value>) {
+    // Thumbnail IFDs repeat most tags, so only the primary image is kept.
+    let fields: Map<String, Value> = exif
+        .fields()
+        .filter(|f| f.ifd_num == In::PRIMARY)
+        .map(|f| (f.tag.to_string(), json!(f.display_value().with_unit(exif).to_string())))
+        .collect();
+
+    if let Some(field) = exif.get_field(Tag::Model, In::PRIMARY) {
+        let model = field.display_value().to_string();
+        metadata.insert("camera_model".to_string(), json!(model.trim_matches('"').trim()));
+    }
+    if let Some(lat) = gps_coordinate(exif, Tag::GPSLatitude, Tag::GPSLatitudeRef, "S") {
+        metadata.insert("gps_lat".to_string(), json!(lat));
+    }
+    if let Some(lon) = gps_coordinate(exif, Tag::GPSLongitude, Tag::GPSLongitudeRef, "W") {
+        metadata.insert("gps_lon".to_string(), json!(lon));
+    }
+
+    metadata.insert("exif".to_string(), Value::Object(fields));
+}
+
+/// Converts degrees/minutes/seconds rationals to signed decimal degrees.
+fn gps_coordinate(exif: &Exif, tag: Tag, ref_tag: Tag, negative_ref: &str) -> Option<f64> {
+    let ExifValue::Rational(ref dms) = exif.get_field(tag, In::PRIMARY)?.value else {
+        return None;
+    };
+    let degrees = dms.iter().zip([1.0, 60.0, 3600.0]).map(|(r, div)| r.to_f64() / div).sum::<f64>();
+
+    let negative = exif
+        .get_field(ref_tag, In::PRIMARY)
+        .is_some_and(|f| f.display_value().to_string().contains(negative_ref));
+    Some(if negative { -degrees } else { degrees })
+}
+
+fn probe(path: &Path, config: &MediaConfig) -> Result<Value> {
+    let mut cmd = Command::new(&config.ffprobe_path);
+    cmd.args(["-v", "error", "-print_format", "json", "-show_format", "-show_streams"])
+        .arg(path);
+
+    let timeout = (config.timeout_secs > 0).then(|| Duration::from_secs(config.timeout_secs));
+    let stdout = ffmpeg::output(cmd, timeout)?;
+    serde_json::from_slice(&stdout).context("ffprobe returned invalid JSON")
+}
+
+fn read_xattrs(path: &Path) -> std::io::Result<Map<String, Value>> {
+    let mut xattrs = Map::new();
+    if !xattr::SUPPORTED_PLATFORM {
+        return Ok(xattrs);
+    }
+    for name in xattr::list(path)? {
+        if let Some(value) = xattr::get(path, &name)? {
+            xattrs.insert(name.to_string_lossy().to_string(), json!(String::from_utf8_lossy(&value)));
+        }
+    }
+    Ok(xattrs)
+}
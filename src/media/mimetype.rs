@@ -0,0 +1,14 @@
+use std::path::Path;
+use anyhow::{Result, Context};
+
+pub fn detect_mimetype(path: &Path) -> Result<String> {
+    // `infer` only reads the magic bytes at the start of the file,
+    // so this stays cheap even for multi-GB videos.
+    let kind = infer::get_from_path(path)
+        .with_context(|| format!("Failed to read file header: {:?}", path))?;
+
+    match kind {
+        Some(k) => Ok(k.mime_type().to_string()),
+        None => Ok("application/octet-stream".to_string()),
+    }
+}
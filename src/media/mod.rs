@@ -0,0 +1,12 @@
+pub mod container;
+pub mod decode;
+pub mod exiftool;
+pub mod ffmpeg;
+pub mod metadata;
+pub mod mimetype;
+pub mod preview;
+#[cfg(feature = "ffmpeg-native")]
+pub mod native;
+
+/// A lazily decoded sequence of raw RGB24 frames, yielded one buffer at a time.
+pub type Frames = Box<dyn Iterator<Item = anyhow::Result<Vec<u8>>>>;
@@ -0,0 +1,164 @@
+use std::path::Path;
+use std::sync::Once;
+use std::time::{Duration, Instant};
+use anyhow::{Result, anyhow};
+use ffmpeg_next as ffmpeg;
+use ffmpeg::format::Pixel;
+use ffmpeg::media::Type;
+use ffmpeg::software::scaling::{context::Context as Scaler, flag::Flags};
+use ffmpeg::util::frame::video::Video;
+use ffmpeg::Packet;
+use crate::utils::config::FrameSampling;
+
+static INIT: Once = Once::new();
+
+/// In-process equivalent of `ffmpeg -vf fps=1/N,scale=R:R -f rawvideo -pix_fmt rgb24`.
+/// Avoids a process spawn per file, which dominates runtime on large image trees.
+/// Like the subprocess, decoding fails once it goes `timeout` without a frame.
+pub fn extract_frames(path: &Path, sampling: &FrameSampling, timeout: Option<Duration>) -> Result<NativeFrames> {
+    INIT.call_once(|| {
+        let _ = ffmpeg::init();
+        ffmpeg::log::set_level(ffmpeg::log::Level::Error);
+    });
+
+    let ictx = ffmpeg::format::input(&path)?;
+    let stream = ictx
+        .streams()
+        .best(Type::Video)
+        .ok_or_else(|| anyhow!("No video stream found in {:?}", path))?;
+    let stream_index = stream.index();
+    let time_base = f64::from(stream.time_base());
+
+    let context = ffmpeg::codec::context::Context::from_parameters(stream.parameters())?;
+    let decoder = context.decoder().video()?;
+
+    let scaler = Scaler::get(
+        decoder.format(),
+        decoder.width(),
+        decoder.height(),
+        Pixel::RGB24,
+        sampling.resolution,
+        sampling.resolution,
+        Flags::BILINEAR,
+    )?;
+
+    let mut frames = NativeFrames {
+        ictx,
+        decoder,
+        scaler,
+        stream_index,
+        time_base,
+        sampling: sampling.clone(),
+        next_sample: 0.0,
+        frames: 0,
+        eof: false,
+        first: None,
+        timeout,
+        deadline: timeout.map(|t| Instant::now() + t),
+    };
+    // Decoding the first frame here lets a file libav opens but can't
+    // decode still fall back to the ffmpeg subprocess.
+    let first = frames.next().transpose()?.ok_or_else(|| anyhow!("No frames decoded from {:?}", path))?;
+    frames.first = Some(first);
+    Ok(frames)
+}
+
+/// Decodes packets on demand, yielding one sampled frame per `next()`.
+pub struct NativeFrames {
+    ictx: ffmpeg::format::context::Input,
+    decoder: ffmpeg::decoder::Video,
+    scaler: Scaler,
+    stream_index: usize,
+    time_base: f64,
+    sampling: FrameSampling,
+    next_sample: f64,
+    frames: u32,
+    eof: bool,
+    /// Decoded by `extract_frames` and not yet yielded.
+    first: Option<Vec<u8>>,
+    timeout: Option<Duration>,
+    /// When decoding gives up unless another frame has come out by then.
+    deadline: Option<Instant>,
+}
+
+impl NativeFrames {
+    fn is_full(&self) -> bool {
+        self.sampling.max_frames.is_some_and(|max| self.frames >= max)
+    }
+
+    /// Pulls decoded frames until one falls on the sampling grid.
+    fn receive(&mut self) -> Result<Option<Vec<u8>>> {
+        let mut decoded = Video::empty();
+        while self.decoder.receive_frame(&mut decoded).is_ok() {
+            // Frames without a timestamp (e.g. still images) are treated as t=0.
+            let secs = decoded.timestamp().map(|ts| ts as f64 * self.time_base).unwrap_or(0.0);
+            if secs < self.next_sample {
+                continue;
+            }
+            self.next_sample = secs + self.sampling.interval_secs;
+
+            let mut rgb = Video::empty();
+            self.scaler.run(&decoded, &mut rgb)?;
+
+            // Rows may be padded, so copy line by line to get a tightly packed buffer.
+            let stride = rgb.stride(0);
+            let row_len = self.sampling.resolution as usize * 3;
+            let data = rgb.data(0);
+            let mut frame = Vec::with_capacity(self.sampling.frame_len());
+            for y in 0..self.sampling.resolution as usize {
+                frame.extend_from_slice(&data[y * stride..y * stride + row_len]);
+            }
+            return Ok(Some(frame));
+        }
+        Ok(None)
+    }
+}
+
+impl Iterator for NativeFrames {
+    type Item = Result<Vec<u8>>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if let Some(frame) = self.first.take() {
+            return Some(Ok(frame));
+        }
+        loop {
+            if self.is_full() {
+                return None;
+            }
+            if self.deadline.is_some_and(|deadline| Instant::now() >= deadline) {
+                let secs = self.timeout.map(|t| t.as_secs()).unwrap_or_default();
+                return Some(Err(anyhow!("Native decode made no progress for {}s", secs)));
+            }
+
+            match self.receive() {
+                Ok(Some(frame)) => {
+                    self.frames += 1;
+                    self.deadline = self.timeout.map(|t| Instant::now() + t);
+                    return Some(Ok(frame));
+                }
+                Ok(None) => {}
+                Err(e) => return Some(Err(e)),
+            }
+
+            if self.eof {
+                return None;
+            }
+
+            let mut packet = Packet::empty();
+            match packet.read(&mut self.ictx) {
+                Ok(()) => {
+                    if packet.stream() == self.stream_index {
+                        if let Err(e) = self.decoder.send_packet(&packet) {
+                            return Some(Err(e.into()));
+                        }
+                    }
+                }
+                Err(ffmpeg::Error::Eof) => {
+                    self.eof = true;
+                    let _ = self.decoder.send_eof();
+                }
+                Err(e) => return Some(Err(e.into())),
+            }
+        }
+    }
+}
@@ -0,0 +1,97 @@
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::process::Command;
+use std::time::Duration;
+use anyhow::{Result, Context};
+use crate::media::ffmpeg;
+use crate::utils::config::{MediaConfig, PreviewConfig, PreviewFormat};
+
+/// Location of the cached preview for `hash`, sharded by the first two hex
+/// digits so no single directory holds millions of entries.
+pub fn preview_path(cache_dir: &Path, hash: &str, media_type: &str, format: PreviewFormat) -> PathBuf {
+    let ext = if media_type.starts_with("audio/") { "opus" } else { format.extension() };
+    cache_dir.join(&hash[..2]).join(format!("{}.{}", hash, ext))
+}
+
+/// Transcodes a small proxy clip (video) or low-bitrate preview (audio) into
+/// the cache. Returns `None` for other media types. Existing previews are reused.
+pub fn generate_preview(
+    path: &Path,
+    hash: &str,
+    media_type: &str,
+    media: &MediaConfig,
+    config: &PreviewConfig,
+) -> Result<Option<PathBuf>> {
+    let is_video = media_type.starts_with("video/");
+    let is_audio = media_type.starts_with("audio/");
+    if !is_video && !is_audio {
+        return Ok(None);
+    }
+
+    let output = preview_path(&config.cache_dir, hash, media_type, config.format);
+    if output.exists() {
+        return Ok(Some(output));
+    }
+    if let Some(parent) = output.parent() {
+        fs::create_dir_all(parent)
+            .with_context(|| format!("Failed to create preview directory: {:?}", parent))?;
+    }
+
+    // Write to a temporary name first so an interrupted transcode never
+    // leaves a truncated file that looks like a finished preview.
+    let partial = output.with_extension(format!(
+        "partial.{}",
+        output.extension().and_then(|e| e.to_str()).unwrap_or_default()
+    ));
+
+    let mut cmd = Command::new(&media.ffmpeg_path);
+    cmd.arg("-v")
+        .arg("error")
+        .arg("-y")
+        .args(&media.extra_args)
+        .arg("-i")
+        .arg(path);
+
+    if let Some(max) = config.max_duration_secs {
+        cmd.arg("-t").arg(max.to_string());
+    }
+
+    if is_audio {
+        cmd.arg("-vn")
+            .arg("-c:a").arg("libopus")
+            .arg("-b:a").arg(&config.audio_bitrate);
+    } else {
+        // -2 keeps the width even, as required by both encoders.
+        cmd.arg("-vf").arg(format!("scale=-2:'min({},ih)'", config.max_height));
+        match config.format {
+            PreviewFormat::Mp4 => {
+                cmd.arg("-c:v").arg("libx264")
+                    .arg("-preset").arg("veryfast")
+                    .arg("-crf").arg(config.crf.to_string())
+                    .arg("-pix_fmt").arg("yuv420p")
+                    .arg("-c:a").arg("aac")
+                    .arg("-b:a").arg(&config.audio_bitrate)
+                    .arg("-movflags").arg("+faststart");
+            }
+            PreviewFormat::Webm => {
+                cmd.arg("-c:v").arg("libvpx-vp9")
+                    .arg("-b:v").arg("0")
+                    .arg("-crf").arg(config.crf.to_string())
+                    .arg("-deadline").arg("realtime")
+                    .arg("-c:a").arg("libopus")
+                    .arg("-b:a").arg(&config.audio_bitrate);
+            }
+        }
+    }
+    cmd.arg(&partial);
+
+    let timeout = (config.timeout_secs > 0).then(|| Duration::from_secs(config.timeout_secs));
+    if let Err(e) = ffmpeg::run(cmd, timeout) {
+        let _ = fs::remove_file(&partial);
+        return Err(e.context(format!("Preview transcode failed for {:?}", path)));
+    }
+
+    fs::rename(&partial, &output)
+        .with_context(|| format!("Failed to move preview into place: {:?}", output))?;
+    Ok(Some(output))
+}
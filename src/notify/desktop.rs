@@ -0,0 +1,43 @@
+use std::process::Command;
+use anyhow::{Result, Context, bail};
+use serde_json::{Map, Value};
+use crate::notify::Channel;
+use crate::utils::config::{DesktopChannel, Subscription};
+
+const TITLE: &str = "Deep Archive";
+
+impl Channel for DesktopChannel {
+    fn subscription(&self) -> &Subscription {
+        &self.subscription
+    }
+
+    fn target(&self) -> String {
+        "the desktop".to_string()
+    }
+
+    fn send(&self, message: &str, _payload: &Map<String, Value>) -> Result<()> {
+        let mut command = if cfg!(target_os = "macos") {
+            let script = format!("display notification {} with title {}", quote(message), quote(TITLE));
+            let mut command = Command::new("osascript");
+            command.arg("-e").arg(script);
+            command
+        } else if cfg!(unix) {
+            let mut command = Command::new("notify-send");
+            command.args(["--app-name=deep-archive", TITLE, message]);
+            command
+        } else {
+            bail!("Desktop notifications are not supported on this platform");
+        };
+        let program = command.get_program().to_string_lossy().to_string();
+        let output = command.output().with_context(|| format!("Failed to run {}", program))?;
+        if !output.status.success() {
+            bail!("{} failed: {}", program, String::from_utf8_lossy(&output.stderr).trim());
+        }
+        Ok(())
+    }
+}
+
+/// An AppleScript string literal.
+fn quote(text: &str) -> String {
+    format!("\"{}\"", text.replace('\\', "\\\\").replace('"', "\\\""))
+}
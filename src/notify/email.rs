@@ -0,0 +1,63 @@
+use std::env;
+use std::time::Duration;
+use anyhow::{Result, Context};
+use lettre::message::header::ContentType;
+use lettre::transport::smtp::authentication::Credentials;
+use lettre::{Message, SmtpTransport, Transport};
+use serde_json::{Map, Value};
+use crate::notify::Channel;
+use crate::utils::config::{EmailChannel, SmtpSecurity, Subscription};
+
+/// How long the SMTP server may take to answer before the run moves on.
+const TIMEOUT: Duration = Duration::from_secs(10);
+
+/// Read when a channel has a `username` but no `password`.
+const PASSWORD_VAR: &str = "DEEP_ARCHIVE_SMTP_PASSWORD";
+
+impl Channel for EmailChannel {
+    fn subscription(&self) -> &Subscription {
+        &self.subscription
+    }
+
+    fn target(&self) -> String {
+        format!("{} via {}", self.to.join(", "), self.server)
+    }
+
+    /// Mails the message as the subject, and the notice's fields one per
+    /// line as the body.
+    fn send(&self, message: &str, payload: &Map<String, Value>) -> Result<()> {
+        let mut email = Message::builder()
+            .from(self.from.parse().with_context(|| format!("Invalid from address '{}'", self.from))?)
+            .subject(format!("[deep-archive] {}", message))
+            .header(ContentType::TEXT_PLAIN);
+        for to in &self.to {
+            email = email.to(to.parse().with_context(|| format!("Invalid to address '{}'", to))?);
+        }
+        let mut body = format!("{}\n\n", message);
+        for (field, value) in payload.iter().filter(|(field, _)| *field != "message") {
+            match value {
+                Value::String(s) => body.push_str(&format!("{}: {}\n", field, s)),
+                other => body.push_str(&format!("{}: {}\n", field, other)),
+            }
+        }
+        let email = email.body(body)?;
+
+        let mut transport = match self.security {
+            SmtpSecurity::Starttls => SmtpTransport::starttls_relay(&self.server)?,
+            SmtpSecurity::Tls => SmtpTransport::relay(&self.server)?,
+            SmtpSecurity::None => SmtpTransport::builder_dangerous(&self.server).port(25),
+        };
+        if let Some(port) = self.port {
+            transport = transport.port(port);
+        }
+        if let Some(username) = &self.username {
+            let password = match &self.password {
+                Some(password) => password.clone(),
+                None => env::var(PASSWORD_VAR).with_context(|| format!("No password for {}, and ${} is not set", username, PASSWORD_VAR))?,
+            };
+            transport = transport.credentials(Credentials::new(username.clone(), password));
+        }
+        transport.timeout(Some(TIMEOUT)).build().send(&email).with_context(|| format!("Sending mail through {} failed", self.server))?;
+        Ok(())
+    }
+}
@@ -0,0 +1,294 @@
+mod desktop;
+#[cfg(feature = "email")]
+mod email;
+#[cfg(feature = "webhooks")]
+mod webhook;
+
+use std::path::Path;
+use std::sync::Mutex;
+use anyhow::Result;
+use serde::Serialize;
+use serde_json::{json, Map, Value};
+use tracing::warn;
+use crate::database::repo::ArchiveVolume;
+use crate::ingest::source;
+use crate::ingest::summary::RunSummary;
+use crate::utils::config::{NoticeKind, NotifyConfig, Subscription};
+use crate::utils::units::{format_duration, format_size};
+
+/// Something worth telling whoever watches the ingest farm, sent to the
+/// configured channels: as a JSON object with its kind in `event` to
+/// webhooks, as its one-line message to the others.
+#[derive(Debug, Clone, Serialize)]
+#[serde(tag = "event", rename_all = "snake_case")]
+pub enum Notice {
+    RunFinished {
+        run_id: i64,
+        /// `completed` or `interrupted`.
+        status: String,
+        /// Files the scanner found or the run was given.
+        files: u64,
+        /// Files committed to the catalog.
+        stored: u64,
+        duplicates: u64,
+        errors: u64,
+        elapsed_secs: f64,
+    },
+    /// A run's failures reached a channel's `error_threshold`; sent once
+    /// per run and channel, while the run goes on.
+    ErrorThreshold { run_id: i64, errors: u64, threshold: u64 },
+    /// An archive volume was written and registered.
+    VolumeFinished {
+        label: String,
+        /// File name of the image or tarball, or the tape's barcode.
+        name: String,
+        format: String,
+        path: String,
+        files: u64,
+        size_bytes: u64,
+        sha256: Option<String>,
+    },
+    /// `archive` wrote all its volumes, which for a large selection on
+    /// tape or disc can take a day.
+    ArchiveFinished { volumes: u64, files: u64, size_bytes: u64, elapsed_secs: f64 },
+}
+
+impl Notice {
+    pub fn run_finished(run_id: i64, summary: &RunSummary) -> Self {
+        let files = |stage: &str| summary.stages.iter().find(|s| s.stage == stage).map_or(0, |s| s.files);
+        Notice::RunFinished {
+            run_id,
+            status: summary.status.clone(),
+            files: files("scan"),
+            stored: files("write"),
+            duplicates: summary.duplicates,
+            errors: summary.error_count(),
+            elapsed_secs: summary.elapsed_secs,
+        }
+    }
+
+    pub fn volume_finished(volume: &ArchiveVolume, path: &Path, files: u64) -> Self {
+        Notice::VolumeFinished {
+            label: volume.label.clone(),
+            name: volume.name.clone(),
+            format: volume.format.clone(),
+            path: path.to_string_lossy().to_string(),
+            files,
+            size_bytes: volume.size_bytes,
+            sha256: volume.sha256.clone(),
+        }
+    }
+
+    pub fn kind(&self) -> NoticeKind {
+        match self {
+            Notice::RunFinished { .. } => NoticeKind::RunFinished,
+            Notice::ErrorThreshold { .. } => NoticeKind::ErrorThreshold,
+            Notice::VolumeFinished { .. } => NoticeKind::VolumeFinished,
+            Notice::ArchiveFinished { .. } => NoticeKind::ArchiveFinished,
+        }
+    }
+
+    /// One line for people, e.g. `Run 12 completed on nas: 1520 files
+    /// stored, 3 errors in 2h 05m`.
+    pub fn message(&self, host: &str) -> String {
+        match self {
+            Notice::RunFinished { run_id, status, stored, errors, elapsed_secs, .. } => {
+                format!("Run {} {} on {}: {} files stored, {} errors in {}", run_id, status, host, stored, errors, format_duration(*elapsed_secs))
+            }
+            Notice::ErrorThreshold { run_id, errors, .. } => format!("Run {} on {} has failed on {} files so far", run_id, host, errors),
+            Notice::VolumeFinished { label, path, files, size_bytes, .. } => {
+                format!("Volume {} written on {} to {}: {} files, {}", label, host, path, files, format_size(*size_bytes))
+            }
+            Notice::ArchiveFinished { volumes, files, size_bytes, elapsed_secs } => format!(
+                "Archive finished on {}: {} volumes, {} files, {} in {}",
+                host,
+                volumes,
+                files,
+                format_size(*size_bytes),
+                format_duration(*elapsed_secs)
+            ),
+        }
+    }
+
+    /// The notice as sent: its fields plus `host`, `ts` (unix seconds) and
+    /// `message`.
+    pub fn payload(&self, host: &str) -> Map<String, Value> {
+        let mut payload = match serde_json::to_value(self) {
+            Ok(Value::Object(fields)) => fields,
+            _ => Map::new(),
+        };
+        payload.insert("host".to_string(), json!(host));
+        payload.insert("ts".to_string(), json!(chrono::Utc::now().timestamp()));
+        payload.insert("message".to_string(), json!(self.message(host)));
+        payload
+    }
+}
+
+/// Somewhere notices are delivered. Each kind of channel in `[notify]`
+/// implements it; a program using the library can add its own with
+/// [`Notifier::add`].
+pub trait Channel: Send + Sync {
+    /// Which events it is sent.
+    fn subscription(&self) -> &Subscription;
+
+    /// Where it delivers to, for logs, e.g. the URL.
+    fn target(&self) -> String;
+
+    /// Delivers one notice: `message` for people, `payload` its fields
+    /// as returned by [`Notice::payload`].
+    fn send(&self, message: &str, payload: &Map<String, Value>) -> Result<()>;
+}
+
+/// Sends notices to the channels in `[notify]`. Delivery is best effort:
+/// a channel that is down is logged and doesn't hold up the run for more
+/// than its timeout.
+pub struct Notifier {
+    channels: Vec<Box<dyn Channel>>,
+    host: String,
+    /// Which channels were sent `error_threshold` for the current run.
+    alerted: Mutex<Vec<bool>>,
+}
+
+impl Notifier {
+    pub fn new(config: &NotifyConfig) -> Self {
+        let mut notifier = Notifier { channels: Vec::new(), host: source::host_name(), alerted: Mutex::new(Vec::new()) };
+        let http = config.webhooks.len() + config.slack.len() + config.matrix.len();
+        if cfg!(feature = "webhooks") {
+            #[cfg(feature = "webhooks")]
+            {
+                config.webhooks.iter().for_each(|c| notifier.add(Box::new(c.clone())));
+                config.slack.iter().for_each(|c| notifier.add(Box::new(c.clone())));
+                config.matrix.iter().for_each(|c| notifier.add(Box::new(c.clone())));
+            }
+        } else if http > 0 {
+            warn!("{} webhook, Slack or Matrix channels are configured, but this build lacks the `webhooks` feature; they are sent nothing", http);
+        }
+        if cfg!(feature = "email") {
+            #[cfg(feature = "email")]
+            config.email.iter().for_each(|c| notifier.add(Box::new(c.clone())));
+        } else if !config.email.is_empty() {
+            warn!("{} email channels are configured, but this build lacks the `email` feature; they are sent nothing", config.email.len());
+        }
+        if let Some(desktop) = &config.desktop {
+            notifier.add(Box::new(desktop.clone()));
+        }
+        notifier
+    }
+
+    pub fn add(&mut self, channel: Box<dyn Channel>) {
+        self.channels.push(channel);
+        self.alerted.get_mut().unwrap().push(false);
+    }
+
+    /// Whether any channel would be sent anything.
+    pub fn is_empty(&self) -> bool {
+        self.channels.is_empty()
+    }
+
+    /// Sends `notice` to every channel that asked for its kind.
+    pub fn notify(&self, notice: &Notice) {
+        for channel in self.channels.iter().filter(|c| c.subscription().wants(notice.kind())) {
+            self.send(channel.as_ref(), notice);
+        }
+    }
+
+    /// Tells the channels whose `error_threshold` a run's `errors` have
+    /// reached, once each.
+    pub fn errors(&self, run_id: i64, errors: u64) {
+        let mut alerted = self.alerted.lock().unwrap();
+        for (channel, alerted) in self.channels.iter().zip(alerted.iter_mut()) {
+            let subscription = channel.subscription();
+            if subscription.wants(NoticeKind::ErrorThreshold) && !*alerted && errors >= subscription.error_threshold {
+                *alerted = true;
+                self.send(channel.as_ref(), &Notice::ErrorThreshold { run_id, errors, threshold: subscription.error_threshold });
+            }
+        }
+    }
+
+    fn send(&self, channel: &dyn Channel, notice: &Notice) {
+        let payload = notice.payload(&self.host);
+        if let Err(e) = channel.send(&notice.message(&self.host), &payload) {
+            warn!("Notifying {} failed: {:#}", channel.target(), e);
+        }
+    }
+}
+
+/// `template` with each `{{field}}` replaced by that field of `payload`:
+/// strings as they are (JSON-escaped, without quotes, for a JSON body),
+/// anything else as JSON, and unknown fields by nothing.
+pub fn render(template: &str, payload: &Map<String, Value>, json: bool) -> String {
+    let mut out = String::with_capacity(template.len());
+    let mut rest = template;
+    while let Some(start) = rest.find("{{") {
+        let Some(end) = rest[start..].find("}}") else {
+            break;
+        };
+        out.push_str(&rest[..start]);
+        let field = rest[start + 2..start + end].trim();
+        match payload.get(field) {
+            Some(Value::String(s)) if json => {
+                let quoted = Value::String(s.clone()).to_string();
+                out.push_str(&quoted[1..quoted.len() - 1]);
+            }
+            Some(Value::String(s)) => out.push_str(s),
+            Some(Value::Null) | None => {}
+            Some(other) => out.push_str(&other.to_string()),
+        }
+        rest = &rest[start + end + 2..];
+    }
+    out.push_str(rest);
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::Arc;
+
+    #[test]
+    fn test_render() {
+        let notice = Notice::ErrorThreshold { run_id: 7, errors: 120, threshold: 100 };
+        let payload = notice.payload("nas \"1\"");
+        assert_eq!(payload["event"], "error_threshold");
+        let body = render("{\"text\": \"{{ message }}\", \"run\": {{run_id}}, \"x\": \"{{missing}}\"}", &payload, true);
+        assert_eq!(body, "{\"text\": \"Run 7 on nas \\\"1\\\" has failed on 120 files so far\", \"run\": 7, \"x\": \"\"}");
+        assert!(serde_json::from_str::<Value>(&body).is_ok());
+        assert_eq!(render("{{host}} {{errors}}/{{threshold}} {{", &payload, false), "nas \"1\" 120/100 {{");
+    }
+
+    struct Recorder {
+        subscription: Subscription,
+        sent: Arc<Mutex<Vec<String>>>,
+    }
+
+    impl Channel for Recorder {
+        fn subscription(&self) -> &Subscription {
+            &self.subscription
+        }
+
+        fn target(&self) -> String {
+            "recorder".to_string()
+        }
+
+        fn send(&self, _message: &str, payload: &Map<String, Value>) -> Result<()> {
+            self.sent.lock().unwrap().push(payload["event"].as_str().unwrap_or_default().to_string());
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn test_subscriptions() {
+        let mut notifier = Notifier::new(&NotifyConfig::default());
+        let (all, errors) = (Arc::new(Mutex::new(Vec::new())), Arc::new(Mutex::new(Vec::new())));
+        notifier.add(Box::new(Recorder { subscription: Subscription { events: Vec::new(), error_threshold: 5 }, sent: all.clone() }));
+        let subscription = Subscription { events: vec![NoticeKind::ErrorThreshold], error_threshold: 10 };
+        notifier.add(Box::new(Recorder { subscription, sent: errors.clone() }));
+
+        for count in [4, 5, 6, 12] {
+            notifier.errors(1, count);
+        }
+        notifier.notify(&Notice::ArchiveFinished { volumes: 1, files: 2, size_bytes: 3, elapsed_secs: 4.0 });
+        assert_eq!(*all.lock().unwrap(), ["error_threshold", "archive_finished"]);
+        assert_eq!(*errors.lock().unwrap(), ["error_threshold"]);
+    }
+}
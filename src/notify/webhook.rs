@@ -0,0 +1,121 @@
+use std::env;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::OnceLock;
+use std::time::Duration;
+use anyhow::{Result, Context, bail};
+use serde_json::{json, Map, Value};
+use ureq::http::Response;
+use ureq::tls::{RootCerts, TlsConfig, TlsProvider};
+use ureq::{Agent, Body};
+use crate::notify::{render, Channel};
+use crate::utils::config::{MatrixRoom, SlackChannel, Subscription, Webhook};
+
+/// How long a channel may take to answer before the run moves on.
+const TIMEOUT: Duration = Duration::from_secs(10);
+
+/// Read when a Matrix room has no `access_token`.
+const MATRIX_TOKEN_VAR: &str = "DEEP_ARCHIVE_MATRIX_TOKEN";
+
+fn agent() -> &'static Agent {
+    static AGENT: OnceLock<Agent> = OnceLock::new();
+    AGENT.get_or_init(|| {
+        Agent::new_with_config(
+            Agent::config_builder()
+                .http_status_as_error(false)
+                .proxy(ureq::Proxy::try_from_env())
+                .timeout_global(Some(TIMEOUT))
+                .tls_config(TlsConfig::builder().provider(TlsProvider::NativeTls).root_certs(RootCerts::PlatformVerifier).build())
+                .user_agent(concat!("deep-archive/", env!("CARGO_PKG_VERSION")))
+                .build(),
+        )
+    })
+}
+
+/// Anything but a 2xx answer is an error.
+fn check(url: &str, result: Result<Response<Body>, ureq::Error>) -> Result<()> {
+    let mut response = result.with_context(|| format!("Request to {} failed", url))?;
+    if !response.status().is_success() {
+        let answer = response.body_mut().read_to_string().unwrap_or_default();
+        bail!("{} answered {}: {}", url, response.status(), answer.trim());
+    }
+    Ok(())
+}
+
+impl Channel for Webhook {
+    fn subscription(&self) -> &Subscription {
+        &self.subscription
+    }
+
+    fn target(&self) -> String {
+        self.url.clone()
+    }
+
+    fn send(&self, _message: &str, payload: &Map<String, Value>) -> Result<()> {
+        let body = match &self.template {
+            Some(template) => render(template, payload, self.content_type.contains("json")),
+            None => Value::Object(payload.clone()).to_string(),
+        };
+        let mut request = agent().post(&self.url).header("content-type", &self.content_type);
+        for (name, value) in &self.headers {
+            request = request.header(name, value);
+        }
+        check(&self.url, request.send(body))
+    }
+}
+
+impl Channel for SlackChannel {
+    fn subscription(&self) -> &Subscription {
+        &self.subscription
+    }
+
+    fn target(&self) -> String {
+        "Slack".to_string()
+    }
+
+    fn send(&self, message: &str, _payload: &Map<String, Value>) -> Result<()> {
+        // The URL holds the secret, so it stays out of the logs.
+        check("Slack", agent().post(&self.url).header("content-type", "application/json").send(json!({ "text": message }).to_string()))
+    }
+}
+
+impl Channel for MatrixRoom {
+    fn subscription(&self) -> &Subscription {
+        &self.subscription
+    }
+
+    fn target(&self) -> String {
+        format!("Matrix room {}", self.room)
+    }
+
+    fn send(&self, message: &str, _payload: &Map<String, Value>) -> Result<()> {
+        // Transaction IDs only need to be unique per access token, so the
+        // server can tell a retried request from a new message.
+        static SEQUENCE: AtomicU64 = AtomicU64::new(0);
+        let token = match &self.access_token {
+            Some(token) => token.clone(),
+            None => env::var(MATRIX_TOKEN_VAR).with_context(|| format!("No access_token for {}, and ${} is not set", self.room, MATRIX_TOKEN_VAR))?,
+        };
+        let txn = format!("deep-archive.{}.{}", chrono::Utc::now().timestamp_millis(), SEQUENCE.fetch_add(1, Ordering::Relaxed));
+        let url = format!(
+            "{}/_matrix/client/v3/rooms/{}/send/m.room.message/{}",
+            self.homeserver.trim_end_matches('/'),
+            encode(&self.room),
+            txn
+        );
+        let body = json!({ "msgtype": "m.notice", "body": message }).to_string();
+        let request = agent().put(&url).header("authorization", &format!("Bearer {}", token)).header("content-type", "application/json");
+        check(&self.target(), request.send(body))
+    }
+}
+
+/// Percent-encodes a path segment such as `!abc:matrix.org`.
+fn encode(segment: &str) -> String {
+    let mut out = String::with_capacity(segment.len());
+    for byte in segment.bytes() {
+        match byte {
+            b'A'..=b'Z' | b'a'..=b'z' | b'0'..=b'9' | b'-' | b'.' | b'_' | b'~' => out.push(byte as char),
+            _ => out.push_str(&format!("%{:02X}", byte)),
+        }
+    }
+    out
+}
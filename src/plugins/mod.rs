@@ -0,0 +1,238 @@
+#[cfg(feature = "plugins")]
+mod wasm;
+
+use std::path::Path;
+use anyhow::{Result, Context};
+use serde::Serialize;
+use serde_json::{Map, Value};
+use tracing::{debug, info, warn};
+use crate::ingest::stages::Analysis;
+use crate::utils::config::PluginsConfig;
+
+/// What a plugin can be called for: the export of its module that is
+/// called, each taking the JSON it is given as `(ptr, len)` in its memory.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Hook {
+    /// Given `{path, size, mtime}` before a file is hashed; returning 0
+    /// leaves the file out of the run.
+    Filter,
+    /// Given `{path, hash, media_type}`; answers with a JSON object stored
+    /// in the file's metadata under the plugin's name.
+    Extract,
+    /// Given the file's `path`, `hash`, `media_type`, `tags`, `nsfw_score`
+    /// and `metadata`; answers with a JSON array of strings that replaces
+    /// its tags.
+    Tag,
+}
+
+impl Hook {
+    pub fn export(self) -> &'static str {
+        match self {
+            Hook::Filter => "filter",
+            Hook::Extract => "extract",
+            Hook::Tag => "tag",
+        }
+    }
+}
+
+/// A loaded plugin. While it is called, the file at `path` is what it
+/// reads through the `deep_archive.read` import.
+pub trait Plugin: Send + Sync {
+    fn name(&self) -> &str;
+
+    /// Whether the module exports `hook`.
+    fn exports(&self, hook: Hook) -> bool;
+
+    /// Calls `filter`: whether to keep the file.
+    fn filter(&self, path: &Path, input: &[u8]) -> Result<bool>;
+
+    /// Calls `extract` or `tag` and returns the JSON answered, if any.
+    fn call(&self, hook: Hook, path: &Path, input: &[u8]) -> Result<Option<Vec<u8>>>;
+}
+
+#[derive(Serialize)]
+struct FileInfo<'a> {
+    path: &'a str,
+    size: u64,
+    mtime: Option<i64>,
+}
+
+#[derive(Serialize)]
+struct Analyzed<'a> {
+    path: &'a str,
+    hash: &'a str,
+    media_type: &'a str,
+    tags: &'a [String],
+    nsfw_score: Option<f32>,
+    metadata: &'a Option<Value>,
+}
+
+/// The plugins a run calls, in the order they were loaded.
+#[derive(Default)]
+pub struct Plugins {
+    plugins: Vec<Box<dyn Plugin>>,
+}
+
+impl Plugins {
+    /// Loads the modules in `plugins.dir`. One that doesn't compile or lacks
+    /// the exports every plugin needs fails the load.
+    pub fn load(config: &PluginsConfig) -> Result<Self> {
+        let Some(dir) = &config.dir else {
+            return Ok(Plugins::default());
+        };
+        #[cfg(feature = "plugins")]
+        let plugins = Plugins { plugins: wasm::load(dir, config)? };
+        #[cfg(not(feature = "plugins"))]
+        let plugins = {
+            if dir.is_dir() {
+                warn!("plugins.dir is set to {:?}, but this build lacks the `plugins` feature; no plugins are loaded", dir);
+            }
+            Plugins::default()
+        };
+        if !plugins.is_empty() {
+            info!("Plugins: {}", plugins.plugins.iter().map(|p| p.name()).collect::<Vec<_>>().join(", "));
+        }
+        Ok(plugins)
+    }
+
+    pub fn add(&mut self, plugin: Box<dyn Plugin>) {
+        self.plugins.push(plugin);
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.plugins.is_empty()
+    }
+
+    /// Whether any plugin exports `hook`.
+    pub fn exports(&self, hook: Hook) -> bool {
+        self.plugins.iter().any(|plugin| plugin.exports(hook))
+    }
+
+    /// Whether every filter keeps the file at `path`. A filter that fails
+    /// is logged and keeps it.
+    pub fn keep(&self, path: &Path) -> bool {
+        let filters: Vec<&dyn Plugin> = self.plugins.iter().filter(|p| p.exports(Hook::Filter)).map(|p| p.as_ref()).collect();
+        if filters.is_empty() {
+            return true;
+        }
+        let input = std::fs::metadata(path).map_err(anyhow::Error::from).and_then(|metadata| {
+            let mtime = metadata.modified().ok().and_then(|t| t.duration_since(std::time::UNIX_EPOCH).ok()).map(|d| d.as_secs() as i64);
+            Ok(serde_json::to_vec(&FileInfo { path: &path.to_string_lossy(), size: metadata.len(), mtime })?)
+        });
+        let input = match input {
+            Ok(input) => input,
+            // Left for the hasher to report.
+            Err(_) => return true,
+        };
+        for plugin in filters {
+            match plugin.filter(path, &input) {
+                Ok(true) => {}
+                Ok(false) => {
+                    debug!("{:?} left out by the {} plugin", path, plugin.name());
+                    return false;
+                }
+                Err(e) => warn!("The {} plugin failed to filter {:?}: {:#}", plugin.name(), path, e),
+            }
+        }
+        true
+    }
+
+    /// Runs the extractors, then the tag post-processors, which see what
+    /// the extractors found.
+    pub fn analyze(&self, file: &mut Analysis) -> Result<()> {
+        let path = file.path.to_string_lossy();
+        for plugin in self.plugins.iter().filter(|p| p.exports(Hook::Extract)) {
+            let input = serde_json::json!({ "path": path, "hash": file.hash, "media_type": file.media_type() });
+            let Some(answer) = plugin.call(Hook::Extract, file.path, &serde_json::to_vec(&input)?)? else {
+                continue;
+            };
+            let found: Map<String, Value> = serde_json::from_slice(&answer).with_context(|| format!("The {} plugin's extract didn't answer with a JSON object", plugin.name()))?;
+            match file.metadata.get_or_insert_with(|| Value::Object(Map::new())) {
+                Value::Object(metadata) => {
+                    metadata.insert(plugin.name().to_string(), Value::Object(found));
+                }
+                _ => warn!("The metadata of {:?} isn't an object; the {} plugin's is dropped", file.path, plugin.name()),
+            }
+        }
+        for plugin in self.plugins.iter().filter(|p| p.exports(Hook::Tag)) {
+            let input = Analyzed {
+                path: &path,
+                hash: file.hash,
+                media_type: file.media_type(),
+                tags: &file.tags,
+                nsfw_score: file.nsfw_score,
+                metadata: &file.metadata,
+            };
+            let Some(answer) = plugin.call(Hook::Tag, file.path, &serde_json::to_vec(&input)?)? else {
+                continue;
+            };
+            let tags: Vec<String> = serde_json::from_slice(&answer).with_context(|| format!("The {} plugin's tag didn't answer with a JSON array of strings", plugin.name()))?;
+            file.tags.clear();
+            for tag in tags {
+                if !tag.is_empty() && !file.tags.contains(&tag) {
+                    file.tags.push(tag);
+                }
+            }
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Keeps small files, and tags files with their size as extracted.
+    struct BySize;
+
+    impl Plugin for BySize {
+        fn name(&self) -> &str {
+            "by_size"
+        }
+
+        fn exports(&self, _hook: Hook) -> bool {
+            true
+        }
+
+        fn filter(&self, _path: &Path, input: &[u8]) -> Result<bool> {
+            let info: Value = serde_json::from_slice(input)?;
+            Ok(info["size"].as_u64().is_some_and(|size| size < 10))
+        }
+
+        fn call(&self, hook: Hook, path: &Path, input: &[u8]) -> Result<Option<Vec<u8>>> {
+            let input: Value = serde_json::from_slice(input)?;
+            let answer = match hook {
+                Hook::Extract => serde_json::json!({ "bytes": std::fs::metadata(path)?.len() }),
+                _ => {
+                    let mut tags = input["tags"].clone();
+                    let bytes = &input["metadata"]["by_size"]["bytes"];
+                    tags.as_array_mut().unwrap().push(Value::String(format!("size:{}", bytes)));
+                    tags
+                }
+            };
+            Ok(Some(serde_json::to_vec(&answer)?))
+        }
+    }
+
+    #[test]
+    fn test_analyze() -> Result<()> {
+        let path = std::env::temp_dir().join(format!("deep_archive_plugins_{}", std::process::id()));
+        std::fs::write(&path, "small")?;
+        let mut plugins = Plugins::default();
+        plugins.add(Box::new(BySize));
+        assert!(plugins.keep(&path));
+
+        let mut file = Analysis::new(&path, "ab12", Some("text/plain".to_string()));
+        file.tags.push("ml:note".to_string());
+        file.metadata = Some(serde_json::json!({ "xattr": {} }));
+        plugins.analyze(&mut file)?;
+        assert_eq!(file.tags, ["ml:note", "size:5"]);
+        assert_eq!(file.metadata.as_ref().unwrap()["by_size"]["bytes"], 5);
+        assert!(file.metadata.as_ref().unwrap().get("xattr").is_some());
+
+        std::fs::write(&path, "not so small")?;
+        assert!(!plugins.keep(&path));
+        std::fs::remove_file(&path)?;
+        Ok(())
+    }
+}
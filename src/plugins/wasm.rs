@@ -0,0 +1,222 @@
+use std::fs::File;
+use std::io::{Read, Seek, SeekFrom};
+use std::path::{Path, PathBuf};
+use anyhow::{Result, Context, anyhow, bail};
+use tracing::info;
+use wasmtime::{Caller, Config, Engine, Extern, Instance, Linker, Memory, Module, Store, StoreLimits, StoreLimitsBuilder};
+use crate::plugins::{Hook, Plugin};
+use crate::utils::config::PluginsConfig;
+
+/// Module the host functions a plugin may import are in.
+const IMPORTS: &str = "deep_archive";
+
+/// What a plugin's calls share: the file it is called for and its limits.
+struct Host {
+    plugin: String,
+    path: PathBuf,
+    /// Opened on the first `read`.
+    file: Option<File>,
+    limits: StoreLimits,
+}
+
+/// A compiled module. Each call gets an instance of its own, so plugins
+/// run on all the workers at once and keep nothing from one file to the
+/// next.
+struct WasmPlugin {
+    name: String,
+    module: Module,
+    linker: Linker<Host>,
+    hooks: Vec<Hook>,
+    fuel: u64,
+    memory: usize,
+}
+
+/// Compiles the `*.wasm` and `*.wat` modules in `dir`, in name order.
+pub(super) fn load(dir: &Path, config: &PluginsConfig) -> Result<Vec<Box<dyn Plugin>>> {
+    let mut paths: Vec<PathBuf> = std::fs::read_dir(dir)
+        .with_context(|| format!("Failed to read the plugins directory {:?}", dir))?
+        .filter_map(|entry| entry.ok().map(|entry| entry.path()))
+        .filter(|path| path.extension().is_some_and(|ext| ext == "wasm" || ext == "wat"))
+        .collect();
+    paths.sort();
+    if paths.is_empty() {
+        info!("No plugins in {:?}", dir);
+        return Ok(Vec::new());
+    }
+    let mut wasm = Config::new();
+    wasm.consume_fuel(true);
+    let engine = Engine::new(&wasm)?;
+    let linker = linker(&engine)?;
+    let mut plugins: Vec<Box<dyn Plugin>> = Vec::new();
+    for path in paths {
+        let plugin = WasmPlugin::load(&engine, &linker, &path, config).with_context(|| format!("Failed to load the plugin {:?}", path))?;
+        plugins.push(Box::new(plugin));
+    }
+    Ok(plugins)
+}
+
+/// The host functions: `read(offset: i64, ptr: i32, len: i32) -> i32` reads
+/// the file the plugin is called for into its memory, returning the bytes
+/// read, 0 at the end or -1 on failure; `log(ptr: i32, len: i32)` logs a
+/// UTF-8 message.
+fn linker(engine: &Engine) -> Result<Linker<Host>> {
+    let mut linker = Linker::new(engine);
+    linker.func_wrap(IMPORTS, "read", |mut caller: Caller<'_, Host>, offset: i64, ptr: i32, len: i32| -> i32 {
+        let Some(memory) = caller.get_export("memory").and_then(Extern::into_memory) else {
+            return -1;
+        };
+        let (data, host) = memory.data_and_store_mut(&mut caller);
+        let Some(buf) = data.get_mut(ptr as u32 as usize..).and_then(|rest| rest.get_mut(..len as u32 as usize)) else {
+            return -1;
+        };
+        let file = match &mut host.file {
+            Some(file) => file,
+            None => match File::open(&host.path) {
+                Ok(file) => host.file.insert(file),
+                Err(_) => return -1,
+            },
+        };
+        match file.seek(SeekFrom::Start(offset.max(0) as u64)).and_then(|_| file.read(buf)) {
+            Ok(read) => read as i32,
+            Err(_) => -1,
+        }
+    })?;
+    linker.func_wrap(IMPORTS, "log", |mut caller: Caller<'_, Host>, ptr: i32, len: i32| {
+        let Some(memory) = caller.get_export("memory").and_then(Extern::into_memory) else {
+            return;
+        };
+        let data = memory.data(&caller);
+        if let Some(message) = data.get(ptr as u32 as usize..).and_then(|rest| rest.get(..len as u32 as usize)) {
+            let host = caller.data();
+            info!("{} plugin on {:?}: {}", host.plugin, host.path, String::from_utf8_lossy(message));
+        }
+    })?;
+    Ok(linker)
+}
+
+impl WasmPlugin {
+    fn load(engine: &Engine, linker: &Linker<Host>, path: &Path, config: &PluginsConfig) -> Result<Self> {
+        let module = Module::from_file(engine, path)?;
+        let exports: Vec<&str> = module.exports().map(|export| export.name()).collect();
+        for needed in ["memory", "alloc"] {
+            if !exports.contains(&needed) {
+                bail!("The module doesn't export `{}`", needed);
+            }
+        }
+        let hooks: Vec<Hook> = [Hook::Filter, Hook::Extract, Hook::Tag].into_iter().filter(|hook| exports.contains(&hook.export())).collect();
+        if hooks.is_empty() {
+            bail!("The module exports none of `filter`, `extract` and `tag`");
+        }
+        let name = path.file_stem().unwrap_or_default().to_string_lossy().to_string();
+        info!("Loaded the {} plugin ({})", name, hooks.iter().map(|hook| hook.export()).collect::<Vec<_>>().join(", "));
+        Ok(WasmPlugin {
+            name,
+            module,
+            linker: linker.clone(),
+            hooks,
+            fuel: config.fuel,
+            memory: config.memory_mib as usize * 1024 * 1024,
+        })
+    }
+
+    /// A fresh instance, with `input` copied into memory it allocated.
+    /// Returns where the input is.
+    fn instantiate(&self, path: &Path, input: &[u8]) -> Result<(Store<Host>, Instance, Memory, i32)> {
+        let limits = StoreLimitsBuilder::new().memory_size(self.memory).build();
+        let mut store = Store::new(self.module.engine(), Host { plugin: self.name.clone(), path: path.to_path_buf(), file: None, limits });
+        store.limiter(|host| &mut host.limits);
+        store.set_fuel(self.fuel)?;
+        let instance = self.linker.instantiate(&mut store, &self.module)?;
+        let memory = instance.get_memory(&mut store, "memory").context("`memory` isn't a memory")?;
+        let alloc = instance.get_typed_func::<i32, i32>(&mut store, "alloc")?;
+        let len = i32::try_from(input.len())?;
+        let ptr = alloc.call(&mut store, len)?;
+        memory.write(&mut store, ptr as u32 as usize, input).context("`alloc` returned memory out of bounds")?;
+        Ok((store, instance, memory, ptr))
+    }
+}
+
+impl Plugin for WasmPlugin {
+    fn name(&self) -> &str {
+        &self.name
+    }
+
+    fn exports(&self, hook: Hook) -> bool {
+        self.hooks.contains(&hook)
+    }
+
+    fn filter(&self, path: &Path, input: &[u8]) -> Result<bool> {
+        let (mut store, instance, _, ptr) = self.instantiate(path, input)?;
+        let filter = instance.get_typed_func::<(i32, i32), i32>(&mut store, "filter")?;
+        Ok(filter.call(&mut store, (ptr, input.len() as i32))? != 0)
+    }
+
+    /// The answer is returned as `ptr << 32 | len`; 0 answers nothing.
+    fn call(&self, hook: Hook, path: &Path, input: &[u8]) -> Result<Option<Vec<u8>>> {
+        let (mut store, instance, memory, ptr) = self.instantiate(path, input)?;
+        let func = instance.get_typed_func::<(i32, i32), i64>(&mut store, hook.export())?;
+        let answer = func.call(&mut store, (ptr, input.len() as i32))? as u64;
+        if answer == 0 {
+            return Ok(None);
+        }
+        let (ptr, len) = ((answer >> 32) as usize, (answer & 0xffff_ffff) as usize);
+        let json = memory
+            .data(&store)
+            .get(ptr..)
+            .and_then(|rest| rest.get(..len))
+            .ok_or_else(|| anyhow!("`{}` answered with memory out of bounds", hook.export()))?;
+        Ok(Some(json.to_vec()))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Keeps files given less than 10 bytes of input, extracts the file's
+    /// first byte and adds a tag.
+    const PLUGIN: &str = r#"
+        (module
+          (import "deep_archive" "read" (func $read (param i64 i32 i32) (result i32)))
+          (memory (export "memory") 1)
+          (global $next (mut i32) (i32.const 1024))
+          (data (i32.const 16) "{\"first\":0}")
+          (data (i32.const 64) "[\"plugin:seen\"]")
+          (func (export "alloc") (param $len i32) (result i32)
+            (local $ptr i32)
+            (local.set $ptr (global.get $next))
+            (global.set $next (i32.add (global.get $next) (local.get $len)))
+            (local.get $ptr))
+          (func (export "filter") (param $ptr i32) (param $len i32) (result i32)
+            (i32.lt_u (local.get $len) (i32.const 10)))
+          (func (export "extract") (param $ptr i32) (param $len i32) (result i64)
+            (drop (call $read (i64.const 0) (i32.const 25) (i32.const 1)))
+            (i64.or (i64.shl (i64.const 16) (i64.const 32)) (i64.const 11)))
+          (func (export "tag") (param $ptr i32) (param $len i32) (result i64)
+            (i64.or (i64.shl (i64.const 64) (i64.const 32)) (i64.const 15))))
+    "#;
+
+    #[test]
+    fn test_plugin() -> Result<()> {
+        let dir = std::env::temp_dir().join(format!("deep_archive_wasm_{}", std::process::id()));
+        std::fs::create_dir_all(&dir)?;
+        std::fs::write(dir.join("first.wat"), PLUGIN)?;
+        std::fs::write(dir.join("notes.txt"), "7")?;
+        let plugins = load(&dir, &PluginsConfig::default())?;
+        assert_eq!(plugins.len(), 1);
+        let plugin = &plugins[0];
+        assert_eq!(plugin.name(), "first");
+        assert!(plugin.filter(&dir.join("notes.txt"), b"{}")?);
+        assert!(!plugin.filter(&dir.join("notes.txt"), br#"{"size":1234}"#)?);
+        // The byte read is written over the 0 in {"first":0}.
+        let extracted = plugin.call(Hook::Extract, &dir.join("notes.txt"), b"{}")?.unwrap();
+        assert_eq!(serde_json::from_slice::<serde_json::Value>(&extracted)?["first"], 7);
+        let tags = plugin.call(Hook::Tag, &dir.join("notes.txt"), b"{}")?.unwrap();
+        assert_eq!(tags, br#"["plugin:seen"]"#);
+
+        std::fs::write(dir.join("broken.wat"), "(module)")?;
+        assert!(load(&dir, &PluginsConfig::default()).is_err());
+        std::fs::remove_dir_all(&dir)?;
+        Ok(())
+    }
+}
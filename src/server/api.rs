@@ -0,0 +1,402 @@
+use std::fs;
+use std::io::Cursor;
+use std::path::{Path, PathBuf};
+use anyhow::{Result, Context, anyhow};
+use image::{DynamicImage, ImageFormat};
+use serde::Deserialize;
+use serde_json::json;
+use crate::database::repo::{Artifact, FilterSet, ReaderPool};
+use crate::database::store;
+use crate::media::{decode, preview};
+use crate::server::auth::{self, Auth};
+use crate::server::http::{Request, Response};
+use crate::server::runs::Runs;
+use crate::utils::config::Config;
+use crate::utils::units::{parse_date, parse_distance, parse_size};
+
+/// Artifacts per page unless `limit` says otherwise, and the most it may say.
+const PAGE_SIZE: usize = 100;
+const MAX_PAGE_SIZE: usize = 1000;
+/// Thumbnails fit in a square this large unless `size` says otherwise.
+const THUMBNAIL_SIZE: u32 = 256;
+/// The gallery served at `/`, a single page over the API.
+const UI: &str = include_str!("ui/index.html");
+
+/// The REST API: artifact search and previews, ingest runs and tag rules,
+/// as JSON under `/api`, and the web gallery at `/`. With `server.tokens`
+/// everything but the gallery page and signing in takes a token.
+pub struct Api {
+    db_path: String,
+    config: Config,
+    readers: ReaderPool,
+    runs: Runs,
+    auth: Auth,
+}
+
+#[derive(Deserialize)]
+struct LoginRequest {
+    token: String,
+}
+
+#[derive(Deserialize)]
+struct IngestRequest {
+    /// A directory to scan or a single file.
+    path: PathBuf,
+    #[serde(default)]
+    reanalyze: bool,
+}
+
+#[derive(Deserialize)]
+struct AliasRequest {
+    tag: String,
+}
+
+impl Api {
+    /// Opens the SQLite catalog at `db_path`, creating it if needed.
+    pub fn new(db_path: &str, config: Config) -> Result<Self> {
+        store::require_sqlite(db_path)?;
+        drop(store::open(db_path, &config.database)?);
+        let readers = ReaderPool::open(db_path, &config.database)?;
+        let runs = Runs::new(db_path, config.clone());
+        let auth = Auth::new(&config.server)?;
+        Ok(Api { db_path: db_path.to_string(), config, readers, runs, auth })
+    }
+
+    /// Runs started through the API, to share with the gRPC services.
+    pub fn runs(&self) -> &Runs {
+        &self.runs
+    }
+
+    pub fn readers(&self) -> &ReaderPool {
+        &self.readers
+    }
+
+    /// The tokens requests are checked against, to share with the gRPC
+    /// services.
+    pub fn auth(&self) -> &Auth {
+        &self.auth
+    }
+
+    pub fn handle(&self, request: &Request) -> Response {
+        self.route(request).unwrap_or_else(|e| Response::error(500, &format!("{:#}", e)))
+    }
+
+    fn route(&self, request: &Request) -> Result<Response> {
+        let segments = request.segments();
+        let segments: Vec<&str> = segments.iter().map(String::as_str).collect();
+        // The page holds no data, and signing in is how a browser gets a token.
+        let public = matches!((request.method.as_str(), segments.as_slice()), ("GET", [] | ["index.html"]) | ("POST", ["api", "login" | "logout"]));
+        if !public {
+            if let Err(refused) = self.auth.check(request, auth::needed(request)) {
+                return Ok(refused);
+            }
+        }
+        match (request.method.as_str(), segments.as_slice()) {
+            ("GET", [] | ["index.html"]) => Ok(Response::new(200, "text/html; charset=utf-8", UI.as_bytes().to_vec())),
+            ("POST", ["api", "login"]) => self.login(request),
+            ("POST", ["api", "logout"]) => Ok(Response::new(204, "application/json", Vec::new()).with_header("Set-Cookie", &self.cookie("", 0))),
+            ("GET", ["api", "artifacts"]) => self.artifacts(request),
+            ("GET", ["api", "artifacts", hash]) => self.artifact(hash),
+            ("GET", ["api", "artifacts", hash, "thumbnail"]) => self.thumbnail(hash, request),
+            ("GET", ["api", "artifacts", hash, "preview"]) => self.preview(hash),
+            ("PUT", ["api", "artifacts", hash, "tags", tag]) => {
+                if self.find(hash)?.is_none() {
+                    return Ok(Response::not_found());
+                }
+                if let Err(e) = store::open(&self.db_path, &self.config.database)?.add_tag(hash, tag) {
+                    return Ok(bad_request(e));
+                }
+                // Implied tags were added along with it.
+                let tags = self.find(hash)?.map(|a| a.tags).unwrap_or_default();
+                Ok(Response::json(200, &json!({ "tags": tags })))
+            }
+            ("DELETE", ["api", "artifacts", hash, "tags", tag]) => {
+                if self.find(hash)?.is_none() {
+                    return Ok(Response::not_found());
+                }
+                match store::open(&self.db_path, &self.config.database)?.remove_tag(hash, tag)? {
+                    true => Ok(Response::new(204, "application/json", Vec::new())),
+                    false => Ok(Response::error(404, &format!("Not tagged '{}'", tag))),
+                }
+            }
+            ("POST", ["api", "ingest"]) => self.ingest(request),
+            ("GET", ["api", "runs"]) => Ok(Response::json(200, &self.runs.list()?)),
+            ("GET", ["api", "runs", id]) => self.run(id),
+            ("GET", ["api", "tags"]) => {
+                let limit = match param(request, "limit") {
+                    Ok(limit) => limit.unwrap_or(PAGE_SIZE),
+                    Err(e) => return Ok(bad_request(e)),
+                };
+                Ok(Response::json(200, &self.readers.reader()?.top_tags(limit)?))
+            }
+            ("GET", ["api", "tags", "rules"]) => Ok(Response::json(200, &store::open(&self.db_path, &self.config.database)?.tag_rules()?)),
+            ("PUT", ["api", "tags", "aliases", alias]) => {
+                let body: AliasRequest = match request.json() {
+                    Ok(body) => body,
+                    Err(e) => return Ok(bad_request(e)),
+                };
+                store::open(&self.db_path, &self.config.database)?.add_alias(alias, &body.tag)?;
+                Ok(Response::json(200, &json!({ "alias": alias, "tag": body.tag })))
+            }
+            ("DELETE", ["api", "tags", "aliases", alias]) => match store::open(&self.db_path, &self.config.database)?.remove_alias(alias)? {
+                true => Ok(Response::new(204, "application/json", Vec::new())),
+                false => Ok(Response::error(404, &format!("No alias '{}'", alias))),
+            },
+            ("PUT", ["api", "tags", "implications", tag, implied]) => {
+                store::open(&self.db_path, &self.config.database)?.add_implication(tag, implied)?;
+                Ok(Response::json(200, &json!({ "tag": tag, "implied": implied })))
+            }
+            ("DELETE", ["api", "tags", "implications", tag, implied]) => {
+                match store::open(&self.db_path, &self.config.database)?.remove_implication(tag, implied)? {
+                    true => Ok(Response::new(204, "application/json", Vec::new())),
+                    false => Ok(Response::error(404, &format!("'{}' does not imply '{}'", tag, implied))),
+                }
+            }
+            _ => Ok(Response::not_found()),
+        }
+    }
+
+    /// Checks a token and keeps it in a cookie, so the gallery can send it
+    /// with its image requests.
+    fn login(&self, request: &Request) -> Result<Response> {
+        let body: LoginRequest = match request.json() {
+            Ok(body) => body,
+            Err(e) => return Ok(bad_request(e)),
+        };
+        let Some(scope) = self.auth.scope(Some(&body.token)) else {
+            return Ok(Response::error(401, "Unknown token"));
+        };
+        if !body.token.chars().all(|c| c.is_ascii_graphic() && !matches!(c, ';' | ',' | '"' | '\\')) {
+            return Ok(Response::error(400, "The token can't be kept in a cookie; send it as a bearer token"));
+        }
+        Ok(Response::json(200, &json!({ "scope": scope })).with_header("Set-Cookie", &self.cookie(&body.token, 30 * 86400)))
+    }
+
+    fn cookie(&self, token: &str, max_age: u64) -> String {
+        let secure = if self.config.server.tls.is_some() { "; Secure" } else { "" };
+        format!("{}={}; Path=/; Max-Age={}; HttpOnly; SameSite=Strict{}", auth::COOKIE, token, max_age, secure)
+    }
+
+    /// A page of matching artifacts in id order, with the total and the
+    /// cursor to pass as `cursor` for the next page.
+    fn artifacts(&self, request: &Request) -> Result<Response> {
+        let filter = match filter(request) {
+            Ok(filter) => filter,
+            Err(e) => return Ok(bad_request(e)),
+        };
+        let reader = self.readers.reader()?;
+        let total = reader.count(&FilterSet { after_id: None, limit: None, ..filter.clone() })?;
+        // One more than asked for tells whether there is a next page.
+        let limit = filter.limit.unwrap_or(PAGE_SIZE);
+        let mut artifacts = reader.find(&FilterSet { limit: Some(limit + 1), ..filter }).collect::<Result<Vec<Artifact>>>()?;
+        let next = match artifacts.len() > limit {
+            true => {
+                artifacts.truncate(limit);
+                artifacts.last().map(|a| a.id)
+            }
+            false => None,
+        };
+        Ok(Response::json(200, &json!({ "artifacts": artifacts, "total": total, "next": next })))
+    }
+
+    fn find(&self, hash: &str) -> Result<Option<Artifact>> {
+        self.readers.reader()?.find(&FilterSet::new().hash(hash).limit(1)).next().transpose()
+    }
+
+    fn artifact(&self, hash: &str) -> Result<Response> {
+        let Some(artifact) = self.find(hash)? else {
+            return Ok(Response::not_found());
+        };
+        let reader = self.readers.reader()?;
+        let paths = reader.paths(artifact.id)?;
+        let relationships = reader.relationships(artifact.id)?;
+        Ok(Response::json(200, &json!({ "artifact": artifact, "paths": paths, "relationships": relationships })))
+    }
+
+    /// A JPEG of an image, or of a video's first sampled frame, decoded
+    /// from its original path.
+    fn thumbnail(&self, hash: &str, request: &Request) -> Result<Response> {
+        let size = match param(request, "size") {
+            Ok(size) => size.unwrap_or(THUMBNAIL_SIZE).clamp(16, 1024),
+            Err(e) => return Ok(bad_request(e)),
+        };
+        let Some(artifact) = self.find(hash)? else {
+            return Ok(Response::not_found());
+        };
+        if !artifact.media_type.starts_with("image/") && !artifact.media_type.starts_with("video/") {
+            return Ok(Response::error(404, &format!("No thumbnail for {}", artifact.media_type)));
+        }
+        let image = decode::thumbnail(Path::new(&artifact.original_path), &artifact.media_type, size, &self.config.media)?;
+        let mut jpeg = Vec::new();
+        DynamicImage::ImageRgb8(image.to_rgb8()).write_to(&mut Cursor::new(&mut jpeg), ImageFormat::Jpeg)?;
+        // Content-addressed, so it never changes.
+        Ok(Response::new(200, "image/jpeg", jpeg).with_header("Cache-Control", "max-age=86400"))
+    }
+
+    /// The proxy clip or audio preview rendered during ingest, if any.
+    fn preview(&self, hash: &str) -> Result<Response> {
+        let Some(artifact) = self.find(hash)? else {
+            return Ok(Response::not_found());
+        };
+        let path = preview::preview_path(&self.config.preview.cache_dir, hash, &artifact.media_type, self.config.preview.format);
+        if !path.exists() {
+            return Ok(Response::error(404, "No preview rendered for this artifact"));
+        }
+        let content_type = match path.extension().and_then(|e| e.to_str()) {
+            Some("opus") => "audio/ogg",
+            Some("webm") => "video/webm",
+            _ => "video/mp4",
+        };
+        let body = fs::read(&path).with_context(|| format!("Failed to read preview {:?}", path))?;
+        Ok(Response::new(200, content_type, body).with_header("Cache-Control", "max-age=86400"))
+    }
+
+    /// Starts a run over a directory or file in the background and answers
+    /// with its id at once.
+    fn ingest(&self, request: &Request) -> Result<Response> {
+        let body: IngestRequest = match request.json() {
+            Ok(body) => body,
+            Err(e) => return Ok(bad_request(e)),
+        };
+        let input = match Runs::input(&body.path) {
+            Ok(input) => input,
+            Err(e) => return Ok(bad_request(e)),
+        };
+        let run_id = self.runs.start(&body.path, input, body.reanalyze, "api")?;
+        Ok(Response::json(202, &json!({ "run_id": run_id })).with_header("Location", &format!("/api/runs/{}", run_id)))
+    }
+
+    fn run(&self, id: &str) -> Result<Response> {
+        let Ok(id) = id.parse::<i64>() else {
+            return Ok(Response::error(400, &format!("Invalid run id '{}'", id)));
+        };
+        let Some(run) = self.runs.get(id)? else {
+            return Ok(Response::not_found());
+        };
+        let mut value = serde_json::to_value(&run)?;
+        if let Some(e) = self.runs.error(id) {
+            value["error"] = json!(e);
+        }
+        Ok(Response::json(200, &value))
+    }
+}
+
+fn bad_request(e: anyhow::Error) -> Response {
+    Response::error(400, &format!("{:#}", e))
+}
+
+fn param<T: std::str::FromStr>(request: &Request, name: &str) -> Result<Option<T>> {
+    request.param(name).map(|v| v.parse().map_err(|_| anyhow!("Invalid {} '{}'", name, v))).transpose()
+}
+
+/// Maps query parameters onto a filter, named as `query`'s options:
+/// `tag`, `any_tag`, `exclude_tag` and `type` repeat, sizes take units
+/// (`10MB`), dates are `YYYY-MM-DD`, and `near=LAT,LON` takes a `radius`
+/// (`5km`). `cursor` and `limit` page through the results.
+pub fn filter(request: &Request) -> Result<FilterSet> {
+    let list = |name| request.params(name).map(str::to_string).collect();
+    let coordinates = |name: &str, count: usize| -> Result<Option<Vec<f64>>> {
+        let Some(value) = request.param(name) else {
+            return Ok(None);
+        };
+        let numbers = value.split(',').map(|n| n.trim().parse::<f64>()).collect::<Result<Vec<_>, _>>().ok().filter(|n| n.len() == count);
+        numbers.map(Some).with_context(|| format!("Invalid {} '{}'", name, value))
+    };
+    let radius = request.param("radius").map_or(Ok(1000.0), parse_distance)?;
+    Ok(FilterSet {
+        all_tags: list("tag"),
+        any_tags: list("any_tag"),
+        exclude_tags: list("exclude_tag"),
+        media_types: list("type"),
+        min_nsfw: param(request, "min_nsfw")?,
+        max_nsfw: param(request, "max_nsfw")?,
+        min_size: request.param("min_size").map(parse_size).transpose()?,
+        max_size: request.param("max_size").map(parse_size).transpose()?,
+        modified_after: request.param("after").map(parse_date).transpose()?,
+        modified_before: request.param("before").map(parse_date).transpose()?,
+        run_id: param(request, "run")?,
+        search: request.param("search").map(str::to_string),
+        camera_model: request.param("camera").map(str::to_string),
+        min_duration: param(request, "min_duration")?,
+        max_duration: param(request, "max_duration")?,
+        include_deleted: param(request, "include_deleted")?.unwrap_or(false),
+        hash: None,
+        related_to: request.param("related_to").map(str::to_string),
+        originals_only: param(request, "originals")?.unwrap_or(false),
+        unverified_since: request.param("unverified_since").map(parse_date).transpose()?,
+        stale_embedding: None,
+        near: coordinates("near", 2)?.map(|c| (c[0], c[1], radius)),
+        bbox: coordinates("bbox", 4)?.map(|c| (c[0], c[1], c[2], c[3])),
+        after_id: param(request, "cursor")?,
+        limit: Some(param(request, "limit")?.unwrap_or(PAGE_SIZE).clamp(1, MAX_PAGE_SIZE)),
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::database::repo::{ArtifactRecord, TransactionManager};
+    use crate::database::store::CatalogStore;
+
+    fn get(api: &Api, target: &str) -> Result<serde_json::Value> {
+        let raw = format!("GET {} HTTP/1.1\r\n\r\n", target);
+        let request = Request::read(&mut raw.as_bytes())?.context("No request")?;
+        let response = api.handle(&request);
+        assert_eq!(response.status, 200, "{}", String::from_utf8_lossy(&response.body));
+        Ok(serde_json::from_slice(&response.body)?)
+    }
+
+    #[test]
+    fn test_api_artifacts() -> Result<()> {
+        let dir = std::env::temp_dir().join(format!("deep-archive-api-{}", std::process::id()));
+        fs::create_dir_all(&dir)?;
+        let db_path = dir.join("catalog.db").to_string_lossy().to_string();
+        let config = Config::default();
+        let mut tm = TransactionManager::new(&db_path, &config.database)?;
+        for i in 0..5 {
+            tm.add(ArtifactRecord {
+                hash_sha256: format!("{:064x}", i),
+                original_path: format!("/media/{}.jpg", i),
+                media_type: if i < 3 { "image/jpeg" } else { "video/mp4" }.to_string(),
+                size_bytes: Some(1000),
+                mtime: None,
+                width: None,
+                height: None,
+                tags: vec![format!("ml:{}", if i % 2 == 0 { "even" } else { "odd" })],
+                nsfw_score: None,
+                nsfw_model_version: None,
+                embeddings: Vec::new(),
+                metadata: None,
+                source: None,
+                timings: None,
+            })?;
+        }
+        tm.flush()?;
+        drop(tm);
+
+        let api = Api::new(&db_path, config)?;
+        let page = get(&api, "/api/artifacts?type=image/*&limit=2")?;
+        assert_eq!((page["total"].as_u64(), page["artifacts"].as_array().map(Vec::len)), (Some(3), Some(2)));
+        let rest = get(&api, &format!("/api/artifacts?type=image%2F*&limit=2&cursor={}", page["next"]))?;
+        assert_eq!(rest["artifacts"][0]["original_path"], "/media/2.jpg");
+        assert!(rest["next"].is_null());
+
+        let even = get(&api, "/api/artifacts?tag=ml:even")?;
+        assert_eq!(even["total"], 3);
+        let detail = get(&api, &format!("/api/artifacts/{:064x}", 1))?;
+        assert_eq!(detail["paths"][0]["path"], "/media/1.jpg");
+
+        let raw = format!("PUT /api/artifacts/{:064x}/tags/user%3Afavourite HTTP/1.1\r\n\r\n", 1);
+        let response = api.handle(&Request::read(&mut raw.as_bytes())?.context("No request")?);
+        assert_eq!(response.status, 200, "{}", String::from_utf8_lossy(&response.body));
+        assert_eq!(get(&api, "/api/artifacts?tag=user:favourite")?["total"], 1);
+        let raw = format!("DELETE /api/artifacts/{:064x}/tags/user%3Afavourite HTTP/1.1\r\n\r\n", 1);
+        assert_eq!(api.handle(&Request::read(&mut raw.as_bytes())?.context("No request")?).status, 204);
+        assert_eq!(get(&api, "/api/artifacts?tag=user:favourite")?["total"], 0);
+
+        let raw = "GET /api/artifacts?min_size=lots HTTP/1.1\r\n\r\n";
+        assert_eq!(api.handle(&Request::read(&mut raw.as_bytes())?.context("No request")?).status, 400);
+        fs::remove_dir_all(dir)?;
+        Ok(())
+    }
+}
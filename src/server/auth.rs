@@ -0,0 +1,150 @@
+use std::net::SocketAddr;
+use std::sync::Arc;
+use anyhow::{Result, bail};
+use sha2::{Digest, Sha256};
+use tracing::{debug, warn};
+use crate::server::http::{Request, Response};
+use crate::utils::config::{ApiToken, Scope, ServerConfig};
+
+/// Cookie `/api/login` sets, so the gallery's images load without an
+/// `Authorization` header.
+pub const COOKIE: &str = "deep_archive_token";
+
+struct Token {
+    name: String,
+    sha256: [u8; 32],
+    scope: Scope,
+}
+
+/// Checks requests against `server.tokens`, or remote workers against
+/// `pipeline.worker_tokens`. Without any, every request may do everything.
+#[derive(Clone, Default)]
+pub struct Auth {
+    tokens: Arc<Vec<Token>>,
+}
+
+impl Auth {
+    pub fn new(config: &ServerConfig) -> Result<Self> {
+        let auth = Self::with_tokens(&config.tokens, "server.tokens")?;
+        if auth.is_open() && !is_loopback(&config.addr) {
+            warn!("Serving on {} without server.tokens: anyone who can reach it can read the catalog and start ingests", config.addr);
+        }
+        Ok(auth)
+    }
+
+    /// Checks against `tokens`, the config's `key`.
+    pub fn with_tokens(tokens: &[ApiToken], key: &str) -> Result<Self> {
+        let mut checked = Vec::new();
+        for token in tokens {
+            let sha256 = match (&token.token, &token.sha256) {
+                (Some(plain), None) if !plain.is_empty() => digest(plain),
+                (None, Some(hex)) => match hex::decode(hex.trim()).ok().and_then(|bytes| <[u8; 32]>::try_from(bytes).ok()) {
+                    Some(sha256) => sha256,
+                    None => bail!("{} '{}': sha256 isn't 64 hex digits", key, token.name),
+                },
+                _ => bail!("{} '{}' needs either a token or its sha256", key, token.name),
+            };
+            checked.push(Token { name: token.name.clone(), sha256, scope: token.scope });
+        }
+        Ok(Auth { tokens: Arc::new(checked) })
+    }
+
+    /// Whether requests need no token.
+    pub fn is_open(&self) -> bool {
+        self.tokens.is_empty()
+    }
+
+    /// What `token` may do; `None` if it isn't one of `server.tokens`.
+    pub fn scope(&self, token: Option<&str>) -> Option<Scope> {
+        if self.is_open() {
+            return Some(Scope::Admin);
+        }
+        let sha256 = digest(token?);
+        // Compares every byte, so the time taken says nothing of how much
+        // of a guess matched.
+        let token = self.tokens.iter().find(|t| t.sha256.iter().zip(&sha256).fold(0, |diff, (a, b)| diff | (a ^ b)) == 0)?;
+        debug!("Request by {}", token.name);
+        Some(token.scope)
+    }
+
+    /// Answers with 401 a request without a valid token, and with 403 one
+    /// whose token doesn't allow `needed`.
+    pub fn check(&self, request: &Request, needed: Scope) -> Result<(), Response> {
+        match self.scope(token(request)) {
+            Some(scope) if scope >= needed => Ok(()),
+            Some(_) => Err(Response::error(403, "This token is read-only")),
+            None => Err(Response::error(401, "A valid API token is required").with_header("WWW-Authenticate", "Bearer realm=\"deep-archive\"")),
+        }
+    }
+}
+
+/// The scope a request needs: reads for `GET`, admin for what changes things.
+pub fn needed(request: &Request) -> Scope {
+    match request.method.as_str() {
+        "GET" | "HEAD" => Scope::Read,
+        _ => Scope::Admin,
+    }
+}
+
+/// The bearer token, or the cookie `/api/login` set.
+pub fn token(request: &Request) -> Option<&str> {
+    if let Some(token) = request.header("authorization").and_then(bearer) {
+        return Some(token);
+    }
+    request
+        .header("cookie")?
+        .split(';')
+        .filter_map(|cookie| cookie.trim().split_once('='))
+        .find(|(name, _)| *name == COOKIE)
+        .map(|(_, value)| value)
+}
+
+/// The token in an `Authorization: Bearer <token>` value.
+pub fn bearer(value: &str) -> Option<&str> {
+    let (scheme, token) = value.trim().split_once(' ')?;
+    scheme.eq_ignore_ascii_case("bearer").then(|| token.trim())
+}
+
+fn digest(token: &str) -> [u8; 32] {
+    Sha256::digest(token.as_bytes()).into()
+}
+
+fn is_loopback(addr: &str) -> bool {
+    match addr.parse::<SocketAddr>() {
+        Ok(addr) => addr.ip().is_loopback(),
+        Err(_) => addr.starts_with("localhost:"),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_auth() -> Result<()> {
+        let token = |name: &str, token: Option<&str>, sha256: Option<String>, scope| ApiToken { name: name.to_string(), token: token.map(str::to_string), sha256, scope };
+        let config = ServerConfig {
+            tokens: vec![
+                token("gallery", Some("r3ad"), None, Scope::Read),
+                token("ops", None, Some(hex::encode(digest("adm1n"))), Scope::Admin),
+            ],
+            ..Default::default()
+        };
+        let auth = Auth::new(&config)?;
+        let request = |raw: &str| Request::read(&mut raw.as_bytes()).unwrap().unwrap();
+        let get = request("GET /api/artifacts HTTP/1.1\r\nAuthorization: Bearer r3ad\r\n\r\n");
+        assert!(auth.check(&get, needed(&get)).is_ok());
+        let put = request("PUT /api/tags/aliases/x HTTP/1.1\r\nCookie: theme=dark; deep_archive_token=r3ad\r\n\r\n");
+        assert_eq!(auth.check(&put, needed(&put)).err().map(|r| r.status), Some(403));
+        let put = request("PUT /api/tags/aliases/x HTTP/1.1\r\nAuthorization: bearer adm1n\r\n\r\n");
+        assert!(auth.check(&put, needed(&put)).is_ok());
+        let anonymous = request("GET /api/artifacts HTTP/1.1\r\n\r\n");
+        assert_eq!(auth.check(&anonymous, Scope::Read).err().map(|r| r.status), Some(401));
+        assert_eq!(auth.scope(Some("r3ad!")), None);
+
+        assert!(Auth::default().check(&anonymous, Scope::Admin).is_ok());
+        let config = ServerConfig { tokens: vec![token("both", Some("a"), Some("00".repeat(32)), Scope::Read)], ..Default::default() };
+        assert!(Auth::new(&config).is_err());
+        Ok(())
+    }
+}
@@ -0,0 +1,672 @@
+use std::net::SocketAddr;
+use std::path::PathBuf;
+use std::pin::Pin;
+use std::time::Duration;
+use anyhow::{Result, Context};
+use tokio::sync::mpsc;
+use tokio_stream::wrappers::{ReceiverStream, TcpListenerStream};
+use tokio_stream::Stream;
+use tonic::{Request, Response, Status};
+use tracing::info;
+use crate::database::repo::{self, FilterSet, ReaderPool};
+use crate::server::auth::{self, Auth};
+use crate::server::runs::Runs;
+use crate::utils::config::Scope;
+use pb::archive_server::{Archive, ArchiveServer};
+use pb::ingest_server::{Ingest, IngestServer};
+use pb::query_server::{Query, QueryServer};
+
+/// The messages of proto/deep_archive.proto, written out for prost; build.rs
+/// generates the services over them.
+pub mod pb {
+    #[derive(Clone, PartialEq, prost::Message)]
+    pub struct Filter {
+        #[prost(string, repeated, tag = "1")]
+        pub tags: Vec<String>,
+        #[prost(string, repeated, tag = "2")]
+        pub any_tags: Vec<String>,
+        #[prost(string, repeated, tag = "3")]
+        pub exclude_tags: Vec<String>,
+        #[prost(string, repeated, tag = "4")]
+        pub media_types: Vec<String>,
+        #[prost(float, optional, tag = "5")]
+        pub min_nsfw: Option<f32>,
+        #[prost(float, optional, tag = "6")]
+        pub max_nsfw: Option<f32>,
+        #[prost(uint64, optional, tag = "7")]
+        pub min_size: Option<u64>,
+        #[prost(uint64, optional, tag = "8")]
+        pub max_size: Option<u64>,
+        #[prost(int64, optional, tag = "9")]
+        pub modified_after: Option<i64>,
+        #[prost(int64, optional, tag = "10")]
+        pub modified_before: Option<i64>,
+        #[prost(int64, optional, tag = "11")]
+        pub run_id: Option<i64>,
+        #[prost(string, optional, tag = "12")]
+        pub search: Option<String>,
+        #[prost(string, optional, tag = "13")]
+        pub camera: Option<String>,
+        #[prost(double, optional, tag = "14")]
+        pub min_duration: Option<f64>,
+        #[prost(double, optional, tag = "15")]
+        pub max_duration: Option<f64>,
+        #[prost(bool, tag = "16")]
+        pub include_deleted: bool,
+        #[prost(string, optional, tag = "17")]
+        pub related_to: Option<String>,
+        #[prost(bool, tag = "18")]
+        pub originals_only: bool,
+        #[prost(int64, optional, tag = "19")]
+        pub after_id: Option<i64>,
+        #[prost(uint64, optional, tag = "20")]
+        pub limit: Option<u64>,
+    }
+
+    #[derive(Clone, PartialEq, prost::Message)]
+    pub struct Artifact {
+        #[prost(int64, tag = "1")]
+        pub id: i64,
+        #[prost(string, tag = "2")]
+        pub hash_sha256: String,
+        #[prost(string, tag = "3")]
+        pub original_path: String,
+        #[prost(string, tag = "4")]
+        pub media_type: String,
+        #[prost(uint64, optional, tag = "5")]
+        pub size_bytes: Option<u64>,
+        #[prost(int64, optional, tag = "6")]
+        pub mtime: Option<i64>,
+        #[prost(uint32, optional, tag = "7")]
+        pub width: Option<u32>,
+        #[prost(uint32, optional, tag = "8")]
+        pub height: Option<u32>,
+        #[prost(float, optional, tag = "9")]
+        pub nsfw_score: Option<f32>,
+        #[prost(string, repeated, tag = "10")]
+        pub tags: Vec<String>,
+        #[prost(string, optional, tag = "11")]
+        pub metadata_json: Option<String>,
+        #[prost(int64, optional, tag = "12")]
+        pub deleted_at: Option<i64>,
+    }
+
+    #[derive(Clone, PartialEq, prost::Message)]
+    pub struct ArtifactPath {
+        #[prost(string, tag = "1")]
+        pub path: String,
+        #[prost(int64, tag = "2")]
+        pub first_seen: i64,
+        #[prost(int64, tag = "3")]
+        pub last_seen: i64,
+    }
+
+    #[derive(Clone, PartialEq, prost::Message)]
+    pub struct Relationship {
+        #[prost(string, tag = "1")]
+        pub kind: String,
+        #[prost(bool, tag = "2")]
+        pub outgoing: bool,
+        #[prost(string, tag = "3")]
+        pub hash_sha256: String,
+        #[prost(string, tag = "4")]
+        pub original_path: String,
+    }
+
+    #[derive(Clone, PartialEq, prost::Message)]
+    pub struct ArchiveCopy {
+        #[prost(string, tag = "1")]
+        pub label: String,
+        #[prost(string, tag = "2")]
+        pub volume: String,
+        #[prost(string, tag = "3")]
+        pub path: String,
+        #[prost(int64, tag = "4")]
+        pub created_at: i64,
+    }
+
+    #[derive(Clone, PartialEq, prost::Message)]
+    pub struct ArtifactDetail {
+        #[prost(message, optional, tag = "1")]
+        pub artifact: Option<Artifact>,
+        #[prost(message, repeated, tag = "2")]
+        pub paths: Vec<ArtifactPath>,
+        #[prost(message, repeated, tag = "3")]
+        pub relationships: Vec<Relationship>,
+        #[prost(message, repeated, tag = "4")]
+        pub archive_copies: Vec<ArchiveCopy>,
+    }
+
+    #[derive(Clone, PartialEq, prost::Message)]
+    pub struct GetArtifactRequest {
+        #[prost(string, tag = "1")]
+        pub hash_sha256: String,
+    }
+
+    #[derive(Clone, PartialEq, prost::Message)]
+    pub struct CountResponse {
+        #[prost(uint64, tag = "1")]
+        pub count: u64,
+    }
+
+    #[derive(Clone, PartialEq, prost::Message)]
+    pub struct StartIngestRequest {
+        #[prost(string, tag = "1")]
+        pub path: String,
+        #[prost(bool, tag = "2")]
+        pub reanalyze: bool,
+    }
+
+    #[derive(Clone, PartialEq, prost::Message)]
+    pub struct GetRunRequest {
+        #[prost(int64, tag = "1")]
+        pub id: i64,
+    }
+
+    #[derive(Clone, PartialEq, prost::Message)]
+    pub struct ListRunsRequest {}
+
+    #[derive(Clone, PartialEq, prost::Message)]
+    pub struct Run {
+        #[prost(int64, tag = "1")]
+        pub id: i64,
+        #[prost(int64, tag = "2")]
+        pub started_at: i64,
+        #[prost(int64, optional, tag = "3")]
+        pub finished_at: Option<i64>,
+        #[prost(string, tag = "4")]
+        pub status: String,
+        #[prost(string, tag = "5")]
+        pub input_roots: String,
+        #[prost(uint64, tag = "6")]
+        pub files_seen: u64,
+        #[prost(uint64, tag = "7")]
+        pub artifacts_added: u64,
+        #[prost(uint64, tag = "8")]
+        pub errors: u64,
+        #[prost(string, optional, tag = "9")]
+        pub error: Option<String>,
+        #[prost(string, optional, tag = "10")]
+        pub summary_json: Option<String>,
+    }
+
+    #[derive(Clone, PartialEq, prost::Message)]
+    pub struct ListVolumesRequest {}
+
+    #[derive(Clone, PartialEq, prost::Message)]
+    pub struct Volume {
+        #[prost(int64, tag = "1")]
+        pub id: i64,
+        #[prost(string, tag = "2")]
+        pub label: String,
+        #[prost(string, tag = "3")]
+        pub name: String,
+        #[prost(string, tag = "4")]
+        pub format: String,
+        #[prost(int64, tag = "5")]
+        pub created_at: i64,
+        #[prost(uint64, optional, tag = "6")]
+        pub capacity_bytes: Option<u64>,
+        #[prost(uint64, tag = "7")]
+        pub size_bytes: u64,
+        #[prost(string, optional, tag = "8")]
+        pub sha256: Option<String>,
+        #[prost(string, optional, tag = "9")]
+        pub barcode: Option<String>,
+        #[prost(uint64, tag = "10")]
+        pub files: u64,
+        #[prost(uint64, tag = "11")]
+        pub file_bytes: u64,
+    }
+
+    #[derive(Clone, PartialEq, prost::Message)]
+    pub struct ListVolumeFilesRequest {
+        #[prost(int64, tag = "1")]
+        pub volume_id: i64,
+    }
+
+    #[derive(Clone, PartialEq, prost::Message)]
+    pub struct VolumeFile {
+        #[prost(string, tag = "1")]
+        pub path: String,
+        #[prost(string, tag = "2")]
+        pub hash_sha256: String,
+        #[prost(uint64, tag = "3")]
+        pub size: u64,
+        #[prost(uint64, optional, tag = "4")]
+        pub offset: Option<u64>,
+    }
+
+    #[derive(Clone, PartialEq, prost::Message)]
+    pub struct LocateResponse {
+        #[prost(message, repeated, tag = "1")]
+        pub copies: Vec<ArchiveCopy>,
+    }
+
+    include!(concat!(env!("OUT_DIR"), "/deep_archive.v1.Ingest.rs"));
+    include!(concat!(env!("OUT_DIR"), "/deep_archive.v1.Query.rs"));
+    include!(concat!(env!("OUT_DIR"), "/deep_archive.v1.Archive.rs"));
+}
+
+type ResponseStream<T> = Pin<Box<dyn Stream<Item = Result<T, Status>> + Send>>;
+
+/// How often `Watch` looks at a run.
+const WATCH_INTERVAL: Duration = Duration::from_secs(1);
+
+/// The Ingest, Query and Archive services over one SQLite catalog.
+#[derive(Clone)]
+pub struct Services {
+    readers: ReaderPool,
+    runs: Runs,
+    auth: Auth,
+}
+
+impl Services {
+    /// Starts ingest runs through `runs`, so the REST API sees them too,
+    /// and takes the API's tokens.
+    pub fn new(readers: ReaderPool, runs: Runs, auth: Auth) -> Self {
+        Services { readers, runs, auth }
+    }
+
+    fn artifact(&self, hash: &str) -> Result<Option<repo::Artifact>> {
+        self.readers.reader()?.find(&FilterSet::new().hash(hash).limit(1)).next().transpose()
+    }
+
+    fn run(&self, id: i64) -> Result<Option<pb::Run>> {
+        Ok(self.runs.get(id)?.map(|run| pb::Run { error: self.runs.error(id), ..run.into() }))
+    }
+}
+
+/// Listens on `addr` and answers on a runtime of its own, on a thread of
+/// its own, for as long as the process runs.
+pub fn start(addr: &str, services: Services) -> Result<std::thread::JoinHandle<()>> {
+    let addr: SocketAddr = addr.parse().with_context(|| format!("Invalid gRPC address '{}'", addr))?;
+    let runtime = tokio::runtime::Builder::new_multi_thread()
+        .worker_threads(2)
+        .thread_name("grpc")
+        .enable_all()
+        .build()
+        .context("Failed to start the gRPC runtime")?;
+    // Bound here so a taken port fails the command rather than the thread.
+    let listener = runtime.block_on(tokio::net::TcpListener::bind(addr)).with_context(|| format!("Failed to listen on {}", addr))?;
+    info!("Serving gRPC on {}", listener.local_addr()?);
+    let auth = services.auth.clone();
+    let router = tonic::transport::Server::builder()
+        .add_service(IngestServer::with_interceptor(services.clone(), authorize(auth.clone())))
+        .add_service(QueryServer::with_interceptor(services.clone(), authorize(auth.clone())))
+        .add_service(ArchiveServer::with_interceptor(services, authorize(auth)));
+    Ok(std::thread::Builder::new().name("grpc".to_string()).spawn(move || {
+        if let Err(e) = runtime.block_on(router.serve_with_incoming(TcpListenerStream::new(listener))) {
+            tracing::error!("gRPC server failed: {}", e);
+        }
+    })?)
+}
+
+/// Refuses calls without a token, given as `authorization: Bearer <token>`
+/// metadata, when `server.tokens` are set.
+fn authorize(auth: Auth) -> impl FnMut(Request<()>) -> Result<Request<()>, Status> + Clone {
+    move |request| {
+        scope(&auth, &request)?;
+        Ok(request)
+    }
+}
+
+fn scope<T>(auth: &Auth, request: &Request<T>) -> Result<Scope, Status> {
+    let token = request.metadata().get("authorization").and_then(|value| value.to_str().ok()).and_then(auth::bearer);
+    auth.scope(token).ok_or_else(|| Status::unauthenticated("A valid API token is required"))
+}
+
+fn internal(e: anyhow::Error) -> Status {
+    Status::internal(format!("{:#}", e))
+}
+
+/// Runs catalog work off the async threads.
+async fn blocking<T: Send + 'static>(work: impl FnOnce() -> Result<T> + Send + 'static) -> Result<T, Status> {
+    tokio::task::spawn_blocking(work).await.map_err(|e| Status::internal(e.to_string()))?.map_err(internal)
+}
+
+/// Streams what `produce` sends from a blocking thread, as it sends it.
+/// Sends fail once the client has gone, which should end `produce`.
+fn stream<T: Send + 'static>(produce: impl FnOnce(&mpsc::Sender<Result<T, Status>>) -> Result<()> + Send + 'static) -> ResponseStream<T> {
+    let (tx, rx) = mpsc::channel(64);
+    tokio::task::spawn_blocking(move || {
+        if let Err(e) = produce(&tx) {
+            let _ = tx.blocking_send(Err(internal(e)));
+        }
+    });
+    Box::pin(ReceiverStream::new(rx))
+}
+
+#[tonic::async_trait]
+impl Ingest for Services {
+    type ListRunsStream = ResponseStream<pb::Run>;
+    type WatchStream = ResponseStream<pb::Run>;
+
+    async fn start(&self, request: Request<pb::StartIngestRequest>) -> Result<Response<pb::Run>, Status> {
+        if scope(&self.auth, &request)? < Scope::Admin {
+            return Err(Status::permission_denied("This token is read-only"));
+        }
+        let request = request.into_inner();
+        let path = PathBuf::from(&request.path);
+        let input = Runs::input(&path).map_err(|e| Status::invalid_argument(format!("{:#}", e)))?;
+        let services = self.clone();
+        let run = blocking(move || {
+            let id = services.runs.start(&path, input, request.reanalyze, "grpc")?;
+            services.run(id)?.context("The run just begun is missing")
+        })
+        .await?;
+        Ok(Response::new(run))
+    }
+
+    async fn get_run(&self, request: Request<pb::GetRunRequest>) -> Result<Response<pb::Run>, Status> {
+        let id = request.into_inner().id;
+        let services = self.clone();
+        match blocking(move || services.run(id)).await? {
+            Some(run) => Ok(Response::new(run)),
+            None => Err(Status::not_found(format!("There is no run {}", id))),
+        }
+    }
+
+    async fn list_runs(&self, _request: Request<pb::ListRunsRequest>) -> Result<Response<Self::ListRunsStream>, Status> {
+        let runs = self.runs.clone();
+        Ok(Response::new(stream(move |tx| {
+            for run in runs.list()? {
+                let error = runs.error(run.id);
+                if tx.blocking_send(Ok(pb::Run { error, ..run.into() })).is_err() {
+                    break;
+                }
+            }
+            Ok(())
+        })))
+    }
+
+    async fn watch(&self, request: Request<pb::GetRunRequest>) -> Result<Response<Self::WatchStream>, Status> {
+        let id = request.into_inner().id;
+        let services = self.clone();
+        if blocking(move || services.run(id)).await?.is_none() {
+            return Err(Status::not_found(format!("There is no run {}", id)));
+        }
+        let services = self.clone();
+        Ok(Response::new(stream(move |tx| {
+            let mut last = None;
+            while !tx.is_closed() {
+                let run = services.run(id)?.with_context(|| format!("Run {} was removed", id))?;
+                let ended = run.status != "running" || run.error.is_some();
+                if last.as_ref() != Some(&run) {
+                    last = Some(run.clone());
+                    if tx.blocking_send(Ok(run)).is_err() {
+                        break;
+                    }
+                }
+                if ended {
+                    break;
+                }
+                std::thread::sleep(WATCH_INTERVAL);
+            }
+            Ok(())
+        })))
+    }
+}
+
+#[tonic::async_trait]
+impl Query for Services {
+    type FindStream = ResponseStream<pb::Artifact>;
+
+    async fn find(&self, request: Request<pb::Filter>) -> Result<Response<Self::FindStream>, Status> {
+        let filter = FilterSet::from(request.into_inner());
+        let readers = self.readers.clone();
+        Ok(Response::new(stream(move |tx| {
+            let reader = readers.reader()?;
+            for artifact in reader.find(&filter) {
+                if tx.blocking_send(Ok(artifact?.into())).is_err() {
+                    break;
+                }
+            }
+            Ok(())
+        })))
+    }
+
+    async fn count(&self, request: Request<pb::Filter>) -> Result<Response<pb::CountResponse>, Status> {
+        let filter = FilterSet::from(request.into_inner());
+        let readers = self.readers.clone();
+        let count = blocking(move || readers.reader()?.count(&filter)).await?;
+        Ok(Response::new(pb::CountResponse { count: count as u64 }))
+    }
+
+    async fn get(&self, request: Request<pb::GetArtifactRequest>) -> Result<Response<pb::ArtifactDetail>, Status> {
+        let hash = request.into_inner().hash_sha256;
+        let services = self.clone();
+        let detail = blocking(move || {
+            let Some(artifact) = services.artifact(&hash)? else {
+                return Ok(None);
+            };
+            let reader = services.readers.reader()?;
+            Ok(Some(pb::ArtifactDetail {
+                paths: reader.paths(artifact.id)?.into_iter().map(Into::into).collect(),
+                relationships: reader.relationships(artifact.id)?.into_iter().map(Into::into).collect(),
+                archive_copies: reader.archive_copies(artifact.id)?.into_iter().map(Into::into).collect(),
+                artifact: Some(artifact.into()),
+            }))
+        })
+        .await?;
+        detail.map(Response::new).ok_or_else(|| Status::not_found("No artifact with that hash"))
+    }
+}
+
+#[tonic::async_trait]
+impl Archive for Services {
+    type ListVolumesStream = ResponseStream<pb::Volume>;
+    type ListVolumeFilesStream = ResponseStream<pb::VolumeFile>;
+
+    async fn list_volumes(&self, _request: Request<pb::ListVolumesRequest>) -> Result<Response<Self::ListVolumesStream>, Status> {
+        let readers = self.readers.clone();
+        Ok(Response::new(stream(move |tx| {
+            for volume in readers.reader()?.volumes()? {
+                if tx.blocking_send(Ok(volume.into())).is_err() {
+                    break;
+                }
+            }
+            Ok(())
+        })))
+    }
+
+    async fn list_volume_files(&self, request: Request<pb::ListVolumeFilesRequest>) -> Result<Response<Self::ListVolumeFilesStream>, Status> {
+        let volume_id = request.into_inner().volume_id;
+        let readers = self.readers.clone();
+        Ok(Response::new(stream(move |tx| {
+            for file in readers.reader()?.volume_files(volume_id)? {
+                if tx.blocking_send(Ok(file.into())).is_err() {
+                    break;
+                }
+            }
+            Ok(())
+        })))
+    }
+
+    async fn locate(&self, request: Request<pb::GetArtifactRequest>) -> Result<Response<pb::LocateResponse>, Status> {
+        let hash = request.into_inner().hash_sha256;
+        let services = self.clone();
+        let copies = blocking(move || match services.artifact(&hash)? {
+            Some(artifact) => Ok(Some(services.readers.reader()?.archive_copies(artifact.id)?)),
+            None => Ok(None),
+        })
+        .await?
+        .ok_or_else(|| Status::not_found("No artifact with that hash"))?;
+        Ok(Response::new(pb::LocateResponse { copies: copies.into_iter().map(Into::into).collect() }))
+    }
+}
+
+impl From<pb::Filter> for FilterSet {
+    fn from(filter: pb::Filter) -> Self {
+        FilterSet {
+            all_tags: filter.tags,
+            any_tags: filter.any_tags,
+            exclude_tags: filter.exclude_tags,
+            media_types: filter.media_types,
+            min_nsfw: filter.min_nsfw,
+            max_nsfw: filter.max_nsfw,
+            min_size: filter.min_size,
+            max_size: filter.max_size,
+            modified_after: filter.modified_after,
+            modified_before: filter.modified_before,
+            run_id: filter.run_id,
+            search: filter.search,
+            camera_model: filter.camera,
+            min_duration: filter.min_duration,
+            max_duration: filter.max_duration,
+            include_deleted: filter.include_deleted,
+            related_to: filter.related_to,
+            originals_only: filter.originals_only,
+            after_id: filter.after_id,
+            limit: filter.limit.map(|n| n as usize),
+            ..FilterSet::default()
+        }
+    }
+}
+
+impl From<repo::Artifact> for pb::Artifact {
+    fn from(a: repo::Artifact) -> Self {
+        pb::Artifact {
+            id: a.id,
+            hash_sha256: a.hash_sha256,
+            original_path: a.original_path,
+            media_type: a.media_type,
+            size_bytes: a.size_bytes,
+            mtime: a.mtime,
+            width: a.width,
+            height: a.height,
+            nsfw_score: a.nsfw_score,
+            tags: a.tags,
+            metadata_json: a.metadata.map(|m| m.to_string()),
+            deleted_at: a.deleted_at,
+        }
+    }
+}
+
+impl From<repo::ArtifactPath> for pb::ArtifactPath {
+    fn from(p: repo::ArtifactPath) -> Self {
+        pb::ArtifactPath { path: p.path, first_seen: p.first_seen, last_seen: p.last_seen }
+    }
+}
+
+impl From<repo::Relationship> for pb::Relationship {
+    fn from(r: repo::Relationship) -> Self {
+        pb::Relationship { kind: r.kind, outgoing: r.outgoing, hash_sha256: r.hash_sha256, original_path: r.original_path }
+    }
+}
+
+impl From<repo::ArchiveCopy> for pb::ArchiveCopy {
+    fn from(c: repo::ArchiveCopy) -> Self {
+        pb::ArchiveCopy { label: c.label, volume: c.volume, path: c.path, created_at: c.created_at }
+    }
+}
+
+impl From<repo::Run> for pb::Run {
+    fn from(run: repo::Run) -> Self {
+        pb::Run {
+            id: run.id,
+            started_at: run.started_at,
+            finished_at: run.finished_at,
+            status: run.status,
+            input_roots: run.input_roots,
+            files_seen: run.files_seen,
+            artifacts_added: run.artifacts_added,
+            errors: run.errors,
+            error: None,
+            summary_json: run.summary.and_then(|s| serde_json::to_string(&s).ok()),
+        }
+    }
+}
+
+impl From<repo::VolumeSummary> for pb::Volume {
+    fn from(v: repo::VolumeSummary) -> Self {
+        pb::Volume {
+            id: v.id,
+            label: v.volume.label,
+            name: v.volume.name,
+            format: v.volume.format,
+            created_at: v.volume.created_at,
+            capacity_bytes: v.volume.capacity_bytes,
+            size_bytes: v.volume.size_bytes,
+            sha256: v.volume.sha256,
+            barcode: v.volume.barcode,
+            files: v.files,
+            file_bytes: v.file_bytes,
+        }
+    }
+}
+
+impl From<repo::VolumeFile> for pb::VolumeFile {
+    fn from(f: repo::VolumeFile) -> Self {
+        pb::VolumeFile { path: f.path, hash_sha256: f.hash, size: f.size, offset: f.offset }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tokio_stream::StreamExt;
+    use crate::database::repo::{ArtifactRecord, TransactionManager};
+    use crate::database::store::CatalogStore;
+    use crate::utils::config::{ApiToken, Config};
+
+    #[test]
+    fn test_query_service() -> Result<()> {
+        let dir = std::env::temp_dir().join(format!("deep-archive-grpc-{}", std::process::id()));
+        std::fs::create_dir_all(&dir)?;
+        let db_path = dir.join("catalog.db").to_string_lossy().to_string();
+        let config = Config::default();
+        let mut tm = TransactionManager::new(&db_path, &config.database)?;
+        for i in 0..3 {
+            tm.add(ArtifactRecord {
+                hash_sha256: format!("{:064x}", i),
+                original_path: format!("/media/{}.jpg", i),
+                media_type: "image/jpeg".to_string(),
+                size_bytes: Some(1000 * (i + 1)),
+                mtime: None,
+                width: None,
+                height: None,
+                tags: vec!["ml:cat".to_string()],
+                nsfw_score: None,
+                nsfw_model_version: None,
+                embeddings: Vec::new(),
+                metadata: Some(serde_json::json!({ "camera": "x" })),
+                source: None,
+                timings: None,
+            })?;
+        }
+        tm.flush()?;
+        drop(tm);
+
+        let mut server = config.server.clone();
+        server.tokens = vec![ApiToken { name: "viewer".to_string(), token: Some("r3ad".to_string()), sha256: None, scope: Scope::Read }];
+        let viewer = Auth::new(&server)?;
+        let services = Services::new(ReaderPool::open(&db_path, &config.database)?, Runs::new(&db_path, config), Auth::default());
+        let runtime = tokio::runtime::Builder::new_current_thread().enable_all().build()?;
+        runtime.block_on(async {
+            let filter = pb::Filter { tags: vec!["ml:cat".to_string()], min_size: Some(2000), ..Default::default() };
+            let count = services.count(Request::new(filter.clone())).await?.into_inner().count;
+            assert_eq!(count, 2);
+            let found: Vec<pb::Artifact> = services.find(Request::new(filter)).await?.into_inner().collect::<Result<_, _>>().await?;
+            assert_eq!(found.iter().map(|a| a.original_path.as_str()).collect::<Vec<_>>(), ["/media/1.jpg", "/media/2.jpg"]);
+
+            let detail = services.get(Request::new(pb::GetArtifactRequest { hash_sha256: format!("{:064x}", 0) })).await?.into_inner();
+            assert_eq!(detail.artifact.and_then(|a| a.metadata_json).as_deref(), Some(r#"{"camera":"x"}"#));
+            assert_eq!(detail.paths.len(), 1);
+            let missing = services.get(Request::new(pb::GetArtifactRequest { hash_sha256: "f".repeat(64) })).await;
+            assert_eq!(missing.err().map(|s| s.code()), Some(tonic::Code::NotFound));
+
+            let start = services.start(Request::new(pb::StartIngestRequest { path: "/no/such/dir".to_string(), reanalyze: false })).await;
+            assert_eq!(start.err().map(|s| s.code()), Some(tonic::Code::InvalidArgument));
+
+            let services = Services { auth: viewer, ..services };
+            let mut start = Request::new(pb::StartIngestRequest { path: "/no/such/dir".to_string(), reanalyze: false });
+            assert_eq!(services.start(Request::new(start.get_ref().clone())).await.err().map(|s| s.code()), Some(tonic::Code::Unauthenticated));
+            start.metadata_mut().insert("authorization", "Bearer r3ad".parse()?);
+            assert_eq!(services.start(start).await.err().map(|s| s.code()), Some(tonic::Code::PermissionDenied));
+            Ok::<_, anyhow::Error>(())
+        })?;
+        std::fs::remove_dir_all(dir)?;
+        Ok(())
+    }
+}
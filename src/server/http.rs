@@ -0,0 +1,237 @@
+use std::io::{self, BufRead, BufReader, Read, Write};
+use anyhow::{Result, Context};
+use serde::de::DeserializeOwned;
+use serde::Serialize;
+
+/// Request bodies are small JSON objects; anything larger is refused.
+const MAX_BODY: usize = 1024 * 1024;
+const MAX_HEADERS: usize = 100;
+/// Longest request line or header, so a client can't grow one without end.
+const MAX_LINE: usize = 8 * 1024;
+
+/// An HTTP/1.1 request, read whole.
+#[derive(Debug, Default)]
+pub struct Request {
+    pub method: String,
+    /// As sent, without the query string; `segments` decodes it.
+    pub path: String,
+    /// Percent-decoded, in the order given; names may repeat.
+    pub query: Vec<(String, String)>,
+    /// Names lowercased.
+    pub headers: Vec<(String, String)>,
+    pub body: Vec<u8>,
+}
+
+impl Request {
+    /// Reads the next request, or `None` if the client closed the
+    /// connection before sending one.
+    pub fn read(reader: &mut impl BufRead) -> io::Result<Option<Request>> {
+        let mut line = String::new();
+        if read_line(reader, &mut line)? == 0 {
+            return Ok(None);
+        }
+        let mut parts = line.split_whitespace();
+        let (Some(method), Some(target)) = (parts.next(), parts.next()) else {
+            return Err(invalid("Malformed request line"));
+        };
+        let (path, query) = target.split_once('?').unwrap_or((target, ""));
+        let mut request = Request {
+            method: method.to_string(),
+            path: path.to_string(),
+            query: parse_query(query),
+            ..Default::default()
+        };
+
+        loop {
+            let mut header = String::new();
+            read_line(reader, &mut header)?;
+            let header = header.trim_end();
+            if header.is_empty() {
+                break;
+            }
+            if request.headers.len() == MAX_HEADERS {
+                return Err(invalid("Too many headers"));
+            }
+            if let Some((name, value)) = header.split_once(':') {
+                request.headers.push((name.trim().to_ascii_lowercase(), value.trim().to_string()));
+            }
+        }
+
+        let length: usize = request.header("content-length").map_or(Ok(0), str::parse).map_err(|_| invalid("Bad Content-Length"))?;
+        if length > MAX_BODY {
+            return Err(invalid("Request body too large"));
+        }
+        request.body = vec![0; length];
+        reader.read_exact(&mut request.body)?;
+        Ok(Some(request))
+    }
+
+    pub fn header(&self, name: &str) -> Option<&str> {
+        self.headers.iter().find(|(n, _)| n == name).map(|(_, v)| v.as_str())
+    }
+
+    /// The first value of a query parameter.
+    pub fn param(&self, name: &str) -> Option<&str> {
+        self.query.iter().find(|(n, _)| n == name).map(|(_, v)| v.as_str())
+    }
+
+    /// Every value of a repeatable query parameter.
+    pub fn params<'a>(&'a self, name: &'a str) -> impl Iterator<Item = &'a str> + 'a {
+        self.query.iter().filter(move |(n, _)| n == name).map(|(_, v)| v.as_str())
+    }
+
+    /// The non-empty path segments, percent-decoded, e.g. `["api",
+    /// "runs", "3"]` for `/api/runs/3`.
+    pub fn segments(&self) -> Vec<String> {
+        self.path.split('/').filter(|s| !s.is_empty()).map(percent_decode).collect()
+    }
+
+    pub fn json<T: DeserializeOwned>(&self) -> Result<T> {
+        serde_json::from_slice(&self.body).context("Request body is not the expected JSON")
+    }
+}
+
+/// A response to write back; the connection is closed after it.
+#[derive(Debug)]
+pub struct Response {
+    pub status: u16,
+    pub content_type: String,
+    pub headers: Vec<(String, String)>,
+    pub body: Vec<u8>,
+}
+
+impl Response {
+    pub fn new(status: u16, content_type: &str, body: Vec<u8>) -> Self {
+        Response { status, content_type: content_type.to_string(), headers: Vec::new(), body }
+    }
+
+    pub fn json<T: Serialize>(status: u16, value: &T) -> Self {
+        match serde_json::to_vec(value) {
+            Ok(body) => Response::new(status, "application/json", body),
+            Err(e) => Response::error(500, &format!("Failed to serialize the response: {}", e)),
+        }
+    }
+
+    /// `{"error": message}`.
+    pub fn error(status: u16, message: &str) -> Self {
+        let body = serde_json::json!({ "error": message }).to_string().into_bytes();
+        Response::new(status, "application/json", body)
+    }
+
+    pub fn not_found() -> Self {
+        Response::error(404, "Not found")
+    }
+
+    pub fn with_header(mut self, name: &str, value: &str) -> Self {
+        self.headers.push((name.to_string(), value.to_string()));
+        self
+    }
+
+    pub fn write_to(&self, writer: &mut impl Write) -> io::Result<()> {
+        write!(writer, "HTTP/1.1 {} {}\r\n", self.status, reason(self.status))?;
+        write!(writer, "Content-Type: {}\r\nContent-Length: {}\r\nConnection: close\r\n", self.content_type, self.body.len())?;
+        for (name, value) in &self.headers {
+            write!(writer, "{}: {}\r\n", name, value)?;
+        }
+        writer.write_all(b"\r\n")?;
+        writer.write_all(&self.body)?;
+        writer.flush()
+    }
+}
+
+/// Answers one request on `stream` with `handler`.
+pub fn respond(stream: impl Read + Write, handler: &impl Fn(&Request) -> Response) -> io::Result<()> {
+    let mut reader = BufReader::new(stream);
+    let response = match Request::read(&mut reader) {
+        Ok(Some(request)) => handler(&request),
+        Ok(None) => return Ok(()),
+        Err(e) if e.kind() == io::ErrorKind::InvalidData => Response::error(400, &e.to_string()),
+        Err(e) => return Err(e),
+    };
+    response.write_to(reader.get_mut())
+}
+
+/// Reads a line of the request head, failing on one of [`MAX_LINE`] bytes
+/// or more without its end.
+fn read_line(reader: &mut impl BufRead, line: &mut String) -> io::Result<usize> {
+    let read = reader.by_ref().take(MAX_LINE as u64).read_line(line)?;
+    if read == MAX_LINE && !line.ends_with('\n') {
+        return Err(invalid("Request line or header too long"));
+    }
+    Ok(read)
+}
+
+fn invalid(message: &str) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, message)
+}
+
+fn reason(status: u16) -> &'static str {
+    match status {
+        200 => "OK",
+        201 => "Created",
+        202 => "Accepted",
+        204 => "No Content",
+        400 => "Bad Request",
+        401 => "Unauthorized",
+        403 => "Forbidden",
+        404 => "Not Found",
+        405 => "Method Not Allowed",
+        409 => "Conflict",
+        503 => "Service Unavailable",
+        _ => "Internal Server Error",
+    }
+}
+
+fn parse_query(query: &str) -> Vec<(String, String)> {
+    query
+        .split('&')
+        .filter(|pair| !pair.is_empty())
+        .map(|pair| {
+            let (name, value) = pair.split_once('=').unwrap_or((pair, ""));
+            (percent_decode(&name.replace('+', " ")), percent_decode(&value.replace('+', " ")))
+        })
+        .collect()
+}
+
+/// Decodes `%XX` escapes; malformed ones are kept as they are.
+pub fn percent_decode(s: &str) -> String {
+    let bytes = s.as_bytes();
+    let mut out = Vec::with_capacity(bytes.len());
+    let mut i = 0;
+    while i < bytes.len() {
+        let hex = bytes.get(i + 1..i + 3).and_then(|h| std::str::from_utf8(h).ok()).and_then(|h| u8::from_str_radix(h, 16).ok());
+        match (bytes[i], hex) {
+            (b'%', Some(byte)) => {
+                out.push(byte);
+                i += 3;
+            }
+            (byte, _) => {
+                out.push(byte);
+                i += 1;
+            }
+        }
+    }
+    String::from_utf8_lossy(&out).into_owned()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_read_request() -> Result<()> {
+        let raw = "POST /api/tags/implications/cat%20pic/a%2Fb?tag=animal%3Acat&tag=b+c&limit=5 HTTP/1.1\r\nHost: x\r\nContent-Length: 13\r\n\r\n{\"tag\":\"cat\"}";
+        let request = Request::read(&mut raw.as_bytes())?.context("No request")?;
+        assert_eq!((request.method.as_str(), request.path.as_str()), ("POST", "/api/tags/implications/cat%20pic/a%2Fb"));
+        assert_eq!(request.params("tag").collect::<Vec<_>>(), ["animal:cat", "b c"]);
+        assert_eq!(request.param("limit"), Some("5"));
+        assert_eq!(request.segments(), ["api", "tags", "implications", "cat pic", "a/b"]);
+        let body: serde_json::Value = request.json()?;
+        assert_eq!(body["tag"], "cat");
+        assert!(Request::read(&mut "".as_bytes())?.is_none());
+
+        let endless = format!("GET / HTTP/1.1\r\nCookie: {}", "a".repeat(MAX_LINE));
+        assert_eq!(Request::read(&mut endless.as_bytes()).unwrap_err().kind(), io::ErrorKind::InvalidData);
+        Ok(())
+    }
+}
@@ -0,0 +1,125 @@
+pub mod api;
+pub mod auth;
+#[cfg(feature = "grpc")]
+pub mod grpc;
+pub mod http;
+pub mod runs;
+pub mod tls;
+
+use std::net::{SocketAddr, TcpListener, TcpStream};
+use std::sync::Arc;
+use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+use std::thread::{self, JoinHandle};
+use std::time::Duration;
+use anyhow::{Result, Context};
+use tracing::{info, warn};
+use crate::server::http::{Request, Response};
+use crate::server::tls::Acceptor;
+use crate::utils::config::ServerConfig;
+
+/// Connections answered at once; more are turned away with 503, so idle
+/// clients can't use up threads.
+const MAX_CONNECTIONS: usize = 64;
+
+/// Accepts connections on `server.addr` on a thread of its own, answering
+/// each on a thread of its own with the handler, over TLS with
+/// `server.tls`, until dropped.
+pub struct Server {
+    addr: SocketAddr,
+    scheme: &'static str,
+    stop: Arc<AtomicBool>,
+    thread: Option<JoinHandle<()>>,
+}
+
+impl Server {
+    pub fn start(config: &ServerConfig, handler: impl Fn(&Request) -> Response + Send + Sync + 'static) -> Result<Self> {
+        let tls = config.tls.as_ref().map(Acceptor::new).transpose()?.map(Arc::new);
+        let scheme = if tls.is_some() { "https" } else { "http" };
+        let listener = TcpListener::bind(&config.addr).with_context(|| format!("Failed to listen on {}", config.addr))?;
+        let addr = listener.local_addr()?;
+        let stop = Arc::new(AtomicBool::new(false));
+        let stopping = stop.clone();
+        let handler = Arc::new(handler);
+        let open = Arc::new(AtomicUsize::new(0));
+        let thread = thread::Builder::new().name("server".to_string()).spawn(move || {
+            for stream in listener.incoming() {
+                if stopping.load(Ordering::Relaxed) {
+                    break;
+                }
+                let stream = match stream {
+                    Ok(stream) => stream,
+                    Err(e) => {
+                        warn!("Failed to accept a connection: {}", e);
+                        continue;
+                    }
+                };
+                let Some(slot) = Slot::take(&open) else {
+                    warn!("Turning a connection away: {} are open", MAX_CONNECTIONS);
+                    // A TLS client can't read a plain answer; it is just closed.
+                    if tls.is_none() {
+                        let _ = stream.set_write_timeout(Some(Duration::from_secs(1)));
+                        let _ = Response::error(503, "Too many connections").write_to(&mut &stream);
+                    }
+                    continue;
+                };
+                let handler = handler.clone();
+                let tls = tls.clone();
+                thread::spawn(move || {
+                    let _slot = slot;
+                    let result = stream.set_read_timeout(Some(Duration::from_secs(30))).and_then(|_| match &tls {
+                        Some(tls) => tls.respond(stream, &*handler),
+                        None => http::respond(stream, &*handler),
+                    });
+                    if let Err(e) = result {
+                        warn!("Request failed: {}", e);
+                    }
+                });
+            }
+        })?;
+        info!("Serving on {}://{}", scheme, addr);
+        Ok(Self { addr, scheme, stop, thread: Some(thread) })
+    }
+
+    pub fn addr(&self) -> SocketAddr {
+        self.addr
+    }
+
+    /// `http://` or `https://` and the address.
+    pub fn url(&self) -> String {
+        format!("{}://{}", self.scheme, self.addr)
+    }
+
+    /// Blocks for as long as the server runs.
+    pub fn wait(mut self) {
+        if let Some(thread) = self.thread.take() {
+            let _ = thread.join();
+        }
+    }
+}
+
+/// One of the [`MAX_CONNECTIONS`], given back on drop.
+struct Slot(Arc<AtomicUsize>);
+
+impl Slot {
+    fn take(open: &Arc<AtomicUsize>) -> Option<Slot> {
+        open.fetch_update(Ordering::SeqCst, Ordering::SeqCst, |n| (n < MAX_CONNECTIONS).then_some(n + 1)).ok()?;
+        Some(Slot(open.clone()))
+    }
+}
+
+impl Drop for Slot {
+    fn drop(&mut self) {
+        self.0.fetch_sub(1, Ordering::SeqCst);
+    }
+}
+
+impl Drop for Server {
+    fn drop(&mut self) {
+        self.stop.store(true, Ordering::Relaxed);
+        // Wakes the listener blocked in accept.
+        let _ = TcpStream::connect(self.addr);
+        if let Some(thread) = self.thread.take() {
+            let _ = thread.join();
+        }
+    }
+}
@@ -0,0 +1,79 @@
+use std::collections::HashMap;
+use std::path::Path;
+use std::sync::{Arc, Mutex};
+use std::thread;
+use anyhow::{Result, bail};
+use serde_json::json;
+use tracing::{error, info};
+use crate::database::repo::Run;
+use crate::database::store;
+use crate::ingest::pipeline::{self, Input};
+use crate::utils::config::Config;
+
+/// Ingest runs started through the server, shared by the REST API and the
+/// gRPC services.
+#[derive(Clone)]
+pub struct Runs {
+    db_path: String,
+    config: Config,
+    /// Runs started here whose pipeline failed, with the error.
+    failed: Arc<Mutex<HashMap<i64, String>>>,
+}
+
+impl Runs {
+    pub fn new(db_path: &str, config: Config) -> Self {
+        Runs { db_path: db_path.to_string(), config, failed: Arc::default() }
+    }
+
+    /// What ingesting `path` means: scanning a directory or taking a
+    /// single file.
+    pub fn input(path: &Path) -> Result<Input> {
+        if path.is_dir() {
+            Ok(Input::Directory(path.to_path_buf()))
+        } else if path.is_file() {
+            Ok(Input::Files(vec![path.to_path_buf()]))
+        } else {
+            bail!("{:?} is not a file or directory", path)
+        }
+    }
+
+    /// Starts a run over `path` in the background and returns its id at
+    /// once.
+    pub fn start(&self, path: &Path, input: Input, reanalyze: bool, origin: &str) -> Result<i64> {
+        let roots = vec![path.canonicalize().unwrap_or_else(|_| path.to_path_buf()).to_string_lossy().to_string()];
+        let options = json!({ origin: true, "path": path, "reanalyze": reanalyze }).to_string();
+
+        // Begun here so the id can be answered with; the pipeline then
+        // picks the empty run up as it would an interrupted one.
+        let run_id = store::open(&self.db_path, &self.config.database)?.begin_run(&roots, &options)?;
+        let db_path = self.db_path.clone();
+        let mut config = self.config.clone();
+        config.pipeline.skip_known &= !reanalyze;
+        let failed = self.failed.clone();
+        thread::Builder::new().name(format!("run-{}", run_id)).spawn(move || {
+            match pipeline::resume(input, roots, Some(run_id), &db_path, config) {
+                Ok(summary) => info!("Run {} finished: {}", run_id, summary.status),
+                Err(e) => {
+                    error!("Run {} failed: {:#}", run_id, e);
+                    failed.lock().unwrap().insert(run_id, format!("{:#}", e));
+                }
+            }
+        })?;
+        info!("Started run {} over {:?}", run_id, path);
+        Ok(run_id)
+    }
+
+    /// Every run in the catalog, newest first.
+    pub fn list(&self) -> Result<Vec<Run>> {
+        store::open(&self.db_path, &self.config.database)?.runs()
+    }
+
+    pub fn get(&self, id: i64) -> Result<Option<Run>> {
+        Ok(self.list()?.into_iter().find(|run| run.id == id))
+    }
+
+    /// Why the pipeline of a run started here failed, if it did.
+    pub fn error(&self, id: i64) -> Option<String> {
+        self.failed.lock().unwrap().get(&id).cloned()
+    }
+}
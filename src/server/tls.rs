@@ -0,0 +1,43 @@
+use std::io;
+use std::net::TcpStream;
+use anyhow::Result;
+use crate::server::http::{Request, Response};
+use crate::utils::config::TlsConfig;
+
+/// Takes connections over TLS with `server.tls`'s certificate (`tls`
+/// feature).
+#[cfg(feature = "tls")]
+pub struct Acceptor(native_tls::TlsAcceptor);
+
+#[cfg(not(feature = "tls"))]
+pub enum Acceptor {}
+
+impl Acceptor {
+    #[cfg(feature = "tls")]
+    pub fn new(config: &TlsConfig) -> Result<Self> {
+        use anyhow::Context;
+        let cert = std::fs::read(&config.cert).with_context(|| format!("Failed to read the certificate {:?}", config.cert))?;
+        let key = std::fs::read(&config.key).with_context(|| format!("Failed to read the key {:?}", config.key))?;
+        let identity = native_tls::Identity::from_pkcs8(&cert, &key).context("Failed to load the TLS certificate and key")?;
+        Ok(Acceptor(native_tls::TlsAcceptor::new(identity)?))
+    }
+
+    #[cfg(not(feature = "tls"))]
+    pub fn new(_config: &TlsConfig) -> Result<Self> {
+        anyhow::bail!("server.tls needs a build with the `tls` feature")
+    }
+
+    /// Answers one request on `stream` once the handshake is done.
+    pub fn respond(&self, stream: TcpStream, handler: &impl Fn(&Request) -> Response) -> io::Result<()> {
+        #[cfg(feature = "tls")]
+        {
+            let stream = self.0.accept(stream).map_err(|e| io::Error::other(format!("TLS handshake failed: {}", e)))?;
+            crate::server::http::respond(stream, handler)
+        }
+        #[cfg(not(feature = "tls"))]
+        {
+            let _ = (stream, handler);
+            match *self {}
+        }
+    }
+}
@@ -1,9 +1,1020 @@
+use std::collections::{BTreeMap, HashMap};
 use std::fs::File;
 use std::io::{Write, BufRead, BufReader};
 use std::path::{Path, PathBuf};
+use std::time::Duration;
 use walkdir::WalkDir;
-use anyhow::{Result, Context, anyhow};
+use anyhow::{Result, Context, anyhow, bail};
+use serde::{Deserialize, Serialize};
 use tracing::info;
+use crate::archive::iso_builder::VOLUME_ID;
+use crate::ingest::stages;
+use crate::utils::priority::IoPriority;
+
+pub const DEFAULT_CONFIG_FILE: &str = "deep-archive.toml";
+
+/// Runtime configuration loaded from `deep-archive.toml`.
+/// Every field has a default, so a missing file or section is valid.
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(default)]
+pub struct Config {
+    pub media: MediaConfig,
+    pub preview: PreviewConfig,
+    pub database: DatabaseConfig,
+    pub archive: ArchiveConfig,
+    pub pipeline: PipelineConfig,
+    pub retry: RetryPolicy,
+    pub process: ProcessConfig,
+    pub server: ServerConfig,
+    pub tui: TuiConfig,
+    pub notify: NotifyConfig,
+    pub daemon: DaemonConfig,
+    pub telemetry: TelemetryConfig,
+    pub hooks: HooksConfig,
+    pub plugins: PluginsConfig,
+    pub webdav: WebDavConfig,
+    pub download: DownloadConfig,
+}
+
+/// `ingest --input-dir webdav://…` (`webdav` feature): the shares are
+/// mirrored locally, as the stages need files to read.
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default)]
+pub struct WebDavConfig {
+    /// Unset takes the user in the URL, if any.
+    pub username: Option<String>,
+    /// Unset reads `$DEEP_ARCHIVE_WEBDAV_PASSWORD`; a Nextcloud app
+    /// password works.
+    pub password: Option<String>,
+    /// Where shares are mirrored, under their host and path, with the
+    /// ETags their files were downloaded at.
+    pub mirror_dir: PathBuf,
+}
+
+impl Default for WebDavConfig {
+    fn default() -> Self {
+        Self { username: None, password: None, mirror_dir: PathBuf::from("data/webdav") }
+    }
+}
+
+/// Downloads that `ingest` runs before ingesting them: `--urls` and
+/// `--ytdlp`.
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default)]
+pub struct DownloadConfig {
+    /// Where each run downloads to, in a directory named after the UTC time
+    /// it started; nothing is removed from it.
+    pub spool_dir: PathBuf,
+    pub ytdlp_path: PathBuf,
+    /// Passed to yt-dlp before the options placing the downloads, e.g.
+    /// `["-f", "bv*+ba/b"]` or `["--cookies-from-browser", "firefox"]`.
+    pub ytdlp_args: Vec<String>,
+    /// Kill yt-dlp if it goes this long without finishing a video; 0 is no
+    /// limit.
+    pub ytdlp_timeout_secs: u64,
+}
+
+impl Default for DownloadConfig {
+    fn default() -> Self {
+        Self { spool_dir: PathBuf::from("data/spool"), ytdlp_path: PathBuf::from("yt-dlp"), ytdlp_args: Vec::new(), ytdlp_timeout_secs: 3600 }
+    }
+}
+
+impl DownloadConfig {
+    /// A new run's directory in the spool.
+    pub fn run_dir(&self) -> PathBuf {
+        self.spool_dir.join(chrono::Utc::now().format("%Y%m%dT%H%M%SZ").to_string())
+    }
+}
+
+/// WebAssembly modules loaded at startup (`plugins` feature) that filter
+/// the files a run takes, extract metadata from them and post-process
+/// their tags.
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default)]
+pub struct PluginsConfig {
+    /// Directory whose `*.wasm` (or `*.wat`) modules are loaded, in name
+    /// order. Unset loads none.
+    pub dir: Option<PathBuf>,
+    /// Fuel (about one per instruction) a plugin may burn per call before
+    /// it is stopped.
+    pub fuel: u64,
+    /// Memory a plugin may grow to per call, in MiB.
+    pub memory_mib: u32,
+}
+
+impl Default for PluginsConfig {
+    fn default() -> Self {
+        Self { dir: None, fuel: 1_000_000_000, memory_mib: 256 }
+    }
+}
+
+/// Commands run for each file once ingest has committed it, given the
+/// artifact as JSON on stdin and its main fields in `DEEP_ARCHIVE_*`
+/// variables. Each is a program and its arguments, run without a shell.
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default)]
+pub struct HooksConfig {
+    /// Run for every file analyzed and committed.
+    pub on_ingested: Option<Vec<String>>,
+    /// Run for files scoring `nsfw_threshold` or more.
+    pub on_nsfw_above_threshold: Option<Vec<String>>,
+    /// Run for files with content the catalog already had, or that another
+    /// file in the run had.
+    pub on_duplicate_found: Option<Vec<String>>,
+    pub nsfw_threshold: f32,
+    /// Seconds a hook may run before it is killed.
+    pub timeout_secs: u64,
+}
+
+impl Default for HooksConfig {
+    fn default() -> Self {
+        Self { on_ingested: None, on_nsfw_above_threshold: None, on_duplicate_found: None, nsfw_threshold: 0.8, timeout_secs: 60 }
+    }
+}
+
+/// Where the spans traced through the pipeline for each file are exported
+/// (`otel` feature).
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default)]
+pub struct TelemetryConfig {
+    /// OTLP/HTTP endpoint, e.g. `http://localhost:4318`; spans go to its
+    /// `/v1/traces`. Unset exports nothing unless
+    /// `$OTEL_EXPORTER_OTLP_ENDPOINT` is set.
+    pub endpoint: Option<String>,
+    pub service_name: String,
+    /// Share of files traced, from 0 to 1.
+    pub sample_ratio: f64,
+}
+
+impl Default for TelemetryConfig {
+    fn default() -> Self {
+        Self { endpoint: None, service_name: "deep-archive".to_string(), sample_ratio: 1.0 }
+    }
+}
+
+impl TelemetryConfig {
+    /// Whether spans are to be exported.
+    pub fn enabled(&self) -> bool {
+        self.endpoint.is_some() || std::env::var_os("OTEL_EXPORTER_OTLP_ENDPOINT").is_some() || std::env::var_os("OTEL_EXPORTER_OTLP_TRACES_ENDPOINT").is_some()
+    }
+}
+
+/// The long-running service `daemon` starts.
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default)]
+pub struct DaemonConfig {
+    /// Directories whose new and changed files are ingested as they appear.
+    pub watch: Vec<PathBuf>,
+    /// Seconds between looks at them.
+    pub interval_secs: u64,
+    /// Seconds a file must have gone unmodified before it is ingested, so
+    /// files still being copied in aren't caught half-written.
+    pub settle_secs: u64,
+    /// Also serve the HTTP API on `server.addr`.
+    pub serve: bool,
+    /// Where `ctl` reaches the daemon; unset uses
+    /// `$XDG_RUNTIME_DIR/deep-archive.sock`, else one in the temp directory.
+    pub socket: Option<PathBuf>,
+}
+
+impl Default for DaemonConfig {
+    fn default() -> Self {
+        Self { watch: Vec::new(), interval_secs: 60, settle_secs: 30, serve: true, socket: None }
+    }
+}
+
+/// Who is told when runs finish, fail a lot or fill a volume, and how.
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(default)]
+pub struct NotifyConfig {
+    pub webhooks: Vec<Webhook>,
+    pub slack: Vec<SlackChannel>,
+    pub matrix: Vec<MatrixRoom>,
+    pub email: Vec<EmailChannel>,
+    /// Notifications on this machine's desktop; unset sends none.
+    pub desktop: Option<DesktopChannel>,
+}
+
+/// Which events a channel is sent.
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default)]
+pub struct Subscription {
+    /// Events to send; empty sends all of them.
+    pub events: Vec<NoticeKind>,
+    /// Failed files in one run that trigger `error_threshold`.
+    pub error_threshold: u64,
+}
+
+impl Default for Subscription {
+    fn default() -> Self {
+        Self { events: Vec::new(), error_threshold: 100 }
+    }
+}
+
+impl Subscription {
+    pub fn wants(&self, kind: NoticeKind) -> bool {
+        self.events.is_empty() || self.events.contains(&kind)
+    }
+}
+
+/// A URL POSTed to on pipeline events, in builds with the `webhooks`
+/// feature.
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default)]
+pub struct Webhook {
+    pub url: String,
+    #[serde(flatten)]
+    pub subscription: Subscription,
+    /// Request body with `{{field}}` placeholders for the event's fields
+    /// (`message` is a one-line summary), for endpoints that expect their
+    /// own shape. Unset sends the event as JSON.
+    pub template: Option<String>,
+    pub content_type: String,
+    /// Extra request headers, e.g. `Authorization`.
+    pub headers: BTreeMap<String, String>,
+}
+
+impl Default for Webhook {
+    fn default() -> Self {
+        Self {
+            url: String::new(),
+            subscription: Subscription::default(),
+            template: None,
+            content_type: "application/json".to_string(),
+            headers: BTreeMap::new(),
+        }
+    }
+}
+
+/// A Slack incoming webhook (or a Mattermost one, which takes the same
+/// body), in builds with the `webhooks` feature.
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(default)]
+pub struct SlackChannel {
+    pub url: String,
+    #[serde(flatten)]
+    pub subscription: Subscription,
+}
+
+/// A Matrix room messages are posted to, in builds with the `webhooks`
+/// feature.
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(default)]
+pub struct MatrixRoom {
+    /// e.g. `https://matrix.org`.
+    pub homeserver: String,
+    /// The room's ID, `!abc123:matrix.org`, not an alias.
+    pub room: String,
+    /// Access token of the account posting; unset reads
+    /// `$DEEP_ARCHIVE_MATRIX_TOKEN`.
+    pub access_token: Option<String>,
+    #[serde(flatten)]
+    pub subscription: Subscription,
+}
+
+/// Mail sent through an SMTP server, in builds with the `email` feature.
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(default)]
+pub struct EmailChannel {
+    pub server: String,
+    /// Unset uses the usual port for `security`: 587, 465 or 25.
+    pub port: Option<u16>,
+    pub security: SmtpSecurity,
+    pub username: Option<String>,
+    /// Unset reads `$DEEP_ARCHIVE_SMTP_PASSWORD`.
+    pub password: Option<String>,
+    pub from: String,
+    pub to: Vec<String>,
+    #[serde(flatten)]
+    pub subscription: Subscription,
+}
+
+/// How the connection to the SMTP server is protected.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum SmtpSecurity {
+    /// Plain connection upgraded with STARTTLS, which the server must offer.
+    #[default]
+    Starttls,
+    /// TLS from the start (SMTPS).
+    Tls,
+    /// No encryption, for a relay on the local machine or network.
+    None,
+}
+
+/// Notifications shown on the desktop: through `notify-send` on Linux and
+/// the BSDs, Notification Center on macOS.
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(default)]
+pub struct DesktopChannel {
+    #[serde(flatten)]
+    pub subscription: Subscription,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum NoticeKind {
+    /// An ingest run finished or was interrupted.
+    RunFinished,
+    /// A run's failures reached `error_threshold`.
+    ErrorThreshold,
+    /// `archive` wrote a volume.
+    VolumeFinished,
+    /// `archive` wrote its last volume.
+    ArchiveFinished,
+}
+
+/// The terminal browser `tui` runs.
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(default)]
+pub struct TuiConfig {
+    pub images: ImageProtocol,
+}
+
+/// How `tui` draws previews in the terminal.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum ImageProtocol {
+    /// Kitty graphics or sixel where the terminal is known to support
+    /// them, else blocks.
+    #[default]
+    Auto,
+    /// The kitty graphics protocol (kitty, WezTerm, Ghostty).
+    Kitty,
+    /// Sixel graphics (foot, mlterm, Windows Terminal, xterm -ti vt340).
+    Sixel,
+    /// Coloured half blocks, two pixels a cell; works in any terminal with
+    /// true colour.
+    Blocks,
+    /// No previews.
+    None,
+}
+
+/// The HTTP server `serve` runs.
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default)]
+pub struct ServerConfig {
+    /// Address to listen on. Without `tokens` anyone who can reach it can
+    /// read the catalog and start ingests, so it is local by default.
+    pub addr: String,
+    /// Where the gRPC services listen as well, in builds with the `grpc`
+    /// feature; unset serves them nowhere.
+    pub grpc_addr: Option<String>,
+    /// Bearer tokens the API takes; with any set, requests without one of
+    /// them are refused.
+    pub tokens: Vec<ApiToken>,
+    /// Serve HTTPS rather than HTTP (`tls` feature).
+    pub tls: Option<TlsConfig>,
+}
+
+impl Default for ServerConfig {
+    fn default() -> Self {
+        Self { addr: "127.0.0.1:8080".to_string(), grpc_addr: None, tokens: Vec::new(), tls: None }
+    }
+}
+
+/// A token for the API, given as `Authorization: Bearer <token>`.
+#[derive(Debug, Clone, Deserialize)]
+pub struct ApiToken {
+    /// Who the token is for, in the log.
+    pub name: String,
+    /// The token itself; or
+    pub token: Option<String>,
+    /// its SHA-256 in hex, which keeps it out of the config file.
+    pub sha256: Option<String>,
+    #[serde(default)]
+    pub scope: Scope,
+}
+
+/// What a token may do.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Default, Deserialize, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Scope {
+    /// Search artifacts and see their previews, runs and tag rules.
+    #[default]
+    Read,
+    /// Also tag, edit the tag rules and start ingests.
+    Admin,
+}
+
+/// The certificate and key HTTPS is served with.
+#[derive(Debug, Clone, Deserialize)]
+pub struct TlsConfig {
+    /// PEM certificate chain, the server's first.
+    pub cert: PathBuf,
+    /// PEM PKCS#8 private key.
+    pub key: PathBuf,
+}
+
+/// Scheduling priority the whole process runs at, so long ingests can be
+/// kept out of the way of interactive use.
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(default)]
+pub struct ProcessConfig {
+    /// -20 (highest) to 19 (lowest); unset leaves it as started.
+    pub nice: Option<i32>,
+    pub io_priority: IoPriority,
+}
+
+/// How often and how patiently transient failures (a network filesystem
+/// timing out, a busy catalog) are retried before a file is given up on.
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default)]
+pub struct RetryPolicy {
+    /// Tries in all, including the first; 1 disables retries.
+    pub attempts: u32,
+    /// Wait before the first retry, doubled for every one after it.
+    pub initial_backoff_ms: u64,
+    pub max_backoff_ms: u64,
+    /// Fraction each wait is randomly stretched or shortened by, so workers
+    /// failing together don't retry together.
+    pub jitter: f64,
+}
+
+impl Default for RetryPolicy {
+    fn default() -> Self {
+        Self {
+            attempts: 3,
+            initial_backoff_ms: 500,
+            max_backoff_ms: 30_000,
+            jitter: 0.2,
+        }
+    }
+}
+
+/// Threads per ingest stage and the queues between them. Unset thread
+/// counts are picked from the number of CPUs and the input's storage.
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default)]
+pub struct PipelineConfig {
+    /// Threads reading and hashing files; by default half the CPUs (2-8),
+    /// or 1 on a spinning disk, where parallel reads only add seeks.
+    pub hashers: Option<usize>,
+    /// Threads decoding media and running the models; by default a quarter
+    /// of the CPUs (2-8).
+    pub workers: Option<usize>,
+    /// Paths found by the scanner waiting to be hashed.
+    pub scan_queue: usize,
+    /// Hashed files waiting for a worker.
+    pub hash_queue: usize,
+    /// Records and errors waiting for the catalog writer.
+    pub db_queue: usize,
+    /// What the input is stored on; `auto` asks the kernel (Linux only) and
+    /// otherwise assumes solid state.
+    pub storage: Storage,
+    /// How long an interrupted run waits for the files in progress before
+    /// it gives up on them and kills their ffmpeg processes.
+    pub shutdown_timeout_secs: u64,
+    /// Address to serve Prometheus metrics on at `/metrics` while the
+    /// pipeline runs, e.g. `127.0.0.1:9898`.
+    pub metrics_addr: Option<String>,
+    /// Lower the hashers' and workers' concurrency while the queue after
+    /// them stays full, and raise it again as it drains.
+    pub adaptive: bool,
+    /// Move threads between the hashers and the workers, keeping their
+    /// sum, to whichever has files piling up while the other is idle.
+    pub rebalance: bool,
+    /// Record files whose content is already in the catalog as another
+    /// path of it, without decoding or analyzing them again.
+    pub skip_known: bool,
+    /// Analysis stages the workers run, in order: any of `mimetype`,
+    /// `models`, `preview`, `metadata` and `plugins`. Without `models` the
+    /// models aren't loaded.
+    pub stages: Vec<String>,
+    /// Address to hand files out to remote workers on (`deep-archive
+    /// worker`), e.g. `0.0.0.0:7700`. This process then only scans and
+    /// catalogs; the workers hash and analyze.
+    pub serve_workers: Option<String>,
+    /// Tokens workers must present to `serve_workers`, given like
+    /// `server.tokens` (their scope is ignored). Required with it.
+    pub worker_tokens: Vec<ApiToken>,
+    /// The token this process presents as a worker.
+    pub worker_token: Option<String>,
+}
+
+impl Default for PipelineConfig {
+    fn default() -> Self {
+        Self {
+            hashers: None,
+            workers: None,
+            scan_queue: 1024,
+            hash_queue: 1024,
+            db_queue: 1024,
+            storage: Storage::Auto,
+            shutdown_timeout_secs: 30,
+            metrics_addr: None,
+            adaptive: false,
+            rebalance: false,
+            skip_known: true,
+            stages: stages::STAGES.map(str::to_string).to_vec(),
+            serve_workers: None,
+            worker_tokens: Vec::new(),
+            worker_token: None,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Storage {
+    #[default]
+    Auto,
+    /// Solid state, network or anything else without seek penalty.
+    Ssd,
+    /// A spinning disk.
+    Hdd,
+}
+
+impl Storage {
+    /// Name as written in the config file.
+    pub fn name(self) -> &'static str {
+        match self {
+            Storage::Auto => "auto",
+            Storage::Ssd => "ssd",
+            Storage::Hdd => "hdd",
+        }
+    }
+}
+
+/// How archive images and tarballs are built.
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default)]
+pub struct ArchiveConfig {
+    pub iso_backend: IsoBackend,
+    /// Tool `archive burn` writes discs with.
+    pub burner: Burner,
+    /// Unix seconds used for every timestamp in archives; by default the
+    /// latest modification time of the files archived.
+    pub epoch: Option<i64>,
+    /// `{project}` in `volume_id` and `file_name`.
+    pub project: String,
+    /// Template for the ID in the header of ISO and UDF images, which
+    /// systems show as the disc's name; see `archive::expand`.
+    pub volume_id: String,
+    /// Template for volume file names when `archive --output` is a
+    /// directory, without the extension.
+    pub file_name: String,
+    /// Extra `bag-info.txt` fields for `bagit` volumes, e.g.
+    /// `Source-Organization`.
+    pub bag_info: BTreeMap<String, String>,
+    /// Buckets `archive upload` can send volumes to, by name.
+    pub remotes: BTreeMap<String, Remote>,
+    /// Compression level for `tar.zst` archives, 1-22.
+    pub zstd_level: i32,
+    /// Deflate level for `zip` archives, 0-9 (0 stores everything).
+    pub zip_level: u32,
+    /// Extensions stored in `zip` archives without compression.
+    pub zip_store_extensions: Vec<String>,
+}
+
+impl Default for ArchiveConfig {
+    fn default() -> Self {
+        Self {
+            iso_backend: IsoBackend::Native,
+            burner: Burner::default(),
+            epoch: None,
+            project: VOLUME_ID.to_string(),
+            volume_id: "{project}_{seq}".to_string(),
+            file_name: "{project}_{date}_{seq}".to_string(),
+            bag_info: BTreeMap::new(),
+            remotes: BTreeMap::new(),
+            // Most media is already compressed; higher levels cost far more
+            // time than they save.
+            zstd_level: 9,
+            zip_level: 6,
+            zip_store_extensions: [
+                "jpg", "jpeg", "png", "gif", "webp", "heic", "avif", "mp4", "m4v", "mov", "mkv", "webm", "avi",
+                "mp3", "m4a", "aac", "ogg", "opus", "flac", "zip", "gz", "zst", "xz", "7z", "rar",
+            ]
+            .map(String::from)
+            .to_vec(),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum IsoBackend {
+    /// The built-in ISO 9660 + Rock Ridge + Joliet writer.
+    #[default]
+    Native,
+    /// `xorriso -as mkisofs`, which must be installed.
+    Xorriso,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Burner {
+    /// `growisofs` from dvd+rw-tools, for DVD and Blu-ray.
+    Growisofs,
+    /// `cdrecord`, or `wodim` where that is what's installed.
+    Cdrecord,
+    /// `xorriso -as cdrecord`, for CD, DVD and Blu-ray.
+    Xorriso,
+    /// `isoburn.exe`, the disc image burner built into Windows.
+    Isoburn,
+}
+
+impl Default for Burner {
+    fn default() -> Self {
+        if cfg!(windows) {
+            Burner::Isoburn
+        } else {
+            Burner::Growisofs
+        }
+    }
+}
+
+/// A bucket for cold copies of volumes. Credentials come from the
+/// environment (`AWS_ACCESS_KEY_ID` and `AWS_SECRET_ACCESS_KEY` for S3,
+/// `B2_APPLICATION_KEY_ID` and `B2_APPLICATION_KEY` for B2), never from
+/// the config file.
+#[derive(Debug, Clone, Deserialize)]
+pub struct Remote {
+    pub provider: Provider,
+    pub bucket: String,
+    /// e.g. `us-east-1`, or `us-west-004` for B2.
+    pub region: String,
+    /// Host of another S3-compatible service, e.g. `https://minio.lan:9000`;
+    /// buckets on it are addressed by path.
+    pub endpoint: Option<String>,
+    /// Prepended to every object key, e.g. `deep-archive/`.
+    #[serde(default)]
+    pub prefix: String,
+    /// S3 storage class for uploads, e.g. `DEEP_ARCHIVE` or `GLACIER`; B2
+    /// has only one and ignores it.
+    pub storage_class: Option<String>,
+    /// Glacier retrieval tier for restores: `Bulk`, `Standard` or `Expedited`.
+    #[serde(default = "default_retrieval_tier")]
+    pub retrieval_tier: String,
+    /// Days a restored Glacier object stays readable.
+    #[serde(default = "default_restore_days")]
+    pub restore_days: u32,
+}
+
+fn default_retrieval_tier() -> String {
+    "Bulk".to_string()
+}
+
+fn default_restore_days() -> u32 {
+    7
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Provider {
+    /// Amazon S3, including its Glacier storage classes.
+    S3,
+    /// Backblaze B2 through its S3-compatible API.
+    B2,
+}
+
+/// Catalog connection tuning. The pragmas apply to SQLite; the write buffer
+/// settings to every backend.
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default)]
+pub struct DatabaseConfig {
+    /// `wal` lets readers query while an ingest is writing.
+    pub journal_mode: JournalMode,
+    /// `normal` is safe in WAL mode and avoids an fsync per transaction.
+    pub synchronous: Synchronous,
+    /// Page cache size per connection in KiB.
+    pub cache_size_kib: u32,
+    /// How long to wait on a locked database before failing.
+    pub busy_timeout_ms: u64,
+    /// Maximum read-only connections held open by a reader pool.
+    pub read_pool_size: u32,
+    /// Records buffered by the writer before they are committed.
+    pub buffer_size: usize,
+    /// Commit buffered records at least this often, so a slow trickle of
+    /// files isn't held back until the end of the run; 0 disables it.
+    pub flush_interval_secs: u64,
+    /// Open the catalog with a SQLCipher key (needs the `sqlcipher` feature).
+    pub encrypted: bool,
+    /// Command printing the key, e.g. a keyring lookup; without it the key
+    /// comes from `$DEEP_ARCHIVE_KEY` or a prompt.
+    pub key_command: Option<Vec<String>>,
+}
+
+impl DatabaseConfig {
+    /// `None` when only `buffer_size` triggers a flush.
+    pub fn flush_interval(&self) -> Option<Duration> {
+        (self.flush_interval_secs > 0).then(|| Duration::from_secs(self.flush_interval_secs))
+    }
+}
+
+/// SQLite's journal modes, so only these reach the `journal_mode` pragma.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum JournalMode {
+    Delete,
+    Truncate,
+    Persist,
+    Memory,
+    #[default]
+    Wal,
+    Off,
+}
+
+impl JournalMode {
+    /// Name as SQLite takes and reports it.
+    pub fn name(self) -> &'static str {
+        match self {
+            JournalMode::Delete => "delete",
+            JournalMode::Truncate => "truncate",
+            JournalMode::Persist => "persist",
+            JournalMode::Memory => "memory",
+            JournalMode::Wal => "wal",
+            JournalMode::Off => "off",
+        }
+    }
+}
+
+/// SQLite's `synchronous` levels, so a typo fails at load instead of being ignored.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Synchronous {
+    Off,
+    #[default]
+    Normal,
+    Full,
+    Extra,
+}
+
+impl Synchronous {
+    /// Name as the `synchronous` pragma takes it.
+    pub fn name(self) -> &'static str {
+        match self {
+            Synchronous::Off => "off",
+            Synchronous::Normal => "normal",
+            Synchronous::Full => "full",
+            Synchronous::Extra => "extra",
+        }
+    }
+}
+
+impl Default for DatabaseConfig {
+    fn default() -> Self {
+        Self {
+            journal_mode: JournalMode::Wal,
+            synchronous: Synchronous::Normal,
+            cache_size_kib: 64 * 1024,
+            busy_timeout_ms: 5000,
+            read_pool_size: 4,
+            buffer_size: 1000,
+            flush_interval_secs: 5,
+            encrypted: false,
+            key_command: None,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default)]
+pub struct MediaConfig {
+    pub ffmpeg_path: PathBuf,
+    pub ffprobe_path: PathBuf,
+    /// Extra arguments inserted before `-i`, e.g. `["-threads", "1"]`.
+    pub extra_args: Vec<String>,
+    /// Hardware decoder passed as `-hwaccel`; `auto` picks the first one ffmpeg reports.
+    pub hwaccel: HwAccel,
+    /// Optional `-hwaccel_device`, e.g. `/dev/dri/renderD128` for VAAPI.
+    pub hwaccel_device: Option<String>,
+    /// How long ffmpeg may go without producing a frame (or, for probes
+    /// and previews, run at all) before it is killed; 0 disables it. The
+    /// native backend gives up after as long without a frame.
+    pub timeout_secs: u64,
+    /// Tried on encrypted zip archives, to tell those that could be opened
+    /// from those that can't.
+    pub container_passwords: Vec<String>,
+    pub sampling: SamplingConfig,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum HwAccel {
+    #[default]
+    None,
+    Auto,
+    Vaapi,
+    Cuda,
+    Videotoolbox,
+    Qsv,
+}
+
+impl HwAccel {
+    /// Concrete accelerators in order of preference for `auto`.
+    pub const PREFERRED: [HwAccel; 4] = [HwAccel::Cuda, HwAccel::Vaapi, HwAccel::Videotoolbox, HwAccel::Qsv];
+
+    /// Parses a name as written in the config file.
+    pub fn from_name(name: &str) -> Option<HwAccel> {
+        match name {
+            "none" => Some(HwAccel::None),
+            "auto" => Some(HwAccel::Auto),
+            _ => HwAccel::PREFERRED.into_iter().find(|h| h.ffmpeg_name() == Some(name)),
+        }
+    }
+
+    /// Name as understood by `ffmpeg -hwaccel`.
+    pub fn ffmpeg_name(self) -> Option<&'static str> {
+        match self {
+            HwAccel::None | HwAccel::Auto => None,
+            HwAccel::Vaapi => Some("vaapi"),
+            HwAccel::Cuda => Some("cuda"),
+            HwAccel::Videotoolbox => Some("videotoolbox"),
+            HwAccel::Qsv => Some("qsv"),
+        }
+    }
+}
+
+impl Default for MediaConfig {
+    fn default() -> Self {
+        Self {
+            ffmpeg_path: PathBuf::from("ffmpeg"),
+            ffprobe_path: PathBuf::from("ffprobe"),
+            extra_args: Vec::new(),
+            hwaccel: HwAccel::None,
+            hwaccel_device: None,
+            timeout_secs: 600,
+            container_passwords: Vec::new(),
+            sampling: SamplingConfig::default(),
+        }
+    }
+}
+
+impl MediaConfig {
+    /// Resolves the frame sampling settings for a mimetype.
+    /// An exact override (`video/mp4`) wins over a wildcard one (`video/*`).
+    pub fn sampling_for(&self, media_type: &str) -> FrameSampling {
+        let base = &self.sampling;
+        let mut resolved = FrameSampling {
+            interval_secs: base.interval_secs,
+            max_frames: base.max_frames,
+            resolution: base.resolution,
+        };
+
+        let wildcard = media_type
+            .split_once('/')
+            .map(|(top, _)| format!("{}/*", top));
+        let overrides = wildcard
+            .and_then(|w| base.overrides.get(&w))
+            .into_iter()
+            .chain(base.overrides.get(media_type));
+
+        for o in overrides {
+            if let Some(v) = o.interval_secs { resolved.interval_secs = v; }
+            if let Some(v) = o.max_frames { resolved.max_frames = Some(v); }
+            if let Some(v) = o.resolution { resolved.resolution = v; }
+        }
+        resolved
+    }
+}
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default)]
+pub struct SamplingConfig {
+    /// Seconds between sampled frames.
+    pub interval_secs: f64,
+    /// Upper bound on frames per file; unlimited if unset.
+    pub max_frames: Option<u32>,
+    /// Frames are scaled to `resolution` x `resolution`.
+    pub resolution: u32,
+    /// Per-mimetype overrides keyed by `type/subtype` or `type/*`.
+    pub overrides: HashMap<String, SamplingOverride>,
+}
+
+impl Default for SamplingConfig {
+    fn default() -> Self {
+        let mut overrides = HashMap::new();
+        overrides.insert("image/*".to_string(), SamplingOverride {
+            max_frames: Some(1),
+            ..Default::default()
+        });
+
+        Self {
+            interval_secs: 5.0,
+            max_frames: None,
+            resolution: 224,
+            overrides,
+        }
+    }
+}
+
+impl SamplingConfig {
+    /// Rejects settings ffmpeg can't sample with: a zero `resolution`
+    /// gives empty frames without end, and `interval_secs` has to be a
+    /// positive number of seconds. Overrides are checked as well.
+    pub fn validate(&self) -> Result<()> {
+        let base = SamplingOverride { interval_secs: Some(self.interval_secs), max_frames: None, resolution: Some(self.resolution) };
+        for (name, o) in std::iter::once(("media.sampling".to_string(), &base))
+            .chain(self.overrides.iter().map(|(key, o)| (format!("media.sampling.overrides.\"{}\"", key), o)))
+        {
+            if o.resolution == Some(0) {
+                bail!("{}.resolution must be greater than 0", name);
+            }
+            if let Some(interval) = o.interval_secs.filter(|i| !(i.is_finite() && *i > 0.0)) {
+                bail!("{}.interval_secs must be a positive number of seconds, not {}", name, interval);
+            }
+        }
+        Ok(())
+    }
+}
+
+#[derive(Debug, Clone, Default, Deserialize)]
+pub struct SamplingOverride {
+    pub interval_secs: Option<f64>,
+    pub max_frames: Option<u32>,
+    pub resolution: Option<u32>,
+}
+
+/// Optional proxy clips for videos and previews for audio, cached by hash.
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default)]
+pub struct PreviewConfig {
+    pub enabled: bool,
+    pub cache_dir: PathBuf,
+    pub format: PreviewFormat,
+    /// Videos are downscaled to at most this height.
+    pub max_height: u32,
+    /// Encoder quality; higher means smaller files.
+    pub crf: u32,
+    pub audio_bitrate: String,
+    /// Only the first N seconds are transcoded if set.
+    pub max_duration_secs: Option<u64>,
+    /// Transcodes take much longer than frame sampling, so this is separate from `media.timeout_secs`.
+    pub timeout_secs: u64,
+}
+
+impl Default for PreviewConfig {
+    fn default() -> Self {
+        Self {
+            enabled: false,
+            cache_dir: PathBuf::from("data/previews"),
+            format: PreviewFormat::Mp4,
+            max_height: 360,
+            crf: 28,
+            audio_bitrate: "64k".to_string(),
+            max_duration_secs: None,
+            timeout_secs: 3600,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum PreviewFormat {
+    /// H.264 + AAC
+    #[default]
+    Mp4,
+    /// VP9 + Opus
+    Webm,
+}
+
+impl PreviewFormat {
+    pub fn extension(self) -> &'static str {
+        match self {
+            PreviewFormat::Mp4 => "mp4",
+            PreviewFormat::Webm => "webm",
+        }
+    }
+}
+
+/// Sampling settings after applying mimetype overrides.
+#[derive(Debug, Clone, PartialEq)]
+pub struct FrameSampling {
+    pub interval_secs: f64,
+    pub max_frames: Option<u32>,
+    pub resolution: u32,
+}
+
+impl FrameSampling {
+    /// Size in bytes of one RGB24 frame.
+    pub fn frame_len(&self) -> usize {
+        self.resolution as usize * self.resolution as usize * 3
+    }
+}
+
+/// Loads the config file. An explicitly given path must exist;
+/// otherwise `deep-archive.toml` in the working directory is used if present.
+pub fn load_config(path: Option<&Path>) -> Result<Config> {
+    let Some(path) = config_file(path) else {
+        return Ok(Config::default());
+    };
+
+    let content = std::fs::read_to_string(path)
+        .with_context(|| format!("Failed to read config file: {:?}", path))?;
+    let config: Config = toml::from_str(&content)
+        .with_context(|| format!("Failed to parse config file: {:?}", path))?;
+    config.media.sampling.validate().with_context(|| format!("Invalid config file: {:?}", path))?;
+    Ok(config)
+}
+
+/// The file `load_config` reads: `path`, else `deep-archive.toml` if
+/// there is one.
+pub fn config_file(path: Option<&Path>) -> Option<&Path> {
+    path.or_else(|| Some(Path::new(DEFAULT_CONFIG_FILE)).filter(|default| default.exists()))
+}
 
 pub struct ModelPaths {
     pub nsfw: PathBuf,
@@ -133,4 +1144,74 @@ mod tests {
 
         Ok(())
     }
+
+    #[test]
+    fn test_parse_config() -> Result<()> {
+        let config: Config = toml::from_str(
+            r#"
+            [media]
+            ffmpeg_path = "/opt/ffmpeg/bin/ffmpeg"
+            extra_args = ["-threads", "1"]
+            "#,
+        )?;
+        assert_eq!(config.media.ffmpeg_path, PathBuf::from("/opt/ffmpeg/bin/ffmpeg"));
+        assert_eq!(config.media.ffprobe_path, PathBuf::from("ffprobe"));
+        assert_eq!(config.media.extra_args, vec!["-threads", "1"]);
+        Ok(())
+    }
+
+    #[test]
+    fn test_sampling_overrides() -> Result<()> {
+        let config: Config = toml::from_str(
+            r#"
+            [media.sampling]
+            interval_secs = 10.0
+
+            [media.sampling.overrides."video/*"]
+            max_frames = 20
+
+            [media.sampling.overrides."video/webm"]
+            resolution = 448
+            "#,
+        )?;
+
+        let webm = config.media.sampling_for("video/webm");
+        assert_eq!(webm.interval_secs, 10.0);
+        assert_eq!(webm.max_frames, Some(20));
+        assert_eq!(webm.resolution, 448);
+
+        // User-supplied overrides replace the defaults entirely.
+        let png = config.media.sampling_for("image/png");
+        assert_eq!(png.max_frames, None);
+        assert_eq!(Config::default().media.sampling_for("image/png").max_frames, Some(1));
+        Ok(())
+    }
+
+    #[test]
+    fn test_sampling_validation() -> Result<()> {
+        Config::default().media.sampling.validate()?;
+        for invalid in [
+            "[media.sampling]\nresolution = 0",
+            "[media.sampling]\ninterval_secs = 0.0",
+            "[media.sampling]\ninterval_secs = -1.0",
+            "[media.sampling]\ninterval_secs = nan",
+            "[media.sampling.overrides.\"video/*\"]\nresolution = 0",
+            "[media.sampling.overrides.\"video/webm\"]\ninterval_secs = inf",
+        ] {
+            let config: Config = toml::from_str(invalid)?;
+            assert!(config.media.sampling.validate().is_err(), "{}", invalid);
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn test_journal_mode() -> Result<()> {
+        let config: Config = toml::from_str("[database]\njournal_mode = \"truncate\"")?;
+        assert_eq!(config.database.journal_mode.name(), "truncate");
+        assert!(toml::from_str::<Config>("[database]\njournal_mode = \"wal; DROP TABLE artifacts\"").is_err());
+        let config: Config = toml::from_str("[database]\nsynchronous = \"full\"")?;
+        assert_eq!(config.database.synchronous.name(), "full");
+        assert!(toml::from_str::<Config>("[database]\nsynchronous = \"nromal\"").is_err());
+        Ok(())
+    }
 }
@@ -1 +1,6 @@
 pub mod config;
+pub mod priority;
+pub mod retry;
+#[cfg(feature = "otel")]
+pub mod telemetry;
+pub mod units;
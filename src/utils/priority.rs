@@ -0,0 +1,96 @@
+use anyhow::{Result, bail};
+use serde::Deserialize;
+use tracing::info;
+
+/// Disk priority class for the process's reads and writes.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum IoPriority {
+    /// Left as the process was started with.
+    #[default]
+    Normal,
+    /// Only served when no other process wants the disk.
+    Idle,
+}
+
+/// Lowers (or, with privileges, raises) the priority of this process.
+/// `nice` ranges from -20 (highest) to 19 (lowest). Threads started
+/// afterwards inherit it, so this runs before any pools are spawned.
+pub fn apply(nice: Option<i32>, io: IoPriority) -> Result<()> {
+    if let Some(nice) = nice {
+        if !(-20..=19).contains(&nice) {
+            bail!("Nice value {} is out of range; use -20 (highest) to 19 (lowest)", nice);
+        }
+        set_nice(nice)?;
+        info!("Running at nice {}", nice);
+    }
+    if io == IoPriority::Idle {
+        set_io_idle()?;
+        info!("Running with idle I/O priority");
+    }
+    Ok(())
+}
+
+#[cfg(unix)]
+fn set_nice(nice: i32) -> Result<()> {
+    // On Linux this sets the calling thread, which new threads copy.
+    if unsafe { libc::setpriority(libc::PRIO_PROCESS, 0, nice) } != 0 {
+        bail!("Failed to set nice {}: {}", nice, std::io::Error::last_os_error());
+    }
+    Ok(())
+}
+
+/// Windows has priority classes rather than nice values: anything above 0
+/// is below normal, 10 and up idle, and below 0 above normal.
+#[cfg(windows)]
+fn set_nice(nice: i32) -> Result<()> {
+    use windows_sys::Win32::System::Threading::{
+        GetCurrentProcess, SetPriorityClass, ABOVE_NORMAL_PRIORITY_CLASS, BELOW_NORMAL_PRIORITY_CLASS, IDLE_PRIORITY_CLASS, NORMAL_PRIORITY_CLASS,
+    };
+    let class = match nice {
+        10.. => IDLE_PRIORITY_CLASS,
+        1..=9 => BELOW_NORMAL_PRIORITY_CLASS,
+        0 => NORMAL_PRIORITY_CLASS,
+        _ => ABOVE_NORMAL_PRIORITY_CLASS,
+    };
+    if unsafe { SetPriorityClass(GetCurrentProcess(), class) } == 0 {
+        bail!("Failed to set the priority class: {}", std::io::Error::last_os_error());
+    }
+    Ok(())
+}
+
+#[cfg(not(any(unix, windows)))]
+fn set_nice(_nice: i32) -> Result<()> {
+    tracing::warn!("Process priority can't be changed on this platform; --nice is ignored");
+    Ok(())
+}
+
+#[cfg(target_os = "linux")]
+fn set_io_idle() -> Result<()> {
+    // From linux/ioprio.h, which libc doesn't carry.
+    const IOPRIO_WHO_PROCESS: libc::c_long = 1;
+    const IOPRIO_CLASS_IDLE: libc::c_long = 3;
+    const IOPRIO_CLASS_SHIFT: u32 = 13;
+    // Like nice, this applies to the calling thread and is inherited.
+    if unsafe { libc::syscall(libc::SYS_ioprio_set, IOPRIO_WHO_PROCESS, 0, IOPRIO_CLASS_IDLE << IOPRIO_CLASS_SHIFT) } != 0 {
+        bail!("Failed to set the I/O priority: {}", std::io::Error::last_os_error());
+    }
+    Ok(())
+}
+
+/// Background mode lowers the process's I/O (and memory) priority, and its
+/// CPU priority with it.
+#[cfg(windows)]
+fn set_io_idle() -> Result<()> {
+    use windows_sys::Win32::System::Threading::{GetCurrentProcess, SetPriorityClass, PROCESS_MODE_BACKGROUND_BEGIN};
+    if unsafe { SetPriorityClass(GetCurrentProcess(), PROCESS_MODE_BACKGROUND_BEGIN) } == 0 {
+        bail!("Failed to enter background mode: {}", std::io::Error::last_os_error());
+    }
+    Ok(())
+}
+
+#[cfg(not(any(target_os = "linux", windows)))]
+fn set_io_idle() -> Result<()> {
+    tracing::warn!("I/O priority can only be changed on Linux and Windows; --io-priority is ignored");
+    Ok(())
+}
@@ -0,0 +1,150 @@
+use std::collections::hash_map::RandomState;
+use std::fmt;
+use std::hash::{BuildHasher, Hasher};
+use std::io;
+use std::thread;
+use std::time::Duration;
+use anyhow::Result;
+use tracing::warn;
+use crate::utils::config::RetryPolicy;
+
+/// Marks a failure as worth retrying where its cause doesn't tell, e.g.
+/// ffmpeg reporting an I/O error on its input.
+#[derive(Debug)]
+pub struct Transient(pub String);
+
+impl fmt::Display for Transient {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str(&self.0)
+    }
+}
+
+impl std::error::Error for Transient {}
+
+/// Whether `e` may go away when tried again: I/O timeouts, resets and
+/// stale handles, a busy or locked catalog, or anything marked
+/// `Transient`. Missing files, bad data and the like are permanent.
+pub fn is_transient(e: &anyhow::Error) -> bool {
+    // A context isn't a cause `chain` can downcast, but the error can.
+    if e.is::<Transient>() {
+        return true;
+    }
+    e.chain().any(|cause| {
+        if cause.is::<Transient>() {
+            return true;
+        }
+        if let Some(e) = cause.downcast_ref::<io::Error>() {
+            return is_transient_io(e);
+        }
+        if let Some(rusqlite::Error::SqliteFailure(failure, _)) = cause.downcast_ref::<rusqlite::Error>() {
+            return matches!(failure.code, rusqlite::ErrorCode::DatabaseBusy | rusqlite::ErrorCode::DatabaseLocked);
+        }
+        #[cfg(feature = "postgres")]
+        if let Some(e) = cause.downcast_ref::<postgres::Error>() {
+            use postgres::error::SqlState;
+            return e.is_closed()
+                || e.code().is_some_and(|code| {
+                    [SqlState::T_R_SERIALIZATION_FAILURE, SqlState::T_R_DEADLOCK_DETECTED, SqlState::ADMIN_SHUTDOWN, SqlState::CANNOT_CONNECT_NOW]
+                        .contains(code)
+                });
+        }
+        false
+    })
+}
+
+fn is_transient_io(e: &io::Error) -> bool {
+    #[cfg(target_os = "linux")]
+    if e.raw_os_error() == Some(libc::EIO) {
+        return true;
+    }
+    matches!(
+        e.kind(),
+        io::ErrorKind::Interrupted
+            | io::ErrorKind::WouldBlock
+            | io::ErrorKind::TimedOut
+            | io::ErrorKind::ConnectionReset
+            | io::ErrorKind::ConnectionAborted
+            | io::ErrorKind::NotConnected
+            | io::ErrorKind::BrokenPipe
+            | io::ErrorKind::ResourceBusy
+            | io::ErrorKind::StaleNetworkFileHandle
+            | io::ErrorKind::NetworkDown
+            | io::ErrorKind::NetworkUnreachable
+            | io::ErrorKind::HostUnreachable
+    )
+}
+
+impl RetryPolicy {
+    /// Runs `f`, trying again after a backoff while it fails transiently
+    /// and attempts remain. `what` names the operation in the log.
+    pub fn run<T>(&self, what: &str, mut f: impl FnMut() -> Result<T>) -> Result<T> {
+        f().or_else(|e| self.recover(e, what, f))
+    }
+
+    /// Continues after a first attempt already failed with `error`, retrying
+    /// with `f`, which may differ from the first attempt (e.g. flushing
+    /// what a failed write left buffered).
+    pub fn recover<T>(&self, mut error: anyhow::Error, what: &str, mut f: impl FnMut() -> Result<T>) -> Result<T> {
+        let mut attempt = 1;
+        while attempt < self.attempts && is_transient(&error) {
+            let wait = self.backoff(attempt);
+            warn!("{} failed ({}/{}), retrying in {:?}: {:#}", what, attempt, self.attempts, wait, error);
+            thread::sleep(wait);
+            attempt += 1;
+            match f() {
+                Ok(value) => return Ok(value),
+                Err(e) => error = e,
+            }
+        }
+        Err(error)
+    }
+
+    /// Wait before retry `attempt` (from 1), with jitter.
+    pub fn backoff(&self, attempt: u32) -> Duration {
+        let exponential = self.initial_backoff_ms.saturating_mul(1 << (attempt - 1).min(20));
+        let base = exponential.min(self.max_backoff_ms) as f64;
+        // A fresh random seed per call; good enough to spread retries.
+        let unit = RandomState::new().build_hasher().finish() as f64 / u64::MAX as f64;
+        let jitter = self.jitter.clamp(0.0, 1.0) * (2.0 * unit - 1.0);
+        Duration::from_millis((base * (1.0 + jitter)) as u64)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use anyhow::{anyhow, Context};
+
+    #[test]
+    fn test_retry_policy() {
+        let policy = RetryPolicy { attempts: 3, initial_backoff_ms: 1, max_backoff_ms: 2, jitter: 0.0 };
+        assert_eq!(policy.backoff(1), Duration::from_millis(1));
+        assert_eq!(policy.backoff(5), Duration::from_millis(2));
+
+        let mut calls = 0;
+        let result = policy.run("Hashing", || {
+            calls += 1;
+            match calls {
+                1 => Err(anyhow::Error::new(io::Error::from(io::ErrorKind::TimedOut)).context("Failed to read")),
+                _ => Ok(calls),
+            }
+        });
+        assert_eq!(result.unwrap(), 2);
+
+        // Permanent failures aren't retried, transient ones only `attempts` times.
+        let mut calls = 0;
+        let result: Result<()> = policy.run("Hashing", || {
+            calls += 1;
+            Err(io::Error::from(io::ErrorKind::NotFound)).context("Failed to open")
+        });
+        assert!(result.is_err());
+        assert_eq!(calls, 1);
+        let mut calls = 0;
+        let result: Result<()> = policy.run("Decoding", || {
+            calls += 1;
+            Err(anyhow!(Transient("ffmpeg: Input/output error".to_string())))
+        });
+        assert!(result.is_err());
+        assert_eq!(calls, 3);
+    }
+}
@@ -0,0 +1,77 @@
+use anyhow::{Result, Context};
+use opentelemetry::trace::TracerProvider;
+use opentelemetry::KeyValue;
+use opentelemetry_otlp::{SpanExporter, WithExportConfig};
+use opentelemetry_sdk::trace::{Sampler, SdkTracerProvider};
+use opentelemetry_sdk::Resource;
+use tracing::Subscriber;
+use tracing_subscriber::filter::{LevelFilter, Targets};
+use tracing_subscriber::registry::LookupSpan;
+use tracing_subscriber::Layer;
+use crate::ingest::source;
+use crate::utils::config::TelemetryConfig;
+
+/// The spans still queued for export, sent when dropped.
+pub struct Telemetry {
+    provider: SdkTracerProvider,
+}
+
+impl Drop for Telemetry {
+    fn drop(&mut self) {
+        if let Err(e) = self.provider.shutdown() {
+            eprintln!("Failed to export the last spans: {}", e);
+        }
+    }
+}
+
+/// A layer exporting this crate's spans, down to the per-file ones at debug
+/// level, in batches from a thread of its own. Keep the `Telemetry` until
+/// the end, as it sends what is left.
+pub fn layer<S>(config: &TelemetryConfig) -> Result<(impl Layer<S>, Telemetry)>
+where
+    S: Subscriber + for<'span> LookupSpan<'span>,
+{
+    // Unset, the exporter goes by $OTEL_EXPORTER_OTLP_ENDPOINT.
+    let mut exporter = SpanExporter::builder().with_http();
+    if let Some(endpoint) = &config.endpoint {
+        exporter = exporter.with_endpoint(traces_url(endpoint));
+    }
+    let exporter = exporter.build().context("Failed to set up the OTLP exporter")?;
+    let resource = Resource::builder()
+        .with_service_name(config.service_name.clone())
+        .with_attribute(KeyValue::new("host.name", source::host_name()))
+        .build();
+    let provider = SdkTracerProvider::builder()
+        .with_batch_exporter(exporter)
+        // Each file is a trace of its own, so this samples files.
+        .with_sampler(Sampler::ParentBased(Box::new(Sampler::TraceIdRatioBased(config.sample_ratio))))
+        .with_resource(resource)
+        .build();
+    let tracer = provider.tracer("deep-archive");
+    // The exporter's own requests would be traced too otherwise.
+    let filter = Targets::new().with_target("deep_archive", LevelFilter::DEBUG);
+    let layer = tracing_opentelemetry::layer().with_tracer(tracer).with_filter(filter);
+    Ok((layer, Telemetry { provider }))
+}
+
+/// `endpoint/v1/traces`, as the exporter makes of
+/// `$OTEL_EXPORTER_OTLP_ENDPOINT`, unless that is already its path.
+fn traces_url(endpoint: &str) -> String {
+    let endpoint = endpoint.trim_end_matches('/');
+    match endpoint.ends_with("/v1/traces") {
+        true => endpoint.to_string(),
+        false => format!("{}/v1/traces", endpoint),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_traces_url() {
+        assert_eq!(traces_url("http://localhost:4318"), "http://localhost:4318/v1/traces");
+        assert_eq!(traces_url("http://tempo:4318/"), "http://tempo:4318/v1/traces");
+        assert_eq!(traces_url("http://collector/v1/traces"), "http://collector/v1/traces");
+    }
+}
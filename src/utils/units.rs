@@ -0,0 +1,123 @@
+use anyhow::{Result, anyhow};
+use chrono::{DateTime, NaiveDate};
+
+/// Parses a byte size such as `4096`, `700MB`, `25GB` or `1.5TiB`.
+/// Decimal suffixes (KB, MB, ...) are powers of 1000, binary ones (KiB, MiB, ...) of 1024.
+pub fn parse_size(input: &str) -> Result<u64> {
+    let s = input.trim();
+    let split = s.find(|c: char| !(c.is_ascii_digit() || c == '.')).unwrap_or(s.len());
+    let (number, unit) = s.split_at(split);
+
+    let number: f64 = number
+        .parse()
+        .map_err(|_| anyhow!("Invalid size: '{}'", input))?;
+
+    let multiplier: u64 = match unit.trim().to_ascii_lowercase().as_str() {
+        "" | "b" => 1,
+        "k" | "kb" => 1_000,
+        "m" | "mb" => 1_000_000,
+        "g" | "gb" => 1_000_000_000,
+        "t" | "tb" => 1_000_000_000_000,
+        "kib" => 1 << 10,
+        "mib" => 1 << 20,
+        "gib" => 1 << 30,
+        "tib" => 1 << 40,
+        other => return Err(anyhow!("Unknown size unit '{}' in '{}'", other, input)),
+    };
+
+    Ok((number * multiplier as f64).round() as u64)
+}
+
+/// Parses a distance such as `500m`, `5km` or `3mi` into meters; a bare
+/// number is meters.
+pub fn parse_distance(input: &str) -> Result<f64> {
+    let s = input.trim();
+    let split = s.find(|c: char| !(c.is_ascii_digit() || c == '.')).unwrap_or(s.len());
+    let (number, unit) = s.split_at(split);
+
+    let number: f64 = number
+        .parse()
+        .map_err(|_| anyhow!("Invalid distance: '{}'", input))?;
+
+    let multiplier = match unit.trim().to_ascii_lowercase().as_str() {
+        "" | "m" => 1.0,
+        "km" => 1_000.0,
+        "mi" => 1_609.344,
+        "ft" => 0.3048,
+        other => return Err(anyhow!("Unknown distance unit '{}' in '{}'", other, input)),
+    };
+
+    Ok(number * multiplier)
+}
+
+/// Renders a byte count with binary units, e.g. `1.5 GiB`.
+pub fn format_size(bytes: u64) -> String {
+    const UNITS: [&str; 5] = ["B", "KiB", "MiB", "GiB", "TiB"];
+    let mut value = bytes as f64;
+    let mut unit = 0;
+    while value >= 1024.0 && unit < UNITS.len() - 1 {
+        value /= 1024.0;
+        unit += 1;
+    }
+    if unit == 0 {
+        format!("{} B", bytes)
+    } else {
+        format!("{:.1} {}", value, UNITS[unit])
+    }
+}
+
+/// Renders seconds as e.g. `42s`, `3m 05s` or `2h 10m`.
+pub fn format_duration(secs: f64) -> String {
+    let secs = secs.round() as u64;
+    match secs {
+        0..60 => format!("{}s", secs),
+        60..3600 => format!("{}m {:02}s", secs / 60, secs % 60),
+        _ => format!("{}h {:02}m", secs / 3600, secs % 3600 / 60),
+    }
+}
+
+/// Parses a `YYYY-MM-DD` date into unix seconds at midnight UTC.
+pub fn parse_date(input: &str) -> Result<i64> {
+    let date = NaiveDate::parse_from_str(input.trim(), "%Y-%m-%d")
+        .map_err(|e| anyhow!("Invalid date '{}' (expected YYYY-MM-DD): {}", input, e))?;
+    Ok(date.and_hms_opt(0, 0, 0).expect("midnight is valid").and_utc().timestamp())
+}
+
+/// Renders unix seconds as `YYYY-MM-DD HH:MM:SS` UTC, or `-` when unknown.
+pub fn format_timestamp(ts: Option<i64>) -> String {
+    ts.and_then(|t| DateTime::from_timestamp(t, 0))
+        .map(|t| t.format("%Y-%m-%d %H:%M:%S").to_string())
+        .unwrap_or_else(|| "-".to_string())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_size() -> Result<()> {
+        assert_eq!(parse_size("4096")?, 4096);
+        assert_eq!(parse_size("25GB")?, 25_000_000_000);
+        assert_eq!(parse_size("1.5 KiB")?, 1536);
+        assert!(parse_size("12 parsecs").is_err());
+        assert_eq!(format_size(512), "512 B");
+        assert_eq!(format_size(1536), "1.5 KiB");
+        Ok(())
+    }
+
+    #[test]
+    fn test_parse_distance() -> Result<()> {
+        assert_eq!(parse_distance("250")?, 250.0);
+        assert_eq!(parse_distance("5km")?, 5_000.0);
+        assert_eq!(parse_distance("2 mi")?, 3_218.688);
+        assert!(parse_distance("3 leagues").is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn test_parse_date() -> Result<()> {
+        assert_eq!(parse_date("2024-01-01")?, 1704067200);
+        assert!(parse_date("01/01/2024").is_err());
+        Ok(())
+    }
+}